@@ -79,12 +79,20 @@ class MovingPainting:
 	var wall_y: float
 	var wall_id: String
 	var gallery_id: int
+	var target_wall: Wall
 
 	func finish_moving() -> void:
 		painting.finish_interactive_placement()
 		if not Lobby.IS_CLIENT:
 			print("New painting position is object_id=", painting.art_object_id, " gallery_id=", gallery_id, " wall_id=", wall_id, " x=", wall_x, " y=", wall_y)
-			ArtObjects.gallery_client.move_art_object(painting.art_object_id, gallery_id, wall_id, wall_x, wall_y)
+			# We're not strict here, so paintings dragged off the edge of the wall are
+			# clamped to it rather than snapping back to where they were.
+			var result = await ArtObjects.move_art_object(painting.art_object_id, gallery_id, wall_id, wall_x, wall_y, false)
+			if result is Dictionary and result.has("x"):
+				if result.x != wall_x or result.y != wall_y:
+					wall_x = result.x
+					wall_y = result.y
+					painting.global_position = target_wall.get_global_base_position() + target_wall.horizontal_direction * wall_x + Vector3.UP * wall_y
 			if not Lobby.IS_HEADLESS:
 				painting.fix_stupid_lighting_bug()
 
@@ -94,6 +102,7 @@ class MovingPainting:
 		wall_y = relative_position.y
 		wall_id = wall.name
 		gallery_id = wall.gallery.gallery_id
+		target_wall = wall
 
 	func move_along_wall(raycast: RayCast3D) -> void:
 		# Note: if we're in a multiplayer situation and this is the client, we'll see jittering.
@@ -65,6 +65,9 @@ var is_duplicate := false
 ## The collection the painting is part of, set by the server.
 @export var collection: String
 
+## Whether the painting is hung rotated 90° clockwise, set by the server.
+@export var rotated: bool
+
 var small_image_texture: ImageTexture
 
 func _get_initial_albedo_color() -> Color:
@@ -88,6 +91,8 @@ func _ready():
 	if inner_painting_scale:
 		configure_wall_label()
 		painting.set_scale(inner_painting_scale)
+		if rotated:
+			painting.rotation.z = -PI / 2
 	else:
 		print("Warning: No inner_painting_scale available for painting!")
 	if not art_object_id:
@@ -186,13 +191,17 @@ func configure_wall_label() -> void:
 		# This is a duplicate, the wall label has already been configured.
 		return
 	var aabb_size := painting.get_aabb().size
+	# This is the size of the painting as it's hung on the wall.
+	var hung_size := Vector2(inner_painting_scale.x, inner_painting_scale.y)
+	if rotated:
+		hung_size = Vector2(inner_painting_scale.y, inner_painting_scale.x)
 	var x := wall_label_primary.position.x
 	var wall_label_x_offset := absf(x) - aabb_size.x / 2
-	var left_edge := _get_side_multiplier(x) * (inner_painting_scale.x / 2 + wall_label_x_offset)
+	var left_edge := _get_side_multiplier(x) * (hung_size.x / 2 + wall_label_x_offset)
 	wall_label_primary.position.x = left_edge
 	var y := wall_label_primary.position.y
 	var wall_label_y_offset := absf(y) - aabb_size.y / 2
-	wall_label_primary.position.y = _get_side_multiplier(y) * (inner_painting_scale.y / 2 + wall_label_y_offset)
+	wall_label_primary.position.y = _get_side_multiplier(y) * (hung_size.y / 2 + wall_label_y_offset)
 	wall_label_primary.text = _default_str(artist, "Anonymous")
 
 	# This is a bit annoying, we have to wait a full frame for the primary label to populate its AABB.
@@ -236,6 +245,7 @@ func init_with_art_object(object: ArtObject):
 	medium = object.medium
 	date = object.date
 	collection = object.collection
+	rotated = object.rotated
 
 
 func try_to_open_in_browser():
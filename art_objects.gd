@@ -1,6 +1,6 @@
 extends Node
 
-const NULL_REQUEST_ID = 0
+const NULL_REQUEST_ID = GalleryConstants.NULL_REQUEST_ID
 
 ## Try to only spend these many microseconds per frame processing
 ## responses, as we don't want to skip frames.
@@ -18,6 +18,11 @@ class ArtObjectsRequest:
 
 class ImageRequest:
 	var image_path: String
+	var pixel_width: int
+	var pixel_height: int
+	## If fetching the image failed recently, this is how many seconds to
+	## wait before trying again, and `response` is null.
+	var retry_after: float
 	var response: Image
 	signal responded
 
@@ -32,14 +37,18 @@ class StringRequest:
 	var response: String
 	signal responded
 
+class VariantRequest:
+	var response: Variant
+	signal responded
+
 func _fetch_image(object_id: int, size: String) -> Image:
 	if Lobby.IS_HEADLESS:
 		return Image.create(1, 1, false, Image.FORMAT_L8)
 	var request := ImageRequest.new()
 	var request_id: int
-	if size == "small":
+	if size == GalleryConstants.image_size_small():
 		request_id = gallery_client.fetch_small_image(object_id)
-	elif size == "large":
+	elif size == GalleryConstants.image_size_large():
 		request_id = gallery_client.fetch_large_image(object_id)
 	else:
 		crash("Invalid image size: " + size)
@@ -51,10 +60,10 @@ func _fetch_image(object_id: int, size: String) -> Image:
 	return request.response
 
 func fetch_small_image(object_id: int) -> Image:
-	return await _fetch_image(object_id, "small")
+	return await _fetch_image(object_id, GalleryConstants.image_size_small())
 
 func fetch_large_image(object_id: int) -> Image:
-	return await _fetch_image(object_id, "large")
+	return await _fetch_image(object_id, GalleryConstants.image_size_large())
 
 func count_art_objects(filter: String) -> int:
 	var request := IntRequest.new()
@@ -66,16 +75,56 @@ func count_art_objects(filter: String) -> int:
 	await request.responded
 	return request.response
 
-func layout(filter: String, dense: bool) -> void:
-	var request := EmptyRequest.new()
-	var request_id := gallery_client.layout("res://Levels/moma-gallery.walls.json", filter, dense)
+## Any art objects in `ordering` are laid out first, in that order, followed by
+## highlights if `featured_first` is true. Walls named in `reserved_walls` are
+## left empty in every gallery. If `allow_rotation` is true, art objects that
+## don't fit somewhere upright can be hung rotated 90°.
+##
+## Returns a Dictionary with `galleries_created`, `first_gallery_id`,
+## `last_gallery_id` and `unplaceable` keys, or an empty one if the layout failed.
+func layout(filter: String, dense: bool, ordering := PackedInt64Array(), reserved_walls := PackedStringArray(), featured_first := false, allow_rotation := false) -> Dictionary:
+	var request := StringRequest.new()
+	var request_id := gallery_client.layout("res://Levels/moma-gallery.walls.json", filter, dense, ordering, reserved_walls, featured_first, allow_rotation, false)
 	if request_id == NULL_REQUEST_ID:
 		push_error("Creating new layout failed!")
 		# Oof, something went wrong.
-		return
+		return {}
+	requests[request_id] = request
+	await request.responded
+	var result = JSON.parse_string(request.response)
+	if not result is Dictionary:
+		push_error("Unable to parse layout summary!")
+		return {}
+	if result.get("unplaceable", 0) > 0:
+		push_warning(str(result.unplaceable) + " art object(s) are too big to fit on any walls.")
+	if result.get("unknown_wall_records", 0) > 0:
+		push_warning(str(result.unknown_wall_records) + " art object(s) are on walls that don't exist.")
+	print("Layout complete, created ", result.get("galleries_created", 0), " galleries.")
+	return result
+
+## Returns the number of galleries that have art in them.
+func count_galleries() -> int:
+	var request := IntRequest.new()
+	var request_id := gallery_client.count_galleries()
+	if request_id == NULL_REQUEST_ID:
+		# Oof, something went wrong.
+		return 0
 	requests[request_id] = request
 	await request.responded
-	print("Layout complete.")
+	return request.response
+
+## When offline, images that aren't already cached won't be fetched from
+## the network.
+func set_offline(offline: bool) -> void:
+	gallery_client.set_offline(offline)
+	if not offline:
+		# Images that failed to fetch while we were offline are worth trying
+		# again right away.
+		gallery_client.clear_image_retry_state()
+
+## Sets how chatty the Rust side is in the console, e.g. "warn" or "debug".
+func set_log_level(level: String) -> void:
+	gallery_client.set_log_level(level)
 
 func migrate() -> void:
 	var request := EmptyRequest.new()
@@ -88,21 +137,63 @@ func migrate() -> void:
 	await request.responded
 	print("Migration complete.")
 
-func import(json_content: String) -> int:
-	var request := IntRequest.new()
-	var request_id := gallery_client.import_non_positive_layout(json_content)
+## Migrates the DB, checks its schema and, if `relayout_if_empty` is true and
+## nothing's been laid out yet, creates an unfiltered initial layout.
+##
+## Returns a Dictionary with `db_version`, `object_count`, `gallery_count`,
+## `migrated`, `laid_out` and `warnings` keys, or an empty one if the request failed.
+func ensure_ready(relayout_if_empty: bool) -> Dictionary:
+	var request := StringRequest.new()
+	var request_id := gallery_client.ensure_ready("res://Levels/moma-gallery.walls.json", relayout_if_empty)
+	if request_id == NULL_REQUEST_ID:
+		push_error("Ensuring the gallery is ready failed!")
+		# Oof, something went wrong.
+		return {}
+	requests[request_id] = request
+	await request.responded
+	var result = JSON.parse_string(request.response)
+	if not result is Dictionary:
+		push_error("Unable to parse readiness report!")
+		return {}
+	for warning in result.get("warnings", []):
+		push_warning(warning)
+	print("Gallery ready, ", result.get("object_count", 0), " art objects in ", result.get("gallery_count", 0), " galleries.")
+	return result
+
+## The conflict policy is one of "skip", "overwrite" or "fail", and says what to do
+## about art objects in the import that are already hanging in the museum.
+func import(json_content: String, conflict_policy := "skip") -> int:
+	var request := StringRequest.new()
+	var request_id := gallery_client.import_non_positive_layout(json_content, conflict_policy)
 	if request_id == NULL_REQUEST_ID:
 		push_error("Import failed!")
 		# Oof, something went wrong.
 		return FAILED
 	requests[request_id] = request
 	await request.responded
-	print("Import complete.")
-	return request.response
-
-func export() -> String:
+	var result = JSON.parse_string(request.response)
+	if not result is Dictionary:
+		push_error("Unable to parse import summary!")
+		return FAILED
+	if result.get("skipped", 0) > 0:
+		push_warning(str(result.skipped) + " art object(s) were on walls that don't exist and weren't imported.")
+	if result.get("conflicted", 0) > 0:
+		if conflict_policy == "skip":
+			push_warning(str(result.conflicted) + " art object(s) are already hanging in the museum and weren't imported.")
+		else:
+			push_warning(str(result.conflicted) + " art object(s) were moved out of the museum.")
+	for rejected in result.get("rejected", []):
+		push_warning("Layout record wasn't imported: " + rejected.reason)
+	for art_object in result.get("unknown_art_objects", []):
+		push_warning("Art object isn't in the database and wasn't imported: " + art_object.title + " (" + art_object.url + ")")
+	print("Import complete, imported ", result.get("imported", 0), " art object(s).")
+	return OK
+
+## If include_metadata is true, the layout can be imported by players whose
+## databases don't have the same art objects.
+func export(include_metadata := false) -> String:
 	var request := StringRequest.new()
-	var request_id := gallery_client.export_non_positive_layout()
+	var request_id := gallery_client.export_non_positive_layout(include_metadata)
 	if request_id == NULL_REQUEST_ID:
 		push_error("Export failed!")
 		# Oof, something went wrong.
@@ -122,6 +213,100 @@ func get_art_objects_for_gallery_wall(gallery_id: int, wall_id: String) -> Array
 	await request.responded
 	return request.response
 
+## Returns an Array of Dictionaries with `value` and `count` keys, one for each
+## distinct value of the given column (`artist`, `culture`, `medium` or
+## `collection`) that at least `min_count` art objects have, most common first.
+func distinct_values(column: String, min_count: int, limit: int) -> Array:
+	var request := StringRequest.new()
+	var request_id := gallery_client.distinct_values(column, min_count, limit)
+	if request_id == NULL_REQUEST_ID:
+		# Oof, something went wrong.
+		return []
+	requests[request_id] = request
+	await request.responded
+	var result = JSON.parse_string(request.response)
+	if not result is Array:
+		push_error("Unable to get distinct values of %s!" % column)
+		return []
+	return result
+
+## Returns an Array of Dictionaries with `gallery_id` and `object_count`
+## keys, one for each gallery that has art in it, ordered by gallery ID.
+func get_gallery_graph() -> Array:
+	var request := StringRequest.new()
+	var request_id := gallery_client.get_gallery_graph()
+	if request_id == NULL_REQUEST_ID:
+		# Oof, something went wrong.
+		return []
+	requests[request_id] = request
+	await request.responded
+	var result = JSON.parse_string(request.response)
+	if not result is Array:
+		push_error("Unable to parse gallery graph!")
+		return []
+	return result
+
+## Returns a Dictionary with the artist's `qid`, `name` and `description`,
+## or null if the artist is unknown.
+func get_artist(qid: int) -> Variant:
+	var request := VariantRequest.new()
+	var request_id := gallery_client.get_artist(qid)
+	if request_id == NULL_REQUEST_ID:
+		# Oof, something went wrong.
+		return null
+	requests[request_id] = request
+	await request.responded
+	return request.response
+
+## Returns a Dictionary whose `same_artist` and `same_medium_century` keys are
+## Arrays of at most `limit` Dictionaries describing art objects related to the
+## given one, or null if something went wrong.
+func get_related_art_objects(object_id: int, limit: int) -> Variant:
+	var request := StringRequest.new()
+	var request_id := gallery_client.get_related_art_objects(object_id, limit)
+	if request_id == NULL_REQUEST_ID:
+		# Oof, something went wrong.
+		return null
+	requests[request_id] = request
+	await request.responded
+	var result = JSON.parse_string(request.response)
+	if not result is Dictionary:
+		push_error("Unable to parse related art objects!")
+		return null
+	return result
+
+## Returns a Dictionary with the `x` and `y` the art object was actually moved
+## to (it may have been clamped to the wall), or null if the move failed. If
+## `strict` is true, moves that hang off the wall or overlap another art object
+## are rejected, and the Dictionary will instead have a `rejected` key with
+## the reason why.
+func move_art_object(art_object_id: int, gallery_id: int, wall_id: String, x: float, y: float, strict: bool) -> Variant:
+	var request := VariantRequest.new()
+	var request_id := gallery_client.move_art_object(art_object_id, gallery_id, wall_id, x, y, strict)
+	if request_id == NULL_REQUEST_ID:
+		# Oof, something went wrong.
+		return null
+	requests[request_id] = request
+	await request.responded
+	return request.response
+
+## Undoes the most recent `move_art_object()`. Returns a Dictionary with the
+## `art_object_id` that was moved back, and a `restored` Dictionary with the
+## `gallery_id`, `wall_id`, `x`, `y` and `rotated` it was moved back to (or null
+## if it was removed from the layout). Returns null if there was nothing to undo.
+func undo_last_move() -> Variant:
+	var request := StringRequest.new()
+	var request_id := gallery_client.undo_last_move()
+	if request_id == NULL_REQUEST_ID:
+		# Oof, something went wrong.
+		return null
+	requests[request_id] = request
+	await request.responded
+	var result = JSON.parse_string(request.response)
+	if not result is Dictionary:
+		return null
+	return result
+
 func get_art_object_url(id: int) -> String:
 	return gallery_client.get_art_object_url(id)
 
@@ -181,16 +366,36 @@ func _ready() -> void:
 	gallery_client.name = "GalleryClient"
 	add_child(gallery_client)
 	var autosync_enabled := PersistedConfig.get_bool(PersistedConfig.AUTOSYNC_ENABLED, false)
-	gallery_client.connect(PersistedConfig.ROOT_DIR, autosync_enabled)
-	if did_create_initial_db:
-		# Note that we're not waiting for the result of the layout.
-		# I'm too lazy to deal with showing the user an initialization screen
-		# right now and will just trust that this works. If it doesn't, it's not
-		# the end of the world, since the user will just see an empty
-		# layout and can try to layout again via the UI.
-		layout(PersistedConfig.get_string(PersistedConfig.GALLERY_FILTER, ""), false)
-		# We're also not waiting for this to complete.
-		migrate()
+	# This can only be enabled by editing settings.cfg, since it's meant for
+	# level designers rather than players.
+	var preview_server_enabled := PersistedConfig.get_bool(PersistedConfig.PREVIEW_SERVER_ENABLED, false)
+	# These can also only be set by editing settings.cfg, e.g. to "webp" to
+	# make the image cache smaller.
+	var image_format := PersistedConfig.get_string(PersistedConfig.IMAGE_FORMAT, "")
+	var keep_original_images := PersistedConfig.get_bool(PersistedConfig.KEEP_ORIGINAL_IMAGES, false)
+	# Note that we don't yet know whether we're a multiplayer client at this
+	# point, so we always connect read-write.
+	gallery_client.connect(
+		PersistedConfig.ROOT_DIR,
+		"user",
+		autosync_enabled,
+		false,
+		false,
+		preview_server_enabled,
+		GalleryClient.DEFAULT_PREVIEW_SERVER_PORT,
+		image_format,
+		keep_original_images
+	)
+	# Note that we're not waiting for the result of this.
+	# I'm too lazy to deal with showing the user an initialization screen
+	# right now and will just trust that this works. If it doesn't, it's not
+	# the end of the world, since the user will just see an empty
+	# layout and can try to layout again via the UI.
+	var filter := PersistedConfig.get_string(PersistedConfig.GALLERY_FILTER, "")
+	ensure_ready(did_create_initial_db and filter == "")
+	if did_create_initial_db and filter != "":
+		# The initial layout that `ensure_ready()` creates is unfiltered.
+		layout(filter, false)
 
 func _process(_delta) -> void:
 	if fatal_error_message:
@@ -236,11 +441,27 @@ func _handle_gallery_response(obj: GalleryResponse):
 		return
 	var request = requests[obj.request_id]
 	requests.erase(obj.request_id)
+	var kind := obj.response_kind()
+	if kind == GalleryConstants.RESPONSE_KIND_ERROR:
+		push_error("Gallery request #", obj.request_id, " failed: ", obj.take_error())
+		request.responded.emit()
+		return
 	if request is ImageRequest:
 		var r: ImageRequest = request
-		var path = obj.take_variant()
-		if path is String:
-			r.image_path = path
+		if kind == GalleryConstants.RESPONSE_KIND_IMAGE:
+			# The image was already decoded by the worker thread.
+			r.response = obj.take_image()
+			r.responded.emit()
+			return
+		var info = obj.take_variant()
+		if info is Dictionary and info.has("retry_after"):
+			r.retry_after = info.retry_after
+			r.responded.emit()
+		elif info is Dictionary:
+			r.image_path = info.path
+			if info.width != null and info.height != null:
+				r.pixel_width = info.width
+				r.pixel_height = info.height
 			image_loading_thread.load_image(r)
 	elif request is ArtObjectsRequest:
 		var r: ArtObjectsRequest = request
@@ -262,6 +483,10 @@ func _handle_gallery_response(obj: GalleryResponse):
 		assert(result is String)
 		r.response = result
 		r.responded.emit()
+	elif request is VariantRequest:
+		var r: VariantRequest = request
+		r.response = obj.take_variant()
+		r.responded.emit()
 	else:
 		assert(false, "Unknown request type, cannot fill response")
 
@@ -23,6 +23,9 @@ var GI_ENABLED := Setting.create(VIDEO_SECTION, "global_illumination")
 var GALLERY_NAME := Setting.create(GALLERY_SECTION, "gallery_name")
 var GALLERY_FILTER := Setting.create(GALLERY_SECTION, "gallery_filter")
 var AUTOSYNC_ENABLED := Setting.create(GALLERY_SECTION, "autosync")
+var PREVIEW_SERVER_ENABLED := Setting.create(GALLERY_SECTION, "preview_server")
+var IMAGE_FORMAT := Setting.create(GALLERY_SECTION, "image_format")
+var KEEP_ORIGINAL_IMAGES := Setting.create(GALLERY_SECTION, "keep_original_images")
 
 var PLAYER_POSITION := Setting.create(PLAYER_SECTION, "position")
 var PLAYER_ROTATION := Setting.create(PLAYER_SECTION, "rotation")
@@ -0,0 +1,134 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+/// Art objects with a side shorter than this, in meters, are probably
+/// the result of a unit mix-up, and would be microscopic dots in a gallery.
+pub const DEFAULT_MIN_SIDE: f64 = 0.02;
+
+/// Art objects with a side longer than this, in meters, are probably the
+/// result of a unit mix-up, and wouldn't fit on any walls anyways.
+pub const DEFAULT_MAX_SIDE: f64 = 12.0;
+
+/// The most extreme aspect ratio (longest side divided by shortest side) that
+/// we consider plausible.
+pub const DEFAULT_MAX_ASPECT_RATIO: f64 = 20.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DimensionLimits {
+    pub min_side: f64,
+    pub max_side: f64,
+    pub max_aspect_ratio: f64,
+}
+
+impl Default for DimensionLimits {
+    fn default() -> Self {
+        Self {
+            min_side: DEFAULT_MIN_SIDE,
+            max_side: DEFAULT_MAX_SIDE,
+            max_aspect_ratio: DEFAULT_MAX_ASPECT_RATIO,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DimensionIssue {
+    /// At least one side is zero or negative (or not a number at all).
+    NonPositive,
+    TooSmall,
+    TooLarge,
+    ExtremeAspectRatio,
+}
+
+impl Display for DimensionIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DimensionIssue::NonPositive => write!(f, "non-positive dimensions"),
+            DimensionIssue::TooSmall => write!(f, "a side is too small"),
+            DimensionIssue::TooLarge => write!(f, "a side is too large"),
+            DimensionIssue::ExtremeAspectRatio => write!(f, "aspect ratio is too extreme"),
+        }
+    }
+}
+
+/// Make sure the given dimensions, in meters, are plausible for a 2D art object.
+pub fn validate_dimensions(
+    width: f64,
+    height: f64,
+    limits: &DimensionLimits,
+) -> Result<(), DimensionIssue> {
+    // Note that this is written so that NaNs are non-positive.
+    if !(width > 0.0 && height > 0.0) {
+        return Err(DimensionIssue::NonPositive);
+    }
+    let shortest = width.min(height);
+    let longest = width.max(height);
+    if shortest < limits.min_side {
+        return Err(DimensionIssue::TooSmall);
+    }
+    if longest > limits.max_side {
+        return Err(DimensionIssue::TooLarge);
+    }
+    if longest / shortest > limits.max_aspect_ratio {
+        return Err(DimensionIssue::ExtremeAspectRatio);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_dimensions, DimensionIssue, DimensionLimits};
+
+    fn validate(width: f64, height: f64) -> Result<(), DimensionIssue> {
+        validate_dimensions(width, height, &DimensionLimits::default())
+    }
+
+    #[test]
+    fn test_plausible_dimensions_are_accepted() {
+        assert_eq!(validate(0.6, 0.3), Ok(()));
+        assert_eq!(validate(0.02, 0.4), Ok(()));
+        assert_eq!(validate(12.0, 3.0), Ok(()));
+    }
+
+    #[test]
+    fn test_non_positive_dimensions_are_rejected() {
+        assert_eq!(validate(0.0, 1.0), Err(DimensionIssue::NonPositive));
+        assert_eq!(validate(1.0, -1.0), Err(DimensionIssue::NonPositive));
+        assert_eq!(validate(f64::NAN, 1.0), Err(DimensionIssue::NonPositive));
+    }
+
+    #[test]
+    fn test_tiny_dimensions_are_rejected() {
+        assert_eq!(validate(0.019, 0.3), Err(DimensionIssue::TooSmall));
+        assert_eq!(validate(0.001, 0.001), Err(DimensionIssue::TooSmall));
+    }
+
+    #[test]
+    fn test_huge_dimensions_are_rejected() {
+        assert_eq!(validate(40.0, 40.0), Err(DimensionIssue::TooLarge));
+        assert_eq!(validate(1.0, 12.1), Err(DimensionIssue::TooLarge));
+    }
+
+    #[test]
+    fn test_extreme_aspect_ratios_are_rejected() {
+        assert_eq!(validate(0.05, 1.1), Err(DimensionIssue::ExtremeAspectRatio));
+        assert_eq!(validate(3.0, 0.1), Err(DimensionIssue::ExtremeAspectRatio));
+        assert_eq!(validate(2.0, 0.1), Ok(()));
+    }
+
+    #[test]
+    fn test_limits_are_configurable() {
+        let limits = DimensionLimits {
+            min_side: 0.001,
+            max_side: 50.0,
+            max_aspect_ratio: 100.0,
+        };
+        assert_eq!(validate_dimensions(40.0, 40.0, &limits), Ok(()));
+        assert_eq!(validate_dimensions(0.01, 0.9, &limits), Ok(()));
+        assert_eq!(
+            validate_dimensions(0.01, 1.1, &limits),
+            Err(DimensionIssue::ExtremeAspectRatio)
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt::Display;
+
+use gallery::gallery_db::ArtObjectRecord;
+use serde::Serialize;
+
+use crate::dimension_limits::{validate_dimensions, DimensionIssue, DimensionLimits};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSkipReason {
+    MissingFilename,
+    InvalidDimensions,
+    DuplicateWikidata,
+    /// Another row with the same art object ID was already imported.
+    DuplicateId,
+    /// The row was structurally fine, but one of its fields couldn't be parsed.
+    DeserializeFailed,
+}
+
+/// A CSV row that wasn't imported, but doesn't prevent the rest of the CSV
+/// from being imported.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ImportSkip {
+    /// The wikidata QID of the row, if known.
+    pub qid: Option<u64>,
+    pub reason: ImportSkipReason,
+}
+
+/// A CSV row that was parsed, but whose data looks wrong, so it should be set
+/// aside for manual review instead of being imported.
+#[derive(Debug, PartialEq)]
+pub struct ImportQuarantine {
+    pub record: ArtObjectRecord,
+    pub issue: DimensionIssue,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Skip(ImportSkip),
+    Quarantine(Box<ImportQuarantine>),
+    /// The CSV itself is malformed (or couldn't be read), so the import
+    /// should be aborted.
+    Csv(csv::Error),
+    /// So many rows were skipped because they couldn't be parsed that the CSV
+    /// is probably mangled, so the import should be aborted.
+    TooManyParseErrors {
+        parse_errors: usize,
+        rows: usize,
+    },
+}
+
+impl From<csv::Error> for ImportError {
+    fn from(err: csv::Error) -> Self {
+        ImportError::Csv(err)
+    }
+}
+
+impl Display for ImportSkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportSkipReason::MissingFilename => write!(f, "missing filename"),
+            ImportSkipReason::InvalidDimensions => write!(f, "invalid dimensions"),
+            ImportSkipReason::DuplicateWikidata => write!(f, "duplicate wikidata"),
+            ImportSkipReason::DuplicateId => write!(f, "duplicate ID"),
+            ImportSkipReason::DeserializeFailed => write!(f, "unable to parse fields"),
+        }
+    }
+}
+
+impl Display for ImportSkip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.qid {
+            Some(qid) => write!(f, "Q{qid} ({})", self.reason),
+            None => write!(f, "row without QID ({})", self.reason),
+        }
+    }
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Skip(skip) => write!(f, "Skipped {skip}"),
+            ImportError::Quarantine(quarantine) => write!(
+                f,
+                "Quarantined {:?} ({})",
+                quarantine.record.object_id, quarantine.issue
+            ),
+            ImportError::Csv(err) => write!(f, "{err}"),
+            ImportError::TooManyParseErrors { parse_errors, rows } => write!(
+                f,
+                "{parse_errors} of {rows} rows couldn't be parsed, the CSV is probably mangled"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Convert errors that only affect a single row into skips, leaving any others
+/// (e.g. rows with the wrong number of fields, or I/O errors) as-is.
+pub fn skip_deserialize_errors(err: csv::Error, qid: Option<u64>) -> ImportError {
+    match err.kind() {
+        csv::ErrorKind::Deserialize { .. } => ImportError::Skip(ImportSkip {
+            qid,
+            reason: ImportSkipReason::DeserializeFailed,
+        }),
+        _ => ImportError::Csv(err),
+    }
+}
+
+/// Quarantine the record if its dimensions are implausible.
+pub fn quarantine_implausible_dimensions(
+    record: ArtObjectRecord,
+    limits: &DimensionLimits,
+) -> Result<ArtObjectRecord, ImportError> {
+    match validate_dimensions(record.width, record.height, limits) {
+        Ok(()) => Ok(record),
+        Err(issue) => Err(ImportError::Quarantine(Box::new(ImportQuarantine {
+            record,
+            issue,
+        }))),
+    }
+}
@@ -0,0 +1,65 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Prints log messages to stderr, so they don't get mixed up with any output
+/// a command writes to stdout.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("error: {}", record.args()),
+            Level::Warn => eprintln!("warning: {}", record.args()),
+            Level::Info => eprintln!("{}", record.args()),
+            Level::Debug | Level::Trace => {
+                eprintln!(
+                    "[{}] {}: {}",
+                    record.level(),
+                    record.target(),
+                    record.args()
+                )
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// `--verbose` shows debug messages, `--quiet` only shows warnings and errors.
+pub fn level_for_flags(verbose: bool, quiet: bool) -> LevelFilter {
+    if quiet {
+        LevelFilter::Warn
+    } else if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    }
+}
+
+pub fn init_logger(level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::LevelFilter;
+
+    use super::level_for_flags;
+
+    #[test]
+    fn test_level_for_flags_works() {
+        assert_eq!(level_for_flags(false, false), LevelFilter::Info);
+        assert_eq!(level_for_flags(true, false), LevelFilter::Debug);
+        assert_eq!(level_for_flags(false, true), LevelFilter::Warn);
+    }
+}
@@ -1,28 +1,78 @@
+mod dimension_limits;
+mod import_skip;
+mod logger;
+mod met_api_refresh;
 mod met_csv;
+mod paths;
+mod public_domain;
 mod wikidata_dump;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fs::{self, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
-use gallery::art_object::ArtObjectId;
+use dimension_limits::{
+    DimensionIssue, DimensionLimits, DEFAULT_MAX_ASPECT_RATIO, DEFAULT_MAX_SIDE, DEFAULT_MIN_SIDE,
+};
+use gallery::art_object::{ArtObjectId, PlacedArtObject};
+use gallery::cache_usage::CacheUsageReport;
 use gallery::gallery_cache::GalleryCache;
 use gallery::gallery_db::{
-    get_default_gallery_db_filename, ArtObjectQueryOptions, ArtObjectRecord, GalleryDb,
-    LayoutRecord,
+    check_positive_gallery_range, get_default_gallery_db_filename, ArtObjectLayoutInfo,
+    ArtObjectQueryOptions, ArtObjectRecord, ArtistRecord, GalleryDb, LayoutRecord, LayoutScope,
+    QuarantinedObjectRecord, DEFAULT_ART_OBJECT_INSERT_ROWS_PER_STATEMENT,
+    LATEST_GALLERY_DB_VERSION,
+};
+use gallery::gallery_db_migration::{
+    adopt_older_gallery_db, get_unversioned_gallery_db_version, migrate_gallery_db_schema,
+};
+use gallery::gallery_db_subset::{copy_cached_files_for_art_objects, export_subset};
+use gallery::gallery_wall::{
+    check_walls, generate_room_walls, resolve_layout_wall_ids, GalleryWall, GalleryWallSet,
+};
+use gallery::image::{
+    get_supported_image_ext, maybe_convert_image_for_loading_in_godot, ImageConversionResult,
 };
-use gallery::gallery_wall::GalleryWall;
-use gallery::image::{get_supported_image_ext, maybe_convert_image_for_loading_in_godot};
-use gallery::layout::layout;
-use gallery::random::Rng;
+use gallery::layout::{
+    balance_by_collection, estimate_layout, layout, layout_segments, layout_source_galleries,
+    move_highlights_to_front, CollectionWeight, LayoutEstimate, LayoutMode, LayoutOptions,
+    LayoutResult, LayoutSegment, DEFAULT_MIN_FREE_REGION_WIDTH, DEFAULT_SALON_GUTTER,
+    LAYOUT_MODE_METADATA_KEY,
+};
+use gallery::layout_score::{
+    score_layout, LayoutScore, LayoutScoreWeights, DEFAULT_NEAR_EMPTY_WALL_WEIGHT,
+    DEFAULT_OCCUPANCY_VARIANCE_WEIGHT, DEFAULT_SAME_ARTIST_WEIGHT, DEFAULT_UNPLACEABLE_WEIGHT,
+};
+use gallery::random::{
+    Rng, LAYOUT_RANDOM_SEED_METADATA_KEY, LAYOUT_RNG_VERSION, LAYOUT_RNG_VERSION_METADATA_KEY,
+};
+use import_skip::{ImportError, ImportSkip, ImportSkipReason};
 use indicatif::{ProgressBar, ProgressStyle};
-use met_csv::{iter_public_domain_2d_met_csv_objects, PublicDomain2DMetObjectOptions};
+use log::{debug, warn};
+use logger::{init_logger, level_for_flags};
+use met_api_refresh::{
+    enrich_met_images, refresh_met_api, EnrichOptions, RefreshOptions, DEFAULT_PROGRESS_FILENAME,
+    DEFAULT_REQUESTS_PER_SECOND,
+};
+use met_csv::{
+    iter_public_domain_2d_met_csv_objects, met_csv_reader, MetCsvStats,
+    PublicDomain2DMetObjectOptions, DEFAULT_MAX_PARSE_ERROR_FRACTION, PROGRESS_INTERVAL_ROWS,
+};
+use paths::SearchPaths;
+use public_domain::PublicDomainPolicy;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use rusqlite::Connection;
+use serde::Serialize;
 use wikidata_dump::{
-    execute_wikidata_query, index_wikidata_dump, iter_wikidata_objects, prepare_wikidata_query,
+    compact_wikidata_cache, execute_wikidata_query, index_wikidata_dump, iter_wikidata_artists,
+    iter_wikidata_objects, pipeline_report_command, prepare_wikidata_query,
+    show_wikidata_cache_stats, show_wikidata_entity, update_wikidata_index, WikidataApiFetcher,
+    WikidataSource, DEFAULT_WIKIDATA_API_REQUESTS_PER_SECOND,
 };
 
 use std::io::BufReader;
@@ -31,17 +81,37 @@ const TRANSACTION_BATCH_SIZE: usize = 1000;
 
 const LAYOUT_START_GALLERY_ID: i64 = 1;
 
+/// The files that make up the demo dataset, see `Commands::Demo`.
+const DEMO_MET_OBJECTS_FILENAME: &str = "MetObjects.csv";
+const DEMO_WIKIDATA_OBJECTS_FILENAME: &str = "WikidataObjects.csv";
+const DEMO_WALLS_FILENAME: &str = "demo.walls.json";
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Verbose output
+    /// Verbose output, including debug messages
     #[arg(short, long, default_value_t = false, global = true)]
     verbose: bool,
 
-    /// Path to database
+    /// Only print warnings and errors
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    quiet: bool,
+
+    /// Path to database. Defaults to a file in the cache directory.
     #[arg(short, long)]
     db_path: Option<PathBuf>,
 
+    /// Directory where downloaded files and the database are kept. Defaults to
+    /// the `GALLERY_CACHE_DIR` env var, or the platform's data directory.
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -53,6 +123,11 @@ enum Sort {
     Random,
 }
 
+#[derive(Copy, Clone, clap::ValueEnum)]
+enum BalanceBy {
+    Collection,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Import MetObjects.csv into database.
@@ -80,7 +155,82 @@ enum Commands {
         /// public domain is actually public domain.
         #[arg(long, default_value_t = false)]
         warnings: bool,
+
+        /// Whether to import Met objects that aren't marked as public domain, but
+        /// probably are based on when they were created and when their artist died.
+        #[arg(long, value_enum, default_value_t = PublicDomainPolicy::Permissive)]
+        pd_policy: PublicDomainPolicy,
+
+        /// Process the CSVs and print a summary, but don't change the database.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+
+        /// Also write the import summary as JSON to this path.
+        #[arg(long)]
+        summary_json: Option<PathBuf>,
+
+        /// Write a CSV listing the QID of every row that was skipped, and why,
+        /// to this path.
+        #[arg(long)]
+        skipped_report: Option<PathBuf>,
+
+        /// Art objects with a side shorter than this many meters are quarantined.
+        #[arg(long, default_value_t = DEFAULT_MIN_SIDE)]
+        min_side: f64,
+
+        /// Art objects with a side longer than this many meters are quarantined.
+        #[arg(long, default_value_t = DEFAULT_MAX_SIDE)]
+        max_side: f64,
+
+        /// Art objects whose longest side is more than this many times their
+        /// shortest side are quarantined.
+        #[arg(long, default_value_t = DEFAULT_MAX_ASPECT_RATIO)]
+        max_aspect_ratio: f64,
+
+        /// Add to the art objects already in the database instead of replacing
+        /// them. Wikidata duplicates of existing art objects are still skipped.
+        #[arg(long, default_value_t = false)]
+        incremental: bool,
+
+        /// When an art object's fallback wikidata QID matches an art object that
+        /// was already imported from wikidata, replace the wikidata one instead
+        /// of skipping the new one. Only matters with `--incremental`.
+        #[arg(long, default_value_t = false)]
+        prefer_met: bool,
+
+        /// How many art objects to add to the database per transaction.
+        #[arg(long, default_value_t = TRANSACTION_BATCH_SIZE)]
+        batch_size: usize,
+
+        /// Parse the met objects CSV on multiple threads.
+        #[arg(long, default_value_t = false)]
+        parallel: bool,
+
+        /// Abort the import if two art objects have the same ID, rather than
+        /// skipping all but the first.
+        #[arg(long, default_value_t = false)]
+        fail_on_duplicates: bool,
+
+        /// Abort the import if more than this fraction of the met objects CSV's
+        /// rows can't be parsed. Fewer than that are just skipped.
+        #[arg(long, default_value_t = DEFAULT_MAX_PARSE_ERROR_FRACTION)]
+        max_parse_error_fraction: f64,
+    },
+    /// Build a complete database from the small demo dataset in the checkout's
+    /// `test_data/demo` directory and lay it out, entirely offline. This is the
+    /// quickest way to get something to walk around in the game, without
+    /// downloading the full Met and Wikidata datasets.
+    Demo {
+        /// Replace the database if it already exists.
+        #[arg(long, default_value_t = false)]
+        force: bool,
     },
+    /// List art objects that weren't imported because their dimensions looked wrong.
+    ListQuarantined,
+    /// Normalize the artist names of the art objects already in the database,
+    /// e.g. turning "Gogh, Vincent van" into "Vincent van Gogh" and splitting
+    /// off roles like "Workshop of". Art imported with `csv` is already normalized.
+    NormalizeArtists,
     /// Layout gallery walls.
     Layout {
         /// Clear the layout (don't populate it with any art).
@@ -99,19 +249,224 @@ enum Commands {
         #[arg(short, long)]
         filter: Option<String>,
 
-        /// Whether to use a dense layout (stack some art vertically).
-        #[arg(long = "dense", default_value_t = false)]
+        /// Whether to use a dense layout (stack some art vertically). Shorthand
+        /// for `--mode dense`.
+        #[arg(long = "dense", default_value_t = false, conflicts_with = "mode")]
         use_dense_layout: bool,
 
+        /// How to arrange art on each wall: `sparse`, `dense`, `salon`, which
+        /// packs walls of small prints in a grid, or `real-gallery`, which gives
+        /// each of the Met's galleries a gallery of its own.
+        #[arg(long)]
+        mode: Option<LayoutMode>,
+
+        /// The space between art packed in a grid with `--mode salon`, in meters.
+        #[arg(long, default_value_t = DEFAULT_SALON_GUTTER)]
+        salon_gutter: f64,
+
         /// Log warnings about whether e.g. a painting won't fit in a gallery.
         #[arg(long, default_value_t = false)]
         warnings: bool,
+
+        /// Path to a walls JSON file. Can be repeated, in which case galleries
+        /// will cycle through the wall sets. Defaults to the MoMA gallery's walls.
+        #[arg(long = "walls")]
+        walls: Vec<PathBuf>,
+
+        /// Exit with an error, without changing the layout, if any art objects
+        /// are too big to fit on any walls.
+        #[arg(long, default_value_t = false)]
+        fail_on_unplaceable: bool,
+
+        /// Path to a JSON array of art object IDs, e.g. `[{"Met": 1}, {"Wikidata": 5}]`.
+        /// These art objects will be laid out first, in the given order, followed by
+        /// the rest of the art objects in the usual sort order.
+        #[arg(long)]
+        ordering_json: Option<PathBuf>,
+
+        /// Name of a wall to leave empty in every gallery, e.g. `wall_04`. Can be
+        /// repeated.
+        #[arg(long = "reserve-wall")]
+        reserved_walls: Vec<String>,
+
+        /// A themed segment of galleries, e.g. `name=portraits filter=portrait`.
+        /// Can be repeated, in which case each segment gets its own range of
+        /// galleries, in order. Art objects matching multiple segments are only
+        /// laid out in the first one. `--filter` applies to every segment.
+        #[arg(long = "segment")]
+        segments: Vec<LayoutSegment>,
+
+        /// Lay out highlighted art objects before everything else (within each
+        /// segment, if there are any). `--ordering-json` still takes precedence.
+        #[arg(long, default_value_t = false)]
+        featured_first: bool,
+
+        /// Allow art objects that don't fit somewhere upright to be rotated 90°,
+        /// e.g. so tall hanging scrolls can be hung sideways on low walls.
+        #[arg(long, default_value_t = false)]
+        allow_rotation: bool,
+
+        /// Interleave the art objects of each collection, so that e.g. with
+        /// `--sort random` the biggest collection doesn't dominate every gallery.
+        /// Each collection is shuffled on its own.
+        #[arg(long)]
+        balance_by: Option<BalanceBy>,
+
+        /// How big a share of the layout a collection gets when balancing by
+        /// collection, e.g. `The Metropolitan Museum of Art=2`. Can be repeated.
+        /// Collections without a weight have a weight of 1.
+        #[arg(long = "collection-weight")]
+        collection_weights: Vec<CollectionWeight>,
+
+        /// Write the parts of walls that were left empty to this path as JSON,
+        /// e.g. so props can be put there.
+        #[arg(long)]
+        free_regions_json: Option<PathBuf>,
+
+        /// Free regions narrower than this are merged with adjacent ones, or
+        /// left out if there aren't any.
+        #[arg(long, default_value_t = DEFAULT_MIN_FREE_REGION_WIDTH)]
+        min_free_region_width: f64,
+
+        /// Leave out art objects that have ever been laid out in a positive
+        /// gallery, e.g. to rotate in art that's never been shown.
+        #[arg(long, default_value_t = false)]
+        exclude_previously_displayed: bool,
+
+        /// Only lay out this range of galleries, e.g. `1-3`, leaving the rest of
+        /// the layout alone. Art objects hanging elsewhere aren't laid out again.
+        #[arg(long = "gallery-range", value_parser = parse_gallery_id_range)]
+        gallery_id_range: Option<(i64, i64)>,
+    },
+    /// Lay out the art with several random seeds and rank the layouts, e.g. to
+    /// find one where every wall has a similar amount of art and works by the
+    /// same artist aren't hung next to each other. Lower scores are better.
+    ExploreSeeds {
+        /// How many seeds to try.
+        #[arg(long, default_value_t = 10)]
+        candidates: u64,
+
+        /// The first seed to try, followed by the next ones in order. If absent,
+        /// will use time since epoch, in seconds.
+        #[arg(short, long)]
+        random_seed: Option<u64>,
+
+        /// Filter artwork to only those matching this value.
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Whether to use a dense layout (stack some art vertically). Shorthand
+        /// for `--mode dense`.
+        #[arg(long = "dense", default_value_t = false, conflicts_with = "mode")]
+        use_dense_layout: bool,
+
+        /// How to arrange art on each wall, see `layout --mode`. `real-gallery`
+        /// isn't supported.
+        #[arg(long)]
+        mode: Option<LayoutMode>,
+
+        /// The space between art packed in a grid with `--mode salon`, in meters.
+        #[arg(long, default_value_t = DEFAULT_SALON_GUTTER)]
+        salon_gutter: f64,
+
+        /// Path to a walls JSON file. Can be repeated, see `layout --walls`.
+        #[arg(long = "walls")]
+        walls: Vec<PathBuf>,
+
+        /// Name of a wall to leave empty in every gallery. Can be repeated.
+        #[arg(long = "reserve-wall")]
+        reserved_walls: Vec<String>,
+
+        /// Lay out highlighted art objects before everything else.
+        #[arg(long, default_value_t = false)]
+        featured_first: bool,
+
+        /// Allow art objects that don't fit somewhere upright to be rotated 90°.
+        #[arg(long, default_value_t = false)]
+        allow_rotation: bool,
+
+        /// Interleave the art objects of each collection, see `layout --balance-by`.
+        #[arg(long)]
+        balance_by: Option<BalanceBy>,
+
+        /// How big a share of the layout a collection gets when balancing by
+        /// collection. Can be repeated.
+        #[arg(long = "collection-weight")]
+        collection_weights: Vec<CollectionWeight>,
+
+        /// How much uneven amounts of art on each wall count against a layout.
+        #[arg(long, default_value_t = DEFAULT_OCCUPANCY_VARIANCE_WEIGHT)]
+        occupancy_variance_weight: f64,
+
+        /// How much each wall that's nearly empty counts against a layout.
+        #[arg(long, default_value_t = DEFAULT_NEAR_EMPTY_WALL_WEIGHT)]
+        near_empty_wall_weight: f64,
+
+        /// How much each art object that doesn't fit counts against a layout.
+        #[arg(long, default_value_t = DEFAULT_UNPLACEABLE_WEIGHT)]
+        unplaceable_weight: f64,
+
+        /// How much each pair of neighboring works by the same artist counts
+        /// against a layout. Make it negative to keep artists together instead.
+        #[arg(long, default_value_t = DEFAULT_SAME_ARTIST_WEIGHT, allow_negative_numbers = true)]
+        same_artist_weight: f64,
+
+        /// Replace the layout with the best one, as if `layout --sort random`
+        /// had been run with its seed.
+        #[arg(long, default_value_t = false)]
+        commit_best: bool,
+    },
+    /// Show statistics about the art objects in the database, and how much disk
+    /// space the cache directory is using.
+    Stats,
+    /// Checkpoint the database's write-ahead log and optimize it.
+    DbMaintenance {
+        /// Also rebuild the database to reclaim space from deleted rows. This
+        /// needs exclusive access to the database, so the game shouldn't be
+        /// running, and temporarily needs up to twice its size in free disk space.
+        #[arg(long, default_value_t = false)]
+        vacuum: bool,
+    },
+    /// Estimate how many galleries a layout would need, without changing the layout.
+    EstimateLayout {
+        /// Filter artwork to only those matching this value.
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Whether to estimate a dense layout (stack some art vertically). Shorthand
+        /// for `--mode dense`.
+        #[arg(long = "dense", default_value_t = false, conflicts_with = "mode")]
+        use_dense_layout: bool,
+
+        /// How to arrange art on each wall, see `layout --mode`.
+        #[arg(long)]
+        mode: Option<LayoutMode>,
+
+        /// The space between art packed in a grid with `--mode salon`, in meters.
+        #[arg(long, default_value_t = DEFAULT_SALON_GUTTER)]
+        salon_gutter: f64,
+
+        /// Path to a walls JSON file. Defaults to the MoMA gallery's walls.
+        #[arg(long = "walls")]
+        walls: Option<PathBuf>,
+
+        /// Print the estimate as JSON.
+        #[arg(long, default_value_t = false)]
+        json: bool,
     },
     /// Show layout for the given gallery.
     ShowLayout {
         /// Gallery id to show.
         #[arg()]
         gallery_id: i64,
+
+        /// Path to a walls JSON file. Can be repeated. Defaults to the MoMA gallery's walls.
+        #[arg(long = "walls")]
+        walls: Vec<PathBuf>,
+
+        /// Print the layout as JSON instead of a table.
+        #[arg(long, default_value_t = false)]
+        json: bool,
     },
     /// Index QIDs in wikidata dump file.
     WikidataIndex {
@@ -120,9 +475,27 @@ enum Commands {
 
         #[arg(short, long)]
         seek_from: Option<u64>,
+
+        /// Expand the index file by explicitly writing zeros instead of creating a
+        /// sparse file. This is much slower and uses several gigabytes of disk
+        /// space, so only use it if your filesystem doesn't support sparse files.
+        #[arg(long, default_value_t = false)]
+        no_sparse: bool,
+    },
+    /// Update the index of a wikidata dump file with the entities in one or
+    /// more incremental dump files, which take precedence over the dump file.
+    WikidataUpdateIndex {
+        #[arg()]
+        dumpfile: PathBuf,
+
+        /// Incremental dump files, in the order they should be applied.
+        #[arg(required = true)]
+        incrementals: Vec<PathBuf>,
     },
     /// Prepare a query for later execution.
     WikidataPrepare {
+        /// With `--source api`, this doesn't need to exist, it just determines
+        /// where fetched entities are cached.
         #[arg()]
         dumpfile: PathBuf,
 
@@ -140,6 +513,15 @@ enum Commands {
         /// Log warnings about whether e.g. an item doesn't have required fields, or doesn't exist.
         #[arg(long, default_value_t = false)]
         warnings: bool,
+
+        /// Where to get entities from. The API is much more convenient for small
+        /// sets of QIDs, since it doesn't need the dumpfile to be downloaded.
+        #[arg(long, value_enum, default_value_t = WikidataSource::Dump)]
+        source: WikidataSource,
+
+        /// With `--source api`, the most requests to make to the API per second.
+        #[arg(long, default_value_t = DEFAULT_WIKIDATA_API_REQUESTS_PER_SECOND)]
+        requests_per_second: f64,
     },
     /// Execute a prepared wikidata query.
     WikidataExecute {
@@ -152,12 +534,68 @@ enum Commands {
         #[arg(short, long)]
         limit: Option<usize>,
     },
+    /// Show a single entity from a wikidata dump file (or its entity cache),
+    /// and whether it has the fields needed to be imported.
+    WikidataShow {
+        #[arg()]
+        dumpfile: PathBuf,
+
+        /// The entity's QID, e.g. 'Q42' or just '42'.
+        #[arg(value_parser = parse_qid)]
+        qid: u64,
+
+        /// Also print the entity's raw JSON.
+        #[arg(long, default_value_t = false)]
+        raw: bool,
+    },
+    /// Show statistics about the entity cache for a wikidata dump file.
+    WikidataCacheStats {
+        #[arg()]
+        dumpfile: PathBuf,
+    },
+    /// Remove entries from a wikidata dump file's entity cache that aren't
+    /// referenced by a prepared query. The kept entries are copied to a new
+    /// cache, which only replaces the original once its checksum matches.
+    WikidataCacheCompact {
+        /// The prepared query JSON.
+        #[arg()]
+        input: PathBuf,
+
+        /// The dumpfile whose cache should be compacted. Defaults to the
+        /// prepared query's dumpfile.
+        #[arg(long)]
+        dumpfile: Option<PathBuf>,
+
+        /// Refuse to compact the cache of a dumpfile other than the one the
+        /// prepared query references. Pass `--keep-all-dumps false` to override.
+        #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+        keep_all_dumps: bool,
+    },
+    /// Report how much of a prepared query made it into the database: how many
+    /// of its QIDs were imported, absorbed as the fallbacks of art objects from
+    /// other collections, quarantined, or are missing entirely, and how many of
+    /// the ones that made it are laid out in positive galleries.
+    PipelineReport {
+        /// The prepared query JSON.
+        #[arg()]
+        input: PathBuf,
+
+        /// Print the report as JSON.
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
     /// Export layout for non-positive galleries.
     ExportLayout {
         #[arg()]
         output: PathBuf,
+
+        /// Export the layout of every gallery, including positive ones. Note that
+        /// `import-layout` only makes sense for non-positive galleries.
+        #[arg(long, default_value_t = false)]
+        all: bool,
     },
-    /// Import layout for non-positive galleries.
+    /// Import layout for non-positive galleries. Walls can be referred to by
+    /// name or by `#<index>`, and records on walls that don't exist are skipped.
     ImportLayout {
         #[arg()]
         input: PathBuf,
@@ -165,34 +603,202 @@ enum Commands {
         /// Clear non-positive galleries before importing.
         #[arg(long, default_value_t = false)]
         clear: bool,
+
+        /// Path to a walls JSON file. Can be repeated. Defaults to the MoMA gallery's walls.
+        #[arg(long = "walls")]
+        walls: Vec<PathBuf>,
+    },
+    /// Check that every wall in the layout exists, exiting with an error if not.
+    ValidateLayout {
+        /// Path to a walls JSON file. Can be repeated. Defaults to the MoMA gallery's walls.
+        #[arg(long = "walls")]
+        walls: Vec<PathBuf>,
+    },
+    /// Write the walls JSON of a simple rectangular room, e.g. to bootstrap a
+    /// new room shape.
+    GenerateWalls {
+        /// The width of the back and front walls, in meters.
+        #[arg(long)]
+        width: f64,
+
+        /// The width of the side walls, in meters.
+        #[arg(long)]
+        depth: f64,
+
+        /// The height of every wall, in meters.
+        #[arg(long)]
+        height: f64,
+
+        /// The position of the wall with the doorway, counting clockwise from
+        /// the back wall at 0, e.g. 2 for the front wall. It's split into a
+        /// wall on either side of the doorway. If absent, there's no doorway.
+        #[arg(long)]
+        door_wall: Option<usize>,
+
+        /// Where to write the JSON. Defaults to standard output.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Export the art objects matching a filter, along with their layout, into
+    /// a fresh database. Name the output file after the default gallery DB
+    /// filename to drop it into another cache directory.
+    ExportSubset {
+        /// Filter to apply to art objects.
+        #[arg(long)]
+        filter: Option<String>,
+
+        /// Path of the database to create. Any existing tables in it are dropped.
+        #[arg(long)]
+        output_db: PathBuf,
+
+        /// Also copy the cached images (and API records) of the exported art
+        /// objects into this directory, using the same layout as the cache.
+        #[arg(long)]
+        include_images: Option<PathBuf>,
     },
     ConvertImage {
         #[arg()]
         filename: PathBuf,
     },
+    /// Re-fetch the Met API records of the Met art objects in the database, and
+    /// update the database with any changes to their titles, dates and sizes.
+    RefreshMetApi {
+        /// Maximum number of requests to make per second.
+        #[arg(long, default_value_t = DEFAULT_REQUESTS_PER_SECOND)]
+        requests_per_second: f64,
+
+        /// Maximum number of art objects to refresh.
+        #[arg(long)]
+        max: Option<usize>,
+
+        /// Path to the file that keeps track of which art object was refreshed
+        /// last, so an interrupted refresh resumes where it left off. Defaults to
+        /// a file in the cache directory.
+        #[arg(long)]
+        progress_file: Option<PathBuf>,
+
+        /// Ignore any saved progress and start from the first art object.
+        #[arg(long, default_value_t = false)]
+        restart: bool,
+    },
+    /// Store the image URLs of the Met art objects in the database, using their
+    /// Met API records, so the game doesn't need to fetch the records to fetch
+    /// their images. Only art objects whose URLs aren't stored yet are enriched.
+    EnrichMetImages {
+        /// Maximum number of requests to make per second.
+        #[arg(long, default_value_t = DEFAULT_REQUESTS_PER_SECOND)]
+        requests_per_second: f64,
+
+        /// Maximum number of art objects to enrich.
+        #[arg(long)]
+        max: Option<usize>,
+    },
+}
+
+impl Commands {
+    /// Whether the command only reads art objects and the layout from the
+    /// database, rather than (re)creating or changing them.
+    fn reads_gallery_db(&self) -> bool {
+        matches!(
+            self,
+            Commands::ListQuarantined
+                | Commands::Stats
+                | Commands::EstimateLayout { .. }
+                | Commands::ShowLayout { .. }
+                | Commands::ExportLayout { .. }
+                | Commands::ValidateLayout { .. }
+                | Commands::ExportSubset { .. }
+                | Commands::PipelineReport { .. }
+        )
+    }
+}
+
+fn parse_qid(value: &str) -> Result<u64, String> {
+    let qid = value.strip_prefix('Q').unwrap_or(value);
+    qid.parse::<u64>()
+        .map_err(|err| format!("{value:?} is not a valid QID: {err}"))
+}
+
+/// Parses an inclusive range of positive gallery IDs, e.g. `1-3`.
+fn parse_gallery_id_range(value: &str) -> Result<(i64, i64), String> {
+    let invalid = || format!("{value:?} is not a range of galleries like 1-3");
+    let (first, last) = value.split_once('-').ok_or_else(invalid)?;
+    let first = first.trim().parse::<i64>().map_err(|_| invalid())?;
+    let last = last.trim().parse::<i64>().map_err(|_| invalid())?;
+    check_positive_gallery_range(first, last).map_err(|err| err.to_string())?;
+    Ok((first, last))
 }
 
 fn run() -> Result<()> {
     let args = Args::parse();
-    let manifest_dir: PathBuf = env!("CARGO_MANIFEST_DIR").into();
-    let cache_dir = manifest_dir.join("..").join("cache");
-    let cache = GalleryCache::new(cache_dir);
+    init_logger(level_for_flags(args.verbose, args.quiet));
+    let search_paths = SearchPaths::from_env(|name| env::var_os(name));
+    let cache_dir = search_paths.resolve_cache_dir(args.cache_dir, |name| env::var_os(name));
+    debug!("Using cache directory {}.", cache_dir.display());
+    let walls_or_default = |walls: Vec<PathBuf>| -> Result<Vec<PathBuf>> {
+        if walls.is_empty() {
+            Ok(vec![search_paths.default_walls_path(&cache_dir)?])
+        } else {
+            Ok(walls)
+        }
+    };
+    let cache = GalleryCache::new(cache_dir.clone());
     let db_path = if let Some(db_path) = &args.db_path {
         db_path.clone()
     } else {
         cache.get_cached_path(get_default_gallery_db_filename())
     };
-    let db = GalleryDb::new(Connection::open(db_path)?);
+    if let Commands::Demo { force } = args.command {
+        // The demo starts from scratch, so it mustn't adopt or migrate anything.
+        return demo_command(&search_paths.dev_demo_dir, &db_path, force);
+    }
+    if args.db_path.is_none() {
+        if let Some(version) = adopt_older_gallery_db(&cache)? {
+            println!(
+                "Copied version {version} of the database to {} and migrated it.",
+                db_path.display()
+            );
+        }
+    }
+    let mut conn = Connection::open(&db_path)?;
+    let unversioned_version = get_unversioned_gallery_db_version(&db_path);
+    if let Some(from_version) = migrate_gallery_db_schema(&mut conn, unversioned_version)? {
+        println!(
+            "Migrated {} from schema version {from_version} to {LATEST_GALLERY_DB_VERSION}.",
+            db_path.display()
+        );
+    }
+    let db = GalleryDb::new(conn);
+    if args.command.reads_gallery_db() {
+        // Better to say what's wrong up front than to fail with a cryptic
+        // error partway through.
+        db.check_schema()?
+            .to_result()
+            .map_err(|err| anyhow!("{err}: {}", db_path.display()))?;
+    }
     match args.command {
         Commands::ConvertImage { filename } => convert_image_command(filename),
+        Commands::Demo { .. } => unreachable!("the demo is built before the database is opened"),
         Commands::Csv {
             met_objects_path,
             wikidata_objects_path,
             max,
             met_objects_all_media,
             warnings,
+            pd_policy,
+            dry_run,
+            summary_json,
+            skipped_report,
+            min_side,
+            max_side,
+            max_aspect_ratio,
+            incremental,
+            prefer_met,
+            batch_size,
+            parallel,
+            fail_on_duplicates,
+            max_parse_error_fraction,
         } => csv_command(
-            args.verbose,
             met_objects_path,
             wikidata_objects_path,
             cache,
@@ -200,43 +806,211 @@ fn run() -> Result<()> {
             max,
             met_objects_all_media,
             warnings,
+            pd_policy,
+            dry_run,
+            summary_json,
+            skipped_report,
+            DimensionLimits {
+                min_side,
+                max_side,
+                max_aspect_ratio,
+            },
+            incremental,
+            prefer_met,
+            batch_size,
+            parallel,
+            fail_on_duplicates,
+            max_parse_error_fraction,
         ),
         Commands::Layout {
             clear,
             sort,
             random_seed,
             use_dense_layout,
+            mode,
+            salon_gutter,
             filter,
             warnings,
+            walls,
+            fail_on_unplaceable,
+            ordering_json,
+            reserved_walls,
+            segments,
+            featured_first,
+            allow_rotation,
+            balance_by,
+            collection_weights,
+            free_regions_json,
+            min_free_region_width,
+            exclude_previously_displayed,
+            gallery_id_range,
         } => layout_command(
             db,
+            walls_or_default(walls)?,
             clear,
             sort,
             random_seed,
-            use_dense_layout,
+            get_layout_mode(use_dense_layout, mode, salon_gutter),
             filter,
-            args.verbose,
             warnings,
+            fail_on_unplaceable,
+            ordering_json,
+            reserved_walls,
+            segments,
+            featured_first,
+            allow_rotation,
+            balance_by,
+            collection_weights,
+            free_regions_json,
+            min_free_region_width,
+            exclude_previously_displayed,
+            gallery_id_range,
+        ),
+        Commands::ExploreSeeds {
+            candidates,
+            random_seed,
+            filter,
+            use_dense_layout,
+            mode,
+            salon_gutter,
+            walls,
+            reserved_walls,
+            featured_first,
+            allow_rotation,
+            balance_by,
+            collection_weights,
+            occupancy_variance_weight,
+            near_empty_wall_weight,
+            unplaceable_weight,
+            same_artist_weight,
+            commit_best,
+        } => explore_seeds_command(
+            db,
+            walls_or_default(walls)?,
+            candidates,
+            random_seed,
+            get_layout_mode(use_dense_layout, mode, salon_gutter),
+            filter,
+            reserved_walls,
+            featured_first,
+            allow_rotation,
+            balance_by,
+            collection_weights,
+            LayoutScoreWeights {
+                occupancy_variance: occupancy_variance_weight,
+                near_empty_walls: near_empty_wall_weight,
+                unplaceable: unplaceable_weight,
+                same_artist_neighbors: same_artist_weight,
+            },
+            commit_best,
         ),
-        Commands::ShowLayout { gallery_id } => show_layout_command(db, gallery_id),
+        Commands::Stats => stats_command(db, &cache),
+        Commands::ListQuarantined => list_quarantined_command(db),
+        Commands::NormalizeArtists => normalize_artists_command(db),
+        Commands::DbMaintenance { vacuum } => db_maintenance_command(db, vacuum),
+        Commands::EstimateLayout {
+            filter,
+            use_dense_layout,
+            mode,
+            salon_gutter,
+            walls,
+            json,
+        } => {
+            let walls = walls_or_default(walls.into_iter().collect())?;
+            let mode = get_layout_mode(use_dense_layout, mode, salon_gutter);
+            estimate_layout_command(db, &walls[0], filter, mode, json)
+        }
+        Commands::ShowLayout {
+            gallery_id,
+            walls,
+            json,
+        } => show_layout_command(db, gallery_id, walls_or_default(walls)?, json),
         Commands::WikidataIndex {
             dumpfile,
             seek_from,
-        } => index_wikidata_dump(dumpfile, seek_from),
+            no_sparse,
+        } => index_wikidata_dump(dumpfile, seek_from, !no_sparse),
+        Commands::WikidataUpdateIndex {
+            dumpfile,
+            incrementals,
+        } => update_wikidata_index(dumpfile, incrementals),
         Commands::WikidataPrepare {
             output,
             dumpfile,
             qids,
             csv,
             warnings,
-        } => prepare_wikidata_query(output, dumpfile, qids, csv, args.verbose, warnings),
+            source,
+            requests_per_second,
+        } => {
+            let mut api = match source {
+                WikidataSource::Dump => None,
+                WikidataSource::Api => Some(WikidataApiFetcher::new(&cache, requests_per_second)?),
+            };
+            prepare_wikidata_query(output, dumpfile, qids, csv, warnings, api.as_mut())
+        }
         Commands::WikidataExecute {
             input,
             output,
             limit,
         } => execute_wikidata_query(input, output, limit),
-        Commands::ExportLayout { output } => export_layout(db, output),
-        Commands::ImportLayout { input, clear } => import_layout(db, input, clear),
+        Commands::WikidataShow { dumpfile, qid, raw } => show_wikidata_entity(dumpfile, qid, raw),
+        Commands::WikidataCacheStats { dumpfile } => show_wikidata_cache_stats(dumpfile),
+        Commands::WikidataCacheCompact {
+            input,
+            dumpfile,
+            keep_all_dumps,
+        } => compact_wikidata_cache(input, dumpfile, keep_all_dumps),
+        Commands::PipelineReport { input, json } => pipeline_report_command(input, db, json),
+        Commands::ExportLayout { output, all } => export_layout(db, output, all),
+        Commands::ImportLayout {
+            input,
+            clear,
+            walls,
+        } => import_layout(db, input, clear, walls_or_default(walls)?),
+        Commands::ValidateLayout { walls } => validate_layout_command(db, walls_or_default(walls)?),
+        Commands::GenerateWalls {
+            width,
+            depth,
+            height,
+            door_wall,
+            output,
+        } => generate_walls_command(width, depth, height, door_wall, output),
+        Commands::ExportSubset {
+            filter,
+            output_db,
+            include_images,
+        } => export_subset_command(db, cache, filter, output_db, include_images),
+        Commands::RefreshMetApi {
+            requests_per_second,
+            max,
+            progress_file,
+            restart,
+        } => {
+            let progress_path =
+                progress_file.unwrap_or_else(|| cache.get_cached_path(DEFAULT_PROGRESS_FILENAME));
+            refresh_met_api_command(
+                db,
+                cache,
+                RefreshOptions {
+                    requests_per_second,
+                    max,
+                    progress_path,
+                    restart,
+                },
+            )
+        }
+        Commands::EnrichMetImages {
+            requests_per_second,
+            max,
+        } => enrich_met_images_command(
+            db,
+            cache,
+            EnrichOptions {
+                requests_per_second,
+                max,
+            },
+        ),
     }
 }
 
@@ -245,114 +1019,996 @@ fn convert_image_command(filename: PathBuf) -> Result<()> {
         println!("Filename is not a supported image format.");
         return Ok(());
     };
-    if maybe_convert_image_for_loading_in_godot(&filename, ext)? {
-        println!("Conversion complete.");
-    } else {
-        println!("No conversion necessary.");
+    match maybe_convert_image_for_loading_in_godot(&filename, ext)? {
+        ImageConversionResult::Converted(steps) => {
+            for step in steps {
+                println!("  {step}");
+            }
+            println!("Conversion complete.");
+        }
+        ImageConversionResult::Unchanged => println!("No conversion necessary."),
+    }
+    Ok(())
+}
+
+fn demo_command(demo_dir: &Path, db_path: &Path, force: bool) -> Result<()> {
+    if !demo_dir.is_dir() {
+        return Err(anyhow!(
+            "Unable to find the demo dataset at {}, it's only available in a checkout of the repository.",
+            demo_dir.display()
+        ));
+    }
+    if db_path.exists() {
+        if !force {
+            return Err(anyhow!(
+                "{} already exists, pass --force to replace it with the demo database.",
+                db_path.display()
+            ));
+        }
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = db_path.as_os_str().to_owned();
+            path.push(suffix);
+            let path = PathBuf::from(path);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
     }
+    build_demo_db(demo_dir, db_path)?;
+    println!("Wrote the demo database to {}.", db_path.display());
+    println!(
+        "When run from the Godot editor, the game uses {} in rust/cache.",
+        get_default_gallery_db_filename()
+    );
     Ok(())
 }
 
-fn export_layout(mut db: GalleryDb, output: PathBuf) -> Result<()> {
-    let records = db.get_layout_records_in_non_positive_galleries()?;
-    let json = serde_json::to_string_pretty(&records)?;
-    fs::write(&output, json)?;
-    println!("Wrote {}.", output.display());
+/// Imports the demo dataset in `demo_dir` into a new database at `db_path` and
+/// lays it out. This deliberately goes through the same code as the `csv` and
+/// `layout` commands, so that it doubles as an end-to-end test of them.
+fn build_demo_db(demo_dir: &Path, db_path: &Path) -> Result<()> {
+    csv_command(
+        Some(demo_dir.join(DEMO_MET_OBJECTS_FILENAME)),
+        Some(demo_dir.join(DEMO_WIKIDATA_OBJECTS_FILENAME)),
+        GalleryCache::new_offline(demo_dir.to_path_buf()),
+        GalleryDb::new(Connection::open(db_path)?),
+        None,
+        false,
+        false,
+        PublicDomainPolicy::Permissive,
+        false,
+        None,
+        None,
+        DimensionLimits::default(),
+        false,
+        false,
+        TRANSACTION_BATCH_SIZE,
+        false,
+        true,
+        DEFAULT_MAX_PARSE_ERROR_FRACTION,
+    )?;
+    layout_command(
+        GalleryDb::new(Connection::open(db_path)?),
+        vec![demo_dir.join(DEMO_WALLS_FILENAME)],
+        false,
+        None,
+        None,
+        LayoutMode::Sparse,
+        None,
+        false,
+        true,
+        None,
+        vec![],
+        vec![],
+        false,
+        false,
+        None,
+        vec![],
+        None,
+        DEFAULT_MIN_FREE_REGION_WIDTH,
+        false,
+        None,
+    )
+}
+
+fn export_layout(mut db: GalleryDb, output: PathBuf, all: bool) -> Result<()> {
+    let scope = if all {
+        LayoutScope::All
+    } else {
+        LayoutScope::NonPositive
+    };
+    let count = db.export_layout_records_to_writer(File::create(&output)?, scope)?;
+    println!("Wrote {count} layout record(s) to {}.", output.display());
     Ok(())
 }
 
-fn import_layout(mut db: GalleryDb, input: PathBuf, clear: bool) -> Result<()> {
+fn import_layout(
+    mut db: GalleryDb,
+    input: PathBuf,
+    clear: bool,
+    walls: Vec<PathBuf>,
+) -> Result<()> {
+    let wall_sets = get_wall_sets(walls)?;
     let json = fs::read_to_string(input)?;
     let records: Vec<LayoutRecord<String>> = serde_json::from_str(&json)?;
+    let resolved = resolve_layout_wall_ids(records, &wall_sets);
+    for (wall_id, count) in &resolved.unknown_wall_ids {
+        warn!("Skipping {count} art object(s) on unknown wall {wall_id:?}.");
+    }
     if clear {
         db.clear_layout_records_in_non_positive_galleries()?;
     }
-    db.upsert_layout_records(&records)?;
-    println!("Imported layout containing {} art objects.", records.len());
+    db.upsert_layout_records(&resolved.records)?;
+    println!(
+        "Imported layout containing {} art objects.",
+        resolved.records.len()
+    );
     Ok(())
 }
 
-fn get_walls() -> Result<Vec<GalleryWall>> {
-    let manifest_dir: PathBuf = env!("CARGO_MANIFEST_DIR").into();
-    let walls_json_file = manifest_dir
-        .join("..")
-        .join("..")
-        .join("Levels")
-        .join("moma-gallery.walls.json");
-    let walls: Vec<GalleryWall> = serde_json::from_str(&fs::read_to_string(walls_json_file)?)?;
-    Ok(walls)
+fn validate_layout_command(db: GalleryDb, walls: Vec<PathBuf>) -> Result<()> {
+    let wall_sets = get_wall_sets(walls)?;
+    let wall_ids = db.get_distinct_wall_ids()?;
+    let unknown_wall_ids: Vec<&String> = wall_ids
+        .iter()
+        .filter(|wall_id| {
+            !wall_sets
+                .iter()
+                .any(|wall_set| wall_set.walls.iter().any(|wall| &&wall.name == wall_id))
+        })
+        .collect();
+    for wall_id in &unknown_wall_ids {
+        println!("Unknown wall: {wall_id:?}");
+    }
+    if !unknown_wall_ids.is_empty() {
+        return Err(anyhow!(
+            "{} of the layout's {} walls don't exist.",
+            unknown_wall_ids.len(),
+            wall_ids.len()
+        ));
+    }
+    println!("All {} of the layout's walls exist.", wall_ids.len());
+    Ok(())
 }
 
-fn show_layout_command(db: GalleryDb, gallery_id: i64) -> Result<()> {
-    let walls = get_walls()?;
-    for wall in walls {
-        println!("Wall {}:", wall.name);
-        for (object, layout) in db.get_art_objects_for_gallery_wall(gallery_id, wall.name)? {
-            println!("  {:?} {:?}", object, layout);
+fn generate_walls_command(
+    width: f64,
+    depth: f64,
+    height: f64,
+    door_wall: Option<usize>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let walls = generate_room_walls(width, depth, height, door_wall)?;
+    let json = serde_json::to_string_pretty(&walls)?;
+    match output {
+        Some(output) => {
+            fs::write(&output, json)?;
+            println!("Wrote {} walls to {}.", walls.len(), output.display());
         }
+        None => println!("{json}"),
     }
     Ok(())
 }
 
-fn layout_command(
-    mut db: GalleryDb,
-    clear: bool,
-    sort: Option<Sort>,
-    random_seed: Option<u64>,
-    use_dense_layout: bool,
+fn export_subset_command(
+    db: GalleryDb,
+    cache: GalleryCache,
     filter: Option<String>,
-    verbose: bool,
-    warnings: bool,
+    output_db: PathBuf,
+    include_images: Option<PathBuf>,
 ) -> Result<()> {
-    let walls = get_walls()?;
-    db.reset_layout_table()?;
+    let mut output = GalleryDb::new(Connection::open(&output_db)?);
+    let exported = export_subset(
+        &db,
+        &ArtObjectQueryOptions {
+            filter,
+            ..Default::default()
+        },
+        &mut output,
+    )?;
+    println!(
+        "Exported {} art objects, {} artists, {} layout records and {} galleries to {}.",
+        exported.art_objects.len(),
+        exported.artists,
+        exported.layout_records,
+        exported.galleries,
+        output_db.display()
+    );
+    if let Some(dest_dir) = include_images {
+        let copied = copy_cached_files_for_art_objects(&cache, &exported.art_objects, &dest_dir)?;
+        println!("Copied {copied} cached files to {}.", dest_dir.display());
+    }
+    Ok(())
+}
 
-    let options = ArtObjectQueryOptions {
-        filter,
+fn refresh_met_api_command(
+    mut db: GalleryDb,
+    cache: GalleryCache,
+    options: RefreshOptions,
+) -> Result<()> {
+    let report = refresh_met_api(&mut db, &cache, &options)?;
+    for object in &report.changed {
+        println!("{}:", ArtObjectId::Met(object.object_id).url());
+        for change in &object.changes {
+            println!("  {change}");
+        }
+    }
+    for (object_id, status) in &report.failed {
+        println!("Unable to fetch object #{object_id} (HTTP {status}).");
+    }
+    println!(
+        "Checked {} art objects ({} unmodified upstream), {} changed, {} failed.",
+        report.checked,
+        report.not_modified,
+        report.changed.len(),
+        report.failed.len()
+    );
+    Ok(())
+}
+
+fn enrich_met_images_command(
+    mut db: GalleryDb,
+    cache: GalleryCache,
+    options: EnrichOptions,
+) -> Result<()> {
+    let report = enrich_met_images(&mut db, &cache, &options)?;
+    for (object_id, status) in &report.failed {
+        println!("Unable to fetch object #{object_id} (HTTP {status}).");
+    }
+    println!(
+        "Enriched {} art objects ({} fetched from the Met API), {} failed.",
+        report.enriched,
+        report.fetched,
+        report.failed.len()
+    );
+    Ok(())
+}
+
+fn get_walls(walls_json_file: &PathBuf) -> Result<Vec<GalleryWall>> {
+    let walls: Vec<GalleryWall> = serde_json::from_str(&fs::read_to_string(walls_json_file)?)?;
+    check_walls(&walls_json_file.display().to_string(), &walls)?;
+    Ok(walls)
+}
+
+/// Load the wall set for each of the given walls JSON files, naming each one after
+/// its filename (e.g. `moma-gallery.walls.json` is named `moma-gallery`).
+///
+/// Galleries only record the name of their wall set, so files that would give
+/// two wall sets the same name, e.g. `a/room.json` and `b/room.walls.json`, are
+/// rejected.
+fn get_wall_sets(walls_json_files: Vec<PathBuf>) -> Result<Vec<GalleryWallSet>> {
+    let mut wall_sets = Vec::with_capacity(walls_json_files.len());
+    let mut files_by_name: HashMap<String, PathBuf> = HashMap::new();
+    for walls_json_file in walls_json_files {
+        let filename = walls_json_file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = filename
+            .strip_suffix(".walls.json")
+            .or_else(|| filename.strip_suffix(".json"))
+            .unwrap_or(&filename)
+            .to_string();
+        if let Some(other_file) = files_by_name.insert(name.clone(), walls_json_file.clone()) {
+            return Err(anyhow!(
+                "{} and {} would both be named wall set {name:?}, please rename one of them",
+                other_file.display(),
+                walls_json_file.display()
+            ));
+        }
+        wall_sets.push(GalleryWallSet::new(name, get_walls(&walls_json_file)?));
+    }
+    Ok(wall_sets)
+}
+
+fn stats_command(db: GalleryDb, cache: &GalleryCache) -> Result<()> {
+    let total = db.count_art_objects(&Default::default())?;
+    println!("{total} art objects.");
+    println!("By medium category:");
+    for (category, count) in db.count_art_objects_by_medium_category()? {
+        println!("  {:<12} {count:>8}", category.as_str());
+    }
+    print_collection_stats(&db)?;
+    print!("{}", CacheUsageReport::for_dir(cache.cache_dir())?);
+    Ok(())
+}
+
+fn print_collection_stats(db: &GalleryDb) -> Result<()> {
+    let collections = db.list_collections()?;
+    println!("By collection ({} total):", collections.len());
+    for collection in collections {
+        println!(
+            "  {:<50} {:>8} ({})",
+            collection.name,
+            collection.object_count,
+            collection.source.as_str()
+        );
+    }
+    Ok(())
+}
+
+fn list_quarantined_command(db: GalleryDb) -> Result<()> {
+    let quarantined = db.list_quarantined_objects()?;
+    for record in &quarantined {
+        println!(
+            "{:?}: {:.3} x {:.3} m, {} - {:?} by {:?} ({})",
+            record.object_id,
+            record.width,
+            record.height,
+            record.reason,
+            record.title,
+            record.artist,
+            record.collection
+        );
+    }
+    println!("{} quarantined art objects.", quarantined.len());
+    Ok(())
+}
+
+fn normalize_artists_command(mut db: GalleryDb) -> Result<()> {
+    let report = db.normalize_artists()?;
+    println!(
+        "Changed the artists of {} art objects, merging {} artists.",
+        report.changed_rows, report.merged_artists
+    );
+    Ok(())
+}
+
+fn db_maintenance_command(mut db: GalleryDb, vacuum: bool) -> Result<()> {
+    if vacuum {
+        println!("Vacuuming database, this may take a while.");
+    }
+    let report = db.maintenance(vacuum)?;
+    let format_size = |size: Option<u64>| match size {
+        Some(size) => format!("{size} bytes"),
+        None => "unknown".to_string(),
+    };
+    println!(
+        "Database size: {} -> {}",
+        format_size(report.db_size_before),
+        format_size(report.db_size_after)
+    );
+    println!(
+        "Write-ahead log size: {} -> {}",
+        format_size(report.wal_size_before),
+        format_size(report.wal_size_after)
+    );
+    println!("Done.");
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ShownLayout {
+    gallery_id: i64,
+    wall_set: String,
+    segment: Option<String>,
+    source_gallery: Option<String>,
+    walls: Vec<ShownWall>,
+}
+
+#[derive(Serialize)]
+struct ShownWall {
+    wall_id: String,
+    reserved: bool,
+    art_objects: Vec<PlacedArtObject>,
+}
+
+const SHOW_LAYOUT_TITLE_WIDTH: usize = 40;
+
+const SHOW_LAYOUT_ARTIST_WIDTH: usize = 24;
+
+/// Shortens the given string to at most `width` characters, marking it with an
+/// ellipsis if anything was cut off.
+fn truncate_for_table(value: &str, width: usize) -> String {
+    if value.chars().count() <= width {
+        value.to_string()
+    } else {
+        let mut truncated: String = value.chars().take(width.saturating_sub(1)).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+fn print_wall_table(wall: &ShownWall) {
+    if wall.reserved {
+        println!("Wall {} (reserved):", wall.wall_id);
+    } else {
+        println!("Wall {}:", wall.wall_id);
+    }
+    if wall.art_objects.is_empty() {
+        println!("  (empty)");
+        return;
+    }
+    println!(
+        "  {:<title_width$} {:<artist_width$} {:>15} {:>7} {:>7}",
+        "Title",
+        "Artist",
+        "Size (cm)",
+        "x (m)",
+        "y (m)",
+        title_width = SHOW_LAYOUT_TITLE_WIDTH,
+        artist_width = SHOW_LAYOUT_ARTIST_WIDTH
+    );
+    for object in &wall.art_objects {
+        let size = format!("{:.1} x {:.1}", object.width * 100.0, object.height * 100.0);
+        println!(
+            "  {:<title_width$} {:<artist_width$} {:>15} {:>7.2} {:>7.2}",
+            truncate_for_table(&object.title, SHOW_LAYOUT_TITLE_WIDTH),
+            truncate_for_table(&object.artist, SHOW_LAYOUT_ARTIST_WIDTH),
+            size,
+            object.x,
+            object.y,
+            title_width = SHOW_LAYOUT_TITLE_WIDTH,
+            artist_width = SHOW_LAYOUT_ARTIST_WIDTH
+        );
+    }
+}
+
+/// Returns the layout mode that the `--dense`, `--mode` and `--salon-gutter`
+/// arguments ask for.
+fn get_layout_mode(
+    use_dense_layout: bool,
+    mode: Option<LayoutMode>,
+    salon_gutter: f64,
+) -> LayoutMode {
+    match mode.unwrap_or(LayoutMode::from_dense(use_dense_layout)) {
+        LayoutMode::Salon { .. } => LayoutMode::Salon {
+            gutter: salon_gutter,
+        },
+        mode => mode,
+    }
+}
+
+/// Estimates the layout that `layout_command()` would create with the same
+/// filter and walls (and no other options), see `estimate_layout()`.
+fn get_layout_estimate(
+    db: &GalleryDb,
+    walls: &PathBuf,
+    filter: Option<String>,
+    mode: LayoutMode,
+) -> Result<LayoutEstimate> {
+    let options = ArtObjectQueryOptions {
+        filter,
         ..Default::default()
     };
+    db.where_clause(&options)
+        .map_err(|err| anyhow!("Invalid filter: {err}"))?;
+    let art_objects = db.get_all_art_objects_for_layout(&options)?;
+    estimate_layout(&get_walls(walls)?, &art_objects, mode)
+}
+
+fn estimate_layout_command(
+    db: GalleryDb,
+    walls: &PathBuf,
+    filter: Option<String>,
+    mode: LayoutMode,
+    json: bool,
+) -> Result<()> {
+    let estimate = get_layout_estimate(&db, walls, filter, mode)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&estimate)?);
+        return Ok(());
+    }
+    println!(
+        "The layout would need {} galleries, with an average of {:.1} art objects each.",
+        estimate.galleries, estimate.average_art_objects_per_gallery
+    );
+    if estimate.unplaceable > 0 {
+        println!(
+            "{} art objects are too big to fit on any walls.",
+            estimate.unplaceable
+        );
+    }
+    Ok(())
+}
+
+fn show_layout_command(
+    db: GalleryDb,
+    gallery_id: i64,
+    walls: Vec<PathBuf>,
+    json: bool,
+) -> Result<()> {
+    let wall_sets = get_wall_sets(walls)?;
+    let gallery = db.get_gallery_record(gallery_id)?;
+    let wall_set = match &gallery {
+        Some(gallery) => wall_sets
+            .into_iter()
+            .find(|wall_set| wall_set.name == gallery.wall_set),
+        None => wall_sets.into_iter().next(),
+    };
+    let (reserved_walls, segment, source_gallery) = gallery
+        .map(|gallery| {
+            (
+                gallery.reserved_walls,
+                gallery.segment,
+                gallery.source_gallery,
+            )
+        })
+        .unwrap_or_default();
+    let Some(wall_set) = wall_set else {
+        if json {
+            return Err(anyhow!("Unable to find wall set for gallery {gallery_id}."));
+        }
+        println!("Unable to find wall set for gallery {gallery_id}.");
+        return Ok(());
+    };
+    let mut shown_walls = Vec::with_capacity(wall_set.walls.len());
+    for wall in wall_set.walls {
+        let reserved = reserved_walls.contains(&wall.name);
+        let art_objects = db
+            .get_art_objects_for_gallery_wall(gallery_id, wall.name.clone())?
+            .into_iter()
+            .map(PlacedArtObject::from)
+            .collect();
+        shown_walls.push(ShownWall {
+            wall_id: wall.name,
+            reserved,
+            art_objects,
+        });
+    }
+    let layout = ShownLayout {
+        gallery_id,
+        wall_set: wall_set.name,
+        segment,
+        source_gallery,
+        walls: shown_walls,
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&layout)?);
+        return Ok(());
+    }
+    println!("Gallery {gallery_id} uses wall set {}.", layout.wall_set);
+    if let Some(segment) = &layout.segment {
+        println!("Gallery {gallery_id} is in segment {segment:?}.");
+    }
+    if let Some(source_gallery) = &layout.source_gallery {
+        println!("Gallery {gallery_id} recreates source gallery {source_gallery:?}.");
+    }
+    for wall in &layout.walls {
+        print_wall_table(wall);
+    }
+    Ok(())
+}
+
+/// Puts the art objects in the order they'll be laid out in, shuffling them if
+/// there's an RNG.
+fn arrange_art_objects(
+    mut art_objects: Vec<ArtObjectLayoutInfo>,
+    balance_by: Option<BalanceBy>,
+    collection_weights: &[CollectionWeight],
+    rng: Option<&mut Rng>,
+    featured_first: bool,
+) -> Vec<ArtObjectLayoutInfo> {
+    match balance_by {
+        Some(BalanceBy::Collection) => {
+            art_objects = balance_by_collection(art_objects, collection_weights, rng);
+        }
+        None => {
+            if let Some(rng) = rng {
+                rng.shuffle(&mut art_objects);
+            }
+        }
+    }
+    if featured_first {
+        move_highlights_to_front(&mut art_objects);
+    }
+    art_objects
+}
+
+fn layout_command(
+    mut db: GalleryDb,
+    walls: Vec<PathBuf>,
+    clear: bool,
+    sort: Option<Sort>,
+    random_seed: Option<u64>,
+    mode: LayoutMode,
+    filter: Option<String>,
+    warnings: bool,
+    fail_on_unplaceable: bool,
+    ordering_json: Option<PathBuf>,
+    reserved_walls: Vec<String>,
+    segments: Vec<LayoutSegment>,
+    featured_first: bool,
+    allow_rotation: bool,
+    balance_by: Option<BalanceBy>,
+    collection_weights: Vec<CollectionWeight>,
+    free_regions_json: Option<PathBuf>,
+    min_free_region_width: f64,
+    exclude_previously_displayed: bool,
+    gallery_id_range: Option<(i64, i64)>,
+) -> Result<()> {
+    if !collection_weights.is_empty() && balance_by.is_none() {
+        return Err(anyhow!(
+            "--collection-weight only makes sense with --balance-by collection"
+        ));
+    }
+    if mode == LayoutMode::RealGallery && !segments.is_empty() {
+        return Err(anyhow!(
+            "--segment can't be used with --mode real-gallery, which has its own galleries"
+        ));
+    }
+    let wall_sets = get_wall_sets(walls)?;
+    let ordering: Option<Vec<ArtObjectId>> = match ordering_json {
+        Some(path) => Some(serde_json::from_str(&fs::read_to_string(path)?)?),
+        None => None,
+    };
 
-    if verbose && options.filter.is_some() {
-        let (query, params) = options.where_clause();
-        println!("Filter SQL: {query}");
+    let options = ArtObjectQueryOptions {
+        filter,
+        exclude_previously_displayed,
+    };
+
+    // Check the filter up front, so a broken one is reported before we do any work.
+    let (query, params) = db
+        .where_clause(&options)
+        .map_err(|err| anyhow!("Invalid filter: {err}"))?;
+    if options.filter.is_some() {
+        debug!("Filter SQL: {query}");
         for (id, param) in params.iter().enumerate() {
-            println!("Param #{}: {:?}", id + 1, param)
+            debug!("Param #{}: {:?}", id + 1, param)
         }
     }
 
-    let mut art_objects = if clear {
-        vec![]
+    let mut rng = match sort {
+        Some(Sort::Random) => {
+            let rng = Rng::new(random_seed);
+            println!("Randomizing layout using seed {}.", rng.seed);
+            Some(rng)
+        }
+        _ => None,
+    };
+    let random_seed = rng.as_ref().map(|rng| rng.seed);
+    let hanging_elsewhere = match gallery_id_range {
+        Some(range) => db.get_art_object_ids_outside_gallery_range(range)?,
+        None => HashSet::new(),
+    };
+    let first_gallery_id = match gallery_id_range {
+        Some((first_gallery_id, _)) => first_gallery_id,
+        None => LAYOUT_START_GALLERY_ID,
+    };
+    let mut get_art_objects = |options: &ArtObjectQueryOptions| -> Result<_> {
+        let mut art_objects = if clear {
+            vec![]
+        } else {
+            db.get_all_art_objects_for_layout(options)?
+        };
+        art_objects.retain(|object| !hanging_elsewhere.contains(&object.id));
+        Ok(arrange_art_objects(
+            art_objects,
+            balance_by,
+            &collection_weights,
+            rng.as_mut(),
+            featured_first,
+        ))
+    };
+
+    let layout_options = LayoutOptions {
+        mode,
+        gallery_start_id: first_gallery_id,
+        ordering: ordering.as_deref(),
+        reserved_walls: &reserved_walls,
+        allow_rotation,
+        collect_free_regions: free_regions_json.is_some(),
+        min_free_region_width,
+        warnings,
+        ..Default::default()
+    };
+    let mut result = if mode == LayoutMode::RealGallery {
+        let art_objects = get_art_objects(&options)?;
+        let source_galleries = db.get_source_galleries_by_id()?;
+        println!(
+            "Laying out {} art objects across their source galleries using {} wall set(s).",
+            art_objects.len(),
+            wall_sets.len()
+        );
+        layout_source_galleries(&wall_sets, art_objects, &source_galleries, &layout_options)?
+    } else if segments.is_empty() {
+        let art_objects = get_art_objects(&options)?;
+        println!(
+            "Laying out {} art objects across galleries using {} wall set(s).",
+            art_objects.len(),
+            wall_sets.len()
+        );
+        layout(&wall_sets, art_objects, &layout_options)?
     } else {
-        db.get_all_art_objects_for_layout(&options)?
+        let mut segment_art_objects = Vec::with_capacity(segments.len());
+        for segment in segments {
+            let segment_options = ArtObjectQueryOptions {
+                filter: segment.combined_filter(options.filter.as_deref()),
+                exclude_previously_displayed,
+            };
+            let art_objects = get_art_objects(&segment_options)?;
+            println!(
+                "Segment {:?} matches {} art objects.",
+                segment.name,
+                art_objects.len()
+            );
+            segment_art_objects.push((segment.name, art_objects));
+        }
+        println!(
+            "Laying out {} segment(s) across galleries using {} wall set(s).",
+            segment_art_objects.len(),
+            wall_sets.len()
+        );
+        layout_segments(&wall_sets, segment_art_objects, &layout_options)?
     };
-    if matches!(sort, Some(Sort::Random)) {
-        let mut rng = Rng::new(random_seed);
-        println!("Randomizing layout using seed {}.", rng.seed);
-        rng.shuffle(&mut art_objects);
+
+    if let Some((_, last_gallery_id)) = gallery_id_range {
+        result.truncate_galleries(last_gallery_id);
+    }
+
+    let unmatched = &result.unmatched_ordering_ids;
+    if unmatched.len() > 0 {
+        println!(
+            "{} art object(s) in the ordering don't exist or don't match the filter:",
+            unmatched.len()
+        );
+        for id in unmatched {
+            println!("  {:?}", id);
+        }
+    }
+
+    let dropped = &result.dropped_source_galleries;
+    if dropped.len() > 0 {
+        println!(
+            "{} source gallery(s) have too much art to fit in one gallery, leaving them out:",
+            dropped.len()
+        );
+        for source_gallery in dropped {
+            println!("  {}", source_gallery);
+        }
+    }
+
+    let unplaceable = &result.unplaceable_art_object_ids;
+    if unplaceable.len() > 0 {
+        println!(
+            "{} art object(s) are too big to fit on any walls:",
+            unplaceable.len()
+        );
+        for &id in unplaceable {
+            let title = db
+                .get_art_object(id)?
+                .map(|object| object.title)
+                .unwrap_or_default();
+            println!("  {:?}: {}", id, title);
+        }
+        if fail_on_unplaceable {
+            return Err(anyhow!(
+                "{} art object(s) can't be placed, not changing the layout.",
+                unplaceable.len()
+            ));
+        }
+    }
+
+    let rotated = result
+        .layout_records
+        .iter()
+        .filter(|record| record.rotated)
+        .count();
+    if rotated > 0 {
+        println!("{rotated} art object(s) were rotated to fit.");
+    }
+
+    match gallery_id_range {
+        Some(range) => {
+            db.set_layout_records_in_gallery_range(&result.layout_records, range)?;
+            db.set_gallery_records_in_gallery_range(&result.gallery_records, range)?;
+        }
+        None => {
+            db.reset_layout_table()?;
+            db.set_layout_records_in_positive_galleries(&result.layout_records)?;
+            db.set_gallery_records_in_positive_galleries(&result.gallery_records)?;
+        }
+    }
+    db.set_layout_anchors(&result.anchors(&wall_sets))?;
+    db.set_layout_metadata(LAYOUT_MODE_METADATA_KEY, mode.name())?;
+    if let Some(random_seed) = random_seed {
+        // Seeds only reproduce a layout under the same RNG version.
+        db.set_layout_metadata(LAYOUT_RANDOM_SEED_METADATA_KEY, &random_seed.to_string())?;
+        db.set_layout_metadata(
+            LAYOUT_RNG_VERSION_METADATA_KEY,
+            &LAYOUT_RNG_VERSION.to_string(),
+        )?;
     }
     println!(
-        "Laying out {} art objects across galleries with {} walls each.",
-        art_objects.len(),
-        walls.len()
+        "Created a layout with {} galleries.",
+        result.galleries_created
     );
+    if let Some(free_regions_json) = free_regions_json {
+        std::fs::write(
+            &free_regions_json,
+            serde_json::to_string_pretty(&result.free_regions)?,
+        )?;
+        println!(
+            "Wrote {} free region(s) to {}.",
+            result.free_regions.len(),
+            free_regions_json.display()
+        );
+    }
 
-    let (galleries_created, layout_records) = layout(
-        use_dense_layout,
-        LAYOUT_START_GALLERY_ID,
-        &walls,
+    Ok(())
+}
+
+/// The `layout` options that `explore-seeds` passes along to every layout it
+/// tries, see `layout_with_seed()`.
+#[derive(Clone, Copy)]
+struct SeedLayoutOptions<'a> {
+    mode: LayoutMode,
+    reserved_walls: &'a [String],
+    featured_first: bool,
+    allow_rotation: bool,
+    balance_by: Option<BalanceBy>,
+    collection_weights: &'a [CollectionWeight],
+}
+
+/// Lays out the art objects as `layout --sort random` would with the given
+/// seed, without changing the database.
+fn layout_with_seed<'a>(
+    seed: u64,
+    wall_sets: &'a Vec<GalleryWallSet>,
+    art_objects: &[ArtObjectLayoutInfo],
+    options: &SeedLayoutOptions,
+) -> Result<LayoutResult<'a>> {
+    let mut rng = Rng::new(Some(seed));
+    let art_objects = arrange_art_objects(
+        art_objects.to_vec(),
+        options.balance_by,
+        options.collection_weights,
+        Some(&mut rng),
+        options.featured_first,
+    );
+    layout(
+        wall_sets,
         art_objects,
-        &HashSet::new(),
-        warnings,
+        &LayoutOptions {
+            mode: options.mode,
+            gallery_start_id: LAYOUT_START_GALLERY_ID,
+            reserved_walls: options.reserved_walls,
+            allow_rotation: options.allow_rotation,
+            ..Default::default()
+        },
+    )
+}
+
+/// Lays out the art objects with each of the given seeds, returning the seeds
+/// and their scores from best to worst. Ties go to the lowest seed, so the
+/// ranking doesn't depend on which layouts finish first.
+fn explore_seeds(
+    seeds: &[u64],
+    wall_sets: &Vec<GalleryWallSet>,
+    art_objects: &[ArtObjectLayoutInfo],
+    artists: &HashMap<ArtObjectId, String>,
+    options: &SeedLayoutOptions,
+    weights: &LayoutScoreWeights,
+) -> Result<Vec<(u64, LayoutScore)>> {
+    let mut ranked = seeds
+        .par_iter()
+        .map(|&seed| -> Result<(u64, LayoutScore)> {
+            let result = layout_with_seed(seed, wall_sets, art_objects, options)?;
+            let score = score_layout(&result, wall_sets, art_objects, artists, weights);
+            Ok((seed, score))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    ranked.sort_by(|(a_seed, a_score), (b_seed, b_score)| {
+        a_score
+            .total
+            .total_cmp(&b_score.total)
+            .then(a_seed.cmp(b_seed))
+    });
+    Ok(ranked)
+}
+
+fn explore_seeds_command(
+    db: GalleryDb,
+    walls: Vec<PathBuf>,
+    candidates: u64,
+    random_seed: Option<u64>,
+    mode: LayoutMode,
+    filter: Option<String>,
+    reserved_walls: Vec<String>,
+    featured_first: bool,
+    allow_rotation: bool,
+    balance_by: Option<BalanceBy>,
+    collection_weights: Vec<CollectionWeight>,
+    weights: LayoutScoreWeights,
+    commit_best: bool,
+) -> Result<()> {
+    if candidates == 0 {
+        return Err(anyhow!("--candidates must be at least 1"));
+    }
+    if mode == LayoutMode::RealGallery {
+        return Err(anyhow!(
+            "--mode real-gallery doesn't shuffle art, so there are no seeds to explore"
+        ));
+    }
+    if !collection_weights.is_empty() && balance_by.is_none() {
+        return Err(anyhow!(
+            "--collection-weight only makes sense with --balance-by collection"
+        ));
+    }
+    let wall_sets = get_wall_sets(walls.clone())?;
+    let options = ArtObjectQueryOptions {
+        filter: filter.clone(),
+        ..Default::default()
+    };
+    db.where_clause(&options)
+        .map_err(|err| anyhow!("Invalid filter: {err}"))?;
+    let art_objects = db.get_all_art_objects_for_layout(&options)?;
+    let artists = db.get_artists_by_id()?;
+    let first_seed = Rng::new(random_seed).seed;
+    let seeds: Vec<u64> = (0..candidates)
+        .map(|i| first_seed.wrapping_add(i))
+        .collect();
+    println!(
+        "Laying out {} art objects with {} seed(s), starting at {first_seed}.",
+        art_objects.len(),
+        seeds.len()
+    );
+    let ranked = explore_seeds(
+        &seeds,
+        &wall_sets,
+        &art_objects,
+        &artists,
+        &SeedLayoutOptions {
+            mode,
+            reserved_walls: &reserved_walls,
+            featured_first,
+            allow_rotation,
+            balance_by,
+            collection_weights: &collection_weights,
+        },
+        &weights,
     )?;
 
-    db.set_layout_records_in_positive_galleries(&layout_records)?;
-    println!("Created a layout with {} galleries.", galleries_created);
+    println!(
+        "{:>20}  {:>8}  {:>8}  {:>10}  {:>11}  {:>11}",
+        "Seed", "Score", "Variance", "Near-empty", "Unplaceable", "Same artist"
+    );
+    for (seed, score) in &ranked {
+        println!(
+            "{:>20}  {:>8.3}  {:>8.4}  {:>10}  {:>11}  {:>11}",
+            seed,
+            score.total,
+            score.occupancy_variance,
+            score.near_empty_walls,
+            score.unplaceable,
+            score.same_artist_neighbors
+        );
+    }
 
-    Ok(())
+    let (best_seed, _) = ranked[0];
+    if !commit_best {
+        println!(
+            "Run `layout --sort random --random-seed {best_seed}` with the same options, or pass \
+             --commit-best, to use the best layout."
+        );
+        return Ok(());
+    }
+    layout_command(
+        db,
+        walls,
+        false,
+        Some(Sort::Random),
+        Some(best_seed),
+        mode,
+        filter,
+        false,
+        false,
+        None,
+        reserved_walls,
+        vec![],
+        featured_first,
+        allow_rotation,
+        balance_by,
+        collection_weights,
+        None,
+        DEFAULT_MIN_FREE_REGION_WIDTH,
+        false,
+        None,
+    )
 }
 
 fn csv_command(
-    verbose: bool,
     met_objects_path: Option<PathBuf>,
     wikidata_objects_path: Option<PathBuf>,
     cache: GalleryCache,
@@ -360,6 +2016,17 @@ fn csv_command(
     max: Option<usize>,
     met_objects_all_media: bool,
     warnings: bool,
+    pd_policy: PublicDomainPolicy,
+    dry_run: bool,
+    summary_json: Option<PathBuf>,
+    skipped_report: Option<PathBuf>,
+    dimension_limits: DimensionLimits,
+    incremental: bool,
+    prefer_met: bool,
+    batch_size: usize,
+    parallel: bool,
+    fail_on_duplicates: bool,
+    max_parse_error_fraction: f64,
 ) -> Result<()> {
     let met_csv_file = met_objects_path.unwrap_or(cache.get_cached_path("MetObjects.csv"));
     println!("Loading met objects from {}.", met_csv_file.display());
@@ -367,44 +2034,336 @@ fn csv_command(
         wikidata_objects_path.unwrap_or(cache.get_cached_path("WikidataObjects.csv"));
     println!("Loading wikidata objects from {}.", met_csv_file.display());
     let met_reader = BufReader::new(File::open(met_csv_file)?);
-    let met_csv_reader = csv::Reader::from_reader(met_reader);
-    let wikidata_reader = BufReader::new(File::open(wikidata_csv_file)?);
+    let met_csv_reader = met_csv_reader(met_reader)?;
+    let wikidata_reader = BufReader::new(File::open(&wikidata_csv_file)?);
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(ProgressStyle::with_template("[{elapsed_precise}] {spinner} {msg}").unwrap());
+    let wikidata_bar = bar.clone();
     let wikidata_objects_iterator =
-        iter_wikidata_objects(csv::Reader::from_reader(wikidata_reader));
-    db.reset_art_objects_table()?;
-    let mut count: usize = 0;
-    let mut records_to_commit = vec![];
+        iter_wikidata_objects(csv::Reader::from_reader(wikidata_reader), dimension_limits)
+            .enumerate()
+            .map(move |(i, result)| {
+                let rows = i + 1;
+                if rows % PROGRESS_INTERVAL_ROWS == 0 {
+                    wikidata_bar.tick();
+                    wikidata_bar.set_message(format!("Parsed {rows} wikidata CSV rows."));
+                }
+                result
+            });
+    // There's nothing to add to if the database has never been imported into.
+    let incremental = incremental && db.has_table("art_objects")?;
+    let dedup = if incremental {
+        println!("Adding to the existing art objects in the database.");
+        WikidataDedup::from_db(&db, prefer_met)?
+    } else {
+        WikidataDedup {
+            prefer_met,
+            ..Default::default()
+        }
+    };
+    if dry_run {
+        println!("Performing a dry run, the database will not be changed.");
+    } else if !incremental {
+        db.reset_art_objects_table()?;
+    }
+    let met_bar = bar.clone();
+    let met_stats = Arc::new(MetCsvStats::default());
     let met_objects_iterator = iter_public_domain_2d_met_csv_objects(
         met_csv_reader,
         PublicDomain2DMetObjectOptions {
             all_media: met_objects_all_media,
             warnings,
-            ..Default::default()
+            pd_policy,
+            dimension_limits,
+            parallel,
+            max_parse_error_fraction: Some(max_parse_error_fraction),
+            stats: met_stats.clone(),
+        },
+        move |rows| {
+            met_bar.tick();
+            met_bar.set_message(format!("Parsed {rows} met CSV rows."));
         },
     );
-    let bar = ProgressBar::new_spinner();
-    bar.set_style(ProgressStyle::with_template("[{elapsed_precise}] {spinner} {msg}").unwrap());
-    let mut fallback_wikidata_qids: HashSet<i64> = HashSet::new();
 
     // We should always put wikidata last, as we want to know what wikidata fallback QIDs
     // from the other collections we've processed so we can skip the same ones in the
     // wikidata to avoid duplicates.
-    let combined_iterator =
-        Box::new(met_objects_iterator).chain(Box::new(wikidata_objects_iterator));
+    let combined_iterator = met_objects_iterator.chain(wikidata_objects_iterator);
+
+    let (summary, artist_qids, skips) = import_art_objects(
+        combined_iterator,
+        if dry_run { None } else { Some(&mut db) },
+        dedup,
+        max,
+        warnings,
+        batch_size,
+        fail_on_duplicates,
+    )?;
+    bar.finish_and_clear();
+    let empty_bool_rows = met_stats.empty_bool_rows();
+    if empty_bool_rows > 0 {
+        println!(
+            "WARNING: {empty_bool_rows} met CSV row(s) had empty True/False cells, which were treated as False."
+        );
+    }
+    if !dry_run {
+        import_wikidata_artists(&mut db, &wikidata_csv_file, artist_qids)?;
+        db.rebuild_collection_stats()?;
+    }
+    summary.print();
+    if !dry_run {
+        print_collection_stats(&db)?;
+        let quarantined = db.list_quarantined_objects()?.len();
+        if quarantined > 0 {
+            println!(
+                "{quarantined} art objects with implausible dimensions were quarantined, run `list-quarantined` to review them."
+            );
+        }
+    }
+    if let Some(summary_json) = summary_json {
+        std::fs::write(&summary_json, serde_json::to_string_pretty(&summary)?)?;
+        println!("Wrote {}.", summary_json.display());
+    }
+    if let Some(skipped_report) = skipped_report {
+        write_skipped_report(&skipped_report, &skips)?;
+        println!("Wrote {}.", skipped_report.display());
+    }
+    println!("Done.");
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct CsvImportSummary {
+    accepted_met: usize,
+    accepted_wikidata: usize,
+    skipped_invalid_dimensions: usize,
+    skipped_duplicate_wikidata: usize,
+    /// Art objects with the same ID as one that was already imported.
+    skipped_duplicate_ids: usize,
+    skipped_missing_filename: usize,
+    parse_errors: usize,
+    /// Art objects previously imported from wikidata that were replaced by
+    /// art objects from another collection.
+    replaced_wikidata: usize,
+}
+
+impl CsvImportSummary {
+    fn accepted(&self) -> usize {
+        self.accepted_met + self.accepted_wikidata
+    }
+
+    fn print(&self) {
+        let rows = [
+            ("Accepted from met", self.accepted_met),
+            ("Accepted from wikidata", self.accepted_wikidata),
+            (
+                "Skipped (invalid dimensions)",
+                self.skipped_invalid_dimensions,
+            ),
+            (
+                "Skipped (duplicate wikidata)",
+                self.skipped_duplicate_wikidata,
+            ),
+            ("Skipped (duplicate ID)", self.skipped_duplicate_ids),
+            ("Skipped (missing filename)", self.skipped_missing_filename),
+            ("CSV parse errors", self.parse_errors),
+            ("Replaced wikidata", self.replaced_wikidata),
+        ];
+        for (label, value) in rows {
+            println!("  {label:<30} {value:>8}");
+        }
+    }
+
+    fn add_skip(&mut self, reason: ImportSkipReason) {
+        match reason {
+            ImportSkipReason::MissingFilename => self.skipped_missing_filename += 1,
+            ImportSkipReason::InvalidDimensions => self.skipped_invalid_dimensions += 1,
+            ImportSkipReason::DuplicateWikidata => self.skipped_duplicate_wikidata += 1,
+            ImportSkipReason::DuplicateId => self.skipped_duplicate_ids += 1,
+            ImportSkipReason::DeserializeFailed => self.parse_errors += 1,
+        }
+    }
+}
+
+fn write_skipped_report(path: &PathBuf, skips: &[ImportSkip]) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for skip in skips {
+        writer.serialize(skip)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn wikidata_qid(object_id: ArtObjectId) -> Option<u64> {
+    match object_id {
+        ArtObjectId::Wikidata(qid) => Some(qid as u64),
+        ArtObjectId::Met(_) => None,
+    }
+}
+
+/// Describes where the given art object came from, for reporting duplicates.
+fn describe_art_object_source(record: &ArtObjectRecord) -> String {
+    let source = match record.object_id {
+        ArtObjectId::Met(_) => "met",
+        ArtObjectId::Wikidata(_) => "wikidata",
+    };
+    format!("{:?} {:?} ({source})", record.object_id, record.title)
+}
+
+fn quarantined_object_record(record: ArtObjectRecord, reason: String) -> QuarantinedObjectRecord {
+    QuarantinedObjectRecord {
+        object_id: record.object_id,
+        title: record.title,
+        artist: record.artist,
+        collection: record.collection,
+        width: record.width,
+        height: record.height,
+        reason,
+    }
+}
+
+/// The wikidata QIDs we know about so far, used to avoid importing the same
+/// artwork from both wikidata and another collection.
+#[derive(Debug, Default)]
+struct WikidataDedup {
+    /// Wikidata QIDs that are the fallback for art objects from other collections.
+    fallback_qids: HashSet<i64>,
+    /// QIDs of art objects that came from wikidata.
+    object_qids: HashSet<i64>,
+    /// Whether an art object whose fallback QID is an art object that came from
+    /// wikidata should replace it. Otherwise, it's skipped.
+    prefer_met: bool,
+}
 
-    for result in combined_iterator {
-        // Notice that we need to provide a type hint for automatic
-        // deserialization.
-        let csv_record: ArtObjectRecord = result?;
+impl WikidataDedup {
+    /// Start off with everything that's already in the database, so that
+    /// duplicates are caught across separate imports.
+    fn from_db(db: &GalleryDb, prefer_met: bool) -> Result<Self> {
+        Ok(WikidataDedup {
+            fallback_qids: db.get_all_fallback_wikidata_qids()?,
+            object_qids: db.get_all_wikidata_object_qids()?,
+            prefer_met,
+        })
+    }
+}
+
+/// Delete the given art objects and add the given records, if we have a database.
+fn commit_art_objects(
+    db: Option<&mut GalleryDb>,
+    ids_to_delete: &mut Vec<ArtObjectId>,
+    records_to_commit: &mut Vec<ArtObjectRecord>,
+) -> Result<()> {
+    if let Some(db) = db {
+        debug!(
+            "Committing {} records, replacing {}.",
+            records_to_commit.len(),
+            ids_to_delete.len()
+        );
+        db.delete_art_objects(ids_to_delete)?;
+        db.add_art_objects_batched(
+            records_to_commit,
+            DEFAULT_ART_OBJECT_INSERT_ROWS_PER_STATEMENT,
+        )?;
+    }
+    ids_to_delete.clear();
+    records_to_commit.clear();
+    Ok(())
+}
+
+/// Go through the given art objects, adding them to the database if one is
+/// provided, `batch_size` at a time. Returns a summary along with the QIDs of all the artists of the
+/// accepted art objects, and every row that was skipped.
+///
+/// Art objects with implausible dimensions are added to the database's
+/// quarantine instead, and are included in the skipped rows.
+///
+/// Art objects with the same ID as one that was already accepted by this
+/// import are reported and skipped, unless `fail_on_duplicates` is true, in
+/// which case the import is aborted.
+///
+/// Skipped rows don't stop the import, but any other CSV errors abort it.
+fn import_art_objects<I: Iterator<Item = Result<ArtObjectRecord, ImportError>>>(
+    art_objects: I,
+    mut db: Option<&mut GalleryDb>,
+    mut dedup: WikidataDedup,
+    max: Option<usize>,
+    warnings: bool,
+    batch_size: usize,
+    fail_on_duplicates: bool,
+) -> Result<(CsvImportSummary, HashSet<i64>, Vec<ImportSkip>)> {
+    let mut summary = CsvImportSummary::default();
+    let mut skips: Vec<ImportSkip> = vec![];
+    let mut quarantined: Vec<QuarantinedObjectRecord> = vec![];
+    let mut records_to_commit = vec![];
+    let mut ids_to_delete = vec![];
+    let mut artist_qids: HashSet<i64> = HashSet::new();
+    // Where each raw ID accepted so far came from.
+    let mut accepted_ids: HashMap<i64, String> = HashMap::new();
+
+    for result in art_objects {
+        let csv_record = match result {
+            Ok(csv_record) => csv_record,
+            Err(ImportError::Skip(skip)) => {
+                if warnings {
+                    println!("Skipping {skip}.");
+                }
+                summary.add_skip(skip.reason);
+                skips.push(skip);
+                continue;
+            }
+            Err(ImportError::Quarantine(quarantine)) => {
+                if warnings {
+                    println!(
+                        "Quarantining {:?} ({}).",
+                        quarantine.record.object_id, quarantine.issue
+                    );
+                }
+                summary.add_skip(ImportSkipReason::InvalidDimensions);
+                skips.push(ImportSkip {
+                    qid: wikidata_qid(quarantine.record.object_id),
+                    reason: ImportSkipReason::InvalidDimensions,
+                });
+                quarantined.push(quarantined_object_record(
+                    quarantine.record,
+                    quarantine.issue.to_string(),
+                ));
+                continue;
+            }
+            Err(ImportError::Csv(err)) => return Err(err.into()),
+            Err(err @ ImportError::TooManyParseErrors { .. }) => return Err(err.into()),
+        };
+        let mut replaced_wikidata_qid = None;
         if let Some(qid) = csv_record.fallback_wikidata_qid {
-            fallback_wikidata_qids.insert(qid);
+            if dedup.object_qids.contains(&qid) {
+                // This item's wikidata fallback was already imported on its own, which
+                // can only happen if it was imported separately.
+                if dedup.prefer_met {
+                    replaced_wikidata_qid = Some(qid);
+                } else {
+                    summary.add_skip(ImportSkipReason::DuplicateWikidata);
+                    skips.push(ImportSkip {
+                        qid: Some(qid as u64),
+                        reason: ImportSkipReason::DuplicateWikidata,
+                    });
+                    continue;
+                }
+            }
+            dedup.fallback_qids.insert(qid);
         } else if let ArtObjectId::Wikidata(qid) = csv_record.object_id {
-            if fallback_wikidata_qids.contains(&qid) {
+            if dedup.fallback_qids.contains(&qid) {
                 // This wikidata item is already the fallback for an item from another CSV
                 // we've processed. Skip it, since we don't want duplicates.
+                summary.add_skip(ImportSkipReason::DuplicateWikidata);
+                skips.push(ImportSkip {
+                    qid: Some(qid as u64),
+                    reason: ImportSkipReason::DuplicateWikidata,
+                });
                 continue;
             }
+            dedup.object_qids.insert(qid);
         }
+        // The CSV iterators should already have quarantined these, but we
+        // definitely can't lay them out, so make sure.
         if csv_record.height <= 0.0 || csv_record.width <= 0.0 {
             if warnings {
                 println!(
@@ -412,24 +2371,59 @@ fn csv_command(
                     csv_record.object_id
                 );
             }
+            summary.add_skip(ImportSkipReason::InvalidDimensions);
+            skips.push(ImportSkip {
+                qid: wikidata_qid(csv_record.object_id),
+                reason: ImportSkipReason::InvalidDimensions,
+            });
+            quarantined.push(quarantined_object_record(
+                csv_record,
+                DimensionIssue::NonPositive.to_string(),
+            ));
             continue;
         }
-        count += 1;
-        if verbose {
-            println!(
-                "#{:?}: medium={} title={}",
-                csv_record.object_id, csv_record.medium, csv_record.title
+        let raw_id = csv_record.object_id.to_raw_i64();
+        if let Some(first_source) = accepted_ids.get(&raw_id) {
+            let collision = format!(
+                "art object ID {raw_id} of {}, which was already used by {first_source}",
+                describe_art_object_source(&csv_record)
             );
+            if fail_on_duplicates {
+                return Err(anyhow!("Found duplicate {collision}"));
+            }
+            println!("Skipping duplicate {collision}.");
+            summary.add_skip(ImportSkipReason::DuplicateId);
+            skips.push(ImportSkip {
+                qid: wikidata_qid(csv_record.object_id),
+                reason: ImportSkipReason::DuplicateId,
+            });
+            continue;
+        }
+        accepted_ids.insert(raw_id, describe_art_object_source(&csv_record));
+        if let Some(qid) = replaced_wikidata_qid {
+            dedup.object_qids.remove(&qid);
+            ids_to_delete.push(ArtObjectId::Wikidata(qid));
+            summary.replaced_wikidata += 1;
+        }
+        match csv_record.object_id {
+            ArtObjectId::Met(_) => summary.accepted_met += 1,
+            ArtObjectId::Wikidata(_) => summary.accepted_wikidata += 1,
         }
+        let count = summary.accepted();
+        if let Some(artist_qid) = csv_record.artist_qid {
+            artist_qids.insert(artist_qid);
+        }
+        debug!(
+            "#{:?}: medium={} title={}",
+            csv_record.object_id, csv_record.medium, csv_record.title
+        );
         records_to_commit.push(csv_record);
-        if records_to_commit.len() >= TRANSACTION_BATCH_SIZE {
-            if verbose {
-                println!("Committing {} records.", records_to_commit.len());
-            }
-            db.add_art_objects(&records_to_commit)?;
-            records_to_commit.clear();
-            bar.tick();
-            bar.set_message(format!("Processed {count} records."));
+        if records_to_commit.len() >= batch_size.max(1) {
+            commit_art_objects(
+                db.as_deref_mut(),
+                &mut ids_to_delete,
+                &mut records_to_commit,
+            )?;
         }
         if let Some(max) = max {
             if count >= max {
@@ -439,14 +2433,39 @@ fn csv_command(
         }
     }
     if records_to_commit.len() > 0 {
-        if verbose {
-            println!("Committing {} records.", records_to_commit.len());
+        commit_art_objects(
+            db.as_deref_mut(),
+            &mut ids_to_delete,
+            &mut records_to_commit,
+        )?;
+    }
+    if let Some(db) = db.as_mut() {
+        db.add_quarantined_objects(&quarantined)?;
+    }
+    Ok((summary, artist_qids, skips))
+}
+
+/// Import the artists of all the art objects we've imported from the given
+/// wikidata CSV.
+fn import_wikidata_artists(
+    db: &mut GalleryDb,
+    wikidata_csv_file: &PathBuf,
+    mut artist_qids: HashSet<i64>,
+) -> Result<()> {
+    println!(
+        "Loading {} artists from wikidata objects.",
+        artist_qids.len()
+    );
+    let reader = BufReader::new(File::open(wikidata_csv_file)?);
+    let mut artists: Vec<ArtistRecord> = vec![];
+    for result in iter_wikidata_artists(csv::Reader::from_reader(reader)) {
+        let artist = result?;
+        // Removing the QID ensures we only add each artist once.
+        if artist_qids.remove(&artist.qid) {
+            artists.push(artist);
         }
-        db.add_art_objects(&records_to_commit)?;
     }
-    bar.set_message(format!("Processed {count} records."));
-    bar.finish();
-    println!("Done.");
+    db.add_artists(&artists)?;
     Ok(())
 }
 
@@ -459,14 +2478,86 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, io::BufReader, path::PathBuf};
+    use std::{collections::HashSet, fs::File, io::BufReader, path::PathBuf};
 
-    use gallery::gallery_cache::GalleryCache;
+    use gallery::{
+        art_object::ArtObjectId, gallery_cache::GalleryCache, gallery_db::ArtObjectRecord,
+        medium::MediumCategory,
+    };
     use rusqlite::Connection;
 
-    use crate::met_csv::iter_public_domain_2d_met_csv_objects;
+    use crate::{
+        dimension_limits::DimensionIssue,
+        import_skip::{ImportError, ImportSkip, ImportSkipReason},
+        met_csv::{iter_public_domain_2d_met_csv_objects, PublicDomain2DMetObjectOptions},
+        paths::SearchPaths,
+        public_domain::PublicDomainPolicy,
+        wikidata_dump::iter_wikidata_objects,
+    };
+
+    use super::{
+        build_demo_db, explore_seeds, explore_seeds_command, generate_walls_command,
+        get_layout_estimate, get_wall_sets, get_walls, import_art_objects, layout_command,
+        layout_with_seed, parse_gallery_id_range, truncate_for_table, CsvImportSummary, GalleryDb,
+        LayoutMode, LayoutRecord, LayoutScoreWeights, SeedLayoutOptions, WikidataDedup,
+        DEFAULT_MIN_FREE_REGION_WIDTH, DEMO_WALLS_FILENAME, TRANSACTION_BATCH_SIZE,
+    };
+
+    fn iter_test_met_objects() -> impl Iterator<Item = Result<ArtObjectRecord, ImportError>> {
+        let manifest_dir: PathBuf = env!("CARGO_MANIFEST_DIR").into();
+        let cache = GalleryCache::new(manifest_dir.join("..").join("test_data"));
+        let csv_file = cache.get_cached_path("MetObjects.csv");
+        let reader = BufReader::new(File::open(csv_file).unwrap());
+        iter_public_domain_2d_met_csv_objects(
+            csv::Reader::from_reader(reader),
+            Default::default(),
+            |_| {},
+        )
+    }
+
+    /// Iterates through the met and then wikidata objects in the
+    /// `test_data/duplicates` directory, each of which has a duplicate row.
+    fn iter_duplicate_objects() -> impl Iterator<Item = Result<ArtObjectRecord, ImportError>> {
+        let manifest_dir: PathBuf = env!("CARGO_MANIFEST_DIR").into();
+        let dir = manifest_dir.join("..").join("test_data").join("duplicates");
+        let met_reader = BufReader::new(File::open(dir.join("MetObjects.csv")).unwrap());
+        let wikidata_reader = BufReader::new(File::open(dir.join("WikidataObjects.csv")).unwrap());
+        iter_public_domain_2d_met_csv_objects(
+            csv::Reader::from_reader(met_reader),
+            PublicDomain2DMetObjectOptions {
+                pd_policy: PublicDomainPolicy::Permissive,
+                ..Default::default()
+            },
+            |_| {},
+        )
+        .chain(iter_wikidata_objects(
+            csv::Reader::from_reader(wikidata_reader),
+            Default::default(),
+        ))
+    }
 
-    use super::GalleryDb;
+    fn make_wikidata_object(qid: i64, width: f64) -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id: ArtObjectId::Wikidata(qid),
+            object_date: "".into(),
+            culture: "".into(),
+            artist: "".into(),
+            artist_role: String::default(),
+            title: format!("Q{qid}"),
+            medium: "".into(),
+            medium_category: MediumCategory::Other,
+            width,
+            height: 1.0,
+            fallback_wikidata_qid: None,
+            filename: "".into(),
+            collection: "".into(),
+            artist_qid: None,
+            highlight: false,
+            tags: String::default(),
+            classification: String::default(),
+            source_gallery: None,
+        }
+    }
 
     #[test]
     fn test_it_works() {
@@ -480,7 +2571,7 @@ mod tests {
         let reader = BufReader::new(File::open(csv_file).unwrap());
         let rdr = csv::Reader::from_reader(reader);
         let mut records = vec![];
-        for result in iter_public_domain_2d_met_csv_objects(rdr, Default::default()) {
+        for result in iter_public_domain_2d_met_csv_objects(rdr, Default::default(), |_| {}) {
             records.push(result.unwrap());
         }
         db.add_art_objects(&records).unwrap();
@@ -490,4 +2581,649 @@ mod tests {
             .unwrap();
         assert!(rows.len() > 0);
     }
+
+    #[test]
+    fn test_parse_gallery_id_range_works() {
+        assert_eq!(parse_gallery_id_range("1-3"), Ok((1, 3)));
+        assert_eq!(parse_gallery_id_range("2 - 2"), Ok((2, 2)));
+        assert!(parse_gallery_id_range("3").is_err());
+        assert!(parse_gallery_id_range("3-1").is_err());
+        assert!(parse_gallery_id_range("0-3").is_err());
+        assert!(parse_gallery_id_range("a-b").is_err());
+    }
+
+    #[test]
+    fn test_import_art_objects_dry_run_summary_works() {
+        let fallback_qids: Vec<i64> = iter_test_met_objects()
+            .filter_map(|result| result.unwrap().fallback_wikidata_qid)
+            .collect();
+        assert_eq!(fallback_qids.len(), 2);
+        let wikidata_objects = vec![
+            Ok(make_wikidata_object(fallback_qids[0], 1.0)),
+            Ok(make_wikidata_object(1, 1.0)),
+            Ok(make_wikidata_object(2, 0.0)),
+        ];
+        let (summary, _, skips) = import_art_objects(
+            iter_test_met_objects().chain(wikidata_objects.into_iter()),
+            None,
+            Default::default(),
+            None,
+            false,
+            TRANSACTION_BATCH_SIZE,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            CsvImportSummary {
+                accepted_met: 4,
+                accepted_wikidata: 1,
+                skipped_invalid_dimensions: 1,
+                skipped_duplicate_wikidata: 1,
+                skipped_duplicate_ids: 0,
+                skipped_missing_filename: 0,
+                parse_errors: 0,
+                replaced_wikidata: 0,
+            }
+        );
+        assert_eq!(
+            skips,
+            vec![
+                ImportSkip {
+                    qid: Some(fallback_qids[0] as u64),
+                    reason: ImportSkipReason::DuplicateWikidata
+                },
+                ImportSkip {
+                    qid: Some(2),
+                    reason: ImportSkipReason::InvalidDimensions
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_art_objects_reports_skipped_wikidata_rows() {
+        let csv = "qid,artist,title,inception,width,height,materials,collection,filename\n\
+                   1,Boop Jones,Funky Painting,1864,100,50,,,funky-painting.jpg\n\
+                   2,Boop Jones,Nameless Painting,1864,100,50,,,\n\
+                   3,Boop Jones,Flat Painting,1864,100,0,,,flat-painting.jpg\n";
+        let (summary, _, skips) = import_art_objects(
+            iter_wikidata_objects(csv::Reader::from_reader(csv.as_bytes()), Default::default()),
+            None,
+            Default::default(),
+            None,
+            false,
+            TRANSACTION_BATCH_SIZE,
+            false,
+        )
+        .unwrap();
+        assert_eq!(summary.accepted(), 1);
+        assert_eq!(summary.skipped_missing_filename, 1);
+        assert_eq!(summary.skipped_invalid_dimensions, 1);
+        assert_eq!(
+            skips,
+            vec![
+                ImportSkip {
+                    qid: Some(2),
+                    reason: ImportSkipReason::MissingFilename
+                },
+                ImportSkip {
+                    qid: Some(3),
+                    reason: ImportSkipReason::InvalidDimensions
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_art_objects_aborts_on_malformed_csv() {
+        let csv = "qid,artist,title,inception,width,height,materials,collection,filename\n\
+                   1,Boop Jones,Funky Painting,1864,100,50,,,funky-painting.jpg\n\
+                   2,Boop Jones\n";
+        let result = import_art_objects(
+            iter_wikidata_objects(csv::Reader::from_reader(csv.as_bytes()), Default::default()),
+            None,
+            Default::default(),
+            None,
+            false,
+            TRANSACTION_BATCH_SIZE,
+            false,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_import_art_objects_skips_duplicate_ids() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        let (summary, _, skips) = import_art_objects(
+            iter_duplicate_objects(),
+            Some(&mut db),
+            Default::default(),
+            None,
+            false,
+            TRANSACTION_BATCH_SIZE,
+            false,
+        )
+        .unwrap();
+        assert_eq!(summary.accepted_met, 2);
+        assert_eq!(summary.accepted_wikidata, 2);
+        assert_eq!(summary.skipped_duplicate_ids, 2);
+        assert_eq!(
+            skips,
+            vec![
+                ImportSkip {
+                    qid: None,
+                    reason: ImportSkipReason::DuplicateId
+                },
+                ImportSkip {
+                    qid: Some(12418),
+                    reason: ImportSkipReason::DuplicateId
+                },
+            ]
+        );
+        assert_eq!(db.count_art_objects(&Default::default()).unwrap(), 4);
+        // The first of the duplicates wins.
+        let titles: Vec<String> = [ArtObjectId::Met(436535), ArtObjectId::Wikidata(12418)]
+            .into_iter()
+            .map(|id| db.get_art_object(id).unwrap().unwrap().title)
+            .collect();
+        assert_eq!(titles, vec!["Wheat Field with Cypresses", "Mona Lisa"]);
+    }
+
+    #[test]
+    fn test_import_art_objects_can_fail_on_duplicate_ids() {
+        let err = import_art_objects(
+            iter_duplicate_objects(),
+            None,
+            Default::default(),
+            None,
+            false,
+            TRANSACTION_BATCH_SIZE,
+            true,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("Wheat Field with Cypresses (duplicate row)"),
+            "{err}"
+        );
+        assert!(err.contains("\"Wheat Field with Cypresses\""), "{err}");
+    }
+
+    #[test]
+    fn test_import_art_objects_adds_records_to_db() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        let (summary, _, _) = import_art_objects(
+            iter_test_met_objects(),
+            Some(&mut db),
+            Default::default(),
+            None,
+            false,
+            TRANSACTION_BATCH_SIZE,
+            false,
+        )
+        .unwrap();
+        assert_eq!(summary.accepted_met, 4);
+        assert_eq!(db.count_art_objects(&Default::default()).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_import_art_objects_quarantines_implausible_dimensions() {
+        let csv = "qid,artist,title,inception,width,height,materials,collection,filename\n\
+                   1,Boop Jones,Funky Painting,1864,100,50,,,funky-painting.jpg\n\
+                   2,Boop Jones,Tiny Painting,1864,0.1,300,,,tiny-painting.jpg\n\
+                   3,Boop Jones,Huge Painting,1864,4000,4000,,,huge-painting.jpg\n\
+                   4,Boop Jones,Skinny Painting,1864,10,300,,,skinny-painting.jpg\n\
+                   5,Boop Jones,Flat Painting,1864,100,0,,,flat-painting.jpg\n";
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        let (summary, _, skips) = import_art_objects(
+            iter_wikidata_objects(csv::Reader::from_reader(csv.as_bytes()), Default::default()),
+            Some(&mut db),
+            Default::default(),
+            None,
+            false,
+            TRANSACTION_BATCH_SIZE,
+            false,
+        )
+        .unwrap();
+        assert_eq!(summary.accepted(), 1);
+        assert_eq!(summary.skipped_invalid_dimensions, 4);
+        assert_eq!(skips.len(), 4);
+
+        assert_eq!(db.count_art_objects(&Default::default()).unwrap(), 1);
+        for qid in 2..=5 {
+            assert_eq!(db.get_art_object(ArtObjectId::Wikidata(qid)).unwrap(), None);
+        }
+        let quarantined: Vec<(ArtObjectId, String)> = db
+            .list_quarantined_objects()
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.object_id, record.reason))
+            .collect();
+        assert_eq!(
+            quarantined,
+            vec![
+                (
+                    ArtObjectId::Wikidata(2),
+                    DimensionIssue::TooSmall.to_string()
+                ),
+                (
+                    ArtObjectId::Wikidata(3),
+                    DimensionIssue::TooLarge.to_string()
+                ),
+                (
+                    ArtObjectId::Wikidata(4),
+                    DimensionIssue::ExtremeAspectRatio.to_string()
+                ),
+                (
+                    ArtObjectId::Wikidata(5),
+                    DimensionIssue::NonPositive.to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_art_objects_quarantines_non_positive_records_from_anywhere() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        import_art_objects(
+            vec![Ok(make_wikidata_object(1, 0.0))].into_iter(),
+            Some(&mut db),
+            Default::default(),
+            None,
+            false,
+            TRANSACTION_BATCH_SIZE,
+            false,
+        )
+        .unwrap();
+        assert_eq!(db.count_art_objects(&Default::default()).unwrap(), 0);
+        assert_eq!(db.list_quarantined_objects().unwrap().len(), 1);
+    }
+
+    fn make_met_object(id: i64, fallback_qid: i64) -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id: ArtObjectId::Met(id),
+            fallback_wikidata_qid: Some(fallback_qid),
+            ..make_wikidata_object(fallback_qid, 1.0)
+        }
+    }
+
+    fn import_incrementally(
+        db: &mut GalleryDb,
+        records: Vec<ArtObjectRecord>,
+        prefer_met: bool,
+    ) -> CsvImportSummary {
+        let dedup = WikidataDedup::from_db(db, prefer_met).unwrap();
+        let (summary, _, _) = import_art_objects(
+            records.into_iter().map(Ok),
+            Some(db),
+            dedup,
+            None,
+            false,
+            TRANSACTION_BATCH_SIZE,
+            false,
+        )
+        .unwrap();
+        summary
+    }
+
+    fn assert_no_duplicates(db: &GalleryDb) {
+        let fallback_qids = db.get_all_fallback_wikidata_qids().unwrap();
+        let object_qids = db.get_all_wikidata_object_qids().unwrap();
+        let duplicates: Vec<&i64> = fallback_qids.intersection(&object_qids).collect();
+        assert!(duplicates.is_empty(), "{duplicates:?} were imported twice");
+    }
+
+    #[test]
+    fn test_separate_imports_skip_wikidata_duplicates_of_existing_fallbacks() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        import_incrementally(&mut db, vec![make_met_object(1, 10)], false);
+        let summary = import_incrementally(
+            &mut db,
+            vec![make_wikidata_object(10, 1.0), make_wikidata_object(11, 1.0)],
+            false,
+        );
+        assert_eq!(summary.accepted_wikidata, 1);
+        assert_eq!(summary.skipped_duplicate_wikidata, 1);
+        assert_eq!(db.count_art_objects(&Default::default()).unwrap(), 2);
+        assert_no_duplicates(&db);
+    }
+
+    #[test]
+    fn test_separate_imports_skip_fallbacks_of_existing_wikidata_objects() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        import_incrementally(
+            &mut db,
+            vec![make_wikidata_object(10, 1.0), make_wikidata_object(11, 1.0)],
+            false,
+        );
+        let summary = import_incrementally(&mut db, vec![make_met_object(1, 10)], false);
+        assert_eq!(summary.accepted_met, 0);
+        assert_eq!(summary.skipped_duplicate_wikidata, 1);
+        assert_eq!(db.get_art_object(ArtObjectId::Met(1)).unwrap(), None);
+        assert_eq!(db.count_art_objects(&Default::default()).unwrap(), 2);
+        assert_no_duplicates(&db);
+    }
+
+    #[test]
+    fn test_separate_imports_can_prefer_met() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        import_incrementally(
+            &mut db,
+            vec![make_wikidata_object(10, 1.0), make_wikidata_object(11, 1.0)],
+            false,
+        );
+        let summary = import_incrementally(&mut db, vec![make_met_object(1, 10)], true);
+        assert_eq!(summary.accepted_met, 1);
+        assert_eq!(summary.replaced_wikidata, 1);
+        assert_eq!(db.get_art_object(ArtObjectId::Wikidata(10)).unwrap(), None);
+        assert!(db.get_art_object(ArtObjectId::Met(1)).unwrap().is_some());
+        assert_eq!(db.count_art_objects(&Default::default()).unwrap(), 2);
+        assert_no_duplicates(&db);
+
+        // Re-importing wikidata shouldn't bring the replaced object back.
+        let summary = import_incrementally(&mut db, vec![make_wikidata_object(10, 1.0)], true);
+        assert_eq!(summary.skipped_duplicate_wikidata, 1);
+        assert_no_duplicates(&db);
+    }
+
+    #[test]
+    fn test_demo_db_has_art_on_multiple_walls() {
+        let dir = std::env::temp_dir().join(format!("demo-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("gallery.sqlite");
+        let demo_dir = SearchPaths::from_env(|_| None).dev_demo_dir;
+        build_demo_db(&demo_dir, &db_path).unwrap();
+
+        let db = GalleryDb::new(Connection::open(&db_path).unwrap());
+        assert!(db.check_schema().unwrap().is_ok());
+        assert_eq!(db.count_art_objects(&Default::default()).unwrap(), 30);
+        let walls: HashSet<(i64, String)> = db
+            .get_all_layout_records()
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.gallery_id, record.wall_id))
+            .collect();
+        assert!(walls.len() >= 2, "art was only placed on {walls:?}");
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_layout_estimate_matches_real_layout() {
+        let dir = std::env::temp_dir().join(format!("estimate-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("gallery.sqlite");
+        let demo_dir = SearchPaths::from_env(|_| None).dev_demo_dir;
+        let walls = demo_dir.join(DEMO_WALLS_FILENAME);
+        build_demo_db(&demo_dir, &db_path).unwrap();
+
+        let db = GalleryDb::new(Connection::open(&db_path).unwrap());
+        let estimate = get_layout_estimate(&db, &walls, None, LayoutMode::Sparse).unwrap();
+        assert_eq!(estimate.galleries, db.count_positive_galleries().unwrap());
+        assert_eq!(
+            (estimate.average_art_objects_per_gallery * estimate.galleries as f64).round() as usize,
+            db.get_all_layout_records().unwrap().len()
+        );
+        assert_eq!(estimate.unplaceable, 0);
+        assert!(
+            get_layout_estimate(&db, &walls, Some("funky -".into()), LayoutMode::Sparse).is_err()
+        );
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_explore_seeds_is_deterministic() {
+        let dir = std::env::temp_dir().join(format!("explore-seeds-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("gallery.sqlite");
+        let demo_dir = SearchPaths::from_env(|_| None).dev_demo_dir;
+        let walls = demo_dir.join(DEMO_WALLS_FILENAME);
+        build_demo_db(&demo_dir, &db_path).unwrap();
+
+        let db = GalleryDb::new(Connection::open(&db_path).unwrap());
+        let wall_sets = get_wall_sets(vec![walls.clone()]).unwrap();
+        let art_objects = db
+            .get_all_art_objects_for_layout(&Default::default())
+            .unwrap();
+        let artists = db.get_artists_by_id().unwrap();
+        let options = SeedLayoutOptions {
+            mode: LayoutMode::Sparse,
+            reserved_walls: &[],
+            featured_first: false,
+            allow_rotation: false,
+            balance_by: None,
+            collection_weights: &[],
+        };
+        let explore = || {
+            explore_seeds(
+                &[5, 6, 7, 8],
+                &wall_sets,
+                &art_objects,
+                &artists,
+                &options,
+                &LayoutScoreWeights::default(),
+            )
+            .unwrap()
+        };
+        let ranked = explore();
+        assert_eq!(ranked, explore());
+        assert_eq!(ranked.len(), 4);
+        assert!(ranked
+            .windows(2)
+            .all(|pair| pair[0].1.total <= pair[1].1.total));
+
+        let (best_seed, _) = ranked[0];
+        let sorted = |mut records: Vec<LayoutRecord<String>>| {
+            records.sort_by_key(|record| {
+                (
+                    record.gallery_id,
+                    record.wall_id.clone(),
+                    record.art_object_id.to_raw_i64(),
+                )
+            });
+            records
+        };
+        let expected = sorted(
+            layout_with_seed(best_seed, &wall_sets, &art_objects, &options)
+                .unwrap()
+                .layout_records
+                .into_iter()
+                .map(|record| LayoutRecord {
+                    gallery_id: record.gallery_id,
+                    wall_id: record.wall_id.to_string(),
+                    art_object_id: record.art_object_id,
+                    x: record.x,
+                    y: record.y,
+                    rotated: record.rotated,
+                })
+                .collect(),
+        );
+        drop(db);
+
+        explore_seeds_command(
+            GalleryDb::new(Connection::open(&db_path).unwrap()),
+            vec![walls],
+            4,
+            Some(5),
+            LayoutMode::Sparse,
+            None,
+            vec![],
+            false,
+            false,
+            None,
+            vec![],
+            LayoutScoreWeights::default(),
+            true,
+        )
+        .unwrap();
+        let db = GalleryDb::new(Connection::open(&db_path).unwrap());
+        assert_eq!(sorted(db.get_all_layout_records().unwrap()), expected);
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_real_gallery_layout_works() {
+        let manifest_dir: PathBuf = env!("CARGO_MANIFEST_DIR").into();
+        let fixture_dir = manifest_dir
+            .join("..")
+            .join("test_data")
+            .join("real_galleries");
+        let dir = std::env::temp_dir().join(format!("real-gallery-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("gallery.sqlite");
+        let reader = BufReader::new(File::open(fixture_dir.join("MetObjects.csv")).unwrap());
+        let records: Vec<ArtObjectRecord> = iter_public_domain_2d_met_csv_objects(
+            csv::Reader::from_reader(reader),
+            Default::default(),
+            |_| {},
+        )
+        .map(Result::unwrap)
+        .collect();
+        assert_eq!(records.len(), 10);
+        let mut db = GalleryDb::new(Connection::open(&db_path).unwrap());
+        db.create_schema().unwrap();
+        db.add_art_objects(&records).unwrap();
+        drop(db);
+
+        layout_command(
+            GalleryDb::new(Connection::open(&db_path).unwrap()),
+            vec![fixture_dir.join("walls.json")],
+            false,
+            None,
+            None,
+            LayoutMode::RealGallery,
+            None,
+            false,
+            true,
+            None,
+            vec![],
+            vec![],
+            false,
+            false,
+            None,
+            vec![],
+            None,
+            DEFAULT_MIN_FREE_REGION_WIDTH,
+            false,
+            None,
+        )
+        .unwrap();
+
+        // The "Great Hall" needs more than one gallery, so it's left out.
+        let db = GalleryDb::new(Connection::open(&db_path).unwrap());
+        let source_gallery = |gallery_id: i64| {
+            db.get_gallery_record(gallery_id)
+                .unwrap()
+                .and_then(|gallery| gallery.source_gallery)
+        };
+        assert_eq!(source_gallery(1), Some("825".into()));
+        assert_eq!(source_gallery(2), Some("826".into()));
+        assert_eq!(source_gallery(3), None);
+        assert_eq!(db.count_positive_galleries().unwrap(), 3);
+        let placed: HashSet<(i64, ArtObjectId)> = db
+            .get_all_layout_records()
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.gallery_id, record.art_object_id))
+            .collect();
+        assert_eq!(
+            placed,
+            HashSet::from([
+                (1, ArtObjectId::Met(3)),
+                (2, ArtObjectId::Met(1)),
+                (2, ArtObjectId::Met(2)),
+                (3, ArtObjectId::Met(9)),
+                (3, ArtObjectId::Met(10)),
+            ])
+        );
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_generated_walls_round_trip() {
+        let dir = std::env::temp_dir().join(format!("generate-walls-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let walls_path = dir.join("room.walls.json");
+        generate_walls_command(8.0, 6.0, 4.0, Some(2), Some(walls_path.clone())).unwrap();
+        let walls = get_walls(&walls_path).unwrap();
+        assert_eq!(walls.len(), 5);
+
+        std::fs::write(
+            &walls_path,
+            r#"[{"name": "wall_01", "width": -8.0, "height": 4.0},
+                {"name": "wall_01", "width": 8.0, "height": 400.0}]"#,
+        )
+        .unwrap();
+        let message = get_walls(&walls_path).unwrap_err().to_string();
+        assert!(message.contains("room.walls.json"), "{message}");
+        assert_eq!(message.lines().count(), 4, "{message}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wall_sets_with_the_same_name_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("wall-set-names-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("other")).unwrap();
+        let room_path = dir.join("room.json");
+        let other_room_path = dir.join("other").join("room.walls.json");
+        let hall_path = dir.join("hall.walls.json");
+        for path in [&room_path, &other_room_path, &hall_path] {
+            generate_walls_command(8.0, 6.0, 4.0, None, Some(path.clone())).unwrap();
+        }
+
+        let wall_sets = get_wall_sets(vec![room_path.clone(), hall_path.clone()]).unwrap();
+        let names: Vec<&str> = wall_sets
+            .iter()
+            .map(|wall_set| wall_set.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["room", "hall"]);
+
+        let message = get_wall_sets(vec![room_path, hall_path, other_room_path])
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("wall set \"room\""), "{message}");
+        assert!(message.contains("room.walls.json"), "{message}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_demo_walls_match_the_game() {
+        let paths = SearchPaths::from_env(|_| None);
+        let wall_names = |path: PathBuf| -> Vec<String> {
+            get_walls(&path)
+                .unwrap()
+                .into_iter()
+                .map(|wall| wall.name)
+                .collect()
+        };
+        assert_eq!(
+            wall_names(paths.dev_demo_dir.join(DEMO_WALLS_FILENAME)),
+            wall_names(paths.dev_walls_path)
+        );
+    }
+
+    #[test]
+    fn test_truncate_for_table_works() {
+        assert_eq!(truncate_for_table("Boop", 4), "Boop");
+        assert_eq!(truncate_for_table("Boop Jones", 5), "Boop…");
+        assert_eq!(truncate_for_table("Café au lait", 5), "Café…");
+        assert_eq!(truncate_for_table("", 5), "");
+    }
 }
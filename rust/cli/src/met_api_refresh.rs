@@ -0,0 +1,712 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use gallery::{
+    art_object::ArtObjectId,
+    gallery_cache::{get_http_status, CacheResult, GalleryCache},
+    gallery_db::{ArtObjectRecord, GalleryDb},
+    image::ImageSize,
+    met_api::{
+        load_cached_met_api_record, load_met_api_record, refresh_met_api_record, MetObjectApiRecord,
+    },
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_REQUESTS_PER_SECOND: f64 = 2.0;
+
+pub const DEFAULT_PROGRESS_FILENAME: &str = "met-api-refresh-progress.json";
+
+/// Sizes that differ by less than this many meters are considered the same,
+/// since the Met API's measurements are floats.
+const SIZE_TOLERANCE: f64 = 0.001;
+
+/// Makes sure that calls to `wait()` return no more often than the given rate.
+pub struct RateLimiter {
+    interval: Duration,
+    next_allowed: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64) -> Result<Self> {
+        if !requests_per_second.is_finite() || requests_per_second <= 0.0 {
+            return Err(anyhow!(
+                "Requests per second must be positive, got {requests_per_second}"
+            ));
+        }
+        Ok(RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next_allowed: None,
+        })
+    }
+
+    pub fn wait(&mut self) {
+        if let Some(next_allowed) = self.next_allowed {
+            let now = Instant::now();
+            if next_allowed > now {
+                sleep(next_allowed - now);
+            }
+        }
+        self.next_allowed = Some(Instant::now() + self.interval);
+    }
+}
+
+/// Which Met object was refreshed last, so an interrupted refresh can pick up
+/// where it left off.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RefreshProgress {
+    pub last_object_id: i64,
+}
+
+impl RefreshProgress {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(RefreshProgress::default());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+impl Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:?} -> {:?}", self.field, self.old, self.new)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ObjectChanges {
+    pub object_id: i64,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RefreshReport {
+    pub checked: usize,
+    /// How many records the Met API said hadn't changed since we cached them.
+    pub not_modified: usize,
+    /// Objects whose records couldn't be fetched, along with the HTTP status, e.g.
+    /// because they've been removed from the Met API.
+    pub failed: Vec<(i64, u16)>,
+    pub changed: Vec<ObjectChanges>,
+}
+
+pub struct RefreshOptions {
+    pub requests_per_second: f64,
+    /// Only refresh this many objects.
+    pub max: Option<usize>,
+    pub progress_path: PathBuf,
+    /// Ignore any existing progress and start from the first object.
+    pub restart: bool,
+}
+
+fn format_size((width, height): (f64, f64)) -> String {
+    format!("{width:.3}m x {height:.3}m")
+}
+
+/// Compares the given DB record against a freshly fetched Met API record,
+/// returning the updated DB record along with what changed.
+///
+/// The DB only stores image URLs for objects that have been enriched (see
+/// `enrich_met_images()`), so `primaryImageSmall` is compared against
+/// `previous`, the record that was cached before the refresh, if any.
+pub fn diff_met_api_record(
+    record: &ArtObjectRecord,
+    api_record: &MetObjectApiRecord,
+    previous: Option<&MetObjectApiRecord>,
+) -> (ArtObjectRecord, Vec<FieldChange>) {
+    let mut updated = record.clone();
+    let mut changes = vec![];
+    // The Met API sometimes has empty fields that are filled out in the CSV,
+    // so we don't want to clobber anything with them.
+    if !api_record.title.is_empty() && api_record.title != record.title {
+        changes.push(FieldChange {
+            field: "title",
+            old: record.title.clone(),
+            new: api_record.title.clone(),
+        });
+        updated.title = api_record.title.clone();
+    }
+    if !api_record.object_date.is_empty() && api_record.object_date != record.object_date {
+        changes.push(FieldChange {
+            field: "objectDate",
+            old: record.object_date.clone(),
+            new: api_record.object_date.clone(),
+        });
+        updated.object_date = api_record.object_date.clone();
+    }
+    if let Some((width, height)) = api_record.size_in_meters() {
+        if (width - record.width).abs() > SIZE_TOLERANCE
+            || (height - record.height).abs() > SIZE_TOLERANCE
+        {
+            changes.push(FieldChange {
+                field: "measurements",
+                old: format_size((record.width, record.height)),
+                new: format_size((width, height)),
+            });
+            updated.width = width;
+            updated.height = height;
+        }
+    }
+    if let Some(previous) = previous {
+        if previous.primary_image_small != api_record.primary_image_small {
+            changes.push(FieldChange {
+                field: "primaryImageSmall",
+                old: previous.primary_image_small.clone(),
+                new: api_record.primary_image_small.clone(),
+            });
+        }
+    }
+    (updated, changes)
+}
+
+/// Removes any cached images of the given record, so they're fetched again
+/// the next time they're needed.
+fn remove_cached_images(cache: &GalleryCache, record: &MetObjectApiRecord) -> Result<()> {
+    for size in [ImageSize::Small, ImageSize::Large] {
+        if let Some(filename) = record.get_cached_image(cache, size) {
+            std::fs::remove_file(cache.get_cached_path(filename))?;
+        }
+    }
+    Ok(())
+}
+
+/// Re-fetches the Met API record of every Met object in the DB, in ascending ID
+/// order, and updates the DB with any changes. Progress is saved after every
+/// object, so if this fails (or is interrupted), it can be resumed later.
+pub fn refresh_met_api(
+    db: &mut GalleryDb,
+    cache: &GalleryCache,
+    options: &RefreshOptions,
+) -> Result<RefreshReport> {
+    let mut rate_limiter = RateLimiter::new(options.requests_per_second)?;
+    let mut progress = if options.restart {
+        RefreshProgress::default()
+    } else {
+        RefreshProgress::load(&options.progress_path)?
+    };
+    if progress.last_object_id > 0 {
+        info!(
+            "Resuming after object #{} (progress is in {}).",
+            progress.last_object_id,
+            options.progress_path.display()
+        );
+    }
+    let mut object_ids = db.get_met_object_ids_after(progress.last_object_id)?;
+    if let Some(max) = options.max {
+        object_ids.truncate(max);
+    }
+    let mut report = RefreshReport::default();
+    for object_id in object_ids {
+        let Some(record) = db.get_art_object(ArtObjectId::Met(object_id))? else {
+            continue;
+        };
+        // If the cached record is corrupt, it'll just be replaced.
+        let previous = load_cached_met_api_record(cache, object_id).unwrap_or(None);
+        let stored_urls = db.get_met_image_urls(object_id)?;
+        rate_limiter.wait();
+        report.checked += 1;
+        match refresh_met_api_record(cache, object_id) {
+            Ok((result, api_record)) => {
+                if result == CacheResult::NotModified {
+                    report.not_modified += 1;
+                }
+                // Even if the record hasn't changed upstream, the DB might be older
+                // than the cached record, so we'll always compare them.
+                let (updated, changes) =
+                    diff_met_api_record(&record, &api_record, previous.as_ref());
+                let record_changed = updated != record;
+                if !changes.is_empty() {
+                    if record_changed {
+                        db.add_art_objects(&vec![updated])?;
+                    }
+                    if let Some(previous) = &previous {
+                        if previous.primary_image_small != api_record.primary_image_small {
+                            remove_cached_images(cache, previous)?;
+                        }
+                    }
+                    report.changed.push(ObjectChanges { object_id, changes });
+                }
+                // Replacing the DB record clears its image URLs, and they might
+                // have changed upstream anyway.
+                if let Some(stored_urls) = stored_urls {
+                    let latest_urls = api_record.image_urls();
+                    if latest_urls != stored_urls || record_changed {
+                        db.set_met_image_urls(&[(object_id, latest_urls)])?;
+                    }
+                }
+            }
+            Err(err) => match get_http_status(&err) {
+                Some(status) => {
+                    warn!("Unable to refresh object #{object_id}: {err}");
+                    report.failed.push((object_id, status));
+                }
+                None => return Err(err),
+            },
+        }
+        progress.last_object_id = object_id;
+        progress.save(&options.progress_path)?;
+    }
+    Ok(report)
+}
+
+/// How many image URLs to store in the DB per transaction.
+const ENRICH_BATCH_SIZE: usize = 100;
+
+pub struct EnrichOptions {
+    pub requests_per_second: f64,
+    /// Only enrich this many objects.
+    pub max: Option<usize>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct EnrichReport {
+    pub enriched: usize,
+    /// How many of the enriched objects needed their Met API records fetched,
+    /// rather than having them already cached.
+    pub fetched: usize,
+    /// Objects whose records couldn't be fetched, along with the HTTP status.
+    pub failed: Vec<(i64, u16)>,
+}
+
+/// Stores the image URLs of every Met object in the DB that doesn't have them
+/// yet, so that the game can fetch their images without first fetching their
+/// Met API records. Cached records are used when available, and the rest are
+/// fetched. Since only objects without URLs are looked at, an interrupted
+/// enrichment just picks up where it left off when run again.
+pub fn enrich_met_images(
+    db: &mut GalleryDb,
+    cache: &GalleryCache,
+    options: &EnrichOptions,
+) -> Result<EnrichReport> {
+    let mut rate_limiter = RateLimiter::new(options.requests_per_second)?;
+    let mut object_ids = db.get_met_object_ids_without_image_urls()?;
+    if let Some(max) = options.max {
+        object_ids.truncate(max);
+    }
+    let mut report = EnrichReport::default();
+    let mut batch = vec![];
+    for object_id in object_ids {
+        // If the cached record is corrupt, it'll just be replaced.
+        let api_record = match load_cached_met_api_record(cache, object_id).unwrap_or(None) {
+            Some(api_record) => api_record,
+            None => {
+                rate_limiter.wait();
+                match load_met_api_record(cache, object_id) {
+                    Ok(api_record) => {
+                        report.fetched += 1;
+                        api_record
+                    }
+                    Err(err) => match get_http_status(&err) {
+                        Some(status) => {
+                            warn!("Unable to fetch object #{object_id}: {err}");
+                            report.failed.push((object_id, status));
+                            continue;
+                        }
+                        None => return Err(err),
+                    },
+                }
+            }
+        };
+        batch.push((object_id, api_record.image_urls()));
+        report.enriched += 1;
+        if batch.len() >= ENRICH_BATCH_SIZE {
+            db.set_met_image_urls(&batch)?;
+            info!("Enriched {} art objects.", report.enriched);
+            batch.clear();
+        }
+    }
+    db.set_met_image_urls(&batch)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use gallery::{
+        art_object::ArtObjectId,
+        gallery_cache::{GalleryCache, HttpTransport},
+        gallery_db::{ArtObjectRecord, GalleryDb},
+        medium::MediumCategory,
+        met_api::MetImageUrls,
+    };
+    use rusqlite::Connection;
+    use ureq::Response;
+
+    use super::{
+        enrich_met_images, refresh_met_api, EnrichOptions, EnrichReport, FieldChange,
+        ObjectChanges, RateLimiter, RefreshOptions, RefreshProgress,
+    };
+
+    /// Serves the given Met API records, keeping track of which object IDs
+    /// were requested and whether the requests were conditional. Objects that
+    /// are in `not_modified` get an HTTP 304 if the request is conditional.
+    #[derive(Default)]
+    struct StubMetApi {
+        records: HashMap<i64, String>,
+        not_modified: Vec<i64>,
+        requests: Arc<Mutex<Vec<(i64, bool)>>>,
+    }
+
+    impl StubMetApi {
+        fn respond(&self, url: &str, conditional: bool) -> Result<Response, Box<ureq::Error>> {
+            let object_id: i64 = url.rsplit('/').next().unwrap().parse().unwrap();
+            self.requests.lock().unwrap().push((object_id, conditional));
+            if conditional && self.not_modified.contains(&object_id) {
+                return Ok("HTTP/1.1 304 Not Modified\r\n\r\n".parse()?);
+            }
+            match self.records.get(&object_id) {
+                Some(body) => Ok(format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{body}"
+                )
+                .parse()?),
+                None => Err(Box::new(ureq::Error::Status(
+                    404,
+                    Response::new(404, "Not Found", "")?,
+                ))),
+            }
+        }
+    }
+
+    impl HttpTransport for StubMetApi {
+        fn get(&self, url: &str) -> Result<Response, Box<ureq::Error>> {
+            self.respond(url, false)
+        }
+
+        fn get_if_modified_since(
+            &self,
+            url: &str,
+            _since: SystemTime,
+        ) -> Result<Response, Box<ureq::Error>> {
+            self.respond(url, true)
+        }
+    }
+
+    fn api_json(object_id: i64, title: &str, date: &str, image: &str) -> String {
+        format!(
+            r#"{{
+                "primaryImageSmall": "{image}",
+                "primaryImage": "",
+                "objectDate": "{date}",
+                "objectID": {object_id},
+                "title": "{title}",
+                "measurements": [
+                    {{"elementName": "Overall", "elementMeasurements": {{"Height": 100, "Width": 50}}}}
+                ]
+            }}"#
+        )
+    }
+
+    fn make_record(object_id: i64, title: &str) -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id: ArtObjectId::Met(object_id),
+            object_date: "1890".into(),
+            culture: "".into(),
+            artist: "Boop Jones".into(),
+            artist_role: String::default(),
+            title: title.into(),
+            medium: "Oil on canvas".into(),
+            medium_category: MediumCategory::Oil,
+            width: 0.5,
+            height: 1.0,
+            fallback_wikidata_qid: None,
+            filename: "".into(),
+            collection: "Metropolitan Museum of Art".into(),
+            artist_qid: None,
+            highlight: false,
+            tags: String::default(),
+            classification: String::default(),
+            source_gallery: None,
+        }
+    }
+
+    fn create_root_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "met-api-refresh-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_cached(cache: &GalleryCache, filename: &str, contents: &str) {
+        let path = cache.get_cached_path(filename);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn create_db() -> GalleryDb {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.create_schema().unwrap();
+        db.add_art_objects(&vec![
+            make_record(1, "Untitled"),
+            make_record(2, "Boring Painting"),
+            make_record(3, "Deleted Painting"),
+            make_record(4, "Another Painting"),
+        ])
+        .unwrap();
+        db
+    }
+
+    fn options(root_dir: &PathBuf, max: Option<usize>) -> RefreshOptions {
+        RefreshOptions {
+            requests_per_second: 1000.0,
+            max,
+            progress_path: root_dir.join("progress.json"),
+            restart: false,
+        }
+    }
+
+    #[test]
+    fn test_refresh_met_api_updates_changed_records() {
+        let root_dir = create_root_dir("updates");
+        let requests = Arc::new(Mutex::new(vec![]));
+        let cache = GalleryCache::with_transport(
+            root_dir.join("cache"),
+            Box::new(StubMetApi {
+                records: HashMap::from([
+                    (
+                        1,
+                        api_json(1, "Impression, Soleil Levant", "1872", "new.jpg"),
+                    ),
+                    (2, api_json(2, "Boring Painting", "1890", "")),
+                    (4, api_json(4, "Another Painting", "1890", "")),
+                ]),
+                not_modified: vec![2],
+                requests: requests.clone(),
+            }),
+        );
+        write_cached(
+            &cache,
+            "met-api/object-1.json",
+            &api_json(1, "Untitled", "1890", "https://example.com/old.jpg"),
+        );
+        write_cached(&cache, "met-api/object-1-small.jpg", "old image");
+        write_cached(
+            &cache,
+            "met-api/object-2.json",
+            &api_json(2, "Boring Painting", "1890", ""),
+        );
+        let mut db = create_db();
+
+        let report = refresh_met_api(&mut db, &cache, &options(&root_dir, Some(3))).unwrap();
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.not_modified, 1);
+        assert_eq!(report.failed, vec![(3, 404)]);
+        assert_eq!(
+            report.changed,
+            vec![ObjectChanges {
+                object_id: 1,
+                changes: vec![
+                    FieldChange {
+                        field: "title",
+                        old: "Untitled".into(),
+                        new: "Impression, Soleil Levant".into()
+                    },
+                    FieldChange {
+                        field: "objectDate",
+                        old: "1890".into(),
+                        new: "1872".into()
+                    },
+                    FieldChange {
+                        field: "primaryImageSmall",
+                        old: "https://example.com/old.jpg".into(),
+                        new: "new.jpg".into()
+                    },
+                ]
+            }]
+        );
+        // Only the objects that were already cached get conditional requests.
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![(1, true), (2, true), (3, false)]
+        );
+
+        let updated = db.get_art_object(ArtObjectId::Met(1)).unwrap().unwrap();
+        assert_eq!(updated.title, "Impression, Soleil Levant");
+        assert_eq!(updated.object_date, "1872");
+        assert_eq!(updated.artist, "Boop Jones");
+        assert_eq!(
+            db.get_art_object(ArtObjectId::Met(2)).unwrap().unwrap(),
+            make_record(2, "Boring Painting")
+        );
+        // The old image is stale now.
+        assert_eq!(cache.get_if_cached("met-api/object-1-small.jpg"), None);
+        assert_eq!(
+            RefreshProgress::load(&root_dir.join("progress.json")).unwrap(),
+            RefreshProgress { last_object_id: 3 }
+        );
+
+        // Resuming should only process what's left.
+        requests.lock().unwrap().clear();
+        let report = refresh_met_api(&mut db, &cache, &options(&root_dir, None)).unwrap();
+        assert_eq!(report.checked, 1);
+        assert_eq!(report.changed, vec![]);
+        assert_eq!(*requests.lock().unwrap(), vec![(4, false)]);
+
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn test_refresh_met_api_updates_measurements() {
+        let root_dir = create_root_dir("measurements");
+        let cache = GalleryCache::with_transport(
+            root_dir.join("cache"),
+            Box::new(StubMetApi {
+                records: HashMap::from([(2, api_json(2, "Boring Painting", "1890", ""))]),
+                ..Default::default()
+            }),
+        );
+        let mut db = create_db();
+        db.add_art_objects(&vec![ArtObjectRecord {
+            width: 0.25,
+            ..make_record(2, "Boring Painting")
+        }])
+        .unwrap();
+        let mut refresh_options = options(&root_dir, Some(1));
+        RefreshProgress { last_object_id: 1 }
+            .save(&refresh_options.progress_path)
+            .unwrap();
+
+        let report = refresh_met_api(&mut db, &cache, &refresh_options).unwrap();
+        assert_eq!(
+            report.changed,
+            vec![ObjectChanges {
+                object_id: 2,
+                changes: vec![FieldChange {
+                    field: "measurements",
+                    old: "0.250m x 1.000m".into(),
+                    new: "0.500m x 1.000m".into()
+                }]
+            }]
+        );
+        assert_eq!(
+            db.get_art_object(ArtObjectId::Met(2)).unwrap().unwrap(),
+            make_record(2, "Boring Painting")
+        );
+
+        // Restarting ignores the saved progress.
+        refresh_options.restart = true;
+        refresh_options.max = Some(0);
+        let report = refresh_met_api(&mut db, &cache, &refresh_options).unwrap();
+        assert_eq!(report.checked, 0);
+
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn test_enrich_met_images_stores_image_urls() {
+        let root_dir = create_root_dir("enrich");
+        let requests = Arc::new(Mutex::new(vec![]));
+        let cache = GalleryCache::with_transport(
+            root_dir.join("cache"),
+            Box::new(StubMetApi {
+                records: HashMap::from([
+                    (
+                        1,
+                        api_json(1, "Impression, Soleil Levant", "1872", "one.jpg"),
+                    ),
+                    (4, api_json(4, "Another Painting", "1890", "four.jpg")),
+                ]),
+                requests: requests.clone(),
+                ..Default::default()
+            }),
+        );
+        write_cached(
+            &cache,
+            "met-api/object-2.json",
+            &api_json(2, "Boring Painting", "1890", ""),
+        );
+        let mut db = create_db();
+        let enrich_options = EnrichOptions {
+            requests_per_second: 1000.0,
+            max: None,
+        };
+
+        let report = enrich_met_images(&mut db, &cache, &enrich_options).unwrap();
+        assert_eq!(
+            report,
+            EnrichReport {
+                enriched: 3,
+                fetched: 2,
+                failed: vec![(3, 404)],
+            }
+        );
+        // Cached records don't need to be fetched.
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![(1, false), (3, false), (4, false)]
+        );
+        assert_eq!(
+            db.get_met_image_urls(1).unwrap(),
+            Some(MetImageUrls {
+                primary_image: "".into(),
+                primary_image_small: "one.jpg".into(),
+            })
+        );
+        assert_eq!(
+            db.get_met_image_urls(2).unwrap(),
+            Some(MetImageUrls::default())
+        );
+        assert_eq!(db.get_met_image_urls(3).unwrap(), None);
+
+        // Running it again only retries what's left.
+        requests.lock().unwrap().clear();
+        let report = enrich_met_images(&mut db, &cache, &enrich_options).unwrap();
+        assert_eq!(report.enriched, 0);
+        assert_eq!(*requests.lock().unwrap(), vec![(3, false)]);
+
+        // Refreshing replaces the record of object 1, but keeps its URLs.
+        refresh_met_api(&mut db, &cache, &options(&root_dir, Some(1))).unwrap();
+        assert_eq!(
+            db.get_art_object(ArtObjectId::Met(1))
+                .unwrap()
+                .unwrap()
+                .title,
+            "Impression, Soleil Levant"
+        );
+        assert_eq!(
+            db.get_met_image_urls(1)
+                .unwrap()
+                .unwrap()
+                .primary_image_small,
+            "one.jpg"
+        );
+
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rate_limiter_works() {
+        assert!(RateLimiter::new(0.0).is_err());
+        let mut rate_limiter = RateLimiter::new(20.0).unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            rate_limiter.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -1,11 +1,31 @@
+use std::{
+    collections::BTreeMap,
+    io::BufRead,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+};
+
 use anyhow::Result;
+use csv::{StringRecord, StringRecordsIntoIter};
 use gallery::{
-    art_object::ArtObjectId, gallery_db::ArtObjectRecord,
+    art_object::ArtObjectId,
+    artist_names::normalize_artist_name,
+    gallery_db::ArtObjectRecord,
+    medium::{classify_medium, MEDIUM_KEYWORDS},
     wikidata::try_to_parse_qid_from_wikidata_url,
 };
 use regex_lite::Regex;
 use serde::{de, Deserialize};
 
+use crate::dimension_limits::DimensionLimits;
+use crate::import_skip::{quarantine_implausible_dimensions, skip_deserialize_errors, ImportError};
+use crate::public_domain::{
+    get_current_year, public_domain_status, PublicDomainConfidence, PublicDomainFacts,
+    PublicDomainPolicy, PublicDomainStatus,
+};
+
 // By default, struct field names are deserialized based on the position of
 // a corresponding field in the CSV data's header record.
 #[derive(Debug, Deserialize)]
@@ -13,13 +33,20 @@ pub struct MetObjectCsvRecord {
     #[serde(rename = "Is Public Domain", deserialize_with = "deserialize_csv_bool")]
     pub public_domain: bool,
 
-    //#[serde(rename = "Is Highlight", deserialize_with = "deserialize_csv_bool")]
-    //pub highlight: bool,
+    #[serde(rename = "Is Highlight", deserialize_with = "deserialize_csv_bool")]
+    pub highlight: bool,
+
     #[serde(rename = "Object ID")]
     pub object_id: i64,
 
+    #[serde(
+        rename = "Artist Begin Date",
+        deserialize_with = "deserialize_csv_year"
+    )]
+    pub artist_begin_date: Option<i32>,
+
     #[serde(rename = "Artist End Date", deserialize_with = "deserialize_csv_year")]
-    pub artist_end_date: Option<u16>,
+    pub artist_end_date: Option<i32>,
 
     #[serde(rename = "Object Wikidata URL")]
     pub object_wikidata_url: String,
@@ -32,6 +59,9 @@ pub struct MetObjectCsvRecord {
     #[serde(rename = "Object Date")]
     pub object_date: String,
 
+    #[serde(rename = "Object End Date", deserialize_with = "deserialize_csv_year")]
+    pub object_end_date: Option<i32>,
+
     #[serde(rename = "Culture")]
     pub culture: String,
 
@@ -43,97 +73,123 @@ pub struct MetObjectCsvRecord {
 
     #[serde(rename = "Dimensions")]
     pub dimensions: String,
-}
 
-/// All artworks by artists who died before this year should definitely be
-/// public domain. Different countries seem to have different qualifications,
-/// e.g. the U.S. makes any work PD 95 years after it was created, while other
-/// countries seem to base it off the time the creator died. This year seems
-/// like a reasonably conservative date to satisfy all the different
-/// countries' requirements.
-const MIN_PUBLIC_DOMAIN_YEAR: u16 = 1928;
-
-#[derive(PartialEq)]
-enum PublicDomainStatus {
-    Definitely,
-    Probably,
-    Nope,
+    // These are missing from some trimmed-down CSVs, like the demo one.
+    #[serde(rename = "Classification", default)]
+    pub classification: String,
+
+    #[serde(rename = "Tags", default)]
+    pub tags: String,
+
+    /// The number of the gallery the object is on view in, e.g. "825", or
+    /// empty if it isn't on view.
+    #[serde(rename = "Gallery Number", default)]
+    pub gallery_number: String,
 }
 
 impl MetObjectCsvRecord {
-    fn public_domain_status(&self) -> PublicDomainStatus {
-        if self.public_domain {
-            return PublicDomainStatus::Definitely;
-        } else if self.object_wikidata_url.len() > 0 {
-            if let Some(year) = self.artist_end_date {
-                if year <= MIN_PUBLIC_DOMAIN_YEAR {
-                    return PublicDomainStatus::Probably;
-                }
-            }
-        }
-        PublicDomainStatus::Nope
+    fn public_domain_status(&self, current_year: i32) -> PublicDomainStatus {
+        let facts = PublicDomainFacts {
+            is_public_domain: self.public_domain,
+            has_wikidata_url: self.object_wikidata_url.len() > 0,
+            artist_begin_year: self.artist_begin_date,
+            artist_end_year: self.artist_end_date,
+            object_end_year: self.object_end_date,
+        };
+        public_domain_status(&facts, current_year)
     }
 }
 
+/// The Met separates tags with `|`, e.g. "Portraits|Women". This removes any
+/// whitespace around them, along with any empty ones.
+fn normalize_met_tags(tags: &str) -> String {
+    tags.split('|')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// The columns that `deserialize_csv_bool()` is used for.
+const BOOL_COLUMNS: [&str; 2] = ["Is Public Domain", "Is Highlight"];
+
+/// Spreadsheet programs like to re-save "True" as "TRUE", so this ignores case.
+/// Empty cells are treated as false, see `MetCsvStats::empty_bool_rows()`.
 fn deserialize_csv_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
 where
     D: de::Deserializer<'de>,
 {
     let s: &str = de::Deserialize::deserialize(deserializer)?;
+    let trimmed = s.trim();
 
-    match s {
-        "True" => Ok(true),
-        "False" => Ok(false),
-        _ => Err(de::Error::unknown_variant(s, &["True", "False"])),
+    if trimmed.is_empty() || trimmed.eq_ignore_ascii_case("false") {
+        Ok(false)
+    } else if trimmed.eq_ignore_ascii_case("true") {
+        Ok(true)
+    } else {
+        Err(de::Error::unknown_variant(s, &["True", "False"]))
     }
 }
 
 /// A very small number of records have malformed year numbers, in such
 /// cases, we'll just ignore the field instead of erroring.
 /// Also, some records have whitespace around the year, so we'll deal with that too.
-fn deserialize_csv_year<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+/// Note that years can be negative, e.g. for objects created B.C.E.
+fn deserialize_csv_year<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
 where
     D: de::Deserializer<'de>,
 {
     let s: &str = de::Deserialize::deserialize(deserializer)?;
     let trimmed = s.trim();
 
-    match trimmed.parse::<u16>() {
+    match trimmed.parse::<i32>() {
         Ok(value) => Ok(Some(value)),
         Err(_) => Ok(None),
     }
 }
 
-/// This list was obtained by running the CLI with `--met-objects-all-media`, then
-/// running the following SQL query on the generated DB:
-///
-///     select medium, count(*) as c from art_objects group by medium order by c desc limit 60;
-///
-/// I then ignored any medium that wasn't flat, two-dimensional art with a
-/// matte surface. Examples of these are stone, glass, silk, iron, ceramic,
-/// pottery, etc.
-const MEDIUM_KEYWORDS: [&str; 20] = [
-    "watercolor",
-    "lithograph",
-    "oil",
-    "photo",
-    "drawing",
-    "gouache",
-    "chalk",
-    "canvas",
-    "ink",
-    "paper",
-    "print",
-    "aquatint",
-    "charcoal",
-    "graphite",
-    "woodblock",
-    "wood block",
-    "etching",
-    "tempera",
-    "fresco",
-    "acrylic",
-];
+/// The fraction of a Met CSV's rows that can fail to parse before the import
+/// is aborted, since that many failures probably means the CSV is mangled
+/// rather than that a few rows are.
+pub const DEFAULT_MAX_PARSE_ERROR_FRACTION: f64 = 0.01;
+
+/// Counts that are kept while parsing a Met CSV, so whoever's importing it can
+/// report on them afterwards. Rows are parsed on multiple threads with
+/// `parallel`, hence the atomics.
+#[derive(Debug, Default)]
+pub struct MetCsvStats {
+    rows: AtomicUsize,
+    parse_errors: AtomicUsize,
+    empty_bool_rows: AtomicUsize,
+}
+
+impl MetCsvStats {
+    /// How many rows have been parsed, whether or not they were imported.
+    pub fn rows(&self) -> usize {
+        self.rows.load(Ordering::SeqCst)
+    }
+
+    /// How many rows were skipped because their fields couldn't be parsed.
+    pub fn parse_errors(&self) -> usize {
+        self.parse_errors.load(Ordering::SeqCst)
+    }
+
+    /// How many rows had an empty "Is Public Domain" or "Is Highlight" cell,
+    /// which was treated as "False".
+    pub fn empty_bool_rows(&self) -> usize {
+        self.empty_bool_rows.load(Ordering::SeqCst)
+    }
+
+    fn too_many_parse_errors(&self, max_fraction: Option<f64>) -> Option<ArtObjectCsvResult> {
+        let max_fraction = max_fraction?;
+        let (rows, parse_errors) = (self.rows(), self.parse_errors());
+        if parse_errors as f64 > rows as f64 * max_fraction {
+            Some(Err(ImportError::TooManyParseErrors { parse_errors, rows }))
+        } else {
+            None
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct PublicDomain2DMetObjectOptions {
@@ -141,15 +197,29 @@ pub struct PublicDomain2DMetObjectOptions {
     pub all_media: bool,
     /// Log warnings to stderr.
     pub warnings: bool,
+    /// Whether to return artwork that's only probably public domain.
+    pub pd_policy: PublicDomainPolicy,
+    /// Artwork with dimensions outside these limits is quarantined.
+    pub dimension_limits: DimensionLimits,
+    /// Parse rows on rayon's thread pool. The results are in the same order
+    /// either way.
+    pub parallel: bool,
+    /// If more than this fraction of the rows can't be parsed, the last result
+    /// is an `ImportError::TooManyParseErrors`. If absent, rows that can't be
+    /// parsed are always just skipped.
+    pub max_parse_error_fraction: Option<f64>,
+    /// Updated as rows are parsed.
+    pub stats: Arc<MetCsvStats>,
 }
 
 fn try_into_art_object(
     dimension_parser: &DimensionParser,
     csv_record: MetObjectCsvRecord,
     options: &PublicDomain2DMetObjectOptions,
+    current_year: i32,
 ) -> Option<ArtObjectRecord> {
-    let public_domain_status = csv_record.public_domain_status();
-    if public_domain_status == PublicDomainStatus::Nope {
+    let public_domain_status = csv_record.public_domain_status(current_year);
+    if public_domain_status.confidence == PublicDomainConfidence::Nope {
         return None;
     }
     let Some((width, height)) = dimension_parser.parse_cm(&csv_record.dimensions) else {
@@ -158,30 +228,43 @@ fn try_into_art_object(
     let lower_medium = csv_record.medium.to_lowercase();
     for medium_keyword in MEDIUM_KEYWORDS.iter() {
         if options.all_media || lower_medium.contains(medium_keyword) {
-            if public_domain_status == PublicDomainStatus::Probably {
+            let is_allowed = options.pd_policy.allows(public_domain_status.confidence);
+            if public_domain_status.confidence == PublicDomainConfidence::Probably {
                 if options.warnings {
                     eprintln!(
-                        r#"WARNING: #{}, \"{}\" by {} may actually be public domain.
+                        r#"WARNING: #{}, \"{}\" by {} may actually be public domain ({}){}.
   Met collection page: https://www.metmuseum.org/art/collection/search/{}
   Wikidata URL: {}"#,
                         csv_record.object_id,
                         csv_record.title,
                         csv_record.artist_display_name,
+                        public_domain_status.reason,
+                        if is_allowed {
+                            ""
+                        } else {
+                            ", skipping due to strict public domain policy"
+                        },
                         csv_record.object_id,
                         csv_record.object_wikidata_url
                     );
                 }
-                // Since this is *probably* public domain, we'll return it.
-                // If it's not PD, we won't be able to get its image anyways, so
-                // we might as well try to get it later.
+                // If our policy allows it, since this is *probably* public domain,
+                // we'll return it. If it's not PD, we won't be able to get its
+                // image anyways, so we might as well try to get it later.
+            }
+            if !is_allowed {
+                return None;
             }
 
+            let artist = normalize_artist_name(&csv_record.artist_display_name);
             return Some(ArtObjectRecord {
                 object_id: ArtObjectId::Met(csv_record.object_id),
-                artist: csv_record.artist_display_name,
+                artist: artist.name,
+                artist_role: artist.role,
                 culture: csv_record.culture,
                 object_date: csv_record.object_date,
                 title: csv_record.title,
+                medium_category: classify_medium(&csv_record.medium),
                 medium: csv_record.medium,
                 width: width / 100.0,   // Convert centimeters to meters
                 height: height / 100.0, // Convert centimeters to meters
@@ -191,6 +274,12 @@ fn try_into_art_object(
                 .map(|qid| qid as i64),
                 filename: String::default(),
                 collection: "Metropolitan Museum of Art".into(),
+                artist_qid: None,
+                highlight: csv_record.highlight,
+                tags: normalize_met_tags(&csv_record.tags),
+                classification: csv_record.classification.trim().to_string(),
+                source_gallery: Some(csv_record.gallery_number.trim().to_string())
+                    .filter(|gallery| !gallery.is_empty()),
             });
         }
     }
@@ -198,25 +287,250 @@ fn try_into_art_object(
     None
 }
 
-type ArtObjectCsvResult = Result<ArtObjectRecord, csv::Error>;
+type ArtObjectCsvResult = Result<ArtObjectRecord, ImportError>;
+
+/// How many rows are parsed between calls to the progress callback.
+pub const PROGRESS_INTERVAL_ROWS: usize = 10_000;
+
+/// How many rows each task parses when parsing in parallel. Parsing a single
+/// row is too little work to be worth scheduling on its own.
+const PARALLEL_CHUNK_ROWS: usize = 1_000;
+
+/// How many chunks can be read ahead of the one that's being consumed, so that
+/// memory use stays bounded when the consumer (e.g. the database) is slow.
+const MAX_CHUNKS_IN_FLIGHT: usize = 64;
+
+struct MetCsvRowParser {
+    dimension_parser: DimensionParser,
+    headers: Option<StringRecord>,
+    /// The indexes of `BOOL_COLUMNS` in `headers`.
+    bool_columns: Vec<usize>,
+    options: PublicDomain2DMetObjectOptions,
+    current_year: i32,
+}
+
+impl MetCsvRowParser {
+    /// Returns `None` if the row isn't something we want to import.
+    fn parse(&self, row: csv::Result<StringRecord>) -> Option<ArtObjectCsvResult> {
+        let stats = &self.options.stats;
+        stats.rows.fetch_add(1, Ordering::SeqCst);
+        if let Ok(row) = &row {
+            let has_empty_bool = self
+                .bool_columns
+                .iter()
+                .any(|&index| row.get(index).is_some_and(|cell| cell.trim().is_empty()));
+            if has_empty_bool {
+                stats.empty_bool_rows.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let result =
+            row.and_then(|row| row.deserialize::<MetObjectCsvRecord>(self.headers.as_ref()));
+        match result {
+            Ok(csv_record) => try_into_art_object(
+                &self.dimension_parser,
+                csv_record,
+                &self.options,
+                self.current_year,
+            )
+            .map(|record| {
+                quarantine_implausible_dimensions(record, &self.options.dimension_limits)
+            }),
+            // The Met CSV doesn't have QIDs for its own objects.
+            Err(err) => {
+                let err = skip_deserialize_errors(err, None);
+                if matches!(err, ImportError::Skip(_)) {
+                    stats.parse_errors.fetch_add(1, Ordering::SeqCst);
+                }
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Calls the progress callback with the total number of rows parsed so far,
+/// every `PROGRESS_INTERVAL_ROWS` rows.
+struct RowCounter<F: FnMut(usize)> {
+    rows: usize,
+    progress: F,
+}
+
+impl<F: FnMut(usize)> RowCounter<F> {
+    fn add(&mut self, rows: usize) {
+        let intervals = self.rows / PROGRESS_INTERVAL_ROWS;
+        self.rows += rows;
+        if self.rows / PROGRESS_INTERVAL_ROWS > intervals {
+            (self.progress)(self.rows);
+        }
+    }
+}
+
+type ParsedChunk = (usize, Vec<Option<ArtObjectCsvResult>>);
+
+/// Reads raw rows on the current thread and parses them in chunks on rayon's
+/// thread pool, yielding the results in the order the rows were read, which
+/// keeps things like `--max` and de-duplication deterministic.
+struct ParallelMetCsvIterator<R: std::io::Read, F: FnMut(usize)> {
+    rows: StringRecordsIntoIter<R>,
+    parser: Arc<MetCsvRowParser>,
+    chunk_rows: usize,
+    sender: mpsc::Sender<ParsedChunk>,
+    receiver: mpsc::Receiver<ParsedChunk>,
+    /// Chunks that were parsed before the ones that were read ahead of them.
+    parsed: BTreeMap<usize, Vec<Option<ArtObjectCsvResult>>>,
+    /// The index of the next chunk to read.
+    next_read: usize,
+    /// The index of the next chunk to yield results from.
+    next_yield: usize,
+    done_reading: bool,
+    current: std::vec::IntoIter<Option<ArtObjectCsvResult>>,
+    counter: RowCounter<F>,
+}
+
+impl<R: std::io::Read, F: FnMut(usize)> ParallelMetCsvIterator<R, F> {
+    fn read_ahead(&mut self) {
+        while !self.done_reading && self.next_read - self.next_yield < MAX_CHUNKS_IN_FLIGHT {
+            let rows: Vec<_> = self.rows.by_ref().take(self.chunk_rows).collect();
+            if rows.len() < self.chunk_rows {
+                self.done_reading = true;
+            }
+            if rows.is_empty() {
+                break;
+            }
+            let index = self.next_read;
+            let parser = self.parser.clone();
+            let sender = self.sender.clone();
+            rayon::spawn(move || {
+                let results = rows.into_iter().map(|row| parser.parse(row)).collect();
+                // The iterator may have been dropped, e.g. because `--max` was reached.
+                let _ignore_hangup = sender.send((index, results));
+            });
+            self.next_read += 1;
+        }
+    }
+
+    fn take_next_chunk(&mut self) -> Vec<Option<ArtObjectCsvResult>> {
+        if let Some(chunk) = self.parsed.remove(&self.next_yield) {
+            return chunk;
+        }
+        loop {
+            let (index, chunk) = self
+                .receiver
+                .recv()
+                .expect("we hold a sender, so the channel can't be disconnected");
+            if index == self.next_yield {
+                return chunk;
+            }
+            self.parsed.insert(index, chunk);
+        }
+    }
+}
+
+impl<R: std::io::Read, F: FnMut(usize)> Iterator for ParallelMetCsvIterator<R, F> {
+    type Item = ArtObjectCsvResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.current.by_ref().flatten().next() {
+                return Some(result);
+            }
+            self.read_ahead();
+            if self.next_yield == self.next_read {
+                return None;
+            }
+            let chunk = self.take_next_chunk();
+            self.next_yield += 1;
+            self.counter.add(chunk.len());
+            self.current = chunk.into_iter();
+        }
+    }
+}
+
+fn iter_met_csv_objects<R: std::io::Read + 'static, F: FnMut(usize) + 'static>(
+    mut reader: csv::Reader<R>,
+    options: PublicDomain2DMetObjectOptions,
+    progress: F,
+    chunk_rows: usize,
+) -> Box<dyn Iterator<Item = ArtObjectCsvResult>> {
+    let parallel = options.parallel;
+    let stats = options.stats.clone();
+    let max_parse_error_fraction = options.max_parse_error_fraction;
+    let headers = reader.headers().ok().cloned();
+    let bool_columns = headers
+        .iter()
+        .flatten()
+        .enumerate()
+        .filter(|(_, header)| BOOL_COLUMNS.contains(header))
+        .map(|(index, _)| index)
+        .collect();
+    let parser = MetCsvRowParser {
+        dimension_parser: DimensionParser::new(),
+        headers,
+        bool_columns,
+        options,
+        current_year: get_current_year(),
+    };
+    let mut counter = RowCounter { rows: 0, progress };
+    let rows = reader.into_records();
+    // Only once every row has been parsed do we know what fraction of them failed.
+    let check_parse_errors =
+        std::iter::once_with(move || stats.too_many_parse_errors(max_parse_error_fraction))
+            .flatten();
+    if !parallel {
+        return Box::new(
+            rows.filter_map(move |row| {
+                counter.add(1);
+                parser.parse(row)
+            })
+            .chain(check_parse_errors),
+        );
+    }
+    let (sender, receiver) = mpsc::channel();
+    let iterator = ParallelMetCsvIterator {
+        rows,
+        parser: Arc::new(parser),
+        chunk_rows,
+        sender,
+        receiver,
+        parsed: BTreeMap::new(),
+        next_read: 0,
+        next_yield: 0,
+        done_reading: false,
+        current: vec![].into_iter(),
+        counter,
+    };
+    Box::new(iterator.chain(check_parse_errors))
+}
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
 
-pub fn iter_public_domain_2d_met_csv_objects<R: std::io::Read>(
+/// Returns a reader for a Met CSV that copes with how spreadsheet programs
+/// tend to re-save it, i.e. with a UTF-8 byte order mark before the first
+/// header, which would otherwise become part of its name, and whitespace
+/// around values.
+pub fn met_csv_reader<R: BufRead>(mut reader: R) -> std::io::Result<csv::Reader<R>> {
+    if reader.fill_buf()?.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+    }
+    Ok(csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader))
+}
+
+/// Returns the art objects in the Met CSV that we want to import, calling
+/// `progress` with the number of rows parsed so far every
+/// `PROGRESS_INTERVAL_ROWS` rows.
+pub fn iter_public_domain_2d_met_csv_objects<
+    R: std::io::Read + 'static,
+    F: FnMut(usize) + 'static,
+>(
     reader: csv::Reader<R>,
     options: PublicDomain2DMetObjectOptions,
-) -> impl Iterator<Item = ArtObjectCsvResult> {
-    let parser = DimensionParser::new();
-    reader
-        .into_deserialize::<MetObjectCsvRecord>()
-        .filter_map(move |result| match result {
-            Ok(csv_record) => match try_into_art_object(&parser, csv_record, &options) {
-                Some(record) => Some(Ok(record)),
-                None => None,
-            },
-            Err(err) => Some(Err(err)),
-        })
+    progress: F,
+) -> Box<dyn Iterator<Item = ArtObjectCsvResult>> {
+    iter_met_csv_objects(reader, options, progress, PARALLEL_CHUNK_ROWS)
 }
 
-const DIMENSIONS_REGEX: &'static str = r"^.+ \(([0-9.]+) x ([0-9.]+) cm\)$";
+const DIMENSIONS_REGEX: &str = r"^.+ \(([0-9.]+) x ([0-9.]+) cm\)$";
 
 struct DimensionParser {
     regex: Regex,
@@ -249,7 +563,300 @@ impl DimensionParser {
 
 #[cfg(test)]
 mod tests {
-    use crate::met_csv::DimensionParser;
+    use std::{
+        cell::RefCell,
+        fs::File,
+        io::{BufReader, Cursor},
+        path::PathBuf,
+        rc::Rc,
+        sync::Arc,
+    };
+
+    use gallery::art_object::ArtObjectId;
+
+    use crate::{
+        import_skip::{ImportError, ImportSkipReason},
+        met_csv::{
+            iter_met_csv_objects, met_csv_reader, normalize_met_tags, ArtObjectCsvResult,
+            DimensionParser, MetCsvStats, PublicDomain2DMetObjectOptions, PARALLEL_CHUNK_ROWS,
+            PROGRESS_INTERVAL_ROWS,
+        },
+    };
+
+    fn read_test_met_csv() -> String {
+        let manifest_dir: PathBuf = env!("CARGO_MANIFEST_DIR").into();
+        std::fs::read_to_string(
+            manifest_dir
+                .join("..")
+                .join("test_data")
+                .join("MetObjects.csv"),
+        )
+        .unwrap()
+    }
+
+    /// Returns the debug representation of each result, since errors can't be
+    /// compared directly.
+    fn parse_met_csv(csv: String, parallel: bool, chunk_rows: usize) -> Vec<String> {
+        iter_met_csv_objects(
+            csv::Reader::from_reader(Cursor::new(csv)),
+            PublicDomain2DMetObjectOptions {
+                parallel,
+                ..Default::default()
+            },
+            |_| {},
+            chunk_rows,
+        )
+        .map(|result| format!("{result:?}"))
+        .collect()
+    }
+
+    /// Parses the given file in `test_data/mangled_met_csv`, which has been
+    /// mangled the way spreadsheet programs tend to mangle the Met CSV.
+    fn parse_mangled_met_csv(
+        filename: &str,
+        max_parse_error_fraction: Option<f64>,
+        parallel: bool,
+    ) -> (Vec<ArtObjectCsvResult>, Arc<MetCsvStats>) {
+        let manifest_dir: PathBuf = env!("CARGO_MANIFEST_DIR").into();
+        let path = manifest_dir
+            .join("..")
+            .join("test_data")
+            .join("mangled_met_csv")
+            .join(filename);
+        let reader = met_csv_reader(BufReader::new(File::open(path).unwrap())).unwrap();
+        let stats = Arc::new(MetCsvStats::default());
+        let results = iter_met_csv_objects(
+            reader,
+            PublicDomain2DMetObjectOptions {
+                parallel,
+                max_parse_error_fraction,
+                stats: stats.clone(),
+                ..Default::default()
+            },
+            |_| {},
+            PARALLEL_CHUNK_ROWS,
+        )
+        .collect();
+        (results, stats)
+    }
+
+    /// Some fields have newlines in them, so rows can't be counted by line.
+    fn count_rows(csv: &str) -> usize {
+        csv::Reader::from_reader(csv.as_bytes()).records().count()
+    }
+
+    /// The test CSV with its rows repeated until there are at least `rows` of them.
+    fn make_large_met_csv(rows: usize) -> String {
+        let csv = read_test_met_csv();
+        let (header, body) = csv.split_once('\n').unwrap();
+        let body_rows = count_rows(&csv);
+        let mut large = format!("{header}\n");
+        for _ in 0..rows.div_ceil(body_rows) {
+            large.push_str(body);
+        }
+        large
+    }
+
+    #[test]
+    fn test_parallel_parsing_yields_same_results_as_serial() {
+        let serial = parse_met_csv(read_test_met_csv(), false, 1);
+        assert!(serial.len() > 0);
+        // Small chunks make it likely that they finish parsing out of order.
+        for chunk_rows in [1, 7, 100_000] {
+            assert_eq!(
+                parse_met_csv(read_test_met_csv(), true, chunk_rows),
+                serial,
+                "chunk_rows={chunk_rows}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_progress_is_reported_every_interval() {
+        for parallel in [false, true] {
+            let calls = Rc::new(RefCell::new(vec![]));
+            let progress_calls = calls.clone();
+            let csv = make_large_met_csv(PROGRESS_INTERVAL_ROWS * 2);
+            let rows = count_rows(&csv);
+            let options = PublicDomain2DMetObjectOptions {
+                parallel,
+                ..Default::default()
+            };
+            iter_met_csv_objects(
+                csv::Reader::from_reader(Cursor::new(csv)),
+                options,
+                move |rows| progress_calls.borrow_mut().push(rows),
+                1_000,
+            )
+            .for_each(drop);
+            let calls = calls.borrow();
+            assert_eq!(
+                calls.len(),
+                rows / PROGRESS_INTERVAL_ROWS,
+                "parallel={parallel}"
+            );
+            assert!(calls.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture` to see how fast
+    /// parsing the met objects CSV is with and without `parallel`.
+    #[test]
+    #[ignore]
+    fn bench_parse_met_csv() {
+        let csv = make_large_met_csv(500_000);
+        let rows = count_rows(&csv);
+        let time = |name: &str, parallel: bool| {
+            let start = std::time::Instant::now();
+            let results = parse_met_csv(csv.clone(), parallel, PARALLEL_CHUNK_ROWS);
+            let secs = start.elapsed().as_secs_f64();
+            println!(
+                "{name}: {:.0} rows/sec",
+                rows as f64 / secs.max(f64::EPSILON)
+            );
+            results
+        };
+        assert_eq!(time("serial", false), time("parallel", true));
+    }
+
+    #[test]
+    fn test_normalize_met_tags_works() {
+        assert_eq!(normalize_met_tags("Portraits|Women"), "Portraits|Women");
+        assert_eq!(
+            normalize_met_tags(" Still Life | Flowers||"),
+            "Still Life|Flowers"
+        );
+        assert_eq!(normalize_met_tags(""), "");
+    }
+
+    #[test]
+    fn test_tags_and_classification_are_parsed() {
+        let csv = "\
+Object ID,Is Highlight,Is Public Domain,Title,Culture,Artist Display Name,Artist Begin Date,Artist End Date,Object Date,Object End Date,Medium,Dimensions,Object Wikidata URL,Classification,Tags
+1,False,True,Boop,,Boop Jones,1800,1850,1840,1840,Oil on canvas,10 x 20 in. (25.4 x 50.8 cm),,Paintings,Portraits| Women
+2,False,True,Bap,,Boop Jones,1800,1850,1840,1840,Oil on canvas,10 x 20 in. (25.4 x 50.8 cm),,,
+";
+        let records: Vec<_> = iter_met_csv_objects(
+            csv::Reader::from_reader(Cursor::new(csv)),
+            Default::default(),
+            |_| {},
+            PARALLEL_CHUNK_ROWS,
+        )
+        .map(Result::unwrap)
+        .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].tags, "Portraits|Women");
+        assert_eq!(records[0].classification, "Paintings");
+        assert_eq!(records[1].tags, "");
+        assert_eq!(records[1].classification, "");
+    }
+
+    #[test]
+    fn test_gallery_number_is_parsed() {
+        let csv = "\
+Object ID,Is Highlight,Is Public Domain,Title,Culture,Artist Display Name,Artist Begin Date,Artist End Date,Object Date,Object End Date,Medium,Dimensions,Object Wikidata URL,Gallery Number
+1,False,True,Boop,,Boop Jones,1800,1850,1840,1840,Oil on canvas,10 x 20 in. (25.4 x 50.8 cm),, 825 
+2,False,True,Bap,,Boop Jones,1800,1850,1840,1840,Oil on canvas,10 x 20 in. (25.4 x 50.8 cm),,
+";
+        let records: Vec<_> = iter_met_csv_objects(
+            csv::Reader::from_reader(Cursor::new(csv)),
+            Default::default(),
+            |_| {},
+            PARALLEL_CHUNK_ROWS,
+        )
+        .map(Result::unwrap)
+        .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].source_gallery, Some("825".into()));
+        assert_eq!(records[1].source_gallery, None);
+    }
+
+    #[test]
+    fn test_byte_order_mark_is_ignored() {
+        let (results, stats) = parse_mangled_met_csv("bom.csv", Some(0.0), false);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap().object_id, ArtObjectId::Met(1));
+        assert_eq!(stats.parse_errors(), 0);
+    }
+
+    #[test]
+    fn test_bools_are_case_insensitive() {
+        let (results, _) = parse_mangled_met_csv("uppercase_bools.csv", Some(0.0), false);
+        let highlights: Vec<(ArtObjectId, bool)> = results
+            .into_iter()
+            .map(|result| {
+                let record = result.unwrap();
+                (record.object_id, record.highlight)
+            })
+            .collect();
+        assert_eq!(
+            highlights,
+            vec![(ArtObjectId::Met(1), true), (ArtObjectId::Met(2), false)]
+        );
+    }
+
+    #[test]
+    fn test_empty_bools_are_false() {
+        let (results, stats) = parse_mangled_met_csv("empty_bools.csv", Some(0.0), false);
+        // The second row isn't public domain, so it isn't imported.
+        assert_eq!(results.len(), 1);
+        let record = results[0].as_ref().unwrap();
+        assert_eq!(record.object_id, ArtObjectId::Met(1));
+        assert_eq!(record.highlight, false);
+        assert_eq!(stats.rows(), 2);
+        assert_eq!(stats.empty_bool_rows(), 2);
+        assert_eq!(stats.parse_errors(), 0);
+    }
+
+    #[test]
+    fn test_unparseable_rows_are_skipped() {
+        let (results, stats) = parse_mangled_met_csv("mostly_broken.csv", None, false);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().object_id, ArtObjectId::Met(1));
+        for result in &results[1..] {
+            let Err(ImportError::Skip(skip)) = result else {
+                panic!("expected skip, got {result:?}");
+            };
+            assert_eq!(skip.reason, ImportSkipReason::DeserializeFailed);
+        }
+        assert_eq!(stats.parse_errors(), 2);
+    }
+
+    #[test]
+    fn test_too_many_unparseable_rows_is_an_error() {
+        for parallel in [false, true] {
+            let (results, _) = parse_mangled_met_csv("mostly_broken.csv", Some(0.5), parallel);
+            assert!(
+                matches!(
+                    results.last(),
+                    Some(Err(ImportError::TooManyParseErrors {
+                        parse_errors: 2,
+                        rows: 3
+                    }))
+                ),
+                "parallel={parallel}"
+            );
+        }
+        let (results, _) = parse_mangled_met_csv("mostly_broken.csv", Some(0.7), false);
+        assert_eq!(results.len(), 3);
+    }
+
+    #[test]
+    fn test_test_met_csv_tags_are_imported() {
+        let records: Vec<_> = iter_met_csv_objects(
+            csv::Reader::from_reader(Cursor::new(read_test_met_csv())),
+            Default::default(),
+            |_| {},
+            PARALLEL_CHUNK_ROWS,
+        )
+        .filter_map(Result::ok)
+        .collect();
+        let advertisement = records
+            .iter()
+            .find(|record| record.object_id == ArtObjectId::Met(39))
+            .unwrap();
+        assert_eq!(advertisement.tags, "Advertisements");
+    }
 
     #[test]
     fn test_dimensions_parse_works() {
@@ -0,0 +1,226 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+/// Environment variable that sets the cache directory, unless `--cache-dir` is given.
+pub const CACHE_DIR_ENV_VAR: &str = "GALLERY_CACHE_DIR";
+
+/// The name of the app's directory within the platform's data directory.
+const APP_DIR_NAME: &str = "atuls-art-gallery";
+
+const DEFAULT_WALLS_FILENAME: &str = "moma-gallery.walls.json";
+
+/// The places the CLI looks for its data when it isn't told where it is.
+pub struct SearchPaths {
+    /// The app's directory in the platform's per-user data directory, if the
+    /// platform has one.
+    pub data_dir: Option<PathBuf>,
+    /// Where the cache lives in a development checkout. This is only meaningful
+    /// when the CLI is run from the checkout it was built from, not when it's
+    /// installed via `cargo install`.
+    pub dev_cache_dir: PathBuf,
+    /// Where the default walls live in a development checkout.
+    pub dev_walls_path: PathBuf,
+    /// Where the demo dataset lives in a development checkout. It isn't
+    /// installed anywhere else.
+    pub dev_demo_dir: PathBuf,
+}
+
+impl SearchPaths {
+    /// Uses `get_env` to look up environment variables, so tests don't need to
+    /// mess with the real environment.
+    pub fn from_env<F: Fn(&str) -> Option<OsString>>(get_env: F) -> Self {
+        let manifest_dir: PathBuf = env!("CARGO_MANIFEST_DIR").into();
+        SearchPaths {
+            data_dir: platform_data_dir(&get_env).map(|dir| dir.join(APP_DIR_NAME)),
+            dev_cache_dir: manifest_dir.join("..").join("cache"),
+            dev_walls_path: manifest_dir
+                .join("..")
+                .join("..")
+                .join("Levels")
+                .join(DEFAULT_WALLS_FILENAME),
+            dev_demo_dir: manifest_dir.join("..").join("test_data").join("demo"),
+        }
+    }
+
+    /// In order of preference: `--cache-dir`, the `GALLERY_CACHE_DIR` env var,
+    /// the platform data directory if it exists, the development checkout's
+    /// cache if it exists, and finally the platform data directory anyways,
+    /// since that's where a fresh install should put things.
+    pub fn resolve_cache_dir<F: Fn(&str) -> Option<OsString>>(
+        &self,
+        cache_dir_arg: Option<PathBuf>,
+        get_env: F,
+    ) -> PathBuf {
+        if let Some(cache_dir) = cache_dir_arg {
+            return cache_dir;
+        }
+        if let Some(cache_dir) = get_env(CACHE_DIR_ENV_VAR).filter(|value| !value.is_empty()) {
+            return cache_dir.into();
+        }
+        if let Some(data_dir) = &self.data_dir {
+            if data_dir.is_dir() {
+                return data_dir.clone();
+            }
+        }
+        if self.dev_cache_dir.is_dir() || self.data_dir.is_none() {
+            return self.dev_cache_dir.clone();
+        }
+        self.data_dir.clone().unwrap()
+    }
+
+    /// Used when no `--walls` are given. Looks in the cache directory first, so
+    /// installed copies of the CLI can be pointed at walls by dropping them
+    /// there, and then in the development checkout.
+    pub fn default_walls_path(&self, cache_dir: &Path) -> Result<PathBuf> {
+        let candidates = [
+            cache_dir.join(DEFAULT_WALLS_FILENAME),
+            self.dev_walls_path.clone(),
+        ];
+        for candidate in &candidates {
+            if candidate.is_file() {
+                return Ok(candidate.clone());
+            }
+        }
+        Err(anyhow!(
+            "Unable to find default walls, please use --walls. Looked in: {}",
+            candidates
+                .iter()
+                .map(|candidate| candidate.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+/// Like `dirs::data_dir()`.
+fn platform_data_dir<F: Fn(&str) -> Option<OsString>>(get_env: &F) -> Option<PathBuf> {
+    let get_dir = |name: &str| {
+        get_env(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+    };
+    if cfg!(windows) {
+        get_dir("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        get_dir("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        get_dir("XDG_DATA_HOME").or_else(|| get_dir("HOME").map(|home| home.join(".local/share")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, ffi::OsString, fs, path::PathBuf};
+
+    use super::{SearchPaths, CACHE_DIR_ENV_VAR, DEFAULT_WALLS_FILENAME};
+
+    fn create_root_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("paths-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn fake_env(vars: &[(&str, &PathBuf)]) -> impl Fn(&str) -> Option<OsString> {
+        let vars: HashMap<String, OsString> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.as_os_str().to_owned()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    fn make_search_paths(root: &PathBuf) -> SearchPaths {
+        SearchPaths {
+            data_dir: Some(root.join("data").join("atuls-art-gallery")),
+            dev_cache_dir: root.join("checkout").join("cache"),
+            dev_walls_path: root.join("checkout").join(DEFAULT_WALLS_FILENAME),
+            dev_demo_dir: root.join("checkout").join("demo"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_cache_dir_order() {
+        let root = create_root_dir("cache-dir");
+        let paths = make_search_paths(&root);
+        let env_dir = root.join("from-env");
+        let env = fake_env(&[(CACHE_DIR_ENV_VAR, &env_dir)]);
+        let no_env = fake_env(&[]);
+
+        let arg_dir = root.join("from-arg");
+        assert_eq!(
+            paths.resolve_cache_dir(Some(arg_dir.clone()), &env),
+            arg_dir
+        );
+        assert_eq!(paths.resolve_cache_dir(None, &env), env_dir);
+
+        // Nothing exists yet, so a fresh install should use the data dir.
+        let data_dir = paths.data_dir.clone().unwrap();
+        assert_eq!(paths.resolve_cache_dir(None, &no_env), data_dir);
+
+        fs::create_dir_all(&paths.dev_cache_dir).unwrap();
+        assert_eq!(paths.resolve_cache_dir(None, &no_env), paths.dev_cache_dir);
+
+        fs::create_dir_all(&data_dir).unwrap();
+        assert_eq!(paths.resolve_cache_dir(None, &no_env), data_dir);
+
+        let empty_env = fake_env(&[(CACHE_DIR_ENV_VAR, &PathBuf::new())]);
+        assert_eq!(paths.resolve_cache_dir(None, &empty_env), data_dir);
+    }
+
+    #[test]
+    fn test_resolve_cache_dir_without_data_dir() {
+        let root = create_root_dir("no-data-dir");
+        let paths = SearchPaths {
+            data_dir: None,
+            ..make_search_paths(&root)
+        };
+        assert_eq!(
+            paths.resolve_cache_dir(None, fake_env(&[])),
+            paths.dev_cache_dir
+        );
+    }
+
+    #[test]
+    fn test_from_env_uses_platform_data_dir() {
+        let root = create_root_dir("from-env");
+        let paths = SearchPaths::from_env(fake_env(&[
+            ("XDG_DATA_HOME", &root),
+            ("HOME", &root),
+            ("APPDATA", &root),
+        ]));
+        let data_dir = paths.data_dir.unwrap();
+        assert!(data_dir.starts_with(&root));
+        assert!(data_dir.ends_with("atuls-art-gallery"));
+
+        let relative = PathBuf::from("relative");
+        let paths = SearchPaths::from_env(fake_env(&[
+            ("XDG_DATA_HOME", &relative),
+            ("HOME", &relative),
+            ("APPDATA", &relative),
+        ]));
+        assert_eq!(paths.data_dir, None);
+    }
+
+    #[test]
+    fn test_default_walls_path_order() {
+        let root = create_root_dir("walls");
+        let paths = make_search_paths(&root);
+        let cache_dir = root.join("cache");
+        fs::create_dir_all(&cache_dir).unwrap();
+        assert!(paths.default_walls_path(&cache_dir).is_err());
+
+        fs::create_dir_all(paths.dev_walls_path.parent().unwrap()).unwrap();
+        fs::write(&paths.dev_walls_path, "[]").unwrap();
+        assert_eq!(
+            paths.default_walls_path(&cache_dir).unwrap(),
+            paths.dev_walls_path
+        );
+
+        let cached_walls = cache_dir.join(DEFAULT_WALLS_FILENAME);
+        fs::write(&cached_walls, "[]").unwrap();
+        assert_eq!(paths.default_walls_path(&cache_dir).unwrap(), cached_walls);
+    }
+}
@@ -0,0 +1,246 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Many countries make a work public domain this many years after its
+/// creator died.
+const YEARS_AFTER_DEATH: i32 = 70;
+
+/// The U.S. makes a work public domain this many years after it was
+/// created (well, published, but we don't know that).
+const YEARS_AFTER_CREATION: i32 = 95;
+
+/// If we know when an artist was born but not when they died, we'll assume
+/// they lived at most this long.
+const MAX_LIFESPAN: i32 = 110;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PublicDomainConfidence {
+    Definitely,
+    Probably,
+    Nope,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PublicDomainStatus {
+    pub confidence: PublicDomainConfidence,
+    /// A human-readable explanation of how we arrived at the confidence.
+    pub reason: String,
+}
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, clap::ValueEnum)]
+pub enum PublicDomainPolicy {
+    /// Only import works that are definitely public domain.
+    Strict,
+    /// Also import works that are probably public domain. If they're not,
+    /// we won't be able to get their images anyways.
+    #[default]
+    Permissive,
+}
+
+impl PublicDomainPolicy {
+    pub fn allows(&self, confidence: PublicDomainConfidence) -> bool {
+        match confidence {
+            PublicDomainConfidence::Definitely => true,
+            PublicDomainConfidence::Probably => *self == PublicDomainPolicy::Permissive,
+            PublicDomainConfidence::Nope => false,
+        }
+    }
+}
+
+/// Everything we know about a work that's relevant to its public domain status.
+#[derive(Debug, Default, Clone)]
+pub struct PublicDomainFacts {
+    /// Whether the collection explicitly says the work is public domain.
+    pub is_public_domain: bool,
+    /// Whether the work has a Wikidata entry, which is where we'd get its
+    /// image from if the collection doesn't consider it public domain.
+    pub has_wikidata_url: bool,
+    pub artist_begin_year: Option<i32>,
+    pub artist_end_year: Option<i32>,
+    pub object_end_year: Option<i32>,
+}
+
+pub fn get_current_year() -> i32 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("current time should be after the unix epoch")
+        .as_secs();
+    // This is off by at most a day around new year's, which is fine for our purposes.
+    1970 + (secs as f64 / (365.2425 * 24.0 * 60.0 * 60.0)) as i32
+}
+
+/// Returns whether the artist died long enough ago, or `None` if we don't know.
+fn check_artist_death(facts: &PublicDomainFacts, current_year: i32) -> Option<(bool, String)> {
+    if let Some(end_year) = facts.artist_end_year {
+        let ok = end_year + YEARS_AFTER_DEATH < current_year;
+        let reason = if ok {
+            format!("artist died in {end_year}, more than {YEARS_AFTER_DEATH} years ago")
+        } else {
+            format!("artist died in {end_year}, less than {YEARS_AFTER_DEATH} years ago")
+        };
+        return Some((ok, reason));
+    }
+    let begin_year = facts.artist_begin_year?;
+    if begin_year + YEARS_AFTER_DEATH >= current_year {
+        Some((
+            false,
+            format!("artist was born in {begin_year}, less than {YEARS_AFTER_DEATH} years ago"),
+        ))
+    } else if begin_year + MAX_LIFESPAN + YEARS_AFTER_DEATH < current_year {
+        Some((
+            true,
+            format!("artist was born in {begin_year}, so likely died more than {YEARS_AFTER_DEATH} years ago"),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Returns whether the work was created long enough ago, or `None` if we don't know.
+fn check_creation(facts: &PublicDomainFacts, current_year: i32) -> Option<(bool, String)> {
+    let end_year = facts.object_end_year?;
+    let ok = end_year + YEARS_AFTER_CREATION < current_year;
+    let reason = if ok {
+        format!("created by {end_year}, more than {YEARS_AFTER_CREATION} years ago")
+    } else {
+        format!("created in {end_year}, less than {YEARS_AFTER_CREATION} years ago")
+    };
+    Some((ok, reason))
+}
+
+/// Determine whether a work is public domain.
+///
+/// If the collection doesn't explicitly say so, a work is only probably public
+/// domain if every rule we have enough information to check says it is, and
+/// we have enough information to check at least one of them.
+pub fn public_domain_status(facts: &PublicDomainFacts, current_year: i32) -> PublicDomainStatus {
+    let status = |confidence, reason: String| PublicDomainStatus { confidence, reason };
+    if facts.is_public_domain {
+        return status(
+            PublicDomainConfidence::Definitely,
+            "marked as public domain by the collection".into(),
+        );
+    }
+    if !facts.has_wikidata_url {
+        return status(
+            PublicDomainConfidence::Nope,
+            "not marked as public domain and has no Wikidata URL".into(),
+        );
+    }
+    let checks: Vec<(bool, String)> = [
+        check_artist_death(facts, current_year),
+        check_creation(facts, current_year),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if checks.is_empty() {
+        return status(
+            PublicDomainConfidence::Nope,
+            "not marked as public domain, and its artist death and creation years are unknown"
+                .into(),
+        );
+    }
+    if let Some((_, reason)) = checks.iter().find(|(ok, _)| !ok) {
+        return status(PublicDomainConfidence::Nope, reason.clone());
+    }
+    let reasons: Vec<String> = checks.into_iter().map(|(_, reason)| reason).collect();
+    status(PublicDomainConfidence::Probably, reasons.join(" and "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        public_domain_status, PublicDomainConfidence, PublicDomainFacts, PublicDomainPolicy,
+    };
+
+    const CURRENT_YEAR: i32 = 2024;
+
+    #[test]
+    fn test_public_domain_status_works() {
+        use PublicDomainConfidence::*;
+
+        // (is_public_domain, has_wikidata_url, artist_begin_year, artist_end_year, object_end_year, expected)
+        let cases = [
+            (true, false, None, None, None, Definitely),
+            (true, true, None, Some(2000), Some(2000), Definitely),
+            (false, false, None, Some(1800), Some(1800), Nope),
+            (false, true, None, None, None, Nope),
+            // Artist death year alone.
+            (false, true, None, Some(1900), None, Probably),
+            (false, true, None, Some(1953), None, Probably),
+            (false, true, None, Some(1954), None, Nope),
+            (false, true, None, Some(9999), None, Nope),
+            // Creation year alone.
+            (false, true, None, None, Some(1928), Probably),
+            (false, true, None, None, Some(1929), Nope),
+            (false, true, None, None, Some(-500), Probably),
+            // Both, which must agree.
+            (false, true, None, Some(1900), Some(1890), Probably),
+            (false, true, None, Some(1950), Some(1945), Nope),
+            (false, true, None, Some(1990), Some(1900), Nope),
+            // Artist birth year, when we don't know when they died.
+            (false, true, Some(1800), None, None, Probably),
+            (false, true, Some(1900), None, None, Nope),
+            (false, true, Some(1960), None, None, Nope),
+            (false, true, Some(1960), None, Some(1900), Nope),
+            (false, true, Some(1850), None, Some(1900), Probably),
+            // The death year takes precedence over the birth year.
+            (false, true, Some(1960), Some(1950), None, Probably),
+        ];
+        for (
+            is_public_domain,
+            has_wikidata_url,
+            artist_begin_year,
+            artist_end_year,
+            object_end_year,
+            expected,
+        ) in cases
+        {
+            let facts = PublicDomainFacts {
+                is_public_domain,
+                has_wikidata_url,
+                artist_begin_year,
+                artist_end_year,
+                object_end_year,
+            };
+            let status = public_domain_status(&facts, CURRENT_YEAR);
+            assert_eq!(status.confidence, expected, "{facts:?}: {}", status.reason);
+            assert!(status.reason.len() > 0);
+        }
+    }
+
+    #[test]
+    fn test_reasons_are_descriptive() {
+        let facts = PublicDomainFacts {
+            has_wikidata_url: true,
+            artist_end_year: Some(1900),
+            object_end_year: Some(1890),
+            ..Default::default()
+        };
+        assert_eq!(
+            public_domain_status(&facts, CURRENT_YEAR).reason,
+            "artist died in 1900, more than 70 years ago and created by 1890, more than 95 years ago"
+        );
+    }
+
+    #[test]
+    fn test_policy_works() {
+        use PublicDomainConfidence::*;
+
+        let cases = [
+            (PublicDomainPolicy::Strict, Definitely, true),
+            (PublicDomainPolicy::Strict, Probably, false),
+            (PublicDomainPolicy::Strict, Nope, false),
+            (PublicDomainPolicy::Permissive, Definitely, true),
+            (PublicDomainPolicy::Permissive, Probably, true),
+            (PublicDomainPolicy::Permissive, Nope, false),
+        ];
+        for (policy, confidence, expected) in cases {
+            assert_eq!(
+                policy.allows(confidence),
+                expected,
+                "{policy:?} {confidence:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::{thread::sleep, time::Duration};
+
+use anyhow::{anyhow, Result};
+use gallery::gallery_cache::GalleryCache;
+use log::warn;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::met_api_refresh::RateLimiter;
+
+/// The most entities that `wbgetentities` will return at once.
+pub const WBGETENTITIES_BATCH_SIZE: usize = 50;
+
+pub const DEFAULT_WIKIDATA_API_REQUESTS_PER_SECOND: f64 = 1.0;
+
+/// Asks the API to refuse our requests while its database replicas are lagging
+/// behind by more than this many seconds, as is customary for bots, see
+/// https://www.mediawiki.org/wiki/Manual:Maxlag_parameter.
+const MAXLAG_SECS: u32 = 5;
+
+/// How many times we'll retry a request that was refused because of `maxlag`.
+const MAX_MAXLAG_RETRIES: u32 = 3;
+
+/// How long we'll wait before retrying a request that was refused because of
+/// `maxlag`, multiplied by the number of times it's been refused.
+const MAXLAG_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+struct WbGetEntitiesError {
+    code: String,
+    #[serde(default)]
+    info: String,
+}
+
+#[derive(Deserialize)]
+struct WbGetEntitiesResponse {
+    #[serde(default)]
+    entities: Map<String, Value>,
+    error: Option<WbGetEntitiesError>,
+}
+
+pub fn wbgetentities_url(qids: &[u64]) -> String {
+    let ids: Vec<String> = qids.iter().map(|qid| format!("Q{qid}")).collect();
+    format!(
+        "https://www.wikidata.org/w/api.php?action=wbgetentities&ids={}&format=json&maxlag={MAXLAG_SECS}",
+        ids.join("%7C")
+    )
+}
+
+/// The filename in the gallery cache that the response to fetching the given
+/// (sorted) QIDs is kept in. There can be up to `WBGETENTITIES_BATCH_SIZE` of
+/// them, so rather than listing them all, the name includes a checksum of them.
+pub fn wbgetentities_cache_filename(qids: &[u64]) -> String {
+    // This is FNV-1a, which is stable across Rust versions, unlike `Hash`.
+    let mut checksum: u64 = 0xcbf29ce484222325;
+    for byte in qids.iter().flat_map(|qid| qid.to_le_bytes()) {
+        checksum ^= byte as u64;
+        checksum = checksum.wrapping_mul(0x100000001b3);
+    }
+    let first = qids.first().copied().unwrap_or_default();
+    format!(
+        "wikidata-api/wbgetentities-Q{first}-{}-{checksum:016x}.json",
+        qids.len()
+    )
+}
+
+enum UnwrapError {
+    Maxlag(String),
+    Other(anyhow::Error),
+}
+
+/// The API wraps entities in an `entities` object keyed by the QIDs that were
+/// asked for, each of which is in the same format as in the dumpfile. Returns
+/// the QID and JSON of each entity that exists.
+///
+/// Note that redirected QIDs are keyed by the QID that was asked for, but the
+/// entity itself has the QID it was redirected to, which is what's returned.
+fn unwrap_wbgetentities_response(json: &str) -> Result<Vec<(u64, String)>, UnwrapError> {
+    let response: WbGetEntitiesResponse =
+        serde_json::from_str(json).map_err(|err| UnwrapError::Other(err.into()))?;
+    if let Some(error) = response.error {
+        if error.code == "maxlag" {
+            return Err(UnwrapError::Maxlag(error.info));
+        }
+        return Err(UnwrapError::Other(anyhow!(
+            "Wikidata API error {}: {}",
+            error.code,
+            error.info
+        )));
+    }
+    let mut result = Vec::with_capacity(response.entities.len());
+    for (key, entity) in response.entities {
+        if entity.get("missing").is_some() {
+            continue;
+        }
+        let qid = entity
+            .get("id")
+            .and_then(Value::as_str)
+            .and_then(|id| id.strip_prefix('Q'))
+            .and_then(|id| id.parse::<u64>().ok())
+            .ok_or_else(|| UnwrapError::Other(anyhow!("Entity {key} has no valid ID")))?;
+        result.push((qid, entity.to_string()));
+    }
+    Ok(result)
+}
+
+/// Fetches entities from the `wbgetentities` API rather than a dumpfile, which
+/// is far more convenient for small sets of QIDs. Responses are kept in the
+/// gallery cache, so fetching the same QIDs again doesn't touch the network.
+pub struct WikidataApiFetcher<'a> {
+    cache: &'a GalleryCache,
+    rate_limiter: RateLimiter,
+}
+
+impl<'a> WikidataApiFetcher<'a> {
+    pub fn new(cache: &'a GalleryCache, requests_per_second: f64) -> Result<Self> {
+        Ok(WikidataApiFetcher {
+            cache,
+            rate_limiter: RateLimiter::new(requests_per_second)?,
+        })
+    }
+
+    /// Fetches the given QIDs, which should be sorted and at most
+    /// `WBGETENTITIES_BATCH_SIZE` long, returning the QID and JSON of each
+    /// entity that exists.
+    fn fetch_batch(&mut self, qids: &[u64]) -> Result<Vec<(u64, String)>> {
+        let filename = wbgetentities_cache_filename(qids);
+        let mut retries = 0;
+        loop {
+            if self.cache.get_if_cached(&filename).is_none() {
+                self.rate_limiter.wait();
+            }
+            self.cache
+                .cache_json_url(wbgetentities_url(qids), &filename, false)?;
+            let result = unwrap_wbgetentities_response(&self.cache.load_cached_string(&filename)?);
+            if result.is_err() {
+                // Otherwise we'd never ask again.
+                std::fs::remove_file(self.cache.get_cached_path(&filename))?;
+            }
+            match result {
+                Ok(entities) => return Ok(entities),
+                Err(UnwrapError::Maxlag(info)) if retries < MAX_MAXLAG_RETRIES => {
+                    retries += 1;
+                    warn!("Wikidata API is lagging ({info}), retrying.");
+                    sleep(MAXLAG_RETRY_DELAY * retries);
+                }
+                Err(UnwrapError::Maxlag(info)) => {
+                    return Err(anyhow!("Wikidata API is lagging: {info}"));
+                }
+                Err(UnwrapError::Other(err)) => return Err(err),
+            }
+        }
+    }
+
+    /// Fetches the given QIDs in batches, returning the QID and JSON of each
+    /// entity that exists.
+    pub fn fetch(&mut self, mut qids: Vec<u64>) -> Result<Vec<(u64, String)>> {
+        // Sorting makes the batches, and therefore their cache filenames,
+        // the same no matter what order the QIDs were given in.
+        qids.sort();
+        qids.dedup();
+        let batches = qids.chunks(WBGETENTITIES_BATCH_SIZE);
+        let total_batches = batches.len();
+        let mut result = Vec::with_capacity(qids.len());
+        for (i, batch) in batches.enumerate() {
+            println!(
+                "Fetching batch {} of {total_batches} from the Wikidata API.",
+                i + 1
+            );
+            result.extend(self.fetch_batch(batch)?);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        unwrap_wbgetentities_response, wbgetentities_cache_filename, wbgetentities_url, UnwrapError,
+    };
+
+    #[test]
+    fn test_wbgetentities_url_works() {
+        assert_eq!(
+            wbgetentities_url(&[1, 42]),
+            "https://www.wikidata.org/w/api.php?action=wbgetentities&ids=Q1%7CQ42&format=json&maxlag=5"
+        );
+    }
+
+    #[test]
+    fn test_wbgetentities_cache_filename_depends_on_every_qid() {
+        let filename = wbgetentities_cache_filename(&[1, 2, 3]);
+        assert!(filename.starts_with("wikidata-api/wbgetentities-Q1-3-"));
+        assert_eq!(filename, wbgetentities_cache_filename(&[1, 2, 3]));
+        assert_ne!(filename, wbgetentities_cache_filename(&[1, 5, 3]));
+    }
+
+    #[test]
+    fn test_unwrap_wbgetentities_response_works() {
+        let json = r#"{
+            "entities": {
+                "Q1": {"type": "item", "id": "Q1", "claims": {}},
+                "Q2": {"id": "Q2", "missing": ""},
+                "Q3": {"type": "item", "id": "Q30", "claims": {}}
+            },
+            "success": 1
+        }"#;
+        let Ok(mut entities) = unwrap_wbgetentities_response(json) else {
+            panic!("expected response to unwrap");
+        };
+        entities.sort();
+        let qids: Vec<u64> = entities.iter().map(|(qid, _)| *qid).collect();
+        assert_eq!(qids, vec![1, 30]);
+        let entity: serde_json::Value = serde_json::from_str(&entities[0].1).unwrap();
+        assert_eq!(entity["type"], "item");
+    }
+
+    #[test]
+    fn test_unwrap_wbgetentities_response_reports_errors() {
+        let maxlag = r#"{"error": {"code": "maxlag", "info": "Waiting for a database server: 7 seconds lagged."}}"#;
+        assert!(matches!(
+            unwrap_wbgetentities_response(maxlag),
+            Err(UnwrapError::Maxlag(info)) if info.contains("7 seconds")
+        ));
+        let other = r#"{"error": {"code": "no-such-entity", "info": "Could not find Q0."}}"#;
+        let Err(UnwrapError::Other(err)) = unwrap_wbgetentities_response(other) else {
+            panic!("expected error");
+        };
+        assert!(err.to_string().contains("no-such-entity"));
+    }
+}
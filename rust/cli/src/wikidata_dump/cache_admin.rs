@@ -0,0 +1,405 @@
+use super::query::PreparedQuery;
+use super::sledcache::sledcache_path_for_dumpfile;
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashSet,
+    hash::{DefaultHasher, Hasher},
+    path::{Path, PathBuf},
+};
+
+/// How many entries we'll accumulate before copying them to the compacted
+/// sledcache as a single atomic batch.
+const COMPACTION_BATCH_SIZE: usize = 10_000;
+
+/// Appended to the sledcache's path to get where it's compacted to.
+const COMPACTING_SUFFIX: &str = ".compacting";
+
+/// Appended to the sledcache's path to get where it's moved while the compacted
+/// one takes its place.
+const PRE_COMPACTION_SUFFIX: &str = ".pre-compaction";
+
+#[derive(Debug, PartialEq)]
+pub struct SledcacheStats {
+    pub entries: usize,
+    /// Total size of all cached values, in bytes.
+    pub value_bytes: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SledcacheCompactionStats {
+    pub retained: usize,
+    pub removed: usize,
+}
+
+/// A checksum of a sledcache's entries, in the order sled iterates over them.
+#[derive(Debug, PartialEq)]
+struct SledcacheChecksum {
+    entries: usize,
+    hash: u64,
+}
+
+struct SledcacheChecksummer {
+    entries: usize,
+    hasher: DefaultHasher,
+}
+
+impl SledcacheChecksummer {
+    fn new() -> Self {
+        SledcacheChecksummer {
+            entries: 0,
+            hasher: DefaultHasher::new(),
+        }
+    }
+
+    fn add(&mut self, key: &[u8], value: &[u8]) {
+        self.entries += 1;
+        // Prefix each part with its length, so e.g. moving a byte from the end
+        // of a key to the start of its value changes the checksum.
+        for part in [key, value] {
+            self.hasher.write_usize(part.len());
+            self.hasher.write(part);
+        }
+    }
+
+    fn finish(self) -> SledcacheChecksum {
+        SledcacheChecksum {
+            entries: self.entries,
+            hash: self.hasher.finish(),
+        }
+    }
+}
+
+fn checksum_sledcache(sledcache: &sled::Db) -> Result<SledcacheChecksum> {
+    let mut checksummer = SledcacheChecksummer::new();
+    for result in sledcache.iter() {
+        let (key, value) = result?;
+        checksummer.add(&key, &value);
+    }
+    Ok(checksummer.finish())
+}
+
+fn qid_from_key(key: &[u8]) -> Option<u64> {
+    let bytes: [u8; 8] = key.try_into().ok()?;
+    Some(u64::from_be_bytes(bytes))
+}
+
+fn get_sledcache_stats(sledcache: &sled::Db) -> Result<SledcacheStats> {
+    let mut entries = 0;
+    let mut value_bytes = 0;
+    for result in sledcache.iter() {
+        let (_key, value) = result?;
+        entries += 1;
+        value_bytes += value.len() as u64;
+    }
+    Ok(SledcacheStats {
+        entries,
+        value_bytes,
+    })
+}
+
+/// Copies all entries in the sledcache whose QIDs are in the given set to the
+/// empty `compacted` sledcache, then makes sure the copy has exactly the
+/// entries that were meant to be kept, by comparing a checksum of them with one
+/// of the copy as read back from disk.
+///
+/// Entries are copied in batches, each of which sled applies atomically. The
+/// original sledcache is never modified, so if anything goes wrong, it can
+/// just be kept.
+fn compact_sledcache(
+    sledcache: &sled::Db,
+    compacted: &sled::Db,
+    qids_to_keep: &HashSet<u64>,
+) -> Result<SledcacheCompactionStats> {
+    let mut removed = 0;
+    let mut checksummer = SledcacheChecksummer::new();
+    let mut batch = sled::Batch::default();
+    let mut batch_len = 0;
+    for result in sledcache.iter() {
+        let (key, value) = result?;
+        // We don't know what keys that aren't QIDs are, so leave them alone.
+        let keep = qid_from_key(&key).is_none_or(|qid| qids_to_keep.contains(&qid));
+        if !keep {
+            removed += 1;
+            continue;
+        }
+        checksummer.add(&key, &value);
+        batch.insert(key, value);
+        batch_len += 1;
+        if batch_len >= COMPACTION_BATCH_SIZE {
+            compacted.apply_batch(std::mem::take(&mut batch))?;
+            batch_len = 0;
+        }
+    }
+    if batch_len > 0 {
+        compacted.apply_batch(batch)?;
+    }
+    compacted.flush()?;
+    let expected = checksummer.finish();
+    verify_compacted_sledcache(compacted, &expected)?;
+    Ok(SledcacheCompactionStats {
+        retained: expected.entries,
+        removed,
+    })
+}
+
+fn verify_compacted_sledcache(compacted: &sled::Db, expected: &SledcacheChecksum) -> Result<()> {
+    let actual = checksum_sledcache(compacted)?;
+    if &actual != expected {
+        return Err(anyhow!(
+            "Compacted sledcache doesn't match the entries that were kept (expected {} entries with checksum {:016x}, got {} with {:016x})",
+            expected.entries,
+            expected.hash,
+            actual.entries,
+            actual.hash
+        ));
+    }
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Compacts the sledcache at the given path into a new one alongside it, and
+/// only replaces the original with it once it's been verified, see
+/// `compact_sledcache()`.
+fn compact_sledcache_at_path(
+    sledcache_path: &Path,
+    qids_to_keep: &HashSet<u64>,
+) -> Result<SledcacheCompactionStats> {
+    let compacting_path = with_suffix(sledcache_path, COMPACTING_SUFFIX);
+    let pre_compaction_path = with_suffix(sledcache_path, PRE_COMPACTION_SUFFIX);
+    if !sledcache_path.exists() && pre_compaction_path.exists() {
+        // We crashed while swapping in the compacted sledcache, so put the
+        // original back and start over.
+        std::fs::rename(&pre_compaction_path, sledcache_path)?;
+    }
+    if !sledcache_path.exists() {
+        return Err(anyhow!("{} does not exist", sledcache_path.display()));
+    }
+    for stale_path in [&compacting_path, &pre_compaction_path] {
+        if stale_path.exists() {
+            std::fs::remove_dir_all(stale_path)?;
+        }
+    }
+    let result = {
+        let sledcache = sled::open(sledcache_path)?;
+        let compacted = sled::open(&compacting_path)?;
+        compact_sledcache(&sledcache, &compacted, qids_to_keep)
+    };
+    let stats = match result {
+        Ok(stats) => stats,
+        Err(err) => {
+            std::fs::remove_dir_all(&compacting_path)?;
+            return Err(err);
+        }
+    };
+    std::fs::rename(sledcache_path, &pre_compaction_path)?;
+    std::fs::rename(&compacting_path, sledcache_path)?;
+    std::fs::remove_dir_all(&pre_compaction_path)?;
+    Ok(stats)
+}
+
+pub fn show_wikidata_cache_stats(dumpfile_path: PathBuf) -> Result<()> {
+    let sledcache_path = sledcache_path_for_dumpfile(&dumpfile_path);
+    if !sledcache_path.exists() {
+        return Err(anyhow!("{} does not exist", sledcache_path.display()));
+    }
+    let sledcache = sled::open(&sledcache_path)?;
+    let stats = get_sledcache_stats(&sledcache)?;
+    println!("Sledcache: {}", sledcache_path.display());
+    println!("Entries: {}", stats.entries);
+    println!("Cached JSON size: {} bytes", stats.value_bytes);
+    println!("Size on disk: {} bytes", sledcache.size_on_disk()?);
+    Ok(())
+}
+
+/// Remove all entries from the sledcache that aren't referenced by the given
+/// prepared query, verifying the result before replacing the original.
+///
+/// By default, the sledcache that's compacted is the one for the query's dumpfile.
+/// If a different dumpfile is given, we'll refuse to compact it unless `keep_all_dumps`
+/// is false, since its cache may contain entries needed by other queries.
+pub fn compact_wikidata_cache(
+    input: PathBuf,
+    dumpfile_path: Option<PathBuf>,
+    keep_all_dumps: bool,
+) -> Result<()> {
+    let query = PreparedQuery::from_path(input)?;
+    let dumpfile_path = dumpfile_path.unwrap_or_else(|| query.dumpfile.clone());
+    if dumpfile_path != query.dumpfile && keep_all_dumps {
+        return Err(anyhow!(
+            "Prepared query references {}, not {}. Refusing to compact.",
+            query.dumpfile.display(),
+            dumpfile_path.display()
+        ));
+    }
+    let sledcache_path = sledcache_path_for_dumpfile(&dumpfile_path);
+    let qids_to_keep: HashSet<u64> = query
+        .qids
+        .iter()
+        .chain(query.dependency_qids.iter())
+        .copied()
+        .collect();
+    println!(
+        "Compacting {}, keeping {} QIDs.",
+        sledcache_path.display(),
+        qids_to_keep.len()
+    );
+    let stats = compact_sledcache_at_path(&sledcache_path, &qids_to_keep)?;
+    println!(
+        "Done, retained {} entries and removed {}.",
+        stats.retained, stats.removed
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, path::PathBuf};
+
+    use super::{
+        checksum_sledcache, compact_sledcache, compact_sledcache_at_path, get_sledcache_stats,
+        verify_compacted_sledcache, with_suffix, SledcacheCompactionStats, COMPACTING_SUFFIX,
+        PRE_COMPACTION_SUFFIX,
+    };
+
+    fn make_temporary_sledcache() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    fn insert_qids(sledcache: &sled::Db, qids: &[u64]) {
+        for qid in qids {
+            sledcache
+                .insert(
+                    qid.to_be_bytes(),
+                    format!("{{\"id\":\"Q{qid}\"}}").as_bytes(),
+                )
+                .unwrap();
+        }
+    }
+
+    fn make_sledcache(qids: &[u64]) -> sled::Db {
+        let sledcache = make_temporary_sledcache();
+        insert_qids(&sledcache, qids);
+        sledcache
+    }
+
+    fn make_sledcache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("cache-admin-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_get_sledcache_stats_works() {
+        let sledcache = make_sledcache(&[1, 2, 30]);
+        let stats = get_sledcache_stats(&sledcache).unwrap();
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.value_bytes, 11 + 11 + 12);
+    }
+
+    #[test]
+    fn test_compact_sledcache_retains_only_referenced_qids() {
+        let sledcache = make_sledcache(&[1, 2, 3, 4, 5]);
+        let compacted = make_temporary_sledcache();
+        let qids_to_keep: HashSet<u64> = [2, 4, 999].into_iter().collect();
+        let stats = compact_sledcache(&sledcache, &compacted, &qids_to_keep).unwrap();
+        assert_eq!(
+            stats,
+            SledcacheCompactionStats {
+                retained: 2,
+                removed: 3
+            }
+        );
+        for qid in [1u64, 3, 5] {
+            assert!(!compacted.contains_key(qid.to_be_bytes()).unwrap());
+        }
+        for qid in [2u64, 4] {
+            assert_eq!(
+                compacted.get(qid.to_be_bytes()).unwrap(),
+                sledcache.get(qid.to_be_bytes()).unwrap()
+            );
+        }
+        // The original is left alone.
+        assert_eq!(sledcache.len(), 5);
+    }
+
+    #[test]
+    fn test_compact_sledcache_ignores_unknown_keys() {
+        let sledcache = make_sledcache(&[1]);
+        sledcache.insert(b"boop", &b"hi"[..]).unwrap();
+        let compacted = make_temporary_sledcache();
+        let stats = compact_sledcache(&sledcache, &compacted, &HashSet::new()).unwrap();
+        assert_eq!(
+            stats,
+            SledcacheCompactionStats {
+                retained: 1,
+                removed: 1
+            }
+        );
+        assert!(compacted.contains_key(b"boop").unwrap());
+    }
+
+    #[test]
+    fn test_verify_compacted_sledcache_notices_differences() {
+        let expected = checksum_sledcache(&make_sledcache(&[1, 2])).unwrap();
+        assert!(verify_compacted_sledcache(&make_sledcache(&[1, 2]), &expected).is_ok());
+        assert!(verify_compacted_sledcache(&make_sledcache(&[1]), &expected).is_err());
+        assert!(verify_compacted_sledcache(&make_sledcache(&[1, 3]), &expected).is_err());
+
+        let changed = make_sledcache(&[1, 2]);
+        changed.insert(2u64.to_be_bytes(), &b"{}"[..]).unwrap();
+        assert!(verify_compacted_sledcache(&changed, &expected).is_err());
+    }
+
+    #[test]
+    fn test_compact_sledcache_at_path_replaces_original() {
+        let dir = make_sledcache_dir("replace");
+        let sledcache_path = dir.join("boop.sledcache");
+        insert_qids(&sled::open(&sledcache_path).unwrap(), &[1, 2, 3]);
+        // Leftovers from an earlier compaction that crashed.
+        std::fs::create_dir_all(with_suffix(&sledcache_path, COMPACTING_SUFFIX)).unwrap();
+
+        let qids_to_keep: HashSet<u64> = [2].into_iter().collect();
+        let stats = compact_sledcache_at_path(&sledcache_path, &qids_to_keep).unwrap();
+        assert_eq!(
+            stats,
+            SledcacheCompactionStats {
+                retained: 1,
+                removed: 2
+            }
+        );
+        let sledcache = sled::open(&sledcache_path).unwrap();
+        assert_eq!(sledcache.len(), 1);
+        assert!(sledcache.contains_key(2u64.to_be_bytes()).unwrap());
+        drop(sledcache);
+        let entries: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["boop.sledcache"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compact_sledcache_at_path_recovers_from_interrupted_swap() {
+        let dir = make_sledcache_dir("recover");
+        let sledcache_path = dir.join("boop.sledcache");
+        let pre_compaction_path = with_suffix(&sledcache_path, PRE_COMPACTION_SUFFIX);
+        insert_qids(&sled::open(&pre_compaction_path).unwrap(), &[1, 2]);
+
+        let qids_to_keep: HashSet<u64> = [1].into_iter().collect();
+        compact_sledcache_at_path(&sledcache_path, &qids_to_keep).unwrap();
+        let sledcache = sled::open(&sledcache_path).unwrap();
+        assert_eq!(sledcache.len(), 1);
+        assert!(sledcache.contains_key(1u64.to_be_bytes()).unwrap());
+        drop(sledcache);
+        assert!(!pre_compaction_path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+};
+
+pub fn file_table_path_for_dumpfile(dumpfile_path: &PathBuf) -> PathBuf {
+    dumpfile_path.with_extension("vecfiles.json")
+}
+
+/// Keeps track of the incremental dump files that entries in a dumpfile's
+/// index can point into.
+///
+/// File ID 0 is always the dumpfile itself, so it isn't stored here. This
+/// also means that dumpfiles that have never been updated don't need a file
+/// table at all.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct FileTable {
+    /// Paths to incremental dump files. The file ID of each is its position
+    /// in this list plus one.
+    pub incrementals: Vec<PathBuf>,
+}
+
+impl FileTable {
+    pub fn load(dumpfile_path: &PathBuf) -> Result<Self> {
+        let path = file_table_path_for_dumpfile(dumpfile_path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    pub fn save(&self, dumpfile_path: &PathBuf) -> Result<()> {
+        let path = file_table_path_for_dumpfile(dumpfile_path);
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    /// Returns the file ID of the given incremental dump file, adding it to
+    /// the table if it isn't already there.
+    pub fn add(&mut self, incremental_path: PathBuf) -> Result<u16> {
+        let incremental_path = std::fs::canonicalize(incremental_path)?;
+        let index = match self
+            .incrementals
+            .iter()
+            .position(|path| path == &incremental_path)
+        {
+            Some(index) => index,
+            None => {
+                if self.incrementals.len() >= u16::MAX as usize {
+                    return Err(anyhow!("Too many incremental dump files"));
+                }
+                self.incrementals.push(incremental_path);
+                self.incrementals.len() - 1
+            }
+        };
+        Ok((index + 1) as u16)
+    }
+
+    /// Returns the paths of all files in the table, indexed by file ID.
+    pub fn paths(&self, dumpfile_path: &PathBuf) -> Vec<PathBuf> {
+        std::iter::once(dumpfile_path.clone())
+            .chain(self.incrementals.iter().cloned())
+            .collect()
+    }
+}
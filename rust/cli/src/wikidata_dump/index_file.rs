@@ -1,45 +1,144 @@
 use anyhow::{anyhow, Result};
 use byteorder::LittleEndian;
 use flate2::bufread::GzDecoder;
-use nom::{bytes::complete::tag, character::complete::digit1, sequence::preceded, IResult};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::{
     collections::HashMap,
+    fmt::Display,
     fs::{File, OpenOptions},
     io::{prelude::*, BufReader, BufWriter},
+    ops::AddAssign,
     path::PathBuf,
     sync::mpsc::{self, Receiver, RecvError},
 };
 use zerocopy::{byteorder::U64, AsBytes, FromBytes, FromZeroes, Unaligned};
 
-use crate::wikidata_dump::BUFREADER_CAPACITY;
+use crate::wikidata_dump::{
+    file_table::FileTable, sledcache::evict_from_sledcache, BUFREADER_CAPACITY,
+};
 
 /// Q-identifiers are *mostly* contiguous, this capacity will accommodate
 /// the entire wikidata dump as of 2024-07-14.
 const INDEX_FILE_CAPACITY: u64 = 300_000_000;
 
+/// Version 1 index files had no header, and every entry pointed into the
+/// dumpfile itself. Version 2 entries can also point into incremental dumps.
+const INDEX_FORMAT_VERSION: u64 = 2;
+
+const INDEX_MAGIC: [u8; 8] = *b"WDVECIDX";
+
+/// The file ID lives in the top bits of each entry's gzip member offset,
+/// which still leaves room for dump files of up to 256 terabytes.
+const FILE_ID_SHIFT: u32 = 48;
+
+const GZIP_MEMBER_OFFSET_MASK: u64 = (1 << FILE_ID_SHIFT) - 1;
+
+/// When looking up lots of QIDs in the index, QIDs whose entries are at most
+/// this many entries apart are read with a single read, since reading the
+/// entries in between is much cheaper than seeking, especially on spinning disks.
+const MAX_INDEX_READ_GAP: u64 = 256;
+
+/// The most entries that are read with a single read, to keep memory use bounded.
+const MAX_INDEX_READ_ENTRIES: u64 = 65_536;
+
+/// Only this many bytes at the start of each line of a dump are scanned for
+/// the entity's type and ID, since they're always near the start of it.
+const QUICK_PARSE_PREFIX_LEN: usize = 200;
+
 #[derive(FromBytes, AsBytes, Unaligned, FromZeroes, Default, Debug)]
 #[repr(C)]
 pub struct IndexValue {
-    /// Offset into the wikidata dump file of the gzip member containing a
-    /// particular entity.
-    pub gzip_member_offset: U64<LittleEndian>,
+    /// The ID of the file containing a particular entity (see `FileTable`)
+    /// in the top 16 bits, and the offset into that file of the gzip member
+    /// containing the entity in the rest.
+    pub file_and_gzip_member_offset: U64<LittleEndian>,
     /// Offset into the gzip member of the entity.
     pub offset_into_gzip_member: U64<LittleEndian>,
 }
 
+impl IndexValue {
+    pub fn new(file_id: u16, gzip_member_offset: u64, offset_into_gzip_member: u64) -> Self {
+        assert!(
+            gzip_member_offset <= GZIP_MEMBER_OFFSET_MASK,
+            "gzip member offset {gzip_member_offset} is too large"
+        );
+        Self {
+            file_and_gzip_member_offset: U64::new(
+                ((file_id as u64) << FILE_ID_SHIFT) | gzip_member_offset,
+            ),
+            offset_into_gzip_member: U64::new(offset_into_gzip_member),
+        }
+    }
+
+    pub fn file_id(&self) -> u16 {
+        (self.file_and_gzip_member_offset.get() >> FILE_ID_SHIFT) as u16
+    }
+
+    pub fn gzip_member_offset(&self) -> u64 {
+        self.file_and_gzip_member_offset.get() & GZIP_MEMBER_OFFSET_MASK
+    }
+}
+
+/// Wikidata has no Q0, so the record for it is where we store this header.
+#[derive(FromBytes, AsBytes, Unaligned, FromZeroes, Debug)]
+#[repr(C)]
+struct IndexHeader {
+    magic: [u8; 8],
+    version: U64<LittleEndian>,
+}
+
+const _: () = assert!(std::mem::size_of::<IndexHeader>() == std::mem::size_of::<IndexValue>());
+
+impl IndexHeader {
+    fn current() -> Self {
+        Self {
+            magic: INDEX_MAGIC,
+            version: U64::new(INDEX_FORMAT_VERSION),
+        }
+    }
+}
+
+/// Returns the format version of the given index file, or an error if it
+/// isn't an index file we know how to read.
+fn read_index_format_version(file: &mut File) -> Result<u64> {
+    let mut buf: Vec<u8> = vec![0; std::mem::size_of::<IndexHeader>()];
+    file.seek(std::io::SeekFrom::Start(0))?;
+    let bytes_read = file.read(&mut buf)?;
+    file.seek(std::io::SeekFrom::Start(0))?;
+    if bytes_read < buf.len() || buf.iter().all(|&byte| byte == 0) {
+        return Ok(1);
+    }
+    let Some(header) = IndexHeader::read_from(&buf) else {
+        return Err(anyhow!("Unable to read index file header"));
+    };
+    if header.magic != INDEX_MAGIC {
+        return Err(anyhow!("File does not appear to be an index file"));
+    }
+    let version = header.version.get();
+    if version > INDEX_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Index file format version is {version}, but only versions up to {INDEX_FORMAT_VERSION} are supported"
+        ));
+    }
+    Ok(version)
+}
+
 pub struct IndexFileReader {
     reader: BufReader<File>,
 }
 
 impl IndexFileReader {
     pub fn new(path: PathBuf) -> Result<Self> {
-        let file = File::open(path)?;
+        let mut file = File::open(path)?;
+        read_index_format_version(&mut file)?;
         let reader = BufReader::new(file);
         Ok(Self { reader })
     }
 
     pub fn read(&mut self, qid: u64) -> Result<Option<IndexValue>> {
+        if qid == 0 {
+            return Ok(None);
+        }
         let value_size = std::mem::size_of::<IndexValue>();
         let file_pos = qid * value_size as u64;
         self.reader.seek(std::io::SeekFrom::Start(file_pos))?;
@@ -48,17 +147,49 @@ impl IndexFileReader {
         if bytes_read != value_size {
             return Ok(None);
         }
+        // Records we never wrote are all zeros, either because they're in a hole
+        // of a sparse file or because they were explicitly zero-filled.
+        if buf.iter().all(|&byte| byte == 0) {
+            return Ok(None);
+        }
         Ok(IndexValue::read_from(&buf))
     }
+
+    /// Like `read()`, but reads the values of `count` consecutive QIDs starting
+    /// with `first_qid` using a single read.
+    pub fn read_range(&mut self, first_qid: u64, count: u64) -> Result<Vec<Option<IndexValue>>> {
+        let value_size = std::mem::size_of::<IndexValue>();
+        self.reader
+            .seek(std::io::SeekFrom::Start(first_qid * value_size as u64))?;
+        let mut buf: Vec<u8> = Vec::with_capacity(count as usize * value_size);
+        // This stops early if the range goes past the end of the index.
+        (&mut self.reader)
+            .take(count * value_size as u64)
+            .read_to_end(&mut buf)?;
+        let mut values: Vec<Option<IndexValue>> = buf
+            .chunks_exact(value_size)
+            .enumerate()
+            .map(|(i, bytes)| {
+                if first_qid + i as u64 == 0 || bytes.iter().all(|&byte| byte == 0) {
+                    None
+                } else {
+                    IndexValue::read_from(bytes)
+                }
+            })
+            .collect();
+        values.resize_with(count as usize, || None);
+        Ok(values)
+    }
 }
 
 /// This encapsulates how the index file maps entity Q-identifiers to gzip members
 /// and their positions within them.
+#[derive(Debug, PartialEq)]
 pub struct QidIndexFileMapping {
-    /// Mapping from gzip members, identified by their byte offset, to details about the location of individual
-    /// entities within each gzip member. This makes it easy for us to decompress each gzip member only once
-    /// to retrieve all the data we need from it.
-    qids_by_gzip_members: HashMap<u64, Vec<QidGzipMemberInfo>>,
+    /// Mapping from gzip members, identified by their file ID and byte offset, to details about the location of
+    /// individual entities within each gzip member. This makes it easy for us to decompress each gzip member only
+    /// once to retrieve all the data we need from it.
+    qids_by_gzip_members: HashMap<(u16, u64), Vec<QidGzipMemberInfo>>,
     total_qids: usize,
 }
 
@@ -72,6 +203,7 @@ impl QidIndexFileMapping {
     }
 }
 
+#[derive(Debug, PartialEq)]
 struct QidGzipMemberInfo {
     qid: u64,
     offset_into_gzip_member: u64,
@@ -81,21 +213,28 @@ struct QidGzipMemberInfo {
 /// the dumpfile by looking up the QIDs in the index.
 ///
 /// QIDs not present in the index will not be included in the metadata.
+///
+/// The index is read in order of increasing QID, with nearby QIDs read together,
+/// but the QIDs of each gzip member are in the same order as in `qids`.
 pub fn get_qid_index_file_mapping(
     reader: &mut IndexFileReader,
     qids: Vec<u64>,
     warnings: bool,
 ) -> Result<QidIndexFileMapping> {
-    let mut qids_by_gzip_members = HashMap::<u64, Vec<QidGzipMemberInfo>>::new();
+    let values = read_index_values(reader, &qids)?;
+    let missing_value = IndexValue::default();
+    let mut qids_by_gzip_members = HashMap::<(u16, u64), Vec<QidGzipMemberInfo>>::new();
     let mut total_qids = 0;
     for qid in qids {
-        let value = reader.read(qid)?.unwrap_or_default();
-        let gzip_member = value.gzip_member_offset.get();
-        // Note that the very first gzip member is just an opening square bracket, i.e. no QID data,
-        // so a value of 0 can _only_ mean we never populated the value when indexing.
-        if gzip_member != 0 {
+        let value = values.get(&qid).unwrap_or(&missing_value);
+        // Note that the very first gzip member of the dumpfile is just an opening square bracket, i.e. no QID data,
+        // so a value of 0 can _only_ mean we never populated the value when indexing. Incremental dumps have
+        // non-zero file IDs, so this holds even if their very first gzip member contains QID data.
+        if value.file_and_gzip_member_offset.get() != 0 {
             total_qids += 1;
-            let entry = qids_by_gzip_members.entry(gzip_member).or_default();
+            let entry = qids_by_gzip_members
+                .entry((value.file_id(), value.gzip_member_offset()))
+                .or_default();
             let offset_into_gzip_member = value.offset_into_gzip_member.get();
             entry.push(QidGzipMemberInfo {
                 qid,
@@ -112,6 +251,39 @@ pub fn get_qid_index_file_mapping(
     })
 }
 
+/// Reads the index values of the given QIDs in order of increasing QID, with
+/// a single read for each run of QIDs that are close together, returning the
+/// values that exist.
+fn read_index_values(
+    reader: &mut IndexFileReader,
+    qids: &[u64],
+) -> Result<HashMap<u64, IndexValue>> {
+    let mut sorted_qids = qids.to_vec();
+    sorted_qids.sort_unstable();
+    sorted_qids.dedup();
+    let mut values = HashMap::with_capacity(sorted_qids.len());
+    let mut start = 0;
+    while start < sorted_qids.len() {
+        let first_qid = sorted_qids[start];
+        let mut end = start + 1;
+        while end < sorted_qids.len()
+            && sorted_qids[end] - sorted_qids[end - 1] <= MAX_INDEX_READ_GAP
+            && sorted_qids[end] - first_qid < MAX_INDEX_READ_ENTRIES
+        {
+            end += 1;
+        }
+        let last_qid = sorted_qids[end - 1];
+        let mut range = reader.read_range(first_qid, last_qid - first_qid + 1)?;
+        for &qid in &sorted_qids[start..end] {
+            if let Some(value) = range[(qid - first_qid) as usize].take() {
+                values.insert(qid, value);
+            }
+        }
+        start = end;
+    }
+    Ok(values)
+}
+
 /// This struct encapsulates writing an index mapping wikidata Q-identifiers
 /// to their locations in a compressed wikidata dump file.
 ///
@@ -131,28 +303,80 @@ pub fn get_qid_index_file_mapping(
 /// index.
 pub struct IndexFileWriter {
     writer: BufWriter<File>,
+    capacity: u64,
+}
+
+pub struct IndexFileOptions {
+    /// The number of records the index file has room for.
+    pub capacity: u64,
+    /// Whether to expand the index file by creating a sparse file, rather than
+    /// explicitly writing zeros. The latter is much slower and takes up a lot
+    /// more disk space, but may be needed on filesystems that don't support
+    /// sparse files well.
+    pub sparse: bool,
+}
+
+impl Default for IndexFileOptions {
+    fn default() -> Self {
+        Self {
+            capacity: INDEX_FILE_CAPACITY,
+            sparse: true,
+        }
+    }
 }
 
 impl IndexFileWriter {
-    pub fn new(path: PathBuf) -> Result<Self> {
-        let file = OpenOptions::new().write(true).create(true).open(path)?;
+    pub fn new(path: PathBuf, options: IndexFileOptions) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        read_index_format_version(&mut file)?;
         let file_size = file.metadata().unwrap().len();
         let default_value = IndexValue::default();
         let value_size = default_value.as_bytes().len() as u64;
-        let capacity_in_bytes = INDEX_FILE_CAPACITY * value_size;
+        let capacity_in_bytes = options.capacity * value_size;
         let mut writer = BufWriter::new(file);
         if file_size < capacity_in_bytes {
-            writer.seek(std::io::SeekFrom::End(0))?;
-            let records_to_write = INDEX_FILE_CAPACITY - (file_size / value_size);
+            let records_to_write = options.capacity - (file_size / value_size);
             println!(
                 "Expanding index file by {} records (record size is {value_size} bytes).",
                 records_to_write
             );
-            for _ in 0..records_to_write {
-                writer.write(&default_value.as_bytes())?;
+            if options.sparse {
+                writer.get_ref().set_len(capacity_in_bytes)?;
+            } else {
+                writer.seek(std::io::SeekFrom::End(0))?;
+                for _ in 0..records_to_write {
+                    writer.write(&default_value.as_bytes())?;
+                }
             }
         }
-        Ok(Self { writer })
+        // Older index files are a subset of the current format, so upgrading
+        // them is just a matter of writing the header.
+        writer.seek(std::io::SeekFrom::Start(0))?;
+        writer.write_all(IndexHeader::current().as_bytes())?;
+        Ok(Self {
+            writer,
+            capacity: options.capacity,
+        })
+    }
+
+    /// Open an existing index file for updating, without expanding it.
+    pub fn open_existing(path: PathBuf) -> Result<Self> {
+        if !path.exists() {
+            return Err(anyhow!("{} does not exist", path.display()));
+        }
+        let value_size = std::mem::size_of::<IndexValue>() as u64;
+        let capacity = std::fs::metadata(&path)?.len() / value_size;
+        Self::new(
+            path,
+            IndexFileOptions {
+                capacity,
+                sparse: true,
+            },
+        )
     }
 
     pub fn flush(&mut self) -> Result<()> {
@@ -161,8 +385,14 @@ impl IndexFileWriter {
     }
 
     pub fn write(&mut self, qid: u64, value: IndexValue) -> Result<()> {
-        if qid > INDEX_FILE_CAPACITY {
-            println!("Warning: INDEX_FILE_CAPACITY={INDEX_FILE_CAPACITY} but qid={qid}.")
+        if qid == 0 {
+            return Err(anyhow!("Q0 is reserved for the index file header"));
+        }
+        if qid >= self.capacity {
+            println!(
+                "Warning: index file capacity is {} but qid={qid}.",
+                self.capacity
+            )
         }
         let bytes = value.as_bytes();
         let curr_pos = self.writer.stream_position().unwrap();
@@ -206,9 +436,8 @@ fn iter_serialized_qids_in_gzipped_member(
             let found_qid = offset_into_gzip_member == entry.offset_into_gzip_member;
             offset_into_gzip_member += bytes_read as u64;
             if found_qid {
-                if string.ends_with(",\n") {
-                    string.truncate(string.len() - 2);
-                }
+                // The last entity in a dump isn't followed by a comma.
+                string.truncate(string.trim_end_matches(&[',', '\n'][..]).len());
                 if !string.ends_with("}") {
                     return Err(anyhow!("Q{} does not appear to be valid JSON", entry.qid));
                 }
@@ -242,14 +471,15 @@ impl Iterator for SerializedQidIterator {
     }
 }
 
-/// Given a dumpfile and metadata about the locations of entities within it,
-/// returns an iterator that yields the entity Q-identifiers along with their
-/// JSON-serialized values.
+/// Given the paths of a dumpfile and its incremental dumps, indexed by file ID
+/// (see `FileTable::paths`), and metadata about the locations of entities
+/// within them, returns an iterator that yields the entity Q-identifiers along
+/// with their JSON-serialized values.
 ///
 /// Note that the result of the iterator shouldn't be assumed to be in any
 /// deterministic order.
 pub fn par_iter_serialized_qids(
-    dumpfile_path: PathBuf,
+    file_paths: Vec<PathBuf>,
     qid_index_file_mapping: QidIndexFileMapping,
 ) -> SerializedQidIterator {
     let (tx, rx) = mpsc::sync_channel::<GzipMemberMessage>(10_000);
@@ -258,12 +488,19 @@ pub fn par_iter_serialized_qids(
         qid_index_file_mapping
             .qids_by_gzip_members
             .into_par_iter()
-            .for_each(|(gzip_member_offset, entries)| {
+            .for_each(|((file_id, gzip_member_offset), entries)| {
+                let Some(dumpfile_path) = file_paths.get(file_id as usize) else {
+                    let _ignore_hangup = tx.send(GzipMemberMessage::FatalError(format!(
+                        "file ID {file_id} is not in the file table"
+                    )));
+                    return;
+                };
                 let Ok((gz_dumpfile_reader, _)) =
                     open_dumpfile_and_seek_from(dumpfile_path.clone(), Some(gzip_member_offset))
                 else {
                     let _ignore_hangup = tx.send(GzipMemberMessage::FatalError(format!(
-                        "opening dumpfile and seeking to {gzip_member_offset} failed"
+                        "opening {} and seeking to {gzip_member_offset} failed",
+                        dumpfile_path.display()
                     )));
                     return;
                 };
@@ -279,6 +516,29 @@ pub fn par_iter_serialized_qids(
     SerializedQidIterator { rx }
 }
 
+/// Given the paths of a dumpfile and its incremental dumps, indexed by file ID
+/// (see `FileTable::paths`), and the index value of an entity, returns the
+/// entity's JSON-serialized value.
+///
+/// Unlike `par_iter_serialized_qids`, this doesn't spin up any threads, so it's
+/// better suited to looking up individual entities.
+pub fn read_serialized_qid(file_paths: &[PathBuf], qid: u64, value: &IndexValue) -> Result<String> {
+    let file_id = value.file_id();
+    let Some(dumpfile_path) = file_paths.get(file_id as usize) else {
+        return Err(anyhow!("file ID {file_id} is not in the file table"));
+    };
+    let (gz_dumpfile_reader, _) =
+        open_dumpfile_and_seek_from(dumpfile_path.clone(), Some(value.gzip_member_offset()))?;
+    let entries = vec![QidGzipMemberInfo {
+        qid,
+        offset_into_gzip_member: value.offset_into_gzip_member.get(),
+    }];
+    match iter_serialized_qids_in_gzipped_member(gz_dumpfile_reader, entries).next() {
+        Some(result) => Ok(result?.1),
+        None => Err(anyhow!("Q{qid} was not found in its gzip member")),
+    }
+}
+
 fn open_dumpfile_and_seek_from(
     dumpfile_path: PathBuf,
     seek_from: Option<u64>,
@@ -295,20 +555,113 @@ fn open_dumpfile_and_seek_from(
 
 /// Given a dumpfile, creates an index that maps entity Q-identifiers to
 /// their locations in the dumpfile.
-pub fn index_wikidata_dump(dumpfile_path: PathBuf, seek_from: Option<u64>) -> Result<()> {
+pub fn index_wikidata_dump(
+    dumpfile_path: PathBuf,
+    seek_from: Option<u64>,
+    sparse: bool,
+) -> Result<()> {
+    index_wikidata_dump_with_options(
+        dumpfile_path,
+        seek_from,
+        IndexFileOptions {
+            sparse,
+            ..Default::default()
+        },
+    )
+}
+
+pub(super) fn index_wikidata_dump_with_options(
+    dumpfile_path: PathBuf,
+    seek_from: Option<u64>,
+    options: IndexFileOptions,
+) -> Result<()> {
     let index_path = index_path_for_dumpfile(&dumpfile_path);
     println!("Writing index to {}.", index_path.display());
     println!("Parsing QIDs from {}...", dumpfile_path.display());
     let now = std::time::SystemTime::now();
-    let mut index_db = IndexFileWriter::new(index_path)?;
+    let mut index_db = IndexFileWriter::new(index_path, options)?;
     println!(
         "Opened index db in {} ms.",
         now.elapsed().unwrap().as_millis()
     );
+    let counts = index_gzip_members(dumpfile_path, seek_from, 0, &mut index_db, |_| {})?;
+    println!("Done, {counts}.");
+    Ok(())
+}
+
+/// Given a dumpfile that has already been indexed, updates its index so that
+/// the entities in the given incremental dumps are read from them instead of
+/// the dumpfile.
+///
+/// Incremental dumps are applied in order, so if an entity is in more than
+/// one of them, the last one wins.
+pub fn update_wikidata_index(dumpfile_path: PathBuf, incrementals: Vec<PathBuf>) -> Result<()> {
+    let index_path = index_path_for_dumpfile(&dumpfile_path);
+    println!("Updating index at {}.", index_path.display());
+    let mut index_db = IndexFileWriter::open_existing(index_path)?;
+    let mut file_table = FileTable::load(&dumpfile_path)?;
+    let mut updated_qids: Vec<u64> = vec![];
+    for incremental_path in incrementals {
+        let file_id = file_table.add(incremental_path.clone())?;
+        // Save the file table first, so the index never refers to a file ID
+        // that isn't in it.
+        file_table.save(&dumpfile_path)?;
+        println!(
+            "Parsing QIDs from {} (file ID {file_id})...",
+            incremental_path.display()
+        );
+        let counts = index_gzip_members(incremental_path, None, file_id, &mut index_db, |qid| {
+            updated_qids.push(qid)
+        })?;
+        println!("Updated {} QIDs ({counts}).", counts.items);
+    }
+    // Otherwise, we'd keep returning the old versions of any cached entities.
+    let evicted = evict_from_sledcache(&dumpfile_path, &updated_qids)?;
+    println!("Done, evicted {evicted} outdated entities from cache.");
+    Ok(())
+}
+
+/// How many lines of a dump were indexed or skipped.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+struct IndexCounts {
+    items: usize,
+    /// Entities that aren't items, e.g. properties or lexemes.
+    non_items: usize,
+    /// Lines that looked like entities, but whose item ID couldn't be found.
+    unparseable: usize,
+}
+
+impl AddAssign for IndexCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.items += other.items;
+        self.non_items += other.non_items;
+        self.unparseable += other.unparseable;
+    }
+}
+
+impl Display for IndexCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} items indexed, {} non-items skipped, {} unparseable lines",
+            self.items, self.non_items, self.unparseable
+        )
+    }
+}
+
+/// Index all the QIDs in the given dump file, which has the given file ID,
+/// calling `on_qid` for each one.
+fn index_gzip_members(
+    dumpfile_path: PathBuf,
+    seek_from: Option<u64>,
+    file_id: u16,
+    index_db: &mut IndexFileWriter,
+    mut on_qid: impl FnMut(u64),
+) -> Result<IndexCounts> {
     let (mut gz, total_len) = open_dumpfile_and_seek_from(dumpfile_path, seek_from)?;
     let mut buf: Vec<u8> = vec![];
     let mut gzip_member_offset: u64 = seek_from.unwrap_or(0);
-    let mut total = 0;
+    let mut total = IndexCounts::default();
     loop {
         buf.clear();
         let now = std::time::SystemTime::now();
@@ -317,7 +670,9 @@ pub fn index_wikidata_dump(dumpfile_path: PathBuf, seek_from: Option<u64>) -> Re
         if bytes_read == 0 {
             break;
         }
-        if buf[0] == b'{' && buf[buf.len() - 1] == b'}' {
+        // The dumpfile's first and last gzip members are just square brackets, but incremental
+        // dumps may consist of a single gzip member with the brackets and all the entities in it.
+        if buf.contains(&b'{') {
             // Unfortunately, the GZip header doesn't seem to have an 'extra' block defined on it,
             // which means there's definitely no metadata that will tell us the size of the block
             // beforehand. If there was, we could have done all this decompression in parallel.
@@ -326,12 +681,15 @@ pub fn index_wikidata_dump(dumpfile_path: PathBuf, seek_from: Option<u64>) -> Re
                 elapsed.as_millis()
             );
             let now = std::time::SystemTime::now();
-            let new_qids = parse_and_upsert_qids(&buf, &mut index_db, gzip_member_offset)?;
+            let counts =
+                parse_and_upsert_qids(&buf, index_db, file_id, gzip_member_offset, &mut on_qid)?;
             let elapsed = now.elapsed().unwrap();
-            total += new_qids;
+            total += counts;
             println!(
-                "{:.2}% done, {new_qids} QIDs parsed from gzip member ({total} total) in {} ms.",
+                "{:.2}% done, {} QIDs parsed from gzip member ({} total) in {} ms.",
                 (gzip_member_offset as f64) / (total_len as f64) * 100.0,
+                counts.items,
+                total.items,
                 elapsed.as_millis()
             );
         }
@@ -342,50 +700,535 @@ pub fn index_wikidata_dump(dumpfile_path: PathBuf, seek_from: Option<u64>) -> Re
         }
         gz = GzDecoder::new(underlying_reader);
     }
-    println!("Done, parsed {total} QIDs.");
-    Ok(())
+    Ok(total)
 }
 
 fn parse_and_upsert_qids(
     buf: &Vec<u8>,
     index_db: &mut IndexFileWriter,
+    file_id: u16,
     gzip_member_offset: u64,
-) -> Result<usize> {
-    let gzip_member_offset = U64::new(gzip_member_offset);
+    on_qid: &mut impl FnMut(u64),
+) -> Result<IndexCounts> {
     let mut buf_reader = BufReader::new(buf.as_slice());
-    let mut total = 0;
-    let mut contents = String::new();
+    let mut counts = IndexCounts::default();
+    let mut contents: Vec<u8> = vec![];
     let mut offset_into_gzip_member: u64 = 0;
     loop {
         contents.clear();
         let bytes_read = buf_reader
-            .read_line(&mut contents)
+            .read_until(b'\n', &mut contents)
             .expect("error reading line from buffer");
         if bytes_read == 0 {
             break;
         }
-        let value = IndexValue {
-            gzip_member_offset,
-            offset_into_gzip_member: U64::new(offset_into_gzip_member),
-        };
+        let value = IndexValue::new(file_id, gzip_member_offset, offset_into_gzip_member);
         offset_into_gzip_member += bytes_read as u64;
-        let Some((_remaining, qid_str)) = quick_parse_item_id(contents.as_str()).ok() else {
-            continue;
-        };
-        if qid_str.len() == 0 {
-            continue;
+        match quick_parse_item_id(&contents) {
+            QuickParsedLine::Item(qid) => {
+                index_db.write(qid, value)?;
+                on_qid(qid);
+                counts.items += 1;
+            }
+            QuickParsedLine::NonItem => counts.non_items += 1,
+            QuickParsedLine::Unparseable => counts.unparseable += 1,
+            QuickParsedLine::Separator => {}
         }
-        index_db.write(qid_str.parse().unwrap(), value)?;
-        total += 1;
     }
     index_db.flush()?;
-    Ok(total)
+    Ok(counts)
+}
+
+#[derive(Debug, PartialEq)]
+enum QuickParsedLine {
+    Item(u64),
+    /// An entity that isn't an item, e.g. a property or lexeme.
+    NonItem,
+    /// Something that looks like an entity, but whose type or ID couldn't be found.
+    Unparseable,
+    /// Brackets, commas, or whitespace between entities.
+    Separator,
 }
 
 /// This quickly parses the item ID from a single line of a wikidata dump JSON blob,
-/// without actually parsing any JSON. As can be seen from the implementation, it's
-/// highly dependent on the specific serialization of wikidata, and will break if
-/// it so much as introduces whitespace between JSON tokens or re-orders keys.
-fn quick_parse_item_id(input: &str) -> IResult<&str, &str> {
-    preceded(tag(r#"{"type":"item","id":"Q"#), digit1)(input)
+/// without actually parsing any JSON. It only looks at the start of the line, and
+/// expects there to be no whitespace between JSON tokens, but doesn't care about
+/// the order of the `type` and `id` keys.
+fn quick_parse_item_id(line: &[u8]) -> QuickParsedLine {
+    if !line.starts_with(b"{") {
+        let is_separator = line
+            .iter()
+            .all(|&byte| matches!(byte, b'[' | b']' | b',' | b' ' | b'\t' | b'\r' | b'\n'));
+        return if is_separator {
+            QuickParsedLine::Separator
+        } else {
+            QuickParsedLine::Unparseable
+        };
+    }
+    let prefix = &line[..line.len().min(QUICK_PARSE_PREFIX_LEN)];
+    let Some(entity_type) = find_string_value(prefix, br#""type":""#) else {
+        return QuickParsedLine::Unparseable;
+    };
+    if entity_type != b"item" {
+        return QuickParsedLine::NonItem;
+    }
+    let Some(id) = find_string_value(prefix, br#""id":""#) else {
+        return QuickParsedLine::Unparseable;
+    };
+    let Some((&b'Q', digits)) = id.split_first() else {
+        return QuickParsedLine::Unparseable;
+    };
+    if digits.is_empty() {
+        return QuickParsedLine::Unparseable;
+    }
+    let qid = digits.iter().try_fold(0u64, |qid, &digit| {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        qid.checked_mul(10)?.checked_add((digit - b'0') as u64)
+    });
+    match qid {
+        Some(qid) => QuickParsedLine::Item(qid),
+        None => QuickParsedLine::Unparseable,
+    }
+}
+
+/// Returns the contents of the string that immediately follows the given key
+/// (which should include the opening quote of the string), if it's entirely
+/// within `haystack`.
+fn find_string_value<'a>(haystack: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    let start = haystack
+        .windows(key.len())
+        .position(|window| window == key)?
+        + key.len();
+    let len = haystack[start..].iter().position(|&byte| byte == b'"')?;
+    Some(&haystack[start..start + len])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Write, path::PathBuf};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use crate::wikidata_dump::{file_table::FileTable, sledcache::sledcache_path_for_dumpfile};
+
+    use super::{
+        get_qid_index_file_mapping, index_path_for_dumpfile, index_wikidata_dump_with_options,
+        par_iter_serialized_qids, quick_parse_item_id, update_wikidata_index, IndexFileOptions,
+        IndexFileReader, IndexFileWriter, IndexValue, QidGzipMemberInfo, QidIndexFileMapping,
+        QuickParsedLine, MAX_INDEX_READ_GAP,
+    };
+
+    const TEST_CAPACITY: u64 = 100;
+
+    fn index_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "gallery-index-file-test-{name}-{}.vecindex",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn value(gzip_member_offset: u64, offset_into_gzip_member: u64) -> IndexValue {
+        IndexValue::new(0, gzip_member_offset, offset_into_gzip_member)
+    }
+
+    fn write_index(path: &PathBuf, sparse: bool) {
+        let mut writer = IndexFileWriter::new(
+            path.clone(),
+            IndexFileOptions {
+                capacity: TEST_CAPACITY,
+                sparse,
+            },
+        )
+        .unwrap();
+        writer.write(5, value(100, 0)).unwrap();
+        writer.write(42, value(200, 37)).unwrap();
+        writer.flush().unwrap();
+    }
+
+    fn assert_lookups_work(path: &PathBuf) {
+        let mut reader = IndexFileReader::new(path.clone()).unwrap();
+        let five = reader.read(5).unwrap().unwrap();
+        assert_eq!(five.gzip_member_offset(), 100);
+        assert_eq!(five.offset_into_gzip_member.get(), 0);
+        let forty_two = reader.read(42).unwrap().unwrap();
+        assert_eq!(forty_two.gzip_member_offset(), 200);
+        assert_eq!(forty_two.offset_into_gzip_member.get(), 37);
+        assert!(reader.read(6).unwrap().is_none());
+        assert!(reader.read(TEST_CAPACITY + 50).unwrap().is_none());
+
+        let mapping = get_qid_index_file_mapping(&mut reader, vec![5, 6, 42], false).unwrap();
+        assert_eq!(mapping.qids(), 2);
+        assert_eq!(mapping.gzip_members(), 2);
+    }
+
+    fn assert_logical_size(path: &PathBuf) {
+        let value_size = std::mem::size_of::<IndexValue>() as u64;
+        let len = std::fs::metadata(path).unwrap().len();
+        assert_eq!(len, TEST_CAPACITY * value_size);
+    }
+
+    #[test]
+    fn test_sparse_index_works() {
+        let path = index_path("sparse");
+        write_index(&path, true);
+        assert_logical_size(&path);
+        assert_lookups_work(&path);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_zero_filled_index_works() {
+        let path = index_path("zero-filled");
+        write_index(&path, false);
+        assert_logical_size(&path);
+        assert_lookups_work(&path);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reopening_index_at_capacity_preserves_it() {
+        let path = index_path("reopen");
+        write_index(&path, true);
+        for sparse in [true, false] {
+            let mut writer = IndexFileWriter::new(
+                path.clone(),
+                IndexFileOptions {
+                    capacity: TEST_CAPACITY,
+                    sparse,
+                },
+            )
+            .unwrap();
+            writer.flush().unwrap();
+        }
+        assert_logical_size(&path);
+        assert_lookups_work(&path);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_index_value_packs_file_id() {
+        let value = IndexValue::new(3, 100, 7);
+        assert_eq!(value.file_id(), 3);
+        assert_eq!(value.gzip_member_offset(), 100);
+        assert_eq!(value.offset_into_gzip_member.get(), 7);
+    }
+
+    #[test]
+    fn test_index_header_is_validated() {
+        let path = index_path("header");
+        write_index(&path, true);
+        let mut reader = IndexFileReader::new(path.clone()).unwrap();
+        assert!(reader.read(0).unwrap().is_none());
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[8] = 99;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(IndexFileReader::new(path.clone()).is_err());
+
+        bytes[0] = b'X';
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(IndexFileReader::new(path.clone()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// This is how `get_qid_index_file_mapping()` used to work, reading each
+    /// QID's value separately in the order given.
+    fn get_qid_index_file_mapping_one_at_a_time(
+        reader: &mut IndexFileReader,
+        qids: Vec<u64>,
+    ) -> QidIndexFileMapping {
+        let mut qids_by_gzip_members = HashMap::<(u16, u64), Vec<QidGzipMemberInfo>>::new();
+        let mut total_qids = 0;
+        for qid in qids {
+            let Some(value) = reader.read(qid).unwrap() else {
+                continue;
+            };
+            total_qids += 1;
+            qids_by_gzip_members
+                .entry((value.file_id(), value.gzip_member_offset()))
+                .or_default()
+                .push(QidGzipMemberInfo {
+                    qid,
+                    offset_into_gzip_member: value.offset_into_gzip_member.get(),
+                });
+        }
+        QidIndexFileMapping {
+            qids_by_gzip_members,
+            total_qids,
+        }
+    }
+
+    fn write_synthetic_index(path: &PathBuf, capacity: u64, qids: impl Iterator<Item = u64>) {
+        let mut writer = IndexFileWriter::new(
+            path.clone(),
+            IndexFileOptions {
+                capacity,
+                sparse: true,
+            },
+        )
+        .unwrap();
+        for qid in qids {
+            // Put a few dozen QIDs in each gzip member, like a real dump.
+            writer
+                .write(qid, IndexValue::new(0, 1000 + qid / 37, qid % 37))
+                .unwrap();
+        }
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn test_read_range_works() {
+        let path = index_path("read-range");
+        write_index(&path, true);
+        let mut reader = IndexFileReader::new(path.clone()).unwrap();
+        let values = reader.read_range(0, 10).unwrap();
+        assert_eq!(values.len(), 10);
+        for (qid, value) in values.iter().enumerate() {
+            assert_eq!(value.is_some(), qid == 5, "Q{qid}");
+        }
+        assert_eq!(values[5].as_ref().unwrap().gzip_member_offset(), 100);
+
+        // Ranges that go past the end of the index are padded.
+        let values = reader.read_range(40, TEST_CAPACITY).unwrap();
+        assert_eq!(values.len(), TEST_CAPACITY as usize);
+        assert_eq!(
+            values[2].as_ref().unwrap().offset_into_gzip_member.get(),
+            37
+        );
+        assert_eq!(values.iter().filter(|value| value.is_some()).count(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_batched_mapping_matches_one_at_a_time_mapping() {
+        let path = index_path("batched-mapping");
+        let capacity = MAX_INDEX_READ_GAP * 20;
+        write_synthetic_index(&path, capacity, (1..capacity).filter(|qid| qid % 3 != 0));
+        let mut qids: Vec<u64> = vec![
+            // Out of order, with duplicates, QIDs that aren't in the index,
+            // big gaps between runs, and QIDs past the end of the index.
+            capacity - 1,
+            7,
+            2,
+            7,
+            0,
+            3,
+            capacity + 500,
+            MAX_INDEX_READ_GAP * 10 + 1,
+            1,
+            MAX_INDEX_READ_GAP * 3 + 2,
+            5,
+        ];
+        qids.extend(
+            (MAX_INDEX_READ_GAP * 5..MAX_INDEX_READ_GAP * 7)
+                .rev()
+                .step_by(5),
+        );
+        let mut reader = IndexFileReader::new(path.clone()).unwrap();
+        let expected = get_qid_index_file_mapping_one_at_a_time(&mut reader, qids.clone());
+        let actual = get_qid_index_file_mapping(&mut reader, qids, false).unwrap();
+        assert_eq!(actual, expected);
+        assert!(actual.qids() > 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture` to see how fast
+    /// looking up lots of QIDs is with and without batched reads.
+    #[test]
+    #[ignore]
+    fn bench_get_qid_index_file_mapping() {
+        const CAPACITY: u64 = 1_000_000;
+        const NUM_QIDS: u64 = 300_000;
+
+        let path = index_path("bench");
+        write_synthetic_index(&path, CAPACITY, (1..CAPACITY).filter(|qid| qid % 2 == 1));
+        // Scatter the QIDs around the index, in no particular order.
+        let qids: Vec<u64> = (0..NUM_QIDS).map(|i| (i * 7_919) % CAPACITY).collect();
+        let time =
+            |name: &str, get_mapping: &dyn Fn(&mut IndexFileReader) -> QidIndexFileMapping| {
+                let mut reader = IndexFileReader::new(path.clone()).unwrap();
+                let start = std::time::Instant::now();
+                let mapping = get_mapping(&mut reader);
+                let secs = start.elapsed().as_secs_f64();
+                println!(
+                    "{name}: {:.0} QIDs/sec",
+                    NUM_QIDS as f64 / secs.max(f64::EPSILON)
+                );
+                mapping
+            };
+        let old = time("one at a time", &|reader| {
+            get_qid_index_file_mapping_one_at_a_time(reader, qids.clone())
+        });
+        let new = time("batched", &|reader| {
+            get_qid_index_file_mapping(reader, qids.clone(), false).unwrap()
+        });
+        assert_eq!(old, new);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn entity(qid: u64, label: &str) -> String {
+        format!(r#"{{"type":"item","id":"Q{qid}","label":"{label}"}}"#)
+    }
+
+    fn write_gzip_members(path: &PathBuf, members: Vec<String>) {
+        let mut file = std::fs::File::create(path).unwrap();
+        for member in members {
+            let mut encoder = GzEncoder::new(&mut file, Compression::default());
+            encoder.write_all(member.as_bytes()).unwrap();
+            encoder.finish().unwrap();
+        }
+    }
+
+    fn read_serialized_qids(dumpfile_path: &PathBuf, qids: Vec<u64>) -> HashMap<u64, String> {
+        let mut reader = IndexFileReader::new(index_path_for_dumpfile(dumpfile_path)).unwrap();
+        let mapping = get_qid_index_file_mapping(&mut reader, qids, false).unwrap();
+        let file_paths = FileTable::load(dumpfile_path).unwrap().paths(dumpfile_path);
+        par_iter_serialized_qids(file_paths, mapping)
+            .map(|result| result.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_incremental_dumps_win_over_dumpfile() {
+        let dir = std::env::temp_dir().join(format!(
+            "gallery-index-file-test-incremental-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dumpfile_path = dir.join("base.json.gz");
+        let incremental_path = dir.join("incremental.json.gz");
+
+        // This mimics the structure of the full dump, where the first and last
+        // gzip members are just square brackets.
+        write_gzip_members(
+            &dumpfile_path,
+            vec![
+                "[\n".into(),
+                format!("{},\n{}", entity(5, "old"), entity(42, "old")),
+                format!(",\n{}", entity(7, "old")),
+                "\n]\n".into(),
+            ],
+        );
+        write_gzip_members(
+            &incremental_path,
+            vec![format!(
+                "[\n{},\n{}\n]\n",
+                entity(5, "new"),
+                entity(99, "new")
+            )],
+        );
+        index_wikidata_dump_with_options(
+            dumpfile_path.clone(),
+            None,
+            IndexFileOptions {
+                capacity: TEST_CAPACITY,
+                sparse: true,
+            },
+        )
+        .unwrap();
+        let serialized = read_serialized_qids(&dumpfile_path, vec![5, 7, 42, 99]);
+        assert_eq!(serialized.len(), 3);
+        assert_eq!(serialized[&5], entity(5, "old"));
+        assert_eq!(serialized[&7], entity(7, "old"));
+
+        let sledcache = sled::open(sledcache_path_for_dumpfile(&dumpfile_path)).unwrap();
+        sledcache
+            .insert(5u64.to_be_bytes(), entity(5, "old").as_bytes())
+            .unwrap();
+        sledcache
+            .insert(42u64.to_be_bytes(), entity(42, "old").as_bytes())
+            .unwrap();
+        sledcache.flush().unwrap();
+        drop(sledcache);
+
+        // Applying the same incremental dump twice should be harmless.
+        for _ in 0..2 {
+            update_wikidata_index(dumpfile_path.clone(), vec![incremental_path.clone()]).unwrap();
+        }
+        assert_eq!(
+            FileTable::load(&dumpfile_path).unwrap().incrementals,
+            vec![std::fs::canonicalize(&incremental_path).unwrap()]
+        );
+        let serialized = read_serialized_qids(&dumpfile_path, vec![5, 7, 42, 99]);
+        assert_eq!(serialized.len(), 4);
+        assert_eq!(serialized[&5], entity(5, "new"));
+        assert_eq!(serialized[&7], entity(7, "old"));
+        assert_eq!(serialized[&42], entity(42, "old"));
+        assert_eq!(serialized[&99], entity(99, "new"));
+
+        let sledcache = sled::open(sledcache_path_for_dumpfile(&dumpfile_path)).unwrap();
+        assert!(!sledcache.contains_key(5u64.to_be_bytes()).unwrap());
+        assert!(sledcache.contains_key(42u64.to_be_bytes()).unwrap());
+        drop(sledcache);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_quick_parse_item_id_works_with_either_key_order() {
+        assert_eq!(
+            quick_parse_item_id(br#"{"type":"item","id":"Q42","labels":{}},"#),
+            QuickParsedLine::Item(42)
+        );
+        assert_eq!(
+            quick_parse_item_id(br#"{"id":"Q42","type":"item","labels":{}}"#),
+            QuickParsedLine::Item(42)
+        );
+        assert_eq!(
+            quick_parse_item_id(b"{\"type\":\"item\",\"id\":\"Q5\"}\n"),
+            QuickParsedLine::Item(5)
+        );
+    }
+
+    #[test]
+    fn test_quick_parse_item_id_skips_non_items() {
+        assert_eq!(
+            quick_parse_item_id(br#"{"type":"property","datatype":"string","id":"P31"}"#),
+            QuickParsedLine::NonItem
+        );
+        assert_eq!(
+            quick_parse_item_id(br#"{"id":"L7","type":"lexeme"}"#),
+            QuickParsedLine::NonItem
+        );
+    }
+
+    #[test]
+    fn test_quick_parse_item_id_rejects_garbage() {
+        for line in [
+            &b"boop"[..],
+            br#"{"boop":1}"#,
+            br#"{"type":"item","id":"P31"}"#,
+            br#"{"type":"item","id":"Q"}"#,
+            br#"{"type":"item","id":"Q12x"}"#,
+            br#"{"type":"item","id":"Q99999999999999999999999"}"#,
+            br#"{"type":"item","id":"Q12"#,
+            br#"{"type":"item"}"#,
+        ] {
+            assert_eq!(
+                quick_parse_item_id(line),
+                QuickParsedLine::Unparseable,
+                "{}",
+                String::from_utf8_lossy(line)
+            );
+        }
+        let late_id = format!(
+            r#"{{"type":"item","labels":"{}","id":"Q1"}}"#,
+            "x".repeat(200)
+        );
+        assert_eq!(
+            quick_parse_item_id(late_id.as_bytes()),
+            QuickParsedLine::Unparseable
+        );
+    }
+
+    #[test]
+    fn test_quick_parse_item_id_ignores_separators() {
+        for line in [&b"[\n"[..], b"]\n", b",\n", b"\n", b""] {
+            assert_eq!(quick_parse_item_id(line), QuickParsedLine::Separator);
+        }
+    }
 }
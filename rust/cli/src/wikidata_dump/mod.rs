@@ -1,8 +1,20 @@
-pub use index_file::index_wikidata_dump;
-pub use query::{execute_wikidata_query, iter_wikidata_objects, prepare_wikidata_query};
+pub use api::{WikidataApiFetcher, DEFAULT_WIKIDATA_API_REQUESTS_PER_SECOND};
+pub use cache_admin::{compact_wikidata_cache, show_wikidata_cache_stats};
+pub use index_file::{index_wikidata_dump, update_wikidata_index};
+pub use pipeline_report::pipeline_report_command;
+pub use query::{
+    execute_wikidata_query, iter_wikidata_artists, iter_wikidata_objects, prepare_wikidata_query,
+    WikidataSource,
+};
+pub use show_entity::show_wikidata_entity;
 
+mod api;
+mod cache_admin;
+mod file_table;
 mod index_file;
+mod pipeline_report;
 mod query;
+mod show_entity;
 mod sledcache;
 mod sparql_csv_export;
 
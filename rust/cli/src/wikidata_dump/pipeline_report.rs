@@ -0,0 +1,286 @@
+use super::query::PreparedQuery;
+use anyhow::Result;
+use gallery::art_object::ArtObjectId;
+use gallery::gallery_db::GalleryDb;
+use serde::Serialize;
+use std::{collections::HashSet, path::PathBuf};
+
+/// How many of the missing QIDs to list in a report, so it doesn't scroll off
+/// the screen when most of a query didn't make it.
+const MISSING_QID_SAMPLE_SIZE: usize = 10;
+
+/// What became of each of the QIDs in a prepared query, by the time they got
+/// to the database. Each QID is counted in exactly one of `imported`,
+/// `absorbed_as_fallback`, `quarantined` and `missing`.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct PipelineReport {
+    /// How many distinct QIDs the prepared query has.
+    pub prepared_qids: usize,
+    /// QIDs that are art objects from wikidata.
+    pub imported: usize,
+    /// QIDs that were deduplicated against art objects from other collections,
+    /// which have them as their fallback QIDs.
+    pub absorbed_as_fallback: usize,
+    /// QIDs that weren't imported because their dimensions looked wrong.
+    pub quarantined: usize,
+    /// QIDs that aren't anywhere in the database, e.g. because they were
+    /// missing required fields when the query was executed.
+    pub missing: usize,
+    /// Some of the missing QIDs, in the order they appear in the query.
+    pub missing_sample: Vec<u64>,
+    /// How many of the imported and absorbed QIDs are hanging in positive
+    /// galleries.
+    pub placed: usize,
+}
+
+impl PipelineReport {
+    /// How many of the QIDs made it into the database in some form.
+    pub fn present(&self) -> usize {
+        self.imported + self.absorbed_as_fallback
+    }
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+/// Cross-references the given QIDs, e.g. those of a prepared query, against
+/// the art objects, quarantined art objects and layout in the database.
+pub fn build_pipeline_report(
+    qids: &[u64],
+    db: &GalleryDb,
+    missing_sample_size: usize,
+) -> Result<PipelineReport> {
+    let object_qids = db.get_all_wikidata_object_qids()?;
+    let fallback_qids = db.get_fallback_wikidata_qids_by_id()?;
+    let absorbed_qids: HashSet<i64> = fallback_qids.values().copied().collect();
+    let quarantined_qids: HashSet<i64> = db
+        .list_quarantined_objects()?
+        .into_iter()
+        .filter_map(|record| match record.object_id {
+            ArtObjectId::Wikidata(qid) => Some(qid),
+            _ => None,
+        })
+        .collect();
+    let placed_qids: HashSet<i64> = db
+        .get_art_object_ids_in_positive_galleries()?
+        .into_iter()
+        .filter_map(|id| match id {
+            ArtObjectId::Wikidata(qid) => Some(qid),
+            _ => fallback_qids.get(&id).copied(),
+        })
+        .collect();
+
+    let mut report = PipelineReport::default();
+    let mut seen = HashSet::new();
+    for &qid in qids {
+        if !seen.insert(qid) {
+            continue;
+        }
+        report.prepared_qids += 1;
+        let db_qid = qid as i64;
+        if object_qids.contains(&db_qid) {
+            report.imported += 1;
+        } else if absorbed_qids.contains(&db_qid) {
+            report.absorbed_as_fallback += 1;
+        } else if quarantined_qids.contains(&db_qid) {
+            report.quarantined += 1;
+            continue;
+        } else {
+            report.missing += 1;
+            if report.missing_sample.len() < missing_sample_size {
+                report.missing_sample.push(qid);
+            }
+            continue;
+        }
+        if placed_qids.contains(&db_qid) {
+            report.placed += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// Reports how much of the given prepared query made it into the database.
+pub fn pipeline_report_command(input: PathBuf, db: GalleryDb, json: bool) -> Result<()> {
+    let query = PreparedQuery::from_path(input)?;
+    let report = build_pipeline_report(&query.qids, &db, MISSING_QID_SAMPLE_SIZE)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    let total = report.prepared_qids;
+    println!("QIDs in prepared query: {total}");
+    for (label, count) in [
+        ("Imported as wikidata art objects", report.imported),
+        (
+            "Absorbed as fallbacks of other art objects",
+            report.absorbed_as_fallback,
+        ),
+        ("Quarantined", report.quarantined),
+        ("Missing", report.missing),
+    ] {
+        println!("  {label}: {count} ({:.1}%)", percent(count, total));
+    }
+    if !report.missing_sample.is_empty() {
+        let sample: Vec<String> = report
+            .missing_sample
+            .iter()
+            .map(|qid| format!("Q{qid}"))
+            .collect();
+        println!(
+            "Some missing QIDs: {}{}",
+            sample.join(", "),
+            if report.missing > sample.len() {
+                ", ..."
+            } else {
+                ""
+            }
+        );
+    }
+    println!(
+        "Placed in positive galleries: {} of {} present ({:.1}%)",
+        report.placed,
+        report.present(),
+        percent(report.placed, report.present())
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use gallery::{
+        art_object::ArtObjectId,
+        gallery_db::{ArtObjectRecord, GalleryDb, LayoutRecord, QuarantinedObjectRecord},
+        medium::MediumCategory,
+    };
+    use rusqlite::Connection;
+
+    use super::{build_pipeline_report, PipelineReport};
+
+    fn make_art_object(
+        object_id: ArtObjectId,
+        fallback_wikidata_qid: Option<i64>,
+    ) -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id,
+            object_date: "".into(),
+            culture: "".into(),
+            artist: "".into(),
+            artist_role: String::default(),
+            title: "Boop".into(),
+            medium: "".into(),
+            medium_category: MediumCategory::Other,
+            width: 1.0,
+            height: 1.0,
+            fallback_wikidata_qid,
+            filename: "".into(),
+            collection: "".into(),
+            artist_qid: None,
+            highlight: false,
+            tags: String::default(),
+            classification: String::default(),
+            source_gallery: None,
+        }
+    }
+
+    fn place(art_object_id: ArtObjectId, gallery_id: i64) -> LayoutRecord<&'static str> {
+        LayoutRecord {
+            gallery_id,
+            wall_id: "wall_a",
+            art_object_id,
+            x: 1.0,
+            y: 1.0,
+            rotated: false,
+        }
+    }
+
+    /// Creates a database with:
+    ///
+    /// * Q1 and Q2 imported from wikidata, with Q1 in gallery 1 and Q2 only in
+    ///   gallery -1.
+    /// * Q3 and Q4 absorbed by Met objects 100 and 101, with only 100 in a
+    ///   positive gallery.
+    /// * Q5 quarantined.
+    fn create_seeded_db() -> GalleryDb {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.create_schema().unwrap();
+        db.add_art_objects(&vec![
+            make_art_object(ArtObjectId::Wikidata(1), None),
+            make_art_object(ArtObjectId::Wikidata(2), None),
+            make_art_object(ArtObjectId::Met(100), Some(3)),
+            make_art_object(ArtObjectId::Met(101), Some(4)),
+            make_art_object(ArtObjectId::Met(102), None),
+        ])
+        .unwrap();
+        db.add_quarantined_objects(&vec![QuarantinedObjectRecord {
+            object_id: ArtObjectId::Wikidata(5),
+            title: "Tiny".into(),
+            artist: "".into(),
+            collection: "".into(),
+            width: 0.001,
+            height: 1.0,
+            reason: "width is too small".into(),
+        }])
+        .unwrap();
+        db.set_layout_records_in_positive_galleries(&vec![
+            place(ArtObjectId::Wikidata(1), 1),
+            place(ArtObjectId::Met(100), 2),
+            place(ArtObjectId::Met(102), 2),
+        ])
+        .unwrap();
+        db.upsert_layout_records(&vec![place(ArtObjectId::Wikidata(2), -1)])
+            .unwrap();
+        db
+    }
+
+    #[test]
+    fn test_each_bucket_is_counted() {
+        let db = create_seeded_db();
+        let report = build_pipeline_report(&[1, 2, 3, 4, 5, 6, 7], &db, 10).unwrap();
+        assert_eq!(
+            report,
+            PipelineReport {
+                prepared_qids: 7,
+                imported: 2,
+                absorbed_as_fallback: 2,
+                quarantined: 1,
+                missing: 2,
+                missing_sample: vec![6, 7],
+                placed: 2,
+            }
+        );
+        assert_eq!(report.present(), 4);
+    }
+
+    #[test]
+    fn test_duplicate_qids_are_counted_once() {
+        let db = create_seeded_db();
+        let report = build_pipeline_report(&[1, 1, 6, 6], &db, 10).unwrap();
+        assert_eq!(report.prepared_qids, 2);
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.placed, 1);
+        assert_eq!(report.missing_sample, vec![6]);
+    }
+
+    #[test]
+    fn test_missing_sample_is_limited() {
+        let db = create_seeded_db();
+        let report = build_pipeline_report(&[10, 9, 8, 7, 6], &db, 3).unwrap();
+        assert_eq!(report.missing, 5);
+        assert_eq!(report.missing_sample, vec![10, 9, 8]);
+        assert_eq!(report.placed, 0);
+    }
+
+    #[test]
+    fn test_empty_db_has_everything_missing() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.create_schema().unwrap();
+        let report = build_pipeline_report(&[1, 2], &db, 10).unwrap();
+        assert_eq!(report.missing, 2);
+        assert_eq!(report.present(), 0);
+    }
+}
@@ -1,16 +1,26 @@
-use super::sledcache::{iter_and_cache_entities, sledcache_path_for_dumpfile, CachedEntityInfo};
+use super::api::WikidataApiFetcher;
+use super::sledcache::{
+    iter_and_cache_entities, iter_and_cache_entities_from_api, sledcache_path_for_dumpfile,
+    CachedEntityInfo,
+};
 use super::sparql_csv_export::parse_sparql_csv_export;
+use crate::dimension_limits::DimensionLimits;
+use crate::import_skip::{
+    quarantine_implausible_dimensions, skip_deserialize_errors, ImportError, ImportSkip,
+    ImportSkipReason,
+};
 use anyhow::Result;
 use gallery::art_object::ArtObjectId;
-use gallery::gallery_db::ArtObjectRecord;
-use gallery::wikidata::WikidataEntity;
+use gallery::artist_names::normalize_artist_name;
+use gallery::gallery_db::{ArtObjectRecord, ArtistRecord};
+use gallery::medium::classify_medium;
+use gallery::wikidata::{WikidataEntity, ANONYMOUS_CREATOR_ID};
 use indicatif::ProgressBar;
-use serde::ser::Error;
+use log::{debug, log_enabled, Level};
 use serde::{Deserialize, Serialize};
-use std::fs::File;
 use std::{
     collections::{HashMap, HashSet},
-    io::{BufReader, BufWriter},
+    io::{BufReader, BufWriter, Read, Write},
     path::PathBuf,
 };
 
@@ -26,6 +36,9 @@ struct WikidataCsvRecordToSerialize<'a> {
     pub materials: String,
     pub collection: &'a str,
     pub filename: &'a str,
+    pub artist_qid: Option<u64>,
+    pub artist_description: &'a str,
+    pub highlight: bool,
 }
 
 #[derive(Deserialize)]
@@ -39,105 +52,240 @@ struct WikidataCsvRecord {
     pub materials: String,
     pub collection: String,
     pub filename: String,
+    // These were added later, so older CSV files won't have them.
+    #[serde(default)]
+    pub artist_qid: Option<u64>,
+    #[serde(default)]
+    pub artist_description: String,
+    #[serde(default)]
+    pub highlight: bool,
+}
+
+/// Where the entities in a prepared query were fetched from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum WikidataSource {
+    /// The dumpfile, via its index.
+    #[default]
+    Dump,
+    /// The Wikidata API, which is much more convenient for small sets of QIDs.
+    Api,
 }
 
 #[derive(Serialize, Deserialize)]
-struct PreparedQuery {
-    dumpfile: PathBuf,
-    qids: Vec<u64>,
-    dependency_qids: Vec<u64>,
+pub(super) struct PreparedQuery {
+    /// When the source is the API, this doesn't need to exist, it just
+    /// determines where the entities are cached.
+    pub dumpfile: PathBuf,
+    pub qids: Vec<u64>,
+    pub dependency_qids: Vec<u64>,
+    // This was added later, so older prepared queries won't have it.
+    #[serde(default)]
+    pub source: WikidataSource,
 }
 
-pub fn iter_wikidata_objects(
-    reader: csv::Reader<BufReader<File>>,
-) -> impl Iterator<Item = Result<ArtObjectRecord, csv::Error>> {
+impl PreparedQuery {
+    pub fn from_path(path: PathBuf) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(
+            std::fs::File::open(path)?,
+        ))?)
+    }
+}
+
+fn wikidata_skip(qid: Option<u64>, reason: ImportSkipReason) -> ImportError {
+    ImportError::Skip(ImportSkip { qid, reason })
+}
+
+/// Iterate through the art objects in the given wikidata CSV.
+///
+/// Rows that can't be imported are yielded as `ImportError::Skip`, rows with
+/// dimensions outside the given limits are yielded as `ImportError::Quarantine`,
+/// and problems with the structure of the CSV itself are yielded as `ImportError::Csv`.
+pub fn iter_wikidata_objects<R: Read>(
+    mut reader: csv::Reader<R>,
+    dimension_limits: DimensionLimits,
+) -> impl Iterator<Item = Result<ArtObjectRecord, ImportError>> {
+    let headers = reader.headers().ok().cloned();
+    let qid_index = headers
+        .as_ref()
+        .and_then(|headers| headers.iter().position(|name| name == "qid"));
+    reader.into_records().map(move |result| {
+        let row = result?;
+        let row_qid = qid_index
+            .and_then(|index| row.get(index))
+            .and_then(|qid| qid.parse::<u64>().ok());
+        let record: WikidataCsvRecord = row
+            .deserialize(headers.as_ref())
+            .map_err(|err| skip_deserialize_errors(err, row_qid))?;
+        if record.filename.len() == 0 {
+            return Err(wikidata_skip(
+                Some(record.qid),
+                ImportSkipReason::MissingFilename,
+            ));
+        }
+        let artist = normalize_artist_name(&record.artist);
+        let record = ArtObjectRecord {
+            object_id: ArtObjectId::Wikidata(record.qid as i64),
+            object_date: record.inception,
+            culture: String::default(),
+            artist: artist.name,
+            artist_role: artist.role,
+            title: record.title,
+            medium_category: classify_medium(&record.materials),
+            medium: record.materials,
+            width: record.width / 100.0, // Convert centimeters to meters
+            height: record.height / 100.0, // Convert centimeters to meters
+            filename: record.filename,
+            fallback_wikidata_qid: None,
+            collection: record.collection,
+            artist_qid: record.artist_qid.map(|qid| qid as i64),
+            highlight: record.highlight,
+            tags: String::default(),
+            classification: String::default(),
+            source_gallery: None,
+        };
+        quarantine_implausible_dimensions(record, &dimension_limits)
+    })
+}
+
+/// Iterate through the artists of all the objects in the given wikidata CSV.
+///
+/// Note that the same artist will be returned once for every object they made.
+pub fn iter_wikidata_artists<R: Read>(
+    reader: csv::Reader<R>,
+) -> impl Iterator<Item = Result<ArtistRecord, csv::Error>> {
     reader
         .into_deserialize::<WikidataCsvRecord>()
-        .map(move |result| match result {
-            Ok(record) => {
-                if record.filename.len() == 0 {
-                    return Err(csv::Error::custom(format!(
-                        "Q{} has no filename",
-                        record.qid
-                    )));
-                }
-                Ok(ArtObjectRecord {
-                    object_id: ArtObjectId::Wikidata(record.qid as i64),
-                    object_date: record.inception,
-                    culture: String::default(),
-                    artist: record.artist,
-                    title: record.title,
-                    medium: record.materials,
-                    width: record.width / 100.0, // Convert centimeters to meters
-                    height: record.height / 100.0, // Convert centimeters to meters
-                    filename: record.filename,
-                    fallback_wikidata_qid: None,
-                    collection: record.collection,
+        .filter_map(move |result| match result {
+            Ok(record) => record.artist_qid.map(|qid| {
+                Ok(ArtistRecord {
+                    qid: qid as i64,
+                    name: normalize_artist_name(&record.artist).name,
+                    description: record.artist_description,
                 })
-            }
-            Err(err) => Err(err),
+            }),
+            Err(err) => Some(Err(err)),
         })
 }
 
-fn get_dependency_label(dependencies: &HashMap<u64, WikidataEntity>, qid: Option<u64>) -> &str {
-    qid.map(|qid| {
-        dependencies
-            .get(&qid)
-            .map(|entity| entity.label())
-            .flatten()
-    })
-    .flatten()
-    .unwrap_or_default()
+/// The only parts of a dependency (an artist, material or collection) that end
+/// up in the CSV. There can be tens of thousands of dependencies, so we keep
+/// just these instead of their entire entities.
+#[derive(Debug)]
+struct DependencyInfo {
+    label: Option<String>,
+    description: Option<String>,
 }
 
-fn get_dependency_labels(dependencies: &HashMap<u64, WikidataEntity>, qids: Vec<u64>) -> Vec<&str> {
+type Dependencies = HashMap<u64, DependencyInfo>;
+
+fn get_dependency_label(dependencies: &Dependencies, qid: Option<u64>) -> &str {
+    qid.and_then(|qid| dependencies.get(&qid))
+        .and_then(|info| info.label.as_deref())
+        .unwrap_or_default()
+}
+
+fn get_dependency_description(dependencies: &Dependencies, qid: Option<u64>) -> &str {
+    qid.and_then(|qid| dependencies.get(&qid))
+        .and_then(|info| info.description.as_deref())
+        .unwrap_or_default()
+}
+
+fn get_dependency_labels(dependencies: &Dependencies, qids: Vec<u64>) -> Vec<&str> {
     qids.into_iter()
+        .filter_map(|qid| dependencies.get(&qid)?.label.as_deref())
+        .collect()
+}
+
+/// What the CSV calls the creator of an anonymous work.
+const UNKNOWN_ARTIST: &str = "Unknown artist";
+
+/// Returns the labels of the given creators, joined together, with anonymous
+/// creators called `UNKNOWN_ARTIST`.
+fn get_artist_label(dependencies: &Dependencies, creator_qids: &[u64]) -> String {
+    let labels: Vec<&str> = creator_qids
+        .iter()
         .filter_map(|qid| {
-            dependencies
-                .get(&qid)
-                .map(|entity| entity.label())
-                .flatten()
+            if *qid == ANONYMOUS_CREATOR_ID {
+                Some(UNKNOWN_ARTIST)
+            } else {
+                dependencies.get(qid)?.label.as_deref()
+            }
         })
-        .collect()
+        .collect();
+    labels.join(", ")
 }
 
-pub fn execute_wikidata_query(input: PathBuf, output: PathBuf, limit: Option<usize>) -> Result<()> {
-    let query: PreparedQuery =
-        serde_json::from_reader(BufReader::new(std::fs::File::open(input)?))?;
-    let sledcache = sled::open(&sledcache_path_for_dumpfile(&query.dumpfile))?;
-    let mut dependencies: HashMap<u64, WikidataEntity> =
-        HashMap::with_capacity(query.dependency_qids.len());
-
-    // TODO: If the user isn't outputting the entire result set, we might want to load dependencies
-    // lazily, as we probably won't need all of them.
-    println!("Loading dependencies.");
-    let bar = ProgressBar::new(query.dependency_qids.len() as u64);
-    for qid in query.dependency_qids.iter() {
-        let value = sledcache
-            .get(qid.to_be_bytes())?
-            .expect("dependency qid in query should exist in sledcache");
-        let entity: WikidataEntity = serde_json::from_slice(value.as_ref())?;
-        dependencies.insert(*qid, entity);
+fn load_cached_entity(sledcache: &sled::Db, qid: u64) -> Result<WikidataEntity> {
+    let value = sledcache
+        .get(qid.to_be_bytes())?
+        .expect("qid in query should exist in sledcache");
+    Ok(serde_json::from_slice(value.as_ref())?)
+}
+
+/// Returns the dependencies in `dependency_qids` that the given entities refer
+/// to, in the same order, so that we only need to load the dependencies of
+/// the entities we're actually going to output.
+fn get_referenced_dependency_qids(
+    sledcache: &sled::Db,
+    qids: &[u64],
+    dependency_qids: &[u64],
+) -> Result<Vec<u64>> {
+    let mut referenced: HashSet<u64> = HashSet::new();
+    for qid in qids {
+        let entity = load_cached_entity(sledcache, *qid)?;
+        referenced.extend(entity.creator_ids());
+        referenced.extend(entity.collection_id());
+        referenced.extend(entity.material_ids());
+    }
+    Ok(dependency_qids
+        .iter()
+        .copied()
+        .filter(|qid| referenced.contains(qid))
+        .collect())
+}
+
+fn load_dependencies(sledcache: &sled::Db, dependency_qids: &[u64]) -> Result<Dependencies> {
+    let mut dependencies = Dependencies::with_capacity(dependency_qids.len());
+    let bar = ProgressBar::new(dependency_qids.len() as u64);
+    for qid in dependency_qids {
+        let entity = load_cached_entity(sledcache, *qid)?;
+        dependencies.insert(
+            *qid,
+            DependencyInfo {
+                label: entity.label().map(str::to_string),
+                description: entity.description().map(str::to_string),
+            },
+        );
         bar.inc(1);
     }
     bar.finish();
+    Ok(dependencies)
+}
 
-    println!("Writing {}.", output.display());
-    let mut writer = csv::Writer::from_path(output)?;
-    let bar = ProgressBar::new(query.qids.len() as u64);
-    let mut count = 0;
-    for qid in query.qids.iter() {
-        if let Some(limit) = limit {
-            if count == limit {
-                break;
-            }
-            count += 1;
-        }
+fn write_wikidata_query_csv<W: Write>(
+    query: &PreparedQuery,
+    sledcache: &sled::Db,
+    writer: &mut csv::Writer<W>,
+    limit: Option<usize>,
+) -> Result<()> {
+    let qids = match limit {
+        Some(limit) => &query.qids[..limit.min(query.qids.len())],
+        None => &query.qids[..],
+    };
+    let dependency_qids = if qids.len() < query.qids.len() {
+        println!("Finding dependencies of {} entities.", qids.len());
+        get_referenced_dependency_qids(sledcache, qids, &query.dependency_qids)?
+    } else {
+        query.dependency_qids.clone()
+    };
+
+    println!("Loading {} dependencies.", dependency_qids.len());
+    let dependencies = load_dependencies(sledcache, &dependency_qids)?;
 
-        let value = sledcache
-            .get(qid.to_be_bytes())?
-            .expect("qid in query should exist in sledcache");
-        let entity: WikidataEntity = serde_json::from_slice(value.as_ref())?;
+    let bar = ProgressBar::new(qids.len() as u64);
+    for qid in qids {
+        let entity = load_cached_entity(sledcache, *qid)?;
 
         // Get required fields.
         let (width, height) = entity.dimensions_in_cm().expect("dimensions should exist");
@@ -148,7 +296,13 @@ pub fn execute_wikidata_query(input: PathBuf, output: PathBuf, limit: Option<usi
 
         // Get optional fields.
         let title = entity.label().unwrap_or_default();
-        let artist = get_dependency_label(&dependencies, entity.creator_id());
+        let creator_qids = entity.creator_ids();
+        let artist_qid = creator_qids
+            .iter()
+            .copied()
+            .find(|qid| dependencies.contains_key(qid));
+        let artist = &get_artist_label(&dependencies, &creator_qids);
+        let artist_description = get_dependency_description(&dependencies, artist_qid);
         let inception = &entity.inception().unwrap_or_default();
         let materials = get_dependency_labels(&dependencies, entity.material_ids());
         let collection = get_dependency_label(&dependencies, entity.collection_id());
@@ -163,6 +317,9 @@ pub fn execute_wikidata_query(input: PathBuf, output: PathBuf, limit: Option<usi
             materials: materials.join(", "),
             collection,
             filename,
+            artist_qid,
+            artist_description,
+            highlight: entity.is_highlight(),
         })?;
 
         bar.inc(1);
@@ -172,13 +329,43 @@ pub fn execute_wikidata_query(input: PathBuf, output: PathBuf, limit: Option<usi
     Ok(())
 }
 
+pub fn execute_wikidata_query(input: PathBuf, output: PathBuf, limit: Option<usize>) -> Result<()> {
+    let query = PreparedQuery::from_path(input)?;
+    let sledcache = sled::open(&sledcache_path_for_dumpfile(&query.dumpfile))?;
+    println!("Writing {}.", output.display());
+    let mut writer = csv::Writer::from_path(output)?;
+    write_wikidata_query_csv(&query, &sledcache, &mut writer, limit)
+}
+
+type CachedEntityIterator = dyn Iterator<Item = Result<CachedEntityInfo>>;
+
+/// Iterate through the given entities, fetching any that aren't cached from the
+/// Wikidata API if a fetcher is given, or the dumpfile otherwise.
+fn iter_and_cache_entities_from_source(
+    api: Option<&mut WikidataApiFetcher>,
+    dumpfile_path: PathBuf,
+    qids: Vec<u64>,
+    warnings: bool,
+) -> Result<Box<CachedEntityIterator>> {
+    Ok(match api {
+        Some(fetcher) => Box::new(iter_and_cache_entities_from_api(
+            fetcher,
+            dumpfile_path,
+            qids,
+        )?),
+        None => Box::new(iter_and_cache_entities(dumpfile_path, qids, warnings)?),
+    })
+}
+
+/// Prepare a query for the given QIDs. If `api` is given, entities are fetched
+/// from the Wikidata API instead of the dumpfile.
 pub fn prepare_wikidata_query(
     output: PathBuf,
     dumpfile_path: PathBuf,
     mut qids: Vec<u64>,
     csv: Option<PathBuf>,
-    verbose: bool,
     warnings: bool,
+    mut api: Option<&mut WikidataApiFetcher>,
 ) -> Result<()> {
     if let Some(csv) = csv {
         parse_sparql_csv_export(csv, &mut qids)?;
@@ -190,7 +377,17 @@ pub fn prepare_wikidata_query(
     let mut dependency_qids: HashSet<u64> = HashSet::new();
     let bar = ProgressBar::new(expected_total as u64);
     println!("Processing {} entities.", expected_total);
-    for result in iter_and_cache_entities(dumpfile_path.clone(), qids, warnings)? {
+    let source = if api.is_some() {
+        WikidataSource::Api
+    } else {
+        WikidataSource::Dump
+    };
+    for result in iter_and_cache_entities_from_source(
+        api.as_deref_mut(),
+        dumpfile_path.clone(),
+        qids,
+        warnings,
+    )? {
         let CachedEntityInfo {
             entity,
             percent_done,
@@ -210,8 +407,10 @@ pub fn prepare_wikidata_query(
                 dimensions
             );
         }
-        if let Some(creator_qid) = entity.creator_id() {
-            dependency_qids.insert(creator_qid);
+        for creator_qid in entity.creator_ids() {
+            if creator_qid != ANONYMOUS_CREATOR_ID {
+                dependency_qids.insert(creator_qid);
+            }
         }
         if let Some(collection_qid) = entity.collection_id() {
             dependency_qids.insert(collection_qid);
@@ -219,8 +418,8 @@ pub fn prepare_wikidata_query(
         for material_qid in entity.material_ids() {
             dependency_qids.insert(material_qid);
         }
-        if verbose {
-            println!(
+        if log_enabled!(Level::Debug) {
+            debug!(
                 "{percent_done:.1}% Q{}: {} - {} ({}, {}, {})",
                 entity.id,
                 entity.label().unwrap_or_default(),
@@ -248,7 +447,7 @@ pub fn prepare_wikidata_query(
         expected_total - total
     );
     let mut dependency_qids =
-        cache_and_get_dependency_qids(dumpfile_path.clone(), dependency_qids, verbose, warnings)?;
+        cache_and_get_dependency_qids(api, dumpfile_path.clone(), dependency_qids, warnings)?;
 
     // The order of this doesn't really matter, but just to keep the output stable, let's sort by id.
     dependency_qids.sort();
@@ -260,6 +459,7 @@ pub fn prepare_wikidata_query(
             .filter(|qid| final_qids_with_required_fields.contains(qid))
             .collect(),
         dependency_qids,
+        source,
     };
     let output_file = std::fs::File::create(output.clone())?;
     let output_writer = BufWriter::new(output_file);
@@ -269,9 +469,9 @@ pub fn prepare_wikidata_query(
 }
 
 fn cache_and_get_dependency_qids(
+    api: Option<&mut WikidataApiFetcher>,
     dumpfile_path: PathBuf,
     dependency_qids: HashSet<u64>,
-    verbose: bool,
     warnings: bool,
 ) -> Result<Vec<u64>> {
     let dependency_qids = dependency_qids.into_iter().collect::<Vec<_>>();
@@ -280,15 +480,17 @@ fn cache_and_get_dependency_qids(
     if expected_total > 0 {
         let bar = ProgressBar::new(expected_total as u64);
         println!("Processing {} dependency entities.", expected_total);
-        for result in iter_and_cache_entities(dumpfile_path, dependency_qids, warnings)? {
+        for result in
+            iter_and_cache_entities_from_source(api, dumpfile_path, dependency_qids, warnings)?
+        {
             let CachedEntityInfo {
                 entity,
                 percent_done,
                 ..
             } = result?;
             final_dependency_qids.push(entity.id);
-            if verbose {
-                println!(
+            if log_enabled!(Level::Debug) {
+                debug!(
                     "{percent_done:.1}% dependency Q{}: {} -{}",
                     entity.id,
                     entity.label().unwrap_or_default(),
@@ -307,3 +509,451 @@ fn cache_and_get_dependency_qids(
     }
     Ok(final_dependency_qids)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use gallery::{
+        art_object::ArtObjectId, gallery_cache::GalleryCache, medium::MediumCategory,
+        wikidata::WikidataEntity,
+    };
+
+    use crate::{
+        dimension_limits::DimensionIssue,
+        import_skip::{ImportError, ImportSkip, ImportSkipReason},
+        wikidata_dump::api::{wbgetentities_cache_filename, WikidataApiFetcher},
+    };
+
+    use super::{
+        execute_wikidata_query, get_referenced_dependency_qids, iter_wikidata_artists,
+        iter_wikidata_objects, prepare_wikidata_query, write_wikidata_query_csv, PreparedQuery,
+        WikidataCsvRecordToSerialize, WikidataSource,
+    };
+
+    fn make_csv() -> Vec<u8> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer
+            .serialize(WikidataCsvRecordToSerialize {
+                qid: 1,
+                artist: "Boop Jones",
+                title: "Funky Painting",
+                inception: "1864",
+                width: 100.0,
+                height: 50.0,
+                materials: "oil paint, canvas".to_string(),
+                collection: "Martian Museum of Art",
+                filename: "funky-painting.jpg",
+                artist_qid: Some(42),
+                artist_description: "Martian painter",
+                highlight: true,
+            })
+            .unwrap();
+        writer
+            .serialize(WikidataCsvRecordToSerialize {
+                qid: 2,
+                artist: "",
+                title: "Anonymous Painting",
+                inception: "",
+                width: 10.0,
+                height: 20.0,
+                materials: "".to_string(),
+                collection: "",
+                filename: "anonymous-painting.jpg",
+                artist_qid: None,
+                artist_description: "",
+                highlight: false,
+            })
+            .unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_artist_qid_round_trips_through_csv() {
+        let csv = make_csv();
+        let objects =
+            iter_wikidata_objects(csv::Reader::from_reader(csv.as_slice()), Default::default())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].object_id, ArtObjectId::Wikidata(1));
+        assert_eq!(objects[0].artist_qid, Some(42));
+        assert_eq!(objects[0].width, 1.0);
+        assert_eq!(objects[0].medium_category, MediumCategory::Oil);
+        assert_eq!(objects[1].artist_qid, None);
+        assert!(objects[0].highlight);
+        assert!(!objects[1].highlight);
+
+        let artists = iter_wikidata_artists(csv::Reader::from_reader(csv.as_slice()))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(artists.len(), 1);
+        assert_eq!(artists[0].qid, 42);
+        assert_eq!(artists[0].name, "Boop Jones");
+        assert_eq!(artists[0].description, "Martian painter");
+    }
+
+    #[test]
+    fn test_csv_without_artist_columns_still_works() {
+        let csv = "qid,artist,title,inception,width,height,materials,collection,filename\n\
+                   1,Boop Jones,Funky Painting,1864,100,50,,,funky-painting.jpg\n";
+        let objects =
+            iter_wikidata_objects(csv::Reader::from_reader(csv.as_bytes()), Default::default())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].artist_qid, None);
+        assert!(!objects[0].highlight);
+        assert_eq!(
+            iter_wikidata_artists(csv::Reader::from_reader(csv.as_bytes())).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_artist_names_are_normalized() {
+        let csv = "qid,artist,title,inception,width,height,materials,collection,filename\n\
+                   1,\"Gogh, Vincent van\",Irises,1889,100,50,,,irises.jpg\n\
+                   2,Workshop of  Rubens,Other Painting,1620,100,50,,,other-painting.jpg\n";
+        let objects =
+            iter_wikidata_objects(csv::Reader::from_reader(csv.as_bytes()), Default::default())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+        assert_eq!(objects[0].artist, "Vincent van Gogh");
+        assert_eq!(objects[0].artist_role, "");
+        assert_eq!(objects[1].artist, "Rubens");
+        assert_eq!(objects[1].artist_role, "workshop of");
+    }
+
+    #[test]
+    fn test_rows_with_unparseable_fields_are_skipped() {
+        let csv = "qid,artist,title,inception,width,height,materials,collection,filename\n\
+                   1,Boop Jones,Funky Painting,1864,big,50,,,funky-painting.jpg\n\
+                   2,Boop Jones,Other Painting,1864,100,50,,,other-painting.jpg\n";
+        let results: Vec<_> =
+            iter_wikidata_objects(csv::Reader::from_reader(csv.as_bytes()), Default::default())
+                .collect();
+        assert_eq!(results.len(), 2);
+        let Err(ImportError::Skip(skip)) = &results[0] else {
+            panic!("expected first row to be skipped");
+        };
+        assert_eq!(
+            skip,
+            &ImportSkip {
+                qid: Some(1),
+                reason: ImportSkipReason::DeserializeFailed
+            }
+        );
+        assert!(results[1].is_ok());
+    }
+
+    #[test]
+    fn test_rows_with_implausible_dimensions_are_quarantined() {
+        let csv = "qid,artist,title,inception,width,height,materials,collection,filename\n\
+                   1,Boop Jones,Huge Painting,1864,4000,4000,,,huge-painting.jpg\n\
+                   2,Boop Jones,Flat Painting,1864,100,0,,,flat-painting.jpg\n";
+        let results: Vec<_> =
+            iter_wikidata_objects(csv::Reader::from_reader(csv.as_bytes()), Default::default())
+                .collect();
+        let issues: Vec<_> = results
+            .iter()
+            .map(|result| match result {
+                Err(ImportError::Quarantine(quarantine)) => {
+                    (quarantine.record.object_id, quarantine.issue)
+                }
+                _ => panic!("expected row to be quarantined"),
+            })
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (ArtObjectId::Wikidata(1), DimensionIssue::TooLarge),
+                (ArtObjectId::Wikidata(2), DimensionIssue::NonPositive),
+            ]
+        );
+    }
+
+    fn entity_statement(property: &str, qid: u64) -> String {
+        format!(
+            r#""{property}":[{{"mainsnak":{{"datavalue":{{"value":{{"numeric-id":{qid}}},"type":"wikibase-entityid"}}}}}}]"#
+        )
+    }
+
+    fn dimension_statement(property: &str) -> String {
+        format!(
+            r#""{property}":[{{"mainsnak":{{"datavalue":{{"value":{{"amount":"+3","unit":"http://www.wikidata.org/entity/Q174728"}},"type":"quantity"}}}}}}]"#
+        )
+    }
+
+    fn make_painting(qid: u64, creator: u64, materials: &[u64], collection: Option<u64>) -> String {
+        let mut claims = vec![
+            r#""P18":[{"mainsnak":{"datavalue":{"value":"Boop.jpg","type":"string"}}}]"#
+                .to_string(),
+            dimension_statement("P2049"),
+            dimension_statement("P2048"),
+            entity_statement("P170", creator),
+        ];
+        if let Some(collection) = collection {
+            claims.push(entity_statement("P195", collection));
+        }
+        if !materials.is_empty() {
+            let statements = materials
+                .iter()
+                .map(|material| {
+                    format!(
+                        r#"{{"mainsnak":{{"datavalue":{{"value":{{"numeric-id":{material}}},"type":"wikibase-entityid"}}}}}}"#
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            claims.push(format!(r#""P186":[{statements}]"#));
+        }
+        format!(
+            r#"{{"id":"Q{qid}","labels":{{"en":{{"value":"Painting {qid}"}}}},"claims":{{{}}}}}"#,
+            claims.join(",")
+        )
+    }
+
+    fn make_dependency(qid: u64) -> String {
+        format!(
+            r#"{{"id":"Q{qid}","labels":{{"en":{{"value":"Thing {qid}"}}}},"descriptions":{{"en":{{"value":"About thing {qid}"}}}},"claims":{{}}}}"#
+        )
+    }
+
+    fn make_query_and_sledcache() -> (PreparedQuery, sled::Db) {
+        let sledcache = sled::Config::new().temporary(true).open().unwrap();
+        let entities = [
+            (1, make_painting(1, 10, &[20, 21], Some(30))),
+            // Q11 isn't a dependency, so it shouldn't end up as the artist.
+            (2, make_painting(2, 11, &[20], None)),
+            (3, make_painting(3, 12, &[], Some(31))),
+            (10, make_dependency(10)),
+            (12, make_dependency(12)),
+            (20, make_dependency(20)),
+            (21, make_dependency(21)),
+            (30, make_dependency(30)),
+            (31, make_dependency(31)),
+        ];
+        for (qid, json) in entities {
+            sledcache
+                .insert(u64::to_be_bytes(qid), json.as_bytes())
+                .unwrap();
+        }
+        let query = PreparedQuery {
+            dumpfile: "boop.json.bz2".into(),
+            qids: vec![1, 2, 3],
+            dependency_qids: vec![10, 12, 20, 21, 30, 31],
+            source: WikidataSource::Dump,
+        };
+        (query, sledcache)
+    }
+
+    /// This is how the CSV used to be written, by loading every dependency's
+    /// entire entity up-front.
+    fn write_csv_eagerly(
+        query: &PreparedQuery,
+        sledcache: &sled::Db,
+        limit: Option<usize>,
+    ) -> String {
+        let load = |qid: &u64| -> WikidataEntity {
+            serde_json::from_slice(sledcache.get(qid.to_be_bytes()).unwrap().unwrap().as_ref())
+                .unwrap()
+        };
+        let dependencies: HashMap<u64, WikidataEntity> = query
+            .dependency_qids
+            .iter()
+            .map(|qid| (*qid, load(qid)))
+            .collect();
+        let label = |qid: Option<u64>| {
+            qid.and_then(|qid| dependencies.get(&qid)?.label())
+                .unwrap_or_default()
+        };
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for qid in query.qids.iter().take(limit.unwrap_or(usize::MAX)) {
+            let entity = load(qid);
+            let (width, height) = entity.dimensions_in_cm().unwrap();
+            let artist_qid = entity
+                .creator_id()
+                .filter(|qid| dependencies.contains_key(qid));
+            let materials: Vec<&str> = entity
+                .material_ids()
+                .into_iter()
+                .filter_map(|qid| dependencies.get(&qid)?.label())
+                .collect();
+            writer
+                .serialize(WikidataCsvRecordToSerialize {
+                    qid: *qid,
+                    artist: label(artist_qid),
+                    title: entity.label().unwrap_or_default(),
+                    inception: &entity.inception().unwrap_or_default(),
+                    width,
+                    height,
+                    materials: materials.join(", "),
+                    collection: label(entity.collection_id()),
+                    filename: entity.image_filename().unwrap(),
+                    artist_qid,
+                    artist_description: artist_qid
+                        .and_then(|qid| dependencies.get(&qid)?.description())
+                        .unwrap_or_default(),
+                    highlight: entity.is_highlight(),
+                })
+                .unwrap();
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_write_wikidata_query_csv_matches_eager_loading() {
+        let (query, sledcache) = make_query_and_sledcache();
+        for limit in [None, Some(0), Some(1), Some(2), Some(10)] {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            write_wikidata_query_csv(&query, &sledcache, &mut writer, limit).unwrap();
+            let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+            assert_eq!(
+                csv,
+                write_csv_eagerly(&query, &sledcache, limit),
+                "{limit:?}"
+            );
+        }
+    }
+
+    /// Returns a painting like `make_painting()`, but with the given P170
+    /// statements instead of a single creator.
+    fn make_painting_with_creators(qid: u64, creator_statements: &[&str]) -> String {
+        let mut painting: serde_json::Value =
+            serde_json::from_str(&make_painting(qid, 0, &[], None)).unwrap();
+        painting["claims"]["P170"] =
+            serde_json::from_str(&format!("[{}]", creator_statements.join(","))).unwrap();
+        painting.to_string()
+    }
+
+    #[test]
+    fn test_write_wikidata_query_csv_handles_multiple_and_anonymous_creators() {
+        let sledcache = sled::Config::new().temporary(true).open().unwrap();
+        let creator = |qid: u64| {
+            format!(
+                r#"{{"mainsnak":{{"snaktype":"value","datavalue":{{"value":{{"numeric-id":{qid}}},"type":"wikibase-entityid"}}}}}}"#
+            )
+        };
+        let anonymous = r#"{"mainsnak":{"snaktype":"somevalue","property":"P170"}}"#;
+        let entities = [
+            (
+                1,
+                make_painting_with_creators(1, &[&creator(10), &creator(11)]),
+            ),
+            (2, make_painting_with_creators(2, &[anonymous])),
+            (10, make_dependency(10)),
+            (11, make_dependency(11)),
+        ];
+        for (qid, json) in entities {
+            sledcache
+                .insert(u64::to_be_bytes(qid), json.as_bytes())
+                .unwrap();
+        }
+        let query = PreparedQuery {
+            dumpfile: "boop.json.bz2".into(),
+            qids: vec![1, 2],
+            dependency_qids: vec![10, 11],
+            source: WikidataSource::Dump,
+        };
+        assert_eq!(
+            get_referenced_dependency_qids(&sledcache, &[1], &query.dependency_qids).unwrap(),
+            vec![10, 11]
+        );
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        write_wikidata_query_csv(&query, &sledcache, &mut writer, None).unwrap();
+        let csv = writer.into_inner().unwrap();
+        let objects =
+            iter_wikidata_objects(csv::Reader::from_reader(csv.as_slice()), Default::default())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+        assert_eq!(objects[0].artist, "Thing 10, Thing 11");
+        assert_eq!(objects[0].artist_qid, Some(10));
+        assert_eq!(objects[1].artist, "Unknown artist");
+        assert_eq!(objects[1].artist_qid, None);
+    }
+
+    #[test]
+    fn test_get_referenced_dependency_qids_works() {
+        let (query, sledcache) = make_query_and_sledcache();
+        assert_eq!(
+            get_referenced_dependency_qids(&sledcache, &[1], &query.dependency_qids).unwrap(),
+            vec![10, 20, 21, 30]
+        );
+        assert_eq!(
+            get_referenced_dependency_qids(&sledcache, &[2], &query.dependency_qids).unwrap(),
+            vec![20]
+        );
+    }
+
+    #[test]
+    fn test_prepare_and_execute_from_api_works_without_dumpfile() {
+        let root_dir: PathBuf =
+            std::env::temp_dir().join(format!("wikidata-api-query-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root_dir);
+        std::fs::create_dir_all(&root_dir).unwrap();
+        let cache = GalleryCache::new_offline(root_dir.join("cache"));
+        let fixtures_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("test_data")
+            .join("wikidata_api");
+        for (qids, fixture) in [
+            (vec![1, 2, 3], "paintings.json"),
+            (vec![10, 11, 20, 21, 30], "dependencies.json"),
+        ] {
+            let cached_path = cache.get_cached_path(wbgetentities_cache_filename(&qids));
+            std::fs::create_dir_all(cached_path.parent().unwrap()).unwrap();
+            std::fs::copy(fixtures_dir.join(fixture), cached_path).unwrap();
+        }
+        let dumpfile = root_dir.join("latest-all.json.gz");
+        let query_path = root_dir.join("query.json");
+        let mut api = WikidataApiFetcher::new(&cache, 1000.0).unwrap();
+        prepare_wikidata_query(
+            query_path.clone(),
+            dumpfile.clone(),
+            vec![3, 2, 1],
+            None,
+            false,
+            Some(&mut api),
+        )
+        .unwrap();
+        assert!(!dumpfile.exists());
+
+        let query = PreparedQuery::from_path(query_path.clone()).unwrap();
+        assert_eq!(query.source, WikidataSource::Api);
+        assert_eq!(query.dumpfile, dumpfile);
+        assert_eq!(query.qids, vec![1]);
+        assert_eq!(query.dependency_qids, vec![10, 20, 21, 30]);
+
+        let csv_path = root_dir.join("WikidataObjects.csv");
+        execute_wikidata_query(query_path, csv_path.clone(), None).unwrap();
+        let objects = iter_wikidata_objects(
+            csv::Reader::from_path(csv_path).unwrap(),
+            Default::default(),
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        assert_eq!(objects.len(), 1);
+        let object = &objects[0];
+        assert_eq!(object.object_id, ArtObjectId::Wikidata(1));
+        assert_eq!(object.title, "Wheat Field with Cypresses");
+        assert_eq!(object.artist, "Vincent van Gogh");
+        assert_eq!(object.artist_qid, Some(10));
+        assert_eq!(object.medium, "oil paint, canvas");
+        assert_eq!(object.collection, "Metropolitan Museum of Art");
+        assert_eq!(object.filename, "Wheat Field with Cypresses.jpg");
+        assert!((object.width - 0.934).abs() < 1e-9);
+        assert!((object.height - 0.73).abs() < 1e-9);
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn test_prepared_query_without_source_is_from_dump() {
+        let query: PreparedQuery = serde_json::from_str(
+            r#"{"dumpfile": "boop.json.gz", "qids": [1], "dependency_qids": []}"#,
+        )
+        .unwrap();
+        assert_eq!(query.source, WikidataSource::Dump);
+    }
+}
@@ -0,0 +1,359 @@
+use super::file_table::FileTable;
+use super::index_file::{index_path_for_dumpfile, read_serialized_qid, IndexFileReader};
+use super::sledcache::{parse_wikidata_entity, sledcache_path_for_dumpfile};
+use anyhow::Result;
+use gallery::wikidata::{WikidataEntity, ANONYMOUS_CREATOR_ID};
+use std::{fmt::Display, path::PathBuf};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(super) enum EntitySource {
+    Sledcache,
+    Index,
+}
+
+impl Display for EntitySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntitySource::Sledcache => write!(f, "sledcache"),
+            EntitySource::Index => write!(f, "dump"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) enum EntityLookup {
+    /// The entity isn't in the sledcache or the dump's index.
+    NotInIndex,
+    ParseFailed {
+        source: EntitySource,
+        json: String,
+        error: anyhow::Error,
+    },
+    Parsed {
+        source: EntitySource,
+        json: String,
+        entity: Box<WikidataEntity>,
+    },
+}
+
+/// Looks up a single entity, preferring the sledcache (if given) over the dump.
+pub(super) fn look_up_entity(
+    dumpfile_path: &PathBuf,
+    sledcache: Option<&sled::Db>,
+    qid: u64,
+) -> Result<EntityLookup> {
+    let cached_json = match sledcache {
+        Some(sledcache) => match sledcache.get(qid.to_be_bytes())? {
+            Some(value) => Some(String::from_utf8(value.as_ref().to_vec())?),
+            None => None,
+        },
+        None => None,
+    };
+    let (source, json) = match cached_json {
+        Some(json) => (EntitySource::Sledcache, json),
+        None => {
+            let mut index_db = IndexFileReader::new(index_path_for_dumpfile(dumpfile_path))?;
+            let Some(value) = index_db.read(qid)? else {
+                return Ok(EntityLookup::NotInIndex);
+            };
+            // See `get_qid_index_file_mapping()` for why this means the QID was never indexed.
+            if value.file_and_gzip_member_offset.get() == 0 {
+                return Ok(EntityLookup::NotInIndex);
+            }
+            let file_paths = FileTable::load(dumpfile_path)?.paths(dumpfile_path);
+            (
+                EntitySource::Index,
+                read_serialized_qid(&file_paths, qid, &value)?,
+            )
+        }
+    };
+    Ok(match parse_wikidata_entity(qid, &json) {
+        Ok(entity) => EntityLookup::Parsed {
+            source,
+            json,
+            entity: Box::new(entity),
+        },
+        Err(error) => EntityLookup::ParseFailed {
+            source,
+            json,
+            error,
+        },
+    })
+}
+
+/// Returns the names of the fields that `prepare_wikidata_query()` requires
+/// but the entity doesn't have.
+pub(super) fn missing_required_fields(entity: &WikidataEntity) -> Vec<&'static str> {
+    let mut missing = vec![];
+    if entity.image_filename().is_none() {
+        missing.push("image");
+    }
+    if entity.dimensions_in_cm().is_none() {
+        missing.push("dimensions");
+    }
+    missing
+}
+
+fn cached_label(sledcache: Option<&sled::Db>, qid: u64) -> Option<String> {
+    let value = sledcache?.get(qid.to_be_bytes()).ok()??;
+    let entity: WikidataEntity = serde_json::from_slice(value.as_ref()).ok()?;
+    entity.label().map(|label| label.to_string())
+}
+
+fn describe_qid(sledcache: Option<&sled::Db>, qid: u64) -> String {
+    match cached_label(sledcache, qid) {
+        Some(label) => format!("Q{qid} ({label})"),
+        None => format!("Q{qid}"),
+    }
+}
+
+fn print_entity_summary(entity: &WikidataEntity, sledcache: Option<&sled::Db>) {
+    let none = || "none".to_string();
+    println!("Label: {}", entity.label().unwrap_or("none"));
+    println!("Description: {}", entity.description().unwrap_or("none"));
+    println!(
+        "Image: {}",
+        entity.image_filename().cloned().unwrap_or_else(none)
+    );
+    println!(
+        "Dimensions: {}",
+        entity
+            .dimensions_in_cm()
+            .map(|(width, height)| format!("{width} x {height} cm"))
+            .unwrap_or_else(none)
+    );
+    println!("Inception: {}", entity.inception().unwrap_or_else(none));
+    let creators: Vec<String> = entity
+        .creator_ids()
+        .into_iter()
+        .map(|qid| {
+            if qid == ANONYMOUS_CREATOR_ID {
+                "anonymous".to_string()
+            } else {
+                describe_qid(sledcache, qid)
+            }
+        })
+        .collect();
+    println!(
+        "Creators: {}",
+        if creators.is_empty() {
+            none()
+        } else {
+            creators.join(", ")
+        }
+    );
+    println!(
+        "Collection: {}",
+        entity
+            .collection_id()
+            .map(|qid| describe_qid(sledcache, qid))
+            .unwrap_or_else(none)
+    );
+    let materials: Vec<String> = entity
+        .material_ids()
+        .into_iter()
+        .map(|qid| describe_qid(sledcache, qid))
+        .collect();
+    println!(
+        "Materials: {}",
+        if materials.is_empty() {
+            none()
+        } else {
+            materials.join(", ")
+        }
+    );
+}
+
+fn print_json(json: &str) {
+    match serde_json::from_str::<serde_json::Value>(json) {
+        Ok(value) => println!(
+            "{}",
+            serde_json::to_string_pretty(&value).unwrap_or(json.to_string())
+        ),
+        Err(_) => println!("{json}"),
+    }
+}
+
+/// Prints everything we know about a single entity in the dumpfile, to help
+/// figure out why it didn't make it into (or looks wrong in) the gallery.
+pub fn show_wikidata_entity(dumpfile_path: PathBuf, qid: u64, raw: bool) -> Result<()> {
+    let sledcache_path = sledcache_path_for_dumpfile(&dumpfile_path);
+    // Don't use `sled::open` unless the sledcache exists, since it'd create it.
+    let sledcache = if sledcache_path.exists() {
+        Some(sled::open(&sledcache_path)?)
+    } else {
+        None
+    };
+    match look_up_entity(&dumpfile_path, sledcache.as_ref(), qid)? {
+        EntityLookup::NotInIndex => {
+            println!("Q{qid} is not in the index or sledcache.");
+        }
+        EntityLookup::ParseFailed {
+            source,
+            json,
+            error,
+        } => {
+            println!("Q{qid} is in the {source}, but its JSON failed to parse: {error}");
+            if raw {
+                print_json(&json);
+            }
+        }
+        EntityLookup::Parsed {
+            source,
+            json,
+            entity,
+        } => {
+            println!("Q{qid} was read from the {source}.");
+            if raw {
+                print_json(&json);
+            }
+            print_entity_summary(&entity, sledcache.as_ref());
+            let missing = missing_required_fields(&entity);
+            if missing.is_empty() {
+                println!("Q{qid} has all required fields.");
+            } else {
+                println!(
+                    "Q{qid} parsed, but is missing required field(s): {}",
+                    missing.join(", ")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::PathBuf};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use crate::wikidata_dump::index_file::{index_wikidata_dump_with_options, IndexFileOptions};
+
+    use super::{
+        cached_label, look_up_entity, missing_required_fields, EntityLookup, EntitySource,
+    };
+
+    const FUNKY_PAINTING: &str = r#"{"type":"item","id":"Q7","labels":{"en":{"language":"en","value":"Funky Painting"}},"claims":{"P18":[{"mainsnak":{"datavalue":{"value":"Funky.jpg","type":"string"}}}],"P2048":[{"mainsnak":{"datavalue":{"value":{"amount":"+50","unit":"http://www.wikidata.org/entity/Q174728"},"type":"quantity"}}}],"P2049":[{"mainsnak":{"datavalue":{"value":{"amount":"+100","unit":"http://www.wikidata.org/entity/Q174728"},"type":"quantity"}}}],"P170":[{"mainsnak":{"datavalue":{"value":{"numeric-id":1,"id":"Q1"},"type":"wikibase-entityid"}}}]}}"#;
+
+    const DIMENSIONLESS_PAINTING: &str = r#"{"type":"item","id":"Q5","labels":{"en":{"language":"en","value":"Flat Painting"}},"claims":{"P18":[{"mainsnak":{"datavalue":{"value":"Flat.jpg","type":"string"}}}]}}"#;
+
+    /// This doesn't have any claims, so it won't deserialize.
+    const CLAIMLESS_ENTITY: &str = r#"{"type":"item","id":"Q6","labels":{}}"#;
+
+    const BOOP_JONES: &str = r#"{"type":"item","id":"Q1","labels":{"en":{"language":"en","value":"Boop Jones"}},"claims":{}}"#;
+
+    fn write_gzip_members(path: &PathBuf, members: Vec<String>) {
+        let mut file = std::fs::File::create(path).unwrap();
+        for member in members {
+            let mut encoder = GzEncoder::new(&mut file, Compression::default());
+            encoder.write_all(member.as_bytes()).unwrap();
+            encoder.finish().unwrap();
+        }
+    }
+
+    /// Creates a tiny indexed dump, in a directory that the caller should remove.
+    fn create_dumpfile(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "gallery-show-entity-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dumpfile_path = dir.join("dump.json.gz");
+        write_gzip_members(
+            &dumpfile_path,
+            vec![
+                "[\n".into(),
+                format!("{DIMENSIONLESS_PAINTING},\n{CLAIMLESS_ENTITY}"),
+                format!(",\n{FUNKY_PAINTING}"),
+                "\n]\n".into(),
+            ],
+        );
+        index_wikidata_dump_with_options(
+            dumpfile_path.clone(),
+            None,
+            IndexFileOptions {
+                capacity: 100,
+                sparse: true,
+            },
+        )
+        .unwrap();
+        (dir, dumpfile_path)
+    }
+
+    #[test]
+    fn test_look_up_entity_reports_each_outcome() {
+        let (dir, dumpfile_path) = create_dumpfile("outcomes");
+
+        let lookup = look_up_entity(&dumpfile_path, None, 99).unwrap();
+        assert!(matches!(lookup, EntityLookup::NotInIndex), "{lookup:?}");
+
+        let lookup = look_up_entity(&dumpfile_path, None, 6).unwrap();
+        let EntityLookup::ParseFailed { source, json, .. } = lookup else {
+            panic!("expected Q6 to fail to parse, got {lookup:?}");
+        };
+        assert_eq!(source, EntitySource::Index);
+        assert_eq!(json, CLAIMLESS_ENTITY);
+
+        let lookup = look_up_entity(&dumpfile_path, None, 5).unwrap();
+        let EntityLookup::Parsed { entity, .. } = lookup else {
+            panic!("expected Q5 to parse, got {lookup:?}");
+        };
+        assert_eq!(entity.label(), Some("Flat Painting"));
+        assert_eq!(missing_required_fields(&entity), vec!["dimensions"]);
+
+        let lookup = look_up_entity(&dumpfile_path, None, 7).unwrap();
+        let EntityLookup::Parsed { entity, json, .. } = lookup else {
+            panic!("expected Q7 to parse, got {lookup:?}");
+        };
+        assert_eq!(json, FUNKY_PAINTING);
+        assert_eq!(entity.dimensions_in_cm(), Some((100.0, 50.0)));
+        assert_eq!(entity.creator_id(), Some(1));
+        assert!(missing_required_fields(&entity).is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_look_up_entity_prefers_sledcache() {
+        let (dir, dumpfile_path) = create_dumpfile("sledcache");
+        let sledcache = sled::open(dir.join("test.sledcache")).unwrap();
+        sledcache
+            .insert(1u64.to_be_bytes(), BOOP_JONES.as_bytes())
+            .unwrap();
+
+        // Q1 isn't in the dump at all, only the sledcache.
+        let lookup = look_up_entity(&dumpfile_path, Some(&sledcache), 1).unwrap();
+        let EntityLookup::Parsed { source, entity, .. } = lookup else {
+            panic!("expected Q1 to parse, got {lookup:?}");
+        };
+        assert_eq!(source, EntitySource::Sledcache);
+        assert_eq!(
+            missing_required_fields(&entity),
+            vec!["image", "dimensions"]
+        );
+
+        let lookup = look_up_entity(&dumpfile_path, Some(&sledcache), 7).unwrap();
+        assert!(
+            matches!(
+                lookup,
+                EntityLookup::Parsed {
+                    source: EntitySource::Index,
+                    ..
+                }
+            ),
+            "{lookup:?}"
+        );
+
+        assert_eq!(
+            cached_label(Some(&sledcache), 1),
+            Some("Boop Jones".to_string())
+        );
+        assert_eq!(cached_label(Some(&sledcache), 7), None);
+        assert_eq!(cached_label(None, 1), None);
+
+        drop(sledcache);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,10 @@
+use super::api::WikidataApiFetcher;
+use super::file_table::FileTable;
 use super::index_file::{get_qid_index_file_mapping, index_path_for_dumpfile, IndexFileReader};
 use anyhow::{anyhow, Result};
 use gallery::wikidata::WikidataEntity;
+use log::warn;
+use std::collections::HashSet;
 use std::path::PathBuf;
 
 use crate::wikidata_dump::index_file::par_iter_serialized_qids;
@@ -9,6 +13,25 @@ pub fn sledcache_path_for_dumpfile(dumpfile_path: &PathBuf) -> PathBuf {
     dumpfile_path.with_extension("sledcache")
 }
 
+/// Remove the given entities from the dumpfile's entity cache, e.g. because
+/// its index now points at newer versions of them. Returns the number of
+/// entities that were actually removed.
+pub fn evict_from_sledcache(dumpfile_path: &PathBuf, qids: &[u64]) -> Result<usize> {
+    let sledcache_path = sledcache_path_for_dumpfile(dumpfile_path);
+    if !sledcache_path.exists() {
+        return Ok(0);
+    }
+    let sledcache = sled::open(&sledcache_path)?;
+    let mut evicted = 0;
+    for qid in qids {
+        if sledcache.remove(qid.to_be_bytes())?.is_some() {
+            evicted += 1;
+        }
+    }
+    sledcache.flush()?;
+    Ok(evicted)
+}
+
 type EntityIterator = dyn Iterator<Item = Result<WikidataEntity>>;
 
 fn iter_and_cache_serialized_qids_without_progress_info(
@@ -18,6 +41,7 @@ fn iter_and_cache_serialized_qids_without_progress_info(
 ) -> Result<Box<EntityIterator>> {
     let index_path = index_path_for_dumpfile(&dumpfile_path);
     let mut index_db = IndexFileReader::new(index_path)?;
+    let file_paths = FileTable::load(&dumpfile_path)?.paths(&dumpfile_path);
     let sledcache_path = sledcache_path_for_dumpfile(&dumpfile_path);
     let sledcache = sled::open(&sledcache_path)?;
     let read_sledcache = sledcache.clone();
@@ -54,7 +78,7 @@ fn iter_and_cache_serialized_qids_without_progress_info(
         qid_index_file_mapping.gzip_members()
     );
     let uncached_iterator: Box<EntityIterator> = Box::new(
-        par_iter_serialized_qids(dumpfile_path, qid_index_file_mapping).map(move |result| {
+        par_iter_serialized_qids(file_paths, qid_index_file_mapping).map(move |result| {
             match result {
                 Ok((qid, value)) => match parse_wikidata_entity(qid, &value) {
                     Ok(entity) => {
@@ -79,6 +103,24 @@ pub struct CachedEntityInfo {
     pub percent_done: f64,
 }
 
+/// Adds progress information to an iterator over the given number of entities.
+fn with_progress_info(
+    iterator: impl Iterator<Item = Result<WikidataEntity>>,
+    total_qids: usize,
+) -> impl Iterator<Item = Result<CachedEntityInfo>> {
+    let mut count = 0;
+    iterator.map(move |result| {
+        count += 1;
+        let percent_done = (count as f64) / (total_qids as f64) * 100.0;
+        let entity = result?;
+        Ok(CachedEntityInfo {
+            entity,
+            count,
+            percent_done,
+        })
+    })
+}
+
 /// Iterate through the given entities in the dumpfile, caching them if they are
 /// not already cached.
 ///
@@ -90,19 +132,44 @@ pub fn iter_and_cache_entities(
     warnings: bool,
 ) -> Result<impl Iterator<Item = Result<CachedEntityInfo>>> {
     let total_qids = qids.len();
-    let mut count = 0;
     let iterator =
         iter_and_cache_serialized_qids_without_progress_info(dumpfile_path, qids, warnings)?;
-    Ok(iterator.map(move |result| {
-        count += 1;
-        let percent_done = (count as f64) / (total_qids as f64) * 100.0;
-        let entity = result?;
-        Ok(CachedEntityInfo {
-            entity,
-            count,
-            percent_done,
-        })
-    }))
+    Ok(with_progress_info(iterator, total_qids))
+}
+
+/// Like `iter_and_cache_entities()`, but any entities that aren't already
+/// cached are fetched from the Wikidata API rather than the dumpfile, which
+/// doesn't need to exist. They're still cached alongside it, though, so the
+/// resulting query can be executed just like one prepared from the dumpfile.
+pub fn iter_and_cache_entities_from_api(
+    fetcher: &mut WikidataApiFetcher,
+    dumpfile_path: PathBuf,
+    qids: Vec<u64>,
+) -> Result<impl Iterator<Item = Result<CachedEntityInfo>>> {
+    let total_qids = qids.len();
+    let sledcache = sled::open(sledcache_path_for_dumpfile(&dumpfile_path))?;
+    let (cached_qids, uncached_qids): (Vec<u64>, Vec<u64>) = qids
+        .into_iter()
+        .partition(|qid| sledcache.contains_key(qid.to_be_bytes()).unwrap_or(false));
+    let mut entities = Vec::with_capacity(total_qids);
+    for qid in cached_qids {
+        let value = sledcache
+            .get(qid.to_be_bytes())?
+            .ok_or_else(|| anyhow!("sledcache does not contain a key it claimed it has"))?;
+        entities.push(parse_wikidata_entity(qid, std::str::from_utf8(&value)?));
+    }
+    let requested_qids: HashSet<u64> = uncached_qids.iter().copied().collect();
+    for (qid, value) in fetcher.fetch(uncached_qids)? {
+        if !requested_qids.contains(&qid) {
+            warn!("Ignoring Q{qid}, which a requested QID redirects to.");
+            continue;
+        }
+        let entity = parse_wikidata_entity(qid, &value)?;
+        sledcache.insert(qid.to_be_bytes(), value.as_bytes())?;
+        entities.push(Ok(entity));
+    }
+    sledcache.flush()?;
+    Ok(with_progress_info(entities.into_iter(), total_qids))
 }
 
 fn parse_wikidata_entity_from_result(result: Result<(u64, String)>) -> Result<WikidataEntity> {
@@ -110,7 +177,7 @@ fn parse_wikidata_entity_from_result(result: Result<(u64, String)>) -> Result<Wi
     parse_wikidata_entity(qid, &qid_json)
 }
 
-fn parse_wikidata_entity(qid: u64, qid_json: &str) -> Result<WikidataEntity> {
+pub(super) fn parse_wikidata_entity(qid: u64, qid_json: &str) -> Result<WikidataEntity> {
     let parse_result: Result<WikidataEntity, _> = serde_json::from_str(&qid_json);
     match parse_result {
         Ok(entity) => {
@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    frame_style::{frame_style, FrameStyle},
+    gallery_db::ArtObjectRecord,
+    object_date::parse_year,
+};
+
 /// Internally we represent art object IDs as an enum, but Godot and our DB
 /// use i64s. This enum includes utilities to help us translate between the two.
 ///
@@ -28,7 +34,14 @@ impl ArtObjectId {
 
     pub fn to_raw_i64(&self) -> i64 {
         match self {
-            ArtObjectId::Met(id) => *id,
+            ArtObjectId::Met(id) => {
+                // Otherwise it'd collide with a wikidata QID.
+                debug_assert!(
+                    id & WIKIDATA_BIT == 0,
+                    "Met ID {id} has the wikidata bit set"
+                );
+                *id
+            }
             ArtObjectId::Wikidata(qid) => *qid | WIKIDATA_BIT,
         }
     }
@@ -42,9 +55,157 @@ impl ArtObjectId {
     }
 }
 
+/// An art object along with its position on a wall, in the form that gets sent
+/// to the game (and shown by tooling).
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct PlacedArtObject {
+    pub object_id: ArtObjectId,
+    pub artist: String,
+    pub medium: String,
+    pub title: String,
+    pub date: String,
+    pub width: f64,
+    pub height: f64,
+    pub x: f64,
+    pub y: f64,
+    pub collection: String,
+    pub artist_qid: Option<i64>,
+    #[serde(default)]
+    pub highlight: bool,
+    /// See `LayoutRecord::rotated`. Note that `width` and `height` are always
+    /// the art object's own dimensions, regardless of how it's hung.
+    #[serde(default)]
+    pub rotated: bool,
+    #[serde(default)]
+    pub frame_style: FrameStyle,
+    /// What kind of work this is, e.g. "Paintings", see
+    /// `ArtObjectRecord::classification`.
+    #[serde(default)]
+    pub classification: String,
+}
+
+impl From<(ArtObjectRecord, (f64, f64), bool)> for PlacedArtObject {
+    fn from((object, (x, y), rotated): (ArtObjectRecord, (f64, f64), bool)) -> Self {
+        // This is cheap enough to work out whenever art objects are queried,
+        // so it doesn't need to be stored in the DB.
+        let frame_style = frame_style(
+            object.medium_category,
+            parse_year(&object.object_date),
+            &object.collection,
+        );
+        PlacedArtObject {
+            object_id: object.object_id,
+            title: object.title,
+            date: object.object_date,
+            width: object.width,
+            height: object.height,
+            artist: object.artist,
+            medium: object.medium,
+            collection: object.collection,
+            artist_qid: object.artist_qid,
+            highlight: object.highlight,
+            x,
+            y,
+            rotated,
+            frame_style,
+            classification: object.classification,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::art_object::ArtObjectId;
+    use crate::{
+        art_object::{ArtObjectId, PlacedArtObject},
+        frame_style::FrameStyle,
+        gallery_db::ArtObjectRecord,
+        medium::MediumCategory,
+    };
+
+    fn make_record() -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id: ArtObjectId::Met(1),
+            object_date: "1872".into(),
+            culture: "French".into(),
+            artist: "Claude Monet".into(),
+            artist_role: String::default(),
+            title: "Impression, Soleil Levant".into(),
+            medium: "Oil on canvas".into(),
+            medium_category: MediumCategory::Oil,
+            width: 0.63,
+            height: 0.48,
+            fallback_wikidata_qid: Some(5),
+            filename: "".into(),
+            collection: "Musée Marmottan Monet".into(),
+            artist_qid: Some(296),
+            highlight: true,
+            tags: "Landscapes|Boats".into(),
+            classification: "Paintings".into(),
+            source_gallery: Some("818".into()),
+        }
+    }
+
+    #[test]
+    fn test_placed_art_object_from_record_works() {
+        assert_eq!(
+            PlacedArtObject::from((make_record(), (1.5, 2.0), false)),
+            PlacedArtObject {
+                object_id: ArtObjectId::Met(1),
+                artist: "Claude Monet".into(),
+                medium: "Oil on canvas".into(),
+                title: "Impression, Soleil Levant".into(),
+                date: "1872".into(),
+                width: 0.63,
+                height: 0.48,
+                x: 1.5,
+                y: 2.0,
+                collection: "Musée Marmottan Monet".into(),
+                artist_qid: Some(296),
+                highlight: true,
+                rotated: false,
+                frame_style: FrameStyle::Default,
+                classification: "Paintings".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_placed_art_object_json_shape_is_stable() {
+        let placed = PlacedArtObject::from((make_record(), (1.5, 2.0), false));
+        assert_eq!(
+            serde_json::to_value(&placed).unwrap(),
+            serde_json::json!({
+                "object_id": {"Met": 1},
+                "artist": "Claude Monet",
+                "medium": "Oil on canvas",
+                "title": "Impression, Soleil Levant",
+                "date": "1872",
+                "width": 0.63,
+                "height": 0.48,
+                "x": 1.5,
+                "y": 2.0,
+                "collection": "Musée Marmottan Monet",
+                "artist_qid": 296,
+                "highlight": true,
+                "rotated": false,
+                "frame_style": "default",
+                "classification": "Paintings"
+            })
+        );
+        // Older serializations didn't include highlights, rotation, frames or
+        // classifications.
+        let mut value = serde_json::to_value(&placed).unwrap();
+        value.as_object_mut().unwrap().remove("highlight");
+        value.as_object_mut().unwrap().remove("rotated");
+        value.as_object_mut().unwrap().remove("frame_style");
+        value.as_object_mut().unwrap().remove("classification");
+        let round_tripped: PlacedArtObject = serde_json::from_value(value).unwrap();
+        assert!(!round_tripped.highlight);
+        assert!(!round_tripped.rotated);
+        assert_eq!(round_tripped.frame_style, FrameStyle::Default);
+        assert_eq!(round_tripped.classification, "");
+        assert_eq!(round_tripped.title, placed.title);
+    }
 
     #[test]
     fn test_it_converts_from_raw_i64() {
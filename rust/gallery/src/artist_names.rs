@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+/// Role annotations that sometimes prefix an artist's name, e.g. "Workshop of
+/// Rubens", or follow it in parentheses, e.g. "Rubens (workshop)", along with
+/// the canonical role they're stored as. These are checked in order, so longer
+/// annotations need to come before any annotations they start with.
+const ARTIST_ROLES: [(&str, &str); 19] = [
+    ("attributed to", "attributed to"),
+    ("attributed", "attributed to"),
+    ("workshop of", "workshop of"),
+    ("workshop", "workshop of"),
+    ("and workshop", "and workshop"),
+    ("studio of", "studio of"),
+    ("studio", "studio of"),
+    ("circle of", "circle of"),
+    ("followers of", "follower of"),
+    ("follower of", "follower of"),
+    ("school of", "school of"),
+    ("style of", "style of"),
+    ("manner of", "manner of"),
+    ("copy after", "copy after"),
+    ("after", "after"),
+    ("pupil of", "pupil of"),
+    ("imitator of", "imitator of"),
+    ("possibly by", "possibly by"),
+    ("possibly", "possibly by"),
+];
+
+/// Name suffixes that follow a comma without meaning the name is in
+/// "Last, First" order, e.g. "John Smith, Jr.".
+const NAME_SUFFIXES: [&str; 7] = ["jr", "jr.", "sr", "sr.", "ii", "iii", "iv"];
+
+/// "Last, First" names with more words than this on either side of the comma
+/// are probably something else, e.g. a description, so they aren't reordered.
+const MAX_WORDS_PER_NAME_PART: usize = 3;
+
+/// Multiple artists are separated by this in the Met's CSV, e.g.
+/// "Rembrandt|Ferdinand Bol".
+const ARTIST_SEPARATOR: char = '|';
+
+/// An artist's name with its role annotation, if any, split off.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct NormalizedArtistName {
+    /// The canonical form of the name, e.g. "Vincent van Gogh".
+    pub name: String,
+    /// The canonical form of the role annotation, e.g. "workshop of", or an
+    /// empty string if the name didn't have one.
+    pub role: String,
+}
+
+/// Normalizes an artist's name from any of our sources so that the same artist
+/// is spelled the same way everywhere, e.g. "Gogh, Vincent van" and
+/// "Vincent  van Gogh" are both "Vincent van Gogh", and "Workshop of Peter
+/// Paul Rubens" is "Peter Paul Rubens" with the role "workshop of".
+///
+/// Diacritics are left alone, since they're part of how the name is spelled;
+/// use `artist_match_key()` to compare names regardless of them.
+pub fn normalize_artist_name(name: &str) -> NormalizedArtistName {
+    let mut role = String::new();
+    let names: Vec<String> = name
+        .split(ARTIST_SEPARATOR)
+        .map(|part| {
+            let (name, part_role) = split_role(&collapse_whitespace(part));
+            // Only the first artist's role is kept, since it's the one that's
+            // usually shown.
+            if role.is_empty() {
+                if let Some(part_role) = part_role {
+                    role = part_role.to_string();
+                }
+            }
+            reorder_last_first(&name)
+        })
+        .filter(|name| !name.is_empty())
+        .collect();
+    NormalizedArtistName {
+        name: names.join(&ARTIST_SEPARATOR.to_string()),
+        role,
+    }
+}
+
+/// Returns a key that's the same for names that only differ in diacritics,
+/// case, punctuation or whitespace, e.g. "Jean-Léon Gérôme" and "jean leon
+/// gerome", so they can be matched up.
+pub fn artist_match_key(name: &str) -> String {
+    let mut folded = String::with_capacity(name.len());
+    for c in name.chars() {
+        match fold_diacritic(c) {
+            Some(replacement) => folded.push_str(replacement),
+            None if c.is_alphanumeric() || c == ARTIST_SEPARATOR => folded.extend(c.to_lowercase()),
+            None => folded.push(' '),
+        }
+    }
+    collapse_whitespace(&folded)
+}
+
+/// Picks the spelling to use for an artist out of the given spellings, which
+/// should all have the same `artist_match_key()`, and how many art objects use
+/// each of them. The most common spelling wins, and ties go to the spelling
+/// with the most diacritics, since removing them is more common than adding
+/// them, and then to the first spelling alphabetically.
+pub fn choose_artist_spelling<'a>(spellings: &HashMap<&'a str, usize>) -> Option<&'a str> {
+    spellings
+        .iter()
+        .max_by(|(a, a_count), (b, b_count)| {
+            let non_ascii = |name: &str| name.chars().filter(|c| !c.is_ascii()).count();
+            a_count
+                .cmp(b_count)
+                .then_with(|| non_ascii(a).cmp(&non_ascii(b)))
+                .then_with(|| b.cmp(a))
+        })
+        .map(|(spelling, _)| *spelling)
+}
+
+fn collapse_whitespace(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn starts_with_ignore_ascii_case(value: &str, prefix: &str) -> bool {
+    value
+        .get(..prefix.len())
+        .map_or(false, |start| start.eq_ignore_ascii_case(prefix))
+}
+
+/// Splits a role annotation off the given whitespace-collapsed name, e.g.
+/// "Attributed to Rembrandt" or "Rembrandt (attributed to)".
+fn split_role(name: &str) -> (String, Option<&'static str>) {
+    for (annotation, role) in ARTIST_ROLES {
+        if starts_with_ignore_ascii_case(name, annotation)
+            && name[annotation.len()..].starts_with(' ')
+        {
+            let rest = name[annotation.len()..].trim();
+            if !rest.is_empty() {
+                return (rest.to_string(), Some(role));
+            }
+        }
+    }
+    if let Some(without_paren) = name.strip_suffix(')') {
+        if let Some(open) = without_paren.rfind('(') {
+            let annotation = without_paren[open + 1..].trim();
+            let rest = without_paren[..open].trim();
+            if !rest.is_empty() {
+                for (known_annotation, role) in ARTIST_ROLES {
+                    if annotation.eq_ignore_ascii_case(known_annotation) {
+                        return (rest.to_string(), Some(role));
+                    }
+                }
+            }
+        }
+    }
+    (name.to_string(), None)
+}
+
+/// Turns "Last, First" into "First Last", e.g. "Gogh, Vincent van" into
+/// "Vincent van Gogh", leaving anything that doesn't look like that alone.
+fn reorder_last_first(name: &str) -> String {
+    let mut parts = name.split(',');
+    let (Some(last), Some(first), None) = (parts.next(), parts.next(), parts.next()) else {
+        return name.to_string();
+    };
+    let (last, first) = (last.trim(), first.trim());
+    let word_count = |part: &str| part.split_whitespace().count();
+    let looks_like_name = |part: &str| {
+        (1..=MAX_WORDS_PER_NAME_PART).contains(&word_count(part))
+            && !part.contains(|c: char| c.is_ascii_digit() || c == '(' || c == ')')
+    };
+    if !looks_like_name(last)
+        || !looks_like_name(first)
+        || NAME_SUFFIXES.contains(&first.to_lowercase().as_str())
+    {
+        return name.to_string();
+    }
+    format!("{first} {last}")
+}
+
+/// Returns the ASCII equivalent of the given letter with diacritics, if it has
+/// one. This only covers Latin letters, which is what almost all of the names
+/// in our collections use.
+fn fold_diacritic(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' | 'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å'
+        | 'Ā' | 'Ă' | 'Ą' => "a",
+        'æ' | 'Æ' => "ae",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' | 'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "c",
+        'ď' | 'đ' | 'ð' | 'Ď' | 'Đ' | 'Ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' | 'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ĕ'
+        | 'Ė' | 'Ę' | 'Ě' => "e",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' | 'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "g",
+        'ĥ' | 'ħ' | 'Ĥ' | 'Ħ' => "h",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' | 'Ì' | 'Í' | 'Î' | 'Ï' | 'Ĩ' | 'Ī'
+        | 'Ĭ' | 'Į' | 'İ' => "i",
+        'ĵ' | 'Ĵ' => "j",
+        'ķ' | 'Ķ' => "k",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' | 'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "l",
+        'ñ' | 'ń' | 'ņ' | 'ň' | 'Ñ' | 'Ń' | 'Ņ' | 'Ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' | 'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø'
+        | 'Ō' | 'Ŏ' | 'Ő' => "o",
+        'œ' | 'Œ' => "oe",
+        'ŕ' | 'ŗ' | 'ř' | 'Ŕ' | 'Ŗ' | 'Ř' => "r",
+        'ś' | 'ŝ' | 'ş' | 'š' | 'Ś' | 'Ŝ' | 'Ş' | 'Š' => "s",
+        'ß' => "ss",
+        'ţ' | 'ť' | 'ŧ' | 'Ţ' | 'Ť' | 'Ŧ' => "t",
+        'þ' | 'Þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' | 'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ũ'
+        | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "u",
+        'ŵ' | 'Ŵ' => "w",
+        'ý' | 'ÿ' | 'ŷ' | 'Ý' | 'Ÿ' | 'Ŷ' => "y",
+        'ź' | 'ż' | 'ž' | 'Ź' | 'Ż' | 'Ž' => "z",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{artist_match_key, choose_artist_spelling, normalize_artist_name};
+
+    #[test]
+    fn test_normalize_artist_name_works_with_real_names() {
+        // These are all based on names in the Met's and Wikidata's collections.
+        let names = [
+            ("Vincent van Gogh", "Vincent van Gogh", ""),
+            ("Gogh, Vincent van", "Vincent van Gogh", ""),
+            ("van Gogh, Vincent", "Vincent van Gogh", ""),
+            ("  Vincent   van\tGogh ", "Vincent van Gogh", ""),
+            ("Hokusai, Katsushika", "Katsushika Hokusai", ""),
+            ("Gérôme, Jean-Léon", "Jean-Léon Gérôme", ""),
+            ("Rembrandt", "Rembrandt", ""),
+            (
+                "Rembrandt (Rembrandt van Rijn)",
+                "Rembrandt (Rembrandt van Rijn)",
+                "",
+            ),
+            ("Rembrandt (attributed to)", "Rembrandt", "attributed to"),
+            ("Attributed to Rembrandt", "Rembrandt", "attributed to"),
+            ("attributed to  Rembrandt", "Rembrandt", "attributed to"),
+            (
+                "Workshop of Peter Paul Rubens",
+                "Peter Paul Rubens",
+                "workshop of",
+            ),
+            (
+                "Peter Paul Rubens (workshop)",
+                "Peter Paul Rubens",
+                "workshop of",
+            ),
+            (
+                "Rubens, Peter Paul (and workshop)",
+                "Peter Paul Rubens",
+                "and workshop",
+            ),
+            (
+                "Follower of Hieronymus Bosch",
+                "Hieronymus Bosch",
+                "follower of",
+            ),
+            ("Circle of Jan van Eyck", "Jan van Eyck", "circle of"),
+            ("Style of Claude Monet", "Claude Monet", "style of"),
+            ("After Raphael", "Raphael", "after"),
+            ("Copy after Titian", "Titian", "copy after"),
+            ("Possibly by El Greco", "El Greco", "possibly by"),
+            ("Afterglow Painter", "Afterglow Painter", ""),
+            ("Workshop", "Workshop", ""),
+            (
+                "Louis Comfort Tiffany, Jr.",
+                "Louis Comfort Tiffany, Jr.",
+                "",
+            ),
+            ("Smith, John, Jr.", "Smith, John, Jr.", ""),
+            (
+                "Master of the Saint Lucy Legend, Netherlandish, active ca. 1480",
+                "Master of the Saint Lucy Legend, Netherlandish, active ca. 1480",
+                "",
+            ),
+            ("Rembrandt|Bol, Ferdinand", "Rembrandt|Ferdinand Bol", ""),
+            (
+                "Workshop of Rembrandt|Ferdinand Bol",
+                "Rembrandt|Ferdinand Bol",
+                "workshop of",
+            ),
+            ("", "", ""),
+            ("   ", "", ""),
+        ];
+        for (name, expected_name, expected_role) in names {
+            let normalized = normalize_artist_name(name);
+            assert_eq!(
+                (normalized.name.as_str(), normalized.role.as_str()),
+                (expected_name, expected_role),
+                "normalizing {name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_artist_name_is_idempotent() {
+        for name in [
+            "Gogh, Vincent van",
+            "Workshop of Peter Paul Rubens",
+            "Rembrandt|Bol, Ferdinand",
+        ] {
+            let once = normalize_artist_name(name).name;
+            assert_eq!(normalize_artist_name(&once).name, once);
+        }
+    }
+
+    #[test]
+    fn test_artist_match_key_ignores_diacritics_case_and_punctuation() {
+        assert_eq!(artist_match_key("Jean-Léon Gérôme"), "jean leon gerome");
+        assert_eq!(artist_match_key("JEAN LEON GEROME"), "jean leon gerome");
+        assert_eq!(artist_match_key("Albrecht Dürer"), "albrecht durer");
+        assert_eq!(
+            artist_match_key("Bartolomé Esteban Murillo"),
+            "bartolome esteban murillo"
+        );
+        assert_eq!(artist_match_key("Vilhelm Hammershøi"), "vilhelm hammershoi");
+        assert_eq!(artist_match_key("J. M. W. Turner"), "j m w turner");
+        assert_ne!(
+            artist_match_key("Claude Monet"),
+            artist_match_key("Édouard Manet")
+        );
+    }
+
+    #[test]
+    fn test_choose_artist_spelling_works() {
+        let spellings = |spellings: &[(&'static str, usize)]| -> HashMap<&'static str, usize> {
+            spellings.iter().copied().collect()
+        };
+        assert_eq!(
+            choose_artist_spelling(&spellings(&[("Albrecht Durer", 3), ("Albrecht Dürer", 1)])),
+            Some("Albrecht Durer")
+        );
+        assert_eq!(
+            choose_artist_spelling(&spellings(&[("Albrecht Durer", 1), ("Albrecht Dürer", 1)])),
+            Some("Albrecht Dürer")
+        );
+        assert_eq!(
+            choose_artist_spelling(&spellings(&[
+                ("Jean-Leon Gerome", 1),
+                ("Jean Leon Gerome", 1)
+            ])),
+            Some("Jean Leon Gerome")
+        );
+        assert_eq!(choose_artist_spelling(&spellings(&[])), None);
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Top-level files with these extensions (or SQLite sidecars of them) are
+/// counted as databases rather than as `OTHER_FILES_CATEGORY`.
+const DB_EXTENSIONS: [&str; 4] = [".sqlite", ".sqlite-wal", ".sqlite-shm", ".sqlite-journal"];
+
+/// The category for the gallery and layout databases.
+pub const DB_FILES_CATEGORY: &str = "db";
+
+/// The category for top-level files that aren't databases.
+pub const OTHER_FILES_CATEGORY: &str = "other";
+
+/// How much disk space one part of the cache directory is using.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CacheUsage {
+    /// The name of the top-level subdirectory (e.g. `met-api` or `wikidata`), or
+    /// `DB_FILES_CATEGORY` or `OTHER_FILES_CATEGORY` for top-level files.
+    pub name: String,
+    pub bytes: u64,
+    pub files: usize,
+}
+
+/// How much disk space the cache directory is using, and where it is, e.g. so
+/// players can find out from within the game. Symlinks aren't followed, so
+/// nothing is counted twice.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct CacheUsageReport {
+    /// The absolute path to the cache directory.
+    pub path: PathBuf,
+    pub total_bytes: u64,
+    pub total_files: usize,
+    /// Ordered by name.
+    pub breakdown: Vec<CacheUsage>,
+}
+
+impl CacheUsageReport {
+    /// Walks the given cache directory, which can take a while on slow disks.
+    pub fn for_dir(cache_dir: &Path) -> Result<Self> {
+        let mut breakdown: BTreeMap<String, CacheUsage> = BTreeMap::new();
+        for entry in std::fs::read_dir(cache_dir)? {
+            let entry = entry?;
+            let metadata = entry.path().symlink_metadata()?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let (category, bytes, files) = if metadata.is_dir() {
+                let (bytes, files) = measure_dir(&entry.path())?;
+                (name, bytes, files)
+            } else if metadata.is_file() {
+                let category = if is_db_file(&name) {
+                    DB_FILES_CATEGORY
+                } else {
+                    OTHER_FILES_CATEGORY
+                };
+                (category.to_string(), metadata.len(), 1)
+            } else {
+                continue;
+            };
+            let usage = breakdown
+                .entry(category.clone())
+                .or_insert_with(|| CacheUsage {
+                    name: category,
+                    ..Default::default()
+                });
+            usage.bytes += bytes;
+            usage.files += files;
+        }
+        let breakdown: Vec<CacheUsage> = breakdown.into_values().collect();
+        Ok(CacheUsageReport {
+            path: std::fs::canonicalize(cache_dir).unwrap_or_else(|_| cache_dir.to_path_buf()),
+            total_bytes: breakdown.iter().map(|usage| usage.bytes).sum(),
+            total_files: breakdown.iter().map(|usage| usage.files).sum(),
+            breakdown,
+        })
+    }
+}
+
+impl Display for CacheUsageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Cache directory: {}", self.path.display())?;
+        writeln!(
+            f,
+            "  {:<12} {:>14} bytes {:>8} files",
+            "total", self.total_bytes, self.total_files
+        )?;
+        for usage in self.breakdown.iter() {
+            writeln!(
+                f,
+                "  {:<12} {:>14} bytes {:>8} files",
+                usage.name, usage.bytes, usage.files
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn is_db_file(filename: &str) -> bool {
+    DB_EXTENSIONS.iter().any(|ext| filename.ends_with(ext))
+}
+
+/// Returns the total size and number of files in the given directory and its
+/// subdirectories, without following symlinks.
+fn measure_dir(dir: &Path) -> Result<(u64, usize)> {
+    let mut bytes = 0;
+    let mut files = 0;
+    let mut dirs_to_visit = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs_to_visit.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let metadata = path.symlink_metadata()?;
+            if metadata.is_dir() {
+                dirs_to_visit.push(path);
+            } else if metadata.is_file() {
+                bytes += metadata.len();
+                files += 1;
+            }
+        }
+    }
+    Ok((bytes, files))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{CacheUsage, CacheUsageReport};
+
+    fn create_cache_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gallery-cache-usage-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file(dir: &PathBuf, relative_path: &str, bytes: usize) {
+        let path = dir.join(relative_path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, vec![0; bytes]).unwrap();
+    }
+
+    #[test]
+    fn test_cache_usage_report_works() {
+        let dir = create_cache_dir("report");
+        write_file(&dir, "gallery5.sqlite", 100);
+        write_file(&dir, "gallery5.sqlite-wal", 10);
+        write_file(&dir, "layout-user.sqlite", 20);
+        write_file(&dir, "met-api/object-1.json", 5);
+        write_file(&dir, "met-api/object-1-small.jpg", 50);
+        write_file(&dir, "wikidata/Q1.jpg", 7);
+        write_file(&dir, "wikidata/nested/Q2.jpg", 3);
+        write_file(&dir, "walls.json", 1);
+
+        let report = CacheUsageReport::for_dir(&dir).unwrap();
+        assert!(report.path.is_absolute());
+        assert_eq!(report.total_bytes, 196);
+        assert_eq!(report.total_files, 8);
+        let usage = |name: &str, bytes, files| CacheUsage {
+            name: name.to_string(),
+            bytes,
+            files,
+        };
+        assert_eq!(
+            report.breakdown,
+            vec![
+                usage("db", 130, 3),
+                usage("met-api", 55, 2),
+                usage("other", 1, 1),
+                usage("wikidata", 10, 2),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cache_usage_report_does_not_follow_symlinks() {
+        let dir = create_cache_dir("symlinks");
+        write_file(&dir, "met-api/object-1.json", 5);
+        std::os::unix::fs::symlink(dir.join("met-api"), dir.join("wikidata")).unwrap();
+        std::os::unix::fs::symlink(dir.join("met-api/object-1.json"), dir.join("boop.json"))
+            .unwrap();
+
+        let report = CacheUsageReport::for_dir(&dir).unwrap();
+        assert_eq!(report.total_bytes, 5);
+        assert_eq!(report.total_files, 1);
+        assert_eq!(report.breakdown.len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_usage_report_round_trips_through_json() {
+        let dir = create_cache_dir("json");
+        write_file(&dir, "gallery5.sqlite", 100);
+        let report = CacheUsageReport::for_dir(&dir).unwrap();
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CacheUsageReport>(&json).unwrap(),
+            report
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
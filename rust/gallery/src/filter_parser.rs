@@ -1,10 +1,12 @@
+use std::fmt::Display;
+
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag, tag_no_case, take_until},
-    character::complete::multispace0,
-    combinator::{map, opt, value},
+    bytes::complete::{is_not, tag, tag_no_case, take_until, take_while1},
+    character::complete::{digit1, multispace0},
+    combinator::{map, map_res, not, opt, value},
     multi::fold_many0,
-    sequence::{delimited, separated_pair, tuple},
+    sequence::{delimited, preceded, separated_pair, terminated, tuple},
     IResult,
 };
 
@@ -14,6 +16,109 @@ pub enum Filter<'a> {
     Or(Box<Filter<'a>>, Box<Filter<'a>>),
     Not(Box<Filter<'a>>),
     Term(&'a str),
+    /// Matches art objects in the given medium category, e.g. `medium_category:oil`.
+    MediumCategory(&'a str),
+    /// Matches art objects in the given collection exactly (once normalized), e.g.
+    /// `collection:"metropolitan museum of art"`.
+    Collection(&'a str),
+    /// Matches art objects that are (or aren't) highlights, e.g. `highlight:true`.
+    Highlight(bool),
+    /// Matches art objects dated between the given years, inclusive, e.g.
+    /// `date:1800-1850` or `date:1915`. The start is never after the end.
+    DateRange(i32, i32),
+    /// Matches art objects that the player, or the collection, has tagged with
+    /// the given tag, e.g. `tag:favorite` or `tag:portraits`.
+    Tag(&'a str),
+    /// Matches art objects whose classification contains the given text, e.g.
+    /// `class:drawings`.
+    Classification(&'a str),
+    /// Matches whatever the saved filter with the given name matches, e.g. `@oils`.
+    Macro(&'a str),
+}
+
+/// The default maximum number of terms in a filter, see `FilterLimits`.
+pub const DEFAULT_MAX_FILTER_TERMS: usize = 32;
+
+/// The default maximum length of a filter in characters, see `FilterLimits`.
+pub const DEFAULT_MAX_FILTER_LENGTH: usize = 1024;
+
+/// Limits on how big a filter can be. Every term becomes a bound parameter and
+/// several `LIKE` clauses, so huge filters are slow to run, and can even exceed
+/// SQLite's limit on the number of bound parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterLimits {
+    pub max_terms: usize,
+    pub max_length: usize,
+}
+
+impl Default for FilterLimits {
+    fn default() -> Self {
+        FilterLimits {
+            max_terms: DEFAULT_MAX_FILTER_TERMS,
+            max_length: DEFAULT_MAX_FILTER_LENGTH,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FilterError {
+    TooLong {
+        length: usize,
+        max_length: usize,
+    },
+    TooManyTerms {
+        terms: usize,
+        max_terms: usize,
+    },
+    /// The filter couldn't be parsed, e.g. because it ends with a `-`.
+    Invalid,
+}
+
+impl Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterError::TooLong { length, max_length } => write!(
+                f,
+                "Filter is {length} characters long, but can be at most {max_length}"
+            ),
+            FilterError::TooManyTerms { terms, max_terms } => write!(
+                f,
+                "Filter has {terms} terms, but can have at most {max_terms}"
+            ),
+            FilterError::Invalid => write!(f, "Filter could not be parsed"),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+impl<'a> Filter<'a> {
+    /// The number of terms in the filter, not including any in the saved
+    /// filters it refers to.
+    pub fn count_terms(&self) -> usize {
+        match self {
+            Filter::And(a, b) | Filter::Or(a, b) => a.count_terms() + b.count_terms(),
+            Filter::Not(value) => value.count_terms(),
+            Filter::Term(_)
+            | Filter::MediumCategory(_)
+            | Filter::Collection(_)
+            | Filter::Highlight(_)
+            | Filter::DateRange(_, _)
+            | Filter::Tag(_)
+            | Filter::Classification(_)
+            | Filter::Macro(_) => 1,
+        }
+    }
+}
+
+fn is_filter_macro_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Whether the given name can be used to refer to a saved filter, i.e. it's
+/// non-empty and only contains ASCII letters, numbers, underscores and dashes.
+pub fn is_valid_filter_macro_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_filter_macro_name_char)
 }
 
 /// Parse a filter query that follows the general pattern of Google's advanced search syntax:
@@ -21,30 +126,88 @@ pub enum Filter<'a> {
 ///   * Adjacent terms are ANDed together
 ///   * Terms with an OR between them are ORed together
 ///   * Terms with a `-` in front of them are negated
+///   * Terms of the form `medium_category:<category>` match the medium category
+///   * Terms of the form `collection:<name>` match the collection name
+///   * `highlight:true` and `highlight:false` match whether the art object is a highlight
+///   * Terms of the form `date:<year>` or `date:<start year>-<end year>` match the
+///     year the art object was made in, see `parse_year()`
+///   * Terms of the form `tag:<tag>` match art objects with that tag
+///   * Terms of the form `class:<classification>` match the classification
+///   * Terms of the form `@<name>` match the saved filter with that name
 ///
 /// Concretely:
 ///
 ///   * `"boop jones"` searches for `"boop"` _and_ `"jones"`
 ///   * `"boop -jones"` searches for `"boop"` and _not_ `"jones"`
 ///   * `"boop or jones"` searches for `"boop"` _or_ `"jones"`
-pub fn parse_filter(input: &str) -> Option<Filter> {
-    let Some((remaining, filter)) = filter(input).ok() else {
-        return None;
+///   * `"boop medium_category:oil"` searches for `"boop"` in oil paintings
+///   * `"boop collection:\"the met\""` searches for `"boop"` in the collection named `"the met"`
+///   * `"boop highlight:true"` searches for `"boop"` in highlights
+///   * `"boop date:1800-1850"` searches for `"boop"` in art objects made from 1800 to 1850
+///   * `"boop -tag:creepy"` searches for `"boop"` in art objects that aren't tagged `"creepy"`
+///   * `"boop class:prints"` searches for `"boop"` in art objects classified as prints
+///   * `"boop -@oils"` searches for `"boop"` in anything the saved filter `"oils"` doesn't match
+///
+/// Note that saved filters aren't expanded here, since that requires access to
+/// the database.
+///
+/// Returns `None` if the filter is empty, and an error if it can't be parsed or
+/// exceeds the default `FilterLimits`.
+pub fn parse_filter(input: &str) -> Result<Option<Filter>, FilterError> {
+    parse_filter_with_limits(input, &FilterLimits::default())
+}
+
+/// Like `parse_filter()`, but with the given limits.
+pub fn parse_filter_with_limits<'a>(
+    input: &'a str,
+    limits: &FilterLimits,
+) -> Result<Option<Filter<'a>>, FilterError> {
+    // Check this first, so we don't waste time parsing something huge.
+    let length = input.chars().count();
+    if length > limits.max_length {
+        return Err(FilterError::TooLong {
+            length,
+            max_length: limits.max_length,
+        });
+    }
+    let Ok((remaining, filter)) = filter(input) else {
+        return Err(FilterError::Invalid);
     };
     if remaining.len() != 0 {
-        return None;
+        return Err(FilterError::Invalid);
     }
-    filter
+    if let Some(filter) = &filter {
+        let terms = filter.count_terms();
+        if terms > limits.max_terms {
+            return Err(FilterError::TooManyTerms {
+                terms,
+                max_terms: limits.max_terms,
+            });
+        }
+    }
+    Ok(filter)
 }
 
 fn term(input: &str) -> IResult<&str, Filter> {
     delimited(
         multispace0,
         map(
-            tuple((opt(value((), tag("-"))), alt((quoted_term, unquoted_term)))),
-            |(negated, term_str)| match negated {
-                Some(()) => Filter::Not(Filter::Term(term_str).into()),
-                None => Filter::Term(term_str),
+            tuple((
+                opt(value((), tag("-"))),
+                alt((
+                    medium_category_term,
+                    collection_term,
+                    highlight_term,
+                    date_term,
+                    tag_term,
+                    class_term,
+                    macro_term,
+                    map(alt((quoted_term, unquoted_term)), Filter::Term),
+                )),
+            )),
+            |(negated, filter)| match negated {
+                Some(()) => Filter::Not(filter.into()),
+                None => filter,
             },
         ),
         multispace0,
@@ -58,16 +221,96 @@ fn or(input: &str) -> IResult<&str, Filter> {
 }
 
 fn filter(input: &str) -> IResult<&str, Option<Filter>> {
-    fold_many0(
-        alt((or, term)),
-        || None,
-        |acc: Option<Filter>, item: Filter| match acc {
-            Some(other) => Some(Filter::And(other.into(), item.into())),
-            None => Some(item),
+    // Terms consume the whitespace around them, but a filter that's nothing
+    // but whitespace has no terms to do that.
+    preceded(
+        multispace0,
+        fold_many0(
+            alt((or, term)),
+            || None,
+            |acc: Option<Filter>, item: Filter| match acc {
+                Some(other) => Some(Filter::And(other.into(), item.into())),
+                None => Some(item),
+            },
+        ),
+    )(input)
+}
+
+fn medium_category_term(input: &str) -> IResult<&str, Filter> {
+    map(
+        preceded(
+            tag_no_case("medium_category:"),
+            alt((quoted_term, unquoted_term)),
+        ),
+        Filter::MediumCategory,
+    )(input)
+}
+
+fn collection_term(input: &str) -> IResult<&str, Filter> {
+    map(
+        preceded(
+            tag_no_case("collection:"),
+            alt((quoted_term, unquoted_term)),
+        ),
+        Filter::Collection,
+    )(input)
+}
+
+fn highlight_term(input: &str) -> IResult<&str, Filter> {
+    map(
+        preceded(
+            tag_no_case("highlight:"),
+            alt((
+                value(true, tag_no_case("true")),
+                value(false, tag_no_case("false")),
+            )),
+        ),
+        Filter::Highlight,
+    )(input)
+}
+
+fn tag_term(input: &str) -> IResult<&str, Filter> {
+    map(
+        preceded(tag_no_case("tag:"), alt((quoted_term, unquoted_term))),
+        Filter::Tag,
+    )(input)
+}
+
+fn class_term(input: &str) -> IResult<&str, Filter> {
+    map(
+        preceded(tag_no_case("class:"), alt((quoted_term, unquoted_term))),
+        Filter::Classification,
+    )(input)
+}
+
+fn year(input: &str) -> IResult<&str, i32> {
+    map_res(digit1, str::parse::<i32>)(input)
+}
+
+fn date_term(input: &str) -> IResult<&str, Filter> {
+    map(
+        preceded(
+            tag_no_case("date:"),
+            // Make sure the term ends here, so e.g. `date:1800s` is just a term.
+            terminated(
+                tuple((year, opt(preceded(tag("-"), year)))),
+                not(unquoted_term),
+            ),
+        ),
+        |(start, end)| {
+            let end = end.unwrap_or(start);
+            Filter::DateRange(start.min(end), start.max(end))
         },
     )(input)
 }
 
+fn macro_term(input: &str) -> IResult<&str, Filter> {
+    map(
+        preceded(tag("@"), take_while1(is_filter_macro_name_char)),
+        Filter::Macro,
+    )(input)
+}
+
 fn unquoted_term(input: &str) -> IResult<&str, &str> {
     is_not(" \t\r\n")(input)
 }
@@ -78,60 +321,228 @@ fn quoted_term(input: &str) -> IResult<&str, &str> {
 
 #[cfg(test)]
 mod tests {
-    use crate::filter_parser::{parse_filter, Filter};
+    use crate::filter_parser::{
+        is_valid_filter_macro_name, parse_filter, parse_filter_with_limits, Filter, FilterError,
+        FilterLimits, DEFAULT_MAX_FILTER_LENGTH, DEFAULT_MAX_FILTER_TERMS,
+    };
+
+    fn make_filter_with_terms(terms: usize) -> String {
+        vec!["a"; terms].join(" ")
+    }
 
     #[test]
     fn test_parse_filter_works() {
-        assert_eq!(parse_filter(""), None);
-        assert_eq!(parse_filter("hi"), Some(Filter::Term("hi")));
+        assert_eq!(parse_filter(""), Ok(None));
+        assert_eq!(parse_filter("hi"), Ok(Some(Filter::Term("hi"))));
         assert_eq!(
             parse_filter("hi there"),
-            Some(Filter::And(
+            Ok(Some(Filter::And(
                 Filter::Term("hi").into(),
                 Filter::Term("there").into(),
-            ))
+            )))
         );
         assert_eq!(
             parse_filter("hi     \"there bub\""),
-            Some(Filter::And(
+            Ok(Some(Filter::And(
                 Filter::Term("hi").into(),
                 Filter::Term("there bub").into(),
-            ))
+            )))
         );
         assert_eq!(
             parse_filter("hi there bub"),
-            Some(Filter::And(
+            Ok(Some(Filter::And(
                 Filter::And(Filter::Term("hi").into(), Filter::Term("there").into()).into(),
                 Filter::Term("bub").into(),
-            ))
+            )))
         );
         assert_eq!(
             parse_filter("hi OR there"),
-            Some(Filter::Or(
+            Ok(Some(Filter::Or(
                 Filter::Term("hi").into(),
                 Filter::Term("there").into(),
-            ))
+            )))
         );
         assert_eq!(
             parse_filter("hi there OR bub"),
-            Some(Filter::And(
+            Ok(Some(Filter::And(
                 Filter::Term("hi").into(),
                 Filter::Or(Filter::Term("there").into(), Filter::Term("bub").into()).into(),
-            ))
+            )))
         );
         assert_eq!(
             parse_filter("hi -there"),
-            Some(Filter::And(
+            Ok(Some(Filter::And(
                 Filter::Term("hi").into(),
                 Filter::Not(Filter::Term("there").into()).into(),
-            ))
+            )))
         );
         assert_eq!(
             parse_filter("hi -\"there bub\""),
-            Some(Filter::And(
+            Ok(Some(Filter::And(
                 Filter::Term("hi").into(),
                 Filter::Not(Filter::Term("there bub").into()).into(),
-            ))
+            )))
+        );
+        assert_eq!(
+            parse_filter("hi medium_category:oil"),
+            Ok(Some(Filter::And(
+                Filter::Term("hi").into(),
+                Filter::MediumCategory("oil").into(),
+            )))
+        );
+        assert_eq!(
+            parse_filter("-medium_category:\"oil\""),
+            Ok(Some(Filter::Not(Filter::MediumCategory("oil").into())))
+        );
+        assert_eq!(
+            parse_filter("hi Collection:\"the met\""),
+            Ok(Some(Filter::And(
+                Filter::Term("hi").into(),
+                Filter::Collection("the met").into(),
+            )))
+        );
+        assert_eq!(
+            parse_filter("hi -@big_oils"),
+            Ok(Some(Filter::And(
+                Filter::Term("hi").into(),
+                Filter::Not(Filter::Macro("big_oils").into()).into(),
+            )))
+        );
+        assert_eq!(
+            parse_filter("hi -highlight:TRUE"),
+            Ok(Some(Filter::And(
+                Filter::Term("hi").into(),
+                Filter::Not(Filter::Highlight(true).into()).into(),
+            )))
+        );
+        assert_eq!(
+            parse_filter("highlight:false"),
+            Ok(Some(Filter::Highlight(false)))
+        );
+        assert_eq!(
+            parse_filter("hi date:1800-1850"),
+            Ok(Some(Filter::And(
+                Filter::Term("hi").into(),
+                Filter::DateRange(1800, 1850).into(),
+            )))
+        );
+        assert_eq!(
+            parse_filter("-DATE:1915"),
+            Ok(Some(Filter::Not(Filter::DateRange(1915, 1915).into())))
+        );
+        assert_eq!(
+            parse_filter("date:1850-1800"),
+            Ok(Some(Filter::DateRange(1800, 1850)))
+        );
+        assert_eq!(
+            parse_filter("date:1800s"),
+            Ok(Some(Filter::Term("date:1800s")))
+        );
+        assert_eq!(
+            parse_filter("date:1800-"),
+            Ok(Some(Filter::Term("date:1800-")))
+        );
+        assert_eq!(
+            parse_filter("boop -TAG:favorite"),
+            Ok(Some(Filter::And(
+                Filter::Term("boop").into(),
+                Filter::Not(Filter::Tag("favorite").into()).into(),
+            )))
         );
+        assert_eq!(
+            parse_filter("tag:\"show-mom\""),
+            Ok(Some(Filter::Tag("show-mom")))
+        );
+        assert_eq!(
+            parse_filter("boop CLASS:\"still life\""),
+            Ok(Some(Filter::And(
+                Filter::Term("boop").into(),
+                Filter::Classification("still life").into(),
+            )))
+        );
+        assert_eq!(
+            parse_filter("-class:prints"),
+            Ok(Some(Filter::Not(Filter::Classification("prints").into())))
+        );
+        assert_eq!(parse_filter("classic"), Ok(Some(Filter::Term("classic"))));
+        assert_eq!(parse_filter("@"), Ok(Some(Filter::Term("@"))));
+        assert_eq!(
+            parse_filter("boop@jones"),
+            Ok(Some(Filter::Term("boop@jones")))
+        );
+    }
+
+    #[test]
+    fn test_unparseable_filters_are_errors() {
+        assert_eq!(parse_filter("-"), Err(FilterError::Invalid));
+        assert_eq!(parse_filter("monet -"), Err(FilterError::Invalid));
+        assert_eq!(parse_filter("   "), Ok(None));
+    }
+
+    #[test]
+    fn test_count_terms_works() {
+        let filter = parse_filter("a -b OR c medium_category:oil -@big_oils date:1900")
+            .unwrap()
+            .unwrap();
+        assert_eq!(filter.count_terms(), 6);
+    }
+
+    #[test]
+    fn test_filter_term_count_is_limited() {
+        let filter = make_filter_with_terms(DEFAULT_MAX_FILTER_TERMS);
+        assert!(parse_filter(&filter).unwrap().is_some());
+        let filter = make_filter_with_terms(DEFAULT_MAX_FILTER_TERMS + 1);
+        assert_eq!(
+            parse_filter(&filter),
+            Err(FilterError::TooManyTerms {
+                terms: DEFAULT_MAX_FILTER_TERMS + 1,
+                max_terms: DEFAULT_MAX_FILTER_TERMS
+            })
+        );
+
+        // Even without a length limit, we don't want thousands of parameters.
+        let limits = FilterLimits {
+            max_length: usize::MAX,
+            ..Default::default()
+        };
+        let filter = make_filter_with_terms(1000);
+        assert_eq!(
+            parse_filter_with_limits(&filter, &limits),
+            Err(FilterError::TooManyTerms {
+                terms: 1000,
+                max_terms: DEFAULT_MAX_FILTER_TERMS
+            })
+        );
+        let limits = FilterLimits {
+            max_terms: 1000,
+            max_length: usize::MAX,
+        };
+        assert!(parse_filter_with_limits(&filter, &limits)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_filter_length_is_limited() {
+        let filter = "a".repeat(DEFAULT_MAX_FILTER_LENGTH);
+        assert!(parse_filter(&filter).unwrap().is_some());
+        let filter = "é".repeat(DEFAULT_MAX_FILTER_LENGTH);
+        assert!(parse_filter(&filter).unwrap().is_some());
+        let filter = make_filter_with_terms(4500);
+        assert_eq!(
+            parse_filter(&filter),
+            Err(FilterError::TooLong {
+                length: 8999,
+                max_length: DEFAULT_MAX_FILTER_LENGTH
+            })
+        );
+    }
+
+    #[test]
+    fn test_is_valid_filter_macro_name_works() {
+        assert!(is_valid_filter_macro_name("big-oils_2"));
+        assert!(!is_valid_filter_macro_name(""));
+        assert!(!is_valid_filter_macro_name("big oils"));
+        assert!(!is_valid_filter_macro_name("@oils"));
     }
 }
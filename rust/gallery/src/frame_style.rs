@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+use crate::medium::MediumCategory;
+
+/// Art from before this year is old enough for a gilded frame.
+const ORNATE_FRAME_BEFORE_YEAR: i32 = 1850;
+
+/// The style of frame that an art object is suggested to be hung in, so the
+/// game doesn't have to guess from raw metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FrameStyle {
+    #[default]
+    Default,
+    OrnateGold,
+    SimpleBlack,
+    LightWood,
+}
+
+impl FrameStyle {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FrameStyle::Default => "default",
+            FrameStyle::OrnateGold => "ornate-gold",
+            FrameStyle::SimpleBlack => "simple-black",
+            FrameStyle::LightWood => "light-wood",
+        }
+    }
+}
+
+/// Whether the collection is devoted to modern art, whose paintings tend to be
+/// framed plainly, if at all.
+fn is_modern_art_collection(collection: &str) -> bool {
+    let collection = collection.to_lowercase();
+    collection.contains("modern") || collection.contains("contemporary")
+}
+
+/// Suggests a frame for an art object with the given medium category,
+/// representative year (see `object_date::parse_year()`) and collection.
+/// Anything we don't know enough about gets `FrameStyle::Default`.
+pub fn frame_style(
+    medium_category: MediumCategory,
+    date_year: Option<i32>,
+    collection: &str,
+) -> FrameStyle {
+    match (medium_category, date_year) {
+        (MediumCategory::Photograph, _) => FrameStyle::SimpleBlack,
+        (MediumCategory::Print | MediumCategory::Watercolor | MediumCategory::Drawing, _) => {
+            FrameStyle::LightWood
+        }
+        (MediumCategory::Oil, Some(year)) if year < ORNATE_FRAME_BEFORE_YEAR => {
+            FrameStyle::OrnateGold
+        }
+        (MediumCategory::Oil, Some(_)) if is_modern_art_collection(collection) => {
+            FrameStyle::SimpleBlack
+        }
+        _ => FrameStyle::Default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::medium::MediumCategory;
+
+    use super::{frame_style, FrameStyle};
+
+    #[test]
+    fn test_frame_style_works() {
+        let met = "The Metropolitan Museum of Art";
+        let moma = "Museum of Modern Art";
+        let cases = [
+            (MediumCategory::Oil, Some(1665), met, FrameStyle::OrnateGold),
+            (MediumCategory::Oil, Some(-300), met, FrameStyle::OrnateGold),
+            (
+                MediumCategory::Oil,
+                Some(1849),
+                moma,
+                FrameStyle::OrnateGold,
+            ),
+            (MediumCategory::Oil, Some(1850), met, FrameStyle::Default),
+            (
+                MediumCategory::Oil,
+                Some(1907),
+                moma,
+                FrameStyle::SimpleBlack,
+            ),
+            (MediumCategory::Oil, None, moma, FrameStyle::Default),
+            (
+                MediumCategory::Photograph,
+                None,
+                met,
+                FrameStyle::SimpleBlack,
+            ),
+            (
+                MediumCategory::Photograph,
+                Some(1840),
+                "",
+                FrameStyle::SimpleBlack,
+            ),
+            (
+                MediumCategory::Print,
+                Some(1830),
+                met,
+                FrameStyle::LightWood,
+            ),
+            (
+                MediumCategory::Watercolor,
+                Some(1900),
+                moma,
+                FrameStyle::LightWood,
+            ),
+            (MediumCategory::Drawing, None, "", FrameStyle::LightWood),
+            (MediumCategory::Other, Some(1500), met, FrameStyle::Default),
+            (MediumCategory::Other, None, "", FrameStyle::Default),
+        ];
+        for (medium_category, date_year, collection, expected) in cases {
+            assert_eq!(
+                frame_style(medium_category, date_year, collection),
+                expected,
+                "{medium_category:?}, {date_year:?}, {collection:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_frame_style_serializes_as_str() {
+        for style in [
+            FrameStyle::Default,
+            FrameStyle::OrnateGold,
+            FrameStyle::SimpleBlack,
+            FrameStyle::LightWood,
+        ] {
+            assert_eq!(
+                serde_json::to_value(style).unwrap(),
+                serde_json::json!(style.as_str())
+            );
+        }
+    }
+}
@@ -1,40 +1,321 @@
 use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
     fs::{create_dir_all, File},
-    path::PathBuf,
-    time::Duration,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock, PoisonError,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use ureq::{Agent, AgentBuilder, Response};
 
+use crate::{
+    rate_limiter::{url_host, HostRateLimiter},
+    shutdown::{ShutdownAwareReader, ShutdownSignal},
+};
+
 const TIMEOUT_SECS: u64 = 10;
 
 const MAX_FILE_SIZE: u64 = 10_000_000;
 
+/// Files with these extensions need to start with one of `IMAGE_MAGIC_BYTES`
+/// to be considered validly cached.
+const IMAGE_EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "gif", "webp", "tif", "tiff"];
+
+const IMAGE_MAGIC_BYTES: [&[u8]; 6] = [
+    b"\xff\xd8\xff",
+    b"\x89PNG",
+    b"GIF8",
+    b"RIFF",
+    b"II*\0",
+    b"MM\0*",
+];
+
+/// Appended to a cached file's name to get the name of the file its
+/// `CacheMetadata` is kept in.
+const METADATA_SUFFIX: &str = ".meta.json";
+
+/// Used to make temporary filenames unique within this process.
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The agent used by every cache that isn't given its own transport, so that
+/// they all share its pool of connections.
+static SHARED_AGENT: OnceLock<Arc<Agent>> = OnceLock::new();
+
+fn shared_agent() -> Arc<Agent> {
+    SHARED_AGENT
+        .get_or_init(|| {
+            Arc::new(
+                AgentBuilder::new()
+                    .timeout(Duration::from_secs(TIMEOUT_SECS))
+                    .build(),
+            )
+        })
+        .clone()
+}
+
 #[derive(Debug, PartialEq)]
 pub enum CacheResult {
     NewlyCached,
     AlreadyCached,
+    /// The file was already cached, and the server said it hasn't changed
+    /// since then.
+    NotModified,
+}
+
+/// Information about the HTTP response a cached file came from, which is kept
+/// alongside it, e.g. so it can be revalidated later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheMetadata {
+    /// The URL the file was requested from.
+    pub url: String,
+    /// The URL the file was actually served from, after following redirects.
+    pub final_url: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// When the file was last fetched or revalidated, in seconds since the
+    /// unix epoch.
+    pub fetched_at: i64,
+    /// The size of the cached file, which for JSON is after pretty-printing.
+    pub size: u64,
+}
+
+impl CacheMetadata {
+    fn from_response(url: &str, response: &Response, size: u64) -> Self {
+        CacheMetadata {
+            url: url.to_string(),
+            final_url: response.get_url().to_string(),
+            status: response.status(),
+            etag: response.header("ETag").map(str::to_string),
+            last_modified: response.header("Last-Modified").map(str::to_string),
+            fetched_at: now_timestamp(),
+            size,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostCacheStats {
+    /// How many HTTP requests were made to the host.
+    pub requests: u64,
+    /// How many bytes from the host were written to the cache.
+    pub bytes_downloaded: u64,
+    /// How many times a file from the host was already validly cached, or
+    /// the host said it hadn't changed.
+    pub hits: u64,
+    /// How many times a file from the host had to be fetched, whether or not
+    /// that succeeded.
+    pub misses: u64,
+}
+
+/// What the cache has done since it was created, see `GalleryCache::stats()`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Keyed by host, e.g. `collectionapi.metmuseum.org`.
+    pub hosts: BTreeMap<String, HostCacheStats>,
+}
+
+/// How to make a request conditional, so that the server can respond with
+/// HTTP 304 if the resource hasn't changed.
+enum Precondition {
+    ModifiedSince(SystemTime),
+    /// Request headers to send, e.g. `If-None-Match`.
+    Headers(Vec<(&'static str, String)>),
+}
+
+/// An error indicating that the server responded with something other
+/// than HTTP 200, so callers can e.g. try a different URL on a 404.
+#[derive(Debug, PartialEq)]
+pub struct HttpStatusError {
+    pub status: u16,
+}
+
+impl Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Got HTTP {}", self.status)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// Returns the HTTP status code of the given error, if it was caused by
+/// the server responding with something other than HTTP 200.
+pub fn get_http_status(err: &anyhow::Error) -> Option<u16> {
+    err.downcast_ref::<HttpStatusError>().map(|err| err.status)
+}
+
+/// Whatever the cache uses to make HTTP requests. This is mostly here so tests
+/// can make sure the network isn't being touched. Errors are boxed, since
+/// `ureq::Error` is big.
+pub trait HttpTransport: Send + Sync {
+    /// Note that redirects should be followed.
+    fn get(&self, url: &str) -> Result<Response, Box<ureq::Error>>;
+
+    /// Like `get()`, but asks the server to respond with HTTP 304 if the
+    /// resource hasn't changed since the given time. Servers are free to
+    /// ignore this, so callers still need to handle HTTP 200.
+    fn get_if_modified_since(
+        &self,
+        url: &str,
+        _since: SystemTime,
+    ) -> Result<Response, Box<ureq::Error>> {
+        self.get(url)
+    }
+
+    /// Like `get()`, but sends the given request headers, e.g. to make the
+    /// request conditional. As with `get_if_modified_since()`, servers are free
+    /// to ignore them.
+    fn get_with_headers(
+        &self,
+        url: &str,
+        _headers: &[(&'static str, String)],
+    ) -> Result<Response, Box<ureq::Error>> {
+        self.get(url)
+    }
+}
+
+impl HttpTransport for Agent {
+    fn get(&self, url: &str) -> Result<Response, Box<ureq::Error>> {
+        Agent::get(self, url).call().map_err(Box::new)
+    }
+
+    fn get_if_modified_since(
+        &self,
+        url: &str,
+        since: SystemTime,
+    ) -> Result<Response, Box<ureq::Error>> {
+        Agent::get(self, url)
+            .set("If-Modified-Since", &format_http_date(since))
+            .call()
+            .map_err(Box::new)
+    }
+
+    fn get_with_headers(
+        &self,
+        url: &str,
+        headers: &[(&'static str, String)],
+    ) -> Result<Response, Box<ureq::Error>> {
+        headers
+            .iter()
+            .fold(Agent::get(self, url), |request, (name, value)| {
+                request.set(name, value)
+            })
+            .call()
+            .map_err(Box::new)
+    }
 }
 
 pub struct GalleryCache {
     cache_dir: PathBuf,
-    agent: Agent,
+    transport: Arc<dyn HttpTransport>,
+    offline: bool,
+    rate_limiter: Option<Arc<HostRateLimiter>>,
+    shutdown: Option<Arc<ShutdownSignal>>,
+    stats: Mutex<CacheStats>,
+    /// Locks for paths that are currently being cached, so that threads fetching
+    /// the same URL at the same time only download it once.
+    in_flight: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
 }
 
 impl GalleryCache {
+    /// Note that the cache shares its HTTP agent with every other cache created
+    /// this way.
     pub fn new(cache_dir: PathBuf) -> Self {
+        Self::with_shared_transport(cache_dir, shared_agent())
+    }
+
+    pub fn with_transport(cache_dir: PathBuf, transport: Box<dyn HttpTransport>) -> Self {
+        Self::with_shared_transport(cache_dir, transport.into())
+    }
+
+    /// Like `with_transport()`, but the transport can also be used by other
+    /// caches, e.g. on other threads.
+    pub fn with_shared_transport(cache_dir: PathBuf, transport: Arc<dyn HttpTransport>) -> Self {
         Self {
             cache_dir,
-            agent: AgentBuilder::new()
-                .timeout(Duration::from_secs(TIMEOUT_SECS))
-                .build(),
+            transport,
+            offline: false,
+            rate_limiter: None,
+            shutdown: None,
+            stats: Mutex::new(CacheStats::default()),
+            in_flight: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Create a cache that never touches the network, e.g. for tests. Trying to
+    /// cache a URL that isn't already cached results in an error.
+    pub fn new_offline(cache_dir: PathBuf) -> Self {
+        Self {
+            offline: true,
+            ..Self::new(cache_dir)
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Change whether the cache is allowed to touch the network, e.g. because
+    /// the player is on a flight.
+    pub fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    /// Makes every request wait its turn with the given rate limiter, which
+    /// can be shared with other caches so that they're limited together.
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<Arc<HostRateLimiter>>) {
+        self.rate_limiter = rate_limiter;
+    }
+
+    /// Once the given signal says we're shutting down, new requests fail right
+    /// away, and downloads that don't finish before its deadline are abandoned
+    /// without caching anything.
+    pub fn set_shutdown_signal(&mut self, shutdown: Option<Arc<ShutdownSignal>>) {
+        self.shutdown = shutdown;
+    }
+
+    /// The signal given to `set_shutdown_signal()`, if any.
+    pub fn shutdown_signal(&self) -> Option<&Arc<ShutdownSignal>> {
+        self.shutdown.as_ref()
+    }
+
+    /// Returns what the cache has done so far, per host.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn update_stats<F: FnOnce(&mut HostCacheStats)>(&self, url: &str, update: F) {
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        update(stats.hosts.entry(url_host(url).to_string()).or_default());
+    }
+
     pub fn cache_dir(&self) -> &PathBuf {
         &self.cache_dir
     }
 
+    /// Returns the path to the given file if it's already cached, without
+    /// trying to fetch it.
+    pub fn get_if_cached<T: AsRef<str>>(&self, filename: T) -> Option<PathBuf> {
+        let cached_path = self.get_cached_path(filename);
+        if cached_path.exists() {
+            Some(cached_path)
+        } else {
+            None
+        }
+    }
+
     pub fn get_cached_path<T: AsRef<str>>(&self, relative_pathname: T) -> PathBuf {
         let mut result = self.cache_dir.clone();
         for path_part in relative_pathname.as_ref().split("/") {
@@ -43,61 +324,236 @@ impl GalleryCache {
         result
     }
 
+    /// Note that if the file is already cached but looks truncated or corrupt,
+    /// it's downloaded again.
     pub fn cache_binary_url<T: AsRef<str>, U: AsRef<str>>(
         &self,
         url: T,
         filename: U,
     ) -> Result<CacheResult> {
         let cached_path = self.get_cached_path(filename);
-        if cached_path.exists() {
-            return Ok(CacheResult::AlreadyCached);
-        }
-        ensure_parent_dir(&cached_path)?;
-        println!("Caching {} -> {}...", url.as_ref(), cached_path.display());
-        let response = self.agent.get(url.as_ref()).call()?;
-        validate_response(&response)?;
-        let mut response_body = response.into_reader();
-        let mut outfile = File::create(cached_path.clone())?;
-        // TODO: Ideally we should prevent the file from growing too large, since the
-        // response may not have had a content-length header.
-        match std::io::copy(&mut response_body, &mut outfile) {
-            Ok(_) => Ok(CacheResult::NewlyCached),
-            Err(err) => {
-                // Note: I haven't actually tested this manually, hopefully it works!
-                drop(outfile);
-                if cached_path.exists() {
-                    let _ = std::fs::remove_file(cached_path);
-                }
-                Err(err.into())
-            }
-        }
+        self.cache_url_once(
+            url.as_ref(),
+            &cached_path,
+            is_valid_binary_file,
+            false,
+            || {
+                info!("Caching {} -> {}...", url.as_ref(), cached_path.display());
+                let response = self.get(url.as_ref(), None)?;
+                let size = write_binary_response(
+                    url.as_ref(),
+                    response,
+                    &cached_path,
+                    self.shutdown.as_deref(),
+                )?;
+                self.update_stats(url.as_ref(), |stats| stats.bytes_downloaded += size);
+                Ok(CacheResult::NewlyCached)
+            },
+        )
     }
 
+    /// Note that if the file is already cached but isn't valid JSON, e.g.
+    /// because a previous write was interrupted, it's downloaded again.
+    ///
+    /// If `force_refresh` is true, the file is fetched again even if it's already
+    /// validly cached. In that case the server is asked to only send it if it's
+    /// changed since the cached file was written, and `CacheResult::NotModified`
+    /// is returned if it hasn't.
     pub fn cache_json_url<T: AsRef<str>, U: AsRef<str>>(
         &self,
         url: T,
         filename: U,
+        force_refresh: bool,
     ) -> Result<CacheResult> {
         let cached_path = self.get_cached_path(filename);
-        if cached_path.exists() {
-            return Ok(CacheResult::AlreadyCached);
+        self.cache_url_once(
+            url.as_ref(),
+            &cached_path,
+            is_valid_json_file,
+            force_refresh,
+            || {
+                let modified_time = if force_refresh && is_valid_json_file(&cached_path) {
+                    std::fs::metadata(&cached_path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                } else {
+                    None
+                };
+                info!("Caching {} -> {}...", url.as_ref(), cached_path.display());
+                let response =
+                    self.get(url.as_ref(), modified_time.map(Precondition::ModifiedSince))?;
+                if response.status() == 304 {
+                    touch_metadata(&cached_path)?;
+                    return Ok(CacheResult::NotModified);
+                }
+                let size = write_json_response(url.as_ref(), response, &cached_path)?;
+                self.update_stats(url.as_ref(), |stats| stats.bytes_downloaded += size);
+                Ok(CacheResult::NewlyCached)
+            },
+        )
+    }
+
+    /// Returns the metadata of the response the given file was cached from, or
+    /// `None` if it was cached before such metadata was kept.
+    pub fn get_cache_metadata<T: AsRef<str>>(&self, filename: T) -> Result<Option<CacheMetadata>> {
+        let metadata_path = get_metadata_path(&self.get_cached_path(filename));
+        if !metadata_path.exists() {
+            return Ok(None);
         }
-        ensure_parent_dir(&cached_path)?;
-        println!("Caching {} -> {}...", url.as_ref(), cached_path.display());
-        let response = self.agent.get(url.as_ref()).call()?;
-        validate_response(&response)?;
-        if response.content_type() != "application/json" {
-            return Err(anyhow!("Content type is {}", response.content_type()));
+        let metadata = serde_json::from_str(&std::fs::read_to_string(&metadata_path)?)
+            .map_err(|err| anyhow!("Failed to load {}: {err}", metadata_path.display()))?;
+        Ok(Some(metadata))
+    }
+
+    /// Asks the server whether the given cached file has changed since it was
+    /// fetched, using the etag and last-modified date in its metadata (or its
+    /// modification time, if the server provided neither), and re-caches it if
+    /// it has. Returns whether its content changed.
+    ///
+    /// Note that this is an error if the file has no metadata, since there's no
+    /// way of knowing what URL it came from.
+    pub fn revalidate<T: AsRef<str>>(&self, filename: T) -> Result<bool> {
+        let filename = filename.as_ref();
+        let Some(metadata) = self.get_cache_metadata(filename)? else {
+            return Err(anyhow!("{filename} has no cache metadata to revalidate"));
+        };
+        let cached_path = self.get_cached_path(filename);
+        let previous_contents = std::fs::read(&cached_path)?;
+        let is_json = is_valid_json_file(&cached_path);
+        let mut headers = vec![];
+        if let Some(etag) = metadata.etag {
+            headers.push(("If-None-Match", etag));
+        }
+        if let Some(last_modified) = metadata.last_modified {
+            headers.push(("If-Modified-Since", last_modified));
         }
-        // TODO: Ideally we should prevent the response from growing too large, since the
-        // response may not have had a content-length header.
-        let response_body = response.into_string()?;
-        let json_body: serde_json::Value = serde_json::from_str(response_body.as_ref())?;
-        let pretty_printed = serde_json::to_string_pretty(&json_body)?;
+        let precondition = if headers.is_empty() {
+            Precondition::ModifiedSince(std::fs::metadata(&cached_path)?.modified()?)
+        } else {
+            Precondition::Headers(headers)
+        };
+        let is_valid = if is_json {
+            is_valid_json_file
+        } else {
+            is_valid_binary_file
+        };
+        let result = self.cache_url_once(&metadata.url, &cached_path, is_valid, true, || {
+            info!(
+                "Revalidating {} -> {}...",
+                metadata.url,
+                cached_path.display()
+            );
+            let response = self.get(&metadata.url, Some(precondition))?;
+            if response.status() == 304 {
+                touch_metadata(&cached_path)?;
+                return Ok(CacheResult::NotModified);
+            }
+            let size = if is_json {
+                write_json_response(&metadata.url, response, &cached_path)?
+            } else {
+                write_binary_response(
+                    &metadata.url,
+                    response,
+                    &cached_path,
+                    self.shutdown.as_deref(),
+                )?
+            };
+            self.update_stats(&metadata.url, |stats| stats.bytes_downloaded += size);
+            // Servers are free to ignore the precondition, in which case we may
+            // have just fetched exactly what we already had.
+            if std::fs::read(&cached_path)? == previous_contents {
+                Ok(CacheResult::NotModified)
+            } else {
+                Ok(CacheResult::NewlyCached)
+            }
+        })?;
+        Ok(result == CacheResult::NewlyCached)
+    }
 
-        std::fs::write(cached_path, pretty_printed)?;
+    /// Calls `download` unless the given path already contains a valid file (or
+    /// `force_refresh` is true), making sure that only one thread at a time does
+    /// so for any given path. The result counts as a hit or miss for the host
+    /// of the given URL.
+    fn cache_url_once<F: FnOnce() -> Result<CacheResult>>(
+        &self,
+        url: &str,
+        cached_path: &PathBuf,
+        is_valid: fn(&Path) -> bool,
+        force_refresh: bool,
+        download: F,
+    ) -> Result<CacheResult> {
+        if !force_refresh && is_valid(cached_path) {
+            self.update_stats(url, |stats| stats.hits += 1);
+            return Ok(CacheResult::AlreadyCached);
+        }
+        let path_lock = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(cached_path.clone())
+            .or_default()
+            .clone();
+        let result = {
+            let _guard = path_lock.lock().unwrap_or_else(PoisonError::into_inner);
+            // Another thread may have cached it while we were waiting.
+            if !force_refresh && is_valid(cached_path) {
+                Ok(CacheResult::AlreadyCached)
+            } else {
+                if !force_refresh && cached_path.exists() {
+                    warn!("{} is invalid, re-caching it.", cached_path.display());
+                }
+                ensure_parent_dir(cached_path).and_then(|_| download())
+            }
+        };
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // If nobody else is waiting on the lock, we can get rid of it.
+        if Arc::strong_count(&path_lock) == 2 {
+            in_flight.remove(cached_path);
+        }
+        drop(in_flight);
+        self.update_stats(url, |stats| match &result {
+            Ok(CacheResult::AlreadyCached | CacheResult::NotModified) => stats.hits += 1,
+            Ok(CacheResult::NewlyCached) | Err(_) => stats.misses += 1,
+        });
+        result
+    }
 
-        Ok(CacheResult::NewlyCached)
+    /// Note that redirects are followed. If `precondition` is present, the
+    /// response may also be an HTTP 304.
+    fn get(&self, url: &str, precondition: Option<Precondition>) -> Result<Response> {
+        if self.offline {
+            return Err(anyhow!("Cache is offline, unable to fetch {url}"));
+        }
+        if let Some(shutdown) = &self.shutdown {
+            if shutdown.is_shutting_down() {
+                return Err(anyhow!("Shutting down, not fetching {url}"));
+            }
+        }
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(url_host(url));
+        }
+        self.update_stats(url, |stats| stats.requests += 1);
+        let is_conditional = precondition.is_some();
+        let result = match precondition {
+            Some(Precondition::ModifiedSince(since)) => {
+                self.transport.get_if_modified_since(url, since)
+            }
+            Some(Precondition::Headers(headers)) => self.transport.get_with_headers(url, &headers),
+            None => self.transport.get(url),
+        };
+        let response = match result.map_err(|err| *err) {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, _)) => return Err(HttpStatusError { status }.into()),
+            Err(err) => return Err(err.into()),
+        };
+        if is_conditional && response.status() == 304 {
+            return Ok(response);
+        }
+        validate_response(&response)?;
+        Ok(response)
     }
 
     pub fn load_cached_string<T: AsRef<str>>(&self, filename: T) -> Result<String> {
@@ -105,9 +561,126 @@ impl GalleryCache {
     }
 }
 
+fn now_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("current time should be after the unix epoch")
+        .as_secs() as i64
+}
+
+fn get_metadata_path(cached_path: &Path) -> PathBuf {
+    let mut filename = cached_path.file_name().unwrap_or_default().to_os_string();
+    filename.push(METADATA_SUFFIX);
+    cached_path.with_file_name(filename)
+}
+
+fn write_metadata(cached_path: &Path, metadata: &CacheMetadata) -> Result<()> {
+    let json = serde_json::to_string_pretty(metadata)?;
+    write_atomically(&get_metadata_path(cached_path), |outfile| {
+        outfile.write_all(json.as_bytes())?;
+        Ok(())
+    })
+}
+
+/// Records that the given cached file was just found to be up-to-date, if it
+/// has metadata.
+fn touch_metadata(cached_path: &Path) -> Result<()> {
+    let metadata_path = get_metadata_path(cached_path);
+    if !metadata_path.exists() {
+        return Ok(());
+    }
+    let mut metadata: CacheMetadata =
+        serde_json::from_str(&std::fs::read_to_string(&metadata_path)?)?;
+    metadata.fetched_at = now_timestamp();
+    write_metadata(cached_path, &metadata)
+}
+
+/// Returns the size of the cached file. If `shutdown` is given, the download
+/// is abandoned once its deadline passes.
+fn write_binary_response(
+    url: &str,
+    response: Response,
+    cached_path: &Path,
+    shutdown: Option<&ShutdownSignal>,
+) -> Result<u64> {
+    let mut metadata = CacheMetadata::from_response(url, &response, 0);
+    let mut response_body: Box<dyn Read + '_> = match shutdown {
+        Some(shutdown) => Box::new(ShutdownAwareReader::new(response.into_reader(), shutdown)),
+        None => response.into_reader(),
+    };
+    // TODO: Ideally we should prevent the file from growing too large, since the
+    // response may not have had a content-length header.
+    write_atomically(cached_path, |outfile| {
+        metadata.size = std::io::copy(&mut response_body, outfile)?;
+        Ok(())
+    })?;
+    write_metadata(cached_path, &metadata)?;
+    Ok(metadata.size)
+}
+
+/// Note that the JSON is pretty-printed before it's written. Returns the size
+/// of the cached file.
+fn write_json_response(url: &str, response: Response, cached_path: &Path) -> Result<u64> {
+    if response.content_type() != "application/json" {
+        return Err(anyhow!("Content type is {}", response.content_type()));
+    }
+    let mut metadata = CacheMetadata::from_response(url, &response, 0);
+    // TODO: Ideally we should prevent the response from growing too large, since the
+    // response may not have had a content-length header.
+    let response_body = response.into_string()?;
+    let json_body: serde_json::Value = serde_json::from_str(response_body.as_ref())?;
+    let pretty_printed = serde_json::to_string_pretty(&json_body)?;
+    metadata.size = pretty_printed.len() as u64;
+    write_atomically(cached_path, |outfile| {
+        outfile.write_all(pretty_printed.as_bytes())?;
+        Ok(())
+    })?;
+    write_metadata(cached_path, &metadata)?;
+    Ok(metadata.size)
+}
+
+/// Formats the given time as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn format_http_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let days = secs / 86400;
+    let secs_of_day = secs % 86400;
+    // This converts days since the epoch into a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
 fn validate_response(response: &Response) -> Result<()> {
     if response.status() != 200 {
-        return Err(anyhow!("Got HTTP {}", response.status()));
+        return Err(HttpStatusError {
+            status: response.status(),
+        }
+        .into());
     }
     // Annoyingly, the Met API doesn't serve a content-length header, so we can't
     // parse them, hence this is optional.
@@ -122,9 +695,699 @@ fn validate_response(response: &Response) -> Result<()> {
     Ok(())
 }
 
+/// Returns whether the file exists and is non-empty. If it looks like an image
+/// based on its extension, it also needs to start with an image format's magic
+/// bytes.
+fn is_valid_binary_file(path: &Path) -> bool {
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    let mut header = [0; 8];
+    let Ok(header_len) = file.read(&mut header) else {
+        return false;
+    };
+    if header_len == 0 {
+        return false;
+    }
+    let is_image = path.extension().is_some_and(|ext| {
+        IMAGE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+    });
+    !is_image
+        || IMAGE_MAGIC_BYTES
+            .iter()
+            .any(|magic| header[..header_len].starts_with(magic))
+}
+
+/// Returns whether the file looks like a complete JSON object or array, i.e.
+/// whether it starts with `{` or `[` and ends with `}` or `]`, ignoring
+/// whitespace. Cached JSON is always written atomically, so this is enough to
+/// catch files that were truncated some other way, without reading and parsing
+/// the whole thing.
+fn is_valid_json_file(path: &Path) -> bool {
+    matches!(
+        first_and_last_non_whitespace_bytes(path),
+        Ok(Some((b'{', b'}') | (b'[', b']')))
+    )
+}
+
+/// Only this many bytes at either end of a file are looked at, so a file padded
+/// with more whitespace than this is considered empty.
+const JSON_PEEK_LEN: u64 = 64;
+
+fn first_and_last_non_whitespace_bytes(path: &Path) -> std::io::Result<Option<(u8, u8)>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut head = vec![];
+    (&mut file).take(JSON_PEEK_LEN).read_to_end(&mut head)?;
+    file.seek(SeekFrom::Start(len.saturating_sub(JSON_PEEK_LEN)))?;
+    let mut tail = vec![];
+    file.take(JSON_PEEK_LEN).read_to_end(&mut tail)?;
+    let first = head.iter().find(|byte| !byte.is_ascii_whitespace());
+    let last = tail.iter().rfind(|byte| !byte.is_ascii_whitespace());
+    Ok(first.copied().zip(last.copied()))
+}
+
+/// Writes to a temporary file alongside the given path and then renames it into
+/// place, so that other processes never see a partially-written file.
+pub fn write_atomically<F: FnOnce(&mut File) -> Result<()>>(path: &Path, write: F) -> Result<()> {
+    let Some(filename) = path.file_name() else {
+        return Err(anyhow!("{} is not a file", path.display()));
+    };
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or_default();
+    let temp_path = path.with_file_name(format!(
+        "{}.tmp-{}-{}-{nanos}",
+        filename.to_string_lossy(),
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let result = File::create(&temp_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()?;
+            Ok(())
+        })
+        .and_then(|_| Ok(std::fs::rename(&temp_path, path)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
 pub fn ensure_parent_dir(cached_path: &PathBuf) -> Result<()> {
     if let Some(parent_dir) = cached_path.parent() {
         create_dir_all(parent_dir)?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread::sleep,
+        time::{Duration, SystemTime},
+    };
+
+    use ureq::Response;
+
+    use crate::shutdown::ShutdownSignal;
+
+    use super::{
+        format_http_date, get_http_status, is_valid_json_file, CacheMetadata, CacheResult,
+        GalleryCache, HostCacheStats, HttpTransport,
+    };
+
+    /// Responds to everything with a 404, keeping track of how many requests were made.
+    struct CountingTransport(Arc<AtomicUsize>);
+
+    impl HttpTransport for CountingTransport {
+        fn get(&self, _url: &str) -> Result<Response, Box<ureq::Error>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Err(Box::new(ureq::Error::Status(
+                404,
+                Response::new(404, "Not Found", "")?,
+            )))
+        }
+    }
+
+    /// Responds to everything with the given body and content type, slowly enough
+    /// that concurrent requests overlap, keeping track of how many requests were made.
+    struct SlowTransport {
+        requests: Arc<AtomicUsize>,
+        content_type: &'static str,
+        body: &'static str,
+    }
+
+    impl HttpTransport for SlowTransport {
+        fn get(&self, _url: &str) -> Result<Response, Box<ureq::Error>> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(50));
+            Ok(format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\n\r\n{}",
+                self.content_type, self.body
+            )
+            .parse()?)
+        }
+    }
+
+    /// Responds to everything with a GIF, but starts shutting down with the given
+    /// grace period first, as if the player quit while it was downloading.
+    struct QuittingTransport {
+        shutdown: Arc<ShutdownSignal>,
+        grace_period: Duration,
+    }
+
+    impl HttpTransport for QuittingTransport {
+        fn get(&self, _url: &str) -> Result<Response, Box<ureq::Error>> {
+            self.shutdown.begin(self.grace_period);
+            Ok("HTTP/1.1 200 OK\r\nContent-Type: image/gif\r\n\r\nGIF89a".parse()?)
+        }
+    }
+
+    /// Responds to conditional requests with a 304 and everything else with the
+    /// given JSON, keeping track of the `If-Modified-Since` times it was sent.
+    struct ConditionalTransport {
+        body: &'static str,
+        conditional_requests: Arc<Mutex<Vec<SystemTime>>>,
+    }
+
+    impl HttpTransport for ConditionalTransport {
+        fn get(&self, _url: &str) -> Result<Response, Box<ureq::Error>> {
+            Ok(format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{}",
+                self.body
+            )
+            .parse()?)
+        }
+
+        fn get_if_modified_since(
+            &self,
+            _url: &str,
+            since: SystemTime,
+        ) -> Result<Response, Box<ureq::Error>> {
+            self.conditional_requests.lock().unwrap().push(since);
+            Ok("HTTP/1.1 304 Not Modified\r\n\r\n".parse()?)
+        }
+    }
+
+    /// Serves the given JSON with an etag, responding to requests whose
+    /// `If-None-Match` header matches it with a 304, and keeping track of the
+    /// headers of conditional requests.
+    struct EtagTransport {
+        etag: Arc<Mutex<&'static str>>,
+        body: Arc<Mutex<&'static str>>,
+        conditional_requests: Arc<Mutex<Vec<Vec<(&'static str, String)>>>>,
+    }
+
+    impl HttpTransport for EtagTransport {
+        fn get(&self, _url: &str) -> Result<Response, Box<ureq::Error>> {
+            Ok(format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nETag: {}\r\nLast-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n{}",
+                self.etag.lock().unwrap(),
+                self.body.lock().unwrap()
+            )
+            .parse()?)
+        }
+
+        fn get_with_headers(
+            &self,
+            url: &str,
+            headers: &[(&'static str, String)],
+        ) -> Result<Response, Box<ureq::Error>> {
+            self.conditional_requests
+                .lock()
+                .unwrap()
+                .push(headers.to_vec());
+            let etag = *self.etag.lock().unwrap();
+            if headers
+                .iter()
+                .any(|(name, value)| *name == "If-None-Match" && value == etag)
+            {
+                return Ok("HTTP/1.1 304 Not Modified\r\n\r\n".parse()?);
+            }
+            self.get(url)
+        }
+    }
+
+    fn create_cache_dir(name: &str) -> PathBuf {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("gallery-cache-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn create_cache(name: &str) -> (GalleryCache, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let cache = GalleryCache::with_transport(
+            create_cache_dir(name),
+            Box::new(CountingTransport(requests.clone())),
+        );
+        (cache, requests)
+    }
+
+    fn create_slow_cache(
+        name: &str,
+        content_type: &'static str,
+        body: &'static str,
+    ) -> (GalleryCache, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let cache = GalleryCache::with_transport(
+            create_cache_dir(name),
+            Box::new(SlowTransport {
+                requests: requests.clone(),
+                content_type,
+                body,
+            }),
+        );
+        (cache, requests)
+    }
+
+    /// Returns the names of any temporary files left in the cache directory.
+    fn leftover_temp_files(cache: &GalleryCache) -> Vec<String> {
+        std::fs::read_dir(cache.cache_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.contains(".tmp-"))
+            .collect()
+    }
+
+    #[test]
+    fn test_offline_cache_never_uses_transport() {
+        let (mut cache, requests) = create_cache("offline");
+        let err = cache
+            .cache_binary_url("https://example.com/boop.jpg", "boop.jpg")
+            .unwrap_err();
+        assert_eq!(get_http_status(&err), Some(404));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        cache.set_offline(true);
+        assert!(cache.is_offline());
+        assert!(cache
+            .cache_binary_url("https://example.com/boop.jpg", "boop.jpg")
+            .is_err());
+        assert!(cache
+            .cache_json_url("https://example.com/boop.json", "boop.json", false)
+            .is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // This isn't a JPEG, so it shouldn't count as being cached.
+        std::fs::write(cache.get_cached_path("boop.jpg"), b"boop").unwrap();
+        assert!(cache
+            .cache_binary_url("https://example.com/boop.jpg", "boop.jpg")
+            .is_err());
+
+        std::fs::write(cache.get_cached_path("boop.jpg"), b"\xff\xd8\xffboop").unwrap();
+        assert_eq!(
+            cache
+                .cache_binary_url("https://example.com/boop.jpg", "boop.jpg")
+                .unwrap(),
+            CacheResult::AlreadyCached
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_get_if_cached_works() {
+        let (cache, requests) = create_cache("get-if-cached");
+        assert_eq!(cache.get_if_cached("sub/boop.jpg"), None);
+        std::fs::create_dir_all(cache.get_cached_path("sub")).unwrap();
+        std::fs::write(cache.get_cached_path("sub/boop.jpg"), b"boop").unwrap();
+        assert_eq!(
+            cache.get_if_cached("sub/boop.jpg"),
+            Some(cache.get_cached_path("sub/boop.jpg"))
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_json_is_recached() {
+        let (cache, requests) =
+            create_slow_cache("truncated-json", "application/json", r#"{"a": 1}"#);
+        let path = cache.get_cached_path("sub/boop.json");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, r#"{"a": "#).unwrap();
+
+        assert_eq!(
+            cache
+                .cache_json_url("https://example.com/boop.json", "sub/boop.json", false)
+                .unwrap(),
+            CacheResult::NewlyCached
+        );
+        let value: serde_json::Value =
+            serde_json::from_str(&cache.load_cached_string("sub/boop.json").unwrap()).unwrap();
+        assert_eq!(value, serde_json::json!({"a": 1}));
+        assert_eq!(
+            cache
+                .cache_json_url("https://example.com/boop.json", "sub/boop.json", false)
+                .unwrap(),
+            CacheResult::AlreadyCached
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_is_valid_json_file_works() {
+        let dir = create_cache_dir("is-valid-json");
+        let is_valid = |contents: &[u8]| {
+            let path = dir.join("boop.json");
+            std::fs::write(&path, contents).unwrap();
+            is_valid_json_file(&path)
+        };
+        assert!(is_valid(br#"{"a": 1}"#));
+        assert!(is_valid(b"\n  [1, 2, 3]\n\n"));
+        let big = format!("[{}]", vec!["1"; 1000].join(", "));
+        assert!(is_valid(big.as_bytes()));
+        assert!(!is_valid(b""));
+        assert!(!is_valid(b"  \n"));
+        assert!(!is_valid(br#"{"a": "#));
+        assert!(!is_valid(&big.as_bytes()[..big.len() - 1]));
+        assert!(!is_valid(b"\x89PNG\r\n\x1a\n}"));
+        assert!(!is_valid_json_file(&dir.join("nonexistent.json")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stats_count_hits_and_misses_per_host() {
+        let (cache, requests) = create_slow_cache("stats", "image/gif", "GIF89a");
+        for _ in 0..3 {
+            cache
+                .cache_binary_url("https://a.example.com/boop.gif", "boop.gif")
+                .unwrap();
+        }
+        cache
+            .cache_binary_url("https://b.example.com/boop.gif", "boop.gif")
+            .unwrap();
+        cache
+            .cache_binary_url("https://b.example.com/blap.gif", "blap.gif")
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        let hosts = cache.stats().hosts;
+        assert_eq!(
+            hosts.get("a.example.com"),
+            Some(&HostCacheStats {
+                requests: 1,
+                bytes_downloaded: 6,
+                hits: 2,
+                misses: 1,
+            })
+        );
+        assert_eq!(
+            hosts.get("b.example.com"),
+            Some(&HostCacheStats {
+                requests: 1,
+                bytes_downloaded: 6,
+                hits: 1,
+                misses: 1,
+            })
+        );
+        assert_eq!(hosts.len(), 2);
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_failed_fetches_count_as_misses() {
+        let (mut cache, _) = create_cache("stats-failed");
+        assert!(cache
+            .cache_binary_url("https://example.com/boop.jpg", "boop.jpg")
+            .is_err());
+        cache.set_offline(true);
+        assert!(cache
+            .cache_binary_url("https://example.com/boop.jpg", "boop.jpg")
+            .is_err());
+        assert_eq!(
+            cache.stats().hosts.get("example.com"),
+            Some(&HostCacheStats {
+                requests: 1,
+                bytes_downloaded: 0,
+                hits: 0,
+                misses: 2,
+            })
+        );
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_empty_image_is_recached() {
+        let (cache, requests) = create_slow_cache("empty-image", "image/gif", "GIF89a");
+        std::fs::write(cache.get_cached_path("boop.gif"), b"").unwrap();
+        assert_eq!(
+            cache
+                .cache_binary_url("https://example.com/boop.gif", "boop.gif")
+                .unwrap(),
+            CacheResult::NewlyCached
+        );
+        assert_eq!(
+            std::fs::read(cache.get_cached_path("boop.gif")).unwrap(),
+            b"GIF89a"
+        );
+        assert_eq!(leftover_temp_files(&cache), Vec::<String>::new());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_failed_json_cache_leaves_nothing_behind() {
+        let (cache, _) = create_slow_cache("failed-json", "application/json", "{boop");
+        assert!(cache
+            .cache_json_url("https://example.com/boop.json", "boop.json", false)
+            .is_err());
+        assert_eq!(cache.get_if_cached("boop.json"), None);
+        assert_eq!(leftover_temp_files(&cache), Vec::<String>::new());
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    fn create_quitting_cache(name: &str, grace_period: Duration) -> GalleryCache {
+        let shutdown = Arc::new(ShutdownSignal::new());
+        let mut cache = GalleryCache::with_transport(
+            create_cache_dir(name),
+            Box::new(QuittingTransport {
+                shutdown: shutdown.clone(),
+                grace_period,
+            }),
+        );
+        cache.set_shutdown_signal(Some(shutdown));
+        cache
+    }
+
+    #[test]
+    fn test_downloads_finish_during_shutdown_grace_period() {
+        let cache = create_quitting_cache("shutdown-grace", Duration::from_secs(60));
+        assert_eq!(
+            cache
+                .cache_binary_url("https://example.com/boop.gif", "boop.gif")
+                .unwrap(),
+            CacheResult::NewlyCached
+        );
+        assert_eq!(
+            std::fs::read(cache.get_cached_path("boop.gif")).unwrap(),
+            b"GIF89a"
+        );
+
+        // Nothing new is started once we're shutting down, though.
+        let err = cache
+            .cache_binary_url("https://example.com/bap.gif", "bap.gif")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Shutting down, not fetching https://example.com/bap.gif"
+        );
+        assert_eq!(cache.get_if_cached("bap.gif"), None);
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_downloads_are_abandoned_after_shutdown_grace_period() {
+        let cache = create_quitting_cache("shutdown-abandon", Duration::ZERO);
+        assert!(cache
+            .cache_binary_url("https://example.com/boop.gif", "boop.gif")
+            .is_err());
+        assert_eq!(cache.get_if_cached("boop.gif"), None);
+        assert_eq!(leftover_temp_files(&cache), Vec::<String>::new());
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_fetches_are_coalesced() {
+        let (cache, requests) = create_slow_cache("concurrent", "application/json", "[1, 2]");
+        let results: Vec<CacheResult> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        cache
+                            .cache_json_url("https://example.com/boop.json", "boop.json", false)
+                            .unwrap()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        let newly_cached = results
+            .iter()
+            .filter(|&result| result == &CacheResult::NewlyCached)
+            .count();
+        assert_eq!(newly_cached, 1);
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+        assert_eq!(leftover_temp_files(&cache), Vec::<String>::new());
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_force_refresh_refetches_valid_json() {
+        let (cache, requests) = create_slow_cache("force-refresh", "application/json", "[1, 2]");
+        std::fs::write(cache.get_cached_path("boop.json"), "[0]").unwrap();
+        assert_eq!(
+            cache
+                .cache_json_url("https://example.com/boop.json", "boop.json", false)
+                .unwrap(),
+            CacheResult::AlreadyCached
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            cache
+                .cache_json_url("https://example.com/boop.json", "boop.json", true)
+                .unwrap(),
+            CacheResult::NewlyCached
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        let value: serde_json::Value =
+            serde_json::from_str(&cache.load_cached_string("boop.json").unwrap()).unwrap();
+        assert_eq!(value, serde_json::json!([1, 2]));
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_force_refresh_uses_conditional_requests() {
+        let conditional_requests = Arc::new(Mutex::new(vec![]));
+        let cache = GalleryCache::with_transport(
+            create_cache_dir("conditional"),
+            Box::new(ConditionalTransport {
+                body: "[1, 2]",
+                conditional_requests: conditional_requests.clone(),
+            }),
+        );
+
+        // There's nothing to compare against yet, so this shouldn't be conditional.
+        assert_eq!(
+            cache
+                .cache_json_url("https://example.com/boop.json", "boop.json", true)
+                .unwrap(),
+            CacheResult::NewlyCached
+        );
+        assert!(conditional_requests.lock().unwrap().is_empty());
+
+        let modified_time = std::fs::metadata(cache.get_cached_path("boop.json"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(
+            cache
+                .cache_json_url("https://example.com/boop.json", "boop.json", true)
+                .unwrap(),
+            CacheResult::NotModified
+        );
+        assert_eq!(*conditional_requests.lock().unwrap(), vec![modified_time]);
+        assert_eq!(
+            cache.load_cached_string("boop.json").unwrap(),
+            "[\n  1,\n  2\n]"
+        );
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_is_written_alongside_cached_files() {
+        let (cache, _) = create_slow_cache("metadata", "image/gif", "GIF89a");
+        assert_eq!(cache.get_cache_metadata("sub/boop.gif").unwrap(), None);
+        cache
+            .cache_binary_url("https://example.com/boop.gif", "sub/boop.gif")
+            .unwrap();
+        let metadata = cache.get_cache_metadata("sub/boop.gif").unwrap().unwrap();
+        assert_eq!(
+            metadata,
+            CacheMetadata {
+                url: "https://example.com/boop.gif".to_string(),
+                status: 200,
+                etag: None,
+                last_modified: None,
+                size: 6,
+                ..metadata.clone()
+            }
+        );
+        assert!(!metadata.final_url.is_empty());
+        assert!(metadata.fetched_at > 0);
+        assert!(cache.get_if_cached("sub/boop.gif.meta.json").is_some());
+        assert_eq!(leftover_temp_files(&cache), Vec::<String>::new());
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_files_without_metadata_are_still_cached() {
+        let (cache, requests) = create_cache("no-metadata");
+        std::fs::write(cache.get_cached_path("boop.json"), "[0]").unwrap();
+        assert_eq!(
+            cache
+                .cache_json_url("https://example.com/boop.json", "boop.json", false)
+                .unwrap(),
+            CacheResult::AlreadyCached
+        );
+        assert_eq!(cache.get_cache_metadata("boop.json").unwrap(), None);
+        assert!(cache.revalidate("boop.json").is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_revalidate_uses_etags() {
+        let etag = Arc::new(Mutex::new("\"v1\""));
+        let body = Arc::new(Mutex::new("[1, 2]"));
+        let conditional_requests = Arc::new(Mutex::new(vec![]));
+        let cache = GalleryCache::with_transport(
+            create_cache_dir("revalidate"),
+            Box::new(EtagTransport {
+                etag: etag.clone(),
+                body: body.clone(),
+                conditional_requests: conditional_requests.clone(),
+            }),
+        );
+        cache
+            .cache_json_url("https://example.com/boop.json", "boop.json", false)
+            .unwrap();
+        let metadata = cache.get_cache_metadata("boop.json").unwrap().unwrap();
+        assert_eq!(metadata.etag, Some("\"v1\"".to_string()));
+        assert_eq!(
+            metadata.last_modified,
+            Some("Sun, 06 Nov 1994 08:49:37 GMT".to_string())
+        );
+        assert_eq!(metadata.size, "[\n  1,\n  2\n]".len() as u64);
+
+        assert!(!cache.revalidate("boop.json").unwrap());
+        assert_eq!(
+            *conditional_requests.lock().unwrap(),
+            vec![vec![
+                ("If-None-Match", "\"v1\"".to_string()),
+                (
+                    "If-Modified-Since",
+                    "Sun, 06 Nov 1994 08:49:37 GMT".to_string()
+                )
+            ]]
+        );
+
+        *etag.lock().unwrap() = "\"v2\"";
+        *body.lock().unwrap() = "[3]";
+        assert!(cache.revalidate("boop.json").unwrap());
+        assert_eq!(cache.load_cached_string("boop.json").unwrap(), "[\n  3\n]");
+        assert_eq!(
+            cache.get_cache_metadata("boop.json").unwrap().unwrap().etag,
+            Some("\"v2\"".to_string())
+        );
+        assert_eq!(conditional_requests.lock().unwrap().len(), 2);
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_format_http_date_works() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            format_http_date(SystemTime::UNIX_EPOCH),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+        let leap_day = SystemTime::UNIX_EPOCH + Duration::from_secs(1709210096);
+        assert_eq!(format_http_date(leap_day), "Thu, 29 Feb 2024 12:34:56 GMT");
+    }
+}
@@ -1,27 +1,107 @@
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{anyhow, Result};
-use rusqlite::{Connection, Transaction};
+use rusqlite::{
+    types::{ToSqlOutput, Value},
+    Connection, ErrorCode, OpenFlags, OptionalExtension, Row, ToSql, Transaction,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     art_object::ArtObjectId,
-    filter_parser::{parse_filter, Filter},
+    artist_names::{
+        artist_match_key, choose_artist_spelling, normalize_artist_name, NormalizedArtistName,
+    },
+    filter_parser::{is_valid_filter_macro_name, parse_filter, Filter},
+    gallery_db_schema::{check_gallery_db_schema, SchemaReport},
+    gallery_wall::GalleryWall,
+    gallery_zone::GalleryZone,
+    medium::MediumCategory,
+    met_api::MetImageUrls,
+    object_date::{parse_century, parse_year},
+    placement::Placement,
 };
 
-pub const LATEST_GALLERY_DB_VERSION: usize = 6;
+/// The version of the gallery DB's schema. Whenever the schema changes, this
+/// should be bumped, and a migration added to `gallery_db_migration`.
+pub const LATEST_GALLERY_DB_VERSION: usize = 15;
+
+/// The version in the gallery DB's filename. This used to be bumped along with
+/// `LATEST_GALLERY_DB_VERSION`, but now that the schema version is recorded
+/// inside the DB, schemas are migrated in place and the filename stays put.
+///
+/// DBs that don't record their schema version have the schema of the version
+/// in their filename.
+pub const GALLERY_DB_FILENAME_VERSION: usize = 13;
+
+/// How many moves `GalleryDb::undo_last_move()` can undo. Older moves are pruned
+/// from the layout history as new ones are recorded.
+pub const MAX_LAYOUT_HISTORY_ENTRIES: usize = 100;
 
 pub fn get_default_gallery_db_filename() -> String {
-    get_gallery_db_filename(LATEST_GALLERY_DB_VERSION)
+    get_gallery_db_filename(GALLERY_DB_FILENAME_VERSION)
 }
 
 pub fn get_gallery_db_filename(version: usize) -> String {
     format!("gallery{version}.sqlite")
 }
 
+/// Returns the schema version recorded in the gallery DB, or `None` if it
+/// doesn't record one, e.g. because it predates schema versions being recorded.
+///
+/// The version is kept in the main database's `user_version`, which survives
+/// resetting tables and isn't part of any table that could be dropped.
+pub fn get_gallery_db_schema_version(conn: &Connection) -> Result<Option<usize>> {
+    let version: i64 = conn.query_row("PRAGMA main.user_version", (), |row| row.get(0))?;
+    Ok((version > 0).then_some(version as usize))
+}
+
+pub fn set_gallery_db_schema_version(conn: &Connection, version: usize) -> Result<()> {
+    conn.execute_batch(&format!("PRAGMA main.user_version = {version}"))?;
+    Ok(())
+}
+
+/// Whitespace that SQLite's `trim()` is told to remove by
+/// `NORMALIZED_COLLECTION_SQL`.
+const COLLECTION_NAME_WHITESPACE: [char; 4] = [' ', '\t', '\r', '\n'];
+
+/// SQL that normalizes the `collection` column the same way
+/// `normalize_collection_name()` does. Note that SQLite's `lower()` only knows
+/// about ASCII letters.
+const NORMALIZED_COLLECTION_SQL: &str = "lower(trim(collection, char(32, 9, 13, 10)))";
+
+/// Normalize a collection name so that variants that only differ in case or
+/// surrounding whitespace, e.g. " Metropolitan museum of art", are the same.
+pub fn normalize_collection_name(name: &str) -> String {
+    name.trim_matches(&COLLECTION_NAME_WHITESPACE[..])
+        .to_ascii_lowercase()
+}
+
+/// Normalize a tag so that e.g. `Favorite` and `favorite ` are the same tag.
+/// Tags follow the same rules as saved filter names, so they can be used in
+/// `tag:<tag>` filters without quoting.
+pub fn normalize_tag(tag: &str) -> Result<String> {
+    let tag = tag.trim().to_lowercase();
+    if !is_valid_filter_macro_name(&tag) {
+        return Err(anyhow!(
+            "Tags can only contain letters, numbers, underscores and dashes: {tag:?}"
+        ));
+    }
+    Ok(tag)
+}
+
 #[derive(Default)]
 pub struct ArtObjectQueryOptions {
     pub filter: Option<String>,
+    /// Leave out art objects that have ever been laid out in a positive gallery,
+    /// see `get_previously_displayed_art_object_ids()`. Only
+    /// `get_all_art_objects_for_layout()` pays attention to this.
+    pub exclude_previously_displayed: bool,
 }
 
 impl ArtObjectQueryOptions {
@@ -29,12 +109,35 @@ impl ArtObjectQueryOptions {
         format!("ORDER BY id")
     }
 
-    pub fn where_clause(&self) -> (String, Vec<String>) {
+    /// Returns the SQL `WHERE` clause for the filter, along with its parameters.
+    ///
+    /// Any saved filters referenced by the filter are looked up with
+    /// `resolve_macro`, which returns `None` if there's no saved filter
+    /// with the given name.
+    ///
+    /// Returns an error if the filter can't be parsed or is too big, see
+    /// `FilterLimits`.
+    pub fn where_clause<F: Fn(&str) -> Result<Option<String>>>(
+        &self,
+        resolve_macro: F,
+    ) -> Result<(String, Vec<String>)> {
         let mut params: Vec<String> = vec![];
         let where_clause = if let Some(filter) = &self.filter {
-            if let Some(ast) = parse_filter(filter) {
+            if let Some(ast) = parse_filter(filter)? {
                 let mut query_parts = vec![];
-                filter_to_sql(ast, &mut query_parts, &mut params);
+                let mut context = FilterToSqlContext {
+                    query_parts: &mut query_parts,
+                    params: &mut params,
+                    resolve_macro: &resolve_macro,
+                    expanding_macros: vec![],
+                };
+                filter_to_sql(ast, &mut context)?;
+                if params.len() > MAX_FILTER_PARAMS {
+                    return Err(anyhow!(
+                        "Filter has {} terms once saved filters are expanded, but can have at most {MAX_FILTER_PARAMS}",
+                        params.len()
+                    ));
+                }
                 let query = query_parts.join("");
                 format!("WHERE {}", query)
             } else {
@@ -43,585 +146,4954 @@ impl ArtObjectQueryOptions {
         } else {
             String::default()
         };
-        (where_clause, params)
+        Ok((where_clause, params))
     }
 }
 
-fn filter_to_sql(filter: Filter, query_parts: &mut Vec<String>, params: &mut Vec<String>) {
+/// Returns an error unless the given (inclusive) range of gallery IDs is
+/// non-empty and only contains positive galleries.
+pub fn check_positive_gallery_range(first_gallery_id: i64, last_gallery_id: i64) -> Result<()> {
+    if first_gallery_id <= 0 || first_gallery_id > last_gallery_id {
+        return Err(anyhow!(
+            "Galleries {first_gallery_id} to {last_gallery_id} aren't a range of positive galleries!"
+        ));
+    }
+    Ok(())
+}
+
+/// How deeply saved filters can refer to other saved filters.
+pub const MAX_FILTER_MACRO_DEPTH: usize = 8;
+
+/// The most parameters a filter can have once saved filters are expanded. This
+/// is SQLite's default limit on the number of bound parameters in older versions.
+pub const MAX_FILTER_PARAMS: usize = 999;
+
+struct FilterToSqlContext<'a, F: Fn(&str) -> Result<Option<String>>> {
+    query_parts: &'a mut Vec<String>,
+    params: &'a mut Vec<String>,
+    resolve_macro: &'a F,
+    /// The names of the saved filters we're in the middle of expanding,
+    /// outermost first.
+    expanding_macros: Vec<String>,
+}
+
+fn filter_to_sql<F: Fn(&str) -> Result<Option<String>>>(
+    filter: Filter,
+    context: &mut FilterToSqlContext<F>,
+) -> Result<()> {
     match filter {
         Filter::And(a, b) => {
-            filter_to_sql(*a, query_parts, params);
-            query_parts.push(" AND ".into());
-            filter_to_sql(*b, query_parts, params);
+            filter_to_sql(*a, context)?;
+            context.query_parts.push(" AND ".into());
+            filter_to_sql(*b, context)?;
         }
         Filter::Or(a, b) => {
-            filter_to_sql(*a, query_parts, params);
-            query_parts.push(" OR ".into());
-            filter_to_sql(*b, query_parts, params);
+            filter_to_sql(*a, context)?;
+            context.query_parts.push(" OR ".into());
+            filter_to_sql(*b, context)?;
         }
         Filter::Not(value) => {
-            query_parts.push("NOT ".into());
-            filter_to_sql(*value, query_parts, params);
+            context.query_parts.push("NOT ".into());
+            filter_to_sql(*value, context)?;
+        }
+        Filter::Macro(name) => {
+            if context.expanding_macros.iter().any(|other| other == name) {
+                let chain: Vec<String> = context
+                    .expanding_macros
+                    .iter()
+                    .chain(std::iter::once(&name.to_string()))
+                    .map(|name| format!("@{name}"))
+                    .collect();
+                return Err(anyhow!(
+                    "Saved filter @{name} refers to itself ({})",
+                    chain.join(" -> ")
+                ));
+            }
+            if context.expanding_macros.len() >= MAX_FILTER_MACRO_DEPTH {
+                return Err(anyhow!(
+                    "Saved filters can only be nested {MAX_FILTER_MACRO_DEPTH} deep, but @{name} is nested deeper"
+                ));
+            }
+            let Some(definition) = (context.resolve_macro)(name)? else {
+                return Err(anyhow!("Saved filter @{name} does not exist"));
+            };
+            let ast = match parse_filter(&definition) {
+                Ok(Some(ast)) => ast,
+                Ok(None) => return Err(anyhow!("Saved filter @{name} is empty")),
+                Err(err) => return Err(anyhow!("Saved filter @{name} is invalid: {err}")),
+            };
+            context.expanding_macros.push(name.to_string());
+            context.query_parts.push("(".into());
+            filter_to_sql(ast, context)?;
+            context.query_parts.push(")".into());
+            context.expanding_macros.pop();
+        }
+        Filter::MediumCategory(category) => {
+            context.params.push(category.to_lowercase());
+            let num = context.params.len();
+            context
+                .query_parts
+                .push(format!("(medium_category = ?{num})"))
+        }
+        Filter::Highlight(highlight) => {
+            let value = if highlight { 1 } else { 0 };
+            context.query_parts.push(format!("(highlight = {value})"))
+        }
+        Filter::DateRange(start, end) => context
+            .query_parts
+            .push(format!("(date_year BETWEEN {start} AND {end})")),
+        Filter::Tag(tag) => {
+            context.params.push(tag.trim().to_lowercase());
+            let num = context.params.len();
+            // This matches either a tag the player added, or one of the
+            // collection's own subject tags. Note that `id` is the art
+            // object's, since the tags table doesn't have an `id` column, and
+            // that the unqualified `tags` is the art object's `tags` column.
+            context.query_parts.push(format!(
+                "(
+                    EXISTS (SELECT 1 FROM tags WHERE tags.art_object_id = id AND tags.tag = ?{num}) OR
+                    instr('|' || lower(tags) || '|', '|' || ?{num} || '|') > 0
+                )"
+            ))
+        }
+        Filter::Classification(classification) => {
+            context.params.push(format!("%{classification}%"));
+            let num = context.params.len();
+            context
+                .query_parts
+                .push(format!("(classification LIKE ?{num})"))
+        }
+        Filter::Collection(name) => {
+            context.params.push(normalize_collection_name(name));
+            let num = context.params.len();
+            context
+                .query_parts
+                .push(format!("({NORMALIZED_COLLECTION_SQL} = ?{num})"))
         }
         Filter::Term(term) => {
-            params.push(format!("%{term}%"));
-            let num = params.len();
-            query_parts.push(format!(
+            context.params.push(format!("%{term}%"));
+            let num = context.params.len();
+            context.query_parts.push(format!(
                 "(
                     (title LIKE ?{num}) OR
                     (artist LIKE ?{num}) OR
                     (medium LIKE ?{num}) OR
                     (culture LIKE ?{num}) OR
-                    (collection LIKE ?{num})
+                    (collection LIKE ?{num}) OR
+                    (tags LIKE ?{num}) OR
+                    (classification LIKE ?{num})
                 )"
             ))
         }
     }
+    Ok(())
+}
+
+/// The name that a save slot's layout database is attached as, see
+/// `GalleryDb::attach_layout_db()`.
+const LAYOUT_DB_SCHEMA: &str = "layout_db";
+
+/// The `user_version` of a layout database whose layout was copied over from
+/// the main database, see `GalleryDb::copy_main_layout_into_layout_db()`.
+const COPIED_MAIN_LAYOUT_USER_VERSION: i64 = 1;
+
+pub fn get_layout_db_filename(slot: &str) -> String {
+    format!("layout-{slot}.sqlite")
+}
+
+/// Save slot names end up in filenames, so they're limited to characters
+/// that are safe to use in them.
+pub fn is_valid_slot_name(slot: &str) -> bool {
+    !slot.is_empty()
+        && slot
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// How long `GalleryDb::open()` connections wait for another connection to let
+/// go of the database before giving up, e.g. a worker that's being replaced and
+/// is still finishing its last write.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returns whether the given error means the database was locked by another
+/// connection for longer than `BUSY_TIMEOUT`, in which case trying again
+/// later might work.
+pub fn is_busy_error(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<rusqlite::Error>()
+            .and_then(|err| err.sqlite_error_code()),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
 }
 
 pub struct GalleryDb {
     conn: Connection,
+    read_only: bool,
+    /// The schema containing the layout, galleries and layout metadata tables.
+    /// This is `main` unless a save slot's layout database has been attached.
+    layout_schema: &'static str,
 }
 
 impl GalleryDb {
     pub fn new(conn: Connection) -> Self {
-        GalleryDb { conn }
+        GalleryDb {
+            conn,
+            read_only: false,
+            layout_schema: "main",
+        }
     }
 
-    pub fn reset_layout_table(&mut self) -> Result<()> {
-        let tx = self.conn.transaction()?;
+    /// Open the database at the given path. If `read_only` is true, the underlying
+    /// connection is opened read-only, so any attempt to modify the database will fail.
+    pub fn open<P: AsRef<Path>>(path: P, read_only: bool) -> Result<Self> {
+        let conn = if read_only {
+            Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | OpenFlags::SQLITE_OPEN_URI
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?
+        } else {
+            Connection::open(path)?
+        };
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(GalleryDb {
+            conn,
+            read_only,
+            layout_schema: "main",
+        })
+    }
 
-        tx.execute("DROP TABLE IF EXISTS layout", ())?;
-        // Note that conceptually, `art_object_id` is a foreign key to the art_objects
-        // table, but we don't want to enforce a constraint because we want to
-        // be able to blow away the art_objects table for re-importing if needed.
-        tx.execute(
-            "
-            CREATE TABLE layout (
-                gallery_id INTEGER NOT NULL,
-                wall_id TEXT NOT NULL,
-                art_object_id INTEGER NOT NULL UNIQUE,
-                x REAL NOT NULL,
-                y REAL NOT NULL
-            )
-            ",
-            (),
+    /// Attach the layout database of a save slot, so that the layout (along with
+    /// the galleries and layout metadata) is read from and written to it, while
+    /// art objects are still read from the main database. Each save slot can
+    /// therefore have its own layout, while sharing all the art.
+    ///
+    /// The layout tables are created if they don't already exist, unless the
+    /// database is read-only.
+    pub fn attach_layout_db<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        if self.layout_schema != "main" {
+            return Err(anyhow!("A layout database is already attached"));
+        }
+        let path = path.as_ref();
+        if self.read_only && !path.exists() {
+            // Otherwise we'd get an obscure "unable to open database file" error.
+            return Err(anyhow!("Layout DB does not exist: {}", path.display()));
+        }
+        self.conn.execute(
+            &format!("ATTACH DATABASE ?1 AS {LAYOUT_DB_SCHEMA}"),
+            [path.to_string_lossy()],
         )?;
-        tx.commit()?;
-
+        self.layout_schema = LAYOUT_DB_SCHEMA;
+        if !self.read_only {
+            if self.has_layout_table("layout")? {
+                let tx = self.conn.transaction()?;
+                GalleryDb::add_missing_layout_columns(&tx, LAYOUT_DB_SCHEMA)?;
+                GalleryDb::create_layout_indexes_if_not_exist(&tx, LAYOUT_DB_SCHEMA)?;
+                tx.commit()?;
+            } else {
+                self.reset_layout_table()?;
+            }
+        }
         Ok(())
     }
 
-    pub fn upsert_layout_records_with_transaction<T: AsRef<str>>(
-        tx: &Transaction,
-        records: &Vec<LayoutRecord<T>>,
-    ) -> Result<()> {
-        for record in records {
+    /// If a save slot's layout database was attached and has never had the
+    /// layout copied into it, copies the layout, galleries and layout metadata
+    /// from the main database into it. This migrates databases from before save
+    /// slots existed, when everything was in a single file.
+    ///
+    /// Returns the number of layout records copied.
+    pub fn copy_main_layout_into_layout_db(&mut self) -> Result<usize> {
+        let layout_schema = self.layout_schema;
+        if layout_schema == "main" {
+            return Ok(0);
+        }
+        let tx = self.conn.transaction()?;
+        let user_version: i64 =
+            tx.query_row(&format!("PRAGMA {layout_schema}.user_version"), (), |row| {
+                row.get(0)
+            })?;
+        if user_version >= COPIED_MAIN_LAYOUT_USER_VERSION {
+            return Ok(0);
+        }
+        let mut copied = 0;
+        if GalleryDb::has_table_in_schema(&tx, "main", "layout")? {
+            GalleryDb::add_missing_layout_columns(&tx, layout_schema)?;
+            let rotated_column = GalleryDb::rotated_column(&tx, "main")?;
+            copied = tx.execute(
+                &format!(
+                    "
+                    INSERT OR IGNORE INTO {layout_schema}.layout (gallery_id, wall_id, art_object_id, x, y, rotated)
+                        SELECT gallery_id, wall_id, art_object_id, x, y, {rotated_column} FROM main.layout
+                    "
+                ),
+                (),
+            )?;
+        }
+        if GalleryDb::has_table_in_schema(&tx, "main", "galleries")? {
+            let reserved_walls_column =
+                if GalleryDb::has_column(&tx, "main", "galleries", "reserved_walls")? {
+                    "reserved_walls"
+                } else {
+                    "'[]'"
+                };
+            let segment_column = if GalleryDb::has_column(&tx, "main", "galleries", "segment")? {
+                "segment"
+            } else {
+                "NULL"
+            };
+            let source_gallery_column =
+                if GalleryDb::has_column(&tx, "main", "galleries", "source_gallery")? {
+                    "source_gallery"
+                } else {
+                    "NULL"
+                };
             tx.execute(
-            "
-                INSERT INTO layout (gallery_id, wall_id, art_object_id, x, y) VALUES (?1, ?2, ?3, ?4, ?5)
-                    ON CONFLICT(art_object_id) DO UPDATE SET
-                        gallery_id=excluded.gallery_id,
-                        wall_id=excluded.wall_id,
-                        x=excluded.x,
-                        y=excluded.y
-                ",
-        (
-                    &record.gallery_id,
-                    record.wall_id.as_ref(),
-                    &record.art_object_id.to_raw_i64(),
-                    &record.x,
-                    &record.y
+                &format!(
+                    "
+                    INSERT OR IGNORE INTO {layout_schema}.galleries (id, wall_set, reserved_walls, segment, source_gallery)
+                        SELECT id, wall_set, {reserved_walls_column}, {segment_column}, {source_gallery_column} FROM main.galleries
+                    "
                 ),
+                (),
             )?;
         }
-        Ok(())
+        if GalleryDb::has_table_in_schema(&tx, "main", "layout_metadata")? {
+            tx.execute(
+                &format!(
+                    "
+                    INSERT OR IGNORE INTO {layout_schema}.layout_metadata (key, value)
+                        SELECT key, value FROM main.layout_metadata
+                    "
+                ),
+                (),
+            )?;
+        }
+        // Note that this survives resetting the layout table, unlike anything we'd
+        // keep in the layout metadata.
+        tx.execute_batch(&format!(
+            "PRAGMA {layout_schema}.user_version = {COPIED_MAIN_LAYOUT_USER_VERSION}"
+        ))?;
+        tx.commit()?;
+        Ok(copied)
     }
 
-    pub fn upsert_layout_records<T: AsRef<str>>(
-        &mut self,
-        records: &Vec<LayoutRecord<T>>,
-    ) -> Result<()> {
-        let tx = self.conn.transaction()?;
-        GalleryDb::upsert_layout_records_with_transaction(&tx, records)?;
-        tx.commit()?;
-        Ok(())
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
-    pub fn get_art_object_ids_in_non_positive_galleries(&mut self) -> Result<HashSet<ArtObjectId>> {
-        let mut statement = self
-            .conn
-            .prepare("SELECT art_object_id FROM layout WHERE gallery_id <= 0")?;
-        let mut rows = statement.query(())?;
-        let mut result = HashSet::<ArtObjectId>::new();
-        while let Some(row) = rows.next()? {
-            let raw_id: i64 = row.get(0)?;
-            result.insert(ArtObjectId::from_raw_i64(raw_id));
-        }
-        Ok(result)
+    /// Returns the schema version recorded in the database, see
+    /// `get_gallery_db_schema_version()`.
+    pub fn schema_version(&self) -> Result<Option<usize>> {
+        get_gallery_db_schema_version(&self.conn)
     }
 
-    pub fn get_layout_records_in_non_positive_galleries(
-        &mut self,
-    ) -> Result<Vec<LayoutRecord<String>>> {
-        let mut statement = self.conn.prepare(
-            "
-                SELECT
-                    gallery_id,
-                    wall_id,
-                    art_object_id,
-                    x,
-                    y
-                FROM layout
-                WHERE gallery_id <= 0
-                ORDER BY gallery_id, wall_id, x, y
-                ",
-        )?;
-        let mut rows = statement.query(())?;
-        let mut result = Vec::<LayoutRecord<String>>::new();
-        while let Some(row) = rows.next()? {
-            result.push(LayoutRecord {
-                gallery_id: row.get(0)?,
-                wall_id: row.get(1)?,
-                art_object_id: ArtObjectId::from_raw_i64(row.get(2)?),
-                x: row.get(3)?,
-                y: row.get(4)?,
-            });
-        }
-        Ok(result)
+    /// Compares the tables that queries rely on against what they expect, so
+    /// a mismatch can be reported up front rather than as a cryptic error
+    /// whenever a query happens to need something that isn't there.
+    pub fn check_schema(&self) -> Result<SchemaReport> {
+        check_gallery_db_schema(&self.conn, self.layout_schema)
     }
 
-    pub fn clear_layout_records_in_non_positive_galleries(&mut self) -> Result<()> {
+    /// Returns the sizes of the database file and its write-ahead log, in bytes,
+    /// or `None` if the database isn't backed by a file.
+    fn get_file_sizes(&self) -> (Option<u64>, Option<u64>) {
+        let Some(path) = self.conn.path().filter(|path| !path.is_empty()) else {
+            return (None, None);
+        };
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return (None, None);
+        };
+        // The WAL file only exists while it's in use.
+        let wal_size = std::fs::metadata(format!("{path}-wal"))
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        (Some(metadata.len()), Some(wal_size))
+    }
+
+    /// Copy as much of the write-ahead log into the database as we can without
+    /// waiting on any other connections, so the log doesn't grow unbounded.
+    pub fn checkpoint_passive(&self) -> Result<()> {
         self.conn
-            .execute("DELETE FROM layout WHERE gallery_id <= 0", ())?;
+            .query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_row| Ok(()))?;
         Ok(())
     }
 
-    /// Clears the layout in positive galleries and fills it with the given records.
-    pub fn set_layout_records_in_positive_galleries<T: AsRef<str>>(
-        &mut self,
-        records: &Vec<LayoutRecord<T>>,
-    ) -> Result<()> {
-        let tx = self.conn.transaction()?;
-        tx.execute("DELETE FROM layout WHERE gallery_id > 0", ())?;
-        for record in records.iter() {
-            if record.gallery_id <= 0 {
-                return Err(anyhow!(
-                    "{:?} is not in a positive gallery!",
-                    record.art_object_id
-                ));
-            }
+    /// Checkpoint and truncate the write-ahead log, and update the query planner's
+    /// statistics.
+    ///
+    /// If `vacuum` is true, the database is also rebuilt to reclaim the space used
+    /// by deleted rows, e.g. from resetting tables. This needs exclusive access to
+    /// the database, and temporarily needs up to twice its size in free disk space.
+    pub fn maintenance(&mut self, vacuum: bool) -> Result<MaintenanceReport> {
+        if self.read_only {
+            return Err(anyhow!(
+                "Cannot perform maintenance on a read-only database"
+            ));
         }
-        GalleryDb::upsert_layout_records_with_transaction(&tx, records)?;
-        tx.commit()?;
-        Ok(())
+        let (db_size_before, wal_size_before) = self.get_file_sizes();
+        self.conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_row| Ok(()))?;
+        self.conn.execute_batch("PRAGMA optimize")?;
+        if vacuum {
+            self.conn.execute_batch("VACUUM")?;
+        }
+        let (db_size_after, wal_size_after) = self.get_file_sizes();
+        Ok(MaintenanceReport {
+            db_size_before,
+            db_size_after,
+            wal_size_before,
+            wal_size_after,
+            vacuumed: vacuum,
+        })
     }
 
-    pub fn count_art_objects(&self, options: &ArtObjectQueryOptions) -> Result<usize> {
-        let (where_clause, params) = options.where_clause();
-        let mut statement = self.conn.prepare(&format!(
-            "
-            SELECT COUNT(*) FROM art_objects {where_clause}
-            ",
-        ))?;
-        Ok(
-            statement.query_row(rusqlite::params_from_iter(params.into_iter()), |row| {
-                row.get(0)
-            })?,
-        )
+    pub fn passes_integrity_check(&self) -> Result<bool> {
+        let result: String = self
+            .conn
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+        Ok(result == "ok")
     }
 
-    pub fn get_all_art_objects_for_layout(
-        &self,
-        options: &ArtObjectQueryOptions,
-    ) -> Result<Vec<ArtObjectLayoutInfo>> {
-        let order_by_clause = options.order_by_clause();
-        let (where_clause, params) = options.where_clause();
-        let mut statement = self.conn.prepare(&format!(
-            "
-            SELECT id, width, height FROM art_objects {where_clause} {order_by_clause}
-            ",
+    /// Like `passes_integrity_check()`, but faster, since it skips some of the
+    /// more expensive checks.
+    pub fn passes_quick_check(&self) -> Result<bool> {
+        let result: String = self
+            .conn
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+        Ok(result == "ok")
+    }
+
+    /// Create all the tables in an empty database. Any existing tables are
+    /// dropped.
+    pub fn create_schema(&mut self) -> Result<()> {
+        self.reset_art_objects_table()?;
+        self.reset_layout_table()?;
+        Ok(())
+    }
+
+    /// Create the tables of a save slot's layout database in an empty database
+    /// that was opened on its own, rather than attached with
+    /// `attach_layout_db()`. It's marked as already having had the main
+    /// database's layout copied into it, since that layout is out of date, see
+    /// `copy_main_layout_into_layout_db()`.
+    pub fn create_layout_db_schema(&mut self) -> Result<()> {
+        self.reset_layout_table()?;
+        self.conn.execute_batch(&format!(
+            "PRAGMA {}.user_version = {COPIED_MAIN_LAYOUT_USER_VERSION}",
+            self.layout_schema
         ))?;
-        let mut rows = statement.query(rusqlite::params_from_iter(params.into_iter()))?;
-        let mut result: Vec<ArtObjectLayoutInfo> = Vec::new();
-        while let Some(row) = rows.next()? {
-            result.push(ArtObjectLayoutInfo {
-                id: ArtObjectId::from_raw_i64(row.get(0)?),
-                width: row.get(1)?,
-                height: row.get(2)?,
-            });
-        }
-        Ok(result)
+        Ok(())
     }
 
-    pub fn reset_art_objects_table(&mut self) -> Result<()> {
+    pub fn reset_layout_table(&mut self) -> Result<()> {
+        let schema = self.layout_schema;
         let tx = self.conn.transaction()?;
 
-        tx.execute("DROP TABLE IF EXISTS art_objects", ())?;
+        tx.execute(&format!("DROP TABLE IF EXISTS {schema}.layout"), ())?;
+        // Note that conceptually, `art_object_id` is a foreign key to the art_objects
+        // table, but we don't want to enforce a constraint because we want to
+        // be able to blow away the art_objects table for re-importing if needed.
         tx.execute(
-            "
-            CREATE TABLE art_objects (
-                id INTEGER PRIMARY KEY,
-                title TEXT NOT NULL,
-                artist TEXT NOT NULL,
-                culture TEXT NOT NULL,
-                date TEXT NOT NULL,
-                medium TEXT NOT NULL,
-                width REAL NOT NULL,
-                height REAL NOT NULL,
-                fallback_wikidata_qid INTEGER,
-                filename TEXT NOT NULL,
-                collection TEXT NOT NULL
-            )
-            ",
+            &format!(
+                "
+                CREATE TABLE {schema}.layout (
+                    gallery_id INTEGER NOT NULL,
+                    wall_id TEXT NOT NULL,
+                    art_object_id INTEGER NOT NULL UNIQUE,
+                    x REAL NOT NULL,
+                    y REAL NOT NULL,
+                    anchor_x REAL,
+                    anchor_y REAL,
+                    rotated INTEGER NOT NULL DEFAULT 0,
+                    modified_at INTEGER NOT NULL DEFAULT 0
+                )
+                "
+            ),
             (),
         )?;
-
+        GalleryDb::create_layout_indexes_if_not_exist(&tx, schema)?;
+        tx.execute(&format!("DROP TABLE IF EXISTS {schema}.galleries"), ())?;
+        GalleryDb::create_galleries_table_if_not_exists(&tx, schema)?;
+        tx.execute(
+            &format!("DROP TABLE IF EXISTS {schema}.layout_metadata"),
+            (),
+        )?;
+        GalleryDb::create_layout_metadata_table_if_not_exists(&tx, schema)?;
+        tx.execute(&format!("DROP TABLE IF EXISTS {schema}.layout_history"), ())?;
+        GalleryDb::create_layout_history_table_if_not_exists(&tx, schema)?;
         tx.commit()?;
 
         Ok(())
     }
 
-    /// Add a bunch of records in a single transaction. This is much faster than adding
-    /// a single record in a single transaction.
-    pub fn add_art_objects(&mut self, records: &Vec<ArtObjectRecord>) -> Result<()> {
-        let tx = self.conn.transaction()?;
-
-        for record in records {
-            tx.execute(
+    /// The galleries table holds metadata about the galleries in the layout. It was added
+    /// after the layout table, so older databases might not have it.
+    fn create_galleries_table_if_not_exists(tx: &Transaction, schema: &str) -> Result<()> {
+        tx.execute(
+            &format!(
                 "
-                INSERT INTO art_objects (
-                    id,
-                    title,
-                    date,
-                    medium,
-                    width,
-                    height,
-                    artist,
-                    culture,
-                    fallback_wikidata_qid,
-                    filename,
-                    collection
-                ) VALUES (
-                    ?1,
-                    ?2,
-                    ?3,
-                    ?4,
-                    ?5,
-                    ?6,
-                    ?7,
-                    ?8,
-                    ?9,
-                    ?10,
-                    ?11
+                CREATE TABLE IF NOT EXISTS {schema}.galleries (
+                    id INTEGER PRIMARY KEY,
+                    wall_set TEXT NOT NULL,
+                    reserved_walls TEXT NOT NULL DEFAULT '[]',
+                    segment TEXT,
+                    source_gallery TEXT
                 )
-                ",
-                (
-                    &record.object_id.to_raw_i64(),
-                    &record.title,
-                    &record.object_date,
-                    &record.medium,
-                    &record.width,
-                    &record.height,
-                    &record.artist,
-                    &record.culture,
-                    &record.fallback_wikidata_qid,
-                    &record.filename,
-                    &record.collection,
-                ),
-            )?;
+                "
+            ),
+            (),
+        )?;
+        // These columns were added after the galleries table.
+        let added_columns = [
+            ("reserved_walls", "TEXT NOT NULL DEFAULT '[]'"),
+            ("segment", "TEXT"),
+            ("source_gallery", "TEXT"),
+        ];
+        for (column, definition) in added_columns {
+            if !GalleryDb::has_column(tx, schema, "galleries", column)? {
+                tx.execute(
+                    &format!("ALTER TABLE {schema}.galleries ADD COLUMN {column} {definition}"),
+                    (),
+                )?;
+            }
         }
+        Ok(())
+    }
 
-        tx.commit()?;
+    /// Older layout tables are missing some columns, which are added here. Note that
+    /// read-only databases can't be altered, so queries that read these columns
+    /// should check that they exist, e.g. via `rotated_column()`.
+    fn add_missing_layout_columns(tx: &Transaction, schema: &str) -> Result<()> {
+        let added_columns = [
+            ("rotated", "INTEGER NOT NULL DEFAULT 0"),
+            ("modified_at", "INTEGER NOT NULL DEFAULT 0"),
+        ];
+        for (column, definition) in added_columns {
+            if !GalleryDb::has_column(tx, schema, "layout", column)? {
+                tx.execute(
+                    &format!("ALTER TABLE {schema}.layout ADD COLUMN {column} {definition}"),
+                    (),
+                )?;
+            }
+        }
+        Ok(())
+    }
 
+    /// Lookups by art object are already indexed thanks to the `UNIQUE` constraint,
+    /// but fetching the art objects on a wall would otherwise scan the whole table.
+    fn create_layout_indexes_if_not_exist(tx: &Transaction, schema: &str) -> Result<()> {
+        tx.execute(
+            &format!(
+                "CREATE INDEX IF NOT EXISTS {schema}.layout_gallery_wall ON layout (gallery_id, wall_id)"
+            ),
+            (),
+        )?;
         Ok(())
     }
 
-    pub fn get_art_object(&self, object_id: ArtObjectId) -> Result<Option<ArtObjectRecord>> {
-        let mut statement = self.conn.prepare_cached(
-            "
-                SELECT
-                    ao.title,
-                    ao.date,
-                    ao.medium,
-                    ao.width,
-                    ao.height,
-                    ao.artist,
-                    ao.culture,
-                    ao.fallback_wikidata_qid,
-                    ao.filename,
-                    ao.collection
-                FROM
-                    art_objects AS ao
-                WHERE
-                    ao.id = ?1",
+    /// Returns an expression for selecting whether layout records are rotated,
+    /// which works even if the layout table predates the `rotated` column.
+    fn rotated_column(conn: &Connection, schema: &str) -> Result<&'static str> {
+        Ok(
+            if GalleryDb::has_column(conn, schema, "layout", "rotated")? {
+                "rotated"
+            } else {
+                "0"
+            },
+        )
+    }
+
+    /// Like `rotated_column()`, but for when layout records were last changed.
+    fn modified_at_column(conn: &Connection, schema: &str) -> Result<&'static str> {
+        Ok(
+            if GalleryDb::has_column(conn, schema, "layout", "modified_at")? {
+                "modified_at"
+            } else {
+                "0"
+            },
+        )
+    }
+
+    /// The layout metadata table holds information about the layout as a whole, e.g. the
+    /// walls it was made for. Like the galleries table, older databases might not have it.
+    fn create_layout_metadata_table_if_not_exists(tx: &Transaction, schema: &str) -> Result<()> {
+        tx.execute(
+            &format!(
+                "
+                CREATE TABLE IF NOT EXISTS {schema}.layout_metadata (
+                    key TEXT PRIMARY KEY,
+                    value TEXT NOT NULL
+                )
+                "
+            ),
+            (),
         )?;
-        let mut rows = statement.query([object_id.to_raw_i64()])?;
-        let Some(row) = rows.next()? else {
-            return Ok(None);
-        };
-        Ok(Some(ArtObjectRecord {
-            object_id,
-            title: row.get(0)?,
-            object_date: row.get(1)?,
-            medium: row.get(2)?,
-            width: row.get(3)?,
-            height: row.get(4)?,
-            artist: row.get(5)?,
-            culture: row.get(6)?,
-            fallback_wikidata_qid: row.get(7)?,
-            filename: row.get(8)?,
-            collection: row.get(9)?,
-        }))
+        Ok(())
     }
 
-    pub fn get_art_objects_for_gallery_wall<T: AsRef<str>>(
-        &self,
-        gallery_id: i64,
-        wall_id: T,
-    ) -> Result<Vec<(ArtObjectRecord, (f64, f64))>> {
-        let mut result = vec![];
+    /// The layout history table holds the moves that can be undone, see
+    /// `upsert_layout_record_with_history()`. Like the galleries table, older
+    /// databases might not have it. It isn't part of exported layouts, so
+    /// moves can't be undone across autosyncs.
+    fn create_layout_history_table_if_not_exists(tx: &Transaction, schema: &str) -> Result<()> {
+        tx.execute(
+            &format!(
+                "
+                CREATE TABLE IF NOT EXISTS {schema}.layout_history (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    art_object_id INTEGER NOT NULL,
+                    old_gallery_id INTEGER,
+                    old_wall_id TEXT,
+                    old_x REAL,
+                    old_y REAL,
+                    old_rotated INTEGER,
+                    new_gallery_id INTEGER NOT NULL,
+                    new_wall_id TEXT NOT NULL,
+                    new_x REAL NOT NULL,
+                    new_y REAL NOT NULL,
+                    new_rotated INTEGER NOT NULL,
+                    timestamp INTEGER NOT NULL
+                )
+                "
+            ),
+            (),
+        )?;
+        Ok(())
+    }
 
-        let mut statement = self.conn.prepare_cached(
-            "
-            SELECT
-                layout.art_object_id,
-                layout.x,
-                layout.y,
-                ao.title,
-                ao.date,
-                ao.medium,
-                ao.width,
-                ao.height,
-                ao.artist,
-                ao.culture,
-                ao.fallback_wikidata_qid,
-                ao.filename,
-                ao.collection
-            FROM
-                art_objects AS ao
-            INNER JOIN
-                layout
-            ON
-                layout.art_object_id = ao.id
-            WHERE
-                layout.gallery_id = ?1 AND
-                layout.wall_id = ?2
-            ",
+    /// The displayed history table holds every art object that has ever been laid
+    /// out in a positive gallery, e.g. so rotating exhibitions can stick to art
+    /// that hasn't been shown yet. Unlike the other layout tables, resetting the
+    /// layout doesn't clear it.
+    fn create_displayed_history_table_if_not_exists(tx: &Transaction, schema: &str) -> Result<()> {
+        tx.execute(
+            &format!(
+                "
+                CREATE TABLE IF NOT EXISTS {schema}.displayed_history (
+                    art_object_id INTEGER PRIMARY KEY
+                )
+                "
+            ),
+            (),
         )?;
-        let mut rows = statement.query(rusqlite::params![&gallery_id, wall_id.as_ref()])?;
-        while let Some(row) = rows.next()? {
-            let id = ArtObjectId::from_raw_i64(row.get(0)?);
-            let location: (f64, f64) = (row.get(1)?, row.get(2)?);
-            let object = ArtObjectRecord {
-                object_id: id,
-                title: row.get(3)?,
-                object_date: row.get(4)?,
-                medium: row.get(5)?,
-                width: row.get(6)?,
-                height: row.get(7)?,
-                artist: row.get(8)?,
-                culture: row.get(9)?,
-                fallback_wikidata_qid: row.get(10)?,
-                filename: row.get(11)?,
-                collection: row.get(12)?,
-            };
-            result.push((object, location));
+        Ok(())
+    }
+
+    fn record_displayed_with_transaction<T: AsRef<str>>(
+        tx: &Transaction,
+        schema: &str,
+        records: &Vec<LayoutRecord<T>>,
+    ) -> Result<()> {
+        GalleryDb::create_displayed_history_table_if_not_exists(tx, schema)?;
+        for record in records {
+            tx.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO {schema}.displayed_history (art_object_id) VALUES (?1)"
+                ),
+                [record.art_object_id.to_raw_i64()],
+            )?;
         }
+        Ok(())
+    }
 
+    /// Returns every art object that has ever been laid out in a positive gallery.
+    pub fn get_previously_displayed_art_object_ids(&self) -> Result<HashSet<ArtObjectId>> {
+        if !self.has_layout_table("displayed_history")? {
+            return Ok(HashSet::new());
+        }
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT art_object_id FROM {}.displayed_history",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query(())?;
+        let mut result = HashSet::new();
+        while let Some(row) = rows.next()? {
+            result.insert(ArtObjectId::from_raw_i64(row.get(0)?));
+        }
         Ok(result)
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub struct ArtObjectRecord {
-    pub object_id: ArtObjectId,
-    pub object_date: String,
-    pub culture: String,
-    pub artist: String,
-    pub title: String,
-    pub medium: String,
-    pub width: f64,
-    pub height: f64,
-    pub fallback_wikidata_qid: Option<i64>,
-    pub filename: String,
-    pub collection: String,
-}
+    /// Forgets every move that could have been undone, e.g. because the art
+    /// objects they moved have since been laid out somewhere else.
+    fn clear_layout_history_with_transaction(tx: &Transaction, schema: &str) -> Result<()> {
+        if GalleryDb::has_table_in_schema(tx, schema, "layout_history")? {
+            tx.execute(&format!("DELETE FROM {schema}.layout_history"), ())?;
+        }
+        Ok(())
+    }
 
-#[derive(Debug, PartialEq)]
-pub struct ArtObjectLayoutInfo {
-    pub id: ArtObjectId,
-    pub width: f64,
-    pub height: f64,
-}
+    pub fn get_layout_metadata(&self, key: &str) -> Result<Option<String>> {
+        if !self.has_layout_table("layout_metadata")? {
+            return Ok(None);
+        }
+        let mut statement = self.conn.prepare_cached(&format!(
+            "SELECT value FROM {}.layout_metadata WHERE key = ?1",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query([key])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        Ok(Some(row.get(0)?))
+    }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub struct LayoutRecord<T: AsRef<str>> {
-    pub gallery_id: i64,
-    pub wall_id: T,
-    pub art_object_id: ArtObjectId,
-    pub x: f64,
-    pub y: f64,
-}
+    pub fn set_layout_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        GalleryDb::create_layout_metadata_table_if_not_exists(&tx, schema)?;
+        tx.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {schema}.layout_metadata (key, value) VALUES (?1, ?2)"
+            ),
+            [key, value],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
 
-#[cfg(test)]
-mod tests {
-    use rusqlite::Connection;
+    fn has_column(conn: &Connection, schema: &str, table: &str, column: &str) -> Result<bool> {
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info(?1, ?2) WHERE name = ?3",
+            [table, schema, column],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
 
-    use crate::{
-        art_object::ArtObjectId,
-        gallery_db::{ArtObjectQueryOptions, LayoutRecord},
-    };
+    /// Clears the metadata for positive galleries and fills it with the given records.
+    pub fn set_gallery_records_in_positive_galleries(
+        &mut self,
+        records: &Vec<GalleryRecord>,
+    ) -> Result<()> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        GalleryDb::create_galleries_table_if_not_exists(&tx, schema)?;
+        tx.execute(&format!("DELETE FROM {schema}.galleries WHERE id > 0"), ())?;
+        for record in records {
+            if record.gallery_id <= 0 {
+                return Err(anyhow!(
+                    "Gallery {} is not a positive gallery!",
+                    record.gallery_id
+                ));
+            }
+            tx.execute(
+                &format!("INSERT INTO {schema}.galleries (id, wall_set, reserved_walls, segment, source_gallery) VALUES (?1, ?2, ?3, ?4, ?5)"),
+                (
+                    &record.gallery_id,
+                    &record.wall_set,
+                    serde_json::to_string(&record.reserved_walls)?,
+                    &record.segment,
+                    &record.source_gallery,
+                ),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
 
-    use super::{ArtObjectLayoutInfo, ArtObjectRecord, GalleryDb};
+    /// Like `set_gallery_records_in_positive_galleries()`, but only for the
+    /// given (inclusive) range of galleries, see `set_layout_records_in_gallery_range()`.
+    pub fn set_gallery_records_in_gallery_range(
+        &mut self,
+        records: &Vec<GalleryRecord>,
+        (first_gallery_id, last_gallery_id): (i64, i64),
+    ) -> Result<()> {
+        check_positive_gallery_range(first_gallery_id, last_gallery_id)?;
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        GalleryDb::create_galleries_table_if_not_exists(&tx, schema)?;
+        tx.execute(
+            &format!("DELETE FROM {schema}.galleries WHERE id BETWEEN ?1 AND ?2"),
+            (first_gallery_id, last_gallery_id),
+        )?;
+        for record in records {
+            if record.gallery_id < first_gallery_id || record.gallery_id > last_gallery_id {
+                return Err(anyhow!(
+                    "Gallery {} is not in galleries {first_gallery_id} to {last_gallery_id}!",
+                    record.gallery_id
+                ));
+            }
+            tx.execute(
+                &format!("INSERT INTO {schema}.galleries (id, wall_set, reserved_walls, segment, source_gallery) VALUES (?1, ?2, ?3, ?4, ?5)"),
+                (
+                    &record.gallery_id,
+                    &record.wall_set,
+                    serde_json::to_string(&record.reserved_walls)?,
+                    &record.segment,
+                    &record.source_gallery,
+                ),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
 
-    const FUNKY_PAINTING_ID: ArtObjectId = ArtObjectId::Met(1);
-    const MONKEY_PAINTING_ID: ArtObjectId = ArtObjectId::Wikidata(5);
+    pub fn has_table(&self, name: &str) -> Result<bool> {
+        GalleryDb::has_table_in_schema(&self.conn, "main", name)
+    }
 
-    fn make_funky_painting() -> ArtObjectRecord {
-        ArtObjectRecord {
-            object_id: FUNKY_PAINTING_ID,
-            object_date: "1864".into(),
-            culture: "Martian".into(),
-            artist: "Boop Jones".into(),
-            title: "Funky Painting".into(),
-            medium: "Oil on canvas".into(),
-            width: 64.5,
-            height: 28.2,
-            fallback_wikidata_qid: Some(1234),
-            filename: "funky-painting.jpg".into(),
-            collection: "Martian Museum of Art".into(),
-        }
+    /// Like `has_table()`, but for tables that live alongside the layout.
+    fn has_layout_table(&self, name: &str) -> Result<bool> {
+        GalleryDb::has_table_in_schema(&self.conn, self.layout_schema, name)
     }
 
-    fn make_monkey_painting() -> ArtObjectRecord {
-        ArtObjectRecord {
-            object_id: MONKEY_PAINTING_ID,
-            object_date: "1914".into(),
-            culture: "Simian".into(),
-            artist: "Curious George".into(),
-            title: "A Funky Monkey".into(),
-            medium: "Oil on canvas".into(),
-            width: 128.5,
-            height: 12.2,
-            fallback_wikidata_qid: None,
-            filename: "monkey-painting.jpg".into(),
-            collection: "Monkey Museum of Art".into(),
-        }
+    fn has_table_in_schema(conn: &Connection, schema: &str, name: &str) -> Result<bool> {
+        let count: i64 = conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM {schema}.sqlite_master WHERE type = 'table' AND name = ?1"
+            ),
+            [name],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
     }
 
-    impl Into<ArtObjectLayoutInfo> for ArtObjectRecord {
-        fn into(self) -> ArtObjectLayoutInfo {
-            ArtObjectLayoutInfo {
-                id: self.object_id,
-                width: self.width,
-                height: self.height,
-            }
+    pub fn get_gallery_record(&self, gallery_id: i64) -> Result<Option<GalleryRecord>> {
+        let schema = self.layout_schema;
+        if !self.has_layout_table("galleries")? {
+            // Older databases don't have a galleries table.
+            return Ok(None);
         }
+        // Older galleries tables might not have some columns either.
+        let reserved_walls_column =
+            if GalleryDb::has_column(&self.conn, schema, "galleries", "reserved_walls")? {
+                "reserved_walls"
+            } else {
+                "'[]'"
+            };
+        let segment_column = if GalleryDb::has_column(&self.conn, schema, "galleries", "segment")? {
+            "segment"
+        } else {
+            "NULL"
+        };
+        let source_gallery_column =
+            if GalleryDb::has_column(&self.conn, schema, "galleries", "source_gallery")? {
+                "source_gallery"
+            } else {
+                "NULL"
+            };
+        let sql = format!(
+            "SELECT id, wall_set, {reserved_walls_column}, {segment_column}, {source_gallery_column} FROM {schema}.galleries WHERE id = ?1"
+        );
+        let mut statement = self.conn.prepare_cached(&sql)?;
+        let mut rows = statement.query([gallery_id])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let reserved_walls: String = row.get(2)?;
+        Ok(Some(GalleryRecord {
+            gallery_id: row.get(0)?,
+            wall_set: row.get(1)?,
+            reserved_walls: serde_json::from_str(&reserved_walls)?,
+            segment: row.get(3)?,
+            source_gallery: row.get(4)?,
+        }))
     }
 
-    fn test_filter(db: &GalleryDb, filter: &'static str, expected: &Vec<ArtObjectLayoutInfo>) {
-        let options = ArtObjectQueryOptions {
-            filter: Some(filter.into()),
-        };
-        let actual = db.get_all_art_objects_for_layout(&options).unwrap();
-        assert_eq!(&actual, expected);
-        assert_eq!(db.count_art_objects(&options).unwrap(), expected.len());
+    /// Upserts the given records, noting that they were modified just now.
+    fn upsert_layout_records_with_transaction<T: AsRef<str>>(
+        tx: &Transaction,
+        schema: &str,
+        records: &Vec<LayoutRecord<T>>,
+    ) -> Result<()> {
+        let modified_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("current time should be after the unix epoch")
+            .as_secs() as i64;
+        GalleryDb::upsert_timestamped_layout_records_with_transaction(
+            tx,
+            schema,
+            records.iter().map(|record| (record, modified_at)),
+        )
+    }
+
+    /// Upserts the given records, along with when each was modified.
+    fn upsert_timestamped_layout_records_with_transaction<'a, T: AsRef<str> + 'a>(
+        tx: &Transaction,
+        schema: &str,
+        records: impl IntoIterator<Item = (&'a LayoutRecord<T>, i64)>,
+    ) -> Result<()> {
+        GalleryDb::add_missing_layout_columns(tx, schema)?;
+        for (record, modified_at) in records {
+            tx.execute(
+            &format!("
+                INSERT INTO {schema}.layout (gallery_id, wall_id, art_object_id, x, y, rotated, modified_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                    ON CONFLICT(art_object_id) DO UPDATE SET
+                        gallery_id=excluded.gallery_id,
+                        wall_id=excluded.wall_id,
+                        x=excluded.x,
+                        y=excluded.y,
+                        rotated=excluded.rotated,
+                        modified_at=excluded.modified_at,
+                        anchor_x=NULL,
+                        anchor_y=NULL
+                "),
+        (
+                    &record.gallery_id,
+                    record.wall_id.as_ref(),
+                    &record.art_object_id.to_raw_i64(),
+                    &record.x,
+                    &record.y,
+                    &record.rotated,
+                    modified_at,
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns where the given art object is in the layout, if anywhere.
+    pub fn get_layout_record(
+        &self,
+        art_object_id: ArtObjectId,
+    ) -> Result<Option<LayoutRecord<String>>> {
+        GalleryDb::get_layout_record_with_connection(&self.conn, self.layout_schema, art_object_id)
+    }
+
+    /// Returns which zone the given art object is in, if it's in the layout.
+    pub fn get_gallery_zone(&self, art_object_id: ArtObjectId) -> Result<Option<GalleryZone>> {
+        Ok(self
+            .get_layout_record(art_object_id)?
+            .map(|record| GalleryZone::from_gallery_id(record.gallery_id)))
+    }
+
+    fn get_layout_record_with_connection(
+        conn: &Connection,
+        schema: &str,
+        art_object_id: ArtObjectId,
+    ) -> Result<Option<LayoutRecord<String>>> {
+        let rotated_column = GalleryDb::rotated_column(conn, schema)?;
+        let mut statement = conn.prepare_cached(&format!(
+            "SELECT gallery_id, wall_id, x, y, {rotated_column} FROM {schema}.layout WHERE art_object_id = ?1"
+        ))?;
+        let mut rows = statement.query([art_object_id.to_raw_i64()])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        Ok(Some(LayoutRecord {
+            gallery_id: row.get(0)?,
+            wall_id: row.get(1)?,
+            art_object_id,
+            x: row.get(2)?,
+            y: row.get(3)?,
+            rotated: row.get(4)?,
+        }))
+    }
+
+    /// Like `upsert_layout_records()`, but for a single record, and remembers where
+    /// the art object was beforehand so the move can be undone via `undo_last_move()`.
+    pub fn upsert_layout_record_with_history<T: AsRef<str>>(
+        &mut self,
+        record: &LayoutRecord<T>,
+    ) -> Result<()> {
+        let schema = self.layout_schema;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("current time should be after the unix epoch")
+            .as_secs() as i64;
+        let tx = self.conn.transaction()?;
+        GalleryDb::create_layout_history_table_if_not_exists(&tx, schema)?;
+        let old = GalleryDb::get_layout_record_with_connection(&tx, schema, record.art_object_id)?;
+        tx.execute(
+            &format!(
+                "
+                INSERT INTO {schema}.layout_history (
+                    art_object_id,
+                    old_gallery_id, old_wall_id, old_x, old_y, old_rotated,
+                    new_gallery_id, new_wall_id, new_x, new_y, new_rotated,
+                    timestamp
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                "
+            ),
+            rusqlite::params![
+                record.art_object_id.to_raw_i64(),
+                old.as_ref().map(|old| old.gallery_id),
+                old.as_ref().map(|old| old.wall_id.as_str()),
+                old.as_ref().map(|old| old.x),
+                old.as_ref().map(|old| old.y),
+                old.as_ref().map(|old| old.rotated),
+                record.gallery_id,
+                record.wall_id.as_ref(),
+                record.x,
+                record.y,
+                record.rotated,
+                timestamp,
+            ],
+        )?;
+        tx.execute(
+            &format!(
+                "
+                DELETE FROM {schema}.layout_history WHERE id NOT IN (
+                    SELECT id FROM {schema}.layout_history ORDER BY id DESC LIMIT ?1
+                )
+                "
+            ),
+            [MAX_LAYOUT_HISTORY_ENTRIES as i64],
+        )?;
+        let record = LayoutRecord {
+            gallery_id: record.gallery_id,
+            wall_id: record.wall_id.as_ref(),
+            art_object_id: record.art_object_id,
+            x: record.x,
+            y: record.y,
+            rotated: record.rotated,
+        };
+        GalleryDb::upsert_layout_records_with_transaction(&tx, schema, &vec![record])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Undoes the most recent move recorded by `upsert_layout_record_with_history()`,
+    /// putting the art object back where it was, or removing it from the layout if
+    /// it wasn't in it. Returns `None` if there's nothing to undo.
+    pub fn undo_last_move(&mut self) -> Result<Option<UndoneMove>> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        if !GalleryDb::has_table_in_schema(&tx, schema, "layout_history")? {
+            return Ok(None);
+        }
+        let entry = {
+            let mut statement = tx.prepare(&format!(
+                "
+                SELECT id, art_object_id, old_gallery_id, old_wall_id, old_x, old_y, old_rotated
+                FROM {schema}.layout_history ORDER BY id DESC LIMIT 1
+                "
+            ))?;
+            let mut rows = statement.query(())?;
+            match rows.next()? {
+                Some(row) => {
+                    let art_object_id = ArtObjectId::from_raw_i64(row.get(1)?);
+                    let old_gallery_id: Option<i64> = row.get(2)?;
+                    let restored = match old_gallery_id {
+                        Some(gallery_id) => Some(LayoutRecord {
+                            gallery_id,
+                            wall_id: row.get(3)?,
+                            art_object_id,
+                            x: row.get(4)?,
+                            y: row.get(5)?,
+                            rotated: row.get(6)?,
+                        }),
+                        None => None,
+                    };
+                    Some((row.get::<_, i64>(0)?, art_object_id, restored))
+                }
+                None => None,
+            }
+        };
+        let Some((id, art_object_id, restored)) = entry else {
+            return Ok(None);
+        };
+        tx.execute(
+            &format!("DELETE FROM {schema}.layout_history WHERE id = ?1"),
+            [id],
+        )?;
+        match &restored {
+            Some(record) => GalleryDb::upsert_layout_records_with_transaction(
+                &tx,
+                schema,
+                &vec![record.clone()],
+            )?,
+            None => {
+                tx.execute(
+                    &format!("DELETE FROM {schema}.layout WHERE art_object_id = ?1"),
+                    [art_object_id.to_raw_i64()],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(Some(UndoneMove {
+            art_object_id,
+            restored,
+        }))
+    }
+
+    pub fn upsert_layout_records<T: AsRef<str>>(
+        &mut self,
+        records: &Vec<LayoutRecord<T>>,
+    ) -> Result<()> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        GalleryDb::upsert_layout_records_with_transaction(&tx, schema, records)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Like `upsert_layout_records()`, but keeps when each record was modified
+    /// rather than noting that they were all modified just now, e.g. when
+    /// restoring an autosync.
+    pub fn upsert_timestamped_layout_records(
+        &mut self,
+        records: &[TimestampedLayoutRecord],
+    ) -> Result<()> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        GalleryDb::upsert_timestamped_layout_records_with_transaction(
+            &tx,
+            schema,
+            records
+                .iter()
+                .map(|timestamped| (&timestamped.record, timestamped.modified_at)),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Merges the given records into the layout, keeping whichever of each art
+    /// object's records was modified last, e.g. to reconcile the layouts of two
+    /// machines. If both were modified at the same time, ours is kept, as are
+    /// ours in positive galleries. Note that art objects which were removed from
+    /// the layout on one side stay wherever they are on the other.
+    ///
+    /// Returns the number of records that were taken from the given ones.
+    pub fn merge_layout_records(&mut self, records: &[TimestampedLayoutRecord]) -> Result<usize> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        GalleryDb::add_missing_layout_columns(&tx, schema)?;
+        let mut newer = vec![];
+        {
+            let mut statement = tx.prepare(&format!(
+                "SELECT gallery_id, modified_at FROM {schema}.layout WHERE art_object_id = ?1"
+            ))?;
+            for timestamped in records {
+                let ours: Option<(i64, i64)> = statement
+                    .query_row([timestamped.record.art_object_id.to_raw_i64()], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })
+                    .optional()?;
+                let is_newer = match ours {
+                    Some((gallery_id, _)) if gallery_id > 0 => false,
+                    Some((_, modified_at)) => modified_at < timestamped.modified_at,
+                    None => true,
+                };
+                if is_newer {
+                    newer.push((&timestamped.record, timestamped.modified_at));
+                }
+            }
+        }
+        let merged = newer.len();
+        GalleryDb::upsert_timestamped_layout_records_with_transaction(&tx, schema, newer)?;
+        tx.commit()?;
+        Ok(merged)
+    }
+
+    pub fn get_art_object_ids_in_non_positive_galleries(&mut self) -> Result<HashSet<ArtObjectId>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT art_object_id FROM {}.layout WHERE gallery_id <= 0",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query(())?;
+        let mut result = HashSet::<ArtObjectId>::new();
+        while let Some(row) = rows.next()? {
+            let raw_id: i64 = row.get(0)?;
+            result.insert(ArtObjectId::from_raw_i64(raw_id));
+        }
+        Ok(result)
+    }
+
+    /// Returns every art object that's currently laid out in a positive gallery.
+    pub fn get_art_object_ids_in_positive_galleries(&self) -> Result<HashSet<ArtObjectId>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT art_object_id FROM {}.layout WHERE gallery_id > 0",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query(())?;
+        let mut result = HashSet::<ArtObjectId>::new();
+        while let Some(row) = rows.next()? {
+            let raw_id: i64 = row.get(0)?;
+            result.insert(ArtObjectId::from_raw_i64(raw_id));
+        }
+        Ok(result)
+    }
+
+    /// Returns every positive gallery that has art in it, along with how many
+    /// art objects it contains, ordered by gallery ID. Note that there may be
+    /// gaps in the gallery IDs.
+    pub fn get_populated_positive_galleries(&self) -> Result<Vec<GalleryObjectCount>> {
+        let mut statement = self.conn.prepare_cached(&format!(
+            "
+            SELECT gallery_id, COUNT(*) FROM {}.layout
+            WHERE gallery_id > 0
+            GROUP BY gallery_id
+            ORDER BY gallery_id
+            ",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query(())?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(GalleryObjectCount {
+                gallery_id: row.get(0)?,
+                object_count: row.get(1)?,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Returns the largest positive gallery ID that has art in it, if any.
+    pub fn max_gallery_id(&self) -> Result<Option<i64>> {
+        Ok(self.conn.query_row(
+            &format!(
+                "SELECT MAX(gallery_id) FROM {}.layout WHERE gallery_id > 0",
+                self.layout_schema
+            ),
+            (),
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Returns how many positive galleries have art in them.
+    pub fn count_positive_galleries(&self) -> Result<usize> {
+        Ok(self.conn.query_row(
+            &format!(
+                "SELECT COUNT(DISTINCT gallery_id) FROM {}.layout WHERE gallery_id > 0",
+                self.layout_schema
+            ),
+            (),
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Returns the positive galleries with art in them whose IDs are within `radius`
+    /// of the given gallery ID (including the gallery itself), nearest first.
+    pub fn get_neighboring_galleries(&self, gallery_id: i64, radius: usize) -> Result<Vec<i64>> {
+        let radius = radius as i64;
+        let mut statement = self.conn.prepare_cached(&format!(
+            "
+            SELECT DISTINCT gallery_id FROM {}.layout
+            WHERE gallery_id > 0 AND gallery_id BETWEEN ?1 - ?2 AND ?1 + ?2
+            ORDER BY ABS(gallery_id - ?1), gallery_id
+            ",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query([gallery_id, radius])?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(row.get(0)?);
+        }
+        Ok(result)
+    }
+
+    pub fn get_layout_records_in_non_positive_galleries(
+        &mut self,
+    ) -> Result<Vec<LayoutRecord<String>>> {
+        self.get_layout_records(LayoutScope::NonPositive)
+    }
+
+    /// Like `get_layout_records_in_non_positive_galleries()`, along with when each
+    /// record was last modified.
+    pub fn get_timestamped_layout_records_in_non_positive_galleries(
+        &self,
+    ) -> Result<Vec<TimestampedLayoutRecord>> {
+        let schema = self.layout_schema;
+        let rotated_column = GalleryDb::rotated_column(&self.conn, schema)?;
+        let modified_at_column = GalleryDb::modified_at_column(&self.conn, schema)?;
+        let mut statement = self.conn.prepare(&format!(
+            "
+            SELECT gallery_id, wall_id, art_object_id, x, y, {rotated_column}, {modified_at_column}
+            FROM {schema}.layout
+            WHERE gallery_id <= 0
+            ORDER BY gallery_id, wall_id, x, y
+            "
+        ))?;
+        let mut rows = statement.query(())?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(TimestampedLayoutRecord {
+                record: layout_record_from_row(row)?,
+                modified_at: row.get(6)?,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Returns every layout record in the given zone.
+    pub fn get_layout_records_in_zone(
+        &self,
+        zone: GalleryZone,
+    ) -> Result<Vec<LayoutRecord<String>>> {
+        self.get_layout_records(LayoutScope::Zone(zone))
+    }
+
+    /// Returns every layout record, in both positive and non-positive galleries.
+    pub fn get_all_layout_records(&self) -> Result<Vec<LayoutRecord<String>>> {
+        self.get_layout_records(LayoutScope::All)
+    }
+
+    fn get_layout_records(&self, scope: LayoutScope) -> Result<Vec<LayoutRecord<String>>> {
+        let mut statement = self.conn.prepare(&self.layout_records_sql(scope)?)?;
+        let mut rows = statement.query(())?;
+        let mut result = Vec::<LayoutRecord<String>>::new();
+        while let Some(row) = rows.next()? {
+            result.push(layout_record_from_row(row)?);
+        }
+        Ok(result)
+    }
+
+    /// Writes the layout records in the given scope as a JSON array, one record
+    /// at a time, so that huge layouts never need to be in memory all at once. The
+    /// result can be parsed just like a serialized `get_all_layout_records()`.
+    ///
+    /// Returns the number of records written.
+    pub fn export_layout_records_to_writer<W: Write>(
+        &mut self,
+        writer: W,
+        scope: LayoutScope,
+    ) -> Result<usize> {
+        let mut statement = self.conn.prepare(&self.layout_records_sql(scope)?)?;
+        let mut rows = statement.query(())?;
+        let mut writer = BufWriter::new(writer);
+        let mut count = 0;
+        writer.write_all(b"[")?;
+        while let Some(row) = rows.next()? {
+            writer.write_all(if count == 0 { b"\n  " } else { b",\n  " })?;
+            serde_json::to_writer(&mut writer, &layout_record_from_row(row)?)?;
+            count += 1;
+        }
+        writer.write_all(if count == 0 { b"]\n" } else { b"\n]\n" })?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    fn layout_records_sql(&self, scope: LayoutScope) -> Result<String> {
+        let rotated_column = GalleryDb::rotated_column(&self.conn, self.layout_schema)?;
+        let where_clause = match scope {
+            LayoutScope::NonPositive => "WHERE gallery_id <= 0".to_string(),
+            LayoutScope::Zone(zone) => {
+                let gallery_ids = zone.gallery_ids();
+                format!(
+                    "WHERE gallery_id BETWEEN {} AND {}",
+                    gallery_ids.start(),
+                    gallery_ids.end()
+                )
+            }
+            LayoutScope::All => "".to_string(),
+        };
+        Ok(format!(
+            "
+                SELECT
+                    gallery_id,
+                    wall_id,
+                    art_object_id,
+                    x,
+                    y,
+                    {rotated_column}
+                FROM {}.layout
+                {where_clause}
+                ORDER BY gallery_id, wall_id, x, y
+                ",
+            self.layout_schema
+        ))
+    }
+
+    /// Returns the wall IDs used by the layout, in both positive and non-positive
+    /// galleries, in alphabetical order.
+    pub fn get_distinct_wall_ids(&self) -> Result<Vec<String>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT DISTINCT wall_id FROM {}.layout ORDER BY wall_id",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query(())?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(row.get(0)?);
+        }
+        Ok(result)
+    }
+
+    pub fn clear_layout_records_in_non_positive_galleries(&mut self) -> Result<()> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            &format!("DELETE FROM {schema}.layout WHERE gallery_id <= 0"),
+            (),
+        )?;
+        GalleryDb::clear_layout_history_with_transaction(&tx, schema)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Clears the layout in positive galleries and fills it with the given records.
+    pub fn set_layout_records_in_positive_galleries<T: AsRef<str>>(
+        &mut self,
+        records: &Vec<LayoutRecord<T>>,
+    ) -> Result<()> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            &format!("DELETE FROM {schema}.layout WHERE gallery_id > 0"),
+            (),
+        )?;
+        GalleryDb::clear_layout_history_with_transaction(&tx, schema)?;
+        for record in records.iter() {
+            if record.gallery_id <= 0 {
+                return Err(anyhow!(
+                    "{:?} is not in a positive gallery!",
+                    record.art_object_id
+                ));
+            }
+        }
+        GalleryDb::upsert_layout_records_with_transaction(&tx, schema, records)?;
+        GalleryDb::record_displayed_with_transaction(&tx, schema, records)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Like `set_layout_records_in_positive_galleries()`, but only clears the
+    /// layout in the given (inclusive) range of positive galleries, e.g. to
+    /// rotate the art in a few galleries while leaving the rest alone.
+    pub fn set_layout_records_in_gallery_range<T: AsRef<str>>(
+        &mut self,
+        records: &Vec<LayoutRecord<T>>,
+        (first_gallery_id, last_gallery_id): (i64, i64),
+    ) -> Result<()> {
+        check_positive_gallery_range(first_gallery_id, last_gallery_id)?;
+        for record in records.iter() {
+            if record.gallery_id < first_gallery_id || record.gallery_id > last_gallery_id {
+                return Err(anyhow!(
+                    "{:?} is not in galleries {first_gallery_id} to {last_gallery_id}!",
+                    record.art_object_id
+                ));
+            }
+        }
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            &format!("DELETE FROM {schema}.layout WHERE gallery_id BETWEEN ?1 AND ?2"),
+            (first_gallery_id, last_gallery_id),
+        )?;
+        GalleryDb::clear_layout_history_with_transaction(&tx, schema)?;
+        GalleryDb::upsert_layout_records_with_transaction(&tx, schema, records)?;
+        GalleryDb::record_displayed_with_transaction(&tx, schema, records)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns the art objects in every gallery outside the given (inclusive)
+    /// range, including non-positive ones.
+    pub fn get_art_object_ids_outside_gallery_range(
+        &self,
+        (first_gallery_id, last_gallery_id): (i64, i64),
+    ) -> Result<HashSet<ArtObjectId>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT art_object_id FROM {}.layout WHERE gallery_id NOT BETWEEN ?1 AND ?2",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query((first_gallery_id, last_gallery_id))?;
+        let mut result = HashSet::new();
+        while let Some(row) = rows.next()? {
+            result.insert(ArtObjectId::from_raw_i64(row.get(0)?));
+        }
+        Ok(result)
+    }
+
+    /// Remembers where the given art objects are relative to their walls' dimensions,
+    /// so they can be moved along with any changes to them via
+    /// `rescale_layout_to_walls()`. Note that moving an art object clears its anchor.
+    pub fn set_layout_anchors(&mut self, anchors: &[(ArtObjectId, LayoutAnchor)]) -> Result<()> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        for (art_object_id, anchor) in anchors {
+            tx.execute(
+                &format!("UPDATE {schema}.layout SET anchor_x = ?1, anchor_y = ?2 WHERE art_object_id = ?3"),
+                (&anchor.x, &anchor.y, &art_object_id.to_raw_i64()),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_layout_anchor(&self, art_object_id: ArtObjectId) -> Result<Option<LayoutAnchor>> {
+        let mut statement = self.conn.prepare_cached(&format!(
+            "SELECT anchor_x, anchor_y FROM {}.layout WHERE art_object_id = ?1 AND anchor_x IS NOT NULL AND anchor_y IS NOT NULL",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query([art_object_id.to_raw_i64()])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        Ok(Some(LayoutAnchor {
+            x: row.get(0)?,
+            y: row.get(1)?,
+        }))
+    }
+
+    /// Moves anchored art objects in non-positive galleries so they're in the same
+    /// place relative to the given walls, e.g. because the walls' dimensions have
+    /// changed since the art objects were placed. Art objects are clamped so that
+    /// they stay on their walls. (Positive galleries don't need this, since they're
+    /// laid out from scratch using the latest walls.)
+    ///
+    /// Returns the number of art objects that were moved.
+    pub fn rescale_layout_to_walls(&mut self, walls: &[GalleryWall]) -> Result<usize> {
+        let schema = self.layout_schema;
+        let tx = self.conn.transaction()?;
+        let mut moves: Vec<(i64, f64, f64)> = vec![];
+        {
+            let rotated_column = GalleryDb::rotated_column(&tx, schema)?;
+            let mut statement = tx.prepare(&format!(
+                "
+                SELECT
+                    layout.art_object_id,
+                    layout.wall_id,
+                    layout.x,
+                    layout.y,
+                    layout.anchor_x,
+                    layout.anchor_y,
+                    art_objects.width,
+                    art_objects.height,
+                    {rotated_column}
+                FROM {schema}.layout AS layout
+                LEFT JOIN main.art_objects AS art_objects ON layout.art_object_id = art_objects.id
+                WHERE
+                    layout.gallery_id <= 0 AND
+                    layout.anchor_x IS NOT NULL AND
+                    layout.anchor_y IS NOT NULL
+                "
+            ))?;
+            let mut rows = statement.query(())?;
+            while let Some(row) = rows.next()? {
+                let wall_id: String = row.get(1)?;
+                let Some(wall) = walls.iter().find(|wall| wall.name == wall_id) else {
+                    continue;
+                };
+                let anchor = LayoutAnchor {
+                    x: row.get(4)?,
+                    y: row.get(5)?,
+                };
+                let mut width: Option<f64> = row.get(6)?;
+                let mut height: Option<f64> = row.get(7)?;
+                let rotated: bool = row.get(8)?;
+                if rotated {
+                    std::mem::swap(&mut width, &mut height);
+                }
+                let (x, y) = anchor.position_on_wall(
+                    wall,
+                    width.unwrap_or_default(),
+                    height.unwrap_or_default(),
+                );
+                let (old_x, old_y): (f64, f64) = (row.get(2)?, row.get(3)?);
+                if x != old_x || y != old_y {
+                    moves.push((row.get(0)?, x, y));
+                }
+            }
+        }
+        for (raw_id, x, y) in moves.iter() {
+            // Note that we're leaving the anchors alone, so that the art object
+            // can go back to where it was if e.g. the wall shrinks and then grows.
+            tx.execute(
+                &format!("UPDATE {schema}.layout SET x = ?1, y = ?2 WHERE art_object_id = ?3"),
+                (x, y, raw_id),
+            )?;
+        }
+        tx.commit()?;
+        Ok(moves.len())
+    }
+
+    /// Like `ArtObjectQueryOptions::where_clause()`, but expands saved filters
+    /// from this database.
+    pub fn where_clause(&self, options: &ArtObjectQueryOptions) -> Result<(String, Vec<String>)> {
+        options.where_clause(|name| self.get_saved_filter(name))
+    }
+
+    pub fn count_art_objects(&self, options: &ArtObjectQueryOptions) -> Result<usize> {
+        let (where_clause, params) = self.where_clause(options)?;
+        let mut statement = self.conn.prepare(&format!(
+            "
+            SELECT COUNT(*) FROM art_objects {where_clause}
+            ",
+        ))?;
+        Ok(
+            statement.query_row(rusqlite::params_from_iter(params.into_iter()), |row| {
+                row.get(0)
+            })?,
+        )
+    }
+
+    /// Returns the number of art objects in each medium category, ordered from
+    /// most to least common.
+    pub fn count_art_objects_by_medium_category(&self) -> Result<Vec<(MediumCategory, usize)>> {
+        let mut statement = self.conn.prepare(
+            "
+            SELECT medium_category, COUNT(*) AS c FROM art_objects
+            GROUP BY medium_category ORDER BY c DESC, medium_category
+            ",
+        )?;
+        let mut rows = statement.query(())?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            let category: String = row.get(0)?;
+            result.push((MediumCategory::from_name(category), row.get(1)?));
+        }
+        Ok(result)
+    }
+
+    pub fn get_all_art_objects_for_layout(
+        &self,
+        options: &ArtObjectQueryOptions,
+    ) -> Result<Vec<ArtObjectLayoutInfo>> {
+        let order_by_clause = options.order_by_clause();
+        let (mut where_clause, params) = self.where_clause(options)?;
+        if options.exclude_previously_displayed && self.has_layout_table("displayed_history")? {
+            let condition = format!(
+                "id NOT IN (SELECT art_object_id FROM {}.displayed_history)",
+                self.layout_schema
+            );
+            where_clause = match where_clause.strip_prefix("WHERE ") {
+                Some(filter) => format!("WHERE ({filter}) AND {condition}"),
+                None => format!("WHERE {condition}"),
+            };
+        }
+        let mut statement = self.conn.prepare(&format!(
+            "
+            SELECT id, width, height, highlight, collection FROM art_objects {where_clause} {order_by_clause}
+            ",
+        ))?;
+        let mut rows = statement.query(rusqlite::params_from_iter(params.into_iter()))?;
+        let mut result: Vec<ArtObjectLayoutInfo> = Vec::new();
+        while let Some(row) = rows.next()? {
+            result.push(ArtObjectLayoutInfo {
+                id: ArtObjectId::from_raw_i64(row.get(0)?),
+                width: row.get(1)?,
+                height: row.get(2)?,
+                highlight: row.get(3)?,
+                collection: row.get(4)?,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Returns every wikidata QID that's the fallback for an art object from a
+    /// non-wikidata collection.
+    pub fn get_all_fallback_wikidata_qids(&self) -> Result<HashSet<i64>> {
+        let mut statement = self.conn.prepare(
+            "SELECT fallback_wikidata_qid FROM art_objects WHERE fallback_wikidata_qid IS NOT NULL",
+        )?;
+        let mut rows = statement.query(())?;
+        let mut result = HashSet::new();
+        while let Some(row) = rows.next()? {
+            result.insert(row.get(0)?);
+        }
+        Ok(result)
+    }
+
+    /// Like `get_all_fallback_wikidata_qids()`, but keyed by the ID of the art
+    /// object each QID is the fallback for.
+    pub fn get_fallback_wikidata_qids_by_id(&self) -> Result<HashMap<ArtObjectId, i64>> {
+        let mut statement = self.conn.prepare(
+            "SELECT id, fallback_wikidata_qid FROM art_objects WHERE fallback_wikidata_qid IS NOT NULL",
+        )?;
+        let mut rows = statement.query(())?;
+        let mut result = HashMap::new();
+        while let Some(row) = rows.next()? {
+            result.insert(ArtObjectId::from_raw_i64(row.get(0)?), row.get(1)?);
+        }
+        Ok(result)
+    }
+
+    /// Returns the source gallery of every art object that has one, see
+    /// `ArtObjectRecord::source_gallery`.
+    pub fn get_source_galleries_by_id(&self) -> Result<HashMap<ArtObjectId, String>> {
+        let mut statement = self.conn.prepare(
+            "SELECT id, source_gallery FROM art_objects WHERE source_gallery IS NOT NULL AND source_gallery != ''",
+        )?;
+        let mut rows = statement.query(())?;
+        let mut result = HashMap::new();
+        while let Some(row) = rows.next()? {
+            result.insert(ArtObjectId::from_raw_i64(row.get(0)?), row.get(1)?);
+        }
+        Ok(result)
+    }
+
+    /// Returns the artist of every art object whose artist is known.
+    pub fn get_artists_by_id(&self) -> Result<HashMap<ArtObjectId, String>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT id, artist FROM art_objects WHERE artist != ''")?;
+        let mut rows = statement.query(())?;
+        let mut result = HashMap::new();
+        while let Some(row) = rows.next()? {
+            result.insert(ArtObjectId::from_raw_i64(row.get(0)?), row.get(1)?);
+        }
+        Ok(result)
+    }
+
+    /// Returns the QIDs of all the art objects that came from wikidata.
+    pub fn get_all_wikidata_object_qids(&self) -> Result<HashSet<i64>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT id FROM art_objects WHERE (id & ?1) != 0")?;
+        let wikidata_bit = ArtObjectId::Wikidata(0).to_raw_i64();
+        let mut rows = statement.query([wikidata_bit])?;
+        let mut result = HashSet::new();
+        while let Some(row) = rows.next()? {
+            if let ArtObjectId::Wikidata(qid) = ArtObjectId::from_raw_i64(row.get(0)?) {
+                result.insert(qid);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Delete the art objects with the given IDs in a single transaction, returning
+    /// how many were deleted.
+    pub fn delete_art_objects(&mut self, ids: &[ArtObjectId]) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut deleted = 0;
+        for id in ids {
+            deleted += tx.execute("DELETE FROM art_objects WHERE id = ?1", [id.to_raw_i64()])?;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    pub fn reset_art_objects_table(&mut self) -> Result<()> {
+        let tx = self.conn.transaction()?;
+
+        tx.execute("DROP TABLE IF EXISTS art_objects", ())?;
+        tx.execute("DROP TABLE IF EXISTS artists", ())?;
+        tx.execute("DROP TABLE IF EXISTS collections", ())?;
+        tx.execute("DROP TABLE IF EXISTS quarantined_objects", ())?;
+        tx.execute(
+            "
+            CREATE TABLE art_objects (
+                id INTEGER PRIMARY KEY,
+                title TEXT NOT NULL,
+                artist TEXT NOT NULL,
+                artist_qid INTEGER,
+                culture TEXT NOT NULL,
+                date TEXT NOT NULL,
+                medium TEXT NOT NULL,
+                medium_category TEXT NOT NULL,
+                width REAL NOT NULL,
+                height REAL NOT NULL,
+                fallback_wikidata_qid INTEGER,
+                filename TEXT NOT NULL,
+                collection TEXT NOT NULL,
+                highlight INTEGER NOT NULL DEFAULT 0,
+                primary_image_url TEXT,
+                primary_image_small_url TEXT,
+                date_year INTEGER,
+                artist_role TEXT NOT NULL DEFAULT '',
+                tags TEXT NOT NULL DEFAULT '',
+                classification TEXT NOT NULL DEFAULT '',
+                source_gallery TEXT
+            )
+            ",
+            (),
+        )?;
+        tx.execute(
+            "
+            CREATE TABLE artists (
+                qid INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                description TEXT NOT NULL
+            )
+            ",
+            (),
+        )?;
+        // Art objects that weren't imported because their data looks wrong,
+        // kept around for manual review.
+        tx.execute(
+            "
+            CREATE TABLE quarantined_objects (
+                id INTEGER PRIMARY KEY,
+                title TEXT NOT NULL,
+                artist TEXT NOT NULL,
+                collection TEXT NOT NULL,
+                width REAL NOT NULL,
+                height REAL NOT NULL,
+                reason TEXT NOT NULL
+            )
+            ",
+            (),
+        )?;
+        // Tags aren't dropped, but they need to exist for `tag:` filters to work.
+        GalleryDb::create_tags_table_if_not_exists(&tx)?;
+        set_gallery_db_schema_version(&tx, LATEST_GALLERY_DB_VERSION)?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Add a bunch of records in a single transaction, replacing any existing ones with
+    /// the same ID. This is much faster than adding a single record in a single transaction.
+    pub fn add_art_objects(&mut self, records: &Vec<ArtObjectRecord>) -> Result<()> {
+        let tx = self.conn.transaction()?;
+
+        {
+            let mut statement = tx.prepare_cached(&art_object_insert_sql(1))?;
+            for record in records {
+                statement.execute(rusqlite::params_from_iter(art_object_insert_params(
+                    record,
+                )?))?;
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Like `add_art_objects()`, but inserts `rows_per_statement` records with each
+    /// statement, which is a lot faster when importing lots of records. The records
+    /// end up exactly the same as they would with `add_art_objects()`.
+    pub fn add_art_objects_batched(
+        &mut self,
+        records: &[ArtObjectRecord],
+        rows_per_statement: usize,
+    ) -> Result<()> {
+        let rows_per_statement = rows_per_statement.max(1);
+        let tx = self.conn.transaction()?;
+
+        for chunk in records.chunks(rows_per_statement) {
+            // Every chunk but the last is the same size, so this is only prepared once
+            // or twice per transaction.
+            let mut params = Vec::with_capacity(chunk.len() * ART_OBJECT_INSERT_COLUMNS.len());
+            for record in chunk {
+                params.extend(art_object_insert_params(record)?);
+            }
+            let mut statement = tx.prepare_cached(&art_object_insert_sql(chunk.len()))?;
+            statement.execute(rusqlite::params_from_iter(params))?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Stores the image URLs of the given Met art objects, so their images can
+    /// be fetched without their Met API records. See `get_met_image_urls()`.
+    pub fn set_met_image_urls(&mut self, urls: &[(i64, MetImageUrls)]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for (object_id, urls) in urls {
+            tx.execute(
+                "
+                UPDATE art_objects SET primary_image_url = ?1, primary_image_small_url = ?2
+                WHERE id = ?3
+                ",
+                (
+                    &urls.primary_image,
+                    &urls.primary_image_small,
+                    ArtObjectId::Met(*object_id).to_raw_i64(),
+                ),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns the stored image URLs of the given Met art object, or `None` if
+    /// they haven't been stored yet (or the object doesn't exist).
+    pub fn get_met_image_urls(&self, met_object_id: i64) -> Result<Option<MetImageUrls>> {
+        let mut statement = self.conn.prepare_cached(
+            "
+            SELECT primary_image_url, primary_image_small_url FROM art_objects
+            WHERE id = ?1 AND primary_image_url IS NOT NULL AND primary_image_small_url IS NOT NULL
+            ",
+        )?;
+        let mut rows = statement.query([ArtObjectId::Met(met_object_id).to_raw_i64()])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        Ok(Some(MetImageUrls {
+            primary_image: row.get(0)?,
+            primary_image_small: row.get(1)?,
+        }))
+    }
+
+    /// Returns the IDs of the Met art objects whose image URLs haven't been
+    /// stored yet, in ascending order.
+    pub fn get_met_object_ids_without_image_urls(&self) -> Result<Vec<i64>> {
+        let mut statement = self.conn.prepare_cached(
+            "
+            SELECT id FROM art_objects
+            WHERE primary_image_url IS NULL OR primary_image_small_url IS NULL
+            ORDER BY id
+            ",
+        )?;
+        let mut rows = statement.query([])?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            if let ArtObjectId::Met(object_id) = ArtObjectId::from_raw_i64(row.get(0)?) {
+                result.push(object_id);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the IDs of the Met art objects whose IDs are greater than `after`,
+    /// in ascending order.
+    pub fn get_met_object_ids_after(&self, after: i64) -> Result<Vec<i64>> {
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT id FROM art_objects WHERE id > ?1 ORDER BY id")?;
+        let mut rows = statement.query([after])?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            if let ArtObjectId::Met(object_id) = ArtObjectId::from_raw_i64(row.get(0)?) {
+                result.push(object_id);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Add a bunch of quarantined art objects in a single transaction, replacing
+    /// any existing ones with the same ID.
+    pub fn add_quarantined_objects(
+        &mut self,
+        records: &Vec<QuarantinedObjectRecord>,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+
+        for record in records {
+            tx.execute(
+                "
+                INSERT OR REPLACE INTO quarantined_objects (
+                    id, title, artist, collection, width, height, reason
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ",
+                (
+                    &record.object_id.to_raw_i64(),
+                    &record.title,
+                    &record.artist,
+                    &record.collection,
+                    &record.width,
+                    &record.height,
+                    &record.reason,
+                ),
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Returns all quarantined art objects, ordered by ID. This is empty for
+    /// databases imported before quarantining existed.
+    pub fn list_quarantined_objects(&self) -> Result<Vec<QuarantinedObjectRecord>> {
+        if !self.has_table("quarantined_objects")? {
+            return Ok(vec![]);
+        }
+        let mut statement = self.conn.prepare_cached(
+            "
+            SELECT id, title, artist, collection, width, height, reason
+            FROM quarantined_objects ORDER BY id
+            ",
+        )?;
+        let mut rows = statement.query(())?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(QuarantinedObjectRecord {
+                object_id: ArtObjectId::from_raw_i64(row.get(0)?),
+                title: row.get(1)?,
+                artist: row.get(2)?,
+                collection: row.get(3)?,
+                width: row.get(4)?,
+                height: row.get(5)?,
+                reason: row.get(6)?,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Add a bunch of artists in a single transaction, replacing any existing
+    /// artists with the same QID.
+    pub fn add_artists(&mut self, records: &Vec<ArtistRecord>) -> Result<()> {
+        let tx = self.conn.transaction()?;
+
+        for record in records {
+            tx.execute(
+                "INSERT OR REPLACE INTO artists (qid, name, description) VALUES (?1, ?2, ?3)",
+                (&record.qid, &record.name, &record.description),
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Re-runs `normalize_artist_name()` over the artists of all art objects, e.g.
+    /// for databases imported before it existed, splitting role annotations off
+    /// into `artist_role`.
+    ///
+    /// Artists whose names only differ in diacritics, case or punctuation once
+    /// normalized are combined, and given the most common of their names.
+    pub fn normalize_artists(&mut self) -> Result<ArtistNormalizationReport> {
+        let tx = self.conn.transaction()?;
+        let mut groups: Vec<(String, String, usize)> = vec![];
+        {
+            let mut statement = tx.prepare(
+                "
+                SELECT artist, artist_role, COUNT(*) FROM art_objects
+                GROUP BY artist, artist_role
+                ",
+            )?;
+            let mut rows = statement.query(())?;
+            while let Some(row) = rows.next()? {
+                groups.push((row.get(0)?, row.get(1)?, row.get(2)?));
+            }
+        }
+        let normalized: Vec<NormalizedArtistName> = groups
+            .iter()
+            .map(|(artist, role, _)| {
+                let mut normalized = normalize_artist_name(artist);
+                if normalized.role.is_empty() {
+                    normalized.role = role.clone();
+                }
+                normalized
+            })
+            .collect();
+        let mut spellings_by_key: HashMap<String, HashMap<&str, usize>> = HashMap::new();
+        for ((_, _, count), normalized) in groups.iter().zip(normalized.iter()) {
+            *spellings_by_key
+                .entry(artist_match_key(&normalized.name))
+                .or_default()
+                .entry(normalized.name.as_str())
+                .or_default() += count;
+        }
+        let spelling_by_key: HashMap<&String, &str> = spellings_by_key
+            .iter()
+            .filter_map(|(key, spellings)| Some((key, choose_artist_spelling(spellings)?)))
+            .collect();
+
+        let mut report = ArtistNormalizationReport::default();
+        let mut artists_before: HashSet<&str> = HashSet::new();
+        let mut artists_after: HashSet<&str> = HashSet::new();
+        for ((artist, role, _), normalized) in groups.iter().zip(normalized.iter()) {
+            let new_artist = spelling_by_key[&artist_match_key(&normalized.name)];
+            if !artist.is_empty() {
+                artists_before.insert(artist.as_str());
+            }
+            if !new_artist.is_empty() {
+                artists_after.insert(new_artist);
+            }
+            if new_artist != artist.as_str() || &normalized.role != role {
+                report.changed_rows += tx.execute(
+                    "
+                    UPDATE art_objects SET artist = ?1, artist_role = ?2
+                    WHERE artist = ?3 AND artist_role = ?4
+                    ",
+                    (new_artist, &normalized.role, artist, role),
+                )?;
+            }
+        }
+        report.merged_artists = artists_before.len().saturating_sub(artists_after.len());
+        tx.commit()?;
+        Ok(report)
+    }
+
+    /// Recompute the collections table from the art objects table. This should
+    /// be called after importing art objects.
+    ///
+    /// Collections whose names only differ once normalized are combined, and
+    /// given the most common of their names.
+    pub fn rebuild_collection_stats(&mut self) -> Result<()> {
+        #[derive(Default)]
+        struct Stats {
+            object_counts_by_name: HashMap<String, usize>,
+            met_count: usize,
+            wikidata_count: usize,
+        }
+
+        let tx = self.conn.transaction()?;
+        tx.execute("DROP TABLE IF EXISTS collections", ())?;
+        tx.execute(
+            "
+            CREATE TABLE collections (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                normalized_name TEXT NOT NULL UNIQUE,
+                object_count INTEGER NOT NULL,
+                source TEXT NOT NULL
+            )
+            ",
+            (),
+        )?;
+        let mut stats_by_normalized_name = BTreeMap::<String, Stats>::new();
+        {
+            let mut statement = tx.prepare(
+                "
+                SELECT collection, COUNT(*), SUM((id & ?1) != 0) FROM art_objects
+                GROUP BY collection
+                ",
+            )?;
+            let wikidata_bit = ArtObjectId::Wikidata(0).to_raw_i64();
+            let mut rows = statement.query([wikidata_bit])?;
+            while let Some(row) = rows.next()? {
+                let name: String = row.get(0)?;
+                let normalized_name = normalize_collection_name(&name);
+                if normalized_name.is_empty() {
+                    continue;
+                }
+                let count: usize = row.get(1)?;
+                let wikidata_count: usize = row.get(2)?;
+                let stats = stats_by_normalized_name.entry(normalized_name).or_default();
+                *stats
+                    .object_counts_by_name
+                    .entry(
+                        name.trim_matches(&COLLECTION_NAME_WHITESPACE[..])
+                            .to_string(),
+                    )
+                    .or_default() += count;
+                stats.met_count += count - wikidata_count;
+                stats.wikidata_count += wikidata_count;
+            }
+        }
+        for (normalized_name, stats) in stats_by_normalized_name {
+            let (name, _) = stats
+                .object_counts_by_name
+                .iter()
+                .max_by(|(a_name, a_count), (b_name, b_count)| {
+                    a_count.cmp(b_count).then_with(|| b_name.cmp(a_name))
+                })
+                .expect("collections should have at least one name");
+            let source = match (stats.met_count > 0, stats.wikidata_count > 0) {
+                (true, true) => CollectionSource::Mixed,
+                (true, false) => CollectionSource::Met,
+                (false, _) => CollectionSource::Wikidata,
+            };
+            tx.execute(
+                "
+                INSERT INTO collections (name, normalized_name, object_count, source)
+                VALUES (?1, ?2, ?3, ?4)
+                ",
+                (
+                    name,
+                    &normalized_name,
+                    stats.met_count + stats.wikidata_count,
+                    source.as_str(),
+                ),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns all the collections represented in the database, ordered by
+    /// normalized name. This is empty if `rebuild_collection_stats()` has never
+    /// been called.
+    pub fn list_collections(&self) -> Result<Vec<CollectionRecord>> {
+        if !self.has_table("collections")? {
+            return Ok(vec![]);
+        }
+        let mut statement = self.conn.prepare_cached(
+            "SELECT id, name, object_count, source FROM collections ORDER BY normalized_name",
+        )?;
+        let mut rows = statement.query(())?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            let source: String = row.get(3)?;
+            let Some(source) = CollectionSource::from_name(&source) else {
+                return Err(anyhow!("Unknown collection source: {source}"));
+            };
+            result.push(CollectionRecord {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                object_count: row.get(2)?,
+                source,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Returns the distinct non-empty values of the given column along with how
+    /// many art objects have each of them, e.g. to populate a dropdown of artists.
+    /// Values with fewer than `min_count` art objects are left out, and the rest
+    /// are ordered by count, most common first, and then alphabetically.
+    pub fn distinct_values(
+        &self,
+        column: DistinctColumn,
+        min_count: usize,
+        limit: usize,
+    ) -> Result<Vec<(String, usize)>> {
+        let column = column.as_str();
+        let mut statement = self.conn.prepare_cached(&format!(
+            "
+            SELECT {column}, COUNT(*) AS c FROM art_objects
+            WHERE {column} != ''
+            GROUP BY {column} HAVING c >= ?1
+            ORDER BY c DESC, {column}
+            LIMIT ?2
+            "
+        ))?;
+        let mut rows = statement.query([min_count as i64, limit as i64])?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push((row.get(0)?, row.get(1)?));
+        }
+        Ok(result)
+    }
+
+    /// Saved filters were added after the other tables, and are player data that
+    /// shouldn't be reset, so the table is created on demand.
+    fn create_saved_filters_table_if_not_exists(tx: &Transaction) -> Result<()> {
+        tx.execute(
+            "
+            CREATE TABLE IF NOT EXISTS saved_filters (
+                name TEXT PRIMARY KEY,
+                filter TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            )
+            ",
+            (),
+        )?;
+        Ok(())
+    }
+
+    /// Makes sure that a saved filter with the given name and filter could be
+    /// expanded if it were saved, e.g. that it doesn't refer to itself or to
+    /// saved filters that don't exist.
+    pub fn validate_saved_filter(&self, name: &str, filter: &str) -> Result<()> {
+        if !is_valid_filter_macro_name(name) {
+            return Err(anyhow!(
+                "Saved filter names can only contain letters, numbers, underscores and dashes: {name:?}"
+            ));
+        }
+        let options = ArtObjectQueryOptions {
+            filter: Some(format!("@{name}")),
+            ..Default::default()
+        };
+        options.where_clause(|other| {
+            if other == name {
+                Ok(Some(filter.to_string()))
+            } else {
+                self.get_saved_filter(other)
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Saves the given filter so that it can be referred to as `@<name>` in other
+    /// filters, replacing any existing saved filter with the same name.
+    pub fn save_filter(&mut self, name: &str, filter: &str) -> Result<()> {
+        self.validate_saved_filter(name, filter)?;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("current time should be after the unix epoch")
+            .as_secs() as i64;
+        let tx = self.conn.transaction()?;
+        GalleryDb::create_saved_filters_table_if_not_exists(&tx)?;
+        // Note that replacing a saved filter keeps its original creation time.
+        tx.execute(
+            "
+            INSERT INTO saved_filters (name, filter, created_at) VALUES (?1, ?2, ?3)
+            ON CONFLICT(name) DO UPDATE SET filter = excluded.filter
+            ",
+            (name, filter, created_at),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_saved_filter(&self, name: &str) -> Result<Option<String>> {
+        if !self.has_table("saved_filters")? {
+            return Ok(None);
+        }
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT filter FROM saved_filters WHERE name = ?1")?;
+        let mut rows = statement.query([name])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        Ok(Some(row.get(0)?))
+    }
+
+    /// Returns all saved filters, ordered by name.
+    pub fn list_saved_filters(&self) -> Result<Vec<SavedFilterRecord>> {
+        if !self.has_table("saved_filters")? {
+            return Ok(vec![]);
+        }
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT name, filter, created_at FROM saved_filters ORDER BY name")?;
+        let mut rows = statement.query(())?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(SavedFilterRecord {
+                name: row.get(0)?,
+                filter: row.get(1)?,
+                created_at: row.get(2)?,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Deletes the saved filter with the given name, returning whether it existed.
+    ///
+    /// Note that any other saved filters referring to it will stop working.
+    pub fn delete_saved_filter(&mut self, name: &str) -> Result<bool> {
+        if !self.has_table("saved_filters")? {
+            return Ok(false);
+        }
+        let deleted = self
+            .conn
+            .execute("DELETE FROM saved_filters WHERE name = ?1", [name])?;
+        Ok(deleted > 0)
+    }
+
+    /// Adds the given saved filters, keeping their creation times, in a single
+    /// transaction. Saved filters with names that are already taken are left
+    /// alone. Returns the number of saved filters that were added.
+    ///
+    /// Unlike `save_filter()`, the filters aren't validated, since they may
+    /// refer to each other.
+    pub fn import_saved_filters(&mut self, records: &[SavedFilterRecord]) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        GalleryDb::create_saved_filters_table_if_not_exists(&tx)?;
+        let mut added = 0;
+        {
+            let mut statement = tx.prepare_cached(
+                "INSERT OR IGNORE INTO saved_filters (name, filter, created_at) VALUES (?1, ?2, ?3)",
+            )?;
+            for record in records {
+                added += statement.execute((&record.name, &record.filter, record.created_at))?;
+            }
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+
+    /// Tags are player data that shouldn't be reset, so like saved filters, the
+    /// table is created on demand, and never dropped.
+    fn create_tags_table_if_not_exists(tx: &Transaction) -> Result<()> {
+        tx.execute(
+            "
+            CREATE TABLE IF NOT EXISTS tags (
+                art_object_id INTEGER NOT NULL,
+                tag TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                UNIQUE(art_object_id, tag)
+            )
+            ",
+            (),
+        )?;
+        Ok(())
+    }
+
+    /// Tags the given art object, returning whether it wasn't already tagged
+    /// with the tag. Tags are normalized with `normalize_tag()`.
+    pub fn add_tag(&mut self, art_object_id: ArtObjectId, tag: &str) -> Result<bool> {
+        let tag = normalize_tag(tag)?;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("current time should be after the unix epoch")
+            .as_secs() as i64;
+        let tx = self.conn.transaction()?;
+        GalleryDb::create_tags_table_if_not_exists(&tx)?;
+        let added = tx.execute(
+            "INSERT OR IGNORE INTO tags (art_object_id, tag, created_at) VALUES (?1, ?2, ?3)",
+            (art_object_id.to_raw_i64(), tag, created_at),
+        )?;
+        tx.commit()?;
+        Ok(added > 0)
+    }
+
+    /// Removes the given tag from the given art object, returning whether it
+    /// was tagged with it.
+    pub fn remove_tag(&mut self, art_object_id: ArtObjectId, tag: &str) -> Result<bool> {
+        if !self.has_table("tags")? {
+            return Ok(false);
+        }
+        let removed = self.conn.execute(
+            "DELETE FROM tags WHERE art_object_id = ?1 AND tag = ?2",
+            (art_object_id.to_raw_i64(), tag.trim().to_lowercase()),
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// Returns the tags of the given art object, in alphabetical order.
+    pub fn get_tags(&self, art_object_id: ArtObjectId) -> Result<Vec<String>> {
+        if !self.has_table("tags")? {
+            return Ok(vec![]);
+        }
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT tag FROM tags WHERE art_object_id = ?1 ORDER BY tag")?;
+        let mut rows = statement.query([art_object_id.to_raw_i64()])?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(row.get(0)?);
+        }
+        Ok(result)
+    }
+
+    /// Returns the IDs of the art objects with the given tag, in the order they
+    /// were tagged.
+    pub fn get_objects_with_tag(&self, tag: &str) -> Result<Vec<ArtObjectId>> {
+        if !self.has_table("tags")? {
+            return Ok(vec![]);
+        }
+        let mut statement = self.conn.prepare_cached(
+            "SELECT art_object_id FROM tags WHERE tag = ?1 ORDER BY created_at, art_object_id",
+        )?;
+        let mut rows = statement.query([tag.trim().to_lowercase()])?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(ArtObjectId::from_raw_i64(row.get(0)?));
+        }
+        Ok(result)
+    }
+
+    /// Returns every tag of every art object, e.g. so they can be autosynced.
+    pub fn get_all_tags(&self) -> Result<Vec<TagRecord>> {
+        if !self.has_table("tags")? {
+            return Ok(vec![]);
+        }
+        let mut statement = self.conn.prepare_cached(
+            "SELECT art_object_id, tag, created_at FROM tags ORDER BY art_object_id, tag",
+        )?;
+        let mut rows = statement.query(())?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(TagRecord {
+                art_object_id: ArtObjectId::from_raw_i64(row.get(0)?),
+                tag: row.get(1)?,
+                created_at: row.get(2)?,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Adds the given tags, keeping their creation times, in a single
+    /// transaction. Tags that art objects already have are left alone, and
+    /// invalid tags are skipped. Returns the number of tags that were added.
+    pub fn import_tags(&mut self, records: &[TagRecord]) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        GalleryDb::create_tags_table_if_not_exists(&tx)?;
+        let mut added = 0;
+        {
+            let mut statement = tx.prepare_cached(
+                "INSERT OR IGNORE INTO tags (art_object_id, tag, created_at) VALUES (?1, ?2, ?3)",
+            )?;
+            for record in records {
+                let Ok(tag) = normalize_tag(&record.tag) else {
+                    continue;
+                };
+                added += statement.execute((
+                    record.art_object_id.to_raw_i64(),
+                    tag,
+                    record.created_at,
+                ))?;
+            }
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+
+    pub fn get_artist(&self, qid: i64) -> Result<Option<ArtistRecord>> {
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT name, description FROM artists WHERE qid = ?1")?;
+        let mut rows = statement.query([qid])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        Ok(Some(ArtistRecord {
+            qid,
+            name: row.get(0)?,
+            description: row.get(1)?,
+        }))
+    }
+
+    pub fn get_art_object(&self, object_id: ArtObjectId) -> Result<Option<ArtObjectRecord>> {
+        let mut statement = self.conn.prepare_cached(&format!(
+            "SELECT {ART_OBJECT_RECORD_COLUMNS} FROM art_objects AS ao WHERE ao.id = ?1"
+        ))?;
+        let mut rows = statement.query([object_id.to_raw_i64()])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        Ok(Some(art_object_record_from_row(row)?))
+    }
+
+    /// Like `get_art_object()`, but gets the art objects with the given IDs in
+    /// as few queries as possible. Art objects that don't exist are left out, and
+    /// the rest aren't in any particular order.
+    pub fn get_art_objects_with_ids(&self, ids: &[ArtObjectId]) -> Result<Vec<ArtObjectRecord>> {
+        let mut result = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(MAX_FILTER_PARAMS) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            // Every chunk but the last is the same size, so this is only prepared
+            // once or twice.
+            let mut statement = self.conn.prepare_cached(&format!(
+                "SELECT {ART_OBJECT_RECORD_COLUMNS} FROM art_objects AS ao WHERE ao.id IN ({placeholders})"
+            ))?;
+            let mut rows = statement.query(rusqlite::params_from_iter(
+                chunk.iter().map(|id| id.to_raw_i64()),
+            ))?;
+            while let Some(row) = rows.next()? {
+                result.push(art_object_record_from_row(row)?);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns at most `limit` art objects matching the given options, skipping
+    /// the first `offset` of them.
+    pub fn get_art_objects(
+        &self,
+        options: &ArtObjectQueryOptions,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<ArtObjectRecord>> {
+        let order_by_clause = options.order_by_clause();
+        let (where_clause, params) = self.where_clause(options)?;
+        let mut statement = self.conn.prepare(&format!(
+            "
+            SELECT {ART_OBJECT_RECORD_COLUMNS} FROM art_objects AS ao {where_clause} {order_by_clause}
+            LIMIT {limit} OFFSET {offset}
+            ",
+        ))?;
+        let mut rows = statement.query(rusqlite::params_from_iter(params.into_iter()))?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            result.push(art_object_record_from_row(row)?);
+        }
+        Ok(result)
+    }
+
+    /// Returns at most `limit` art objects matching the given options, along with
+    /// where each one is hung, e.g. to show them as pins on a map of the museum.
+    /// Art objects that are hung come first, ordered by gallery, wall and then
+    /// position, followed by ones that aren't currently on display.
+    pub fn search_placed_art_objects(
+        &self,
+        options: &ArtObjectQueryOptions,
+        limit: usize,
+    ) -> Result<Vec<PlacedSearchResult>> {
+        let (where_clause, params) = self.where_clause(options)?;
+        let mut statement = self.conn.prepare(&format!(
+            "
+            SELECT art_objects.id, title, artist, layout.gallery_id, layout.wall_id, layout.x
+            FROM art_objects
+            LEFT JOIN {}.layout AS layout ON layout.art_object_id = art_objects.id
+            {where_clause}
+            ORDER BY
+                layout.gallery_id IS NULL,
+                layout.gallery_id,
+                layout.wall_id,
+                layout.x,
+                art_objects.id
+            LIMIT {limit}
+            ",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query(rusqlite::params_from_iter(params.into_iter()))?;
+        let mut result = vec![];
+        while let Some(row) = rows.next()? {
+            let gallery_id: Option<i64> = row.get(3)?;
+            let placement = match gallery_id {
+                Some(gallery_id) => Some(SearchResultPlacement {
+                    gallery_id,
+                    wall_id: row.get(4)?,
+                    x: row.get(5)?,
+                }),
+                None => None,
+            };
+            result.push(PlacedSearchResult {
+                object_id: ArtObjectId::from_raw_i64(row.get(0)?),
+                title: row.get(1)?,
+                artist: row.get(2)?,
+                placement,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Returns at most `limit` art objects by the same artist as the given one, and
+    /// at most `limit` with the same medium category from the same century (see
+    /// `parse_century()`), e.g. to suggest on the given one's plaque. Highlights
+    /// come first, and then art objects are ordered by ID.
+    pub fn get_related_art_objects(
+        &self,
+        object_id: ArtObjectId,
+        limit: usize,
+    ) -> Result<RelatedArtObjects> {
+        let mut related = RelatedArtObjects::default();
+        let Some(object) = self.get_art_object(object_id)? else {
+            return Ok(related);
+        };
+        let raw_id = object_id.to_raw_i64();
+
+        // Not every artist has a QID, but when they do, it's more reliable than
+        // their name, which can be spelled differently in different collections.
+        let artist_match = match (object.artist_qid, object.artist.is_empty()) {
+            (Some(qid), _) => Some(("ao.artist_qid = ?2", Value::Integer(qid))),
+            (None, false) => Some(("ao.artist = ?2", Value::Text(object.artist.clone()))),
+            (None, true) => None,
+        };
+        if let Some((condition, value)) = artist_match {
+            let mut statement = self.conn.prepare_cached(&format!(
+                "
+                SELECT {ART_OBJECT_RECORD_COLUMNS} FROM art_objects AS ao
+                WHERE ao.id != ?1 AND {condition}
+                ORDER BY ao.highlight DESC, ao.id
+                LIMIT ?3
+                "
+            ))?;
+            let mut rows = statement.query(rusqlite::params![raw_id, value, limit as i64])?;
+            while let Some(row) = rows.next()? {
+                related.same_artist.push(art_object_record_from_row(row)?);
+            }
+        }
+
+        // The century isn't in the database, so we need to parse the date of each
+        // art object with the same medium category until we have enough.
+        if let Some(century) = parse_century(&object.object_date) {
+            let mut statement = self.conn.prepare_cached(&format!(
+                "
+                SELECT {ART_OBJECT_RECORD_COLUMNS} FROM art_objects AS ao
+                WHERE ao.id != ?1 AND ao.medium_category = ?2
+                ORDER BY ao.highlight DESC, ao.id
+                "
+            ))?;
+            let mut rows =
+                statement.query(rusqlite::params![raw_id, object.medium_category.as_str()])?;
+            while related.same_medium_century.len() < limit {
+                let Some(row) = rows.next()? else {
+                    break;
+                };
+                let record = art_object_record_from_row(row)?;
+                if parse_century(&record.object_date) == Some(century) {
+                    related.same_medium_century.push(record);
+                }
+            }
+        }
+
+        Ok(related)
+    }
+
+    /// Returns the art objects on the given wall, along with their positions on it
+    /// and whether they're rotated (see `LayoutRecord::rotated`).
+    pub fn get_art_objects_for_gallery_wall<T: AsRef<str>>(
+        &self,
+        gallery_id: i64,
+        wall_id: T,
+    ) -> Result<Vec<(ArtObjectRecord, (f64, f64), bool)>> {
+        let mut result = vec![];
+
+        let rotated_column = GalleryDb::rotated_column(&self.conn, self.layout_schema)?;
+        let mut statement = self.conn.prepare_cached(&format!(
+            "
+            SELECT
+                layout.art_object_id,
+                layout.x,
+                layout.y,
+                ao.title,
+                ao.date,
+                ao.medium,
+                ao.width,
+                ao.height,
+                ao.artist,
+                ao.culture,
+                ao.fallback_wikidata_qid,
+                ao.filename,
+                ao.collection,
+                ao.artist_qid,
+                ao.medium_category,
+                ao.highlight,
+                ao.artist_role,
+                ao.tags,
+                ao.classification,
+                ao.source_gallery,
+                {rotated_column}
+            FROM
+                main.art_objects AS ao
+            INNER JOIN
+                {}.layout AS layout
+            ON
+                layout.art_object_id = ao.id
+            WHERE
+                layout.gallery_id = ?1 AND
+                layout.wall_id = ?2
+            ",
+            self.layout_schema
+        ))?;
+        let mut rows = statement.query(rusqlite::params![&gallery_id, wall_id.as_ref()])?;
+        while let Some(row) = rows.next()? {
+            let id = ArtObjectId::from_raw_i64(row.get(0)?);
+            let location: (f64, f64) = (row.get(1)?, row.get(2)?);
+            let object = ArtObjectRecord {
+                object_id: id,
+                title: row.get(3)?,
+                object_date: row.get(4)?,
+                medium: row.get(5)?,
+                width: row.get(6)?,
+                height: row.get(7)?,
+                artist: row.get(8)?,
+                culture: row.get(9)?,
+                fallback_wikidata_qid: row.get(10)?,
+                filename: row.get(11)?,
+                collection: row.get(12)?,
+                artist_qid: row.get(13)?,
+                medium_category: MediumCategory::from_name(row.get::<_, String>(14)?),
+                highlight: row.get(15)?,
+                artist_role: row.get(16)?,
+                tags: row.get(17)?,
+                classification: row.get(18)?,
+                source_gallery: row.get(19)?,
+            };
+            result.push((object, location, row.get(20)?));
+        }
+
+        Ok(result)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArtObjectRecord {
+    pub object_id: ArtObjectId,
+    pub object_date: String,
+    pub culture: String,
+    pub artist: String,
+    /// How the artist was involved in making this, e.g. "workshop of", or an
+    /// empty string if it's just by them. See `normalize_artist_name()`.
+    pub artist_role: String,
+    pub title: String,
+    pub medium: String,
+    /// A normalized version of `medium`, see `classify_medium()`.
+    pub medium_category: MediumCategory,
+    pub width: f64,
+    pub height: f64,
+    pub fallback_wikidata_qid: Option<i64>,
+    pub filename: String,
+    pub collection: String,
+    /// The wikidata QID of the artist, if known. See `ArtistRecord`.
+    pub artist_qid: Option<i64>,
+    /// Whether this is one of the collection's most notable works, e.g. a Met
+    /// highlight.
+    pub highlight: bool,
+    /// The collection's subject keywords, separated by `|`, e.g.
+    /// "Portraits|Women". Unrelated to the tags players add, which are kept in
+    /// the `tags` table.
+    pub tags: String,
+    /// What kind of work this is according to the collection, e.g. "Paintings"
+    /// or "Drawings".
+    pub classification: String,
+    /// The gallery the art object hangs in at its museum, e.g. "825" for the
+    /// Met's gallery 825, if it's on view. See `LayoutMode::RealGallery`.
+    pub source_gallery: Option<String>,
+}
+
+/// See `GalleryDb::get_related_art_objects()`.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct RelatedArtObjects {
+    pub same_artist: Vec<ArtObjectRecord>,
+    pub same_medium_century: Vec<ArtObjectRecord>,
+}
+
+/// See `GalleryDb::search_placed_art_objects()`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlacedSearchResult {
+    pub object_id: ArtObjectId,
+    pub title: String,
+    pub artist: String,
+    /// Where the art object is hung, or `None` if it isn't currently on display.
+    pub placement: Option<SearchResultPlacement>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SearchResultPlacement {
+    pub gallery_id: i64,
+    pub wall_id: String,
+    pub x: f64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ArtistRecord {
+    pub qid: i64,
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtObjectLayoutInfo {
+    pub id: ArtObjectId,
+    pub width: f64,
+    pub height: f64,
+    pub highlight: bool,
+    /// Only used to balance layouts by collection, see `balance_by_collection()`.
+    pub collection: String,
+}
+
+/// Where an art object's center is on its wall, as fractions of the wall's width
+/// and height, from 0.0 to 1.0.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LayoutAnchor {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl LayoutAnchor {
+    pub fn from_position(x: f64, y: f64, wall: &GalleryWall) -> Self {
+        let fraction = |value: f64, size: f64| {
+            if size > 0.0 {
+                (value / size).clamp(0.0, 1.0)
+            } else {
+                0.5
+            }
+        };
+        LayoutAnchor {
+            x: fraction(x, wall.width),
+            y: fraction(y, wall.height),
+        }
+    }
+
+    /// Returns the position of the center of an art object with the given
+    /// dimensions, clamped so that it's entirely on the wall if possible.
+    pub fn position_on_wall(&self, wall: &GalleryWall, width: f64, height: f64) -> (f64, f64) {
+        let placement = Placement {
+            x: self.x * wall.width,
+            y: self.y * wall.height,
+            width,
+            height,
+        };
+        match placement.clamped_to_wall(wall) {
+            Some(clamped) => (clamped.x, clamped.y),
+            // It's too big to fit, so just make sure its center is on the wall.
+            None => (
+                placement.x.clamp(0.0, wall.width.max(0.0)),
+                placement.y.clamp(0.0, wall.height.max(0.0)),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct GalleryRecord {
+    pub gallery_id: i64,
+    /// The name of the wall set that the gallery's walls come from.
+    pub wall_set: String,
+    /// Walls that were intentionally left empty by the layout, so players can
+    /// hang their own art on them.
+    #[serde(default)]
+    pub reserved_walls: Vec<String>,
+    /// The name of the layout segment that the gallery is part of, if any.
+    #[serde(default)]
+    pub segment: Option<String>,
+    /// The gallery at the art objects' museum that this one recreates, if any,
+    /// e.g. "825", so the game can label it "Met Gallery 825". See
+    /// `LayoutMode::RealGallery`.
+    #[serde(default)]
+    pub source_gallery: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct GalleryObjectCount {
+    pub gallery_id: i64,
+    pub object_count: usize,
+}
+
+/// An art object that wasn't imported because its data looks wrong.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct QuarantinedObjectRecord {
+    pub object_id: ArtObjectId,
+    pub title: String,
+    pub artist: String,
+    pub collection: String,
+    pub width: f64,
+    pub height: f64,
+    /// Why the art object was quarantined, e.g. "aspect ratio is too extreme".
+    pub reason: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedFilterRecord {
+    pub name: String,
+    pub filter: String,
+    /// When the filter was first saved, in seconds since the unix epoch.
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagRecord {
+    pub art_object_id: ArtObjectId,
+    pub tag: String,
+    /// When the art object was tagged, in seconds since the unix epoch.
+    pub created_at: i64,
+}
+
+/// Where a collection's art objects were imported from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollectionSource {
+    Met,
+    Wikidata,
+    /// Some of the collection's art objects came from the Met, and others from
+    /// Wikidata.
+    Mixed,
+}
+
+impl CollectionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CollectionSource::Met => "met",
+            CollectionSource::Wikidata => "wikidata",
+            CollectionSource::Mixed => "mixed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "met" => Some(CollectionSource::Met),
+            "wikidata" => Some(CollectionSource::Wikidata),
+            "mixed" => Some(CollectionSource::Mixed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CollectionRecord {
+    pub id: i64,
+    /// The most common spelling of the collection's name.
+    pub name: String,
+    pub object_count: usize,
+    pub source: CollectionSource,
+}
+
+/// The columns of the art objects table that `GalleryDb::distinct_values()` can list.
+/// This is a closed set so that arbitrary column names never end up in SQL.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DistinctColumn {
+    Artist,
+    Culture,
+    Medium,
+    Collection,
+}
+
+impl DistinctColumn {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DistinctColumn::Artist => "artist",
+            DistinctColumn::Culture => "culture",
+            DistinctColumn::Medium => "medium",
+            DistinctColumn::Collection => "collection",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "artist" => Some(DistinctColumn::Artist),
+            "culture" => Some(DistinctColumn::Culture),
+            "medium" => Some(DistinctColumn::Medium),
+            "collection" => Some(DistinctColumn::Collection),
+            _ => None,
+        }
+    }
+}
+
+/// The columns needed by `art_object_record_from_row()`.
+const ART_OBJECT_RECORD_COLUMNS: &str = "
+    ao.id,
+    ao.title,
+    ao.date,
+    ao.medium,
+    ao.width,
+    ao.height,
+    ao.artist,
+    ao.culture,
+    ao.fallback_wikidata_qid,
+    ao.filename,
+    ao.collection,
+    ao.artist_qid,
+    ao.medium_category,
+    ao.highlight,
+    ao.artist_role,
+    ao.tags,
+    ao.classification,
+    ao.source_gallery
+";
+
+/// How many records `GalleryDb::add_art_objects_batched()` should insert per statement
+/// by default. Each record has a parameter per column, so this keeps us well under
+/// SQLite's limit on the number of parameters in a statement.
+pub const DEFAULT_ART_OBJECT_INSERT_ROWS_PER_STATEMENT: usize = 100;
+
+/// The columns set by `art_object_insert_params()`, in order.
+const ART_OBJECT_INSERT_COLUMNS: [&str; 19] = [
+    "id",
+    "title",
+    "date",
+    "medium",
+    "width",
+    "height",
+    "artist",
+    "culture",
+    "fallback_wikidata_qid",
+    "filename",
+    "collection",
+    "artist_qid",
+    "medium_category",
+    "highlight",
+    "date_year",
+    "artist_role",
+    "tags",
+    "classification",
+    "source_gallery",
+];
+
+/// Returns an `INSERT OR REPLACE` statement for the given number of art object records.
+fn art_object_insert_sql(rows: usize) -> String {
+    let columns = ART_OBJECT_INSERT_COLUMNS.len();
+    let values = (0..rows)
+        .map(|row| {
+            let placeholders = (1..=columns)
+                .map(|column| format!("?{}", row * columns + column))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("({placeholders})")
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "INSERT OR REPLACE INTO art_objects ({}) VALUES {values}",
+        ART_OBJECT_INSERT_COLUMNS.join(", ")
+    )
+}
+
+fn art_object_insert_params(
+    record: &ArtObjectRecord,
+) -> rusqlite::Result<[ToSqlOutput<'_>; ART_OBJECT_INSERT_COLUMNS.len()]> {
+    Ok([
+        ToSqlOutput::from(record.object_id.to_raw_i64()),
+        record.title.to_sql()?,
+        record.object_date.to_sql()?,
+        record.medium.to_sql()?,
+        record.width.to_sql()?,
+        record.height.to_sql()?,
+        record.artist.to_sql()?,
+        record.culture.to_sql()?,
+        record.fallback_wikidata_qid.to_sql()?,
+        record.filename.to_sql()?,
+        record.collection.to_sql()?,
+        record.artist_qid.to_sql()?,
+        ToSqlOutput::from(record.medium_category.as_str()),
+        record.highlight.to_sql()?,
+        // Derived from the date, so `date:` filters don't need to parse it.
+        ToSqlOutput::Owned(Value::from(parse_year(&record.object_date))),
+        record.artist_role.to_sql()?,
+        record.tags.to_sql()?,
+        record.classification.to_sql()?,
+        record.source_gallery.to_sql()?,
+    ])
+}
+
+fn art_object_record_from_row(row: &Row) -> rusqlite::Result<ArtObjectRecord> {
+    Ok(ArtObjectRecord {
+        object_id: ArtObjectId::from_raw_i64(row.get(0)?),
+        title: row.get(1)?,
+        object_date: row.get(2)?,
+        medium: row.get(3)?,
+        width: row.get(4)?,
+        height: row.get(5)?,
+        artist: row.get(6)?,
+        culture: row.get(7)?,
+        fallback_wikidata_qid: row.get(8)?,
+        filename: row.get(9)?,
+        collection: row.get(10)?,
+        artist_qid: row.get(11)?,
+        medium_category: MediumCategory::from_name(row.get::<_, String>(12)?),
+        highlight: row.get(13)?,
+        artist_role: row.get(14)?,
+        tags: row.get(15)?,
+        classification: row.get(16)?,
+        source_gallery: row.get(17)?,
+    })
+}
+
+/// A `LayoutRecord` along with when it was last modified, in seconds since the
+/// unix epoch, see `GalleryDb::merge_layout_records()`. Records from before
+/// this was kept track of were modified at 0.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TimestampedLayoutRecord {
+    #[serde(flatten)]
+    pub record: LayoutRecord<String>,
+    #[serde(default)]
+    pub modified_at: i64,
+}
+
+/// See `GalleryDb::undo_last_move()`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct UndoneMove {
+    pub art_object_id: ArtObjectId,
+    /// Where the art object was put back, or `None` if it was removed from the
+    /// layout, since it wasn't in it before the move.
+    pub restored: Option<LayoutRecord<String>>,
+}
+
+/// Which layout records to get, e.g. for `GalleryDb::export_layout_records_to_writer()`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LayoutScope {
+    /// Only the non-positive galleries, which are the player's own, i.e. every
+    /// zone but `GalleryZone::Generated`.
+    NonPositive,
+    /// Only the galleries in the given zone.
+    Zone(GalleryZone),
+    /// Both positive and non-positive galleries.
+    All,
+}
+
+/// Expects the columns selected by `GalleryDb::layout_records_sql()`.
+fn layout_record_from_row(row: &Row) -> rusqlite::Result<LayoutRecord<String>> {
+    Ok(LayoutRecord {
+        gallery_id: row.get(0)?,
+        wall_id: row.get(1)?,
+        art_object_id: ArtObjectId::from_raw_i64(row.get(2)?),
+        x: row.get(3)?,
+        y: row.get(4)?,
+        rotated: row.get(5)?,
+    })
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct LayoutRecord<T: AsRef<str>> {
+    /// See `GalleryZone` for what this means, e.g. that records in positive
+    /// galleries are replaced by the next layout.
+    pub gallery_id: i64,
+    pub wall_id: T,
+    pub art_object_id: ArtObjectId,
+    pub x: f64,
+    pub y: f64,
+    /// Whether the art object is hung rotated 90° clockwise, so its width and
+    /// height are swapped on the wall, see `layout()`. Exports from before this
+    /// existed don't have it, and nothing in them was rotated.
+    #[serde(default)]
+    pub rotated: bool,
+}
+
+/// File sizes are in bytes, and are `None` if the database isn't backed by a file.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceReport {
+    pub db_size_before: Option<u64>,
+    pub db_size_after: Option<u64>,
+    pub wal_size_before: Option<u64>,
+    pub wal_size_after: Option<u64>,
+    pub vacuumed: bool,
+}
+
+/// See `GalleryDb::normalize_artists()`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ArtistNormalizationReport {
+    /// How many art objects had their artist or artist role changed.
+    pub changed_rows: usize,
+    /// How many fewer distinct artist names there are than before.
+    pub merged_artists: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        path::PathBuf,
+        time::Duration,
+    };
+
+    use anyhow::anyhow;
+    use rusqlite::Connection;
+
+    use crate::{
+        art_object::ArtObjectId,
+        filter_parser::DEFAULT_MAX_FILTER_TERMS,
+        gallery_db::{
+            ArtObjectQueryOptions, GalleryObjectCount, GalleryRecord, LayoutAnchor, LayoutRecord,
+        },
+        gallery_wall::GalleryWall,
+        gallery_zone::GalleryZone,
+        medium::MediumCategory,
+        met_api::MetImageUrls,
+    };
+
+    use super::{
+        get_default_gallery_db_filename, get_layout_db_filename, is_busy_error, is_valid_slot_name,
+        normalize_collection_name, ArtObjectLayoutInfo, ArtObjectRecord, ArtistNormalizationReport,
+        ArtistRecord, CollectionRecord, CollectionSource, DistinctColumn, GalleryDb, LayoutScope,
+        MaintenanceReport, PlacedSearchResult, QuarantinedObjectRecord, RelatedArtObjects,
+        SavedFilterRecord, SearchResultPlacement, TagRecord, TimestampedLayoutRecord, UndoneMove,
+        DEFAULT_ART_OBJECT_INSERT_ROWS_PER_STATEMENT, MAX_FILTER_MACRO_DEPTH, MAX_FILTER_PARAMS,
+        MAX_LAYOUT_HISTORY_ENTRIES,
+    };
+
+    const FUNKY_PAINTING_ID: ArtObjectId = ArtObjectId::Met(1);
+    const MONKEY_PAINTING_ID: ArtObjectId = ArtObjectId::Wikidata(5);
+    const MONKEY_ARTIST_QID: i64 = 42;
+
+    fn make_funky_painting() -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id: FUNKY_PAINTING_ID,
+            object_date: "1864".into(),
+            culture: "Martian".into(),
+            artist: "Boop Jones".into(),
+            artist_role: String::default(),
+            title: "Funky Painting".into(),
+            medium: "Oil on canvas".into(),
+            medium_category: MediumCategory::Oil,
+            width: 64.5,
+            height: 28.2,
+            fallback_wikidata_qid: Some(1234),
+            filename: "funky-painting.jpg".into(),
+            collection: "Martian Museum of Art".into(),
+            artist_qid: None,
+            highlight: false,
+            tags: String::default(),
+            classification: String::default(),
+            source_gallery: None,
+        }
+    }
+
+    fn make_monkey_painting() -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id: MONKEY_PAINTING_ID,
+            object_date: "1914".into(),
+            culture: "Simian".into(),
+            artist: "Curious George".into(),
+            artist_role: String::default(),
+            title: "A Funky Monkey".into(),
+            medium: "Oil on canvas".into(),
+            medium_category: MediumCategory::Oil,
+            width: 128.5,
+            height: 12.2,
+            fallback_wikidata_qid: None,
+            filename: "monkey-painting.jpg".into(),
+            collection: "Monkey Museum of Art".into(),
+            artist_qid: Some(MONKEY_ARTIST_QID),
+            highlight: true,
+            tags: String::default(),
+            classification: String::default(),
+            source_gallery: None,
+        }
+    }
+
+    impl Into<ArtObjectLayoutInfo> for ArtObjectRecord {
+        fn into(self) -> ArtObjectLayoutInfo {
+            ArtObjectLayoutInfo {
+                id: self.object_id,
+                width: self.width,
+                height: self.height,
+                highlight: self.highlight,
+                collection: self.collection,
+            }
+        }
+    }
+
+    fn test_filter(db: &GalleryDb, filter: &'static str, expected: &Vec<ArtObjectLayoutInfo>) {
+        let options = ArtObjectQueryOptions {
+            filter: Some(filter.into()),
+            ..Default::default()
+        };
+        let actual = db.get_all_art_objects_for_layout(&options).unwrap();
+        assert_eq!(&actual, expected);
+        assert_eq!(db.count_art_objects(&options).unwrap(), expected.len());
+    }
+
+    fn create_db() -> GalleryDb {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        db.reset_layout_table().unwrap();
+        db
+    }
+
+    #[test]
+    fn test_get_art_object_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+
+        // Make sure we can retrieve it.
+        assert_eq!(
+            db.get_art_object(FUNKY_PAINTING_ID).unwrap(),
+            Some(make_funky_painting())
+        );
+        assert_eq!(db.get_art_object(ArtObjectId::Met(12345)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_art_objects_with_ids_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        assert_eq!(db.get_art_objects_with_ids(&[]).unwrap(), vec![]);
+        let mut records = db
+            .get_art_objects_with_ids(&[
+                MONKEY_PAINTING_ID,
+                ArtObjectId::Met(12345),
+                FUNKY_PAINTING_ID,
+            ])
+            .unwrap();
+        records.sort_by_key(|record| record.object_id.to_raw_i64());
+        let mut expected = vec![make_funky_painting(), make_monkey_painting()];
+        expected.sort_by_key(|record| record.object_id.to_raw_i64());
+        assert_eq!(records, expected);
+
+        // There can be more IDs than SQLite allows parameters in a statement.
+        let ids: Vec<ArtObjectId> = (100..2600)
+            .map(ArtObjectId::Met)
+            .chain([FUNKY_PAINTING_ID])
+            .collect();
+        assert_eq!(
+            db.get_art_objects_with_ids(&ids).unwrap(),
+            vec![make_funky_painting()]
+        );
+    }
+
+    #[test]
+    fn test_get_related_art_objects_works() {
+        let mut db = create_db();
+        let mut other_monkey_painting = make_monkey_painting();
+        other_monkey_painting.object_id = ArtObjectId::Wikidata(6);
+        other_monkey_painting.object_date = "ca. 1850".into();
+        other_monkey_painting.highlight = false;
+        let mut renamed_monkey_painting = make_monkey_painting();
+        renamed_monkey_painting.object_id = ArtObjectId::Wikidata(7);
+        renamed_monkey_painting.artist = "George, Curious".into();
+        renamed_monkey_painting.object_date = "20th century".into();
+        renamed_monkey_painting.highlight = false;
+        let mut watercolor = make_funky_painting();
+        watercolor.object_id = ArtObjectId::Met(2);
+        watercolor.object_date = "1910s".into();
+        watercolor.medium_category = MediumCategory::Watercolor;
+        db.add_art_objects(&vec![
+            make_funky_painting(),
+            make_monkey_painting(),
+            other_monkey_painting.clone(),
+            renamed_monkey_painting.clone(),
+            watercolor.clone(),
+        ])
+        .unwrap();
+
+        let related = db.get_related_art_objects(MONKEY_PAINTING_ID, 10).unwrap();
+        assert_eq!(
+            related.same_artist,
+            vec![
+                other_monkey_painting.clone(),
+                renamed_monkey_painting.clone()
+            ]
+        );
+        assert_eq!(related.same_medium_century, vec![renamed_monkey_painting]);
+
+        // Artists without QIDs are matched by name, and highlights come first.
+        let related = db
+            .get_related_art_objects(watercolor.object_id, 10)
+            .unwrap();
+        assert_eq!(related.same_artist, vec![make_funky_painting()]);
+        assert_eq!(related.same_medium_century, vec![]);
+
+        let related = db
+            .get_related_art_objects(other_monkey_painting.object_id, 1)
+            .unwrap();
+        assert_eq!(related.same_artist, vec![make_monkey_painting()]);
+        assert_eq!(related.same_medium_century, vec![make_funky_painting()]);
+
+        assert_eq!(
+            db.get_related_art_objects(ArtObjectId::Met(12345), 10)
+                .unwrap(),
+            RelatedArtObjects::default()
+        );
+    }
+
+    #[test]
+    fn test_distinct_values_works() {
+        let mut db = create_db();
+        let painting = |id: i64, artist: &str, culture: &str| ArtObjectRecord {
+            object_id: ArtObjectId::Met(id),
+            artist: artist.into(),
+            culture: culture.into(),
+            ..make_funky_painting()
+        };
+        db.add_art_objects(&vec![
+            painting(1, "Zed", "Martian"),
+            painting(2, "Zed", "Martian"),
+            painting(3, "Amy", "Martian"),
+            painting(4, "Amy", ""),
+            painting(5, "Bob", ""),
+            painting(6, "", ""),
+            painting(7, "", ""),
+            painting(8, "", ""),
+        ])
+        .unwrap();
+
+        let values = |column, min_count, limit| -> Vec<(String, usize)> {
+            db.distinct_values(column, min_count, limit).unwrap()
+        };
+        assert_eq!(
+            values(DistinctColumn::Artist, 1, 10),
+            vec![("Amy".into(), 2), ("Zed".into(), 2), ("Bob".into(), 1)]
+        );
+        assert_eq!(
+            values(DistinctColumn::Artist, 2, 10),
+            vec![("Amy".into(), 2), ("Zed".into(), 2)]
+        );
+        assert_eq!(
+            values(DistinctColumn::Artist, 1, 1),
+            vec![("Amy".into(), 2)]
+        );
+        assert_eq!(
+            values(DistinctColumn::Culture, 0, 10),
+            vec![("Martian".into(), 3)]
+        );
+        assert_eq!(
+            values(DistinctColumn::Collection, 1, 10),
+            vec![("Martian Museum of Art".into(), 8)]
+        );
+    }
+
+    #[test]
+    fn test_distinct_column_from_name_works() {
+        for column in [
+            DistinctColumn::Artist,
+            DistinctColumn::Culture,
+            DistinctColumn::Medium,
+            DistinctColumn::Collection,
+        ] {
+            assert_eq!(DistinctColumn::from_name(column.as_str()), Some(column));
+        }
+        assert_eq!(DistinctColumn::from_name("title"), None);
+        assert_eq!(
+            DistinctColumn::from_name("artist; DROP TABLE art_objects"),
+            None
+        );
+    }
+
+    /// Returns lots of art objects, some of which replace earlier ones.
+    fn make_synthetic_art_objects(count: usize) -> Vec<ArtObjectRecord> {
+        (0..count)
+            .map(|i| {
+                let object_id = if i % 2 == 0 {
+                    ArtObjectId::Met((i % (count - 10)) as i64)
+                } else {
+                    ArtObjectId::Wikidata(i as i64)
+                };
+                ArtObjectRecord {
+                    object_id,
+                    title: format!("Painting #{i}"),
+                    object_date: format!("{}", 1500 + i % 500),
+                    artist_qid: if i % 3 == 0 { Some(i as i64) } else { None },
+                    fallback_wikidata_qid: if i % 5 == 0 { Some(i as i64) } else { None },
+                    highlight: i % 7 == 0,
+                    width: i as f64 / 10.0,
+                    ..make_funky_painting()
+                }
+            })
+            .collect()
+    }
+
+    fn dump_art_objects_table(db: &GalleryDb) -> Vec<Vec<rusqlite::types::Value>> {
+        let mut statement = db
+            .conn
+            .prepare("SELECT * FROM art_objects ORDER BY id")
+            .unwrap();
+        let columns = statement.column_count();
+        let rows = statement
+            .query_map((), |row| {
+                (0..columns)
+                    .map(|i| row.get::<_, rusqlite::types::Value>(i))
+                    .collect()
+            })
+            .unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_add_art_objects_batched_matches_add_art_objects() {
+        let records = make_synthetic_art_objects(1234);
+        let mut unbatched = create_db();
+        unbatched.add_art_objects(&records).unwrap();
+        for rows_per_statement in [0, 1, 7, DEFAULT_ART_OBJECT_INSERT_ROWS_PER_STATEMENT] {
+            let mut batched = create_db();
+            batched
+                .add_art_objects_batched(&records, rows_per_statement)
+                .unwrap();
+            assert_eq!(
+                dump_art_objects_table(&batched),
+                dump_art_objects_table(&unbatched),
+                "{rows_per_statement} rows per statement"
+            );
+        }
+        assert_eq!(
+            unbatched.get_art_object(ArtObjectId::Wikidata(1)).unwrap(),
+            Some(records[1].clone())
+        );
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture` to see how fast
+    /// importing art objects is.
+    #[test]
+    #[ignore]
+    fn bench_add_art_objects() {
+        let records = make_synthetic_art_objects(10_000);
+        let time = |name: &str, add: &dyn Fn(&mut GalleryDb)| {
+            let mut db = create_db();
+            let start = std::time::Instant::now();
+            add(&mut db);
+            let secs = start.elapsed().as_secs_f64();
+            println!(
+                "{name}: {:.0} rows/sec",
+                records.len() as f64 / secs.max(f64::EPSILON)
+            );
+        };
+        time("add_art_objects", &|db| {
+            db.add_art_objects(&records).unwrap()
+        });
+        time("add_art_objects_batched", &|db| {
+            db.add_art_objects_batched(&records, DEFAULT_ART_OBJECT_INSERT_ROWS_PER_STATEMENT)
+                .unwrap()
+        });
+    }
+
+    #[test]
+    fn test_get_art_objects_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        let all = ArtObjectQueryOptions::default();
+        let funky = ArtObjectQueryOptions {
+            filter: Some("funky".into()),
+            ..Default::default()
+        };
+
+        // Art objects are ordered by ID, and Met IDs come before Wikidata ones.
+        assert_eq!(
+            db.get_art_objects(&all, 0, 10).unwrap(),
+            vec![make_funky_painting(), make_monkey_painting()]
+        );
+        assert_eq!(
+            db.get_art_objects(&all, 0, 1).unwrap(),
+            vec![make_funky_painting()]
+        );
+        assert_eq!(
+            db.get_art_objects(&all, 1, 10).unwrap(),
+            vec![make_monkey_painting()]
+        );
+        assert_eq!(db.get_art_objects(&all, 2, 10).unwrap(), vec![]);
+
+        let monkey = ArtObjectQueryOptions {
+            filter: Some("monkey".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            db.get_art_objects(&monkey, 0, 10).unwrap(),
+            vec![make_monkey_painting()]
+        );
+        assert_eq!(db.get_art_objects(&funky, 0, 10).unwrap().len(), 2);
+        assert_eq!(db.get_art_objects(&funky, 1, 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_search_placed_art_objects_works() {
+        let mut db = create_db();
+        let mut unplaced_funky = make_funky_painting();
+        unplaced_funky.object_id = ArtObjectId::Met(2);
+        unplaced_funky.title = "Unplaced Funky Painting".into();
+        db.add_art_objects(&vec![
+            make_funky_painting(),
+            make_monkey_painting(),
+            unplaced_funky,
+        ])
+        .unwrap();
+        let layout_record = |gallery_id, wall_id, art_object_id| LayoutRecord {
+            gallery_id,
+            wall_id,
+            art_object_id,
+            x: 1.0,
+            y: 2.0,
+            rotated: false,
+        };
+        db.upsert_layout_records(&vec![
+            layout_record(2, "wall_a", FUNKY_PAINTING_ID),
+            layout_record(1, "wall_b", MONKEY_PAINTING_ID),
+        ])
+        .unwrap();
+        let placement = |gallery_id, wall_id: &str| {
+            Some(SearchResultPlacement {
+                gallery_id,
+                wall_id: wall_id.to_string(),
+                x: 1.0,
+            })
+        };
+        let funky = ArtObjectQueryOptions {
+            filter: Some("funky".into()),
+            ..Default::default()
+        };
+
+        // Placed art objects come first, ordered by gallery.
+        assert_eq!(
+            db.search_placed_art_objects(&funky, 10).unwrap(),
+            vec![
+                PlacedSearchResult {
+                    object_id: MONKEY_PAINTING_ID,
+                    title: "A Funky Monkey".into(),
+                    artist: "Curious George".into(),
+                    placement: placement(1, "wall_b"),
+                },
+                PlacedSearchResult {
+                    object_id: FUNKY_PAINTING_ID,
+                    title: "Funky Painting".into(),
+                    artist: "Boop Jones".into(),
+                    placement: placement(2, "wall_a"),
+                },
+                PlacedSearchResult {
+                    object_id: ArtObjectId::Met(2),
+                    title: "Unplaced Funky Painting".into(),
+                    artist: "Boop Jones".into(),
+                    placement: None,
+                },
+            ]
+        );
+        assert_eq!(db.search_placed_art_objects(&funky, 2).unwrap().len(), 2);
+
+        let unplaced = ArtObjectQueryOptions {
+            filter: Some("unplaced".into()),
+            ..Default::default()
+        };
+        let results = db.search_placed_art_objects(&unplaced, 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].placement, None);
+    }
+
+    #[test]
+    fn test_wikidata_qid_getters_work() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        assert_eq!(
+            db.get_all_fallback_wikidata_qids().unwrap(),
+            [1234].into_iter().collect()
+        );
+        assert_eq!(
+            db.get_fallback_wikidata_qids_by_id().unwrap(),
+            [(FUNKY_PAINTING_ID, 1234)].into_iter().collect()
+        );
+        assert_eq!(
+            db.get_all_wikidata_object_qids().unwrap(),
+            [5].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_delete_art_objects_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        assert_eq!(
+            db.delete_art_objects(&[MONKEY_PAINTING_ID, ArtObjectId::Met(12345)])
+                .unwrap(),
+            1
+        );
+        assert_eq!(db.get_art_object(MONKEY_PAINTING_ID).unwrap(), None);
+        assert_eq!(
+            db.get_art_object(FUNKY_PAINTING_ID).unwrap(),
+            Some(make_funky_painting())
+        );
+    }
+
+    #[test]
+    fn test_get_met_object_ids_after_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![
+            make_funky_painting(),
+            make_monkey_painting(),
+            ArtObjectRecord {
+                object_id: ArtObjectId::Met(7),
+                ..make_funky_painting()
+            },
+        ])
+        .unwrap();
+        assert_eq!(db.get_met_object_ids_after(0).unwrap(), vec![1, 7]);
+        assert_eq!(db.get_met_object_ids_after(1).unwrap(), vec![7]);
+        assert_eq!(db.get_met_object_ids_after(7).unwrap(), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn test_met_image_urls_work() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        assert_eq!(db.get_met_image_urls(1).unwrap(), None);
+        assert_eq!(db.get_met_object_ids_without_image_urls().unwrap(), vec![1]);
+
+        let urls = MetImageUrls {
+            primary_image: "https://images.metmuseum.org/original/funky.jpg".into(),
+            primary_image_small: "https://images.metmuseum.org/web-large/funky.jpg".into(),
+        };
+        db.set_met_image_urls(&[(1, urls.clone())]).unwrap();
+        assert_eq!(db.get_met_image_urls(1).unwrap(), Some(urls));
+        assert_eq!(
+            db.get_met_object_ids_without_image_urls().unwrap(),
+            Vec::<i64>::new()
+        );
+
+        // Objects without images have empty URLs, which still count as stored.
+        db.set_met_image_urls(&[(1, MetImageUrls::default())])
+            .unwrap();
+        assert_eq!(
+            db.get_met_image_urls(1).unwrap(),
+            Some(MetImageUrls::default())
+        );
+        assert_eq!(db.get_met_image_urls(2).unwrap(), None);
+    }
+
+    #[test]
+    fn test_add_art_objects_replaces_existing_ones() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+        let mut painting = make_funky_painting();
+        painting.title = "Funkier Painting".into();
+        db.add_art_objects(&vec![painting.clone()]).unwrap();
+        assert_eq!(
+            db.get_art_object(FUNKY_PAINTING_ID).unwrap(),
+            Some(painting)
+        );
+        assert_eq!(db.count_art_objects(&Default::default()).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_artists_work() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        let artist = ArtistRecord {
+            qid: MONKEY_ARTIST_QID,
+            name: "Curious George".into(),
+            description: "Monkey and painter".into(),
+        };
+        db.add_artists(&vec![artist.clone()]).unwrap();
+        db.upsert_layout_records(&vec![LayoutRecord {
+            gallery_id: 1,
+            wall_id: "wall_a",
+            art_object_id: MONKEY_PAINTING_ID,
+            x: 1.0,
+            y: 2.0,
+            rotated: false,
+        }])
+        .unwrap();
+
+        // Make sure the artist QID makes it through the layout join, and that we can
+        // look up the artist with it.
+        let objects = db.get_art_objects_for_gallery_wall(1, "wall_a").unwrap();
+        assert_eq!(objects.len(), 1);
+        let artist_qid = objects[0].0.artist_qid.unwrap();
+        assert_eq!(db.get_artist(artist_qid).unwrap(), Some(artist));
+
+        // Met-only objects have no artist QID.
+        assert_eq!(
+            db.get_art_object(FUNKY_PAINTING_ID)
+                .unwrap()
+                .unwrap()
+                .artist_qid,
+            None
+        );
+        assert_eq!(db.get_artist(12345).unwrap(), None);
+    }
+
+    #[test]
+    fn test_filtering_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+
+        let funky_layout_info = vec![make_funky_painting().into()];
+        let empty_layout_info = vec![];
+
+        // Search for the art object.
+        test_filter(&db, "boop", &funky_layout_info);
+
+        // Excluding a term in the art object shouldn't find anything.
+        test_filter(&db, "-boop", &empty_layout_info);
+
+        // Excluding a term *not* in the art object should find it.
+        test_filter(&db, "-blargblarg", &funky_layout_info);
+
+        // Ensure unquoted terms are ANDed together...
+        test_filter(&db, "boop jones", &funky_layout_info);
+        test_filter(&db, "jones boop", &funky_layout_info);
+
+        // Ensure quoted terms are exact substring matches...
+        test_filter(&db, "\"boop jones\"", &funky_layout_info);
+        test_filter(&db, "\"jones boop\"", &empty_layout_info);
+    }
+
+    #[test]
+    fn test_normalize_collection_name_works() {
+        assert_eq!(normalize_collection_name("  The MET\t"), "the met");
+        assert_eq!(normalize_collection_name("the met"), "the met");
+        assert_eq!(normalize_collection_name(" "), "");
+    }
+
+    #[test]
+    fn test_collections_work() {
+        let mut db = create_db();
+        assert_eq!(db.list_collections().unwrap(), vec![]);
+
+        let in_collection =
+            |record: ArtObjectRecord, object_id, collection: &str| ArtObjectRecord {
+                object_id,
+                collection: collection.into(),
+                ..record
+            };
+        db.add_art_objects(&vec![
+            make_funky_painting(),
+            in_collection(
+                make_funky_painting(),
+                ArtObjectId::Met(2),
+                "Metropolitan Museum of Art",
+            ),
+            make_monkey_painting(),
+            in_collection(
+                make_monkey_painting(),
+                ArtObjectId::Wikidata(6),
+                " metropolitan museum of art ",
+            ),
+            in_collection(
+                make_monkey_painting(),
+                ArtObjectId::Wikidata(7),
+                "Metropolitan Museum of Art",
+            ),
+            in_collection(make_monkey_painting(), ArtObjectId::Wikidata(8), ""),
+        ])
+        .unwrap();
+        db.rebuild_collection_stats().unwrap();
+        assert_eq!(
+            db.list_collections().unwrap(),
+            vec![
+                CollectionRecord {
+                    id: 1,
+                    name: "Martian Museum of Art".into(),
+                    object_count: 1,
+                    source: CollectionSource::Met,
+                },
+                CollectionRecord {
+                    id: 2,
+                    name: "Metropolitan Museum of Art".into(),
+                    object_count: 3,
+                    source: CollectionSource::Mixed,
+                },
+                CollectionRecord {
+                    id: 3,
+                    name: "Monkey Museum of Art".into(),
+                    object_count: 1,
+                    source: CollectionSource::Wikidata,
+                },
+            ]
+        );
+
+        let ids = |filter: &str| -> Vec<ArtObjectId> {
+            let options = ArtObjectQueryOptions {
+                filter: Some(filter.into()),
+                ..Default::default()
+            };
+            db.get_all_art_objects_for_layout(&options)
+                .unwrap()
+                .into_iter()
+                .map(|info| info.id)
+                .collect()
+        };
+        assert_eq!(
+            ids("collection:\"METROPOLITAN museum of art\""),
+            vec![
+                ArtObjectId::Met(2),
+                ArtObjectId::Wikidata(6),
+                ArtObjectId::Wikidata(7)
+            ]
+        );
+        assert_eq!(
+            ids("-collection:\"metropolitan museum of art\""),
+            vec![
+                FUNKY_PAINTING_ID,
+                MONKEY_PAINTING_ID,
+                ArtObjectId::Wikidata(8)
+            ]
+        );
+        // Unlike regular terms, this doesn't match substrings.
+        assert_eq!(ids("collection:martian"), vec![]);
+        assert_eq!(ids("\"martian museum\""), vec![FUNKY_PAINTING_ID]);
+
+        db.reset_art_objects_table().unwrap();
+        assert_eq!(db.list_collections().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_normalize_artists_works() {
+        let mut db = create_db();
+        let by_artist = |id: i64, artist: &str| ArtObjectRecord {
+            object_id: ArtObjectId::Met(id),
+            artist: artist.into(),
+            ..make_funky_painting()
+        };
+        db.add_art_objects(&vec![
+            by_artist(1, "Albrecht Dürer"),
+            by_artist(2, "Dürer, Albrecht"),
+            by_artist(3, "Albrecht Durer"),
+            by_artist(4, "Workshop of Albrecht Dürer"),
+            by_artist(5, "Vincent van Gogh"),
+            by_artist(6, ""),
+        ])
+        .unwrap();
+        let report = db.normalize_artists().unwrap();
+        assert_eq!(
+            report,
+            ArtistNormalizationReport {
+                changed_rows: 3,
+                merged_artists: 3,
+            }
+        );
+        let artist = |id: i64| {
+            let record = db.get_art_object(ArtObjectId::Met(id)).unwrap().unwrap();
+            (record.artist, record.artist_role)
+        };
+        assert_eq!(artist(1), ("Albrecht Dürer".into(), "".into()));
+        assert_eq!(artist(2), ("Albrecht Dürer".into(), "".into()));
+        assert_eq!(artist(3), ("Albrecht Dürer".into(), "".into()));
+        assert_eq!(artist(4), ("Albrecht Dürer".into(), "workshop of".into()));
+        assert_eq!(artist(5), ("Vincent van Gogh".into(), "".into()));
+        assert_eq!(artist(6), ("".into(), "".into()));
+
+        // Normalizing again shouldn't change anything.
+        assert_eq!(
+            db.normalize_artists().unwrap(),
+            ArtistNormalizationReport::default()
+        );
+    }
+
+    #[test]
+    fn test_quarantined_objects_work() {
+        let mut db = create_db();
+        assert_eq!(db.list_quarantined_objects().unwrap(), vec![]);
+        let record = QuarantinedObjectRecord {
+            object_id: MONKEY_PAINTING_ID,
+            title: "A Funky Monkey".into(),
+            artist: "Curious George".into(),
+            collection: "Monkey Museum of Art".into(),
+            width: 40.0,
+            height: 40.0,
+            reason: "too large".into(),
+        };
+        db.add_quarantined_objects(&vec![record]).unwrap();
+        let quarantined = db.list_quarantined_objects().unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].object_id, MONKEY_PAINTING_ID);
+        assert_eq!(quarantined[0].reason, "too large");
+        assert_eq!(db.get_art_object(MONKEY_PAINTING_ID).unwrap(), None);
+
+        // Re-importing clears the quarantine.
+        db.reset_art_objects_table().unwrap();
+        assert_eq!(db.list_quarantined_objects().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_saved_filters_work() {
+        let mut db = create_db();
+        assert_eq!(db.list_saved_filters().unwrap(), vec![]);
+        assert_eq!(db.get_saved_filter("oils").unwrap(), None);
+        assert!(!db.delete_saved_filter("oils").unwrap());
+
+        db.save_filter("oils", "medium_category:oil").unwrap();
+        db.save_filter("apes", "monkey").unwrap();
+        assert_eq!(
+            db.get_saved_filter("oils").unwrap(),
+            Some("medium_category:oil".to_string())
+        );
+        let filters = db.list_saved_filters().unwrap();
+        assert_eq!(
+            filters
+                .iter()
+                .map(|filter| filter.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["apes", "oils"]
+        );
+        let created_at = filters[1].created_at;
+        assert!(created_at > 0);
+
+        // Replacing a saved filter keeps its creation time.
+        db.save_filter("oils", "\"oil on canvas\"").unwrap();
+        let filters = db.list_saved_filters().unwrap();
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters[1].filter, "\"oil on canvas\"");
+        assert_eq!(filters[1].created_at, created_at);
+
+        assert!(db.save_filter("big oils", "oil").is_err());
+        assert!(db.save_filter("", "oil").is_err());
+        assert!(db.save_filter("nothing", "").is_err());
+        assert!(db.save_filter("typo", "@nonexistent").is_err());
+
+        assert!(db.delete_saved_filter("oils").unwrap());
+        assert!(!db.delete_saved_filter("oils").unwrap());
+        assert_eq!(db.get_saved_filter("oils").unwrap(), None);
+        assert_eq!(db.list_saved_filters().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_import_saved_filters_works() {
+        let mut db = create_db();
+        db.save_filter("oils", "oil").unwrap();
+        let saved_filter = |name: &str, filter: &str| SavedFilterRecord {
+            name: name.to_string(),
+            filter: filter.to_string(),
+            created_at: 5,
+        };
+        let imported = db
+            .import_saved_filters(&[
+                saved_filter("oils", "canvas"),
+                saved_filter("creepy", "@spooky"),
+                saved_filter("spooky", "@creepy or ghost"),
+            ])
+            .unwrap();
+        assert_eq!(imported, 2);
+        let filters = db.list_saved_filters().unwrap();
+        assert_eq!(filters.len(), 3);
+        assert_eq!(filters[0], saved_filter("creepy", "@spooky"));
+        assert_eq!(filters[1].filter, "oil");
+        assert_ne!(filters[1].created_at, 5);
+        assert_eq!(filters[2], saved_filter("spooky", "@creepy or ghost"));
+
+        // Importing what we listed shouldn't change anything.
+        assert_eq!(db.import_saved_filters(&filters).unwrap(), 0);
+        assert_eq!(db.list_saved_filters().unwrap(), filters);
+    }
+
+    #[test]
+    fn test_highlight_filtering_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+
+        let funky_only = vec![make_funky_painting().into()];
+        let monkey_only = vec![make_monkey_painting().into()];
+        test_filter(&db, "highlight:true", &monkey_only);
+        test_filter(&db, "highlight:false", &funky_only);
+        test_filter(&db, "-highlight:true", &funky_only);
+        test_filter(&db, "boop highlight:true", &vec![]);
+        assert!(
+            db.get_art_object(MONKEY_PAINTING_ID)
+                .unwrap()
+                .unwrap()
+                .highlight
+        );
+    }
+
+    #[test]
+    fn test_date_range_filtering_works() {
+        let mut db = create_db();
+        let undated = ArtObjectRecord {
+            object_id: ArtObjectId::Met(2),
+            object_date: "n.d.".into(),
+            ..make_funky_painting()
+        };
+        db.add_art_objects(&vec![
+            make_funky_painting(),
+            make_monkey_painting(),
+            undated.clone(),
+        ])
+        .unwrap();
+
+        let funky_only = vec![make_funky_painting().into()];
+        let monkey_only = vec![make_monkey_painting().into()];
+        test_filter(
+            &db,
+            "date:1864-1914",
+            &vec![make_funky_painting().into(), make_monkey_painting().into()],
+        );
+        test_filter(&db, "date:1864", &funky_only);
+        test_filter(&db, "date:1863", &vec![]);
+        test_filter(&db, "date:1865-1913", &vec![]);
+        test_filter(&db, "date:1865-1914", &monkey_only);
+        test_filter(&db, "date:1914-1865", &monkey_only);
+        test_filter(&db, "date:1800-1864", &funky_only);
+        test_filter(&db, "monkey date:1800-1900", &vec![]);
+        test_filter(&db, "-date:1900-2000", &vec![make_funky_painting().into()]);
+        // Objects whose dates can't be parsed never match date ranges, but
+        // can still be found in other ways.
+        test_filter(
+            &db,
+            "funky -monkey",
+            &vec![make_funky_painting().into(), undated.into()],
+        );
+    }
+
+    #[test]
+    fn test_tags_work() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        assert_eq!(
+            db.get_tags(FUNKY_PAINTING_ID).unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(!db.remove_tag(FUNKY_PAINTING_ID, "favorite").unwrap());
+
+        assert!(db.add_tag(FUNKY_PAINTING_ID, "Favorite ").unwrap());
+        assert!(!db.add_tag(FUNKY_PAINTING_ID, "favorite").unwrap());
+        assert!(db.add_tag(FUNKY_PAINTING_ID, "creepy").unwrap());
+        assert!(db.add_tag(MONKEY_PAINTING_ID, "favorite").unwrap());
+        assert!(db.add_tag(FUNKY_PAINTING_ID, "show mom").is_err());
+        assert!(db.add_tag(FUNKY_PAINTING_ID, "").is_err());
+        assert_eq!(
+            db.get_tags(FUNKY_PAINTING_ID).unwrap(),
+            vec!["creepy".to_string(), "favorite".to_string()]
+        );
+        assert_eq!(
+            db.get_objects_with_tag("FAVORITE").unwrap(),
+            vec![FUNKY_PAINTING_ID, MONKEY_PAINTING_ID]
+        );
+        assert_eq!(db.get_objects_with_tag("boop").unwrap(), vec![]);
+
+        assert!(db.remove_tag(FUNKY_PAINTING_ID, "Favorite").unwrap());
+        assert!(!db.remove_tag(FUNKY_PAINTING_ID, "favorite").unwrap());
+        assert_eq!(
+            db.get_tags(FUNKY_PAINTING_ID).unwrap(),
+            vec!["creepy".to_string()]
+        );
+        assert_eq!(
+            db.get_objects_with_tag("favorite").unwrap(),
+            vec![MONKEY_PAINTING_ID]
+        );
+    }
+
+    #[test]
+    fn test_tags_survive_resetting_art_objects() {
+        let mut db = create_db();
+        db.add_tag(FUNKY_PAINTING_ID, "favorite").unwrap();
+        db.reset_art_objects_table().unwrap();
+        assert_eq!(
+            db.get_tags(FUNKY_PAINTING_ID).unwrap(),
+            vec!["favorite".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_import_tags_works() {
+        let mut db = create_db();
+        db.add_tag(FUNKY_PAINTING_ID, "favorite").unwrap();
+        let tag = |art_object_id, tag: &str| TagRecord {
+            art_object_id,
+            tag: tag.to_string(),
+            created_at: 5,
+        };
+        let imported = db
+            .import_tags(&[
+                tag(FUNKY_PAINTING_ID, "favorite"),
+                tag(FUNKY_PAINTING_ID, "creepy"),
+                tag(MONKEY_PAINTING_ID, "not valid"),
+                tag(MONKEY_PAINTING_ID, "Show-Mom"),
+            ])
+            .unwrap();
+        assert_eq!(imported, 2);
+        let all_tags = db.get_all_tags().unwrap();
+        assert_eq!(all_tags.len(), 3);
+        assert_eq!(all_tags[0], tag(FUNKY_PAINTING_ID, "creepy"));
+        assert_eq!(all_tags[1].tag, "favorite");
+        assert_ne!(all_tags[1].created_at, 5);
+        assert_eq!(all_tags[2], tag(MONKEY_PAINTING_ID, "show-mom"));
+
+        // Importing what we exported shouldn't change anything.
+        assert_eq!(db.import_tags(&all_tags).unwrap(), 0);
+        assert_eq!(db.get_all_tags().unwrap(), all_tags);
+    }
+
+    #[test]
+    fn test_tag_filtering_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        db.add_tag(FUNKY_PAINTING_ID, "creepy").unwrap();
+        db.add_tag(FUNKY_PAINTING_ID, "favorite").unwrap();
+        db.add_tag(MONKEY_PAINTING_ID, "favorite").unwrap();
+
+        let funky_only = vec![make_funky_painting().into()];
+        let monkey_only = vec![make_monkey_painting().into()];
+        let both = vec![make_funky_painting().into(), make_monkey_painting().into()];
+        test_filter(&db, "tag:creepy", &funky_only);
+        test_filter(&db, "tag:CREEPY", &funky_only);
+        test_filter(&db, "-tag:creepy", &monkey_only);
+        test_filter(&db, "tag:favorite", &both);
+        test_filter(&db, "tag:favorite -tag:creepy", &monkey_only);
+        test_filter(&db, "tag:creepy OR monkey", &both);
+        test_filter(&db, "monkey tag:creepy", &vec![]);
+        test_filter(&db, "tag:boop", &vec![]);
+        test_filter(&db, "-tag:boop", &both);
+
+        // Tags should work in saved filters, and in queries that alias the table.
+        db.save_filter("spooky", "tag:creepy").unwrap();
+        test_filter(&db, "@spooky", &funky_only);
+        let options = ArtObjectQueryOptions {
+            filter: Some("-@spooky".into()),
+            ..Default::default()
+        };
+        let records = db.get_art_objects(&options, 0, 10).unwrap();
+        assert_eq!(records, vec![make_monkey_painting()]);
+    }
+
+    #[test]
+    fn test_collection_tags_and_classification_filtering_works() {
+        let mut db = create_db();
+        let funky = ArtObjectRecord {
+            tags: "Portraits|Men|Still Life".into(),
+            classification: "Paintings".into(),
+            ..make_funky_painting()
+        };
+        let monkey = ArtObjectRecord {
+            tags: "Animals|Monkeys".into(),
+            classification: "Drawings".into(),
+            ..make_monkey_painting()
+        };
+        db.add_art_objects(&vec![funky.clone(), monkey.clone()])
+            .unwrap();
+        db.add_tag(MONKEY_PAINTING_ID, "portraits").unwrap();
+
+        let funky_only = vec![funky.clone().into()];
+        let monkey_only = vec![monkey.clone().into()];
+        let both = vec![funky.clone().into(), monkey.clone().into()];
+        test_filter(&db, "tag:men", &funky_only);
+        test_filter(&db, "tag:\"still life\"", &funky_only);
+        // Tags only match whole tags.
+        test_filter(&db, "tag:monkey", &vec![]);
+        test_filter(&db, "tag:still", &vec![]);
+        // Player tags and collection tags are matched alike.
+        test_filter(&db, "tag:portraits", &both);
+        test_filter(&db, "-tag:animals", &funky_only);
+        test_filter(&db, "class:drawing", &monkey_only);
+        test_filter(&db, "CLASS:PAINTINGS", &funky_only);
+        test_filter(&db, "-class:paintings", &monkey_only);
+        test_filter(&db, "class:prints", &vec![]);
+        // Free-text terms match tags and classifications too.
+        test_filter(&db, "monkeys", &monkey_only);
+        test_filter(&db, "\"still life\"", &funky_only);
+        test_filter(&db, "drawings", &monkey_only);
+
+        assert_eq!(db.get_art_object(FUNKY_PAINTING_ID).unwrap(), Some(funky));
+    }
+
+    #[test]
+    fn test_source_galleries_work() {
+        let mut db = create_db();
+        let funky = ArtObjectRecord {
+            source_gallery: Some("825".into()),
+            ..make_funky_painting()
+        };
+        let monkey = ArtObjectRecord {
+            source_gallery: Some("".into()),
+            ..make_monkey_painting()
+        };
+        db.add_art_objects(&vec![funky.clone(), monkey]).unwrap();
+        assert_eq!(db.get_art_object(FUNKY_PAINTING_ID).unwrap(), Some(funky));
+        // Empty source galleries are as good as none.
+        assert_eq!(
+            db.get_source_galleries_by_id().unwrap(),
+            HashMap::from([(FUNKY_PAINTING_ID, "825".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_get_artists_by_id_works() {
+        let mut db = create_db();
+        let monkey = ArtObjectRecord {
+            artist: "".into(),
+            ..make_monkey_painting()
+        };
+        db.add_art_objects(&vec![make_funky_painting(), monkey])
+            .unwrap();
+        assert_eq!(
+            db.get_artists_by_id().unwrap(),
+            HashMap::from([(FUNKY_PAINTING_ID, "Boop Jones".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_saved_filter_expansion_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        db.save_filter("monkeys", "monkey").unwrap();
+        db.save_filter("martians", "marvin OR martian").unwrap();
+        db.save_filter("simian_oils", "@monkeys medium_category:oil")
+            .unwrap();
+
+        let funky_only = vec![make_funky_painting().into()];
+        let monkey_only = vec![make_monkey_painting().into()];
+        test_filter(&db, "@monkeys", &monkey_only);
+        test_filter(&db, "@simian_oils", &monkey_only);
+        test_filter(&db, "-@monkeys", &funky_only);
+        test_filter(&db, "funky -@monkeys", &funky_only);
+        // The saved filter's OR shouldn't leak out into the surrounding filter.
+        test_filter(&db, "monkey @martians", &vec![]);
+
+        let options = ArtObjectQueryOptions {
+            filter: Some("@nonexistent".into()),
+            ..Default::default()
+        };
+        assert!(db.count_art_objects(&options).is_err());
+
+        // Deleting a saved filter breaks the ones that refer to it.
+        db.delete_saved_filter("monkeys").unwrap();
+        let options = ArtObjectQueryOptions {
+            filter: Some("@simian_oils".into()),
+            ..Default::default()
+        };
+        assert!(db.count_art_objects(&options).is_err());
+    }
+
+    #[test]
+    fn test_self_referential_saved_filters_are_rejected() {
+        let mut db = create_db();
+        let err = db.save_filter("loop", "funky -@loop").unwrap_err();
+        assert!(err.to_string().contains("refers to itself"), "{err}");
+        assert_eq!(db.get_saved_filter("loop").unwrap(), None);
+
+        // Indirect cycles should be rejected too, leaving the original alone.
+        db.save_filter("a", "funky").unwrap();
+        db.save_filter("b", "@a").unwrap();
+        let err = db.save_filter("a", "@b").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Saved filter @a refers to itself (@a -> @b -> @a)"
+        );
+        assert_eq!(db.get_saved_filter("a").unwrap(), Some("funky".to_string()));
+
+        // Cycles that somehow made it into the database are still caught.
+        let options = ArtObjectQueryOptions {
+            filter: Some("@a".into()),
+            ..Default::default()
+        };
+        assert!(options
+            .where_clause(|name| Ok(Some(format!("@{name}"))))
+            .is_err());
+    }
+
+    #[test]
+    fn test_saved_filter_depth_is_limited() {
+        let mut db = create_db();
+        db.save_filter("level0", "funky").unwrap();
+        for level in 1..MAX_FILTER_MACRO_DEPTH {
+            db.save_filter(&format!("level{level}"), &format!("@level{}", level - 1))
+                .unwrap();
+        }
+        let err = db
+            .save_filter("too_deep", &format!("@level{}", MAX_FILTER_MACRO_DEPTH - 1))
+            .unwrap_err();
+        assert!(err.to_string().contains("nested"), "{err}");
+    }
+
+    #[test]
+    fn test_huge_filters_are_rejected() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+        let count = |db: &GalleryDb, filter: String| {
+            db.count_art_objects(&ArtObjectQueryOptions {
+                filter: Some(filter),
+                ..Default::default()
+            })
+        };
+
+        let err = count(&db, vec!["funky"; 1000].join(" ")).unwrap_err();
+        assert!(err.to_string().contains("characters long"), "{err}");
+        let err = count(&db, vec!["a"; 100].join(" ")).unwrap_err();
+        assert!(err.to_string().contains("100 terms"), "{err}");
+        // This used to silently match everything.
+        let err = count(&db, "funky -".into()).unwrap_err();
+        assert!(err.to_string().contains("could not be parsed"), "{err}");
+
+        // Saved filters can't be used to sneak past SQLite's parameter limit.
+        let terms = vec!["a"; DEFAULT_MAX_FILTER_TERMS].join(" ");
+        db.save_filter("many", &terms).unwrap();
+        let refs = vec!["@many"; DEFAULT_MAX_FILTER_TERMS].join(" ");
+        let err = db.save_filter("too_many", &refs).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("can have at most {MAX_FILTER_PARAMS}")),
+            "{err}"
+        );
+        let err = count(&db, refs).unwrap_err();
+        assert!(
+            err.to_string().contains("once saved filters are expanded"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_medium_categories_work() {
+        let mut db = create_db();
+        let mut monkey_painting = make_monkey_painting();
+        monkey_painting.medium = "Watercolor on paper".into();
+        monkey_painting.medium_category = MediumCategory::Watercolor;
+        let mut other_monkey_painting = make_monkey_painting();
+        other_monkey_painting.object_id = ArtObjectId::Wikidata(6);
+        other_monkey_painting.medium_category = MediumCategory::Watercolor;
+        db.add_art_objects(&vec![
+            make_funky_painting(),
+            monkey_painting,
+            other_monkey_painting,
+        ])
+        .unwrap();
+
+        assert_eq!(
+            db.get_art_object(FUNKY_PAINTING_ID)
+                .unwrap()
+                .unwrap()
+                .medium_category,
+            MediumCategory::Oil
+        );
+        assert_eq!(
+            db.count_art_objects_by_medium_category().unwrap(),
+            vec![(MediumCategory::Watercolor, 2), (MediumCategory::Oil, 1)]
+        );
+
+        let funky_layout_info = vec![make_funky_painting().into()];
+        test_filter(&db, "medium_category:oil", &funky_layout_info);
+        test_filter(&db, "medium_category:OIL", &funky_layout_info);
+        test_filter(&db, "-medium_category:watercolor", &funky_layout_info);
+        test_filter(&db, "medium_category:print", &vec![]);
+    }
+
+    #[test]
+    fn test_undo_last_move_works() {
+        let mut db = create_db();
+        let record = |x: f64| LayoutRecord {
+            gallery_id: -1,
+            wall_id: "wall_a".to_string(),
+            art_object_id: FUNKY_PAINTING_ID,
+            x,
+            y: 1.0,
+            rotated: false,
+        };
+        assert_eq!(db.undo_last_move().unwrap(), None);
+
+        db.upsert_layout_record_with_history(&record(1.0)).unwrap();
+        db.upsert_layout_record_with_history(&record(2.0)).unwrap();
+        assert_eq!(
+            db.get_layout_record(FUNKY_PAINTING_ID).unwrap(),
+            Some(record(2.0))
+        );
+
+        assert_eq!(
+            db.undo_last_move().unwrap(),
+            Some(UndoneMove {
+                art_object_id: FUNKY_PAINTING_ID,
+                restored: Some(record(1.0)),
+            })
+        );
+        assert_eq!(
+            db.get_layout_record(FUNKY_PAINTING_ID).unwrap(),
+            Some(record(1.0))
+        );
+
+        // The art object wasn't in the layout before its first move.
+        assert_eq!(
+            db.undo_last_move().unwrap(),
+            Some(UndoneMove {
+                art_object_id: FUNKY_PAINTING_ID,
+                restored: None,
+            })
+        );
+        assert_eq!(db.get_layout_record(FUNKY_PAINTING_ID).unwrap(), None);
+        assert_eq!(db.undo_last_move().unwrap(), None);
+    }
+
+    #[test]
+    fn test_layout_history_is_pruned_and_cleared() {
+        let mut db = create_db();
+        let record = |x: f64| LayoutRecord {
+            gallery_id: -1,
+            wall_id: "wall_a",
+            art_object_id: FUNKY_PAINTING_ID,
+            x,
+            y: 1.0,
+            rotated: false,
+        };
+        for i in 0..MAX_LAYOUT_HISTORY_ENTRIES + 5 {
+            db.upsert_layout_record_with_history(&record(i as f64))
+                .unwrap();
+        }
+        let mut undone = 0;
+        while db.undo_last_move().unwrap().is_some() {
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_LAYOUT_HISTORY_ENTRIES);
+        assert_eq!(
+            db.get_layout_record(FUNKY_PAINTING_ID).unwrap().unwrap().x,
+            4.0
+        );
+
+        // Laying out galleries makes the history obsolete.
+        db.upsert_layout_record_with_history(&record(1.0)).unwrap();
+        db.set_layout_records_in_positive_galleries::<&str>(&vec![])
+            .unwrap();
+        assert_eq!(db.undo_last_move().unwrap(), None);
+    }
+
+    #[test]
+    fn test_layout_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+
+        // Add a painting to the layout.
+        db.set_layout_records_in_positive_galleries(&vec![LayoutRecord {
+            gallery_id: 1,
+            wall_id: "wall_02",
+            art_object_id: FUNKY_PAINTING_ID,
+            x: 1.2,
+            y: 3.4,
+            rotated: false,
+        }])
+        .unwrap();
+
+        // Make sure it got placed where we placed it.
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(1, "wall_02").unwrap(),
+            vec![(make_funky_painting(), (1.2, 3.4), false)]
+        );
+
+        // Make sure there's nothing in the place we want to move it to.
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(3, "wall_04").unwrap(),
+            vec![]
+        );
+        assert_eq!(db.get_distinct_wall_ids().unwrap(), vec!["wall_02"]);
+
+        // Move the painting.
+        db.upsert_layout_records(&vec![LayoutRecord {
+            gallery_id: 3,
+            wall_id: "wall_04",
+            art_object_id: FUNKY_PAINTING_ID,
+            x: 5.6,
+            y: 7.8,
+            rotated: false,
+        }])
+        .unwrap();
+
+        // Make sure there's nothing in the place we moved it from.
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(1, "wall_02").unwrap(),
+            vec![]
+        );
+
+        // Make sure it actually got moved to where we moved it.
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(3, "wall_04").unwrap(),
+            vec![(make_funky_painting(), (5.6, 7.8), false)]
+        );
+        assert_eq!(db.get_distinct_wall_ids().unwrap(), vec!["wall_04"]);
+    }
+
+    #[test]
+    fn test_layout_in_gallery_range_leaves_other_galleries_alone() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        let record = |gallery_id: i64, art_object_id: ArtObjectId| LayoutRecord {
+            gallery_id,
+            wall_id: "wall_01",
+            art_object_id,
+            x: 1.0,
+            y: 1.0,
+            rotated: false,
+        };
+        db.set_layout_records_in_positive_galleries(&vec![record(5, FUNKY_PAINTING_ID)])
+            .unwrap();
+        let options = ArtObjectQueryOptions {
+            exclude_previously_displayed: true,
+            ..Default::default()
+        };
+        let ids = |db: &GalleryDb| -> Vec<ArtObjectId> {
+            db.get_all_art_objects_for_layout(&options)
+                .unwrap()
+                .into_iter()
+                .map(|object| object.id)
+                .collect()
+        };
+        assert_eq!(ids(&db), vec![MONKEY_PAINTING_ID]);
+
+        db.set_layout_records_in_gallery_range(&vec![record(2, MONKEY_PAINTING_ID)], (1, 3))
+            .unwrap();
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(5, "wall_01")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(2, "wall_01")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            db.get_art_object_ids_outside_gallery_range((1, 3)).unwrap(),
+            HashSet::from([FUNKY_PAINTING_ID])
+        );
+
+        // Clearing the range doesn't make its art count as undisplayed.
+        db.set_layout_records_in_gallery_range::<&str>(&vec![], (1, 3))
+            .unwrap();
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(2, "wall_01").unwrap(),
+            vec![]
+        );
+        assert_eq!(ids(&db), vec![]);
+        assert_eq!(
+            db.get_previously_displayed_art_object_ids().unwrap(),
+            HashSet::from([FUNKY_PAINTING_ID, MONKEY_PAINTING_ID])
+        );
+
+        // Records outside the range are rejected.
+        assert!(db
+            .set_layout_records_in_gallery_range(&vec![record(4, MONKEY_PAINTING_ID)], (1, 3))
+            .is_err());
+        assert!(db
+            .set_layout_records_in_gallery_range::<&str>(&vec![], (0, 3))
+            .is_err());
+    }
+
+    fn make_wall(width: f64, height: f64) -> GalleryWall {
+        GalleryWall {
+            name: "wall_01".into(),
+            width,
+            height,
+            index: None,
+        }
+    }
+
+    #[test]
+    fn test_rescale_layout_to_walls_works() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![
+            ArtObjectRecord {
+                width: 1.0,
+                height: 0.5,
+                ..make_funky_painting()
+            },
+            ArtObjectRecord {
+                width: 1.0,
+                height: 0.5,
+                ..make_monkey_painting()
+            },
+        ])
+        .unwrap();
+        let old_wall = make_wall(4.0, 3.0);
+        let records = vec![
+            LayoutRecord {
+                gallery_id: -1,
+                wall_id: "wall_01",
+                art_object_id: FUNKY_PAINTING_ID,
+                x: 2.0,
+                y: 1.5,
+                rotated: false,
+            },
+            LayoutRecord {
+                gallery_id: -1,
+                wall_id: "wall_01",
+                art_object_id: MONKEY_PAINTING_ID,
+                x: 3.5,
+                y: 1.5,
+                rotated: false,
+            },
+        ];
+        db.upsert_layout_records(&records).unwrap();
+        db.set_layout_anchors(
+            &records
+                .iter()
+                .map(|r| {
+                    (
+                        r.art_object_id,
+                        LayoutAnchor::from_position(r.x, r.y, &old_wall),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        assert_eq!(
+            db.get_layout_anchor(FUNKY_PAINTING_ID).unwrap(),
+            Some(LayoutAnchor { x: 0.5, y: 0.5 })
+        );
+
+        let new_wall = make_wall(2.0, 2.0);
+        assert_eq!(db.rescale_layout_to_walls(&[new_wall.clone()]).unwrap(), 2);
+        let placements = db.get_art_objects_for_gallery_wall(-1, "wall_01").unwrap();
+        let position_of = |id: ArtObjectId| {
+            placements
+                .iter()
+                .find(|(object, _, _)| object.object_id == id)
+                .unwrap()
+                .1
+        };
+        // The funky painting should still be centered.
+        assert_eq!(position_of(FUNKY_PAINTING_ID), (1.0, 1.0));
+        // The monkey painting would hang off the right edge of the wall, so it
+        // should be clamped.
+        let (x, y) = position_of(MONKEY_PAINTING_ID);
+        assert_eq!((x, y), (1.5, 1.0));
+        assert!(x + 0.5 <= new_wall.width && y + 0.25 <= new_wall.height);
+
+        // Rescaling again shouldn't change anything.
+        assert_eq!(db.rescale_layout_to_walls(&[new_wall]).unwrap(), 0);
+
+        // Going back to the old walls should restore the original positions.
+        assert_eq!(db.rescale_layout_to_walls(&[old_wall]).unwrap(), 2);
+        let placements = db.get_art_objects_for_gallery_wall(-1, "wall_01").unwrap();
+        let positions: Vec<(f64, f64)> = placements.iter().map(|(_, pos, _)| *pos).collect();
+        assert!(positions.contains(&(2.0, 1.5)) && positions.contains(&(3.5, 1.5)));
+    }
+
+    #[test]
+    fn test_rotated_layout_records_work_with_old_layout_tables() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+        // This is what the layout table looked like before art objects could be rotated.
+        db.conn
+            .execute_batch(
+                "
+                CREATE TABLE layout (
+                    gallery_id INTEGER NOT NULL,
+                    wall_id TEXT NOT NULL,
+                    art_object_id INTEGER NOT NULL UNIQUE,
+                    x REAL NOT NULL,
+                    y REAL NOT NULL,
+                    anchor_x REAL,
+                    anchor_y REAL
+                );
+                ",
+            )
+            .unwrap();
+        let record = LayoutRecord {
+            gallery_id: 1,
+            wall_id: "wall_02".to_string(),
+            art_object_id: FUNKY_PAINTING_ID,
+            x: 1.2,
+            y: 3.4,
+            rotated: false,
+        };
+        db.conn
+            .execute(
+                "INSERT INTO layout (gallery_id, wall_id, art_object_id, x, y) VALUES (1, 'wall_02', ?1, 1.2, 3.4)",
+                [FUNKY_PAINTING_ID.to_raw_i64()],
+            )
+            .unwrap();
+        assert_eq!(db.get_all_layout_records().unwrap(), vec![record.clone()]);
+
+        let rotated_record = LayoutRecord {
+            rotated: true,
+            ..record
+        };
+        db.upsert_layout_records(&vec![rotated_record.clone()])
+            .unwrap();
+        assert_eq!(db.get_all_layout_records().unwrap(), vec![rotated_record]);
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(1, "wall_02").unwrap(),
+            vec![(make_funky_painting(), (1.2, 3.4), true)]
+        );
     }
 
-    fn create_db() -> GalleryDb {
-        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
-        db.reset_art_objects_table().unwrap();
-        db.reset_layout_table().unwrap();
-        db
+    #[test]
+    fn test_art_objects_for_gallery_wall_are_looked_up_via_index() {
+        let db = create_db();
+        let plan: Vec<String> = db
+            .conn
+            .prepare(
+                "EXPLAIN QUERY PLAN SELECT art_object_id FROM layout WHERE gallery_id = 1 AND wall_id = 'wall_01'",
+            )
+            .unwrap()
+            .query_map((), |row| row.get(3))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert!(
+            plan.iter().any(|step| step.contains("layout_gallery_wall")),
+            "query plan is {plan:?}"
+        );
     }
 
     #[test]
-    fn test_get_art_object_works() {
+    fn test_moving_art_objects_clears_their_anchors() {
         let mut db = create_db();
         db.add_art_objects(&vec![make_funky_painting()]).unwrap();
-
-        // Make sure we can retrieve it.
+        let record = LayoutRecord {
+            gallery_id: 0,
+            wall_id: "wall_01",
+            art_object_id: FUNKY_PAINTING_ID,
+            x: 1.0,
+            y: 1.0,
+            rotated: false,
+        };
+        db.upsert_layout_records(&vec![record.clone()]).unwrap();
+        db.set_layout_anchors(&[(FUNKY_PAINTING_ID, LayoutAnchor { x: 0.5, y: 0.5 })])
+            .unwrap();
+        db.upsert_layout_records(&vec![LayoutRecord { x: 2.0, ..record }])
+            .unwrap();
+        assert_eq!(db.get_layout_anchor(FUNKY_PAINTING_ID).unwrap(), None);
         assert_eq!(
-            db.get_art_object(FUNKY_PAINTING_ID).unwrap(),
-            Some(make_funky_painting())
+            db.rescale_layout_to_walls(&[make_wall(10.0, 10.0)])
+                .unwrap(),
+            0
         );
-        assert_eq!(db.get_art_object(ArtObjectId::Met(12345)).unwrap(), None);
     }
 
     #[test]
-    fn test_filtering_works() {
+    fn test_layout_records_are_timestamped() {
         let mut db = create_db();
         db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+        let record = LayoutRecord {
+            gallery_id: 0,
+            wall_id: "wall_01".to_string(),
+            art_object_id: FUNKY_PAINTING_ID,
+            x: 1.0,
+            y: 1.0,
+            rotated: false,
+        };
+        db.upsert_layout_records(&vec![record.clone()]).unwrap();
+        let timestamped = db
+            .get_timestamped_layout_records_in_non_positive_galleries()
+            .unwrap();
+        assert_eq!(timestamped.len(), 1);
+        assert_eq!(timestamped[0].record, record);
+        assert!(timestamped[0].modified_at > 0);
 
-        let funky_layout_info = vec![make_funky_painting().into()];
-        let empty_layout_info = vec![];
+        let restored = TimestampedLayoutRecord {
+            record,
+            modified_at: 5,
+        };
+        db.upsert_timestamped_layout_records(&[restored.clone()])
+            .unwrap();
+        assert_eq!(
+            db.get_timestamped_layout_records_in_non_positive_galleries()
+                .unwrap(),
+            vec![restored]
+        );
+    }
 
-        // Search for the art object.
-        test_filter(&db, "boop", &funky_layout_info);
+    #[test]
+    fn test_merge_layout_records_keeps_newest() {
+        let mut db = create_db();
+        db.add_art_objects(&vec![make_funky_painting(), make_monkey_painting()])
+            .unwrap();
+        let funky = LayoutRecord {
+            gallery_id: 0,
+            wall_id: "wall_01".to_string(),
+            art_object_id: FUNKY_PAINTING_ID,
+            x: 1.0,
+            y: 1.0,
+            rotated: false,
+        };
+        let monkey = LayoutRecord {
+            art_object_id: MONKEY_PAINTING_ID,
+            x: 3.0,
+            ..funky.clone()
+        };
+        let timestamped =
+            |record: &LayoutRecord<String>, x: f64, modified_at: i64| TimestampedLayoutRecord {
+                record: LayoutRecord {
+                    x,
+                    ..record.clone()
+                },
+                modified_at,
+            };
+        db.upsert_timestamped_layout_records(&[
+            timestamped(&funky, 1.0, 10),
+            timestamped(&monkey, 3.0, 20),
+        ])
+        .unwrap();
 
-        // Excluding a term in the art object shouldn't find anything.
-        test_filter(&db, "-boop", &empty_layout_info);
+        let merged = db
+            .merge_layout_records(&[
+                // Newer than ours, so it should win.
+                timestamped(&funky, 5.0, 15),
+                // Older than ours, so it should lose.
+                timestamped(&monkey, 6.0, 15),
+            ])
+            .unwrap();
+        assert_eq!(merged, 1);
+        assert_eq!(
+            db.get_timestamped_layout_records_in_non_positive_galleries()
+                .unwrap(),
+            vec![timestamped(&monkey, 3.0, 20), timestamped(&funky, 5.0, 15)]
+        );
 
-        // Excluding a term *not* in the art object should find it.
-        test_filter(&db, "-blargblarg", &funky_layout_info);
+        // Ties go to ours, and records we don't have are added.
+        db.clear_layout_records_in_non_positive_galleries().unwrap();
+        db.upsert_timestamped_layout_records(&[timestamped(&funky, 1.0, 10)])
+            .unwrap();
+        let merged = db
+            .merge_layout_records(&[timestamped(&funky, 2.0, 10), timestamped(&monkey, 4.0, 0)])
+            .unwrap();
+        assert_eq!(merged, 1);
+        assert_eq!(
+            db.get_timestamped_layout_records_in_non_positive_galleries()
+                .unwrap(),
+            vec![timestamped(&funky, 1.0, 10), timestamped(&monkey, 4.0, 0)]
+        );
+    }
 
-        // Ensure unquoted terms are ANDed together...
-        test_filter(&db, "boop jones", &funky_layout_info);
-        test_filter(&db, "jones boop", &funky_layout_info);
+    #[test]
+    fn test_layout_metadata_works() {
+        let mut db = create_db();
+        assert_eq!(db.get_layout_metadata("walls_hash").unwrap(), None);
+        db.set_layout_metadata("walls_hash", "boop").unwrap();
+        db.set_layout_metadata("walls_hash", "blap").unwrap();
+        assert_eq!(
+            db.get_layout_metadata("walls_hash").unwrap(),
+            Some("blap".to_string())
+        );
+        db.reset_layout_table().unwrap();
+        assert_eq!(db.get_layout_metadata("walls_hash").unwrap(), None);
 
-        // Ensure quoted terms are exact substring matches...
-        test_filter(&db, "\"boop jones\"", &funky_layout_info);
-        test_filter(&db, "\"jones boop\"", &empty_layout_info);
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        assert_eq!(db.get_layout_metadata("walls_hash").unwrap(), None);
+        db.set_layout_metadata("walls_hash", "boop").unwrap();
+        assert_eq!(
+            db.get_layout_metadata("walls_hash").unwrap(),
+            Some("boop".to_string())
+        );
     }
 
     #[test]
-    fn test_layout_works() {
+    fn test_gallery_zones_work_at_boundaries() {
         let mut db = create_db();
-        db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+        let records: Vec<LayoutRecord<&str>> = [1, 0, -1, -2]
+            .into_iter()
+            .map(|gallery_id| LayoutRecord {
+                gallery_id,
+                wall_id: "wall_a",
+                art_object_id: ArtObjectId::Met(10 + gallery_id),
+                x: 1.0,
+                y: 1.0,
+                rotated: false,
+            })
+            .collect();
+        db.upsert_layout_records(&records).unwrap();
 
-        // Add a painting to the layout.
-        db.set_layout_records_in_positive_galleries(&vec![LayoutRecord {
-            gallery_id: 1,
-            wall_id: "wall_02",
-            art_object_id: FUNKY_PAINTING_ID,
-            x: 1.2,
-            y: 3.4,
-        }])
-        .unwrap();
+        let zone_ids = |zone| -> Vec<ArtObjectId> {
+            db.get_layout_records_in_zone(zone)
+                .unwrap()
+                .into_iter()
+                .map(|record| record.art_object_id)
+                .collect()
+        };
+        assert_eq!(zone_ids(GalleryZone::Generated), vec![ArtObjectId::Met(11)]);
+        assert_eq!(zone_ids(GalleryZone::Lobby), vec![ArtObjectId::Met(10)]);
+        assert_eq!(zone_ids(GalleryZone::Stash), vec![ArtObjectId::Met(9)]);
+        assert_eq!(zone_ids(GalleryZone::Custom(-2)), vec![ArtObjectId::Met(8)]);
 
-        // Make sure it got placed where we placed it.
         assert_eq!(
-            db.get_art_objects_for_gallery_wall(1, "wall_02").unwrap(),
-            vec![(make_funky_painting(), (1.2, 3.4))]
+            db.get_gallery_zone(ArtObjectId::Met(11)).unwrap(),
+            Some(GalleryZone::Generated)
         );
-
-        // Make sure there's nothing in the place we want to move it to.
         assert_eq!(
-            db.get_art_objects_for_gallery_wall(3, "wall_04").unwrap(),
-            vec![]
+            db.get_gallery_zone(ArtObjectId::Met(10)).unwrap(),
+            Some(GalleryZone::Lobby)
         );
-
-        // Move the painting.
-        db.upsert_layout_records(&vec![LayoutRecord {
-            gallery_id: 3,
-            wall_id: "wall_04",
-            art_object_id: FUNKY_PAINTING_ID,
-            x: 5.6,
-            y: 7.8,
-        }])
-        .unwrap();
-
-        // Make sure there's nothing in the place we moved it from.
         assert_eq!(
-            db.get_art_objects_for_gallery_wall(1, "wall_02").unwrap(),
-            vec![]
+            db.get_gallery_zone(ArtObjectId::Met(9)).unwrap(),
+            Some(GalleryZone::Stash)
         );
+        assert_eq!(db.get_gallery_zone(ArtObjectId::Met(1234)).unwrap(), None);
 
-        // Make sure it actually got moved to where we moved it.
+        // The lobby is one of the player's galleries, just like the stash.
         assert_eq!(
-            db.get_art_objects_for_gallery_wall(3, "wall_04").unwrap(),
-            vec![(make_funky_painting(), (5.6, 7.8))]
+            db.get_layout_records_in_non_positive_galleries()
+                .unwrap()
+                .len(),
+            3
         );
     }
 
@@ -638,6 +5110,7 @@ mod tests {
             art_object_id: FUNKY_PAINTING_ID,
             x: 1.2,
             y: 3.4,
+            rotated: false,
         }])
         .unwrap();
         db.set_layout_records_in_positive_galleries(&vec![LayoutRecord {
@@ -646,6 +5119,7 @@ mod tests {
             art_object_id: MONKEY_PAINTING_ID,
             x: 1.2,
             y: 3.4,
+            rotated: false,
         }])
         .unwrap();
 
@@ -655,6 +5129,10 @@ mod tests {
                 .len(),
             0
         );
+        assert_eq!(
+            db.get_art_object_ids_in_positive_galleries().unwrap(),
+            [MONKEY_PAINTING_ID].into_iter().collect()
+        );
 
         let funky_layout_record = LayoutRecord {
             gallery_id: 0,
@@ -662,6 +5140,7 @@ mod tests {
             art_object_id: FUNKY_PAINTING_ID,
             x: 1.2,
             y: 3.4,
+            rotated: false,
         };
 
         // Move a painting to gallery 0.
@@ -693,6 +5172,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_gallery_records_work() {
+        let mut db = create_db();
+        assert_eq!(db.get_gallery_record(1).unwrap(), None);
+        let record = GalleryRecord {
+            gallery_id: 1,
+            wall_set: "moma-gallery".into(),
+            reserved_walls: vec!["wall_04".into()],
+            segment: Some("portraits".into()),
+            source_gallery: Some("825".into()),
+        };
+        db.set_gallery_records_in_positive_galleries(&vec![record.clone()])
+            .unwrap();
+        assert_eq!(db.get_gallery_record(1).unwrap(), Some(record));
+        db.set_gallery_records_in_positive_galleries(&vec![])
+            .unwrap();
+        assert_eq!(db.get_gallery_record(1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_gallery_records_work_with_old_galleries_table() {
+        let mut db = create_db();
+        db.conn.execute("DROP TABLE galleries", ()).unwrap();
+        db.conn
+            .execute(
+                "CREATE TABLE galleries (id INTEGER PRIMARY KEY, wall_set TEXT NOT NULL)",
+                (),
+            )
+            .unwrap();
+        db.conn
+            .execute(
+                "INSERT INTO galleries (id, wall_set) VALUES (1, 'boop')",
+                (),
+            )
+            .unwrap();
+        let old_record = db.get_gallery_record(1).unwrap().unwrap();
+        assert_eq!(old_record.reserved_walls, Vec::<String>::new());
+        assert_eq!(old_record.segment, None);
+        let record = GalleryRecord {
+            gallery_id: 2,
+            wall_set: "boop".into(),
+            reserved_walls: vec!["wall_a".into()],
+            segment: None,
+            source_gallery: None,
+        };
+        db.set_gallery_records_in_positive_galleries(&vec![record.clone()])
+            .unwrap();
+        assert_eq!(db.get_gallery_record(2).unwrap(), Some(record));
+    }
+
+    #[test]
+    fn test_gallery_records_work_without_galleries_table() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        assert_eq!(db.get_gallery_record(1).unwrap(), None);
+    }
+
     #[test]
     fn test_clear_layout_records_in_non_positive_galleries_works() {
         let mut db = create_db();
@@ -706,6 +5242,7 @@ mod tests {
             art_object_id: FUNKY_PAINTING_ID,
             x: 1.2,
             y: 3.4,
+            rotated: false,
         }])
         .unwrap();
 
@@ -716,6 +5253,7 @@ mod tests {
             art_object_id: MONKEY_PAINTING_ID,
             x: 1.2,
             y: 3.4,
+            rotated: false,
         }])
         .unwrap();
 
@@ -737,4 +5275,386 @@ mod tests {
             0
         );
     }
+
+    /// Counts what's written to it instead of keeping it.
+    #[derive(Default)]
+    struct CountingWriter {
+        bytes: usize,
+        largest_write: usize,
+    }
+
+    impl std::io::Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.bytes += buf.len();
+            self.largest_write = self.largest_write.max(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_export_layout_records_to_writer_works() {
+        let mut db = create_db();
+        let mut buf = vec![];
+        assert_eq!(
+            db.export_layout_records_to_writer(&mut buf, LayoutScope::All)
+                .unwrap(),
+            0
+        );
+        let empty: Vec<LayoutRecord<String>> = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(empty, vec![]);
+
+        let records: Vec<LayoutRecord<String>> = (0..5000)
+            .map(|i| LayoutRecord {
+                gallery_id: i % 10 - 4,
+                wall_id: format!("wall_{:02}", i % 7),
+                art_object_id: ArtObjectId::Met(i),
+                x: i as f64,
+                y: 1.5,
+                rotated: i % 3 == 0,
+            })
+            .collect();
+        db.upsert_layout_records(&records).unwrap();
+
+        let mut buf = vec![];
+        let count = db
+            .export_layout_records_to_writer(&mut buf, LayoutScope::NonPositive)
+            .unwrap();
+        assert_eq!(count, 2500);
+        let exported: Vec<LayoutRecord<String>> = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(
+            exported,
+            db.get_layout_records_in_non_positive_galleries().unwrap()
+        );
+
+        let mut buf = vec![];
+        let count = db
+            .export_layout_records_to_writer(&mut buf, LayoutScope::All)
+            .unwrap();
+        assert_eq!(count, records.len());
+        let exported: Vec<LayoutRecord<String>> = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(exported, db.get_all_layout_records().unwrap());
+
+        // Importing the export elsewhere should give us the same layout.
+        let mut other_db = create_db();
+        other_db.upsert_layout_records(&exported).unwrap();
+        assert_eq!(
+            other_db.get_all_layout_records().unwrap(),
+            db.get_all_layout_records().unwrap()
+        );
+
+        // The export should trickle out rather than arrive all at once.
+        let mut counter = CountingWriter::default();
+        db.export_layout_records_to_writer(&mut counter, LayoutScope::All)
+            .unwrap();
+        assert_eq!(counter.bytes, buf.len());
+        assert!(counter.largest_write < buf.len() / 10);
+    }
+
+    #[test]
+    fn test_read_only_db_rejects_writes() {
+        let path = std::env::temp_dir().join(format!(
+            "gallery-db-test-read-only-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        {
+            let mut db = GalleryDb::open(&path, false).unwrap();
+            assert!(!db.is_read_only());
+            db.reset_art_objects_table().unwrap();
+            db.reset_layout_table().unwrap();
+            db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+        }
+        let mut db = GalleryDb::open(&path, true).unwrap();
+        assert!(db.is_read_only());
+        assert_eq!(
+            db.get_art_object(FUNKY_PAINTING_ID).unwrap(),
+            Some(make_funky_painting())
+        );
+        assert!(db.reset_layout_table().is_err());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_is_busy_error_works() {
+        let path = std::env::temp_dir().join(format!(
+            "gallery-db-test-busy-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut db = GalleryDb::open(&path, false).unwrap();
+        db.reset_layout_table().unwrap();
+        let locker = Connection::open(&path).unwrap();
+        locker.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        // Don't make the test wait out `BUSY_TIMEOUT`.
+        db.conn.busy_timeout(Duration::ZERO).unwrap();
+        let err = db.reset_layout_table().unwrap_err();
+        assert!(is_busy_error(&err), "{err:?}");
+        assert!(!is_busy_error(&anyhow!("Oof")));
+        drop(locker);
+        db.reset_layout_table().unwrap();
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn create_temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("gallery-db-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn funky_painting_at(gallery_id: i64, x: f64) -> LayoutRecord<&'static str> {
+        LayoutRecord {
+            gallery_id,
+            wall_id: "wall_02",
+            art_object_id: FUNKY_PAINTING_ID,
+            x,
+            y: 3.4,
+            rotated: false,
+        }
+    }
+
+    #[test]
+    fn test_slots_have_independent_layouts() {
+        let dir = create_temp_dir("slots");
+        let db_path = dir.join(get_default_gallery_db_filename());
+        {
+            let mut db = GalleryDb::open(&db_path, false).unwrap();
+            db.reset_art_objects_table().unwrap();
+            db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+        }
+        let mut slot_a = GalleryDb::open(&db_path, false).unwrap();
+        slot_a
+            .attach_layout_db(dir.join(get_layout_db_filename("a")))
+            .unwrap();
+        let mut slot_b = GalleryDb::open(&db_path, false).unwrap();
+        slot_b
+            .attach_layout_db(dir.join(get_layout_db_filename("b")))
+            .unwrap();
+        assert!(slot_b
+            .attach_layout_db(dir.join(get_layout_db_filename("c")))
+            .is_err());
+
+        slot_a
+            .upsert_layout_records(&vec![funky_painting_at(-1, 1.0)])
+            .unwrap();
+        slot_b
+            .upsert_layout_records(&vec![funky_painting_at(-1, 2.0)])
+            .unwrap();
+        slot_a.set_layout_metadata("boop", "a").unwrap();
+
+        assert_eq!(
+            slot_a
+                .get_art_objects_for_gallery_wall(-1, "wall_02")
+                .unwrap(),
+            vec![(make_funky_painting(), (1.0, 3.4), false)]
+        );
+        assert_eq!(
+            slot_b
+                .get_art_objects_for_gallery_wall(-1, "wall_02")
+                .unwrap(),
+            vec![(make_funky_painting(), (2.0, 3.4), false)]
+        );
+        assert_eq!(
+            slot_a.get_layout_metadata("boop").unwrap(),
+            Some("a".into())
+        );
+        assert_eq!(slot_b.get_layout_metadata("boop").unwrap(), None);
+
+        // Nothing should have been written to the layout in the main DB.
+        drop(slot_a);
+        drop(slot_b);
+        let db = GalleryDb::open(&db_path, true).unwrap();
+        assert!(!db.has_table("layout").unwrap());
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_main_layout_into_layout_db_works() {
+        let dir = create_temp_dir("copy-main-layout");
+        let db_path = dir.join(get_default_gallery_db_filename());
+        {
+            let mut db = GalleryDb::open(&db_path, false).unwrap();
+            db.reset_art_objects_table().unwrap();
+            db.reset_layout_table().unwrap();
+            db.add_art_objects(&vec![make_funky_painting()]).unwrap();
+            db.upsert_layout_records(&vec![funky_painting_at(1, 1.0)])
+                .unwrap();
+            db.set_gallery_records_in_positive_galleries(&vec![GalleryRecord {
+                gallery_id: 1,
+                wall_set: "default".into(),
+                reserved_walls: vec![],
+                segment: None,
+                source_gallery: None,
+            }])
+            .unwrap();
+            db.set_layout_metadata("boop", "main").unwrap();
+        }
+        let layout_db_path = dir.join(get_layout_db_filename("a"));
+        let mut db = GalleryDb::open(&db_path, false).unwrap();
+        db.attach_layout_db(&layout_db_path).unwrap();
+        assert_eq!(db.copy_main_layout_into_layout_db().unwrap(), 1);
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(1, "wall_02").unwrap(),
+            vec![(make_funky_painting(), (1.0, 3.4), false)]
+        );
+        assert!(db.get_gallery_record(1).unwrap().is_some());
+        assert_eq!(db.get_layout_metadata("boop").unwrap(), Some("main".into()));
+
+        // The slot's layout shouldn't be clobbered by the main DB's layout later.
+        db.upsert_layout_records(&vec![funky_painting_at(1, 2.0)])
+            .unwrap();
+        drop(db);
+        let mut db = GalleryDb::open(&db_path, false).unwrap();
+        db.attach_layout_db(&layout_db_path).unwrap();
+        assert_eq!(db.copy_main_layout_into_layout_db().unwrap(), 0);
+        assert_eq!(
+            db.get_art_objects_for_gallery_wall(1, "wall_02").unwrap(),
+            vec![(make_funky_painting(), (2.0, 3.4), false)]
+        );
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_slot_names_are_validated() {
+        assert!(is_valid_slot_name("user"));
+        assert!(is_valid_slot_name("slot_2-b"));
+        assert!(!is_valid_slot_name(""));
+        assert!(!is_valid_slot_name("../user"));
+        assert!(!is_valid_slot_name("slot 2"));
+    }
+
+    fn create_db_with_galleries(gallery_ids: &[i64]) -> GalleryDb {
+        let mut db = create_db();
+        let records: Vec<LayoutRecord<&str>> = gallery_ids
+            .iter()
+            .enumerate()
+            .map(|(i, &gallery_id)| LayoutRecord {
+                gallery_id,
+                wall_id: "wall_a",
+                art_object_id: ArtObjectId::Met(i as i64),
+                x: i as f64,
+                y: 0.0,
+                rotated: false,
+            })
+            .collect();
+        db.upsert_layout_records(&records).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_gallery_graph_works_with_empty_db() {
+        let db = create_db();
+        assert_eq!(db.get_populated_positive_galleries().unwrap(), vec![]);
+        assert_eq!(db.max_gallery_id().unwrap(), None);
+        assert_eq!(db.count_positive_galleries().unwrap(), 0);
+        assert_eq!(
+            db.get_neighboring_galleries(1, 5).unwrap(),
+            Vec::<i64>::new()
+        );
+    }
+
+    #[test]
+    fn test_gallery_graph_excludes_non_positive_galleries() {
+        let db = create_db_with_galleries(&[-2, 0, 0]);
+        assert_eq!(db.get_populated_positive_galleries().unwrap(), vec![]);
+        assert_eq!(db.max_gallery_id().unwrap(), None);
+        assert_eq!(db.count_positive_galleries().unwrap(), 0);
+        assert_eq!(
+            db.get_neighboring_galleries(1, 5).unwrap(),
+            Vec::<i64>::new()
+        );
+    }
+
+    #[test]
+    fn test_gallery_graph_works_with_gaps() {
+        let db = create_db_with_galleries(&[-1, 1, 1, 2, 5, 5, 5, 9]);
+        assert_eq!(
+            db.get_populated_positive_galleries().unwrap(),
+            vec![
+                GalleryObjectCount {
+                    gallery_id: 1,
+                    object_count: 2
+                },
+                GalleryObjectCount {
+                    gallery_id: 2,
+                    object_count: 1
+                },
+                GalleryObjectCount {
+                    gallery_id: 5,
+                    object_count: 3
+                },
+                GalleryObjectCount {
+                    gallery_id: 9,
+                    object_count: 1
+                },
+            ]
+        );
+        assert_eq!(db.max_gallery_id().unwrap(), Some(9));
+        assert_eq!(db.count_positive_galleries().unwrap(), 4);
+        assert_eq!(db.get_neighboring_galleries(4, 3).unwrap(), vec![5, 2, 1]);
+        assert_eq!(db.get_neighboring_galleries(1, 1).unwrap(), vec![1, 2]);
+        assert_eq!(
+            db.get_neighboring_galleries(7, 0).unwrap(),
+            Vec::<i64>::new()
+        );
+    }
+
+    #[test]
+    fn test_maintenance_works() {
+        let path = std::env::temp_dir().join(format!(
+            "gallery-db-test-maintenance-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut db = GalleryDb::open(&path, false).unwrap();
+        db.reset_layout_table().unwrap();
+        for _ in 0..3 {
+            db.reset_art_objects_table().unwrap();
+            let records: Vec<ArtObjectRecord> = (0..500)
+                .map(|i| ArtObjectRecord {
+                    object_id: ArtObjectId::Met(i),
+                    ..make_funky_painting()
+                })
+                .collect();
+            db.add_art_objects(&records).unwrap();
+        }
+        db.reset_art_objects_table().unwrap();
+
+        let report = db.maintenance(true).unwrap();
+        assert!(report.vacuumed);
+        let db_size_before = report.db_size_before.unwrap();
+        let db_size_after = report.db_size_after.unwrap();
+        assert!(db_size_after > 0);
+        assert!(db_size_after <= db_size_before);
+        assert_eq!(report.wal_size_after, Some(0));
+        assert!(db.passes_integrity_check().unwrap());
+        db.checkpoint_passive().unwrap();
+
+        let report = db.maintenance(false).unwrap();
+        assert!(!report.vacuumed);
+        assert!(db.passes_integrity_check().unwrap());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_maintenance_works_in_memory() {
+        let mut db = create_db();
+        assert_eq!(
+            db.maintenance(false).unwrap(),
+            MaintenanceReport {
+                vacuumed: false,
+                ..Default::default()
+            }
+        );
+    }
 }
@@ -1,41 +1,761 @@
-use anyhow::Result;
-use rusqlite::Connection;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use log::info;
+use rusqlite::{Connection, Transaction};
 
 use crate::{
     gallery_cache::GalleryCache,
     gallery_db::{
-        get_default_gallery_db_filename, get_gallery_db_filename, GalleryDb,
-        LATEST_GALLERY_DB_VERSION,
+        get_default_gallery_db_filename, get_gallery_db_filename, get_gallery_db_schema_version,
+        get_layout_db_filename, set_gallery_db_schema_version, GalleryDb,
+        GALLERY_DB_FILENAME_VERSION, LATEST_GALLERY_DB_VERSION,
     },
+    medium::classify_medium,
+    object_date::parse_year,
 };
 
 const OLDEST_SUPPORTED_GALLERY_DB_VERSION_TO_TRIVIALLY_MIGRATE: usize = 5;
 
-pub fn migrate_gallery_db(cache: &GalleryCache) -> Result<bool> {
+/// DBs at least this old can have their schema migrated in place, see
+/// `MIGRATIONS`. Older ones only have their layout copied out of them.
+pub const OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION: usize = 6;
+
+/// Migrates the schema of a gallery DB, within a transaction, from one version
+/// to the next.
+///
+/// Note that these deliberately use raw SQL rather than anything in
+/// `GalleryDb`, since they need to keep working with the schema as it was at
+/// the time, no matter how `GalleryDb` changes afterwards.
+type Migration = fn(&Transaction) -> Result<()>;
+
+/// The migration at index `i` migrates from version
+/// `OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION + i` to the version after it.
+const MIGRATIONS: [Migration;
+    LATEST_GALLERY_DB_VERSION - OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION] = [
+    migrate_v6_to_v7,
+    migrate_v7_to_v8,
+    migrate_v8_to_v9,
+    migrate_v9_to_v10,
+    migrate_v10_to_v11,
+    migrate_v11_to_v12,
+    migrate_v12_to_v13,
+    migrate_v13_to_v14,
+    migrate_v14_to_v15,
+];
+
+fn add_column(tx: &Transaction, table: &str, column: &str, definition: &str) -> Result<()> {
+    tx.execute(
+        &format!("ALTER TABLE main.{table} ADD COLUMN {column} {definition}"),
+        (),
+    )?;
+    Ok(())
+}
+
+fn has_main_table(tx: &Transaction, name: &str) -> Result<bool> {
+    let count: i64 = tx.query_row(
+        "SELECT COUNT(*) FROM main.sqlite_master WHERE type = 'table' AND name = ?1",
+        [name],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Adds the artists table, and the creator QIDs of art objects that refer to it.
+fn migrate_v6_to_v7(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "
+        CREATE TABLE main.artists (
+            qid INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT NOT NULL
+        )
+        ",
+        (),
+    )?;
+    add_column(tx, "art_objects", "artist_qid", "INTEGER")
+}
+
+/// Classifies the medium of every art object.
+fn migrate_v7_to_v8(tx: &Transaction) -> Result<()> {
+    add_column(
+        tx,
+        "art_objects",
+        "medium_category",
+        "TEXT NOT NULL DEFAULT ''",
+    )?;
+    let mediums = {
+        let mut statement = tx.prepare("SELECT DISTINCT medium FROM main.art_objects")?;
+        let rows = statement.query_map((), |row| row.get::<_, String>(0))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    let mut statement =
+        tx.prepare("UPDATE main.art_objects SET medium_category = ?1 WHERE medium = ?2")?;
+    for medium in mediums {
+        statement.execute((classify_medium(&medium).as_str(), &medium))?;
+    }
+    Ok(())
+}
+
+/// Adds layout anchors and layout metadata. Databases made by the CLI before
+/// save slots existed have the layout in them, while others might not.
+fn migrate_v8_to_v9(tx: &Transaction) -> Result<()> {
+    if !has_main_table(tx, "layout")? {
+        return Ok(());
+    }
+    add_column(tx, "layout", "anchor_x", "REAL")?;
+    add_column(tx, "layout", "anchor_y", "REAL")?;
+    tx.execute(
+        "
+        CREATE TABLE IF NOT EXISTS main.layout_metadata (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )
+        ",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Adds whether each art object is a highlight of its collection.
+fn migrate_v9_to_v10(tx: &Transaction) -> Result<()> {
+    add_column(tx, "art_objects", "highlight", "INTEGER NOT NULL DEFAULT 0")
+}
+
+/// Adds the image URLs that the Met API was enriched with.
+fn migrate_v10_to_v11(tx: &Transaction) -> Result<()> {
+    add_column(tx, "art_objects", "primary_image_url", "TEXT")?;
+    add_column(tx, "art_objects", "primary_image_small_url", "TEXT")
+}
+
+/// Parses the year out of every art object's date.
+fn migrate_v11_to_v12(tx: &Transaction) -> Result<()> {
+    add_column(tx, "art_objects", "date_year", "INTEGER")?;
+    let dates = {
+        let mut statement = tx.prepare("SELECT DISTINCT date FROM main.art_objects")?;
+        let rows = statement.query_map((), |row| row.get::<_, String>(0))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    let mut statement = tx.prepare("UPDATE main.art_objects SET date_year = ?1 WHERE date = ?2")?;
+    for date in dates {
+        statement.execute((parse_year(&date), &date))?;
+    }
+    Ok(())
+}
+
+/// Adds the roles that used to be mixed into artist names, e.g. "Workshop of".
+fn migrate_v12_to_v13(tx: &Transaction) -> Result<()> {
+    add_column(tx, "art_objects", "artist_role", "TEXT NOT NULL DEFAULT ''")
+}
+
+/// Adds the collection's subject tags and classification. Existing art
+/// objects don't get any until they're re-imported.
+fn migrate_v13_to_v14(tx: &Transaction) -> Result<()> {
+    add_column(tx, "art_objects", "tags", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(
+        tx,
+        "art_objects",
+        "classification",
+        "TEXT NOT NULL DEFAULT ''",
+    )
+}
+
+/// Adds the gallery that each art object hangs in at its museum. Like tags,
+/// existing art objects don't get one until they're re-imported.
+fn migrate_v14_to_v15(tx: &Transaction) -> Result<()> {
+    add_column(tx, "art_objects", "source_gallery", "TEXT")
+}
+
+/// Runs the migrations from `from_version` up to `to_version`.
+fn apply_migrations(tx: &Transaction, from_version: usize, to_version: usize) -> Result<()> {
+    for version in from_version..to_version {
+        info!(
+            "Migrating gallery DB schema from version {version} to {}.",
+            version + 1
+        );
+        MIGRATIONS[version - OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION](tx)?;
+    }
+    Ok(())
+}
+
+/// Returns the schema version that a gallery DB at the given path has if it
+/// doesn't record its version, which is the version in its filename, e.g. 9
+/// for `gallery9.sqlite`.
+pub fn get_unversioned_gallery_db_version<P: AsRef<Path>>(path: P) -> usize {
+    path.as_ref()
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("gallery"))
+        .and_then(|name| name.strip_suffix(".sqlite"))
+        .and_then(|version| version.parse().ok())
+        .unwrap_or(GALLERY_DB_FILENAME_VERSION)
+}
+
+/// Migrates the schema of the given gallery DB in place, in a single
+/// transaction, to `LATEST_GALLERY_DB_VERSION`. If the DB doesn't record its
+/// schema version, it's assumed to be `unversioned_version`.
+///
+/// Returns the version that was migrated from, or `None` if the DB didn't need
+/// migrating, e.g. because it's up to date or doesn't have any art objects yet.
+pub fn migrate_gallery_db_schema(
+    conn: &mut Connection,
+    unversioned_version: usize,
+) -> Result<Option<usize>> {
+    let tx = conn.transaction()?;
+    if !has_main_table(&tx, "art_objects")? {
+        return Ok(None);
+    }
+    let recorded_version = get_gallery_db_schema_version(&tx)?;
+    let version = recorded_version.unwrap_or(unversioned_version);
+    if version > LATEST_GALLERY_DB_VERSION {
+        return Err(anyhow!(
+            "Gallery DB schema version {version} is newer than the latest supported version {LATEST_GALLERY_DB_VERSION}"
+        ));
+    }
+    if version < OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION {
+        return Err(anyhow!(
+            "Gallery DB schema version {version} is too old to migrate in place"
+        ));
+    }
+    if version == LATEST_GALLERY_DB_VERSION && recorded_version.is_some() {
+        return Ok(None);
+    }
+    apply_migrations(&tx, version, LATEST_GALLERY_DB_VERSION)?;
+    set_gallery_db_schema_version(&tx, LATEST_GALLERY_DB_VERSION)?;
+    tx.commit()?;
+    Ok((version < LATEST_GALLERY_DB_VERSION).then_some(version))
+}
+
+/// If there's no DB at the default filename yet, copies the newest older DB
+/// whose schema can be migrated in place there, leaving the original alone,
+/// and migrates it to the latest schema.
+///
+/// Returns the version of the DB that was copied, if any.
+pub fn adopt_older_gallery_db(cache: &GalleryCache) -> Result<Option<usize>> {
+    let to_db_path = cache.get_cached_path(get_default_gallery_db_filename());
+    if to_db_path.exists() {
+        return Ok(None);
+    }
     for version in
-        (OLDEST_SUPPORTED_GALLERY_DB_VERSION_TO_TRIVIALLY_MIGRATE..LATEST_GALLERY_DB_VERSION).rev()
+        (OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION..GALLERY_DB_FILENAME_VERSION).rev()
     {
         let from_db_path = cache.get_cached_path(get_gallery_db_filename(version));
-        if from_db_path.exists() {
-            // Rather than migrating the database schema, which is
-            // how migrations conventionally work, we're going to pull the
-            // small amount of user data that we want to migrate out of the
-            // old DB and into the new DB.
-            let to_db_path = cache.get_cached_path(get_default_gallery_db_filename());
-            println!(
-                "Migrating layout records from {} to {}.",
-                from_db_path.display(),
-                to_db_path.display()
+        if !from_db_path.exists() {
+            continue;
+        }
+        info!(
+            "Copying {} to {} to migrate it.",
+            from_db_path.display(),
+            to_db_path.display()
+        );
+        std::fs::copy(&from_db_path, &to_db_path)?;
+        // The copy has the default filename, so it needs to be migrated right
+        // away, before its version would be mistaken for the filename's.
+        let mut conn = Connection::open(&to_db_path)?;
+        migrate_gallery_db_schema(&mut conn, version)?;
+        return Ok(Some(version));
+    }
+    Ok(None)
+}
+
+/// Migrates the user's gallery DB to the latest schema in place, and their
+/// layout into the layout DB of the given save slot, both from the default DB
+/// (from before save slots existed, when the layout was kept alongside the art
+/// objects) and from DBs too old to be migrated in place.
+///
+/// Returns whether any layout records were migrated.
+pub fn migrate_gallery_db(cache: &GalleryCache, slot: &str) -> Result<bool> {
+    let to_db_path = cache.get_cached_path(get_default_gallery_db_filename());
+    let layout_db_path = cache.get_cached_path(get_layout_db_filename(slot));
+    let mut conn = Connection::open(&to_db_path)?;
+    if let Some(from_version) = migrate_gallery_db_schema(&mut conn, GALLERY_DB_FILENAME_VERSION)? {
+        info!(
+            "Migrated {} from schema version {from_version} to {LATEST_GALLERY_DB_VERSION}.",
+            to_db_path.display()
+        );
+    }
+    let mut to_db = GalleryDb::new(conn);
+    to_db.attach_layout_db(&layout_db_path)?;
+    let copied = to_db.copy_main_layout_into_layout_db()?;
+    if copied > 0 {
+        info!(
+            "Copied {copied} layout records from {} to {}.",
+            to_db_path.display(),
+            layout_db_path.display()
+        );
+    }
+    for version in (OLDEST_SUPPORTED_GALLERY_DB_VERSION_TO_TRIVIALLY_MIGRATE
+        ..GALLERY_DB_FILENAME_VERSION)
+        .rev()
+    {
+        let from_db_path = cache.get_cached_path(get_gallery_db_filename(version));
+        if !from_db_path.exists() {
+            continue;
+        }
+        if version >= OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION {
+            // The newest older DB is either the one that was adopted by
+            // `adopt_older_gallery_db()`, or superseded by the current one.
+            break;
+        }
+        // This DB is too old for its schema to be migrated, so we're going
+        // to pull the small amount of user data that we want to migrate out
+        // of the old DB and into the new DB.
+        info!(
+            "Migrating layout records from {} to {}.",
+            from_db_path.display(),
+            layout_db_path.display()
+        );
+        let mut from_db = GalleryDb::new(Connection::open(from_db_path)?);
+        let layout_records = from_db.get_layout_records_in_non_positive_galleries()?;
+        info!("Found {} layout records to migrate.", layout_records.len());
+        to_db.upsert_layout_records(&layout_records)?;
+        info!("Migrated {} layout records.", layout_records.len());
+        // TODO: Delete the old DB since we don't need it anymore?
+        return Ok(true);
+    }
+    Ok(copied > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rusqlite::Connection;
+
+    use crate::{
+        art_object::ArtObjectId,
+        gallery_cache::GalleryCache,
+        gallery_db::{
+            get_default_gallery_db_filename, get_gallery_db_filename,
+            get_gallery_db_schema_version, get_layout_db_filename, set_gallery_db_schema_version,
+            GalleryDb, GALLERY_DB_FILENAME_VERSION, LATEST_GALLERY_DB_VERSION,
+        },
+        medium::classify_medium,
+    };
+
+    use super::{
+        adopt_older_gallery_db, apply_migrations, get_unversioned_gallery_db_version,
+        migrate_gallery_db, migrate_gallery_db_schema,
+        OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION,
+    };
+
+    /// The columns of the art objects table, along with the version they were
+    /// added in and a value to fill them with.
+    const ART_OBJECT_COLUMNS: [(&str, &str, usize, &str); 21] = [
+        ("id", "INTEGER PRIMARY KEY", 6, "1"),
+        ("title", "TEXT NOT NULL", 6, "'Impression, Soleil Levant'"),
+        ("artist", "TEXT NOT NULL", 6, "'Claude Monet'"),
+        ("culture", "TEXT NOT NULL", 6, "''"),
+        ("date", "TEXT NOT NULL", 6, "'1872'"),
+        ("medium", "TEXT NOT NULL", 6, "'Oil on canvas'"),
+        ("width", "REAL NOT NULL", 6, "48.0"),
+        ("height", "REAL NOT NULL", 6, "63.0"),
+        ("fallback_wikidata_qid", "INTEGER", 6, "NULL"),
+        ("filename", "TEXT NOT NULL", 6, "'Monet.jpg'"),
+        ("collection", "TEXT NOT NULL", 6, "'Musée Marmottan Monet'"),
+        ("artist_qid", "INTEGER", 7, "296"),
+        ("medium_category", "TEXT NOT NULL", 8, "'oil'"),
+        ("highlight", "INTEGER NOT NULL DEFAULT 0", 10, "1"),
+        (
+            "primary_image_url",
+            "TEXT",
+            11,
+            "'https://example.com/large.jpg'",
+        ),
+        (
+            "primary_image_small_url",
+            "TEXT",
+            11,
+            "'https://example.com/small.jpg'",
+        ),
+        ("date_year", "INTEGER", 12, "1872"),
+        (
+            "artist_role",
+            "TEXT NOT NULL DEFAULT ''",
+            13,
+            "'Workshop of'",
+        ),
+        ("tags", "TEXT NOT NULL DEFAULT ''", 14, "'Landscapes|Boats'"),
+        (
+            "classification",
+            "TEXT NOT NULL DEFAULT ''",
+            14,
+            "'Paintings'",
+        ),
+        ("source_gallery", "TEXT", 15, "'818'"),
+    ];
+
+    /// Creates the schema as it was at the given version, without recording
+    /// the version, like the CLI used to, and fills it with an art object
+    /// that's laid out in the default gallery.
+    fn create_old_db(version: usize) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        create_old_schema(&conn, version);
+        conn
+    }
+
+    fn create_old_schema(conn: &Connection, version: usize) {
+        let columns: Vec<_> = ART_OBJECT_COLUMNS
+            .iter()
+            .filter(|(_, _, added_in, _)| *added_in <= version)
+            .collect();
+        let definitions: Vec<String> = columns
+            .iter()
+            .map(|(name, definition, _, _)| format!("{name} {definition}"))
+            .collect();
+        let names: Vec<&str> = columns.iter().map(|(name, _, _, _)| *name).collect();
+        let values: Vec<&str> = columns.iter().map(|(_, _, _, value)| *value).collect();
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE art_objects ({});
+            INSERT INTO art_objects ({}) VALUES ({});
+            ",
+            definitions.join(", "),
+            names.join(", "),
+            values.join(", ")
+        ))
+        .unwrap();
+        if version >= 7 {
+            conn.execute_batch(
+                "
+                CREATE TABLE artists (
+                    qid INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    description TEXT NOT NULL
+                );
+                INSERT INTO artists (qid, name, description)
+                    VALUES (296, 'Claude Monet', 'French painter');
+                ",
+            )
+            .unwrap();
+        }
+        let anchor_columns = if version >= 9 {
+            ", anchor_x REAL, anchor_y REAL"
+        } else {
+            ""
+        };
+        conn.execute_batch(&format!(
+            "
+            CREATE TABLE layout (
+                gallery_id INTEGER NOT NULL,
+                wall_id TEXT NOT NULL,
+                art_object_id INTEGER NOT NULL UNIQUE,
+                x REAL NOT NULL,
+                y REAL NOT NULL
+                {anchor_columns}
             );
-            let mut from_db = GalleryDb::new(Connection::open(from_db_path)?);
-            let layout_records = from_db.get_layout_records_in_non_positive_galleries()?;
-            println!("Found {} layout records to migrate.", layout_records.len());
-            let mut to_db = GalleryDb::new(Connection::open(to_db_path)?);
-            to_db.upsert_layout_records(&layout_records)?;
-            println!("Migrated {} layout records.", layout_records.len());
-            // TODO: Delete the old DB since we don't need it anymore?
-            return Ok(true);
+            INSERT INTO layout (gallery_id, wall_id, art_object_id, x, y)
+                VALUES (-1, 'wall_01', 1, 1.5, 2.5);
+            "
+        ))
+        .unwrap();
+        if version >= 9 {
+            conn.execute_batch(
+                "CREATE TABLE layout_metadata (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+            )
+            .unwrap();
+        }
+    }
+
+    fn get_column_names(conn: &Connection, table: &str) -> Vec<String> {
+        let mut statement = conn
+            .prepare("SELECT name FROM pragma_table_info(?1) ORDER BY cid")
+            .unwrap();
+        let names = statement
+            .query_map([table], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap();
+        names
+    }
+
+    fn get_value<T: rusqlite::types::FromSql>(conn: &Connection, sql: &str) -> T {
+        conn.query_row(sql, (), |row| row.get(0)).unwrap()
+    }
+
+    /// Migrates a DB from the given version to the next one, checking that it
+    /// ends up with the next version's schema and that its data survives.
+    fn migrate_one_version(version: usize) -> Connection {
+        let mut conn = create_old_db(version);
+        let tx = conn.transaction().unwrap();
+        apply_migrations(&tx, version, version + 1).unwrap();
+        tx.commit().unwrap();
+
+        let expected = create_old_db(version + 1);
+        for table in ["art_objects", "artists", "layout", "layout_metadata"] {
+            assert_eq!(
+                get_column_names(&conn, table),
+                get_column_names(&expected, table),
+                "{table}"
+            );
+        }
+        let object: (String, String, String, f64, f64) = conn
+            .query_row(
+                "SELECT title, artist, collection, width, height FROM art_objects WHERE id = 1",
+                (),
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            object,
+            (
+                "Impression, Soleil Levant".to_string(),
+                "Claude Monet".to_string(),
+                "Musée Marmottan Monet".to_string(),
+                48.0,
+                63.0
+            )
+        );
+        let layout: (i64, String, f64, f64) = conn
+            .query_row(
+                "SELECT gallery_id, wall_id, x, y FROM layout WHERE art_object_id = 1",
+                (),
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(layout, (-1, "wall_01".to_string(), 1.5, 2.5));
+        conn
+    }
+
+    #[test]
+    fn test_migrate_v6_to_v7_works() {
+        let conn = migrate_one_version(6);
+        let artist_qid: Option<i64> = get_value(&conn, "SELECT artist_qid FROM art_objects");
+        assert_eq!(artist_qid, None);
+        let artists: i64 = get_value(&conn, "SELECT COUNT(*) FROM artists");
+        assert_eq!(artists, 0);
+    }
+
+    #[test]
+    fn test_migrate_v7_to_v8_works() {
+        let conn = migrate_one_version(7);
+        let artist_qid: i64 = get_value(&conn, "SELECT artist_qid FROM art_objects");
+        assert_eq!(artist_qid, 296);
+        let category: String = get_value(&conn, "SELECT medium_category FROM art_objects");
+        assert_eq!(category, classify_medium("Oil on canvas").as_str());
+    }
+
+    #[test]
+    fn test_migrate_v8_to_v9_works() {
+        let conn = migrate_one_version(8);
+        let anchor: (Option<f64>, Option<f64>) = conn
+            .query_row("SELECT anchor_x, anchor_y FROM layout", (), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(anchor, (None, None));
+        let metadata: i64 = get_value(&conn, "SELECT COUNT(*) FROM layout_metadata");
+        assert_eq!(metadata, 0);
+    }
+
+    #[test]
+    fn test_migrate_v8_to_v9_works_without_layout() {
+        let mut conn = create_old_db(8);
+        conn.execute_batch("DROP TABLE layout").unwrap();
+        let tx = conn.transaction().unwrap();
+        apply_migrations(&tx, 8, 9).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(get_column_names(&conn, "layout"), Vec::<String>::new());
+        assert_eq!(
+            get_column_names(&conn, "layout_metadata"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_migrate_v9_to_v10_works() {
+        let conn = migrate_one_version(9);
+        let highlight: i64 = get_value(&conn, "SELECT highlight FROM art_objects");
+        assert_eq!(highlight, 0);
+    }
+
+    #[test]
+    fn test_migrate_v10_to_v11_works() {
+        let conn = migrate_one_version(10);
+        let highlight: i64 = get_value(&conn, "SELECT highlight FROM art_objects");
+        assert_eq!(highlight, 1);
+        let url: Option<String> = get_value(&conn, "SELECT primary_image_url FROM art_objects");
+        assert_eq!(url, None);
+    }
+
+    #[test]
+    fn test_migrate_v11_to_v12_works() {
+        let conn = migrate_one_version(11);
+        let url: String = get_value(&conn, "SELECT primary_image_small_url FROM art_objects");
+        assert_eq!(url, "https://example.com/small.jpg");
+        let year: i64 = get_value(&conn, "SELECT date_year FROM art_objects");
+        assert_eq!(year, 1872);
+    }
+
+    #[test]
+    fn test_migrate_v12_to_v13_works() {
+        let conn = migrate_one_version(12);
+        let year: i64 = get_value(&conn, "SELECT date_year FROM art_objects");
+        assert_eq!(year, 1872);
+        let role: String = get_value(&conn, "SELECT artist_role FROM art_objects");
+        assert_eq!(role, "");
+    }
+
+    #[test]
+    fn test_migrate_v13_to_v14_works() {
+        let conn = migrate_one_version(13);
+        let role: String = get_value(&conn, "SELECT artist_role FROM art_objects");
+        assert_eq!(role, "Workshop of");
+        let tags: String = get_value(&conn, "SELECT tags FROM art_objects");
+        assert_eq!(tags, "");
+        let classification: String = get_value(&conn, "SELECT classification FROM art_objects");
+        assert_eq!(classification, "");
+    }
+
+    #[test]
+    fn test_migrate_v14_to_v15_works() {
+        let conn = migrate_one_version(14);
+        let tags: String = get_value(&conn, "SELECT tags FROM art_objects");
+        assert_eq!(tags, "Landscapes|Boats");
+        let source_gallery: Option<String> =
+            get_value(&conn, "SELECT source_gallery FROM art_objects");
+        assert_eq!(source_gallery, None);
+    }
+
+    #[test]
+    fn test_oldest_migratable_db_is_migrated_to_latest_schema() {
+        let mut conn = create_old_db(OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION);
+        assert_eq!(
+            migrate_gallery_db_schema(&mut conn, OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION)
+                .unwrap(),
+            Some(OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION)
+        );
+        assert_eq!(
+            get_gallery_db_schema_version(&conn).unwrap(),
+            Some(LATEST_GALLERY_DB_VERSION)
+        );
+
+        let latest_path = std::env::temp_dir().join(format!(
+            "gallery-db-migration-test-latest-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&latest_path);
+        GalleryDb::new(Connection::open(&latest_path).unwrap())
+            .create_schema()
+            .unwrap();
+        let latest_conn = Connection::open(&latest_path).unwrap();
+        for table in ["art_objects", "artists"] {
+            let mut columns = get_column_names(&conn, table);
+            let mut expected = get_column_names(&latest_conn, table);
+            columns.sort();
+            expected.sort();
+            assert_eq!(columns, expected, "{table}");
         }
+        drop(latest_conn);
+        std::fs::remove_file(&latest_path).unwrap();
+
+        // Now that it records its version, it's left alone.
+        assert_eq!(
+            migrate_gallery_db_schema(&mut conn, OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION)
+                .unwrap(),
+            None
+        );
+        let db = GalleryDb::new(conn);
+        let object = db.get_art_object(ArtObjectId::from_raw_i64(1)).unwrap();
+        assert_eq!(object.unwrap().title, "Impression, Soleil Levant");
+    }
+
+    #[test]
+    fn test_unversioned_latest_db_records_its_version() {
+        let mut conn = create_old_db(LATEST_GALLERY_DB_VERSION);
+        assert_eq!(
+            migrate_gallery_db_schema(&mut conn, LATEST_GALLERY_DB_VERSION).unwrap(),
+            None
+        );
+        assert_eq!(
+            get_gallery_db_schema_version(&conn).unwrap(),
+            Some(LATEST_GALLERY_DB_VERSION)
+        );
+    }
+
+    #[test]
+    fn test_newer_and_too_old_dbs_are_not_migrated() {
+        let mut conn = create_old_db(LATEST_GALLERY_DB_VERSION);
+        set_gallery_db_schema_version(&conn, LATEST_GALLERY_DB_VERSION + 1).unwrap();
+        assert!(migrate_gallery_db_schema(&mut conn, LATEST_GALLERY_DB_VERSION).is_err());
+
+        let mut conn = create_old_db(OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION);
+        assert!(migrate_gallery_db_schema(
+            &mut conn,
+            OLDEST_IN_PLACE_MIGRATABLE_GALLERY_DB_VERSION - 1
+        )
+        .is_err());
+        assert_eq!(get_gallery_db_schema_version(&conn).unwrap(), None);
+    }
+
+    #[test]
+    fn test_empty_db_is_not_migrated() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate_gallery_db_schema(&mut conn, 6).unwrap(), None);
+        assert_eq!(get_gallery_db_schema_version(&conn).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_unversioned_gallery_db_version_works() {
+        assert_eq!(
+            get_unversioned_gallery_db_version("cache/gallery9.sqlite"),
+            9
+        );
+        assert_eq!(
+            get_unversioned_gallery_db_version("my-gallery.sqlite"),
+            GALLERY_DB_FILENAME_VERSION
+        );
+    }
+
+    #[test]
+    fn test_older_db_is_adopted_and_its_layout_migrated() {
+        let root_dir: PathBuf =
+            std::env::temp_dir().join(format!("gallery-db-migration-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root_dir);
+        std::fs::create_dir_all(&root_dir).unwrap();
+        let cache = GalleryCache::new_offline(root_dir.clone());
+        let old_db_path = cache.get_cached_path(get_gallery_db_filename(9));
+        create_old_schema(&Connection::open(&old_db_path).unwrap(), 9);
+        let older_db_path = cache.get_cached_path(get_gallery_db_filename(5));
+        create_old_schema(&Connection::open(&older_db_path).unwrap(), 6);
+        Connection::open(&older_db_path)
+            .unwrap()
+            .execute_batch("UPDATE layout SET x = 100")
+            .unwrap();
+
+        assert_eq!(adopt_older_gallery_db(&cache).unwrap(), Some(9));
+        assert_eq!(adopt_older_gallery_db(&cache).unwrap(), None);
+        let db = GalleryDb::open(
+            cache.get_cached_path(get_default_gallery_db_filename()),
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            db.schema_version().unwrap(),
+            Some(LATEST_GALLERY_DB_VERSION)
+        );
+        let old_conn = Connection::open(&old_db_path).unwrap();
+        assert_eq!(get_gallery_db_schema_version(&old_conn).unwrap(), None);
+
+        // The adopted DB's layout wins over the one too old to migrate.
+        assert!(migrate_gallery_db(&cache, "slot").unwrap());
+        let mut db = GalleryDb::new(
+            Connection::open(cache.get_cached_path(get_default_gallery_db_filename())).unwrap(),
+        );
+        db.attach_layout_db(cache.get_cached_path(get_layout_db_filename("slot")))
+            .unwrap();
+        let records = db.get_all_layout_records().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].art_object_id, ArtObjectId::from_raw_i64(1));
+        assert_eq!((records[0].x, records[0].y), (1.5, 2.5));
+        std::fs::remove_dir_all(&root_dir).unwrap();
     }
-    Ok(false)
 }
@@ -0,0 +1,598 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use rusqlite::{Connection, ErrorCode, Row};
+
+use crate::{
+    art_object::ArtObjectId,
+    gallery_db::{GalleryDb, LayoutRecord, SavedFilterRecord, TagRecord},
+};
+
+/// SQLite keeps these files alongside the database, and they belong to
+/// whichever database file has the same name.
+const SIDECAR_SUFFIXES: [&str; 2] = ["-wal", "-shm"];
+
+/// The presence of a file with this suffix alongside a database means it needs
+/// a full check for corruption, see `mark_db_for_check()`.
+const NEEDS_CHECK_SUFFIX: &str = ".needs-check";
+
+/// Which of the databases a `DbRecoveryReport` is about.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RecoveredDb {
+    /// The main gallery database, which contains the art objects, along with
+    /// tags and saved filters.
+    Gallery,
+    /// A save slot's layout database, see `GalleryDb::attach_layout_db()`.
+    Layout,
+}
+
+/// What happened when a corrupt database was rebuilt.
+#[derive(Debug, PartialEq)]
+pub struct DbRecoveryReport {
+    pub db: RecoveredDb,
+    /// Where the corrupt database was moved to.
+    pub quarantined_path: PathBuf,
+    /// How many layout records from non-positive galleries made it into the
+    /// rebuilt database.
+    pub salvaged_layout_records: usize,
+    pub salvaged_tags: usize,
+    pub salvaged_saved_filters: usize,
+    /// Why salvaging failed, if it did. Note that some records may still have
+    /// been salvaged.
+    pub salvage_error: Option<String>,
+}
+
+impl Display for DbRecoveryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.db {
+            RecoveredDb::Gallery => "gallery database",
+            RecoveredDb::Layout => "save slot's layout database",
+        };
+        write!(
+            f,
+            "The {name} was corrupt, so it was moved to {} and an empty one was created in its place. ",
+            self.quarantined_path.display()
+        )?;
+        match self.db {
+            RecoveredDb::Gallery => write!(
+                f,
+                "{} tag(s) and {} saved filter(s) were salvaged from it",
+                self.salvaged_tags, self.salvaged_saved_filters
+            )?,
+            RecoveredDb::Layout => write!(
+                f,
+                "{} layout record(s) in non-positive galleries were salvaged from it",
+                self.salvaged_layout_records
+            )?,
+        }
+        if let Some(err) = &self.salvage_error {
+            write!(
+                f,
+                ", but an error occurred, so some may have been lost ({err})"
+            )?;
+        }
+        match self.db {
+            RecoveredDb::Gallery => {
+                write!(f, ". Please re-run the importer to restore art objects.")
+            }
+            RecoveredDb::Layout => write!(
+                f,
+                ". The rest of the layout and the move history were lost, so the galleries will need to be laid out again."
+            ),
+        }
+    }
+}
+
+pub fn is_corruption_error(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<rusqlite::Error>()
+            .and_then(|err| err.sqlite_error_code()),
+        Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)
+    )
+}
+
+/// Makes the next `recover_corrupt_gallery_db()` or `recover_corrupt_layout_db()`
+/// of the given database do a full check for corruption, which is too slow to
+/// do every time. This should be called whenever using the database fails with
+/// a corruption error, since the corruption could be anywhere in it.
+pub fn mark_db_for_check(db_path: &Path) -> Result<()> {
+    std::fs::write(with_suffix(db_path, NEEDS_CHECK_SUFFIX), "")?;
+    Ok(())
+}
+
+/// Returns whether the database at the given path is corrupt. Errors that
+/// don't indicate corruption, e.g. the database being locked, are returned
+/// as-is, since we don't want to throw away a perfectly good database.
+///
+/// Unless `quick_check` is true, this only checks that the database's header
+/// and schema can be read, so corruption elsewhere will go unnoticed.
+fn is_corrupt(db_path: &Path, quick_check: bool) -> Result<bool> {
+    let result = GalleryDb::open(db_path, false).and_then(|db| {
+        // Opening the database doesn't actually read anything, but this does.
+        db.has_table("layout")?;
+        if quick_check {
+            db.passes_quick_check()
+        } else {
+            Ok(true)
+        }
+    });
+    match result {
+        Ok(passed) => Ok(!passed),
+        Err(err) if is_corruption_error(&err) => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+fn quarantine(db_path: &Path, timestamp: u64) -> Result<PathBuf> {
+    let Some(filename) = db_path.file_name() else {
+        return Err(anyhow!("{} is not a file", db_path.display()));
+    };
+    let quarantined_path = db_path.with_file_name(format!(
+        "{}.corrupt-{timestamp}",
+        filename.to_string_lossy()
+    ));
+    std::fs::rename(db_path, &quarantined_path)?;
+    // Move these too, so that they don't get applied to the new database,
+    // and so that they're available when salvaging from the corrupt one.
+    for suffix in SIDECAR_SUFFIXES {
+        let sidecar_path = with_suffix(db_path, suffix);
+        if sidecar_path.exists() {
+            std::fs::rename(&sidecar_path, with_suffix(&quarantined_path, suffix))?;
+        }
+    }
+    Ok(quarantined_path)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// The player data that could be read from a corrupt database.
+#[derive(Debug, Default)]
+struct Salvage {
+    /// Layout records in non-positive galleries.
+    layout_records: Vec<LayoutRecord<String>>,
+    tags: Vec<TagRecord>,
+    saved_filters: Vec<SavedFilterRecord>,
+    errors: Vec<anyhow::Error>,
+}
+
+/// Appends as many rows of the given query on the given table as we can to
+/// `records`, stopping at the first error. A missing table just means there's
+/// nothing to salvage.
+fn salvage_rows<T>(
+    conn: &Connection,
+    table: &str,
+    sql: &str,
+    records: &mut Vec<T>,
+    to_record: impl Fn(&Row) -> rusqlite::Result<T>,
+) -> Result<()> {
+    let has_table: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [table],
+        |row| row.get(0),
+    )?;
+    if !has_table {
+        return Ok(());
+    }
+    let mut statement = conn.prepare(sql)?;
+    let mut rows = statement.query(())?;
+    while let Some(row) = rows.next()? {
+        records.push(to_record(row)?);
+    }
+    Ok(())
+}
+
+/// Read as much player data as we can from the given corrupt database. Each
+/// table is read separately, so an error in one doesn't keep the others from
+/// being salvaged, and any rows read before an error occurs are kept.
+fn salvage(db_path: &Path, db: RecoveredDb) -> Salvage {
+    let mut salvage = Salvage::default();
+    let conn = match Connection::open(db_path) {
+        Ok(conn) => conn,
+        Err(err) => {
+            salvage.errors.push(err.into());
+            return salvage;
+        }
+    };
+    let results = match db {
+        RecoveredDb::Gallery => vec![
+            salvage_rows(
+                &conn,
+                "tags",
+                "SELECT art_object_id, tag, created_at FROM tags",
+                &mut salvage.tags,
+                |row| {
+                    Ok(TagRecord {
+                        art_object_id: ArtObjectId::from_raw_i64(row.get(0)?),
+                        tag: row.get(1)?,
+                        created_at: row.get(2)?,
+                    })
+                },
+            ),
+            salvage_rows(
+                &conn,
+                "saved_filters",
+                "SELECT name, filter, created_at FROM saved_filters",
+                &mut salvage.saved_filters,
+                |row| {
+                    Ok(SavedFilterRecord {
+                        name: row.get(0)?,
+                        filter: row.get(1)?,
+                        created_at: row.get(2)?,
+                    })
+                },
+            ),
+        ],
+        RecoveredDb::Layout => vec![salvage_rows(
+            &conn,
+            "layout",
+            "SELECT gallery_id, wall_id, art_object_id, x, y FROM layout WHERE gallery_id <= 0",
+            &mut salvage.layout_records,
+            |row| {
+                Ok(LayoutRecord {
+                    gallery_id: row.get(0)?,
+                    wall_id: row.get(1)?,
+                    art_object_id: ArtObjectId::from_raw_i64(row.get(2)?),
+                    x: row.get(3)?,
+                    y: row.get(4)?,
+                    // Only players hang art in non-positive galleries, and they
+                    // always hang it upright.
+                    rotated: false,
+                })
+            },
+        )],
+    };
+    salvage
+        .errors
+        .extend(results.into_iter().filter_map(|result| result.err()));
+    salvage
+}
+
+/// If the given database is corrupt, moves it out of the way, creates an empty
+/// database in its place, and copies over whatever player data can be
+/// salvaged from the corrupt one.
+fn recover_corrupt_db(db_path: &Path, db: RecoveredDb) -> Result<Option<DbRecoveryReport>> {
+    let needs_check_path = with_suffix(db_path, NEEDS_CHECK_SUFFIX);
+    let needs_check = needs_check_path.exists();
+    if needs_check {
+        info!("Checking {} for corruption.", db_path.display());
+    }
+    if !is_corrupt(db_path, needs_check)? {
+        if needs_check {
+            std::fs::remove_file(&needs_check_path)?;
+        }
+        return Ok(None);
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("current time should be after the unix epoch")
+        .as_secs();
+    warn!("{} is corrupt, quarantining it.", db_path.display());
+    let quarantined_path = quarantine(db_path, timestamp)?;
+    let salvage = salvage(&quarantined_path, db);
+    let mut new_db = GalleryDb::open(db_path, false)?;
+    match db {
+        RecoveredDb::Gallery => new_db.create_schema()?,
+        RecoveredDb::Layout => new_db.create_layout_db_schema()?,
+    }
+    new_db.upsert_layout_records(&salvage.layout_records)?;
+    let salvaged_tags = new_db.import_tags(&salvage.tags)?;
+    let salvaged_saved_filters = new_db.import_saved_filters(&salvage.saved_filters)?;
+    if needs_check {
+        std::fs::remove_file(&needs_check_path)?;
+    }
+    let salvage_error = (!salvage.errors.is_empty()).then(|| {
+        salvage
+            .errors
+            .iter()
+            .map(|err| err.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")
+    });
+    Ok(Some(DbRecoveryReport {
+        db,
+        quarantined_path,
+        salvaged_layout_records: salvage.layout_records.len(),
+        salvaged_tags,
+        salvaged_saved_filters,
+        salvage_error,
+    }))
+}
+
+/// If the main gallery database at the given path is corrupt, moves it out of
+/// the way, creates an empty database in its place, and copies over whatever
+/// tags and saved filters can be salvaged from the corrupt one. The art
+/// objects need to be re-imported.
+///
+/// To keep startup fast, a full check for corruption is only done if the
+/// database was marked with `mark_db_for_check()`.
+///
+/// Returns `None` if the database isn't corrupt.
+pub fn recover_corrupt_gallery_db(db_path: &Path) -> Result<Option<DbRecoveryReport>> {
+    recover_corrupt_db(db_path, RecoveredDb::Gallery)
+}
+
+/// Like `recover_corrupt_gallery_db()`, but for a save slot's layout database,
+/// salvaging the layout records in non-positive galleries, which is where
+/// players hang art. Does nothing if the layout database doesn't exist yet.
+pub fn recover_corrupt_layout_db(layout_db_path: &Path) -> Result<Option<DbRecoveryReport>> {
+    if !layout_db_path.exists() {
+        return Ok(None);
+    }
+    recover_corrupt_db(layout_db_path, RecoveredDb::Layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Seek, SeekFrom, Write},
+        path::PathBuf,
+    };
+
+    use crate::{
+        art_object::ArtObjectId,
+        gallery_db::{ArtObjectQueryOptions, GalleryDb, LayoutRecord},
+    };
+
+    use super::{
+        mark_db_for_check, recover_corrupt_gallery_db, recover_corrupt_layout_db, salvage,
+        with_suffix, RecoveredDb, NEEDS_CHECK_SUFFIX,
+    };
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gallery-db-recovery-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn layout_record(gallery_id: i64, id: i64) -> LayoutRecord<String> {
+        LayoutRecord {
+            gallery_id,
+            wall_id: "wall_a".into(),
+            art_object_id: ArtObjectId::Met(id),
+            x: 1.0,
+            y: 2.0,
+            rotated: false,
+        }
+    }
+
+    /// Adds a lot of layout records in positive galleries to the given database,
+    /// so it spans many pages.
+    fn add_filler(db: &mut GalleryDb) {
+        let filler: Vec<LayoutRecord<String>> =
+            (100..5000).map(|id| layout_record(1, id)).collect();
+        db.upsert_layout_records(&filler).unwrap();
+        assert!(db.passes_quick_check().unwrap());
+    }
+
+    /// Creates a main gallery database with a tag and a saved filter.
+    fn create_db(db_path: &PathBuf) {
+        let mut db = GalleryDb::open(db_path, false).unwrap();
+        db.create_schema().unwrap();
+        db.add_tag(ArtObjectId::Met(1), "favorite").unwrap();
+        db.save_filter("oils", "oil").unwrap();
+        add_filler(&mut db);
+    }
+
+    /// Creates a save slot's layout database with a few layout records in
+    /// non-positive galleries.
+    fn create_layout_db(layout_db_path: &PathBuf) {
+        let mut db = GalleryDb::open(layout_db_path, false).unwrap();
+        db.create_layout_db_schema().unwrap();
+        db.upsert_layout_records(&vec![layout_record(0, 1), layout_record(-1, 2)])
+            .unwrap();
+        add_filler(&mut db);
+    }
+
+    /// Overwrites part of the database past its schema with garbage.
+    fn scribble_on(db_path: &PathBuf) {
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(db_path)
+            .unwrap();
+        let len = file.metadata().unwrap().len();
+        file.seek(SeekFrom::Start(len / 2)).unwrap();
+        file.write_all(&vec![0xff; 16384]).unwrap();
+    }
+
+    #[test]
+    fn test_healthy_db_is_left_alone() {
+        let dir = temp_dir("healthy");
+        let db_path = dir.join("gallery.sqlite");
+        create_db(&db_path);
+        assert_eq!(recover_corrupt_gallery_db(&db_path).unwrap(), None);
+        mark_db_for_check(&db_path).unwrap();
+        assert_eq!(recover_corrupt_gallery_db(&db_path).unwrap(), None);
+        assert!(!with_suffix(&db_path, NEEDS_CHECK_SUFFIX).exists());
+        let db = GalleryDb::open(&db_path, true).unwrap();
+        assert_eq!(
+            db.get_tags(ArtObjectId::Met(1)).unwrap(),
+            vec!["favorite".to_string()]
+        );
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_layout_db_is_left_alone() {
+        let dir = temp_dir("missing-layout");
+        let layout_db_path = dir.join("layout-user.sqlite");
+        assert_eq!(recover_corrupt_layout_db(&layout_db_path).unwrap(), None);
+        assert!(!layout_db_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_salvage_works() {
+        let dir = temp_dir("salvage");
+        let db_path = dir.join("gallery.sqlite");
+        create_db(&db_path);
+        let gallery_salvage = salvage(&db_path, RecoveredDb::Gallery);
+        assert!(
+            gallery_salvage.errors.is_empty(),
+            "{:?}",
+            gallery_salvage.errors
+        );
+        assert_eq!(gallery_salvage.layout_records.len(), 0);
+        assert_eq!(gallery_salvage.tags.len(), 1);
+        assert_eq!(gallery_salvage.tags[0].tag, "favorite");
+        assert_eq!(gallery_salvage.saved_filters.len(), 1);
+        assert_eq!(gallery_salvage.saved_filters[0].name, "oils");
+
+        let layout_db_path = dir.join("layout-user.sqlite");
+        create_layout_db(&layout_db_path);
+        let mut layout_salvage = salvage(&layout_db_path, RecoveredDb::Layout);
+        assert!(
+            layout_salvage.errors.is_empty(),
+            "{:?}",
+            layout_salvage.errors
+        );
+        layout_salvage
+            .layout_records
+            .sort_by_key(|record| record.art_object_id.to_raw_i64());
+        assert_eq!(
+            layout_salvage.layout_records,
+            vec![layout_record(0, 1), layout_record(-1, 2)]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_salvage_works_without_player_data_tables() {
+        let dir = temp_dir("salvage-old");
+        let db_path = dir.join("gallery.sqlite");
+        let mut db = GalleryDb::open(&db_path, false).unwrap();
+        db.create_schema().unwrap();
+        drop(db);
+        let salvage = salvage(&db_path, RecoveredDb::Gallery);
+        assert!(salvage.errors.is_empty(), "{:?}", salvage.errors);
+        assert_eq!(salvage.tags.len(), 0);
+        assert_eq!(salvage.saved_filters.len(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_db_is_quarantined_without_being_marked() {
+        let dir = temp_dir("truncated");
+        let db_path = dir.join("gallery.sqlite");
+        create_db(&db_path);
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&db_path)
+            .unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len / 2).unwrap();
+        drop(file);
+
+        let report = recover_corrupt_gallery_db(&db_path).unwrap().unwrap();
+        assert!(report.quarantined_path.exists());
+        assert!(report.salvage_error.is_some());
+        assert!(report.to_string().contains("some may have been lost"));
+        let db = GalleryDb::open(&db_path, false).unwrap();
+        assert!(db.passes_quick_check().unwrap());
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_db_is_quarantined_and_rebuilt_once_marked() {
+        let dir = temp_dir("corrupt");
+        let db_path = dir.join("gallery.sqlite");
+        create_db(&db_path);
+        scribble_on(&db_path);
+
+        // Its schema is still readable, so we don't notice until something
+        // runs into the corruption.
+        assert_eq!(recover_corrupt_gallery_db(&db_path).unwrap(), None);
+        mark_db_for_check(&db_path).unwrap();
+
+        let report = recover_corrupt_gallery_db(&db_path).unwrap().unwrap();
+        assert!(!with_suffix(&db_path, NEEDS_CHECK_SUFFIX).exists());
+        assert!(report.quarantined_path.exists());
+        assert_eq!(report.quarantined_path.parent(), Some(dir.as_path()));
+        assert!(report
+            .quarantined_path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("gallery.sqlite.corrupt-"));
+        assert!(report.to_string().contains("re-run the importer"));
+        assert_eq!(report.db, RecoveredDb::Gallery);
+        assert_eq!(report.salvaged_tags, 1);
+        assert_eq!(report.salvaged_saved_filters, 1);
+
+        let mut db = GalleryDb::open(&db_path, false).unwrap();
+        assert!(db.passes_quick_check().unwrap());
+        assert_eq!(
+            db.count_art_objects(&ArtObjectQueryOptions::default())
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            db.get_tags(ArtObjectId::Met(1)).unwrap(),
+            vec!["favorite".to_string()]
+        );
+        assert_eq!(db.get_saved_filter("oils").unwrap(), Some("oil".into()));
+        drop(db);
+
+        // Now that it's been rebuilt, it shouldn't be touched again.
+        assert_eq!(recover_corrupt_gallery_db(&db_path).unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_layout_db_is_rebuilt_with_salvaged_records() {
+        let dir = temp_dir("corrupt-layout");
+        let db_path = dir.join("gallery.sqlite");
+        let layout_db_path = dir.join("layout-user.sqlite");
+        create_db(&db_path);
+        create_layout_db(&layout_db_path);
+        scribble_on(&layout_db_path);
+        mark_db_for_check(&layout_db_path).unwrap();
+
+        assert_eq!(recover_corrupt_gallery_db(&db_path).unwrap(), None);
+        let report = recover_corrupt_layout_db(&layout_db_path).unwrap().unwrap();
+        assert_eq!(report.db, RecoveredDb::Layout);
+        assert_eq!(report.salvaged_layout_records, 2);
+        assert!(report
+            .quarantined_path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("layout-user.sqlite.corrupt-"));
+        assert!(report.to_string().contains("move history were lost"));
+
+        // The salvaged records should be what the slot sees, rather than
+        // whatever's left over in the main database from before save slots.
+        let mut db = GalleryDb::open(&db_path, false).unwrap();
+        db.upsert_layout_records(&vec![layout_record(0, 3)])
+            .unwrap();
+        db.attach_layout_db(&layout_db_path).unwrap();
+        assert_eq!(db.copy_main_layout_into_layout_db().unwrap(), 0);
+        let mut records = db.get_layout_records_in_non_positive_galleries().unwrap();
+        records.sort_by_key(|record| record.art_object_id.to_raw_i64());
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.art_object_id)
+                .collect::<Vec<_>>(),
+            vec![ArtObjectId::Met(1), ArtObjectId::Met(2)]
+        );
+        drop(db);
+
+        assert_eq!(recover_corrupt_layout_db(&layout_db_path).unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,362 @@
+use std::fmt::Display;
+
+use anyhow::{anyhow, Result};
+use rusqlite::Connection;
+
+/// The columns that queries expect the `art_objects` table to have at
+/// `LATEST_GALLERY_DB_VERSION`, along with their declared types.
+pub const EXPECTED_ART_OBJECTS_COLUMNS: [(&str, &str); 21] = [
+    ("id", "INTEGER"),
+    ("title", "TEXT"),
+    ("artist", "TEXT"),
+    ("artist_qid", "INTEGER"),
+    ("culture", "TEXT"),
+    ("date", "TEXT"),
+    ("medium", "TEXT"),
+    ("medium_category", "TEXT"),
+    ("width", "REAL"),
+    ("height", "REAL"),
+    ("fallback_wikidata_qid", "INTEGER"),
+    ("filename", "TEXT"),
+    ("collection", "TEXT"),
+    ("highlight", "INTEGER"),
+    ("primary_image_url", "TEXT"),
+    ("primary_image_small_url", "TEXT"),
+    ("date_year", "INTEGER"),
+    ("artist_role", "TEXT"),
+    ("tags", "TEXT"),
+    ("classification", "TEXT"),
+    ("source_gallery", "TEXT"),
+];
+
+/// Like `EXPECTED_ART_OBJECTS_COLUMNS`, but for the `layout` table.
+pub const EXPECTED_LAYOUT_COLUMNS: [(&str, &str); 9] = [
+    ("gallery_id", "INTEGER"),
+    ("wall_id", "TEXT"),
+    ("art_object_id", "INTEGER"),
+    ("x", "REAL"),
+    ("y", "REAL"),
+    ("anchor_x", "REAL"),
+    ("anchor_y", "REAL"),
+    ("rotated", "INTEGER"),
+    ("modified_at", "INTEGER"),
+];
+
+/// Layout columns that are added whenever the layout is written to, and that
+/// reads work without, so it's fine for them to be missing.
+const OPTIONAL_LAYOUT_COLUMNS: [&str; 2] = ["rotated", "modified_at"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaColumn {
+    pub table: String,
+    pub column: String,
+}
+
+impl Display for SchemaColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.table, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MistypedColumn {
+    pub column: SchemaColumn,
+    pub expected_type: String,
+    pub actual_type: String,
+}
+
+/// How a database's schema differs from what this version of the crate
+/// expects, see `GalleryDb::check_schema()`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SchemaReport {
+    pub missing_tables: Vec<String>,
+    pub missing_columns: Vec<SchemaColumn>,
+    /// Columns we don't know about. Queries only ever read the columns they
+    /// need, so these aren't a problem.
+    pub extra_columns: Vec<SchemaColumn>,
+    pub mistyped_columns: Vec<MistypedColumn>,
+}
+
+impl SchemaReport {
+    /// Returns whether queries will work against the database, i.e. whether
+    /// everything they need is there and has the right type.
+    pub fn is_ok(&self) -> bool {
+        self.problems().is_empty()
+    }
+
+    /// Returns a description of each thing that will make queries fail.
+    pub fn problems(&self) -> Vec<String> {
+        let missing_tables = self
+            .missing_tables
+            .iter()
+            .map(|table| format!("database schema is missing table {table}"));
+        let missing_columns = self
+            .missing_columns
+            .iter()
+            .map(|column| format!("database schema is missing column {column}"));
+        let mistyped_columns = self.mistyped_columns.iter().map(|mistyped| {
+            format!(
+                "database schema has column {} of type {}, expected {}",
+                mistyped.column, mistyped.actual_type, mistyped.expected_type
+            )
+        });
+        missing_tables
+            .chain(missing_columns)
+            .chain(mistyped_columns)
+            .collect()
+    }
+
+    /// Returns an error describing every problem with the schema, if there are
+    /// any, along with what to do about it.
+    pub fn to_result(&self) -> Result<()> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "{} — re-run the importer or Migrate",
+            problems.join(", ")
+        ))
+    }
+}
+
+/// Returns the name and declared type of each column in the given table, which
+/// is empty if the table doesn't exist.
+fn get_columns(conn: &Connection, schema: &str, table: &str) -> Result<Vec<(String, String)>> {
+    let mut statement = conn.prepare("SELECT name, type FROM pragma_table_info(?1, ?2)")?;
+    let columns = statement
+        .query_map([table, schema], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(columns)
+}
+
+fn check_table(
+    conn: &Connection,
+    schema: &str,
+    table: &str,
+    expected_columns: &[(&str, &str)],
+    optional_columns: &[&str],
+    report: &mut SchemaReport,
+) -> Result<()> {
+    let columns = get_columns(conn, schema, table)?;
+    if columns.is_empty() {
+        report.missing_tables.push(table.to_string());
+        return Ok(());
+    }
+    let schema_column = |column: &str| SchemaColumn {
+        table: table.to_string(),
+        column: column.to_string(),
+    };
+    for (expected_name, expected_type) in expected_columns {
+        match columns.iter().find(|(name, _)| name == expected_name) {
+            Some((_, actual_type)) if !actual_type.eq_ignore_ascii_case(expected_type) => {
+                report.mistyped_columns.push(MistypedColumn {
+                    column: schema_column(expected_name),
+                    expected_type: expected_type.to_string(),
+                    actual_type: actual_type.clone(),
+                });
+            }
+            Some(_) => {}
+            None if optional_columns.contains(expected_name) => {}
+            None => report.missing_columns.push(schema_column(expected_name)),
+        }
+    }
+    for (name, _) in columns.iter() {
+        if !expected_columns
+            .iter()
+            .any(|(expected_name, _)| expected_name == name)
+        {
+            report.extra_columns.push(schema_column(name));
+        }
+    }
+    Ok(())
+}
+
+/// Compares the `art_objects` table in the main schema, and the `layout` table
+/// in the given layout schema, against what this version of the crate expects.
+pub fn check_gallery_db_schema(conn: &Connection, layout_schema: &str) -> Result<SchemaReport> {
+    let mut report = SchemaReport::default();
+    check_table(
+        conn,
+        "main",
+        "art_objects",
+        &EXPECTED_ART_OBJECTS_COLUMNS,
+        &[],
+        &mut report,
+    )?;
+    check_table(
+        conn,
+        layout_schema,
+        "layout",
+        &EXPECTED_LAYOUT_COLUMNS,
+        &OPTIONAL_LAYOUT_COLUMNS,
+        &mut report,
+    )?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::gallery_db::GalleryDb;
+
+    use super::{
+        check_gallery_db_schema, MistypedColumn, SchemaColumn, EXPECTED_ART_OBJECTS_COLUMNS,
+        EXPECTED_LAYOUT_COLUMNS,
+    };
+
+    fn column(table: &str, column: &str) -> SchemaColumn {
+        SchemaColumn {
+            table: table.to_string(),
+            column: column.to_string(),
+        }
+    }
+
+    /// Creates the given tables, each with the expected columns, except that
+    /// `alter` can change or remove (by returning `None`) any column's
+    /// definition.
+    fn create_tables(
+        tables: &[(&str, &[(&str, &str)])],
+        alter: impl Fn(&str, &str) -> Option<String>,
+    ) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        for (table, columns) in tables {
+            let definitions: Vec<String> = columns
+                .iter()
+                .filter_map(|(name, sql_type)| {
+                    let full_name = format!("{table}.{name}");
+                    alter(&full_name, sql_type).map(|sql_type| format!("{name} {sql_type}"))
+                })
+                .collect();
+            conn.execute_batch(&format!(
+                "CREATE TABLE {table} ({})",
+                definitions.join(", ")
+            ))
+            .unwrap();
+        }
+        conn
+    }
+
+    fn create_altered_db(alter: impl Fn(&str, &str) -> Option<String>) -> Connection {
+        create_tables(
+            &[
+                ("art_objects", &EXPECTED_ART_OBJECTS_COLUMNS[..]),
+                ("layout", &EXPECTED_LAYOUT_COLUMNS[..]),
+            ],
+            alter,
+        )
+    }
+
+    #[test]
+    fn test_fresh_schema_is_ok() {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.create_schema().unwrap();
+        let report = db.check_schema().unwrap();
+        assert_eq!(report, Default::default());
+        assert!(report.to_result().is_ok());
+    }
+
+    #[test]
+    fn test_missing_column_is_reported() {
+        let conn = create_altered_db(|name, sql_type| {
+            (name != "art_objects.collection").then(|| sql_type.to_string())
+        });
+        let report = check_gallery_db_schema(&conn, "main").unwrap();
+        assert_eq!(
+            report.missing_columns,
+            vec![column("art_objects", "collection")]
+        );
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.to_result().unwrap_err().to_string(),
+            "database schema is missing column art_objects.collection — re-run the importer or Migrate"
+        );
+    }
+
+    #[test]
+    fn test_mistyped_column_is_reported() {
+        let conn = create_altered_db(|name, sql_type| {
+            Some(
+                if name == "art_objects.width" {
+                    "TEXT"
+                } else {
+                    sql_type
+                }
+                .to_string(),
+            )
+        });
+        let report = check_gallery_db_schema(&conn, "main").unwrap();
+        assert_eq!(
+            report.mistyped_columns,
+            vec![MistypedColumn {
+                column: column("art_objects", "width"),
+                expected_type: "REAL".to_string(),
+                actual_type: "TEXT".to_string(),
+            }]
+        );
+        assert_eq!(
+            report.problems(),
+            vec!["database schema has column art_objects.width of type TEXT, expected REAL"]
+        );
+    }
+
+    #[test]
+    fn test_types_are_case_insensitive() {
+        let conn = create_altered_db(|_, sql_type| Some(sql_type.to_lowercase()));
+        assert!(check_gallery_db_schema(&conn, "main").unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_extra_columns_are_reported_but_ok() {
+        let conn = create_altered_db(|_, sql_type| Some(sql_type.to_string()));
+        conn.execute_batch("ALTER TABLE layout ADD COLUMN scale REAL")
+            .unwrap();
+        let report = check_gallery_db_schema(&conn, "main").unwrap();
+        assert_eq!(report.extra_columns, vec![column("layout", "scale")]);
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn test_optional_layout_columns_can_be_missing() {
+        let conn = create_altered_db(|name, sql_type| {
+            (name != "layout.rotated" && name != "layout.modified_at").then(|| sql_type.to_string())
+        });
+        assert_eq!(
+            check_gallery_db_schema(&conn, "main").unwrap(),
+            Default::default()
+        );
+    }
+
+    #[test]
+    fn test_missing_tables_are_reported() {
+        let conn = create_tables(
+            &[("art_objects", &EXPECTED_ART_OBJECTS_COLUMNS[..])],
+            |_, sql_type| Some(sql_type.to_string()),
+        );
+        let report = check_gallery_db_schema(&conn, "main").unwrap();
+        assert_eq!(report.missing_tables, vec!["layout".to_string()]);
+        assert_eq!(
+            report.problems(),
+            vec!["database schema is missing table layout"]
+        );
+    }
+
+    #[test]
+    fn test_layout_table_is_checked_in_attached_layout_db() {
+        let root_dir =
+            std::env::temp_dir().join(format!("gallery-db-schema-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root_dir);
+        std::fs::create_dir_all(&root_dir).unwrap();
+        let mut db = GalleryDb::open(root_dir.join("gallery.sqlite"), false).unwrap();
+        db.reset_art_objects_table().unwrap();
+        assert_eq!(
+            db.check_schema().unwrap().missing_tables,
+            vec!["layout".to_string()]
+        );
+        db.attach_layout_db(root_dir.join("layout.sqlite")).unwrap();
+        assert!(db.check_schema().unwrap().is_ok());
+        drop(db);
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::{
+    art_object::ArtObjectId,
+    gallery_cache::{ensure_parent_dir, GalleryCache},
+    gallery_db::{ArtObjectQueryOptions, ArtObjectRecord, GalleryDb, LayoutRecord},
+    image::ImageSize,
+    met_api::{load_cached_met_api_record, met_api_record_filename},
+    wikidata::{load_cached_wikidata_image_info, wikidata_image_info_filename, WikidataImageInfo},
+};
+
+/// How many art objects to read from the source database at a time.
+const BATCH_SIZE: usize = 1000;
+
+const IMAGE_SIZES: [ImageSize; 2] = [ImageSize::Small, ImageSize::Large];
+
+/// What made it into a database created by `export_subset()`.
+#[derive(Debug)]
+pub struct ExportedSubset {
+    pub art_objects: Vec<ArtObjectRecord>,
+    pub artists: usize,
+    pub layout_records: usize,
+    pub galleries: usize,
+}
+
+/// Creates a fresh schema in `output` and copies the art objects in `db` that
+/// match the given options into it, along with their artists, any layout records
+/// that reference them, and the metadata for any positive galleries they're in.
+///
+/// Note that the game will only use `output` if it has the default gallery DB
+/// filename, see `get_default_gallery_db_filename()`.
+pub fn export_subset(
+    db: &GalleryDb,
+    options: &ArtObjectQueryOptions,
+    output: &mut GalleryDb,
+) -> Result<ExportedSubset> {
+    output.create_schema()?;
+
+    let mut art_objects = vec![];
+    loop {
+        let batch = db.get_art_objects(options, art_objects.len(), BATCH_SIZE)?;
+        if batch.is_empty() {
+            break;
+        }
+        output.add_art_objects(&batch)?;
+        let mut image_urls = vec![];
+        for object in &batch {
+            if let ArtObjectId::Met(object_id) = object.object_id {
+                if let Some(urls) = db.get_met_image_urls(object_id)? {
+                    image_urls.push((object_id, urls));
+                }
+            }
+        }
+        output.set_met_image_urls(&image_urls)?;
+        art_objects.extend(batch);
+    }
+    let ids: HashSet<ArtObjectId> = art_objects.iter().map(|object| object.object_id).collect();
+
+    let artist_qids: BTreeSet<i64> = art_objects
+        .iter()
+        .filter_map(|object| object.artist_qid)
+        .collect();
+    let mut artists = vec![];
+    for qid in artist_qids {
+        if let Some(artist) = db.get_artist(qid)? {
+            artists.push(artist);
+        }
+    }
+    output.add_artists(&artists)?;
+
+    let layout_records: Vec<LayoutRecord<String>> = db
+        .get_all_layout_records()?
+        .into_iter()
+        .filter(|record| ids.contains(&record.art_object_id))
+        .collect();
+    output.upsert_layout_records(&layout_records)?;
+
+    let positive_gallery_ids: BTreeSet<i64> = layout_records
+        .iter()
+        .map(|record| record.gallery_id)
+        .filter(|&gallery_id| gallery_id > 0)
+        .collect();
+    let mut galleries = vec![];
+    for gallery_id in positive_gallery_ids {
+        if let Some(gallery) = db.get_gallery_record(gallery_id)? {
+            galleries.push(gallery);
+        }
+    }
+    output.set_gallery_records_in_positive_galleries(&galleries)?;
+
+    output.rebuild_collection_stats()?;
+
+    Ok(ExportedSubset {
+        art_objects,
+        artists: artists.len(),
+        layout_records: layout_records.len(),
+        galleries: galleries.len(),
+    })
+}
+
+/// Returns the filenames, relative to the cache directory, of every cached file
+/// that the game would use to show the given art object.
+pub fn get_cached_files_for_art_object(
+    cache: &GalleryCache,
+    record: &ArtObjectRecord,
+) -> Result<Vec<String>> {
+    let mut filenames = vec![];
+    let wikidata_info = match record.object_id {
+        ArtObjectId::Met(object_id) => {
+            if let Some(met_record) = load_cached_met_api_record(cache, object_id)? {
+                filenames.push(met_api_record_filename(object_id));
+                for size in IMAGE_SIZES {
+                    filenames.extend(met_record.get_cached_image(cache, size));
+                }
+            }
+            // The game falls back to wikidata if the Met doesn't have an image.
+            match record.fallback_wikidata_qid {
+                Some(qid) => {
+                    let info = load_cached_wikidata_image_info(cache, qid)?;
+                    let info_filename = wikidata_image_info_filename(qid);
+                    if cache.get_if_cached(&info_filename).is_some() {
+                        filenames.push(info_filename);
+                    }
+                    info
+                }
+                None => None,
+            }
+        }
+        ArtObjectId::Wikidata(qid) => Some(WikidataImageInfo {
+            qid,
+            image_filename: record.filename.clone(),
+        }),
+    };
+    if let Some(info) = wikidata_info {
+        for size in IMAGE_SIZES {
+            filenames.extend(info.get_cached_image(cache, size));
+        }
+    }
+    Ok(filenames)
+}
+
+/// Copies the cached files for the given art objects into `dest_dir`, using the
+/// same layout as the cache directory. Returns the number of files copied.
+pub fn copy_cached_files_for_art_objects(
+    cache: &GalleryCache,
+    records: &[ArtObjectRecord],
+    dest_dir: &Path,
+) -> Result<usize> {
+    let dest_cache = GalleryCache::new_offline(dest_dir.to_path_buf());
+    let mut copied = 0;
+    for record in records {
+        for filename in get_cached_files_for_art_object(cache, record)? {
+            let dest_path = dest_cache.get_cached_path(&filename);
+            ensure_parent_dir(&dest_path)?;
+            std::fs::copy(cache.get_cached_path(&filename), &dest_path)?;
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rusqlite::Connection;
+
+    use crate::{
+        art_object::ArtObjectId,
+        gallery_cache::GalleryCache,
+        gallery_db::{
+            ArtObjectQueryOptions, ArtObjectRecord, ArtistRecord, GalleryDb, GalleryRecord,
+            LayoutRecord,
+        },
+        medium::MediumCategory,
+    };
+
+    use super::{copy_cached_files_for_art_objects, export_subset};
+
+    fn make_record(object_id: ArtObjectId, title: &str) -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id,
+            object_date: "1890".into(),
+            culture: "".into(),
+            artist: "Boop Jones".into(),
+            artist_role: String::default(),
+            title: title.into(),
+            medium: "Oil on canvas".into(),
+            medium_category: MediumCategory::Oil,
+            width: 1.0,
+            height: 1.0,
+            fallback_wikidata_qid: None,
+            filename: "".into(),
+            collection: "Martian Museum of Art".into(),
+            artist_qid: None,
+            highlight: false,
+            tags: String::default(),
+            classification: String::default(),
+            source_gallery: None,
+        }
+    }
+
+    fn layout_record(gallery_id: i64, art_object_id: ArtObjectId) -> LayoutRecord<String> {
+        LayoutRecord {
+            gallery_id,
+            wall_id: "wall_a".into(),
+            art_object_id,
+            x: 1.0,
+            y: 1.0,
+            rotated: false,
+        }
+    }
+
+    fn create_db() -> GalleryDb {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.create_schema().unwrap();
+        db.add_art_objects(&vec![
+            ArtObjectRecord {
+                artist_qid: Some(42),
+                ..make_record(ArtObjectId::Met(1), "Impression, Soleil Levant")
+            },
+            make_record(ArtObjectId::Wikidata(2), "Impression of a Monkey"),
+            make_record(ArtObjectId::Met(3), "Boring Painting"),
+        ])
+        .unwrap();
+        db.add_artists(&vec![ArtistRecord {
+            qid: 42,
+            name: "Claude Monet".into(),
+            description: "French painter".into(),
+        }])
+        .unwrap();
+        db.rebuild_collection_stats().unwrap();
+        db.upsert_layout_records(&vec![
+            layout_record(1, ArtObjectId::Met(1)),
+            layout_record(-1, ArtObjectId::Wikidata(2)),
+            layout_record(2, ArtObjectId::Met(3)),
+        ])
+        .unwrap();
+        let gallery = |gallery_id| GalleryRecord {
+            gallery_id,
+            wall_set: "default".into(),
+            reserved_walls: vec![],
+            segment: None,
+            source_gallery: None,
+        };
+        db.set_gallery_records_in_positive_galleries(&vec![gallery(1), gallery(2)])
+            .unwrap();
+        db
+    }
+
+    fn impression_options() -> ArtObjectQueryOptions {
+        ArtObjectQueryOptions {
+            filter: Some("impression".into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_export_subset_works() {
+        let db = create_db();
+        let mut output = GalleryDb::new(Connection::open_in_memory().unwrap());
+        let exported = export_subset(&db, &impression_options(), &mut output).unwrap();
+        assert_eq!(exported.art_objects.len(), 2);
+        assert_eq!(exported.artists, 1);
+        assert_eq!(exported.layout_records, 2);
+        assert_eq!(exported.galleries, 1);
+
+        assert_eq!(
+            output
+                .count_art_objects(&ArtObjectQueryOptions::default())
+                .unwrap(),
+            2
+        );
+        assert!(output
+            .get_art_object(ArtObjectId::Met(1))
+            .unwrap()
+            .is_some());
+        assert!(output
+            .get_art_object(ArtObjectId::Wikidata(2))
+            .unwrap()
+            .is_some());
+        assert_eq!(output.get_art_object(ArtObjectId::Met(3)).unwrap(), None);
+        assert!(output.get_artist(42).unwrap().is_some());
+
+        let mut layout_ids: Vec<ArtObjectId> = output
+            .get_all_layout_records()
+            .unwrap()
+            .into_iter()
+            .map(|record| record.art_object_id)
+            .collect();
+        layout_ids.sort_by_key(|id| id.to_raw_i64());
+        let mut expected_ids = vec![ArtObjectId::Met(1), ArtObjectId::Wikidata(2)];
+        expected_ids.sort_by_key(|id| id.to_raw_i64());
+        assert_eq!(layout_ids, expected_ids);
+        assert!(output.get_gallery_record(1).unwrap().is_some());
+        assert_eq!(output.get_gallery_record(2).unwrap(), None);
+
+        let collections = output.list_collections().unwrap();
+        assert_eq!(collections.len(), 1);
+        assert_eq!(collections[0].object_count, 2);
+    }
+
+    #[test]
+    fn test_copy_cached_files_for_art_objects_works() {
+        let root_dir: PathBuf =
+            std::env::temp_dir().join(format!("gallery-db-subset-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root_dir);
+        let cache = GalleryCache::new_offline(root_dir.join("cache"));
+        let dest_dir = root_dir.join("dest");
+        let write_cached = |filename: &str, contents: &str| {
+            let path = cache.get_cached_path(filename);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write_cached(
+            "met-api/object-1.json",
+            r#"{
+                "primaryImageSmall": "https://example.com/small.jpg",
+                "primaryImage": "https://example.com/large.jpg",
+                "objectDate": "1872",
+                "objectID": 1,
+                "title": "Impression, Soleil Levant"
+            }"#,
+        );
+        write_cached("met-api/object-1-small.jpg", "small");
+        write_cached("wikidata/Q2-small-500px.jpg", "monkey");
+        write_cached("met-api/object-3-small.jpg", "boring");
+
+        let records = vec![
+            make_record(ArtObjectId::Met(1), "Impression, Soleil Levant"),
+            ArtObjectRecord {
+                filename: "Monkey.jpg".into(),
+                ..make_record(ArtObjectId::Wikidata(2), "Impression of a Monkey")
+            },
+        ];
+        assert_eq!(
+            copy_cached_files_for_art_objects(&cache, &records, &dest_dir).unwrap(),
+            3
+        );
+        let dest_cache = GalleryCache::new_offline(dest_dir);
+        for filename in [
+            "met-api/object-1.json",
+            "met-api/object-1-small.jpg",
+            "wikidata/Q2-small-500px.jpg",
+        ] {
+            assert_eq!(
+                std::fs::read(dest_cache.get_cached_path(filename)).unwrap(),
+                std::fs::read(cache.get_cached_path(filename)).unwrap(),
+                "{filename}"
+            );
+        }
+        assert_eq!(dest_cache.get_if_cached("met-api/object-3-small.jpg"), None);
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+}
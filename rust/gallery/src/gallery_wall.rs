@@ -1,8 +1,638 @@
-use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+};
 
-#[derive(Debug, Deserialize)]
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::gallery_db::LayoutRecord;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GalleryWall {
     pub width: f64,
     pub height: f64,
     pub name: String,
+    /// A number that layout records can use to refer to the wall as `#<index>`
+    /// instead of by name, which is harder to get wrong when editing layouts by
+    /// hand. Walls without one are numbered by their position in their wall set,
+    /// starting at 0. It isn't serialized when absent, so that it doesn't change
+    /// the hashes of existing walls, see `hash_wall_sets()`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+}
+
+/// Walls shorter than this, in meters, are probably a typo, e.g. centimeters
+/// instead of meters.
+pub const MIN_WALL_HEIGHT: f64 = 0.5;
+
+/// Walls taller than this, in meters, are probably a typo too.
+pub const MAX_WALL_HEIGHT: f64 = 20.0;
+
+/// Something wrong with a list of walls, see `validate_walls()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WallValidationError {
+    NoWalls,
+    /// The wall at the given zero-based position has an empty name.
+    EmptyName {
+        position: usize,
+    },
+    NonPositiveWidth {
+        name: String,
+        width: f64,
+    },
+    NonPositiveHeight {
+        name: String,
+        height: f64,
+    },
+    /// See `MIN_WALL_HEIGHT` and `MAX_WALL_HEIGHT`.
+    HeightOutOfRange {
+        name: String,
+        height: f64,
+    },
+    DuplicateName {
+        name: String,
+    },
+}
+
+impl Display for WallValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WallValidationError::NoWalls => write!(f, "there are no walls"),
+            WallValidationError::EmptyName { position } => {
+                write!(f, "wall #{position} has no name")
+            }
+            WallValidationError::NonPositiveWidth { name, width } => {
+                write!(f, "wall {name:?} has a width of {width}, which isn't positive")
+            }
+            WallValidationError::NonPositiveHeight { name, height } => {
+                write!(f, "wall {name:?} has a height of {height}, which isn't positive")
+            }
+            WallValidationError::HeightOutOfRange { name, height } => write!(
+                f,
+                "wall {name:?} has a height of {height}, which isn't between {MIN_WALL_HEIGHT} and {MAX_WALL_HEIGHT} meters"
+            ),
+            WallValidationError::DuplicateName { name } => {
+                write!(f, "more than one wall is named {name:?}")
+            }
+        }
+    }
+}
+
+/// Unlike `value <= 0.0`, this treats NaN as not positive.
+fn is_positive(value: f64) -> bool {
+    value > 0.0
+}
+
+/// Checks the given walls, e.g. from a hand-edited walls JSON file, for
+/// mistakes that would make `layout()` produce bizarre results, returning every
+/// mistake rather than just the first.
+pub fn validate_walls(walls: &[GalleryWall]) -> Result<(), Vec<WallValidationError>> {
+    let mut errors = vec![];
+    if walls.is_empty() {
+        errors.push(WallValidationError::NoWalls);
+    }
+    let mut names = HashSet::new();
+    let mut duplicate_names = HashSet::new();
+    for (position, wall) in walls.iter().enumerate() {
+        let name = &wall.name;
+        if name.is_empty() {
+            errors.push(WallValidationError::EmptyName { position });
+        } else if !names.insert(name) && duplicate_names.insert(name) {
+            errors.push(WallValidationError::DuplicateName { name: name.clone() });
+        }
+        if !is_positive(wall.width) {
+            errors.push(WallValidationError::NonPositiveWidth {
+                name: name.clone(),
+                width: wall.width,
+            });
+        }
+        if !is_positive(wall.height) {
+            errors.push(WallValidationError::NonPositiveHeight {
+                name: name.clone(),
+                height: wall.height,
+            });
+        } else if !(MIN_WALL_HEIGHT..=MAX_WALL_HEIGHT).contains(&wall.height) {
+            errors.push(WallValidationError::HeightOutOfRange {
+                name: name.clone(),
+                height: wall.height,
+            });
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Like `validate_walls()`, but returns a single error listing every mistake,
+/// mentioning where the walls came from, e.g. their filename.
+pub fn check_walls(source: &str, walls: &[GalleryWall]) -> Result<()> {
+    validate_walls(walls).map_err(|errors| {
+        let lines: Vec<String> = errors.iter().map(|error| format!("  {error}")).collect();
+        anyhow!("Invalid walls in {source}:\n{}", lines.join("\n"))
+    })
+}
+
+/// Like `check_walls()`, but for every wall set at once, using their names as
+/// their sources, so that the mistakes in all of them are reported together.
+pub fn check_wall_sets(wall_sets: &[GalleryWallSet]) -> Result<()> {
+    let messages: Vec<String> = wall_sets
+        .iter()
+        .filter_map(|wall_set| check_walls(&wall_set.name, &wall_set.walls).err())
+        .map(|err| err.to_string())
+        .collect();
+    if messages.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("{}", messages.join("\n")))
+    }
+}
+
+/// How wide the doorway of a room made by `generate_room_walls()` is, in meters.
+pub const ROOM_DOOR_WIDTH: f64 = 2.0;
+
+/// Returns the walls of a rectangular room, e.g. to bootstrap the walls JSON of
+/// a new room shape. The walls go clockwise from the back wall, which is
+/// `width` wide, and are named `wall_01`, `wall_02` and so on.
+///
+/// If there's a door, it's in the middle of the wall at the given zero-based
+/// position (e.g. 2 for the front wall), which is split into a wall on either
+/// side of it.
+pub fn generate_room_walls(
+    width: f64,
+    depth: f64,
+    height: f64,
+    door_wall: Option<usize>,
+) -> Result<Vec<GalleryWall>> {
+    if let Some(door_wall) = door_wall.filter(|&position| position > 3) {
+        return Err(anyhow!(
+            "A rectangular room has walls #0 to #3, not #{door_wall}"
+        ));
+    }
+    let mut widths = vec![];
+    for (position, wall_width) in [width, depth, width, depth].into_iter().enumerate() {
+        if door_wall == Some(position) {
+            let side_width = (wall_width - ROOM_DOOR_WIDTH) / 2.0;
+            if !is_positive(side_width) {
+                return Err(anyhow!(
+                    "Wall #{position} is too narrow for a {ROOM_DOOR_WIDTH} meter door"
+                ));
+            }
+            widths.extend([side_width, side_width]);
+        } else {
+            widths.push(wall_width);
+        }
+    }
+    let walls: Vec<GalleryWall> = widths
+        .into_iter()
+        .enumerate()
+        .map(|(position, width)| GalleryWall {
+            width,
+            height,
+            name: format!("wall_{:02}", position + 1),
+            index: None,
+        })
+        .collect();
+    check_walls("generated room", &walls)?;
+    Ok(walls)
+}
+
+/// Returns the name of the wall that the given wall ID refers to, which can
+/// either be the wall's name or `#<index>`, see `GalleryWall::index`.
+pub fn resolve_wall_id<'a>(walls: &'a [GalleryWall], wall_id: &str) -> Option<&'a str> {
+    if let Some(wall) = walls.iter().find(|wall| wall.name == wall_id) {
+        return Some(&wall.name);
+    }
+    let index: usize = wall_id.strip_prefix('#')?.parse().ok()?;
+    walls
+        .iter()
+        .enumerate()
+        .find(|(position, wall)| wall.index.unwrap_or(*position) == index)
+        .map(|(_, wall)| wall.name.as_str())
+}
+
+/// Like `resolve_wall_id()`, but tries each of the given wall sets in order.
+pub fn resolve_wall_id_in_wall_sets<'a>(
+    wall_sets: &'a [GalleryWallSet],
+    wall_id: &str,
+) -> Option<&'a str> {
+    wall_sets
+        .iter()
+        .find_map(|wall_set| resolve_wall_id(&wall_set.walls, wall_id))
+}
+
+/// What `resolve_layout_wall_ids()` found.
+#[derive(Debug, Default, PartialEq)]
+pub struct ResolvedLayoutRecords {
+    /// The records whose walls were found, with their wall IDs replaced by the
+    /// names of their walls.
+    pub records: Vec<LayoutRecord<String>>,
+    /// The wall IDs that didn't refer to any walls, along with how many records
+    /// referred to each one.
+    pub unknown_wall_ids: BTreeMap<String, usize>,
+}
+
+impl ResolvedLayoutRecords {
+    /// The number of records that were left out because their walls weren't found.
+    pub fn skipped(&self) -> usize {
+        self.unknown_wall_ids.values().sum()
+    }
+}
+
+/// Resolves the wall IDs of the given layout records, e.g. from a hand-edited
+/// layout file, against the walls of the given wall sets, leaving out any
+/// records whose walls don't exist, so typos don't silently put art objects on
+/// walls that nobody will ever see.
+///
+/// If there aren't any wall sets, e.g. because the game hasn't told us about
+/// its walls yet, wall names can't be checked, so they're all kept as-is, but
+/// `#<index>` IDs are left out since there's nothing to resolve them with.
+pub fn resolve_layout_wall_ids(
+    records: Vec<LayoutRecord<String>>,
+    wall_sets: &[GalleryWallSet],
+) -> ResolvedLayoutRecords {
+    let mut result = ResolvedLayoutRecords::default();
+    for mut record in records {
+        let wall_name = if wall_sets.is_empty() {
+            (!record.wall_id.starts_with('#')).then(|| record.wall_id.clone())
+        } else {
+            resolve_wall_id_in_wall_sets(wall_sets, &record.wall_id).map(str::to_string)
+        };
+        match wall_name {
+            Some(wall_name) => {
+                record.wall_id = wall_name;
+                result.records.push(record);
+            }
+            None => *result.unknown_wall_ids.entry(record.wall_id).or_default() += 1,
+        }
+    }
+    result
+}
+
+/// The name given to the wall set when we're only given a plain list of walls.
+pub const DEFAULT_WALL_SET_NAME: &str = "default";
+
+fn default_weight() -> usize {
+    1
+}
+
+/// A named set of walls that make up a single gallery (room).
+///
+/// Different wall sets correspond to different room scenes in the game, which
+/// allows galleries to vary in shape.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GalleryWallSet {
+    pub name: String,
+    pub walls: Vec<GalleryWall>,
+
+    /// How many consecutive galleries should use this wall set before moving on
+    /// to the next one.
+    #[serde(default = "default_weight")]
+    pub weight: usize,
+}
+
+impl GalleryWallSet {
+    pub fn new<T: AsRef<str>>(name: T, walls: Vec<GalleryWall>) -> Self {
+        GalleryWallSet {
+            name: name.as_ref().to_string(),
+            walls,
+            weight: default_weight(),
+        }
+    }
+}
+
+/// Returns a hash of the given wall sets, so we can tell whether they've changed
+/// since e.g. the last layout.
+pub fn hash_wall_sets(wall_sets: &[GalleryWallSet]) -> Result<String> {
+    let json = serde_json::to_string(wall_sets)?;
+    Ok(format!("{:x}", md5::compute(json.as_bytes())))
+}
+
+/// Given a list of wall sets, returns the one that the gallery at the given
+/// zero-based index should use.
+///
+/// Wall sets are cycled through in order, with each one being used for
+/// `weight` consecutive galleries.
+pub fn wall_set_for_gallery_index(
+    wall_sets: &Vec<GalleryWallSet>,
+    gallery_index: usize,
+) -> Result<&GalleryWallSet> {
+    let total_weight: usize = wall_sets.iter().map(|wall_set| wall_set.weight).sum();
+    if total_weight == 0 {
+        return Err(anyhow!("At least one wall set must have a positive weight"));
+    }
+    let mut position = gallery_index % total_weight;
+    for wall_set in wall_sets {
+        if position < wall_set.weight {
+            return Ok(wall_set);
+        }
+        position -= wall_set.weight;
+    }
+    unreachable!("position should always be less than total weight")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{art_object::ArtObjectId, gallery_db::LayoutRecord};
+
+    use super::{
+        check_wall_sets, check_walls, generate_room_walls, hash_wall_sets, resolve_layout_wall_ids,
+        resolve_wall_id, validate_walls, wall_set_for_gallery_index, GalleryWall, GalleryWallSet,
+        WallValidationError, ROOM_DOOR_WIDTH,
+    };
+
+    fn make_wall(name: &str, index: Option<usize>) -> GalleryWall {
+        GalleryWall {
+            name: name.into(),
+            width: 5.0,
+            height: 3.0,
+            index,
+        }
+    }
+
+    fn make_record(wall_id: &str) -> LayoutRecord<String> {
+        LayoutRecord {
+            gallery_id: -1,
+            wall_id: wall_id.into(),
+            art_object_id: ArtObjectId::Met(1),
+            x: 1.0,
+            y: 1.0,
+            rotated: false,
+        }
+    }
+
+    #[test]
+    fn test_resolve_wall_id_works() {
+        let walls = vec![make_wall("wall_01", None), make_wall("wall_02", None)];
+        assert_eq!(resolve_wall_id(&walls, "wall_02"), Some("wall_02"));
+        assert_eq!(resolve_wall_id(&walls, "#0"), Some("wall_01"));
+        assert_eq!(resolve_wall_id(&walls, "#1"), Some("wall_02"));
+        assert_eq!(resolve_wall_id(&walls, "#2"), None);
+        assert_eq!(resolve_wall_id(&walls, "#boop"), None);
+        assert_eq!(resolve_wall_id(&walls, "wall_O2"), None);
+
+        let walls = vec![make_wall("wall_01", Some(7)), make_wall("wall_02", Some(3))];
+        assert_eq!(resolve_wall_id(&walls, "#7"), Some("wall_01"));
+        assert_eq!(resolve_wall_id(&walls, "#3"), Some("wall_02"));
+        assert_eq!(resolve_wall_id(&walls, "#0"), None);
+    }
+
+    #[test]
+    fn test_resolve_layout_wall_ids_skips_typos() {
+        let wall_sets = vec![GalleryWallSet::new(
+            "boop",
+            vec![make_wall("wall_01", None), make_wall("wall_02", None)],
+        )];
+        let resolved = resolve_layout_wall_ids(
+            vec![
+                make_record("wall_01"),
+                make_record("#1"),
+                make_record("wall_O2"),
+                make_record("wall_O2"),
+                make_record("#5"),
+            ],
+            &wall_sets,
+        );
+        assert_eq!(
+            resolved.records,
+            vec![make_record("wall_01"), make_record("wall_02")]
+        );
+        assert_eq!(
+            resolved.unknown_wall_ids,
+            BTreeMap::from([("#5".to_string(), 1), ("wall_O2".to_string(), 2)])
+        );
+        assert_eq!(resolved.skipped(), 3);
+
+        // Without any walls, only names can be kept.
+        let resolved =
+            resolve_layout_wall_ids(vec![make_record("wall_O2"), make_record("#1")], &[]);
+        assert_eq!(resolved.records, vec![make_record("wall_O2")]);
+        assert_eq!(resolved.skipped(), 1);
+    }
+
+    #[test]
+    fn test_missing_wall_indices_are_not_serialized() {
+        let wall_sets = vec![GalleryWallSet::new(
+            "boop",
+            vec![make_wall("wall_01", None)],
+        )];
+        let json = serde_json::to_string(&wall_sets).unwrap();
+        assert!(!json.contains("index"), "{json}");
+        assert_ne!(
+            hash_wall_sets(&wall_sets).unwrap(),
+            hash_wall_sets(&vec![GalleryWallSet::new(
+                "boop",
+                vec![make_wall("wall_01", Some(0))]
+            )])
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_wall_set_for_gallery_index_works() {
+        let mut big = GalleryWallSet::new("big", vec![]);
+        big.weight = 2;
+        let small = GalleryWallSet::new("small", vec![]);
+        let wall_sets = vec![big, small];
+        let names: Vec<&str> = (0..6)
+            .map(|i| {
+                wall_set_for_gallery_index(&wall_sets, i)
+                    .unwrap()
+                    .name
+                    .as_str()
+            })
+            .collect();
+        assert_eq!(names, vec!["big", "big", "small", "big", "big", "small"]);
+    }
+
+    #[test]
+    fn test_wall_set_for_gallery_index_rejects_zero_weight() {
+        let mut wall_set = GalleryWallSet::new("boop", vec![]);
+        wall_set.weight = 0;
+        assert!(wall_set_for_gallery_index(&vec![wall_set], 0).is_err());
+    }
+
+    #[test]
+    fn test_hash_wall_sets_works() {
+        let make_wall_sets = |width: f64| {
+            vec![GalleryWallSet::new(
+                "boop",
+                vec![GalleryWall {
+                    name: "wall_01".into(),
+                    width,
+                    height: 3.0,
+                    index: None,
+                }],
+            )]
+        };
+        let hash = hash_wall_sets(&make_wall_sets(4.0)).unwrap();
+        assert_eq!(hash, hash_wall_sets(&make_wall_sets(4.0)).unwrap());
+        assert_ne!(hash, hash_wall_sets(&make_wall_sets(4.2)).unwrap());
+    }
+
+    #[test]
+    fn test_validate_walls_accepts_good_walls() {
+        let walls = vec![make_wall("wall_01", None), make_wall("wall_02", None)];
+        assert_eq!(validate_walls(&walls), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_walls_rejects_no_walls() {
+        assert_eq!(validate_walls(&[]), Err(vec![WallValidationError::NoWalls]));
+    }
+
+    #[test]
+    fn test_validate_walls_rejects_non_positive_dimensions() {
+        let walls = vec![
+            GalleryWall {
+                width: -1.0,
+                ..make_wall("wall_01", None)
+            },
+            GalleryWall {
+                height: 0.0,
+                ..make_wall("wall_02", None)
+            },
+            GalleryWall {
+                width: f64::NAN,
+                ..make_wall("wall_03", None)
+            },
+        ];
+        let errors = validate_walls(&walls).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0],
+            WallValidationError::NonPositiveWidth {
+                name: "wall_01".into(),
+                width: -1.0
+            }
+        );
+        assert_eq!(
+            errors[1],
+            WallValidationError::NonPositiveHeight {
+                name: "wall_02".into(),
+                height: 0.0
+            }
+        );
+        assert!(matches!(
+            &errors[2],
+            WallValidationError::NonPositiveWidth { name, .. } if name == "wall_03"
+        ));
+    }
+
+    #[test]
+    fn test_validate_walls_rejects_implausible_heights() {
+        let walls = vec![
+            GalleryWall {
+                height: 300.0,
+                ..make_wall("wall_01", None)
+            },
+            GalleryWall {
+                height: 0.03,
+                ..make_wall("wall_02", None)
+            },
+        ];
+        assert_eq!(
+            validate_walls(&walls),
+            Err(vec![
+                WallValidationError::HeightOutOfRange {
+                    name: "wall_01".into(),
+                    height: 300.0
+                },
+                WallValidationError::HeightOutOfRange {
+                    name: "wall_02".into(),
+                    height: 0.03
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_walls_rejects_bad_names() {
+        let walls = vec![
+            make_wall("wall_01", None),
+            make_wall("", None),
+            make_wall("wall_01", None),
+            make_wall("wall_01", None),
+        ];
+        assert_eq!(
+            validate_walls(&walls),
+            Err(vec![
+                WallValidationError::EmptyName { position: 1 },
+                WallValidationError::DuplicateName {
+                    name: "wall_01".into()
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_check_walls_reports_every_error() {
+        let walls = vec![
+            GalleryWall {
+                width: 0.0,
+                ..make_wall("wall_01", None)
+            },
+            make_wall("wall_01", None),
+        ];
+        let message = check_walls("boop.walls.json", &walls)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("boop.walls.json"), "{message}");
+        assert!(message.contains("isn't positive"), "{message}");
+        assert!(message.contains("more than one wall"), "{message}");
+    }
+
+    #[test]
+    fn test_check_wall_sets_reports_every_wall_set() {
+        let bad_walls = |name: &str| {
+            GalleryWallSet::new(
+                name,
+                vec![GalleryWall {
+                    height: 0.0,
+                    ..make_wall("wall_01", None)
+                }],
+            )
+        };
+        let wall_sets = vec![
+            bad_walls("small"),
+            GalleryWallSet::new("medium", vec![make_wall("wall_01", None)]),
+            bad_walls("large"),
+        ];
+        let message = check_wall_sets(&wall_sets).unwrap_err().to_string();
+        assert!(message.contains("Invalid walls in small:"), "{message}");
+        assert!(!message.contains("medium"), "{message}");
+        assert!(message.contains("Invalid walls in large:"), "{message}");
+        assert!(check_wall_sets(&wall_sets[1..2]).is_ok());
+    }
+
+    #[test]
+    fn test_generate_room_walls_works() {
+        let walls = generate_room_walls(8.0, 6.0, 4.0, None).unwrap();
+        let widths: Vec<f64> = walls.iter().map(|wall| wall.width).collect();
+        assert_eq!(widths, vec![8.0, 6.0, 8.0, 6.0]);
+        assert_eq!(walls[3].name, "wall_04");
+
+        let walls = generate_room_walls(8.0, 6.0, 4.0, Some(2)).unwrap();
+        let side_width = (8.0 - ROOM_DOOR_WIDTH) / 2.0;
+        let widths: Vec<f64> = walls.iter().map(|wall| wall.width).collect();
+        assert_eq!(widths, vec![8.0, 6.0, side_width, side_width, 6.0]);
+        assert!(walls.iter().all(|wall| wall.height == 4.0));
+
+        // The generated JSON should be just as valid once it's read back in.
+        let json = serde_json::to_string_pretty(&walls).unwrap();
+        let walls: Vec<GalleryWall> = serde_json::from_str(&json).unwrap();
+        assert_eq!(validate_walls(&walls), Ok(()));
+    }
+
+    #[test]
+    fn test_generate_room_walls_rejects_bad_rooms() {
+        assert!(generate_room_walls(8.0, 1.5, 4.0, Some(1)).is_err());
+        assert!(generate_room_walls(8.0, 6.0, 4.0, Some(4)).is_err());
+        assert!(generate_room_walls(8.0, 6.0, 100.0, None).is_err());
+        assert!(generate_room_walls(0.0, 6.0, 4.0, None).is_err());
+    }
 }
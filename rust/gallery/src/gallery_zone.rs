@@ -0,0 +1,95 @@
+use std::ops::RangeInclusive;
+
+/// The gallery the lobby is in.
+pub const LOBBY_GALLERY_ID: i64 = 0;
+
+/// The gallery the player's stash is in, i.e. the first gallery of their
+/// private collection.
+pub const STASH_GALLERY_ID: i64 = -1;
+
+/// What a layout record's `gallery_id` means.
+///
+/// Generated galleries are filled by `layout()`, and everything in them is
+/// replaced whenever the art is laid out again. Every other zone belongs to the
+/// player: it's what's autosynced, exported and imported (what the rest of the
+/// code calls the "non-positive" galleries), and it survives new layouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GalleryZone {
+    /// Galleries 1 and up, i.e. the temporary exhibition.
+    Generated,
+    /// Gallery 0.
+    Lobby,
+    /// Gallery -1.
+    Stash,
+    /// Any other gallery of the player's private collection, i.e. -2 and below.
+    /// Use `from_gallery_id()` rather than constructing this directly, since
+    /// e.g. `Custom(-1)` isn't the stash.
+    Custom(i64),
+}
+
+impl GalleryZone {
+    /// The gallery IDs of every zone but `Generated`.
+    pub const PLAYER_GALLERY_IDS: RangeInclusive<i64> = i64::MIN..=LOBBY_GALLERY_ID;
+
+    pub fn from_gallery_id(gallery_id: i64) -> Self {
+        match gallery_id {
+            LOBBY_GALLERY_ID => GalleryZone::Lobby,
+            STASH_GALLERY_ID => GalleryZone::Stash,
+            id if id > LOBBY_GALLERY_ID => GalleryZone::Generated,
+            id => GalleryZone::Custom(id),
+        }
+    }
+
+    /// Returns the gallery IDs that are in this zone.
+    pub fn gallery_ids(&self) -> RangeInclusive<i64> {
+        match self {
+            GalleryZone::Generated => (LOBBY_GALLERY_ID + 1)..=i64::MAX,
+            GalleryZone::Lobby => LOBBY_GALLERY_ID..=LOBBY_GALLERY_ID,
+            GalleryZone::Stash => STASH_GALLERY_ID..=STASH_GALLERY_ID,
+            GalleryZone::Custom(id) => *id..=*id,
+        }
+    }
+
+    /// Whether the zone belongs to the player, rather than being replaced by
+    /// the next layout.
+    pub fn is_player_owned(&self) -> bool {
+        *self != GalleryZone::Generated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GalleryZone;
+
+    #[test]
+    fn test_boundary_gallery_ids_work() {
+        assert_eq!(GalleryZone::from_gallery_id(1), GalleryZone::Generated);
+        assert_eq!(GalleryZone::from_gallery_id(0), GalleryZone::Lobby);
+        assert_eq!(GalleryZone::from_gallery_id(-1), GalleryZone::Stash);
+        assert_eq!(GalleryZone::from_gallery_id(-2), GalleryZone::Custom(-2));
+        assert_eq!(
+            GalleryZone::from_gallery_id(i64::MAX),
+            GalleryZone::Generated
+        );
+        assert_eq!(
+            GalleryZone::from_gallery_id(i64::MIN),
+            GalleryZone::Custom(i64::MIN)
+        );
+    }
+
+    #[test]
+    fn test_gallery_ids_round_trip() {
+        for gallery_id in [i64::MIN, -5, -2, -1, 0, 1, 2, i64::MAX] {
+            let zone = GalleryZone::from_gallery_id(gallery_id);
+            assert!(zone.gallery_ids().contains(&gallery_id), "{gallery_id}");
+            assert_eq!(
+                zone.is_player_owned(),
+                GalleryZone::PLAYER_GALLERY_IDS.contains(&gallery_id),
+                "{gallery_id}"
+            );
+        }
+        assert_eq!(*GalleryZone::Generated.gallery_ids().start(), 1);
+        assert_eq!(GalleryZone::Lobby.gallery_ids(), 0..=0);
+        assert_eq!(GalleryZone::Stash.gallery_ids(), -1..=-1);
+    }
+}
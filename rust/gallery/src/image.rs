@@ -1,11 +1,12 @@
-use std::{fmt::Display, path::PathBuf};
+use std::{fmt::Display, io::Write, path::PathBuf, str::FromStr};
 
-use crate::gallery_cache::{CacheResult, GalleryCache};
-use anyhow::Result;
-use image::{codecs::jpeg::JpegEncoder, ColorType, ImageReader};
+use crate::gallery_cache::{write_atomically, CacheResult, GalleryCache};
+use anyhow::{anyhow, Result};
+use image::{codecs::jpeg::JpegEncoder, ColorType, DynamicImage, ImageReader};
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize, Copy, Clone)]
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ImageSize {
     Small,
     Large,
@@ -20,14 +21,61 @@ impl Display for ImageSize {
     }
 }
 
-const JPG_EXT: &'static str = ".jpg";
+/// Images with more pixels than this aren't worth decoding ahead of time,
+/// since e.g. a 16 megapixel image takes up 48 MB as RGB8.
+pub const MAX_DECODED_IMAGE_PIXELS: u64 = 16_000_000;
 
-const JPEG_EXT: &'static str = ".jpeg";
+/// An image whose pixels have been decoded into the same layout as Godot's
+/// `Image.FORMAT_RGB8`: three bytes per pixel, row by row, with no padding.
+#[derive(Debug, PartialEq)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgb8: Vec<u8>,
+}
+
+impl DecodedImage {
+    /// Returns whether there are exactly as many bytes as the image's
+    /// dimensions require.
+    pub fn is_valid(&self) -> bool {
+        self.rgb8.len() as u64 == self.width as u64 * self.height as u64 * 3
+    }
+}
+
+impl From<DynamicImage> for DecodedImage {
+    fn from(image: DynamicImage) -> Self {
+        let rgb8 = image.into_rgb8();
+        DecodedImage {
+            width: rgb8.width(),
+            height: rgb8.height(),
+            rgb8: rgb8.into_raw(),
+        }
+    }
+}
+
+/// Decodes the given image into RGB8 pixels, or returns `None` if it has more
+/// than `max_pixels` pixels. Only the image's header is read in the latter case.
+pub fn decode_image_as_rgb8(filename: &PathBuf, max_pixels: u64) -> Result<Option<DecodedImage>> {
+    let (width, height) = image::image_dimensions(filename)?;
+    if width as u64 * height as u64 > max_pixels {
+        return Ok(None);
+    }
+    let img = ImageReader::open(filename)?.decode()?;
+    Ok(Some(DecodedImage::from(img)))
+}
+
+const JPG_EXT: &str = ".jpg";
+
+const JPEG_EXT: &str = ".jpeg";
+
+const PNG_EXT: &str = ".png";
+
+const WEBP_EXT: &str = ".webp";
 
 /// We only care about the ones Godot can import right now:
 ///
 /// https://docs.godotengine.org/en/stable/tutorials/assets_pipeline/importing_images.html#supported-image-formats
-const SUPPORTED_LOWERCASE_IMAGE_FORMATS: [&'static str; 4] = [JPG_EXT, JPEG_EXT, ".webp", ".png"];
+const SUPPORTED_LOWERCASE_IMAGE_FORMATS: [&str; 4] = [JPG_EXT, JPEG_EXT, WEBP_EXT, PNG_EXT];
 
 fn is_jpeg(ext: &'static str) -> bool {
     return ext == JPG_EXT || ext == JPEG_EXT;
@@ -46,40 +94,801 @@ pub fn get_supported_image_ext(filename: &str) -> Option<&'static str> {
     None
 }
 
+/// A format that downloaded images can be re-encoded to, to make the cache
+/// smaller, see `ImageCacheOptions`.
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Webp,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 1] = [ImageFormat::Webp];
+
+    /// The lowercase file extension, including the leading period.
+    pub fn ext(&self) -> &'static str {
+        match self {
+            ImageFormat::Webp => WEBP_EXT,
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "webp" => Ok(ImageFormat::Webp),
+            _ => Err(anyhow!("Unsupported image format: {s:?}")),
+        }
+    }
+}
+
+/// The quality (0 to 100) that images are re-encoded as lossy WebP at. At the
+/// distance paintings are viewed from in the gallery, this is indistinguishable
+/// from the original, while being considerably smaller than a typical JPEG.
+pub const WEBP_QUALITY: f32 = 82.0;
+
+/// How `cache_image()` stores the images it downloads.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ImageCacheOptions {
+    /// If set, JPEGs and PNGs are re-encoded to this format after they're
+    /// downloaded.
+    pub preferred_format: Option<ImageFormat>,
+    /// Whether to keep the original image around after it's been re-encoded.
+    /// Otherwise only the re-encoded one is kept.
+    pub keep_original: bool,
+}
+
+/// Returns the filename that the given cached image is kept under once it's
+/// been re-encoded to the given format, or `None` if it doesn't need to be.
+fn transcoded_image_filename(
+    image_filename: &str,
+    ext: &'static str,
+    format: ImageFormat,
+) -> Option<String> {
+    if ext == format.ext() || !(is_jpeg(ext) || ext == PNG_EXT) {
+        return None;
+    }
+    let stem = image_filename.strip_suffix(ext)?;
+    Some(format!("{stem}{}", format.ext()))
+}
+
+/// Re-encodes the image at `from` to the given format, writing it to `to`.
+///
+/// Note that the `image` crate can only encode lossless WebP, which is bigger
+/// than the JPEGs we'd be replacing, so we use libwebp for that instead.
+pub fn transcode_image(from: &PathBuf, to: &PathBuf, format: ImageFormat) -> Result<()> {
+    let img = ImageReader::open(from)?.with_guessed_format()?.decode()?;
+    let encoded = match format {
+        ImageFormat::Webp => {
+            if img.color().has_alpha() {
+                let rgba8 = img.into_rgba8();
+                webp::Encoder::from_rgba(&rgba8, rgba8.width(), rgba8.height()).encode(WEBP_QUALITY)
+            } else {
+                let rgb8 = img.into_rgb8();
+                webp::Encoder::from_rgb(&rgb8, rgb8.width(), rgb8.height()).encode(WEBP_QUALITY)
+            }
+        }
+    };
+    // Otherwise an interrupted write would leave a truncated image that we'd
+    // think was already transcoded.
+    write_atomically(to, |outfile| {
+        outfile.write_all(&encoded)?;
+        Ok(())
+    })
+}
+
+/// Returns the filename of a re-encoded version of the given image, if one has
+/// already been cached.
+fn get_cached_transcoded_image(
+    cache: &GalleryCache,
+    image_filename: &str,
+    ext: &'static str,
+) -> Option<String> {
+    ImageFormat::ALL
+        .iter()
+        .filter_map(|format| transcoded_image_filename(image_filename, ext, *format))
+        .find(|filename| cache.get_if_cached(filename).is_some())
+}
+
+/// Downloads the image at the given URL into the cache under the given filename,
+/// unless it's already there, and returns the filename it can be loaded from.
+///
+/// If `options` prefers a different format, that's the filename of the
+/// re-encoded image instead, which is only ever re-encoded once. Images that
+/// have already been re-encoded are used as-is regardless of `options`, since
+/// their originals may not have been kept.
 pub fn cache_image(
     cache: &GalleryCache,
     image_url: &str,
     image_filename: &str,
     ext: &'static str,
-) -> Result<()> {
+    options: &ImageCacheOptions,
+) -> Result<String> {
+    if let Some(transcoded_filename) = get_cached_transcoded_image(cache, image_filename, ext) {
+        return Ok(transcoded_filename);
+    }
+    let transcoded = options.preferred_format.and_then(|format| {
+        transcoded_image_filename(image_filename, ext, format).map(|filename| (format, filename))
+    });
+    let full_path = cache.get_cached_path(image_filename);
     if cache.cache_binary_url(&image_url, &image_filename)? == CacheResult::NewlyCached {
-        let full_path = cache.get_cached_path(image_filename);
         maybe_convert_image_for_loading_in_godot(&full_path, ext)?;
     }
-    Ok(())
+    let Some((format, transcoded_filename)) = transcoded else {
+        return Ok(image_filename.to_string());
+    };
+    let transcoded_path = cache.get_cached_path(&transcoded_filename);
+    if let Err(err) = transcode_image(&full_path, &transcoded_path, format) {
+        // The original is still perfectly usable.
+        warn!(
+            "Unable to transcode {} to {format:?}: {err:?}",
+            full_path.display()
+        );
+        return Ok(image_filename.to_string());
+    }
+    if !options.keep_original {
+        std::fs::remove_file(&full_path)?;
+    }
+    Ok(transcoded_filename)
 }
 
+/// Like `cache_image()`, but only returns the filename if the image is already
+/// cached, preferring a re-encoded version of it if there is one.
+pub fn get_cached_image(
+    cache: &GalleryCache,
+    image_filename: &str,
+    ext: &'static str,
+) -> Option<String> {
+    get_cached_transcoded_image(cache, image_filename, ext).or_else(|| {
+        cache
+            .get_if_cached(image_filename)
+            .map(|_| image_filename.to_string())
+    })
+}
+
+/// Returns the (width, height) of the given image in pixels, reading only as much of
+/// the file as is needed to determine it (usually just the header).
+pub fn get_image_pixel_dimensions(filename: &PathBuf) -> Option<(u32, u32)> {
+    match image::image_dimensions(filename) {
+        Ok(dimensions) => Some(dimensions),
+        Err(err) => {
+            warn!(
+                "Unable to read dimensions of {}: {:?}",
+                filename.display(),
+                err
+            );
+            None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImageConversionStep {
+    /// The image had 8-bit luminance pixels, which were converted to RGB8.
+    ConvertedL8ToRgb8,
+    /// The image had the given EXIF orientation, and its pixels were rotated
+    /// and/or flipped so they're in the normal orientation.
+    NormalizedOrientation(u16),
+    /// The given number of bytes of metadata, e.g. EXIF or ICC profiles,
+    /// were removed.
+    StrippedMetadata(usize),
+}
+
+impl Display for ImageConversionStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageConversionStep::ConvertedL8ToRgb8 => write!(f, "converted L8 pixels to RGB8"),
+            ImageConversionStep::NormalizedOrientation(orientation) => {
+                write!(f, "normalized EXIF orientation {orientation}")
+            }
+            ImageConversionStep::StrippedMetadata(bytes) => {
+                write!(f, "stripped {bytes} bytes of metadata")
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImageConversionResult {
+    /// The image could already be loaded in Godot.
+    Unchanged,
+    /// The image was rewritten in place, after taking the given steps.
+    Converted(Vec<ImageConversionStep>),
+}
+
+/// Rewrites the image so that Godot can load it as-is, if needed.
+///
+/// This is idempotent: images that have already been converted are left alone.
 pub fn maybe_convert_image_for_loading_in_godot(
     filename: &PathBuf,
     ext: &'static str,
-) -> Result<bool> {
-    if is_jpeg(ext) {
-        let img = ImageReader::open(filename)?.decode()?;
-        // Annoyingly, Godot errors when trying to load a JPEG with 8-bit luminance pixel values,
-        // and a lot of images from Wikidata in particular are in this format, e.g.:
-        //
-        //     https://www.wikidata.org/wiki/Q19930505
-        //
-        // So, we'll convert them to RGB8, which Godot supports.
-        if img.color() == ColorType::L8 {
-            println!("Converting L8 JPEG image {} to RGB8.", filename.display());
-            let converted = img.into_rgb8();
-            let outfile = std::fs::File::create(filename)?;
-            // TODO: This kind of sucks because we're re-encoding the image in a lossy format.
-            let encoder = JpegEncoder::new_with_quality(outfile, 95);
-            converted.write_with_encoder(encoder)?;
-            return Ok(true);
+) -> Result<ImageConversionResult> {
+    if !is_jpeg(ext) {
+        return Ok(ImageConversionResult::Unchanged);
+    }
+    let bytes = std::fs::read(filename)?;
+    // If we can't make sense of the segments, the decoder will probably
+    // complain below, so just pretend there aren't any.
+    let segments = read_jpeg_segments(&bytes).unwrap_or_default();
+    let metadata_size: usize = segments
+        .iter()
+        .filter(|segment| segment.is_metadata())
+        .map(|segment| segment.len())
+        .sum();
+    // Godot ignores EXIF orientation, so images that rely on it would show
+    // up rotated or flipped.
+    let orientation = read_jpeg_exif_orientation(&bytes, &segments)
+        .filter(|orientation| (2..=8).contains(orientation));
+    let mut img = image::load_from_memory_with_format(&bytes, image::ImageFormat::Jpeg)?;
+    let mut steps = vec![];
+    // Annoyingly, Godot errors when trying to load a JPEG with 8-bit luminance pixel values,
+    // and a lot of images from Wikidata in particular are in this format, e.g.:
+    //
+    //     https://www.wikidata.org/wiki/Q19930505
+    //
+    // So, we'll convert them to RGB8, which Godot supports.
+    if img.color() == ColorType::L8 {
+        debug!("Converting L8 JPEG image {} to RGB8.", filename.display());
+        img = DynamicImage::ImageRgb8(img.into_rgb8());
+        steps.push(ImageConversionStep::ConvertedL8ToRgb8);
+    }
+    if let Some(orientation) = orientation {
+        debug!(
+            "Normalizing EXIF orientation {orientation} of JPEG image {}.",
+            filename.display()
+        );
+        img = apply_exif_orientation(img, orientation);
+        steps.push(ImageConversionStep::NormalizedOrientation(orientation));
+    }
+    if !steps.is_empty() {
+        let outfile = std::fs::File::create(filename)?;
+        // TODO: This kind of sucks because we're re-encoding the image in a lossy format.
+        let encoder = JpegEncoder::new_with_quality(outfile, 95);
+        // Note that the encoder doesn't write any metadata, so this strips it too.
+        img.into_rgb8().write_with_encoder(encoder)?;
+        if metadata_size > 0 {
+            steps.push(ImageConversionStep::StrippedMetadata(metadata_size));
         }
+    } else if metadata_size > 0 {
+        // Nothing else needs to change, so we can just remove the metadata
+        // without re-encoding.
+        std::fs::write(filename, strip_jpeg_metadata(&bytes, &segments))?;
+        steps.push(ImageConversionStep::StrippedMetadata(metadata_size));
+    }
+    if steps.is_empty() {
+        Ok(ImageConversionResult::Unchanged)
+    } else {
+        Ok(ImageConversionResult::Converted(steps))
+    }
+}
+
+const JPEG_SOI_MARKER: u8 = 0xd8;
+
+const JPEG_SOS_MARKER: u8 = 0xda;
+
+const JPEG_EOI_MARKER: u8 = 0xd9;
+
+const JPEG_APP1_MARKER: u8 = 0xe1;
+
+/// Adobe's APP14 segment affects how colors are decoded, so it's not just metadata.
+const JPEG_APP14_MARKER: u8 = 0xee;
+
+const JPEG_COM_MARKER: u8 = 0xfe;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+
+/// A segment of a JPEG file that comes before the image data.
+struct JpegSegment {
+    marker: u8,
+    /// Where the segment starts, including its marker.
+    start: usize,
+    end: usize,
+}
+
+impl JpegSegment {
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns the segment's contents, excluding its marker and length.
+    fn payload<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        &bytes[(self.start + 4).min(self.end)..self.end]
+    }
+
+    /// Whether the image can be decoded without this segment. Note that
+    /// we leave APP0, which contains the JFIF header, alone.
+    fn is_metadata(&self) -> bool {
+        let is_app = (0xe1..=0xef).contains(&self.marker) && self.marker != JPEG_APP14_MARKER;
+        is_app || self.marker == JPEG_COM_MARKER
+    }
+}
+
+/// Returns all the segments before the image data of the given JPEG, or `None`
+/// if it isn't a well-formed JPEG.
+fn read_jpeg_segments(bytes: &[u8]) -> Option<Vec<JpegSegment>> {
+    if bytes.get(0..2)? != [0xff, JPEG_SOI_MARKER] {
+        return None;
+    }
+    let mut segments = vec![];
+    let mut pos = 2;
+    loop {
+        if *bytes.get(pos)? != 0xff {
+            return None;
+        }
+        let marker = *bytes.get(pos + 1)?;
+        match marker {
+            // Markers can be padded with any number of 0xff bytes.
+            0xff => {
+                pos += 1;
+                continue;
+            }
+            JPEG_SOS_MARKER | JPEG_EOI_MARKER => return Some(segments),
+            // These don't have a length.
+            0x01 | 0xd0..=0xd7 => {
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let len = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > bytes.len() {
+            return None;
+        }
+        segments.push(JpegSegment {
+            marker,
+            start: pos,
+            end,
+        });
+        pos = end;
+    }
+}
+
+/// Returns the orientation in the JPEG's EXIF metadata, if it has any.
+fn read_jpeg_exif_orientation(bytes: &[u8], segments: &[JpegSegment]) -> Option<u16> {
+    segments
+        .iter()
+        .filter(|segment| segment.marker == JPEG_APP1_MARKER)
+        .map(|segment| segment.payload(bytes))
+        .find_map(|payload| payload.strip_prefix(EXIF_HEADER))
+        .and_then(read_tiff_orientation)
+}
+
+/// Returns the orientation tag of the first IFD in the given TIFF data.
+fn read_tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let read_u32 = |pos: usize| -> Option<u32> {
+        let bytes: [u8; 4] = tiff.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    if read_u16(2)? != 42 {
+        return None;
+    }
+    let ifd = read_u32(4)? as usize;
+    let entry_count = read_u16(ifd)? as usize;
+    for i in 0..entry_count {
+        let entry = ifd + 2 + i * 12;
+        if read_u16(entry)? == EXIF_ORIENTATION_TAG {
+            // The orientation is a single SHORT, which is stored at the
+            // start of the entry's 4-byte value field.
+            return read_u16(entry + 8);
+        }
+    }
+    None
+}
+
+/// Rotates and/or flips the image so that it looks the way the given EXIF
+/// orientation says it should, in the normal orientation.
+fn apply_exif_orientation(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+fn strip_jpeg_metadata(bytes: &[u8], segments: &[JpegSegment]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    for segment in segments.iter().filter(|segment| segment.is_metadata()) {
+        stripped.extend_from_slice(&bytes[pos..segment.start]);
+        pos = segment.end;
+    }
+    stripped.extend_from_slice(&bytes[pos..]);
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use image::{codecs::jpeg::JpegEncoder, DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+
+    use crate::gallery_cache::GalleryCache;
+
+    use super::{
+        cache_image, decode_image_as_rgb8, get_cached_image,
+        maybe_convert_image_for_loading_in_godot, read_jpeg_segments, transcoded_image_filename,
+        DecodedImage, ImageCacheOptions, ImageConversionResult, ImageConversionStep, ImageFormat,
+    };
+
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+
+    const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+
+    fn temp_jpeg_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "gallery-image-test-{name}-{}.jpg",
+            std::process::id()
+        ))
+    }
+
+    /// Encodes a 32x16 JPEG whose left half is red and whose right half is blue.
+    fn make_half_red_half_blue_jpeg() -> Vec<u8> {
+        let img = RgbImage::from_fn(32, 16, |x, _| if x < 16 { RED } else { BLUE });
+        let mut bytes = vec![];
+        img.write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, 95))
+            .unwrap();
+        bytes
+    }
+
+    /// Creates an offline cache with a 32x16 JPEG already cached as
+    /// `images/painting.jpg`.
+    fn create_cache_with_jpeg(name: &str) -> GalleryCache {
+        let cache_dir = std::env::temp_dir().join(format!(
+            "gallery-image-test-cache-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        std::fs::create_dir_all(cache_dir.join("images")).unwrap();
+        std::fs::write(
+            cache_dir.join("images").join("painting.jpg"),
+            make_half_red_half_blue_jpeg(),
+        )
+        .unwrap();
+        GalleryCache::new_offline(cache_dir)
+    }
+
+    fn webp_options(keep_original: bool) -> ImageCacheOptions {
+        ImageCacheOptions {
+            preferred_format: Some(ImageFormat::Webp),
+            keep_original,
+        }
+    }
+
+    /// Inserts a segment with the given marker and payload right after the
+    /// JPEG's start-of-image marker.
+    fn insert_segment(jpeg: &[u8], marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut result = jpeg[0..2].to_vec();
+        result.extend_from_slice(&[0xff, marker]);
+        result.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        result.extend_from_slice(payload);
+        result.extend_from_slice(&jpeg[2..]);
+        result
+    }
+
+    fn make_exif_payload(orientation: u16, big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut payload = b"Exif\0\0".to_vec();
+        payload.extend_from_slice(if big_endian { b"MM" } else { b"II" });
+        payload.extend_from_slice(&u16_bytes(42));
+        payload.extend_from_slice(&u32_bytes(8));
+        // One IFD entry: the orientation, which is a single SHORT.
+        payload.extend_from_slice(&u16_bytes(1));
+        payload.extend_from_slice(&u16_bytes(0x0112));
+        payload.extend_from_slice(&u16_bytes(3));
+        payload.extend_from_slice(&u32_bytes(1));
+        payload.extend_from_slice(&u16_bytes(orientation));
+        payload.extend_from_slice(&[0, 0]);
+        // No more IFDs.
+        payload.extend_from_slice(&u32_bytes(0));
+        payload
+    }
+
+    fn is_mostly(pixel: &Rgb<u8>, color: Rgb<u8>) -> bool {
+        pixel
+            .0
+            .iter()
+            .zip(color.0.iter())
+            .all(|(a, b)| (*a as i32 - *b as i32).abs() < 64)
+    }
+
+    /// Converts a JPEG with the given orientation, and returns the result.
+    fn convert_with_orientation(name: &str, orientation: u16, big_endian: bool) -> RgbImage {
+        let path = temp_jpeg_path(name);
+        let jpeg = insert_segment(
+            &make_half_red_half_blue_jpeg(),
+            0xe1,
+            &make_exif_payload(orientation, big_endian),
+        );
+        std::fs::write(&path, jpeg).unwrap();
+        assert_eq!(
+            maybe_convert_image_for_loading_in_godot(&path, ".jpg").unwrap(),
+            ImageConversionResult::Converted(vec![
+                ImageConversionStep::NormalizedOrientation(orientation),
+                ImageConversionStep::StrippedMetadata(4 + 32),
+            ])
+        );
+        // Converting again shouldn't do anything.
+        assert_eq!(
+            maybe_convert_image_for_loading_in_godot(&path, ".jpg").unwrap(),
+            ImageConversionResult::Unchanged
+        );
+        let img = image::open(&path).unwrap().into_rgb8();
+        std::fs::remove_file(&path).unwrap();
+        img
+    }
+
+    #[test]
+    fn test_rgb8_images_are_row_major() {
+        let mut img = RgbImage::new(2, 2);
+        img.put_pixel(1, 0, Rgb([1, 2, 3]));
+        img.put_pixel(0, 1, Rgb([4, 5, 6]));
+        let decoded = DecodedImage::from(DynamicImage::ImageRgb8(img));
+        assert_eq!((decoded.width, decoded.height), (2, 2));
+        assert_eq!(decoded.rgb8, vec![0, 0, 0, 1, 2, 3, 4, 5, 6, 0, 0, 0]);
+        assert!(decoded.is_valid());
+    }
+
+    #[test]
+    fn test_l8_images_are_converted_to_rgb8() {
+        let img = GrayImage::from_pixel(1, 2, Luma([42]));
+        let decoded = DecodedImage::from(DynamicImage::ImageLuma8(img));
+        assert_eq!(decoded.rgb8, vec![42, 42, 42, 42, 42, 42]);
+        assert!(decoded.is_valid());
+    }
+
+    #[test]
+    fn test_is_valid_checks_length() {
+        let decoded = DecodedImage {
+            width: 2,
+            height: 1,
+            rgb8: vec![0; 5],
+        };
+        assert!(!decoded.is_valid());
+    }
+
+    #[test]
+    fn test_decode_image_as_rgb8_respects_max_pixels() {
+        let path = std::env::temp_dir().join(format!(
+            "gallery-image-test-decode-{}.jpg",
+            std::process::id()
+        ));
+        RgbImage::from_pixel(4, 3, Rgb([255, 255, 255]))
+            .save(&path)
+            .unwrap();
+        let decoded = decode_image_as_rgb8(&path, 12).unwrap().unwrap();
+        assert_eq!((decoded.width, decoded.height), (4, 3));
+        assert!(decoded.is_valid());
+        assert_eq!(decode_image_as_rgb8(&path, 11).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_orientation_3_is_rotated_180_degrees() {
+        let img = convert_with_orientation("orientation-3", 3, true);
+        assert_eq!(img.dimensions(), (32, 16));
+        assert!(is_mostly(img.get_pixel(4, 8), BLUE));
+        assert!(is_mostly(img.get_pixel(28, 8), RED));
+    }
+
+    #[test]
+    fn test_orientation_6_is_rotated_90_degrees_clockwise() {
+        let img = convert_with_orientation("orientation-6", 6, false);
+        assert_eq!(img.dimensions(), (16, 32));
+        assert!(is_mostly(img.get_pixel(8, 4), RED));
+        assert!(is_mostly(img.get_pixel(8, 28), BLUE));
+    }
+
+    #[test]
+    fn test_orientation_8_is_rotated_90_degrees_counterclockwise() {
+        let img = convert_with_orientation("orientation-8", 8, true);
+        assert_eq!(img.dimensions(), (16, 32));
+        assert!(is_mostly(img.get_pixel(8, 4), BLUE));
+        assert!(is_mostly(img.get_pixel(8, 28), RED));
+    }
+
+    #[test]
+    fn test_metadata_is_stripped_without_reencoding() {
+        let path = temp_jpeg_path("strip-metadata");
+        let original = make_half_red_half_blue_jpeg();
+        let icc_payload = [b"ICC_PROFILE\0".as_slice(), &[7; 1000]].concat();
+        let jpeg = insert_segment(
+            &insert_segment(&original, 0xe2, &icc_payload),
+            0xe1,
+            &make_exif_payload(1, false),
+        );
+        std::fs::write(&path, &jpeg).unwrap();
+        assert_eq!(
+            maybe_convert_image_for_loading_in_godot(&path, ".jpg").unwrap(),
+            ImageConversionResult::Converted(vec![ImageConversionStep::StrippedMetadata(
+                jpeg.len() - original.len()
+            )])
+        );
+        // The pixels should be untouched.
+        let stripped = std::fs::read(&path).unwrap();
+        assert_eq!(stripped, original);
+        assert!(read_jpeg_segments(&stripped)
+            .unwrap()
+            .iter()
+            .all(|segment| !segment.is_metadata()));
+        assert_eq!(
+            maybe_convert_image_for_loading_in_godot(&path, ".jpg").unwrap(),
+            ImageConversionResult::Unchanged
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_l8_jpegs_are_converted_to_rgb8() {
+        let path = temp_jpeg_path("l8");
+        GrayImage::from_pixel(8, 8, Luma([128]))
+            .save(&path)
+            .unwrap();
+        assert_eq!(
+            maybe_convert_image_for_loading_in_godot(&path, ".jpg").unwrap(),
+            ImageConversionResult::Converted(vec![ImageConversionStep::ConvertedL8ToRgb8])
+        );
+        assert_eq!(image::open(&path).unwrap().color(), image::ColorType::Rgb8);
+        assert_eq!(
+            maybe_convert_image_for_loading_in_godot(&path, ".jpg").unwrap(),
+            ImageConversionResult::Unchanged
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_image_format_parses_case_insensitively() {
+        assert_eq!("webp".parse::<ImageFormat>().unwrap(), ImageFormat::Webp);
+        assert_eq!("WebP".parse::<ImageFormat>().unwrap(), ImageFormat::Webp);
+        assert!("gif".parse::<ImageFormat>().is_err());
+    }
+
+    #[test]
+    fn test_only_jpegs_and_pngs_are_transcoded() {
+        let transcode = |filename, ext| transcoded_image_filename(filename, ext, ImageFormat::Webp);
+        assert_eq!(
+            transcode("met/1-small.jpg", ".jpg"),
+            Some("met/1-small.webp".into())
+        );
+        assert_eq!(transcode("Q1.jpeg", ".jpeg"), Some("Q1.webp".into()));
+        assert_eq!(transcode("Q1.png", ".png"), Some("Q1.webp".into()));
+        assert_eq!(transcode("Q1.webp", ".webp"), None);
+    }
+
+    #[test]
+    fn test_cache_image_transcodes_to_webp() {
+        let cache = create_cache_with_jpeg("transcode");
+        let original_path = cache.get_cached_path("images/painting.jpg");
+        let original_dimensions = image::image_dimensions(&original_path).unwrap();
+        let filename = cache_image(
+            &cache,
+            "https://example.com/painting.jpg",
+            "images/painting.jpg",
+            ".jpg",
+            &webp_options(false),
+        )
+        .unwrap();
+        assert_eq!(filename, "images/painting.webp");
+        let webp_path = cache.get_cached_path(&filename);
+        assert_eq!(
+            image::ImageReader::open(&webp_path)
+                .unwrap()
+                .with_guessed_format()
+                .unwrap()
+                .format(),
+            Some(image::ImageFormat::WebP)
+        );
+        assert_eq!(
+            image::image_dimensions(&webp_path).unwrap(),
+            original_dimensions
+        );
+        let img = image::open(&webp_path).unwrap().into_rgb8();
+        assert!(is_mostly(img.get_pixel(4, 8), RED));
+        assert!(is_mostly(img.get_pixel(28, 8), BLUE));
+        assert!(!original_path.exists());
+
+        // The original is gone, but we shouldn't need it, or transcode again.
+        let modified = std::fs::metadata(&webp_path).unwrap().modified().unwrap();
+        for options in [webp_options(false), ImageCacheOptions::default()] {
+            assert_eq!(
+                cache_image(
+                    &cache,
+                    "https://example.com/painting.jpg",
+                    "images/painting.jpg",
+                    ".jpg",
+                    &options,
+                )
+                .unwrap(),
+                "images/painting.webp"
+            );
+        }
+        assert_eq!(
+            std::fs::metadata(&webp_path).unwrap().modified().unwrap(),
+            modified
+        );
+        assert_eq!(
+            get_cached_image(&cache, "images/painting.jpg", ".jpg"),
+            Some("images/painting.webp".to_string())
+        );
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_cache_image_can_keep_originals() {
+        let cache = create_cache_with_jpeg("keep-original");
+        let filename = cache_image(
+            &cache,
+            "https://example.com/painting.jpg",
+            "images/painting.jpg",
+            ".jpg",
+            &webp_options(true),
+        )
+        .unwrap();
+        assert_eq!(filename, "images/painting.webp");
+        assert_eq!(
+            image::image_dimensions(cache.get_cached_path("images/painting.jpg")).unwrap(),
+            image::image_dimensions(cache.get_cached_path(&filename)).unwrap()
+        );
+        assert_eq!(
+            get_cached_image(&cache, "images/painting.jpg", ".jpg"),
+            Some(filename)
+        );
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
+    }
+
+    #[test]
+    fn test_cache_image_without_preference_leaves_images_alone() {
+        let cache = create_cache_with_jpeg("no-preference");
+        assert_eq!(
+            cache_image(
+                &cache,
+                "https://example.com/painting.jpg",
+                "images/painting.jpg",
+                ".jpg",
+                &ImageCacheOptions::default(),
+            )
+            .unwrap(),
+            "images/painting.jpg"
+        );
+        assert!(cache.get_if_cached("images/painting.webp").is_none());
+        std::fs::remove_dir_all(cache.cache_dir()).unwrap();
     }
-    Ok(false)
 }
@@ -1,13 +1,23 @@
-use std::collections::HashSet;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    str::FromStr,
+};
 
-use crate::art_object::ArtObjectId;
+use crate::{art_object::ArtObjectId, random::Rng};
 
 use super::{
-    gallery_db::{ArtObjectLayoutInfo, LayoutRecord},
-    gallery_wall::GalleryWall,
+    gallery_db::{
+        normalize_collection_name, ArtObjectLayoutInfo, GalleryRecord, LayoutAnchor, LayoutRecord,
+    },
+    gallery_wall::{
+        wall_set_for_gallery_index, GalleryWall, GalleryWallSet, DEFAULT_WALL_SET_NAME,
+    },
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
 
 /// Try to push paintings down closer to eye level if possible.
 const PAINTING_EYE_LEVEL_Y_OFFSET: f64 = 0.5;
@@ -25,51 +35,214 @@ const PAINTING_HORIZ_MIN_MOUNT_AREA: f64 = 2.0;
 
 const PAINTING_VERT_MIN_MOUNT_AREA: f64 = 0.5;
 
+/// Art objects are only packed salon-style if neither side is longer than this,
+/// see `place_paintings_in_salon_grid()`.
+pub const SALON_MAX_ART_OBJECT_SIZE: f64 = 1.0;
+
+/// The space between art objects packed salon-style, unless told otherwise.
+pub const DEFAULT_SALON_GUTTER: f64 = PAINTING_VERT_MARGIN;
+
+/// How much bigger than the space left in a salon-style row an art object can be
+/// while still fitting in it, to account for floating point error.
+const SALON_FIT_EPSILON: f64 = 0.001;
+
+/// The layout metadata key for the name of the `LayoutMode` used to create the
+/// most recent layout.
+pub const LAYOUT_MODE_METADATA_KEY: &str = "layout_mode";
+
+/// How art objects are arranged on each wall.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutMode {
+    /// A single row of art objects, centered vertically.
+    #[default]
+    Sparse,
+    /// Like `Sparse`, but with at most one art object stacked below each one.
+    Dense,
+    /// Walls of small art objects are packed in a grid, salon-style, with
+    /// `gutter` between them, see `place_paintings_in_salon_grid()`. Walls of
+    /// bigger ones are laid out like `Sparse`.
+    Salon {
+        #[serde(default = "default_salon_gutter")]
+        gutter: f64,
+    },
+    /// Like `Sparse`, but each of the galleries that art objects hang in at
+    /// their museum gets a gallery of its own, see `layout_source_galleries()`.
+    #[serde(rename = "real-gallery")]
+    RealGallery,
+}
+
+fn default_salon_gutter() -> f64 {
+    DEFAULT_SALON_GUTTER
+}
+
+impl LayoutMode {
+    /// The mode that layout requests from before there were modes, which only
+    /// said whether they were dense, meant.
+    pub fn from_dense(dense: bool) -> Self {
+        if dense {
+            LayoutMode::Dense
+        } else {
+            LayoutMode::Sparse
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LayoutMode::Sparse => "sparse",
+            LayoutMode::Dense => "dense",
+            LayoutMode::Salon { .. } => "salon",
+            LayoutMode::RealGallery => "real-gallery",
+        }
+    }
+}
+
+/// Parses the name of a mode, e.g. `salon`, which has the default gutter.
+impl FromStr for LayoutMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sparse" => Ok(LayoutMode::Sparse),
+            "dense" => Ok(LayoutMode::Dense),
+            "salon" => Ok(LayoutMode::Salon {
+                gutter: DEFAULT_SALON_GUTTER,
+            }),
+            "real-gallery" => Ok(LayoutMode::RealGallery),
+            _ => Err(anyhow!(
+                "Unknown layout mode {s:?}, expected sparse, dense, salon or real-gallery"
+            )),
+        }
+    }
+}
+
+/// Free regions narrower than this are merged into their neighbors, or dropped
+/// if they don't have any, unless told otherwise.
+pub const DEFAULT_MIN_FREE_REGION_WIDTH: f64 = 1.0;
+
+/// How far apart free regions on the same wall can be while still counting as
+/// adjacent, to account for floating point error.
+const FREE_REGION_ADJACENCY_EPSILON: f64 = 0.001;
+
+/// A horizontal span of a wall that the layout left empty, e.g. so the game can
+/// put a bench or a plant there. It extends from the floor to `height_available`.
+///
+/// Only spans of the wall's full height are free regions, so the space below a
+/// painting isn't one, even if nothing is stacked there.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct FreeRegion<T: AsRef<str>> {
+    pub gallery_id: i64,
+    pub wall_id: T,
+    pub x_start: f64,
+    pub x_end: f64,
+    pub height_available: f64,
+}
+
+impl<T: AsRef<str>> FreeRegion<T> {
+    pub fn width(&self) -> f64 {
+        self.x_end - self.x_start
+    }
+
+    fn is_on_same_wall(&self, other: &FreeRegion<T>) -> bool {
+        self.gallery_id == other.gallery_id && self.wall_id.as_ref() == other.wall_id.as_ref()
+    }
+}
+
+/// Sorts the given free regions by gallery, wall and position, merging any that
+/// are narrower than `min_width` with adjacent ones on the same wall, and then
+/// dropping any that are still too narrow.
+pub fn merge_free_regions<T: AsRef<str>>(
+    mut regions: Vec<FreeRegion<T>>,
+    min_width: f64,
+) -> Vec<FreeRegion<T>> {
+    regions.sort_by(|a, b| {
+        (a.gallery_id, a.wall_id.as_ref())
+            .cmp(&(b.gallery_id, b.wall_id.as_ref()))
+            .then(a.x_start.total_cmp(&b.x_start))
+    });
+    let mut merged: Vec<FreeRegion<T>> = Vec::with_capacity(regions.len());
+    for region in regions {
+        if let Some(last) = merged.last_mut() {
+            let is_adjacent = last.is_on_same_wall(&region)
+                && region.x_start <= last.x_end + FREE_REGION_ADJACENCY_EPSILON;
+            if is_adjacent && (last.width() < min_width || region.width() < min_width) {
+                last.x_end = last.x_end.max(region.x_end);
+                last.height_available = last.height_available.min(region.height_available);
+                continue;
+            }
+        }
+        merged.push(region);
+    }
+    merged.retain(|region| region.width() >= min_width);
+    merged
+}
+
 pub struct ArtObjectLayoutFitter {
     unused: Vec<ArtObjectLayoutInfo>,
     remaining: Vec<ArtObjectLayoutInfo>,
     warnings: bool,
+    allow_rotation: bool,
 }
 
 impl ArtObjectLayoutFitter {
-    pub fn new(remaining: Vec<ArtObjectLayoutInfo>, warnings: bool) -> Self {
+    /// If `allow_rotation` is true, art objects that don't fit somewhere can be
+    /// rotated 90° if that makes them fit.
+    pub fn new(remaining: Vec<ArtObjectLayoutInfo>, warnings: bool, allow_rotation: bool) -> Self {
         ArtObjectLayoutFitter {
             unused: vec![],
             remaining,
             warnings,
+            allow_rotation,
         }
     }
 
+    /// Returns the next art object that fits in the given space, along with
+    /// whether it had to be rotated to fit. If it was, its width and height
+    /// are swapped, so they're its dimensions as hung.
+    ///
+    /// If several art objects fit, the one that was skipped over earliest
+    /// wins, followed by the remaining ones in their original order.
     pub fn get_object_fitting_in(
         &mut self,
         max_width: f64,
         max_height: f64,
-        walls: &Vec<GalleryWall>,
-    ) -> Option<ArtObjectLayoutInfo> {
-        let idx = self
+        walls: &[&GalleryWall],
+    ) -> Option<(ArtObjectLayoutInfo, bool)> {
+        let allow_rotation = self.allow_rotation;
+        let fit = self
             .unused
             .iter()
-            .position(|art_object| can_object_fit_in(&art_object, max_width, max_height));
-        if let Some(idx) = idx {
-            return Some(self.unused.swap_remove(idx));
+            .enumerate()
+            .find_map(|(idx, art_object)| {
+                fit_object_in(&art_object, max_width, max_height, allow_rotation)
+                    .map(|rotated| (idx, rotated))
+            });
+        if let Some((idx, rotated)) = fit {
+            // Don't use `swap_remove()` here, since it would change the order in which
+            // the rest of the unused art objects are placed.
+            return Some(orient(self.unused.remove(idx), rotated));
         }
         while let Some(art_object) = self.remaining.pop() {
-            if can_object_fit_in(&art_object, max_width, max_height) {
-                return Some(art_object);
+            if let Some(rotated) = fit_object_in(&art_object, max_width, max_height, allow_rotation)
+            {
+                return Some(orient(art_object, rotated));
             }
-            if can_object_fit_anywhere(&art_object, &walls) {
+            if can_object_fit_anywhere(&art_object, &walls, allow_rotation) {
                 self.unused.push(art_object);
             } else if self.warnings {
-                println!(
-                    "Warning: object {:?} can't fit on any walls.",
-                    art_object.id
-                );
+                warn!("Object {:?} can't fit on any walls.", art_object.id);
             }
         }
 
         None
     }
 
+    /// Returns the art object that was skipped over earliest, or if there
+    /// aren't any, the next remaining one.
+    pub fn peek(&self) -> Option<&ArtObjectLayoutInfo> {
+        self.unused.first().or_else(|| self.remaining.last())
+    }
+
     pub fn is_empty(&self) -> bool {
         self.unused.is_empty() && self.remaining.is_empty()
     }
@@ -83,38 +256,119 @@ fn can_object_fit_in(object_layout: &ArtObjectLayoutInfo, max_width: f64, max_he
     object_layout.width < max_width && object_layout.height < max_height
 }
 
-fn can_object_fit_anywhere(object_layout: &ArtObjectLayoutInfo, walls: &Vec<GalleryWall>) -> bool {
+/// Returns whether the art object needs to be rotated 90° to fit in the given
+/// space, or `None` if it doesn't fit at all. Rotation is only ever a fallback
+/// for art objects that don't fit upright.
+fn fit_object_in(
+    object_layout: &ArtObjectLayoutInfo,
+    max_width: f64,
+    max_height: f64,
+    allow_rotation: bool,
+) -> Option<bool> {
+    if can_object_fit_in(object_layout, max_width, max_height) {
+        Some(false)
+    } else if allow_rotation && can_object_fit_in(&rotate(object_layout), max_width, max_height) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+fn rotate(object_layout: &ArtObjectLayoutInfo) -> ArtObjectLayoutInfo {
+    ArtObjectLayoutInfo {
+        width: object_layout.height,
+        height: object_layout.width,
+        collection: object_layout.collection.clone(),
+        ..*object_layout
+    }
+}
+
+fn orient(object_layout: ArtObjectLayoutInfo, rotated: bool) -> (ArtObjectLayoutInfo, bool) {
+    if rotated {
+        (rotate(&object_layout), true)
+    } else {
+        (object_layout, false)
+    }
+}
+
+fn can_object_fit_anywhere(
+    object_layout: &ArtObjectLayoutInfo,
+    walls: &[&GalleryWall],
+    allow_rotation: bool,
+) -> bool {
     for wall in walls {
-        if can_object_fit_in(
+        if fit_object_in(
             object_layout,
             wall.width - PAINTING_HORIZ_MARGIN * 2.0,
             wall.height,
-        ) {
+            allow_rotation,
+        )
+        .is_some()
+        {
             return true;
         }
     }
     false
 }
 
-pub fn place_paintings_along_wall<'a>(
-    gallery_id: i64,
-    walls: &Vec<GalleryWall>,
-    wall_name: &'a str,
-    finder: &mut ArtObjectLayoutFitter,
+/// What `place_paintings_along_wall()` and `place_paintings_in_salon_grid()`
+/// need to lay out a wall, which stays the same as the former recursively fills
+/// in the space around each art object it places.
+pub struct WallPlacement<'a, 'b> {
+    pub gallery_id: i64,
+    /// Every wall being laid out, so art objects that can't fit on any of them
+    /// aren't set aside for later.
+    pub walls: &'b [&'b GalleryWall],
+    pub wall_name: &'a str,
+    pub finder: &'b mut ArtObjectLayoutFitter,
+    pub layout_records: &'b mut Vec<LayoutRecord<&'a str>>,
+    /// Art objects that get an empty space where they would've been, rather than
+    /// a layout record.
+    pub except_art_object_ids: &'b HashSet<ArtObjectId>,
+    /// Where to report the parts of the wall that are left empty, if anywhere.
+    pub free_regions: Option<&'b mut Vec<FreeRegion<&'a str>>>,
+}
+
+impl<'a, 'b> WallPlacement<'a, 'b> {
+    fn add_free_region(&mut self, x_start: f64, x_end: f64, height_available: f64) {
+        if let Some(free_regions) = self.free_regions.as_deref_mut() {
+            free_regions.push(FreeRegion {
+                gallery_id: self.gallery_id,
+                wall_id: self.wall_name,
+                x_start,
+                x_end,
+                height_available,
+            });
+        }
+    }
+}
+
+pub fn place_paintings_along_wall(
+    placement: &mut WallPlacement,
     x_start: f64,
     y_start: f64,
     max_width: f64,
     max_height: f64,
     center_vertically: bool,
     use_dense_layout: bool,
-    layout_records: &mut Vec<LayoutRecord<&'a str>>,
-    except_art_object_ids: &HashSet<ArtObjectId>,
 ) {
+    // Any part of the wall we give up on is free, as long as it's the wall's full
+    // height (see `FreeRegion`), which is only the case if we're centering.
+    let abandon = |placement: &mut WallPlacement, start: f64, end: f64| {
+        if center_vertically {
+            placement.add_free_region(start, end, max_height);
+        }
+    };
     let max_painting_width = max_width - PAINTING_HORIZ_MARGIN * 2.0;
     if max_painting_width <= 0.0 {
+        abandon(placement, x_start, x_start + max_width);
         return;
     }
-    if let Some(art_object) = finder.get_object_fitting_in(max_painting_width, max_height, &walls) {
+    if let Some((art_object, rotated)) =
+        placement
+            .finder
+            .get_object_fitting_in(max_painting_width, max_height, placement.walls)
+    {
         let x = x_start + max_width / 2.0;
         let y = y_start
             + if center_vertically {
@@ -132,13 +386,14 @@ pub fn place_paintings_along_wall<'a>(
 
         // Note that even if the art object shouldn't be placed, we leave an empty space where it
         // would've been. This helps keep layouts consistent.
-        if !except_art_object_ids.contains(&art_object.id) {
-            layout_records.push(LayoutRecord {
-                gallery_id,
-                wall_id: &wall_name,
+        if !placement.except_art_object_ids.contains(&art_object.id) {
+            placement.layout_records.push(LayoutRecord {
+                gallery_id: placement.gallery_id,
+                wall_id: placement.wall_name,
                 art_object_id: art_object.id,
                 x,
                 y,
+                rotated,
             });
         }
 
@@ -152,96 +407,1882 @@ pub fn place_paintings_along_wall<'a>(
                 let right_edge =
                     x_start + (max_width / 2.0 + art_object.width / 2.0 + PAINTING_HORIZ_MARGIN);
                 place_paintings_along_wall(
-                    gallery_id,
-                    walls,
-                    wall_name,
-                    finder,
+                    placement,
                     left_edge,
                     below_y_start,
                     right_edge - left_edge,
                     vertical_space_below,
                     false,
                     false,
-                    layout_records,
-                    except_art_object_ids,
                 );
             }
         }
         if margin_width > PAINTING_HORIZ_MIN_MOUNT_AREA {
             place_paintings_along_wall(
-                gallery_id,
-                walls,
-                wall_name,
-                finder,
+                placement,
                 x_start,
                 y_start,
                 margin_width,
                 max_height,
                 center_vertically,
                 use_dense_layout,
-                layout_records,
-                except_art_object_ids,
             );
             place_paintings_along_wall(
-                gallery_id,
-                walls,
-                wall_name,
-                finder,
+                placement,
                 x_start + (max_width / 2.0 + art_object.width / 2.0),
                 y_start,
                 margin_width,
                 max_height,
                 center_vertically,
                 use_dense_layout,
-                layout_records,
-                except_art_object_ids,
             );
+        } else {
+            let right_edge = x_start + (max_width / 2.0 + art_object.width / 2.0);
+            abandon(placement, x_start, x_start + margin_width);
+            abandon(placement, right_edge, right_edge + margin_width);
         }
+    } else {
+        abandon(placement, x_start, x_start + max_width);
     }
 }
 
+fn is_salon_sized(object_layout: &ArtObjectLayoutInfo) -> bool {
+    object_layout.width <= SALON_MAX_ART_OBJECT_SIZE
+        && object_layout.height <= SALON_MAX_ART_OBJECT_SIZE
+}
+
+/// Packs small art objects onto the given wall salon-style: in rows from a
+/// baseline upward, each as tall as its first art object, with `gutter` between
+/// the art objects in a row and between rows. Once nothing else fits, each row is
+/// centered horizontally, and the block of rows vertically.
+///
+/// Only art objects no bigger than `SALON_MAX_ART_OBJECT_SIZE` are packed, and
+/// only if the next one the fitter has is. Returns how many art objects were
+/// packed, which is zero if nothing was, in which case the wall should be laid
+/// out with `place_paintings_along_wall()` instead.
+///
+/// `wall` is the one named by `placement.wall_name`.
+pub fn place_paintings_in_salon_grid(
+    placement: &mut WallPlacement,
+    wall: &GalleryWall,
+    gutter: f64,
+) -> usize {
+    if !placement.finder.peek().is_some_and(is_salon_sized) {
+        return 0;
+    }
+    let max_width = wall.width - PAINTING_HORIZ_MARGIN * 2.0;
+    let max_height = wall.height - PAINTING_MIN_DISTANCE_FROM_FLOOR - PAINTING_VERT_MARGIN;
+    let mut rows: Vec<(f64, Vec<(ArtObjectLayoutInfo, bool)>)> = vec![];
+    let mut used_height = 0.0;
+    loop {
+        let row_max_height = if rows.is_empty() {
+            max_height
+        } else {
+            max_height - used_height - gutter
+        };
+        if row_max_height <= 0.0 || max_width <= 0.0 {
+            break;
+        }
+        let Some(first) = placement.finder.get_object_fitting_in(
+            max_width.min(SALON_MAX_ART_OBJECT_SIZE) + SALON_FIT_EPSILON,
+            row_max_height.min(SALON_MAX_ART_OBJECT_SIZE) + SALON_FIT_EPSILON,
+            placement.walls,
+        ) else {
+            break;
+        };
+        let row_height = first.0.height;
+        let mut row_width = first.0.width;
+        let mut row = vec![first];
+        loop {
+            let remaining_width = max_width - row_width - gutter;
+            if remaining_width <= 0.0 {
+                break;
+            }
+            let Some(next) = placement.finder.get_object_fitting_in(
+                remaining_width.min(SALON_MAX_ART_OBJECT_SIZE) + SALON_FIT_EPSILON,
+                row_height + SALON_FIT_EPSILON,
+                placement.walls,
+            ) else {
+                break;
+            };
+            row_width += gutter + next.0.width;
+            row.push(next);
+        }
+        used_height = if rows.is_empty() {
+            row_height
+        } else {
+            used_height + gutter + row_height
+        };
+        rows.push((row_width, row));
+    }
+    if rows.is_empty() {
+        return 0;
+    }
+
+    // Center the block, without letting it get any closer to the floor than the
+    // baseline.
+    let mut row_bottom = ((wall.height - used_height) / 2.0).max(PAINTING_MIN_DISTANCE_FROM_FLOOR);
+    let mut x_extent = (wall.width, 0.0_f64);
+    let mut packed = 0;
+    for (row_width, row) in rows {
+        let row_left = (wall.width - row_width) / 2.0;
+        x_extent = (
+            x_extent.0.min(row_left),
+            x_extent.1.max(row_left + row_width),
+        );
+        let mut left = row_left;
+        let mut row_height: f64 = 0.0;
+        for (art_object, rotated) in row {
+            // Like `place_paintings_along_wall()`, leave an empty space where an art
+            // object that shouldn't be placed would've been.
+            if !placement.except_art_object_ids.contains(&art_object.id) {
+                placement.layout_records.push(LayoutRecord {
+                    gallery_id: placement.gallery_id,
+                    wall_id: placement.wall_name,
+                    art_object_id: art_object.id,
+                    x: left + art_object.width / 2.0,
+                    y: row_bottom + art_object.height / 2.0,
+                    rotated,
+                });
+            }
+            left += art_object.width + gutter;
+            row_height = row_height.max(art_object.height);
+            packed += 1;
+        }
+        row_bottom += row_height + gutter;
+    }
+    for (x_start, x_end) in [(0.0, x_extent.0), (x_extent.1, wall.width)] {
+        placement.add_free_region(x_start, x_end, wall.height);
+    }
+    packed
+}
+
+/// Returns the IDs of all the given art objects that are too big to fit on
+/// any of the given walls, even rotated if `allow_rotation` is true.
+pub fn find_unplaceable_objects(
+    art_objects: &[ArtObjectLayoutInfo],
+    walls: &[&GalleryWall],
+    allow_rotation: bool,
+) -> Vec<ArtObjectId> {
+    art_objects
+        .iter()
+        .filter(|art_object| !can_object_fit_anywhere(art_object, walls, allow_rotation))
+        .map(|art_object| art_object.id)
+        .collect()
+}
+
+/// Move the art objects with the given IDs to the front, in the given order,
+/// leaving the rest in their original order after them.
+///
+/// Returns the reordered art objects, along with any IDs in the ordering that
+/// don't correspond to any of the art objects.
+pub fn apply_ordering(
+    art_objects: Vec<ArtObjectLayoutInfo>,
+    ordering: &[ArtObjectId],
+) -> (Vec<ArtObjectLayoutInfo>, Vec<ArtObjectId>) {
+    let mut art_objects: Vec<Option<ArtObjectLayoutInfo>> =
+        art_objects.into_iter().map(Some).collect();
+    let indices: HashMap<ArtObjectId, usize> = art_objects
+        .iter()
+        .enumerate()
+        .map(|(idx, art_object)| (art_object.as_ref().unwrap().id, idx))
+        .collect();
+    let mut ordered = Vec::with_capacity(art_objects.len());
+    let mut unmatched_ids = vec![];
+    for id in ordering {
+        // Duplicate IDs will have already been taken, so they're ignored.
+        match indices.get(id) {
+            Some(&idx) => ordered.extend(art_objects[idx].take()),
+            None => unmatched_ids.push(*id),
+        }
+    }
+    ordered.extend(art_objects.into_iter().flatten());
+    (ordered, unmatched_ids)
+}
+
+/// Move highlights (see `ArtObjectRecord::highlight`) to the front, leaving both
+/// the highlights and the rest in their original order. Since galleries are
+/// filled in order, this puts highlights in the first galleries, near the
+/// entrance, regardless of how the art objects were sorted.
+///
+/// Note that an `ordering` passed to `layout()` is applied afterwards, and takes
+/// precedence.
+pub fn move_highlights_to_front(art_objects: &mut Vec<ArtObjectLayoutInfo>) {
+    // This is a stable sort, so the original order is otherwise preserved.
+    art_objects.sort_by_key(|art_object| !art_object.highlight);
+}
+
+/// How big a share of a layout balanced by collection a collection should get,
+/// relative to the others, see `balance_by_collection()`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CollectionWeight {
+    pub collection: String,
+    pub weight: f64,
+}
+
+/// Parses e.g. `The Metropolitan Museum of Art=1.5`. Collection names can
+/// contain `=`, so everything after the last one is the weight.
+impl FromStr for CollectionWeight {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((collection, weight)) = s.rsplit_once('=') else {
+            return Err(anyhow!(
+                "Collection weight {s:?} must be <collection>=<weight>"
+            ));
+        };
+        let collection = collection.trim();
+        if collection.is_empty() {
+            return Err(anyhow!("Collection weight {s:?} has an empty collection"));
+        }
+        let weight: f64 = weight
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Collection weight {s:?} has an invalid weight"))?;
+        if !weight.is_finite() || weight <= 0.0 {
+            return Err(anyhow!("Collection weight {s:?} must be positive"));
+        }
+        Ok(CollectionWeight {
+            collection: collection.to_string(),
+            weight,
+        })
+    }
+}
+
+/// Interleave the art objects of each collection so that, e.g. when the art
+/// objects are shuffled, a collection with ten times as many art objects as
+/// another doesn't get ten times as many spots near the entrance.
+///
+/// Each collection gets a share of the art objects proportional to its weight,
+/// until it runs out of art objects. Collections without a weight get a weight
+/// of 1, so by default every collection gets an equal share.
+///
+/// If `rng` is present, each collection's art objects are shuffled first;
+/// otherwise they stay in their original order. Either way, the result only
+/// depends on the art objects, the weights and the RNG's seed.
+pub fn balance_by_collection(
+    art_objects: Vec<ArtObjectLayoutInfo>,
+    weights: &[CollectionWeight],
+    rng: Option<&mut Rng>,
+) -> Vec<ArtObjectLayoutInfo> {
+    let total = art_objects.len();
+    // This is keyed by normalized collection name, so the order in which
+    // collections are interleaved doesn't depend on the order of the art objects.
+    let mut groups: BTreeMap<String, Vec<ArtObjectLayoutInfo>> = BTreeMap::new();
+    for art_object in art_objects {
+        groups
+            .entry(normalize_collection_name(&art_object.collection))
+            .or_default()
+            .push(art_object);
+    }
+    let weights: HashMap<String, f64> = weights
+        .iter()
+        .map(|weight| (normalize_collection_name(&weight.collection), weight.weight))
+        .collect();
+    let mut rng = rng;
+    let mut groups: Vec<(f64, usize, std::vec::IntoIter<ArtObjectLayoutInfo>)> = groups
+        .into_iter()
+        .map(|(collection, mut group)| {
+            if let Some(rng) = rng.as_mut() {
+                rng.shuffle(&mut group);
+            }
+            let weight = weights.get(&collection).copied().unwrap_or(1.0);
+            (weight, 0, group.into_iter())
+        })
+        .collect();
+    let mut result = Vec::with_capacity(total);
+    while result.len() < total {
+        // Take the next art object from whichever collection is furthest behind
+        // its share, preferring earlier collections when there's a tie.
+        let mut next: Option<(f64, usize)> = None;
+        for (idx, (weight, taken, group)) in groups.iter().enumerate() {
+            if group.len() == 0 {
+                continue;
+            }
+            let position = (*taken + 1) as f64 / *weight;
+            if next.map_or(true, |(best, _)| position < best) {
+                next = Some((position, idx));
+            }
+        }
+        let Some((_, idx)) = next else {
+            break;
+        };
+        let (_, taken, group) = &mut groups[idx];
+        *taken += 1;
+        result.extend(group.next());
+    }
+    result
+}
+
+pub struct LayoutResult<'a> {
+    pub galleries_created: usize,
+    pub layout_records: Vec<LayoutRecord<&'a str>>,
+    /// A record for each gallery indicating which wall set it uses.
+    pub gallery_records: Vec<GalleryRecord>,
+    /// Art objects that were excluded from the layout because they don't fit
+    /// on any walls.
+    pub unplaceable_art_object_ids: Vec<ArtObjectId>,
+    /// IDs in the requested ordering that weren't among the art objects to lay
+    /// out, e.g. because they didn't match the filter or don't exist.
+    pub unmatched_ordering_ids: Vec<ArtObjectId>,
+    /// The parts of walls that were left empty, if they were asked for.
+    pub free_regions: Vec<FreeRegion<&'a str>>,
+    /// Source galleries whose art objects didn't all fit in one gallery, and
+    /// were left out, see `layout_source_galleries()`.
+    pub dropped_source_galleries: Vec<String>,
+}
+
+impl<'a> LayoutResult<'a> {
+    /// Returns where each laid out art object is relative to its wall, given the
+    /// wall sets that were used to create the layout.
+    pub fn anchors(&self, wall_sets: &[GalleryWallSet]) -> Vec<(ArtObjectId, LayoutAnchor)> {
+        let wall_sets_by_gallery: HashMap<i64, &GalleryWallSet> = self
+            .gallery_records
+            .iter()
+            .filter_map(|gallery| {
+                wall_sets
+                    .iter()
+                    .find(|wall_set| wall_set.name == gallery.wall_set)
+                    .map(|wall_set| (gallery.gallery_id, wall_set))
+            })
+            .collect();
+        self.layout_records
+            .iter()
+            .filter_map(|record| {
+                let wall = wall_sets_by_gallery
+                    .get(&record.gallery_id)?
+                    .walls
+                    .iter()
+                    .find(|wall| wall.name == record.wall_id)?;
+                Some((
+                    record.art_object_id,
+                    LayoutAnchor::from_position(record.x, record.y, wall),
+                ))
+            })
+            .collect()
+    }
+
+    /// Drops every gallery after the given one, along with everything in it,
+    /// e.g. so that a layout fits in a limited range of galleries.
+    pub fn truncate_galleries(&mut self, last_gallery_id: i64) {
+        self.layout_records
+            .retain(|record| record.gallery_id <= last_gallery_id);
+        self.gallery_records
+            .retain(|gallery| gallery.gallery_id <= last_gallery_id);
+        self.free_regions
+            .retain(|region| region.gallery_id <= last_gallery_id);
+        self.galleries_created = self.gallery_records.len();
+    }
+}
+
+/// How `layout()`, `layout_segments()` and `layout_source_galleries()` lay out
+/// art objects. The defaults are what the game's initial layout uses.
+#[derive(Debug, Clone, Copy)]
+pub struct LayoutOptions<'a> {
+    pub mode: LayoutMode,
+    /// The ID of the first gallery to lay out.
+    pub gallery_start_id: i64,
+    /// If given, these art objects are laid out first, in this order, followed
+    /// by the rest of the art objects in their original order.
+    pub ordering: Option<&'a [ArtObjectId]>,
+    /// Art objects that are hung elsewhere, e.g. by players in non-positive
+    /// galleries, which are skipped rather than laid out again.
+    pub except_art_object_ids: Option<&'a HashSet<ArtObjectId>>,
+    /// Walls with these names are left empty in every gallery, so players have
+    /// somewhere to hang their own finds.
+    pub reserved_walls: &'a [String],
+    /// Whether art objects that don't fit somewhere upright can be rotated 90°,
+    /// see `LayoutRecord::rotated`. This is mostly useful for tall, narrow works
+    /// like hanging scrolls, which might not otherwise fit on any walls.
+    pub allow_rotation: bool,
+    /// Whether to include the parts of walls that were left empty in the
+    /// result, see `FreeRegion` and `merge_free_regions()`.
+    pub collect_free_regions: bool,
+    pub min_free_region_width: f64,
+    /// Whether to log warnings, e.g. about art objects that can't fit on any
+    /// walls.
+    pub warnings: bool,
+}
+
+impl Default for LayoutOptions<'_> {
+    fn default() -> Self {
+        LayoutOptions {
+            mode: LayoutMode::default(),
+            gallery_start_id: 1,
+            ordering: None,
+            except_art_object_ids: None,
+            reserved_walls: &[],
+            allow_rotation: false,
+            collect_free_regions: false,
+            min_free_region_width: DEFAULT_MIN_FREE_REGION_WIDTH,
+            warnings: false,
+        }
+    }
+}
+
+/// Lay out the given art objects across galleries, as configured by `options`.
+///
+/// Each gallery uses one of the given wall sets, cycling through them in order (see
+/// `wall_set_for_gallery_index()`).
 pub fn layout<'a>(
-    use_dense_layout: bool,
-    gallery_start_id: i64,
-    walls: &'a Vec<GalleryWall>,
-    mut art_objects: Vec<ArtObjectLayoutInfo>,
-    except_art_object_ids: &HashSet<ArtObjectId>,
-    warnings: bool,
-) -> Result<(usize, Vec<LayoutRecord<&'a str>>)> {
+    wall_sets: &'a Vec<GalleryWallSet>,
+    art_objects: Vec<ArtObjectLayoutInfo>,
+    options: &LayoutOptions,
+) -> Result<LayoutResult<'a>> {
+    let LayoutOptions {
+        mode,
+        gallery_start_id,
+        ordering,
+        except_art_object_ids,
+        reserved_walls,
+        allow_rotation,
+        collect_free_regions,
+        min_free_region_width,
+        warnings,
+    } = *options;
+    let no_art_object_ids = HashSet::new();
+    let except_art_object_ids = except_art_object_ids.unwrap_or(&no_art_object_ids);
+    let is_reserved = |wall: &GalleryWall| reserved_walls.contains(&wall.name);
+    for wall_set in wall_sets {
+        if wall_set.walls.is_empty() {
+            return Err(anyhow!("Wall set {:?} has no walls", wall_set.name));
+        }
+        if wall_set.weight > 0 && wall_set.walls.iter().all(is_reserved) {
+            return Err(anyhow!(
+                "All walls in wall set {:?} are reserved",
+                wall_set.name
+            ));
+        }
+    }
+    // Objects only need to fit on walls that we'll actually use, otherwise we'll loop forever.
+    let all_walls: Vec<&GalleryWall> = wall_sets
+        .iter()
+        .filter(|wall_set| wall_set.weight > 0)
+        .flat_map(|wall_set| wall_set.walls.iter())
+        .filter(|wall| !is_reserved(wall))
+        .collect();
+    let (mut art_objects, unmatched_ordering_ids) = match ordering {
+        Some(ordering) => apply_ordering(art_objects, ordering),
+        None => (art_objects, vec![]),
+    };
+    if warnings {
+        for id in unmatched_ordering_ids.iter() {
+            warn!("Object {:?} in ordering isn't being laid out.", id);
+        }
+    }
+    let unplaceable_art_object_ids =
+        find_unplaceable_objects(&art_objects, &all_walls, allow_rotation);
+    if !unplaceable_art_object_ids.is_empty() {
+        let unplaceable: HashSet<&ArtObjectId> = unplaceable_art_object_ids.iter().collect();
+        art_objects.retain(|art_object| !unplaceable.contains(&art_object.id));
+        if warnings {
+            for id in unplaceable_art_object_ids.iter() {
+                warn!("Object {:?} can't fit on any walls.", id);
+            }
+        }
+    }
     // Reverse the objects, since we'll be popping them off the end of the vec.
     // This isn't terribly efficient but it'll do for now.
     art_objects.reverse();
-    let mut finder = ArtObjectLayoutFitter::new(art_objects, warnings);
+    let mut finder = ArtObjectLayoutFitter::new(art_objects, warnings, allow_rotation);
     let mut layout_records: Vec<LayoutRecord<&'a str>> = vec![];
+    let mut free_regions: Vec<FreeRegion<&'a str>> = vec![];
     let mut wall_idx = 0;
     let mut gallery_id = gallery_start_id;
     let mut galleries_created: usize = 0;
+    let mut wall_set = wall_set_for_gallery_index(wall_sets, galleries_created)?;
     while !finder.is_empty() {
-        let wall = walls.get(wall_idx).unwrap();
-        place_paintings_along_wall(
-            gallery_id,
-            &walls,
-            &wall.name,
-            &mut finder,
-            0.0,
-            0.0,
-            wall.width,
-            wall.height,
-            true,
-            use_dense_layout,
-            &mut layout_records,
-            except_art_object_ids,
-        );
+        let wall = wall_set.walls.get(wall_idx).unwrap();
+        if !is_reserved(wall) {
+            let mut placement = WallPlacement {
+                gallery_id,
+                walls: &all_walls,
+                wall_name: &wall.name,
+                finder: &mut finder,
+                layout_records: &mut layout_records,
+                except_art_object_ids,
+                free_regions: collect_free_regions.then_some(&mut free_regions),
+            };
+            let packed = match mode {
+                LayoutMode::Salon { gutter } => {
+                    place_paintings_in_salon_grid(&mut placement, wall, gutter)
+                }
+                LayoutMode::Sparse | LayoutMode::Dense | LayoutMode::RealGallery => 0,
+            };
+            if packed == 0 {
+                place_paintings_along_wall(
+                    &mut placement,
+                    0.0,
+                    0.0,
+                    wall.width,
+                    wall.height,
+                    true,
+                    mode == LayoutMode::Dense,
+                );
+            }
+        }
         wall_idx += 1;
-        if wall_idx == walls.len() {
+        if wall_idx == wall_set.walls.len() {
             wall_idx = 0;
             gallery_id += 1;
             galleries_created += 1;
+            wall_set = wall_set_for_gallery_index(wall_sets, galleries_created)?;
+        }
+    }
+    if collect_free_regions && wall_idx > 0 {
+        // We ran out of art before getting to the rest of the last gallery's walls.
+        for wall in wall_set.walls[wall_idx..].iter() {
+            if !is_reserved(wall) {
+                free_regions.push(FreeRegion {
+                    gallery_id,
+                    wall_id: &wall.name,
+                    x_start: 0.0,
+                    x_end: wall.width,
+                    height_available: wall.height,
+                });
+            }
         }
     }
     if layout_records.len() > 0 {
         // We have to account for the very first gallery too.
         galleries_created += 1;
     }
-    Ok((galleries_created, layout_records))
+    let mut gallery_records = Vec::with_capacity(galleries_created);
+    for i in 0..galleries_created {
+        let wall_set = wall_set_for_gallery_index(wall_sets, i)?;
+        gallery_records.push(GalleryRecord {
+            gallery_id: gallery_start_id + i as i64,
+            wall_set: wall_set.name.clone(),
+            segment: None,
+            source_gallery: None,
+            reserved_walls: wall_set
+                .walls
+                .iter()
+                .filter(|wall| is_reserved(wall))
+                .map(|wall| wall.name.clone())
+                .collect(),
+        });
+    }
+    Ok(LayoutResult {
+        galleries_created,
+        layout_records,
+        gallery_records,
+        unplaceable_art_object_ids,
+        unmatched_ordering_ids,
+        free_regions: merge_free_regions(free_regions, min_free_region_width),
+        dropped_source_galleries: vec![],
+    })
+}
+
+/// Roughly how big a layout will be, e.g. so level designers can budget for
+/// streaming galleries before committing to it, see `estimate_layout()`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LayoutEstimate {
+    pub galleries: usize,
+    pub average_art_objects_per_gallery: f64,
+    /// The number of art objects that are too big to fit on any walls.
+    pub unplaceable: usize,
+}
+
+impl<'a> From<&LayoutResult<'a>> for LayoutEstimate {
+    fn from(result: &LayoutResult<'a>) -> Self {
+        let average_art_objects_per_gallery = if result.galleries_created > 0 {
+            result.layout_records.len() as f64 / result.galleries_created as f64
+        } else {
+            0.0
+        };
+        LayoutEstimate {
+            galleries: result.galleries_created,
+            average_art_objects_per_gallery,
+            unplaceable: result.unplaceable_art_object_ids.len(),
+        }
+    }
+}
+
+/// Estimates how big a layout of the given art objects across galleries with
+/// the given walls would be, without writing it anywhere.
+///
+/// This runs `layout()` itself, so the estimate is exact for a layout of the
+/// same art objects in the same order, with a single wall set and without any
+/// reserved walls or rotation.
+pub fn estimate_layout(
+    walls: &[GalleryWall],
+    art_objects: &[ArtObjectLayoutInfo],
+    mode: LayoutMode,
+) -> Result<LayoutEstimate> {
+    let wall_sets = vec![GalleryWallSet::new(DEFAULT_WALL_SET_NAME, walls.to_vec())];
+    let result = layout(
+        &wall_sets,
+        art_objects.to_vec(),
+        &LayoutOptions {
+            mode,
+            ..Default::default()
+        },
+    )?;
+    Ok(LayoutEstimate::from(&result))
+}
+
+/// A themed section of the museum, e.g. portraits, which gets its own
+/// contiguous range of galleries.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LayoutSegment {
+    pub name: String,
+    /// Only art objects matching this filter are laid out in the segment. If
+    /// absent, every art object that isn't in an earlier segment is.
+    #[serde(default)]
+    pub filter: Option<String>,
+}
+
+impl LayoutSegment {
+    /// Returns the filter for this segment's art objects, given a filter that
+    /// applies to every segment.
+    pub fn combined_filter(&self, filter: Option<&str>) -> Option<String> {
+        match (filter, self.filter.as_deref()) {
+            // Terms separated by whitespace are ANDed together.
+            (Some(filter), Some(segment_filter)) => Some(format!("{filter} {segment_filter}")),
+            (Some(filter), None) => Some(filter.to_string()),
+            (None, segment_filter) => segment_filter.map(|filter| filter.to_string()),
+        }
+    }
+}
+
+/// Parses e.g. `name=portrait filter=portrait -monkey`, where everything after
+/// `filter=` is the filter.
+impl FromStr for LayoutSegment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some(rest) = s.trim_start().strip_prefix("name=") else {
+            return Err(anyhow!("Segment {s:?} must start with name="));
+        };
+        let (name, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if name.is_empty() {
+            return Err(anyhow!("Segment {s:?} has an empty name"));
+        }
+        let rest = rest.trim();
+        let filter = if rest.is_empty() {
+            None
+        } else if let Some(filter) = rest.strip_prefix("filter=") {
+            Some(filter.trim().to_string()).filter(|filter| !filter.is_empty())
+        } else {
+            return Err(anyhow!("Expected filter= after name in segment {s:?}"));
+        };
+        Ok(LayoutSegment {
+            name: name.to_string(),
+            filter,
+        })
+    }
+}
+
+/// Lay out each segment's art objects in turn, using `layout()`, so that each
+/// segment gets its own contiguous range of galleries starting right after the
+/// previous one's.
+///
+/// Art objects that are in more than one segment are only laid out in the
+/// first one. Any `ordering` is applied within each segment.
+pub fn layout_segments<'a>(
+    wall_sets: &'a Vec<GalleryWallSet>,
+    segments: Vec<(String, Vec<ArtObjectLayoutInfo>)>,
+    options: &LayoutOptions,
+) -> Result<LayoutResult<'a>> {
+    let mut combined = LayoutResult {
+        galleries_created: 0,
+        layout_records: vec![],
+        gallery_records: vec![],
+        unplaceable_art_object_ids: vec![],
+        unmatched_ordering_ids: vec![],
+        free_regions: vec![],
+        dropped_source_galleries: vec![],
+    };
+    let mut seen_ids: HashSet<ArtObjectId> = HashSet::new();
+    for (name, mut art_objects) in segments {
+        art_objects.retain(|art_object| seen_ids.insert(art_object.id));
+        // Only pass along the part of the ordering that's in this segment, so
+        // `layout()` doesn't complain about the rest.
+        let segment_ordering: Option<Vec<ArtObjectId>> = options.ordering.map(|ordering| {
+            let ids: HashSet<ArtObjectId> = art_objects.iter().map(|object| object.id).collect();
+            ordering
+                .iter()
+                .filter(|id| ids.contains(id))
+                .copied()
+                .collect()
+        });
+        let result = layout(
+            wall_sets,
+            art_objects,
+            &LayoutOptions {
+                gallery_start_id: options.gallery_start_id + combined.galleries_created as i64,
+                ordering: segment_ordering.as_deref(),
+                ..*options
+            },
+        )?;
+        if options.warnings {
+            info!(
+                "Segment {name:?} has {} galleries.",
+                result.galleries_created
+            );
+        }
+        combined.galleries_created += result.galleries_created;
+        combined.layout_records.extend(result.layout_records);
+        combined
+            .gallery_records
+            .extend(
+                result
+                    .gallery_records
+                    .into_iter()
+                    .map(|gallery| GalleryRecord {
+                        segment: Some(name.clone()),
+                        ..gallery
+                    }),
+            );
+        combined
+            .unplaceable_art_object_ids
+            .extend(result.unplaceable_art_object_ids);
+        combined.free_regions.extend(result.free_regions);
+    }
+    if let Some(ordering) = options.ordering {
+        combined.unmatched_ordering_ids = ordering
+            .iter()
+            .filter(|id| !seen_ids.contains(id))
+            .copied()
+            .collect();
+        if options.warnings {
+            for id in combined.unmatched_ordering_ids.iter() {
+                warn!("Object {:?} in ordering isn't being laid out.", id);
+            }
+        }
+    }
+    Ok(combined)
+}
+
+/// Orders source galleries the way a museum numbers them, so e.g. gallery 99
+/// comes before gallery 100. Ones that aren't numbers come last, alphabetically.
+fn compare_source_galleries(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a_number), Ok(b_number)) => a_number.cmp(&b_number).then_with(|| a.cmp(b)),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+/// Lay out the art objects the way they hang at their museum, for
+/// `LayoutMode::RealGallery`: each distinct source gallery (see
+/// `ArtObjectRecord::source_gallery`) gets a gallery of its own, in the order
+/// they're numbered, with `GalleryRecord::source_gallery` set so the game can
+/// label it. Within each one, art objects are laid out like `Sparse`.
+///
+/// Source galleries whose art objects don't all fit in a single gallery are
+/// left out, see `LayoutResult::dropped_source_galleries`. Art objects without
+/// a source gallery are laid out in the galleries after all the rest.
+///
+/// Note that `options.mode` is ignored, since this is what
+/// `LayoutMode::RealGallery` means.
+pub fn layout_source_galleries<'a>(
+    wall_sets: &'a Vec<GalleryWallSet>,
+    art_objects: Vec<ArtObjectLayoutInfo>,
+    source_galleries: &HashMap<ArtObjectId, String>,
+    options: &LayoutOptions,
+) -> Result<LayoutResult<'a>> {
+    let (art_objects, unmatched_ordering_ids) = match options.ordering {
+        Some(ordering) => apply_ordering(art_objects, ordering),
+        None => (art_objects, vec![]),
+    };
+    if options.warnings {
+        for id in unmatched_ordering_ids.iter() {
+            warn!("Object {:?} in ordering isn't being laid out.", id);
+        }
+    }
+    let mut groups: HashMap<&str, Vec<ArtObjectLayoutInfo>> = HashMap::new();
+    let mut overflow = vec![];
+    for art_object in art_objects {
+        match source_galleries.get(&art_object.id) {
+            Some(source_gallery) => groups
+                .entry(source_gallery.as_str())
+                .or_default()
+                .push(art_object),
+            None => overflow.push(art_object),
+        }
+    }
+    let mut groups: Vec<(&str, Vec<ArtObjectLayoutInfo>)> = groups.into_iter().collect();
+    groups.sort_by(|(a, _), (b, _)| compare_source_galleries(a, b));
+
+    let mut combined = LayoutResult {
+        galleries_created: 0,
+        layout_records: vec![],
+        gallery_records: vec![],
+        unplaceable_art_object_ids: vec![],
+        unmatched_ordering_ids,
+        free_regions: vec![],
+        dropped_source_galleries: vec![],
+    };
+    let groups = groups
+        .into_iter()
+        .map(|(source_gallery, art_objects)| (Some(source_gallery), art_objects))
+        .chain([(None, overflow)]);
+    for (source_gallery, art_objects) in groups {
+        let start_id = options.gallery_start_id + combined.galleries_created as i64;
+        let mut result = layout(
+            wall_sets,
+            art_objects,
+            &LayoutOptions {
+                mode: LayoutMode::RealGallery,
+                gallery_start_id: start_id,
+                ordering: None,
+                ..*options
+            },
+        )?;
+        // When the art runs out on a gallery's last wall, `layout()` counts the
+        // empty gallery after it too, which would make a source gallery that
+        // exactly fills one gallery look like it needs two.
+        let galleries_used = result
+            .layout_records
+            .iter()
+            .map(|record| (record.gallery_id - start_id) as usize + 1)
+            .max()
+            .unwrap_or_default();
+        result.galleries_created = galleries_used;
+        result.gallery_records.truncate(galleries_used);
+        combined
+            .unplaceable_art_object_ids
+            .extend(result.unplaceable_art_object_ids);
+        if let Some(source_gallery) = source_gallery {
+            if result.galleries_created > 1 {
+                warn!(
+                    "Source gallery {source_gallery:?} needs {} galleries, leaving it out.",
+                    result.galleries_created
+                );
+                combined
+                    .dropped_source_galleries
+                    .push(source_gallery.to_string());
+                continue;
+            }
+        }
+        combined.galleries_created += result.galleries_created;
+        combined.layout_records.extend(result.layout_records);
+        combined
+            .gallery_records
+            .extend(
+                result
+                    .gallery_records
+                    .into_iter()
+                    .map(|gallery| GalleryRecord {
+                        source_gallery: source_gallery.map(str::to_string),
+                        ..gallery
+                    }),
+            );
+        combined.free_regions.extend(result.free_regions);
+    }
+    Ok(combined)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::{
+        art_object::ArtObjectId,
+        gallery_db::ArtObjectLayoutInfo,
+        gallery_wall::{GalleryWall, GalleryWallSet},
+        random::Rng,
+    };
+
+    use super::{
+        apply_ordering, balance_by_collection, compare_source_galleries, estimate_layout,
+        find_unplaceable_objects, layout, layout_segments, layout_source_galleries,
+        merge_free_regions, move_highlights_to_front, ArtObjectLayoutFitter, CollectionWeight,
+        FreeRegion, LayoutEstimate, LayoutMode, LayoutOptions, LayoutResult, LayoutSegment,
+        DEFAULT_SALON_GUTTER, SALON_MAX_ART_OBJECT_SIZE,
+    };
+
+    fn make_wall_set(name: &str, wall_names: &[&str], width: f64, height: f64) -> GalleryWallSet {
+        GalleryWallSet::new(
+            name,
+            wall_names
+                .iter()
+                .map(|wall_name| GalleryWall {
+                    name: wall_name.to_string(),
+                    width,
+                    height,
+                    index: None,
+                })
+                .collect(),
+        )
+    }
+
+    fn make_art_objects(count: i64) -> Vec<ArtObjectLayoutInfo> {
+        (1..=count)
+            .map(|id| ArtObjectLayoutInfo {
+                id: ArtObjectId::Met(id),
+                width: 1.0,
+                height: 1.0,
+                highlight: false,
+                collection: String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_layout_cycles_through_wall_sets() {
+        let wall_sets = vec![
+            make_wall_set("big", &["big_01", "big_02", "big_03"], 10.0, 4.0),
+            make_wall_set("small", &["small_01"], 4.0, 3.0),
+        ];
+        let LayoutResult {
+            galleries_created,
+            layout_records,
+            gallery_records,
+            ..
+        } = layout(&wall_sets, make_art_objects(50), &LayoutOptions::default()).unwrap();
+
+        assert!(galleries_created >= 2);
+        assert_eq!(gallery_records.len(), galleries_created);
+        for (i, gallery) in gallery_records.iter().enumerate() {
+            assert_eq!(gallery.gallery_id, i as i64 + 1);
+            assert_eq!(gallery.wall_set, if i % 2 == 0 { "big" } else { "small" });
+        }
+        for record in layout_records.iter() {
+            let gallery = gallery_records
+                .iter()
+                .find(|gallery| gallery.gallery_id == record.gallery_id)
+                .unwrap();
+            let wall_set = wall_sets
+                .iter()
+                .find(|wall_set| wall_set.name == gallery.wall_set)
+                .unwrap();
+            assert!(
+                wall_set
+                    .walls
+                    .iter()
+                    .any(|wall| wall.name == record.wall_id),
+                "{} is not a wall in {}",
+                record.wall_id,
+                wall_set.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_truncate_galleries_works() {
+        let wall_sets = vec![make_wall_set("small", &["small_01"], 4.0, 3.0)];
+        let mut result = layout(
+            &wall_sets,
+            make_art_objects(50),
+            &LayoutOptions {
+                gallery_start_id: 3,
+                collect_free_regions: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(result.galleries_created > 2);
+
+        result.truncate_galleries(4);
+        assert_eq!(result.galleries_created, 2);
+        let gallery_ids: Vec<i64> = result
+            .gallery_records
+            .iter()
+            .map(|gallery| gallery.gallery_id)
+            .collect();
+        assert_eq!(gallery_ids, vec![3, 4]);
+        assert!(!result.layout_records.is_empty());
+        assert!(result
+            .layout_records
+            .iter()
+            .all(|record| record.gallery_id <= 4));
+        assert!(result
+            .free_regions
+            .iter()
+            .all(|region| region.gallery_id <= 4));
+    }
+
+    #[test]
+    fn test_layout_rejects_empty_wall_sets() {
+        let wall_sets = vec![make_wall_set("empty", &[], 10.0, 4.0)];
+        assert!(layout(&wall_sets, make_art_objects(1), &LayoutOptions::default()).is_err());
+    }
+
+    fn make_art_objects_with_huge_painting() -> Vec<ArtObjectLayoutInfo> {
+        let mut art_objects = make_art_objects(3);
+        art_objects.insert(
+            1,
+            ArtObjectLayoutInfo {
+                id: ArtObjectId::Met(100),
+                width: 10.0,
+                height: 1.0,
+                highlight: false,
+                collection: String::new(),
+            },
+        );
+        art_objects
+    }
+
+    #[test]
+    fn test_find_unplaceable_objects_works() {
+        let wall_set = make_wall_set("small", &["small_01", "small_02"], 4.0, 3.0);
+        let walls: Vec<&GalleryWall> = wall_set.walls.iter().collect();
+        assert_eq!(
+            find_unplaceable_objects(&make_art_objects_with_huge_painting(), &walls, false),
+            vec![ArtObjectId::Met(100)]
+        );
+        assert_eq!(
+            find_unplaceable_objects(&make_art_objects(3), &walls, false),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_layout_excludes_unplaceable_objects() {
+        let wall_sets = vec![make_wall_set("small", &["small_01", "small_02"], 4.0, 3.0)];
+        let result = layout(
+            &wall_sets,
+            make_art_objects_with_huge_painting(),
+            &LayoutOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            result.unplaceable_art_object_ids,
+            vec![ArtObjectId::Met(100)]
+        );
+        assert_eq!(result.layout_records.len(), 3);
+        assert!(result
+            .layout_records
+            .iter()
+            .all(|record| record.art_object_id != ArtObjectId::Met(100)));
+    }
+
+    fn make_art_objects_with_scroll() -> Vec<ArtObjectLayoutInfo> {
+        let mut art_objects = make_art_objects(2);
+        art_objects.push(ArtObjectLayoutInfo {
+            id: ArtObjectId::Met(100),
+            width: 0.4,
+            height: 3.0,
+            highlight: false,
+            collection: String::new(),
+        });
+        art_objects
+    }
+
+    #[test]
+    fn test_layout_rotates_objects_only_if_allowed() {
+        let wall_sets = vec![make_wall_set("low", &["low_01", "low_02"], 10.0, 2.5)];
+        let layout_scroll = |allow_rotation: bool| {
+            layout(
+                &wall_sets,
+                make_art_objects_with_scroll(),
+                &LayoutOptions {
+                    allow_rotation,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let result = layout_scroll(false);
+        assert_eq!(
+            result.unplaceable_art_object_ids,
+            vec![ArtObjectId::Met(100)]
+        );
+        assert_eq!(result.layout_records.len(), 2);
+        assert!(result.layout_records.iter().all(|record| !record.rotated));
+
+        let result = layout_scroll(true);
+        assert_eq!(result.unplaceable_art_object_ids, vec![]);
+        assert_eq!(result.layout_records.len(), 3);
+        for record in result.layout_records {
+            // Art objects that fit upright are never rotated.
+            assert_eq!(
+                record.rotated,
+                record.art_object_id == ArtObjectId::Met(100),
+                "{record:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_unplaceable_objects_considers_rotation() {
+        let wall_set = make_wall_set("low", &["low_01"], 10.0, 2.5);
+        let walls: Vec<&GalleryWall> = wall_set.walls.iter().collect();
+        assert_eq!(
+            find_unplaceable_objects(&make_art_objects_with_scroll(), &walls, false),
+            vec![ArtObjectId::Met(100)]
+        );
+        assert_eq!(
+            find_unplaceable_objects(&make_art_objects_with_scroll(), &walls, true),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_apply_ordering_works() {
+        let ids = |art_objects: &[ArtObjectLayoutInfo]| -> Vec<ArtObjectId> {
+            art_objects.iter().map(|art_object| art_object.id).collect()
+        };
+        let (art_objects, unmatched) = apply_ordering(
+            make_art_objects(4),
+            &[
+                ArtObjectId::Met(3),
+                ArtObjectId::Met(100),
+                ArtObjectId::Met(1),
+                ArtObjectId::Met(3),
+            ],
+        );
+        assert_eq!(
+            ids(&art_objects),
+            vec![
+                ArtObjectId::Met(3),
+                ArtObjectId::Met(1),
+                ArtObjectId::Met(2),
+                ArtObjectId::Met(4)
+            ]
+        );
+        assert_eq!(unmatched, vec![ArtObjectId::Met(100)]);
+    }
+
+    #[test]
+    fn test_layout_honors_ordering() {
+        let wall_sets = vec![make_wall_set(
+            "big",
+            &["big_01", "big_02", "big_03"],
+            10.0,
+            4.0,
+        )];
+        let ordering: Vec<ArtObjectId> = [17, 3, 42, 8, 25]
+            .into_iter()
+            .map(ArtObjectId::Met)
+            .collect();
+        let result = layout(
+            &wall_sets,
+            make_art_objects(50),
+            &LayoutOptions {
+                mode: LayoutMode::Dense,
+                ordering: Some(&ordering),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.layout_records.len(), 50);
+        let first_ids: Vec<ArtObjectId> = result.layout_records[..ordering.len()]
+            .iter()
+            .map(|record| record.art_object_id)
+            .collect();
+        assert_eq!(first_ids, ordering);
+        assert_eq!(result.layout_records[0].gallery_id, 1);
+        assert_eq!(result.layout_records[0].wall_id, "big_01");
+        assert!(result.unmatched_ordering_ids.is_empty());
+    }
+
+    #[test]
+    fn test_skipped_art_objects_are_placed_in_order() {
+        let wall = GalleryWall {
+            name: "wall".into(),
+            width: 10.0,
+            height: 3.0,
+            index: None,
+        };
+        let walls = vec![&wall];
+        let mut art_objects = make_art_objects(4);
+        for art_object in &mut art_objects[..3] {
+            art_object.width = 3.0;
+        }
+        art_objects.reverse();
+        let mut fitter = ArtObjectLayoutFitter::new(art_objects, false, false);
+        let mut next_id = |max_width: f64| {
+            fitter
+                .get_object_fitting_in(max_width, 2.0, &walls)
+                .map(|(art_object, _)| art_object.id)
+        };
+
+        // Met 1 through 3 are too wide, so they're skipped over until Met 4
+        // fits, and are then placed in their original order once they fit.
+        assert_eq!(next_id(2.0), Some(ArtObjectId::Met(4)));
+        assert_eq!(next_id(4.0), Some(ArtObjectId::Met(1)));
+        assert_eq!(next_id(4.0), Some(ArtObjectId::Met(2)));
+        assert_eq!(next_id(4.0), Some(ArtObjectId::Met(3)));
+        assert_eq!(next_id(4.0), None);
+    }
+
+    #[test]
+    fn test_layout_leaves_reserved_walls_empty() {
+        let wall_sets = vec![
+            make_wall_set("big", &["big_01", "big_02", "wall_04"], 10.0, 4.0),
+            make_wall_set("small", &["small_01", "wall_04"], 4.0, 3.0),
+        ];
+        let result = layout(
+            &wall_sets,
+            make_art_objects(100),
+            &LayoutOptions {
+                mode: LayoutMode::Dense,
+                reserved_walls: &["wall_04".to_string()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.layout_records.len(), 100);
+        assert!(result.galleries_created >= 2);
+        assert!(result
+            .layout_records
+            .iter()
+            .all(|record| record.wall_id != "wall_04"));
+        for wall_id in ["big_01", "big_02", "small_01"] {
+            assert!(
+                result
+                    .layout_records
+                    .iter()
+                    .any(|record| record.wall_id == wall_id),
+                "{wall_id} should have art on it"
+            );
+        }
+        for gallery in result.gallery_records.iter() {
+            assert_eq!(gallery.reserved_walls, vec!["wall_04".to_string()]);
+        }
+    }
+
+    #[test]
+    fn test_layout_rejects_wall_sets_with_only_reserved_walls() {
+        let wall_sets = vec![make_wall_set("small", &["small_01", "small_02"], 4.0, 3.0)];
+        let reserved_walls = vec!["small_01".to_string(), "small_02".to_string()];
+        assert!(layout(
+            &wall_sets,
+            make_art_objects(1),
+            &LayoutOptions {
+                reserved_walls: &reserved_walls,
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_layout_segments_works() {
+        let wall_sets = vec![make_wall_set("small", &["small_01", "small_02"], 4.0, 3.0)];
+        // Objects 5 through 8 match both segments.
+        let portraits = make_art_objects(8);
+        let everything = make_art_objects(20).split_off(4);
+        let result = layout_segments(
+            &wall_sets,
+            vec![
+                ("portraits".to_string(), portraits),
+                ("everything".to_string(), everything),
+            ],
+            &LayoutOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(result.layout_records.len(), 20);
+        let placed_ids: HashSet<ArtObjectId> = result
+            .layout_records
+            .iter()
+            .map(|record| record.art_object_id)
+            .collect();
+        assert_eq!(placed_ids.len(), 20, "art objects should be placed once");
+
+        assert_eq!(result.gallery_records.len(), result.galleries_created);
+        let segment_of = |gallery_id: i64| {
+            result
+                .gallery_records
+                .iter()
+                .find(|gallery| gallery.gallery_id == gallery_id)
+                .unwrap()
+                .segment
+                .clone()
+                .unwrap()
+        };
+        // Each segment's galleries should be contiguous, starting at 1.
+        let segments: Vec<String> = result
+            .gallery_records
+            .iter()
+            .map(|gallery| gallery.segment.clone().unwrap())
+            .collect();
+        let first_everything = segments
+            .iter()
+            .position(|segment| segment == "everything")
+            .unwrap();
+        assert!(first_everything > 0);
+        assert!(segments[..first_everything]
+            .iter()
+            .all(|segment| segment == "portraits"));
+        assert!(segments[first_everything..]
+            .iter()
+            .all(|segment| segment == "everything"));
+        for (i, gallery) in result.gallery_records.iter().enumerate() {
+            assert_eq!(gallery.gallery_id, i as i64 + 1);
+        }
+
+        // First segment wins.
+        for record in result.layout_records.iter() {
+            let ArtObjectId::Met(id) = record.art_object_id else {
+                unreachable!();
+            };
+            let expected = if id <= 8 { "portraits" } else { "everything" };
+            assert_eq!(segment_of(record.gallery_id), expected, "object {id}");
+        }
+    }
+
+    #[test]
+    fn test_layout_segments_reports_unmatched_ordering_ids() {
+        let wall_sets = vec![make_wall_set("small", &["small_01", "small_02"], 4.0, 3.0)];
+        let result = layout_segments(
+            &wall_sets,
+            vec![
+                ("a".to_string(), make_art_objects(2)),
+                ("b".to_string(), make_art_objects(4).split_off(2)),
+            ],
+            &LayoutOptions {
+                ordering: Some(&[
+                    ArtObjectId::Met(4),
+                    ArtObjectId::Met(100),
+                    ArtObjectId::Met(2),
+                ]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.unmatched_ordering_ids, vec![ArtObjectId::Met(100)]);
+        assert_eq!(result.layout_records[0].art_object_id, ArtObjectId::Met(2));
+    }
+
+    #[test]
+    fn test_layout_segment_from_str_works() {
+        assert_eq!(
+            "name=portrait filter=portrait -monkey"
+                .parse::<LayoutSegment>()
+                .unwrap(),
+            LayoutSegment {
+                name: "portrait".to_string(),
+                filter: Some("portrait -monkey".to_string()),
+            }
+        );
+        assert_eq!(
+            "name=rest".parse::<LayoutSegment>().unwrap(),
+            LayoutSegment {
+                name: "rest".to_string(),
+                filter: None,
+            }
+        );
+        assert!("filter=portrait".parse::<LayoutSegment>().is_err());
+        assert!("name= filter=portrait".parse::<LayoutSegment>().is_err());
+        assert!("name=portrait boop".parse::<LayoutSegment>().is_err());
+    }
+
+    #[test]
+    fn test_layout_segment_combined_filter_works() {
+        let segment = LayoutSegment {
+            name: "portrait".to_string(),
+            filter: Some("portrait".to_string()),
+        };
+        assert_eq!(
+            segment.combined_filter(Some("oil")),
+            Some("oil portrait".to_string())
+        );
+        assert_eq!(segment.combined_filter(None), Some("portrait".to_string()));
+    }
+
+    #[test]
+    fn test_layout_result_anchors_works() {
+        let wall_sets = vec![make_wall_set("small", &["small_01", "small_02"], 4.0, 3.0)];
+        let result = layout(&wall_sets, make_art_objects(3), &LayoutOptions::default()).unwrap();
+        let anchors = result.anchors(&wall_sets);
+        assert_eq!(anchors.len(), 3);
+        for (record, (id, anchor)) in result.layout_records.iter().zip(anchors) {
+            assert_eq!(record.art_object_id, id);
+            assert!((anchor.x * 4.0 - record.x).abs() < 1e-9);
+            assert!((anchor.y * 3.0 - record.y).abs() < 1e-9);
+        }
+        assert_eq!(result.anchors(&[]), vec![]);
+    }
+
+    fn free_region(wall_id: &str, x_start: f64, x_end: f64, height: f64) -> FreeRegion<&str> {
+        FreeRegion {
+            gallery_id: 1,
+            wall_id,
+            x_start,
+            x_end,
+            height_available: height,
+        }
+    }
+
+    fn layout_free_regions<'a>(
+        wall_sets: &'a Vec<GalleryWallSet>,
+        art_object_count: i64,
+        reserved_walls: &[String],
+        collect_free_regions: bool,
+    ) -> Vec<FreeRegion<&'a str>> {
+        layout(
+            wall_sets,
+            make_art_objects(art_object_count),
+            &LayoutOptions {
+                reserved_walls,
+                collect_free_regions,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .free_regions
+    }
+
+    #[test]
+    fn test_layout_reports_free_regions_flanking_painting() {
+        let wall_sets = vec![make_wall_set("big", &["big_01"], 10.0, 4.0)];
+        assert_eq!(
+            layout_free_regions(&wall_sets, 1, &[], true),
+            vec![
+                free_region("big_01", 0.0, 4.5, 4.0),
+                free_region("big_01", 5.5, 10.0, 4.0),
+            ]
+        );
+        assert_eq!(layout_free_regions(&wall_sets, 1, &[], false), vec![]);
+    }
+
+    #[test]
+    fn test_layout_reports_unused_walls_as_free_regions() {
+        let wall_sets = vec![make_wall_set(
+            "big",
+            &["big_01", "big_02", "big_03"],
+            10.0,
+            4.0,
+        )];
+        assert_eq!(
+            layout_free_regions(&wall_sets, 1, &["big_03".to_string()], true),
+            vec![
+                free_region("big_01", 0.0, 4.5, 4.0),
+                free_region("big_01", 5.5, 10.0, 4.0),
+                free_region("big_02", 0.0, 10.0, 4.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_free_regions_works() {
+        let regions = vec![
+            free_region("a", 5.0, 5.4, 3.0),
+            free_region("a", 0.5, 3.0, 3.0),
+            free_region("a", 0.0, 0.5, 2.0),
+            free_region("b", 0.0, 0.5, 3.0),
+            free_region("b", 1.0, 2.0, 3.0),
+            free_region("b", 2.0, 4.0, 3.0),
+        ];
+        assert_eq!(
+            merge_free_regions(regions, 1.0),
+            vec![
+                free_region("a", 0.0, 3.0, 2.0),
+                free_region("b", 1.0, 2.0, 3.0),
+                free_region("b", 2.0, 4.0, 3.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_highlights_are_laid_out_first() {
+        let wall_sets = vec![make_wall_set(
+            "big",
+            &["big_01", "big_02", "big_03"],
+            10.0,
+            4.0,
+        )];
+        let highlight_ids = [40, 7, 23];
+        let mut art_objects = make_art_objects(50);
+        for art_object in art_objects.iter_mut() {
+            art_object.highlight = highlight_ids.contains(&art_object.id.to_raw_i64());
+        }
+        move_highlights_to_front(&mut art_objects);
+        let first_ids: Vec<ArtObjectId> = art_objects[..4].iter().map(|object| object.id).collect();
+        // Highlights keep their relative order, as does everything else.
+        assert_eq!(
+            first_ids,
+            vec![
+                ArtObjectId::Met(7),
+                ArtObjectId::Met(23),
+                ArtObjectId::Met(40),
+                ArtObjectId::Met(1)
+            ]
+        );
+
+        let result = layout(
+            &wall_sets,
+            art_objects,
+            &LayoutOptions {
+                mode: LayoutMode::Dense,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.layout_records.len(), 50);
+        let first_records = &result.layout_records[..highlight_ids.len()];
+        for record in first_records {
+            assert_eq!(record.gallery_id, 1);
+            assert!(highlight_ids.contains(&record.art_object_id.to_raw_i64()));
+        }
+    }
+
+    /// Returns `count` art objects in the given collection, with IDs starting
+    /// at `first_id`.
+    fn make_collection_art_objects(
+        collection: &str,
+        first_id: i64,
+        count: i64,
+    ) -> Vec<ArtObjectLayoutInfo> {
+        (first_id..first_id + count)
+            .map(|id| ArtObjectLayoutInfo {
+                id: ArtObjectId::Met(id),
+                width: 1.0,
+                height: 1.0,
+                highlight: false,
+                collection: collection.to_string(),
+            })
+            .collect()
+    }
+
+    /// Two collections of very different sizes, with the big one first, like
+    /// the Met and wikidata.
+    fn make_unbalanced_art_objects() -> Vec<ArtObjectLayoutInfo> {
+        let mut art_objects = make_collection_art_objects("Big Museum", 1, 1000);
+        art_objects.extend(make_collection_art_objects("Small Museum", 5000, 100));
+        art_objects
+    }
+
+    /// Returns how many of the first `count` art objects are in the small museum.
+    fn count_small(art_objects: &[ArtObjectLayoutInfo], count: usize) -> usize {
+        art_objects[..count]
+            .iter()
+            .filter(|art_object| art_object.collection == "Small Museum")
+            .count()
+    }
+
+    #[test]
+    fn test_collection_weight_parsing_works() {
+        assert_eq!(
+            "The Metropolitan Museum of Art=2.5"
+                .parse::<CollectionWeight>()
+                .unwrap(),
+            CollectionWeight {
+                collection: "The Metropolitan Museum of Art".into(),
+                weight: 2.5,
+            }
+        );
+        assert_eq!(
+            "a=b=1".parse::<CollectionWeight>().unwrap().collection,
+            "a=b"
+        );
+        for invalid in [
+            "met", "=1", "met=", "met=boop", "met=0", "met=-1", "met=inf",
+        ] {
+            assert!(invalid.parse::<CollectionWeight>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_balance_by_collection_interleaves_equally_by_default() {
+        let balanced = balance_by_collection(make_unbalanced_art_objects(), &[], None);
+        assert_eq!(balanced.len(), 1100);
+        assert_eq!(count_small(&balanced, 200), 100);
+        // Once the small museum runs out, the big one fills the rest.
+        assert_eq!(count_small(&balanced, 1100), 100);
+        assert_eq!(balanced[0].id, ArtObjectId::Met(1));
+        assert_eq!(balanced[1].id, ArtObjectId::Met(5000));
+        assert_eq!(balanced[2].id, ArtObjectId::Met(2));
+    }
+
+    #[test]
+    fn test_balance_by_collection_follows_weights() {
+        let weights = vec![
+            "big museum=3".parse::<CollectionWeight>().unwrap(),
+            "Small Museum=1".parse::<CollectionWeight>().unwrap(),
+        ];
+        let mut rng = Rng::new(Some(1234));
+        let balanced =
+            balance_by_collection(make_unbalanced_art_objects(), &weights, Some(&mut rng));
+        for count in [40, 100, 400] {
+            let ratio = count_small(&balanced, count) as f64 / count as f64;
+            assert!(
+                (ratio - 0.25).abs() < 0.02,
+                "{ratio} of the first {count} are from the small museum"
+            );
+        }
+    }
+
+    #[test]
+    fn test_balance_by_collection_is_deterministic_per_seed() {
+        let ids = |seed: u64, small_first: bool| -> Vec<ArtObjectId> {
+            let mut art_objects = make_unbalanced_art_objects();
+            if small_first {
+                // Each collection's art objects stay in the same order.
+                art_objects.rotate_left(1000);
+            }
+            let mut rng = Rng::new(Some(seed));
+            balance_by_collection(art_objects, &[], Some(&mut rng))
+                .iter()
+                .map(|art_object| art_object.id)
+                .collect()
+        };
+        assert_eq!(ids(1, false), ids(1, true));
+        assert_ne!(ids(1, false), ids(2, false));
+        // Shuffling only happens within collections.
+        let shuffled = ids(1, false);
+        let small: Vec<i64> = shuffled
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .take(100)
+            .map(|id| id.to_raw_i64())
+            .collect();
+        assert!(small.iter().all(|&id| id >= 5000));
+        assert!(small.windows(2).any(|pair| pair[0] > pair[1]));
+    }
+
+    /// Lays out the given art objects salon-style on a single 10x4 wall.
+    fn layout_salon<'a>(
+        wall_sets: &'a Vec<GalleryWallSet>,
+        art_objects: Vec<ArtObjectLayoutInfo>,
+    ) -> LayoutResult<'a> {
+        layout(
+            wall_sets,
+            art_objects,
+            &LayoutOptions {
+                mode: LayoutMode::Salon {
+                    gutter: DEFAULT_SALON_GUTTER,
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_salon_layout_packs_small_art_objects_in_grid() {
+        let wall_sets = vec![make_wall_set("default", &["wall_1"], 10.0, 4.0)];
+        let sizes = [(0.5, 0.4), (0.3, 0.6), (0.8, 0.5), (0.4, 0.4), (0.6, 0.3)];
+        let art_objects: Vec<ArtObjectLayoutInfo> = (0..20)
+            .map(|i| {
+                let (width, height) = sizes[i % sizes.len()];
+                ArtObjectLayoutInfo {
+                    id: ArtObjectId::Met(i as i64 + 1),
+                    width,
+                    height,
+                    highlight: false,
+                    collection: String::new(),
+                }
+            })
+            .collect();
+        let result = layout_salon(&wall_sets, art_objects.clone());
+        assert_eq!(result.layout_records.len(), 20);
+        assert!(result
+            .layout_records
+            .iter()
+            .all(|record| record.gallery_id == 1 && record.wall_id == "wall_1"));
+
+        // The left, bottom, right and top of each art object as hung.
+        let rects: Vec<(f64, f64, f64, f64)> = result
+            .layout_records
+            .iter()
+            .map(|record| {
+                let object = art_objects
+                    .iter()
+                    .find(|object| object.id == record.art_object_id)
+                    .unwrap();
+                (
+                    record.x - object.width / 2.0,
+                    record.y - object.height / 2.0,
+                    record.x + object.width / 2.0,
+                    record.y + object.height / 2.0,
+                )
+            })
+            .collect();
+        let epsilon = 1e-9;
+        for &(left, bottom, right, top) in rects.iter() {
+            assert!(left >= 0.0 && right <= 10.0 && bottom >= 0.0 && top <= 4.0);
+        }
+        for (i, a) in rects.iter().enumerate() {
+            for b in rects[i + 1..].iter() {
+                let overlaps = a.0 < b.2 - epsilon
+                    && b.0 < a.2 - epsilon
+                    && a.1 < b.3 - epsilon
+                    && b.1 < a.3 - epsilon;
+                assert!(!overlaps, "{a:?} overlaps {b:?}");
+            }
+        }
+
+        // Art objects in each row share a bottom.
+        let mut rows: Vec<Vec<(f64, f64, f64, f64)>> = vec![];
+        for rect in rects {
+            match rows
+                .iter_mut()
+                .find(|row| (row[0].1 - rect.1).abs() < epsilon)
+            {
+                Some(row) => row.push(rect),
+                None => rows.push(vec![rect]),
+            }
+        }
+        assert!(rows.len() > 1);
+        rows.sort_by(|a, b| a[0].1.total_cmp(&b[0].1));
+        for row in rows.iter_mut() {
+            row.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for pair in row.windows(2) {
+                assert!((pair[1].0 - pair[0].2 - DEFAULT_SALON_GUTTER).abs() < epsilon);
+            }
+        }
+        for pair in rows.windows(2) {
+            let below_top = pair[0].iter().map(|rect| rect.3).fold(0.0, f64::max);
+            assert!((pair[1][0].1 - below_top - DEFAULT_SALON_GUTTER).abs() < epsilon);
+        }
+    }
+
+    #[test]
+    fn test_salon_layout_falls_back_for_large_art_objects() {
+        let wall_sets = vec![make_wall_set("default", &["wall_1", "wall_2"], 6.0, 3.0)];
+        let art_objects: Vec<ArtObjectLayoutInfo> = make_art_objects(6)
+            .into_iter()
+            .map(|object| ArtObjectLayoutInfo {
+                width: SALON_MAX_ART_OBJECT_SIZE * 1.5,
+                ..object
+            })
+            .collect();
+        let sparse = layout(&wall_sets, art_objects.clone(), &LayoutOptions::default()).unwrap();
+        assert_eq!(
+            layout_salon(&wall_sets, art_objects).layout_records,
+            sparse.layout_records
+        );
+    }
+
+    #[test]
+    fn test_layout_mode_parsing_works() {
+        assert_eq!("dense".parse::<LayoutMode>().unwrap(), LayoutMode::Dense);
+        assert_eq!(
+            "salon".parse::<LayoutMode>().unwrap(),
+            LayoutMode::Salon {
+                gutter: DEFAULT_SALON_GUTTER
+            }
+        );
+        assert!("cozy".parse::<LayoutMode>().is_err());
+        assert_eq!(
+            serde_json::from_str::<LayoutMode>(r#"{"salon": {}}"#).unwrap(),
+            LayoutMode::Salon {
+                gutter: DEFAULT_SALON_GUTTER
+            }
+        );
+        assert_eq!(LayoutMode::from_dense(false).name(), "sparse");
+        assert_eq!(
+            "real-gallery".parse::<LayoutMode>().unwrap(),
+            LayoutMode::RealGallery
+        );
+        assert_eq!(
+            serde_json::from_str::<LayoutMode>(r#""real-gallery""#).unwrap(),
+            LayoutMode::RealGallery
+        );
+        assert_eq!(LayoutMode::RealGallery.name(), "real-gallery");
+    }
+
+    #[test]
+    fn test_estimate_layout_matches_real_layout() {
+        let wall_set = make_wall_set("default", &["wall_1", "wall_2", "wall_3"], 6.0, 3.0);
+        let mut art_objects = make_art_objects(40);
+        art_objects.push(ArtObjectLayoutInfo {
+            id: ArtObjectId::Met(41),
+            width: 100.0,
+            height: 1.0,
+            highlight: false,
+            collection: String::new(),
+        });
+        for mode in [
+            LayoutMode::Sparse,
+            LayoutMode::Dense,
+            LayoutMode::Salon {
+                gutter: DEFAULT_SALON_GUTTER,
+            },
+        ] {
+            let estimate = estimate_layout(&wall_set.walls, &art_objects, mode).unwrap();
+            let wall_sets = vec![wall_set.clone()];
+            let result = layout(
+                &wall_sets,
+                art_objects.clone(),
+                &LayoutOptions {
+                    mode,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert!(estimate.galleries > 1);
+            assert_eq!(estimate, LayoutEstimate::from(&result));
+            assert_eq!(estimate.galleries, result.galleries_created);
+            assert_eq!(estimate.unplaceable, 1);
+            assert!(
+                (estimate.average_art_objects_per_gallery * estimate.galleries as f64 - 40.0).abs()
+                    < 1e-9
+            );
+        }
+    }
+
+    #[test]
+    fn test_estimate_layout_of_nothing_is_empty() {
+        let wall_set = make_wall_set("default", &["wall_1"], 6.0, 3.0);
+        assert_eq!(
+            estimate_layout(&wall_set.walls, &[], LayoutMode::Sparse).unwrap(),
+            LayoutEstimate::default()
+        );
+    }
+
+    #[test]
+    fn test_compare_source_galleries_works() {
+        let mut source_galleries = vec!["Great Hall", "826", "100", "99", "Balcony"];
+        source_galleries.sort_by(|a, b| compare_source_galleries(a, b));
+        assert_eq!(
+            source_galleries,
+            vec!["99", "100", "826", "Balcony", "Great Hall"]
+        );
+    }
+
+    #[test]
+    fn test_layout_source_galleries_works() {
+        // Each gallery only has room for two art objects.
+        let wall_sets = vec![make_wall_set("small", &["wall_1", "wall_2"], 4.0, 3.0)];
+        let source_galleries: HashMap<ArtObjectId, String> = [
+            (1, "826"),
+            (2, "826"),
+            (3, "825"),
+            (4, "Great Hall"),
+            (5, "Great Hall"),
+            (6, "Great Hall"),
+        ]
+        .into_iter()
+        .map(|(id, source_gallery)| (ArtObjectId::Met(id), source_gallery.to_string()))
+        .collect();
+        let result = layout_source_galleries(
+            &wall_sets,
+            make_art_objects(8),
+            &source_galleries,
+            &LayoutOptions {
+                ordering: Some(&[ArtObjectId::Met(2), ArtObjectId::Met(100)]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(result.galleries_created, 3);
+        assert_eq!(
+            result
+                .gallery_records
+                .iter()
+                .map(|gallery| (gallery.gallery_id, gallery.source_gallery.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![(1, Some("825")), (2, Some("826")), (3, None)]
+        );
+        let placed: Vec<(i64, ArtObjectId)> = result
+            .layout_records
+            .iter()
+            .map(|record| (record.gallery_id, record.art_object_id))
+            .collect();
+        // The ordering still applies within each source gallery.
+        assert_eq!(
+            placed,
+            vec![
+                (1, ArtObjectId::Met(3)),
+                (2, ArtObjectId::Met(2)),
+                (2, ArtObjectId::Met(1)),
+                (3, ArtObjectId::Met(7)),
+                (3, ArtObjectId::Met(8)),
+            ]
+        );
+        assert_eq!(result.dropped_source_galleries, vec!["Great Hall"]);
+        assert_eq!(result.unmatched_ordering_ids, vec![ArtObjectId::Met(100)]);
+    }
 }
@@ -0,0 +1,293 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    path::PathBuf,
+};
+
+use crate::{
+    art_object::ArtObjectId,
+    gallery_db::{ArtObjectLayoutInfo, LayoutRecord},
+    gallery_wall::{GalleryWall, GalleryWallSet},
+};
+
+/// Run the golden tests with this environment variable set to `1`, e.g.
+/// `UPDATE_GOLDENS=1 cargo test golden`, to overwrite the golden files with
+/// the current layouts instead of comparing against them. Only do this when a
+/// change to layout behavior is intentional, and review the diff of the golden
+/// files before committing them.
+pub const UPDATE_GOLDENS_ENV_VAR: &str = "UPDATE_GOLDENS";
+
+/// Positions that differ by less than this are considered the same, so that
+/// floating-point noise doesn't fail the golden tests.
+pub const GOLDEN_EPSILON: f64 = 1e-6;
+
+const GOLDEN_ART_OBJECT_COUNT: i64 = 60;
+
+/// Too wide for any of the golden walls, so it's never placed.
+pub const GOLDEN_UNPLACEABLE_ID: ArtObjectId = ArtObjectId::Met(999);
+
+/// Returns a fixed set of art objects of varied sizes, from small prints to
+/// big canvases, in the order the gallery DB would return them (by ID).
+pub fn make_golden_art_objects() -> Vec<ArtObjectLayoutInfo> {
+    let mut art_objects: Vec<ArtObjectLayoutInfo> = (1..=GOLDEN_ART_OBJECT_COUNT)
+        .map(|i| ArtObjectLayoutInfo {
+            id: if i % 4 == 0 {
+                ArtObjectId::Wikidata(1000 + i)
+            } else {
+                ArtObjectId::Met(i)
+            },
+            // These are arbitrary, but deterministic.
+            width: 0.3 + ((i * 37) % 23) as f64 * 0.1,
+            height: 0.2 + ((i * 53) % 19) as f64 * 0.1,
+            highlight: false,
+            collection: String::new(),
+        })
+        .collect();
+    art_objects.push(ArtObjectLayoutInfo {
+        id: GOLDEN_UNPLACEABLE_ID,
+        width: 12.0,
+        height: 1.0,
+        highlight: false,
+        collection: String::new(),
+    });
+    art_objects.sort_by_key(|art_object| art_object.id.to_raw_i64());
+    art_objects
+}
+
+fn make_walls(names: &[&str], width: f64, height: f64) -> Vec<GalleryWall> {
+    names
+        .iter()
+        .map(|name| GalleryWall {
+            name: name.to_string(),
+            width,
+            height,
+            index: None,
+        })
+        .collect()
+}
+
+/// Returns a fixed set of wall sets: a big gallery with a shorter fourth wall,
+/// and a small one.
+pub fn make_golden_wall_sets() -> Vec<GalleryWallSet> {
+    let mut big_walls = make_walls(&["big_01", "big_02", "big_03"], 10.0, 4.0);
+    big_walls.extend(make_walls(&["big_04"], 6.0, 3.0));
+    vec![
+        GalleryWallSet::new("big", big_walls),
+        GalleryWallSet::new("small", make_walls(&["small_01", "small_02"], 4.0, 3.0)),
+    ]
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    let manifest_dir: PathBuf = env!("CARGO_MANIFEST_DIR").into();
+    manifest_dir
+        .join("..")
+        .join("test_data")
+        .join("layout_goldens")
+        .join(format!("{name}.json"))
+}
+
+fn should_update_goldens() -> bool {
+    std::env::var(UPDATE_GOLDENS_ENV_VAR).as_deref() == Ok("1")
+}
+
+/// Panics with a description of which art objects moved if the given layout
+/// records don't match the golden file with the given name, or overwrites the
+/// golden file if `UPDATE_GOLDENS_ENV_VAR` is set.
+pub fn assert_layout_matches_golden(name: &str, layout_records: &[LayoutRecord<&str>]) {
+    let actual: Vec<LayoutRecord<String>> = layout_records
+        .iter()
+        .map(|record| LayoutRecord {
+            gallery_id: record.gallery_id,
+            wall_id: record.wall_id.to_string(),
+            art_object_id: record.art_object_id,
+            x: record.x,
+            y: record.y,
+            rotated: record.rotated,
+        })
+        .collect();
+    let path = golden_path(name);
+    if should_update_goldens() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let json = serde_json::to_string_pretty(&actual).unwrap();
+        std::fs::write(&path, json + "\n").unwrap();
+        return;
+    }
+    let json = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "Unable to read {}: {e}. Run with {UPDATE_GOLDENS_ENV_VAR}=1 to create it.",
+            path.display()
+        )
+    });
+    let expected: Vec<LayoutRecord<String>> = serde_json::from_str(&json).unwrap();
+    if let Some(diff) = diff_layouts(&expected, &actual) {
+        panic!(
+            "Layout doesn't match {}:\n{diff}If this is intentional, run with \
+            {UPDATE_GOLDENS_ENV_VAR}=1 to update it.",
+            path.display()
+        );
+    }
+}
+
+fn describe(record: &LayoutRecord<String>) -> String {
+    format!(
+        "gallery {} wall {} at ({:.3}, {:.3}){}",
+        record.gallery_id,
+        record.wall_id,
+        record.x,
+        record.y,
+        if record.rotated { " rotated" } else { "" }
+    )
+}
+
+fn is_same_wall(a: &LayoutRecord<String>, b: &LayoutRecord<String>) -> bool {
+    a.gallery_id == b.gallery_id && a.wall_id == b.wall_id && a.rotated == b.rotated
+}
+
+fn is_same_position(a: &LayoutRecord<String>, b: &LayoutRecord<String>) -> bool {
+    is_same_wall(a, b) && (a.x - b.x).abs() < GOLDEN_EPSILON && (a.y - b.y).abs() < GOLDEN_EPSILON
+}
+
+/// Returns a line for each art object that was placed differently in the
+/// actual layout, or `None` if the layouts are the same within `GOLDEN_EPSILON`.
+pub fn diff_layouts(
+    expected: &[LayoutRecord<String>],
+    actual: &[LayoutRecord<String>],
+) -> Option<String> {
+    let mut diff = String::new();
+    let actual_by_id: HashMap<ArtObjectId, &LayoutRecord<String>> = actual
+        .iter()
+        .map(|record| (record.art_object_id, record))
+        .collect();
+    let expected_ids: HashSet<ArtObjectId> =
+        expected.iter().map(|record| record.art_object_id).collect();
+    for expected in expected {
+        let id = expected.art_object_id;
+        let Some(actual) = actual_by_id.get(&id) else {
+            writeln!(
+                diff,
+                "  {id:?} is no longer placed, was on {}",
+                describe(expected)
+            )
+            .unwrap();
+            continue;
+        };
+        if is_same_position(expected, actual) {
+            continue;
+        }
+        if is_same_wall(expected, actual) {
+            writeln!(
+                diff,
+                "  {id:?} moved by ({:+.3}, {:+.3}) on gallery {} wall {}",
+                actual.x - expected.x,
+                actual.y - expected.y,
+                actual.gallery_id,
+                actual.wall_id
+            )
+            .unwrap();
+        } else {
+            writeln!(
+                diff,
+                "  {id:?} moved from {} to {}",
+                describe(expected),
+                describe(actual)
+            )
+            .unwrap();
+        }
+    }
+    for actual in actual {
+        if !expected_ids.contains(&actual.art_object_id) {
+            writeln!(
+                diff,
+                "  {:?} is newly placed on {}",
+                actual.art_object_id,
+                describe(actual)
+            )
+            .unwrap();
+        }
+    }
+    if diff.is_empty() {
+        let ids = |records: &[LayoutRecord<String>]| -> Vec<ArtObjectId> {
+            records.iter().map(|record| record.art_object_id).collect()
+        };
+        if ids(expected) != ids(actual) {
+            writeln!(
+                diff,
+                "  Art objects are placed in the same spots, but in a different order"
+            )
+            .unwrap();
+        }
+    }
+    if diff.is_empty() {
+        None
+    } else {
+        Some(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{art_object::ArtObjectId, gallery_db::LayoutRecord};
+
+    use super::{diff_layouts, make_golden_art_objects, GOLDEN_EPSILON};
+
+    fn make_record(id: i64, wall_id: &str, x: f64, y: f64) -> LayoutRecord<String> {
+        LayoutRecord {
+            gallery_id: 1,
+            wall_id: wall_id.to_string(),
+            art_object_id: ArtObjectId::Met(id),
+            x,
+            y,
+            rotated: false,
+        }
+    }
+
+    #[test]
+    fn test_diff_layouts_ignores_tiny_differences() {
+        let expected = vec![make_record(1, "big_01", 5.0, 1.5)];
+        let actual = vec![make_record(1, "big_01", 5.0 + GOLDEN_EPSILON / 2.0, 1.5)];
+        assert_eq!(diff_layouts(&expected, &actual), None);
+    }
+
+    #[test]
+    fn test_diff_layouts_describes_moves() {
+        let expected = vec![
+            make_record(1, "big_01", 5.0, 1.5),
+            make_record(2, "big_01", 2.0, 1.5),
+            make_record(3, "big_02", 5.0, 1.5),
+        ];
+        let actual = vec![
+            make_record(1, "big_01", 5.25, 1.0),
+            make_record(2, "big_02", 2.0, 1.5),
+            make_record(4, "big_02", 5.0, 1.5),
+        ];
+        assert_eq!(
+            diff_layouts(&expected, &actual).unwrap(),
+            [
+                "  Met(1) moved by (+0.250, -0.500) on gallery 1 wall big_01\n",
+                "  Met(2) moved from gallery 1 wall big_01 at (2.000, 1.500) to gallery 1 wall big_02 at (2.000, 1.500)\n",
+                "  Met(3) is no longer placed, was on gallery 1 wall big_02 at (5.000, 1.500)\n",
+                "  Met(4) is newly placed on gallery 1 wall big_02 at (5.000, 1.500)\n",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_diff_layouts_notices_reordering() {
+        let a = make_record(1, "big_01", 5.0, 1.5);
+        let b = make_record(2, "big_02", 5.0, 1.5);
+        assert!(diff_layouts(&[a.clone(), b.clone()], &[b, a]).is_some());
+    }
+
+    #[test]
+    fn test_golden_art_objects_are_sorted_by_id() {
+        let ids: Vec<i64> = make_golden_art_objects()
+            .iter()
+            .map(|art_object| art_object.id.to_raw_i64())
+            .collect();
+        let mut sorted_ids = ids.clone();
+        sorted_ids.sort();
+        assert_eq!(ids, sorted_ids);
+        assert_eq!(ids.len(), 61);
+    }
+}
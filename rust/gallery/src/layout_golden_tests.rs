@@ -0,0 +1,57 @@
+use crate::{
+    gallery_db::{ArtObjectLayoutInfo, LayoutRecord},
+    gallery_wall::GalleryWallSet,
+    layout::{layout, LayoutMode, LayoutOptions, LayoutResult},
+    layout_fixtures::{
+        assert_layout_matches_golden, make_golden_art_objects, make_golden_wall_sets,
+        GOLDEN_UNPLACEABLE_ID,
+    },
+};
+
+fn layout_golden<'a>(
+    mode: LayoutMode,
+    wall_sets: &'a Vec<GalleryWallSet>,
+    art_objects: Vec<ArtObjectLayoutInfo>,
+) -> LayoutResult<'a> {
+    layout(
+        wall_sets,
+        art_objects,
+        &LayoutOptions {
+            mode,
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+fn check_layout_matches_golden(mode: LayoutMode, name: &str) {
+    let wall_sets = make_golden_wall_sets();
+    let result = layout_golden(mode, &wall_sets, make_golden_art_objects());
+    assert_eq!(
+        result.unplaceable_art_object_ids,
+        vec![GOLDEN_UNPLACEABLE_ID]
+    );
+    assert_layout_matches_golden(name, &result.layout_records);
+}
+
+#[test]
+fn test_dense_layout_with_default_sort_matches_golden() {
+    check_layout_matches_golden(LayoutMode::Dense, "dense_default_sort");
+}
+
+#[test]
+fn test_sparse_layout_with_default_sort_matches_golden() {
+    check_layout_matches_golden(LayoutMode::Sparse, "sparse_default_sort");
+}
+
+#[test]
+fn test_layout_is_deterministic() {
+    let wall_sets = make_golden_wall_sets();
+    for mode in [LayoutMode::Dense, LayoutMode::Sparse] {
+        let layouts: Vec<Vec<LayoutRecord<&str>>> = (0..3)
+            .map(|_| layout_golden(mode, &wall_sets, make_golden_art_objects()).layout_records)
+            .collect();
+        assert_eq!(layouts[0], layouts[1]);
+        assert_eq!(layouts[0], layouts[2]);
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+
+use crate::{
+    art_object::ArtObjectId,
+    gallery_db::{ArtObjectLayoutInfo, LayoutRecord},
+    gallery_wall::GalleryWallSet,
+    layout::LayoutResult,
+};
+
+/// Walls with less than this fraction of their width covered by art count as
+/// near-empty.
+pub const NEAR_EMPTY_WALL_OCCUPANCY: f64 = 0.1;
+
+pub const DEFAULT_OCCUPANCY_VARIANCE_WEIGHT: f64 = 10.0;
+
+pub const DEFAULT_NEAR_EMPTY_WALL_WEIGHT: f64 = 1.0;
+
+pub const DEFAULT_UNPLACEABLE_WEIGHT: f64 = 5.0;
+
+pub const DEFAULT_SAME_ARTIST_WEIGHT: f64 = 0.5;
+
+/// How much each part of a `LayoutScore` counts towards its total. A negative
+/// weight rewards rather than penalizes, e.g. a negative `same_artist_neighbors`
+/// weight keeps works by the same artist together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayoutScoreWeights {
+    pub occupancy_variance: f64,
+    pub near_empty_walls: f64,
+    pub unplaceable: f64,
+    pub same_artist_neighbors: f64,
+}
+
+impl Default for LayoutScoreWeights {
+    fn default() -> Self {
+        LayoutScoreWeights {
+            occupancy_variance: DEFAULT_OCCUPANCY_VARIANCE_WEIGHT,
+            near_empty_walls: DEFAULT_NEAR_EMPTY_WALL_WEIGHT,
+            unplaceable: DEFAULT_UNPLACEABLE_WEIGHT,
+            same_artist_neighbors: DEFAULT_SAME_ARTIST_WEIGHT,
+        }
+    }
+}
+
+/// How pleasing a layout is, see `score_layout()`. Lower is better.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutScore {
+    /// The variance of how much of each wall's width is covered by art, which
+    /// is high when some walls are crowded and others are sparse.
+    pub occupancy_variance: f64,
+    /// See `NEAR_EMPTY_WALL_OCCUPANCY`.
+    pub near_empty_walls: usize,
+    /// Art objects that didn't fit on any walls.
+    pub unplaceable: usize,
+    /// Pairs of art objects by the same artist that are next to each other on
+    /// a wall.
+    pub same_artist_neighbors: usize,
+    /// The weighted sum of everything else.
+    pub total: f64,
+}
+
+/// Returns how much of each wall's width is covered by art, from 0.0 to 1.0,
+/// for every wall in the layout's galleries. Reserved walls are left out, since
+/// they're supposed to be empty.
+///
+/// Art objects stacked on top of each other are both counted, so this is only
+/// approximate for dense layouts.
+fn wall_occupancies(
+    result: &LayoutResult,
+    wall_sets: &[GalleryWallSet],
+    art_objects: &HashMap<ArtObjectId, &ArtObjectLayoutInfo>,
+) -> Vec<f64> {
+    let mut covered: HashMap<(i64, &str), f64> = HashMap::new();
+    for record in &result.layout_records {
+        let Some(art_object) = art_objects.get(&record.art_object_id) else {
+            continue;
+        };
+        let width = if record.rotated {
+            art_object.height
+        } else {
+            art_object.width
+        };
+        *covered
+            .entry((record.gallery_id, record.wall_id))
+            .or_default() += width;
+    }
+    let mut occupancies = vec![];
+    for gallery in &result.gallery_records {
+        let Some(wall_set) = wall_sets
+            .iter()
+            .find(|wall_set| wall_set.name == gallery.wall_set)
+        else {
+            continue;
+        };
+        for wall in &wall_set.walls {
+            if wall.width <= 0.0 || gallery.reserved_walls.contains(&wall.name) {
+                continue;
+            }
+            let covered = covered
+                .get(&(gallery.gallery_id, wall.name.as_str()))
+                .copied()
+                .unwrap_or_default();
+            occupancies.push((covered / wall.width).min(1.0));
+        }
+    }
+    occupancies
+}
+
+fn variance(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / values.len() as f64
+}
+
+/// Counts the art objects that are next to one by the same artist, from left to
+/// right on each wall. Art objects whose artist is unknown never count.
+fn count_same_artist_neighbors(
+    layout_records: &[LayoutRecord<&str>],
+    artists: &HashMap<ArtObjectId, String>,
+) -> usize {
+    let mut walls: HashMap<(i64, &str), Vec<&LayoutRecord<&str>>> = HashMap::new();
+    for record in layout_records {
+        walls
+            .entry((record.gallery_id, record.wall_id))
+            .or_default()
+            .push(record);
+    }
+    let mut count = 0;
+    for records in walls.values_mut() {
+        records.sort_by(|a, b| a.x.total_cmp(&b.x));
+        for pair in records.windows(2) {
+            let artist = |record: &LayoutRecord<&str>| artists.get(&record.art_object_id);
+            if artist(pair[0]).is_some() && artist(pair[0]) == artist(pair[1]) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// Scores the given layout, e.g. to choose the best of several random layouts.
+/// `art_objects` are the art objects that were laid out, and `artists` is the
+/// artist of each one, if known.
+pub fn score_layout(
+    result: &LayoutResult,
+    wall_sets: &[GalleryWallSet],
+    art_objects: &[ArtObjectLayoutInfo],
+    artists: &HashMap<ArtObjectId, String>,
+    weights: &LayoutScoreWeights,
+) -> LayoutScore {
+    let art_objects: HashMap<ArtObjectId, &ArtObjectLayoutInfo> = art_objects
+        .iter()
+        .map(|art_object| (art_object.id, art_object))
+        .collect();
+    let occupancies = wall_occupancies(result, wall_sets, &art_objects);
+    let occupancy_variance = variance(&occupancies);
+    let near_empty_walls = occupancies
+        .iter()
+        .filter(|&&occupancy| occupancy < NEAR_EMPTY_WALL_OCCUPANCY)
+        .count();
+    let unplaceable = result.unplaceable_art_object_ids.len();
+    let same_artist_neighbors = count_same_artist_neighbors(&result.layout_records, artists);
+    let total = weights.occupancy_variance * occupancy_variance
+        + weights.near_empty_walls * near_empty_walls as f64
+        + weights.unplaceable * unplaceable as f64
+        + weights.same_artist_neighbors * same_artist_neighbors as f64;
+    LayoutScore {
+        occupancy_variance,
+        near_empty_walls,
+        unplaceable,
+        same_artist_neighbors,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        art_object::ArtObjectId,
+        gallery_db::{ArtObjectLayoutInfo, GalleryRecord, LayoutRecord},
+        gallery_wall::{GalleryWall, GalleryWallSet},
+        layout::LayoutResult,
+    };
+
+    use super::{score_layout, LayoutScore, LayoutScoreWeights};
+
+    fn make_wall_sets() -> Vec<GalleryWallSet> {
+        let wall = |name: &str| GalleryWall {
+            name: name.to_string(),
+            width: 4.0,
+            height: 3.0,
+            index: None,
+        };
+        vec![GalleryWallSet::new(
+            "default",
+            vec![wall("wall_a"), wall("wall_b")],
+        )]
+    }
+
+    /// Four 1x1 meter art objects, Met 1 through 4.
+    fn make_art_objects() -> Vec<ArtObjectLayoutInfo> {
+        (1..=4)
+            .map(|id| ArtObjectLayoutInfo {
+                id: ArtObjectId::Met(id),
+                width: 1.0,
+                height: 1.0,
+                highlight: false,
+                collection: "".into(),
+            })
+            .collect()
+    }
+
+    /// Lays out the given art objects in gallery 1, given as `(id, wall_id, x)`.
+    fn make_result<'a>(
+        placements: &[(i64, &'a str, f64)],
+        reserved_walls: &[&str],
+    ) -> LayoutResult<'a> {
+        LayoutResult {
+            galleries_created: 1,
+            layout_records: placements
+                .iter()
+                .map(|&(id, wall_id, x)| LayoutRecord {
+                    gallery_id: 1,
+                    wall_id,
+                    art_object_id: ArtObjectId::Met(id),
+                    x,
+                    y: 1.5,
+                    rotated: false,
+                })
+                .collect(),
+            gallery_records: vec![GalleryRecord {
+                gallery_id: 1,
+                wall_set: "default".into(),
+                reserved_walls: reserved_walls.iter().map(|wall| wall.to_string()).collect(),
+                segment: None,
+                source_gallery: None,
+            }],
+            unplaceable_art_object_ids: vec![],
+            unmatched_ordering_ids: vec![],
+            free_regions: vec![],
+            dropped_source_galleries: vec![],
+        }
+    }
+
+    fn score(result: &LayoutResult, artists: &HashMap<ArtObjectId, String>) -> LayoutScore {
+        score_layout(
+            result,
+            &make_wall_sets(),
+            &make_art_objects(),
+            artists,
+            &LayoutScoreWeights::default(),
+        )
+    }
+
+    #[test]
+    fn test_evenly_filled_walls_score_better() {
+        let even = make_result(&[(1, "wall_a", 1.0), (2, "wall_b", 1.0)], &[]);
+        let lopsided = make_result(&[(1, "wall_a", 1.0), (2, "wall_a", 3.0)], &[]);
+        let even_score = score(&even, &HashMap::new());
+        let lopsided_score = score(&lopsided, &HashMap::new());
+        assert_eq!(even_score.occupancy_variance, 0.0);
+        assert_eq!(even_score.near_empty_walls, 0);
+        assert_eq!(lopsided_score.occupancy_variance, 0.0625);
+        assert_eq!(lopsided_score.near_empty_walls, 1);
+        assert!(even_score.total < lopsided_score.total);
+    }
+
+    #[test]
+    fn test_reserved_walls_are_ignored() {
+        let result = make_result(&[(1, "wall_a", 1.0), (2, "wall_a", 3.0)], &["wall_b"]);
+        let score = score(&result, &HashMap::new());
+        assert_eq!(score.occupancy_variance, 0.0);
+        assert_eq!(score.near_empty_walls, 0);
+        assert_eq!(score.total, 0.0);
+    }
+
+    #[test]
+    fn test_unplaceable_art_objects_score_worse() {
+        let placed = make_result(&[(1, "wall_a", 1.0), (2, "wall_b", 1.0)], &[]);
+        let mut unplaced = make_result(&[(1, "wall_a", 1.0), (2, "wall_b", 1.0)], &[]);
+        unplaced.unplaceable_art_object_ids = vec![ArtObjectId::Met(3)];
+        let unplaced_score = score(&unplaced, &HashMap::new());
+        assert_eq!(unplaced_score.unplaceable, 1);
+        assert!(score(&placed, &HashMap::new()).total < unplaced_score.total);
+    }
+
+    #[test]
+    fn test_same_artist_neighbors_score_worse() {
+        let artists: HashMap<ArtObjectId, String> = [(1, "Boop"), (2, "Boop"), (3, "Bap")]
+            .into_iter()
+            .map(|(id, artist)| (ArtObjectId::Met(id), artist.to_string()))
+            .collect();
+        // Met 4's artist is unknown, so it isn't a neighbor of anything.
+        let apart = make_result(
+            &[
+                (1, "wall_a", 1.0),
+                (3, "wall_a", 3.0),
+                (2, "wall_b", 1.0),
+                (4, "wall_b", 3.0),
+            ],
+            &[],
+        );
+        let together = make_result(
+            &[
+                (3, "wall_a", 1.0),
+                (4, "wall_a", 3.0),
+                (2, "wall_b", 3.0),
+                (1, "wall_b", 1.0),
+            ],
+            &[],
+        );
+        let apart_score = score(&apart, &artists);
+        let together_score = score(&together, &artists);
+        assert_eq!(apart_score.same_artist_neighbors, 0);
+        assert_eq!(together_score.same_artist_neighbors, 1);
+        assert!(apart_score.total < together_score.total);
+
+        // Unless that's what's wanted.
+        let weights = LayoutScoreWeights {
+            same_artist_neighbors: -1.0,
+            ..Default::default()
+        };
+        let score_with_weights = |result: &LayoutResult| {
+            score_layout(
+                result,
+                &make_wall_sets(),
+                &make_art_objects(),
+                &artists,
+                &weights,
+            )
+            .total
+        };
+        assert!(score_with_weights(&together) < score_with_weights(&apart));
+    }
+}
@@ -1,11 +1,30 @@
 pub mod art_object;
+pub mod artist_names;
+pub mod cache_usage;
 pub mod filter_parser;
+pub mod frame_style;
 pub mod gallery_cache;
 pub mod gallery_db;
 pub mod gallery_db_migration;
+pub mod gallery_db_recovery;
+pub mod gallery_db_schema;
+pub mod gallery_db_subset;
 pub mod gallery_wall;
+pub mod gallery_zone;
 pub mod image;
 pub mod layout;
+pub mod layout_score;
+pub mod medium;
 pub mod met_api;
+pub mod object_date;
+pub mod placement;
 pub mod random;
+pub mod rate_limiter;
+pub mod shared_layout;
+pub mod shutdown;
 pub mod wikidata;
+
+#[cfg(test)]
+mod layout_fixtures;
+#[cfg(test)]
+mod layout_golden_tests;
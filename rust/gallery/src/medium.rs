@@ -0,0 +1,187 @@
+/// Keywords for mediums that are flat, two-dimensional art with a matte surface.
+///
+/// This list was obtained by running the CLI with `--met-objects-all-media`, then
+/// running the following SQL query on the generated DB:
+///
+/// ```sql
+/// select medium, count(*) as c from art_objects group by medium order by c desc limit 60;
+/// ```
+///
+/// I then ignored any medium that wasn't flat, two-dimensional art with a
+/// matte surface. Examples of these are stone, glass, silk, iron, ceramic,
+/// pottery, etc.
+pub const MEDIUM_KEYWORDS: [&str; 20] = [
+    "watercolor",
+    "lithograph",
+    "oil",
+    "photo",
+    "drawing",
+    "gouache",
+    "chalk",
+    "canvas",
+    "ink",
+    "paper",
+    "print",
+    "aquatint",
+    "charcoal",
+    "graphite",
+    "woodblock",
+    "wood block",
+    "etching",
+    "tempera",
+    "fresco",
+    "acrylic",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediumCategory {
+    Oil,
+    Watercolor,
+    Print,
+    Drawing,
+    Photograph,
+    Other,
+}
+
+pub const ALL_MEDIUM_CATEGORIES: [MediumCategory; 6] = [
+    MediumCategory::Oil,
+    MediumCategory::Watercolor,
+    MediumCategory::Print,
+    MediumCategory::Drawing,
+    MediumCategory::Photograph,
+    MediumCategory::Other,
+];
+
+/// Keyword rules for each category. These are checked in order, so e.g. a
+/// "gelatin silver print" is a photograph rather than a print, and an
+/// "etching printed in ink" is a print rather than a drawing.
+const CATEGORY_KEYWORDS: [(MediumCategory, &[&str]); 5] = [
+    (
+        MediumCategory::Photograph,
+        &[
+            "photo",
+            "gelatin silver",
+            "albumen",
+            "daguerreotype",
+            "salted paper",
+            "platinum print",
+            "cyanotype",
+            "tintype",
+            "ambrotype",
+        ],
+    ),
+    (
+        MediumCategory::Print,
+        &[
+            "lithograph",
+            "etching",
+            "engraving",
+            "aquatint",
+            "woodcut",
+            "woodblock",
+            "wood block",
+            "mezzotint",
+            "drypoint",
+            "screenprint",
+            "print",
+        ],
+    ),
+    (
+        MediumCategory::Oil,
+        &["oil", "öl", "huile", "óleo", "olieverf"],
+    ),
+    (
+        MediumCategory::Watercolor,
+        &["watercolor", "watercolour", "aquarelle", "gouache"],
+    ),
+    (
+        MediumCategory::Drawing,
+        &[
+            "drawing", "chalk", "charcoal", "graphite", "pencil", "pastel", "crayon", "ink", "pen",
+        ],
+    ),
+];
+
+impl MediumCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediumCategory::Oil => "oil",
+            MediumCategory::Watercolor => "watercolor",
+            MediumCategory::Print => "print",
+            MediumCategory::Drawing => "drawing",
+            MediumCategory::Photograph => "photograph",
+            MediumCategory::Other => "other",
+        }
+    }
+
+    /// Parse the result of `as_str()`, returning `Other` if it's unrecognized.
+    pub fn from_name<T: AsRef<str>>(value: T) -> Self {
+        let value = value.as_ref();
+        ALL_MEDIUM_CATEGORIES
+            .into_iter()
+            .find(|category| category.as_str() == value)
+            .unwrap_or(MediumCategory::Other)
+    }
+}
+
+/// Map a raw medium string (e.g. "Oil on canvas" from the Met, or a list of
+/// materials like "oil paint, canvas" from wikidata) to a category.
+pub fn classify_medium<T: AsRef<str>>(medium: T) -> MediumCategory {
+    let lower_medium = medium.as_ref().to_lowercase();
+    for (category, keywords) in CATEGORY_KEYWORDS.iter() {
+        if keywords
+            .iter()
+            .any(|keyword| lower_medium.contains(keyword))
+        {
+            return *category;
+        }
+    }
+    MediumCategory::Other
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify_medium, MediumCategory, ALL_MEDIUM_CATEGORIES};
+
+    #[test]
+    fn test_classify_medium_works() {
+        let cases = [
+            ("Oil on canvas", MediumCategory::Oil),
+            ("oil on canvas, mounted", MediumCategory::Oil),
+            ("Öl auf Leinwand", MediumCategory::Oil),
+            ("oil paint, canvas", MediumCategory::Oil),
+            ("Oil on wood", MediumCategory::Oil),
+            ("Watercolor over graphite", MediumCategory::Watercolor),
+            ("Gouache on paper", MediumCategory::Watercolor),
+            ("Pen and brown ink, watercolor", MediumCategory::Watercolor),
+            ("Etching, printed in black ink", MediumCategory::Print),
+            (
+                "Woodblock print; ink and color on paper",
+                MediumCategory::Print,
+            ),
+            ("Lithograph", MediumCategory::Print),
+            ("Gelatin silver print", MediumCategory::Photograph),
+            (
+                "Albumen silver print from glass negative",
+                MediumCategory::Photograph,
+            ),
+            ("Black chalk on blue paper", MediumCategory::Drawing),
+            ("Charcoal", MediumCategory::Drawing),
+            ("Pen and brown ink, brush and wash", MediumCategory::Drawing),
+            ("Tempera on wood, gold ground", MediumCategory::Other),
+            ("Paper", MediumCategory::Other),
+            ("", MediumCategory::Other),
+        ];
+        for (medium, expected) in cases {
+            assert_eq!(classify_medium(medium), expected, "{medium:?}");
+        }
+    }
+
+    #[test]
+    fn test_medium_category_str_round_trips() {
+        for category in ALL_MEDIUM_CATEGORIES {
+            assert_eq!(MediumCategory::from_name(category.as_str()), category);
+        }
+        assert_eq!(MediumCategory::from_name("boop"), MediumCategory::Other);
+    }
+}
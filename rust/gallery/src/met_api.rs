@@ -1,13 +1,14 @@
-use std::fs::create_dir_all;
+use std::{collections::HashMap, fs::create_dir_all};
 
 use crate::{
-    gallery_cache::GalleryCache,
-    image::{cache_image, get_supported_image_ext, ImageSize},
+    gallery_cache::{CacheResult, GalleryCache},
+    image::{cache_image, get_cached_image, get_supported_image_ext, ImageCacheOptions, ImageSize},
 };
 use anyhow::{anyhow, Result};
+use log::{info, warn};
 use serde::Deserialize;
 
-const ROOT_CACHE_SUBDIR: &'static str = "met-api";
+const ROOT_CACHE_SUBDIR: &str = "met-api";
 
 pub fn migrate_met_api_cache(cache: &GalleryCache) -> Result<()> {
     let mut created_subdir = false;
@@ -22,7 +23,7 @@ pub fn migrate_met_api_cache(cache: &GalleryCache) -> Result<()> {
                 && (filename.ends_with(".json") || filename.ends_with(".jpg"))
             {
                 if !created_subdir {
-                    println!(
+                    info!(
                         "Migrating met api cache files into {}.",
                         root_cache_subdir.display()
                     );
@@ -31,7 +32,7 @@ pub fn migrate_met_api_cache(cache: &GalleryCache) -> Result<()> {
                 }
                 let dest_path = root_cache_subdir.join(filename.as_ref());
                 if let Err(err) = std::fs::rename(path.clone(), dest_path.clone()) {
-                    eprintln!(
+                    warn!(
                         "Unable to move {} to {}: {err:?}",
                         path.display(),
                         dest_path.display()
@@ -43,25 +44,59 @@ pub fn migrate_met_api_cache(cache: &GalleryCache) -> Result<()> {
     Ok(())
 }
 
-pub fn load_met_api_record(cache: &GalleryCache, object_id: i64) -> Result<MetObjectApiRecord> {
-    let filename = format!("{ROOT_CACHE_SUBDIR}/object-{}.json", object_id);
-    cache.cache_json_url(
-        format!(
-            "https://collectionapi.metmuseum.org/public/collection/v1/objects/{}",
-            object_id
-        ),
-        &filename,
-    )?;
-    match serde_json::from_str(&cache.load_cached_string(&filename)?) {
+pub(crate) fn met_api_record_filename(object_id: i64) -> String {
+    format!("{ROOT_CACHE_SUBDIR}/object-{}.json", object_id)
+}
+
+fn parse_cached_met_api_record(cache: &GalleryCache, filename: &str) -> Result<MetObjectApiRecord> {
+    match serde_json::from_str(&cache.load_cached_string(filename)?) {
         Ok(record) => Ok(record),
         Err(err) => Err(anyhow!("Failed to load {}: {}", filename, err)),
     }
 }
 
+fn met_api_record_url(object_id: i64) -> String {
+    format!(
+        "https://collectionapi.metmuseum.org/public/collection/v1/objects/{}",
+        object_id
+    )
+}
+
+pub fn load_met_api_record(cache: &GalleryCache, object_id: i64) -> Result<MetObjectApiRecord> {
+    let filename = met_api_record_filename(object_id);
+    cache.cache_json_url(met_api_record_url(object_id), &filename, false)?;
+    parse_cached_met_api_record(cache, &filename)
+}
+
+/// Like `load_met_api_record()`, but fetches the record again even if it's
+/// already cached, in case it's changed upstream.
+pub fn refresh_met_api_record(
+    cache: &GalleryCache,
+    object_id: i64,
+) -> Result<(CacheResult, MetObjectApiRecord)> {
+    let filename = met_api_record_filename(object_id);
+    let result = cache.cache_json_url(met_api_record_url(object_id), &filename, true)?;
+    Ok((result, parse_cached_met_api_record(cache, &filename)?))
+}
+
+/// Like `load_met_api_record()`, but returns `None` instead of fetching the
+/// record if it isn't already cached.
+pub fn load_cached_met_api_record(
+    cache: &GalleryCache,
+    object_id: i64,
+) -> Result<Option<MetObjectApiRecord>> {
+    let filename = met_api_record_filename(object_id);
+    if cache.get_if_cached(&filename).is_none() {
+        return Ok(None);
+    }
+    Ok(Some(parse_cached_met_api_record(cache, &filename)?))
+}
+
 /// Historical note: I used to extract measurements out of this and use them, but
 /// then I realized that the measurements could be parsed from the original
 /// Met CSV. Furthermore, not all met objects (e.g. 389607) even _have_ measurements
-/// specified as structured data, rendering them even less useful.
+/// specified as structured data, rendering them even less useful. They're now
+/// only used to notice when the Met has corrected an object's size.
 #[derive(Debug, Deserialize)]
 pub struct MetObjectApiRecord {
     #[serde(rename = "primaryImageSmall")]
@@ -77,9 +112,50 @@ pub struct MetObjectApiRecord {
     pub object_id: u64,
 
     pub title: String,
+
+    #[serde(default)]
+    pub measurements: Option<Vec<MetObjectMeasurement>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetObjectMeasurement {
+    #[serde(rename = "elementName")]
+    pub element_name: String,
+
+    /// Keyed by e.g. `Height` and `Width`, in centimeters.
+    #[serde(rename = "elementMeasurements")]
+    pub element_measurements: HashMap<String, f64>,
 }
 
 impl MetObjectApiRecord {
+    /// Returns the width and height of the object in meters, preferring its
+    /// overall measurements, if the record has them.
+    pub fn size_in_meters(&self) -> Option<(f64, f64)> {
+        let measurements = self.measurements.as_ref()?;
+        let sizes: Vec<(&str, f64, f64)> = measurements
+            .iter()
+            .filter_map(|measurement| {
+                let width = measurement.element_measurements.get("Width")?;
+                let height = measurement.element_measurements.get("Height")?;
+                Some((measurement.element_name.as_str(), *width, *height))
+            })
+            .collect();
+        let (_, width, height) = sizes
+            .iter()
+            .find(|(name, _, _)| *name == "Overall")
+            .or(sizes.first())?;
+        Some((width / 100.0, height / 100.0))
+    }
+
+    /// The URLs of the object's images, which can be stored in the gallery
+    /// DB so that fetching the images doesn't require this record.
+    pub fn image_urls(&self) -> MetImageUrls {
+        MetImageUrls {
+            primary_image: self.primary_image.clone(),
+            primary_image_small: self.primary_image_small.clone(),
+        }
+    }
+
     /// Try to download & cache the an image of the object if it's 2D artwork.
     ///
     /// If it's in the cache, returns the cached version. Otherwise, downloads and adds
@@ -90,17 +166,134 @@ impl MetObjectApiRecord {
         &self,
         cache: &GalleryCache,
         size: ImageSize,
+        options: &ImageCacheOptions,
     ) -> Result<Option<String>> {
-        let image_url = match size {
-            ImageSize::Small => &self.primary_image_small,
-            ImageSize::Large => &self.primary_image,
-        };
+        self.image_urls()
+            .try_to_download_image(cache, self.object_id as i64, size, options)
+    }
+
+    /// Like `try_to_download_image()`, but only returns the filename if the
+    /// image is already cached.
+    pub fn get_cached_image(&self, cache: &GalleryCache, size: ImageSize) -> Option<String> {
+        self.image_urls()
+            .get_cached_image(cache, self.object_id as i64, size)
+    }
+}
+
+/// The `primaryImage` and `primaryImageSmall` URLs of a Met object, either of
+/// which may be empty if the Met doesn't have any images of it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MetImageUrls {
+    pub primary_image: String,
+    pub primary_image_small: String,
+}
+
+impl MetImageUrls {
+    /// Like `MetObjectApiRecord::try_to_download_image()`, for the Met object
+    /// with the given ID.
+    pub fn try_to_download_image(
+        &self,
+        cache: &GalleryCache,
+        object_id: i64,
+        size: ImageSize,
+        options: &ImageCacheOptions,
+    ) -> Result<Option<String>> {
+        let image_url = self.url(size);
         if let Some(ext) = get_supported_image_ext(image_url) {
-            let image_filename =
-                format!("{ROOT_CACHE_SUBDIR}/object-{}-{size}{ext}", self.object_id);
-            cache_image(cache, image_url, &image_filename, ext)?;
-            return Ok(Some(image_filename));
+            let image_filename = met_image_cache_path(object_id, size, ext);
+            return Ok(Some(cache_image(
+                cache,
+                image_url,
+                &image_filename,
+                ext,
+                options,
+            )?));
         }
         Ok(None)
     }
+
+    /// Like `try_to_download_image()`, but only returns the filename if the
+    /// image is already cached.
+    pub fn get_cached_image(
+        &self,
+        cache: &GalleryCache,
+        object_id: i64,
+        size: ImageSize,
+    ) -> Option<String> {
+        let ext = get_supported_image_ext(self.url(size))?;
+        get_cached_image(cache, &met_image_cache_path(object_id, size, ext), ext)
+    }
+
+    pub fn url(&self, size: ImageSize) -> &str {
+        match size {
+            ImageSize::Small => &self.primary_image_small,
+            ImageSize::Large => &self.primary_image,
+        }
+    }
+}
+
+/// The filename, relative to the cache directory, that the given size of a Met
+/// object's image is cached under if its URL has the given extension. Note that
+/// it may have been re-encoded, see `get_cached_image()`.
+pub fn met_image_cache_path(object_id: i64, size: ImageSize, ext: &str) -> String {
+    format!("{ROOT_CACHE_SUBDIR}/object-{object_id}-{size}{ext}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::ImageSize;
+
+    use super::{met_image_cache_path, MetObjectApiRecord};
+
+    #[test]
+    fn test_met_image_cache_path_works() {
+        assert_eq!(
+            met_image_cache_path(123, ImageSize::Small, ".jpg"),
+            "met-api/object-123-small.jpg"
+        );
+        assert_eq!(
+            met_image_cache_path(123, ImageSize::Large, ".png"),
+            "met-api/object-123-large.png"
+        );
+    }
+
+    #[test]
+    fn test_size_in_meters_works() {
+        let parse = |measurements: &str| -> MetObjectApiRecord {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "primaryImageSmall": "",
+                    "primaryImage": "",
+                    "objectDate": "1890",
+                    "objectID": 1,
+                    "title": "Boop",
+                    "measurements": {measurements}
+                }}"#
+            ))
+            .unwrap()
+        };
+        assert_eq!(parse("null").size_in_meters(), None);
+        assert_eq!(
+            parse(r#"[{"elementName": "Overall", "elementMeasurements": {"Depth": 2}}]"#)
+                .size_in_meters(),
+            None
+        );
+        assert_eq!(
+            parse(
+                r#"[
+                    {"elementName": "Frame", "elementMeasurements": {"Height": 120, "Width": 80}},
+                    {"elementName": "Overall", "elementMeasurements": {"Height": 100, "Width": 50}}
+                ]"#
+            )
+            .size_in_meters(),
+            Some((0.5, 1.0))
+        );
+        assert_eq!(
+            parse(
+                r#"[{"elementName": "Sheet", "elementMeasurements": {"Height": 30, "Width": 20}}]"#
+            )
+            .size_in_meters(),
+            Some((0.2, 0.3))
+        );
+    }
 }
@@ -0,0 +1,210 @@
+/// Parses the century out of a free-text date like the ones in `ArtObjectRecord::object_date`,
+/// e.g. "ca. 1915", "1890s", "19th century" or "300 B.C.". Centuries are numbered
+/// the same way as in `wikidata.rs`, so 1800 is in the 19th century, and centuries
+/// before Christ are negative, so "4th century B.C." is -4.
+///
+/// When a date spans multiple centuries, e.g. "1790–1810", the first one is used.
+pub fn parse_century(date: &str) -> Option<i32> {
+    let date = date.to_lowercase();
+    let before_christ = is_before_christ(&date);
+    let sign = if before_christ { -1 } else { 1 };
+    let numbers = find_numbers(&date);
+
+    if date.contains("century") {
+        if let Some(century) = numbers
+            .iter()
+            .find(|number| is_ordinal(&date[number.end..]))
+            .and_then(|number| number.value.try_into().ok())
+            .filter(|century: &i32| *century > 0)
+        {
+            return Some(century * sign);
+        }
+    }
+
+    // Small numbers are probably days of the month, e.g. "May 3, 1889", unless
+    // nothing else looks like a year, e.g. "A.D. 79".
+    let year = numbers
+        .iter()
+        .find(|number| number.len >= 3)
+        .or_else(|| numbers.first())?
+        .value;
+    let century: i32 = (year / 100 + 1).try_into().ok()?;
+    Some(century * sign)
+}
+
+/// Parses a representative year out of a free-text date like the ones in
+/// `ArtObjectRecord::object_date`, e.g. so art objects can be filtered by
+/// period. Years before Christ are negative, so "300 B.C." is -300.
+///
+/// Dates that span a range of years are represented by its midpoint, e.g.
+/// "1790–1810" is 1800, "1890s" is 1895 and "19th century" is 1850.
+pub fn parse_year(date: &str) -> Option<i32> {
+    let date = date.to_lowercase();
+    let before_christ = is_before_christ(&date);
+    let sign = if before_christ { -1 } else { 1 };
+    let numbers = find_numbers(&date);
+
+    if date.contains("century") {
+        let centuries: Vec<i32> = numbers
+            .iter()
+            .filter(|number| is_ordinal(&date[number.end..]))
+            .filter_map(|number| number.value.try_into().ok())
+            .filter(|century: &i32| *century > 0)
+            .collect();
+        if let Some(&first) = centuries.first() {
+            let last = centuries.get(1).copied().unwrap_or(first);
+            let (first, last) = (first.min(last), first.max(last));
+            let midpoint = ((first - 1) * 100 + last * 100) / 2;
+            return Some(midpoint * sign);
+        }
+    }
+
+    // Small numbers are probably days of the month, e.g. "May 3, 1889", unless
+    // nothing else looks like a year, e.g. "A.D. 79".
+    let index = numbers
+        .iter()
+        .position(|number| number.len >= 3)
+        .or_else(|| (!numbers.is_empty()).then_some(0))?;
+    let start = &numbers[index];
+    let start_year: i32 = start.value.try_into().ok()?;
+    if date[start.end..].starts_with('s') {
+        // A decade, e.g. "1890s".
+        return Some((start_year + 5) * sign);
+    }
+    let end_year = numbers
+        .get(index + 1)
+        .filter(|end| is_range_separator(&date[start.end..end.start]))
+        .and_then(|end| {
+            let end_year: i32 = end.value.try_into().ok()?;
+            if end.len < start.len {
+                // An abbreviated range, e.g. "1885–90".
+                let scale = 10_i32.pow(end.len as u32);
+                Some(start_year - start_year % scale + end_year)
+            } else {
+                Some(end_year)
+            }
+        })
+        .unwrap_or(start_year);
+    Some((start_year + end_year) / 2 * sign)
+}
+
+fn is_range_separator(between: &str) -> bool {
+    matches!(between.trim(), "-" | "–" | "—" | "to")
+}
+
+struct Number {
+    value: u32,
+    len: usize,
+    /// The byte offset of the number's first digit.
+    start: usize,
+    /// The byte offset just after the number's last digit.
+    end: usize,
+}
+
+fn find_numbers(date: &str) -> Vec<Number> {
+    let mut numbers = vec![];
+    let mut start: Option<usize> = None;
+    for (i, c) in date.char_indices().chain([(date.len(), ' ')]) {
+        match (c.is_ascii_digit(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(number_start)) => {
+                if let Ok(value) = date[number_start..i].parse::<u32>() {
+                    numbers.push(Number {
+                        value,
+                        len: i - number_start,
+                        start: number_start,
+                        end: i,
+                    });
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    numbers
+}
+
+fn is_ordinal(rest: &str) -> bool {
+    ["st", "nd", "rd", "th"]
+        .iter()
+        .any(|suffix| rest.starts_with(suffix))
+}
+
+fn is_before_christ(date: &str) -> bool {
+    date.contains("b.c")
+        || date
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .any(|word| word == "bc" || word == "bce")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_century, parse_year};
+
+    #[test]
+    fn test_parse_century_works_with_years() {
+        assert_eq!(parse_century("1915"), Some(20));
+        assert_eq!(parse_century("ca. 1915"), Some(20));
+        assert_eq!(parse_century("1890s"), Some(19));
+        assert_eq!(parse_century("1800"), Some(19));
+        assert_eq!(parse_century("1790–1810"), Some(18));
+        assert_eq!(parse_century("May 3, 1889"), Some(19));
+        assert_eq!(parse_century("A.D. 79"), Some(1));
+    }
+
+    #[test]
+    fn test_parse_century_works_with_centuries() {
+        assert_eq!(parse_century("19th century"), Some(19));
+        assert_eq!(parse_century("late 19th Century"), Some(19));
+        assert_eq!(parse_century("1st century"), Some(1));
+        assert_eq!(parse_century("2nd–3rd century"), Some(2));
+        assert_eq!(parse_century("early 20th century, ca. 1905"), Some(20));
+    }
+
+    #[test]
+    fn test_parse_century_works_before_christ() {
+        assert_eq!(parse_century("300 B.C."), Some(-4));
+        assert_eq!(parse_century("ca. 1250 BCE"), Some(-13));
+        assert_eq!(parse_century("4th century BC"), Some(-4));
+    }
+
+    #[test]
+    fn test_parse_century_returns_none_without_numbers() {
+        assert_eq!(parse_century(""), None);
+        assert_eq!(parse_century("unknown"), None);
+        assert_eq!(parse_century("nineteenth century"), None);
+    }
+
+    #[test]
+    fn test_parse_year_works_with_real_dates() {
+        // These are all taken from the Met's and Wikidata's collections.
+        let dates = [
+            ("1915", Some(1915)),
+            ("ca. 1915", Some(1915)),
+            ("1890s", Some(1895)),
+            ("ca. 1890s", Some(1895)),
+            ("May 3, 1889", Some(1889)),
+            ("dated 1734", Some(1734)),
+            ("1790–1810", Some(1800)),
+            ("1665–66", Some(1665)),
+            ("1885–90", Some(1887)),
+            ("ca. 1500-1510", Some(1505)),
+            ("1860 to 1870", Some(1865)),
+            ("Edo period (1615–1868)", Some(1741)),
+            ("A.D. 79", Some(79)),
+            ("19th century", Some(1850)),
+            ("late 19th century", Some(1850)),
+            ("18th–19th century", Some(1800)),
+            ("1st century", Some(50)),
+            ("300 B.C.", Some(-300)),
+            ("ca. 1250–1200 BCE", Some(-1225)),
+            ("4th century BC", Some(-350)),
+            ("", None),
+            ("unknown", None),
+            ("nineteenth century", None),
+        ];
+        for (date, expected) in dates {
+            assert_eq!(parse_year(date), expected, "{date:?}");
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use std::fmt::Display;
+
+use crate::{art_object::ArtObjectId, gallery_db::ArtObjectLayoutInfo, gallery_wall::GalleryWall};
+
+/// Paintings can overlap each other by this much (in meters) before we
+/// consider them to be overlapping. This prevents paintings that were
+/// placed right next to each other from being rejected due to floating
+/// point imprecision.
+pub const OVERLAP_TOLERANCE: f64 = 0.01;
+
+/// Art objects can hang past the edges of a wall (or its margins) by this much
+/// (in meters) before `check_placement()` considers them off the wall. This is
+/// only meant to absorb floating point imprecision, unlike `OVERLAP_TOLERANCE`.
+const WALL_EDGE_TOLERANCE: f64 = 1e-9;
+
+/// The rectangle an art object occupies on a wall.
+///
+/// As with layout records, `x` and `y` are the center of the art object,
+/// relative to the bottom-left corner of the wall.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlacementError {
+    TooBigForWall,
+    OutOfBounds,
+    Overlaps(ArtObjectId),
+}
+
+impl Display for PlacementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlacementError::TooBigForWall => write!(f, "The art object is too big for the wall."),
+            PlacementError::OutOfBounds => write!(f, "The art object would hang off the wall."),
+            PlacementError::Overlaps(id) => {
+                write!(
+                    f,
+                    "The art object would overlap another art object ({id:?})."
+                )
+            }
+        }
+    }
+}
+
+/// How much space (in meters) `check_placement()` leaves around an art object.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PlacementMargins {
+    /// Between the art object and the edges of the wall.
+    pub wall: f64,
+    /// Between the art object and the others on the wall.
+    pub between: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlacementCheck {
+    Ok,
+    /// How far (in meters) the art object hangs past each edge of the wall,
+    /// less its margin. Edges it doesn't hang past are zero.
+    OffWall {
+        left: f64,
+        right: f64,
+        bottom: f64,
+        top: f64,
+    },
+    /// The art objects that the art object would overlap, or be closer than
+    /// the margin to.
+    Overlaps {
+        ids: Vec<ArtObjectId>,
+    },
+}
+
+impl Placement {
+    fn left(&self) -> f64 {
+        self.x - self.width / 2.0
+    }
+
+    fn right(&self) -> f64 {
+        self.x + self.width / 2.0
+    }
+
+    fn bottom(&self) -> f64 {
+        self.y - self.height / 2.0
+    }
+
+    fn top(&self) -> f64 {
+        self.y + self.height / 2.0
+    }
+
+    pub fn is_within_wall(&self, wall: &GalleryWall) -> bool {
+        self.left() >= 0.0
+            && self.bottom() >= 0.0
+            && self.right() <= wall.width
+            && self.top() <= wall.height
+    }
+
+    /// Returns the closest placement that's entirely on the wall, or `None` if
+    /// the art object is too big to fit on it.
+    pub fn clamped_to_wall(&self, wall: &GalleryWall) -> Option<Placement> {
+        if self.width > wall.width || self.height > wall.height {
+            return None;
+        }
+        let half_width = self.width / 2.0;
+        let half_height = self.height / 2.0;
+        Some(Placement {
+            x: self.x.clamp(half_width, wall.width - half_width),
+            y: self.y.clamp(half_height, wall.height - half_height),
+            ..*self
+        })
+    }
+
+    pub fn overlaps(&self, other: &Placement) -> bool {
+        let overlap_width = self.right().min(other.right()) - self.left().max(other.left());
+        let overlap_height = self.top().min(other.top()) - self.bottom().max(other.bottom());
+        overlap_width > OVERLAP_TOLERANCE && overlap_height > OVERLAP_TOLERANCE
+    }
+}
+
+/// Validate moving an art object to the given placement on a wall.
+///
+/// If `strict` is true, placements that hang off the wall or overlap any of
+/// the `others` on it are rejected. Otherwise, placements that hang off the
+/// wall are clamped to it, and overlaps are allowed.
+///
+/// If `wall` is `None`, its dimensions are unknown, so only overlaps are checked.
+pub fn validate_placement(
+    placement: Placement,
+    wall: Option<&GalleryWall>,
+    others: &[(ArtObjectId, Placement)],
+    strict: bool,
+) -> Result<Placement, PlacementError> {
+    let mut placement = placement;
+    if let Some(wall) = wall {
+        if !placement.is_within_wall(wall) {
+            let Some(clamped) = placement.clamped_to_wall(wall) else {
+                return Err(PlacementError::TooBigForWall);
+            };
+            if strict {
+                return Err(PlacementError::OutOfBounds);
+            }
+            placement = clamped;
+        }
+    }
+    if strict {
+        if let Some((id, _)) = others.iter().find(|(_, other)| placement.overlaps(other)) {
+            return Err(PlacementError::Overlaps(*id));
+        }
+    }
+    Ok(placement)
+}
+
+/// Checks whether the candidate art object could hang with its center at the
+/// given position on a wall, given where the `existing` art objects on it are
+/// centered. The candidate itself is ignored if it's among them, so it can be
+/// checked while it's being dragged around.
+///
+/// Unlike `validate_placement()`, this doesn't change anything, and reports
+/// every art object that's in the way, so it can be used for live feedback.
+/// Art objects that hang off the wall are reported as such even if they
+/// overlap others too.
+pub fn check_placement(
+    wall: &GalleryWall,
+    existing: &[(ArtObjectLayoutInfo, (f64, f64))],
+    candidate: &ArtObjectLayoutInfo,
+    x: f64,
+    y: f64,
+    margins: PlacementMargins,
+) -> PlacementCheck {
+    let placement = Placement {
+        x,
+        y,
+        width: candidate.width,
+        height: candidate.height,
+    };
+    let overflow = |amount: f64| {
+        if amount > WALL_EDGE_TOLERANCE {
+            amount
+        } else {
+            0.0
+        }
+    };
+    let left = overflow(margins.wall - placement.left());
+    let right = overflow(placement.right() - (wall.width - margins.wall));
+    let bottom = overflow(margins.wall - placement.bottom());
+    let top = overflow(placement.top() - (wall.height - margins.wall));
+    if left > 0.0 || right > 0.0 || bottom > 0.0 || top > 0.0 {
+        return PlacementCheck::OffWall {
+            left,
+            right,
+            bottom,
+            top,
+        };
+    }
+    // Anything closer than the margin overlaps this.
+    let padded = Placement {
+        width: placement.width + margins.between * 2.0,
+        height: placement.height + margins.between * 2.0,
+        ..placement
+    };
+    let ids: Vec<ArtObjectId> = existing
+        .iter()
+        .filter(|(other, (x, y))| {
+            other.id != candidate.id
+                && padded.overlaps(&Placement {
+                    x: *x,
+                    y: *y,
+                    width: other.width,
+                    height: other.height,
+                })
+        })
+        .map(|(other, _)| other.id)
+        .collect();
+    if ids.is_empty() {
+        PlacementCheck::Ok
+    } else {
+        PlacementCheck::Overlaps { ids }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        art_object::ArtObjectId, gallery_db::ArtObjectLayoutInfo, gallery_wall::GalleryWall,
+    };
+
+    use super::{
+        check_placement, validate_placement, Placement, PlacementCheck, PlacementError,
+        PlacementMargins,
+    };
+
+    fn wall() -> GalleryWall {
+        GalleryWall {
+            name: "wall_a".to_string(),
+            width: 5.0,
+            height: 3.0,
+            index: None,
+        }
+    }
+
+    fn painting(x: f64, y: f64) -> Placement {
+        Placement {
+            x,
+            y,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_placement_within_wall_is_unchanged() {
+        for strict in [false, true] {
+            let placement = painting(0.5, 2.5);
+            assert_eq!(
+                validate_placement(placement, Some(&wall()), &[], strict),
+                Ok(placement)
+            );
+        }
+    }
+
+    #[test]
+    fn test_placement_off_edge_is_clamped() {
+        let result = validate_placement(painting(4.9, -1.0), Some(&wall()), &[], false);
+        assert_eq!(result, Ok(painting(4.5, 0.5)));
+    }
+
+    #[test]
+    fn test_placement_off_edge_is_rejected_when_strict() {
+        let result = validate_placement(painting(4.9, 1.0), Some(&wall()), &[], true);
+        assert_eq!(result, Err(PlacementError::OutOfBounds));
+    }
+
+    #[test]
+    fn test_placement_too_big_for_wall_is_rejected() {
+        let placement = Placement {
+            x: 2.5,
+            y: 1.5,
+            width: 6.0,
+            height: 1.0,
+        };
+        for strict in [false, true] {
+            assert_eq!(
+                validate_placement(placement, Some(&wall()), &[], strict),
+                Err(PlacementError::TooBigForWall)
+            );
+        }
+    }
+
+    #[test]
+    fn test_placement_on_unknown_wall_is_unchanged() {
+        let placement = painting(100.0, 100.0);
+        assert_eq!(
+            validate_placement(placement, None, &[], true),
+            Ok(placement)
+        );
+    }
+
+    #[test]
+    fn test_overlapping_placement_is_rejected_when_strict() {
+        let others = [(ArtObjectId::Met(2), painting(2.0, 1.5))];
+        let placement = painting(2.5, 1.5);
+        assert_eq!(
+            validate_placement(placement, Some(&wall()), &others, true),
+            Err(PlacementError::Overlaps(ArtObjectId::Met(2)))
+        );
+        assert_eq!(
+            validate_placement(placement, Some(&wall()), &others, false),
+            Ok(placement)
+        );
+    }
+
+    #[test]
+    fn test_adjacent_placement_does_not_overlap() {
+        let others = [(ArtObjectId::Met(2), painting(2.0, 1.5))];
+        // Touching, and even slightly overlapping within tolerance, is fine.
+        for x in [3.0, 2.995] {
+            let placement = painting(x, 1.5);
+            assert_eq!(
+                validate_placement(placement, Some(&wall()), &others, true),
+                Ok(placement)
+            );
+        }
+    }
+
+    #[test]
+    fn test_clamped_placement_may_overlap_when_not_strict() {
+        let others = [(ArtObjectId::Met(2), painting(4.5, 1.5))];
+        let result = validate_placement(painting(5.0, 1.5), Some(&wall()), &others, false);
+        assert_eq!(result, Ok(painting(4.5, 1.5)));
+        let result = validate_placement(painting(4.0, 1.5), Some(&wall()), &others, true);
+        assert_eq!(result, Err(PlacementError::Overlaps(ArtObjectId::Met(2))));
+    }
+
+    fn layout_info(id: i64) -> ArtObjectLayoutInfo {
+        ArtObjectLayoutInfo {
+            id: ArtObjectId::Met(id),
+            width: 1.0,
+            height: 1.0,
+            highlight: false,
+            collection: String::new(),
+        }
+    }
+
+    const MARGINS: PlacementMargins = PlacementMargins {
+        wall: 0.25,
+        between: 0.5,
+    };
+
+    #[test]
+    fn test_check_placement_allows_touching_edges() {
+        let existing = [(layout_info(2), (2.0, 1.5))];
+        for (x, y) in [(0.5, 0.5), (4.5, 2.5), (1.0, 1.5), (3.0, 1.5), (2.0, 0.5)] {
+            assert_eq!(
+                check_placement(
+                    &wall(),
+                    &existing,
+                    &layout_info(1),
+                    x,
+                    y,
+                    Default::default()
+                ),
+                PlacementCheck::Ok,
+                "{x}, {y}"
+            );
+        }
+        // The same goes for touching the margins.
+        for (x, y) in [(4.25, 0.75), (4.25, 2.25), (3.5, 1.5)] {
+            assert_eq!(
+                check_placement(&wall(), &existing, &layout_info(1), x, y, MARGINS),
+                PlacementCheck::Ok,
+                "{x}, {y}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_placement_reports_margin_violations() {
+        let existing = [(layout_info(2), (2.0, 1.5)), (layout_info(3), (4.0, 1.5))];
+        assert_eq!(
+            check_placement(&wall(), &existing, &layout_info(1), 3.0, 1.5, MARGINS),
+            PlacementCheck::Overlaps {
+                ids: vec![ArtObjectId::Met(2), ArtObjectId::Met(3)]
+            }
+        );
+        assert_eq!(
+            check_placement(&wall(), &existing, &layout_info(1), 0.5, 1.5, MARGINS),
+            PlacementCheck::OffWall {
+                left: 0.25,
+                right: 0.0,
+                bottom: 0.0,
+                top: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_check_placement_reports_how_far_off_wall() {
+        assert_eq!(
+            check_placement(&wall(), &[], &layout_info(1), 5.0, -0.5, Default::default()),
+            PlacementCheck::OffWall {
+                left: 0.0,
+                right: 0.5,
+                bottom: 1.0,
+                top: 0.0,
+            }
+        );
+        // Even an art object that doesn't overlap the wall at all.
+        let too_big = ArtObjectLayoutInfo {
+            width: 6.0,
+            ..layout_info(1)
+        };
+        let PlacementCheck::OffWall { left, right, .. } =
+            check_placement(&wall(), &[], &too_big, 2.5, 1.5, Default::default())
+        else {
+            panic!("expected art object to be off the wall");
+        };
+        assert_eq!((left, right), (0.5, 0.5));
+    }
+
+    #[test]
+    fn test_check_placement_ignores_candidate() {
+        let existing = [(layout_info(1), (2.0, 1.5))];
+        assert_eq!(
+            check_placement(&wall(), &existing, &layout_info(1), 2.25, 1.5, MARGINS),
+            PlacementCheck::Ok
+        );
+    }
+}
@@ -1,7 +1,21 @@
-const MODULUS: u64 = 2 << 32;
+const MODULUS: u64 = 1 << 32;
 const MULTIPLIER: u64 = 1664525;
 const INCREMENT: u64 = 1013904223;
 
+/// Bumped whenever the sequence of numbers generated from a given seed
+/// changes, e.g. because the generator or the shuffling algorithm changed.
+/// Layouts randomized with the same seed under an older version will differ.
+///
+/// Version 1 used a modulus of 2^33 and a biased shuffle.
+pub const LAYOUT_RNG_VERSION: u32 = 2;
+
+/// The layout metadata key for the `LAYOUT_RNG_VERSION` used to randomize the
+/// most recent layout.
+pub const LAYOUT_RNG_VERSION_METADATA_KEY: &str = "rng_version";
+
+/// The layout metadata key for the seed used to randomize the most recent layout.
+pub const LAYOUT_RANDOM_SEED_METADATA_KEY: &str = "random_seed";
+
 #[derive(Default, Debug)]
 pub struct Rng {
     pub seed: u64,
@@ -12,6 +26,10 @@ pub struct Rng {
 ///
 /// The parameters for this RNG are taken from Numerical Recipes
 /// by Knuth and H. W. Lewis.
+///
+/// Note that the low bits of an LCG with a power-of-two modulus are very
+/// predictable (the lowest bit just alternates), so anything that derives
+/// integers from it should use the high bits, like `gen_range()` does.
 impl Rng {
     pub fn new(seed: Option<u64>) -> Self {
         Rng {
@@ -19,8 +37,15 @@ impl Rng {
         }
     }
 
+    pub fn next_u32(&mut self) -> u32 {
+        // Since the modulus is a power of two, wrapping is the same as taking
+        // the remainder, and it avoids overflowing on huge initial seeds.
+        self.seed = self.seed.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT) % MODULUS;
+        self.seed as u32
+    }
+
     pub fn random(&mut self) -> f64 {
-        self.seed = (MULTIPLIER * self.seed + INCREMENT) % MODULUS;
+        self.next_u32();
         self.latest_random()
     }
 
@@ -28,9 +53,40 @@ impl Rng {
         (self.seed as f64) / (MODULUS as f64)
     }
 
+    /// Returns a uniformly distributed number in `0..upper`.
+    ///
+    /// This uses Lemire's multiply-and-shift method, rejecting the few values
+    /// that would otherwise make some results more likely than others.
+    ///
+    /// Panics if `upper` is zero or doesn't fit in a `u32`.
+    pub fn gen_range(&mut self, upper: usize) -> usize {
+        assert!(upper > 0, "upper bound must be positive");
+        let upper: u64 = u32::try_from(upper)
+            .expect("upper bound must fit in a u32")
+            .into();
+        let threshold = (MODULUS - upper) % upper;
+        loop {
+            let product = self.next_u32() as u64 * upper;
+            if product % MODULUS >= threshold {
+                return (product >> 32) as usize;
+            }
+        }
+    }
+
+    /// Returns a random element of the slice, or `None` if it's empty.
+    pub fn choose<'a, T>(&mut self, slice: &'a [T]) -> Option<&'a T> {
+        if slice.is_empty() {
+            None
+        } else {
+            Some(&slice[self.gen_range(slice.len())])
+        }
+    }
+
+    /// Shuffles the slice in place using the Fisher-Yates algorithm, so
+    /// that every permutation is equally likely.
     pub fn shuffle<T>(&mut self, array: &mut [T]) {
-        for i in 0..array.len() {
-            let target = (self.random() * array.len() as f64).floor() as usize;
+        for i in (1..array.len()).rev() {
+            let target = self.gen_range(i + 1);
             array.swap(i, target);
         }
     }
@@ -50,3 +106,118 @@ fn seed_from_current_time() -> u64 {
         .unwrap()
         .as_secs()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Rng;
+
+    /// Pearson's chi-squared statistic for the given counts, assuming every
+    /// bucket is equally likely.
+    fn chi_squared(counts: &[usize]) -> f64 {
+        let total: usize = counts.iter().sum();
+        let expected = total as f64 / counts.len() as f64;
+        counts
+            .iter()
+            .map(|&count| (count as f64 - expected).powi(2) / expected)
+            .sum()
+    }
+
+    #[test]
+    fn test_seeds_are_reproducible() {
+        let mut rng = Rng::new(Some(0));
+        assert_eq!(rng.next_u32(), 1013904223);
+        assert_eq!(rng.next_u32(), 1196435762);
+
+        let mut a = Rng::new(Some(1234));
+        let mut b = Rng::new(Some(1234));
+        let mut array_a: Vec<usize> = (0..50).collect();
+        let mut array_b = array_a.clone();
+        a.shuffle(&mut array_a);
+        b.shuffle(&mut array_b);
+        assert_eq!(array_a, array_b);
+        assert_ne!(array_a, (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_huge_seeds_do_not_overflow() {
+        let mut rng = Rng::new(Some(u64::MAX));
+        let value = rng.random();
+        assert!((0.0..1.0).contains(&value));
+        assert!(rng.seed < 1 << 32);
+    }
+
+    #[test]
+    fn test_gen_range_stays_in_bounds() {
+        let mut rng = Rng::new(Some(5));
+        assert_eq!(rng.gen_range(1), 0);
+        for upper in [2, 3, 7, 10, 1000] {
+            for _ in 0..1000 {
+                assert!(rng.gen_range(upper) < upper);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gen_range_is_uniform() {
+        let mut rng = Rng::new(Some(42));
+        let mut counts = [0; 10];
+        for _ in 0..100_000 {
+            counts[rng.gen_range(counts.len())] += 1;
+        }
+        // The critical value for 9 degrees of freedom at p = 0.001 is 27.88.
+        let statistic = chi_squared(&counts);
+        assert!(statistic < 27.88, "{counts:?} has chi-squared {statistic}");
+    }
+
+    #[test]
+    fn test_choose_works() {
+        let mut rng = Rng::new(Some(1));
+        let empty: [u8; 0] = [];
+        assert_eq!(rng.choose(&empty), None);
+        assert_eq!(rng.choose(&["only"]), Some(&"only"));
+        let choices = ["a", "b", "c"];
+        for _ in 0..100 {
+            assert!(choices.contains(rng.choose(&choices).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_shuffle_permutations_are_uniform() {
+        let mut rng = Rng::new(Some(1));
+        let mut counts: HashMap<[u8; 3], usize> = HashMap::new();
+        for _ in 0..60_000 {
+            let mut array = [0, 1, 2];
+            rng.shuffle(&mut array);
+            *counts.entry(array).or_default() += 1;
+        }
+        assert_eq!(counts.len(), 6);
+        let counts: Vec<usize> = counts.into_values().collect();
+        // The critical value for 5 degrees of freedom at p = 0.001 is 20.52.
+        // The naive swap-with-any-index shuffle scores in the hundreds here.
+        let statistic = chi_squared(&counts);
+        assert!(statistic < 20.52, "{counts:?} has chi-squared {statistic}");
+    }
+
+    #[test]
+    fn test_shuffle_positions_are_uniform() {
+        let mut rng = Rng::new(Some(7));
+        let mut counts = [[0; 5]; 5];
+        for _ in 0..50_000 {
+            let mut array = [0, 1, 2, 3, 4];
+            rng.shuffle(&mut array);
+            for (position, &value) in array.iter().enumerate() {
+                counts[value][position] += 1;
+            }
+        }
+        // The critical value for 4 degrees of freedom at p = 0.001 is 18.47.
+        for (value, positions) in counts.iter().enumerate() {
+            let statistic = chi_squared(positions);
+            assert!(
+                statistic < 18.47,
+                "{value} landed at {positions:?}, chi-squared {statistic}"
+            );
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    art_object::ArtObjectId,
+    gallery_db::{ArtObjectRecord, GalleryDb, LayoutRecord},
+};
+
+/// The version of the format written by `SharedLayout::from_layout_records()`.
+pub const SHARED_LAYOUT_VERSION: usize = 1;
+
+/// Enough about an art object for a player whose database doesn't have it to
+/// know what it is, and where to get it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SharedArtObject {
+    pub id: ArtObjectId,
+    pub title: String,
+    pub artist: String,
+    pub collection: String,
+    pub width: f64,
+    pub height: f64,
+    /// Where the art object can be seen online, see `ArtObjectId::url()`.
+    pub url: String,
+    /// The name of the image on Wikimedia Commons, for wikidata objects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_filename: Option<String>,
+}
+
+impl From<&ArtObjectRecord> for SharedArtObject {
+    fn from(record: &ArtObjectRecord) -> Self {
+        SharedArtObject {
+            id: record.object_id,
+            title: record.title.clone(),
+            artist: record.artist.clone(),
+            collection: record.collection.clone(),
+            width: record.width,
+            height: record.height,
+            url: record.object_id.url(),
+            image_filename: match record.object_id {
+                ArtObjectId::Wikidata(_) => Some(record.filename.clone()),
+                ArtObjectId::Met(_) => None,
+            },
+        }
+    }
+}
+
+/// Like `LayoutRecord`, but with the art object's metadata embedded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SharedLayoutRecord {
+    pub gallery_id: i64,
+    pub wall_id: String,
+    pub x: f64,
+    pub y: f64,
+    #[serde(default)]
+    pub rotated: bool,
+    pub art_object: SharedArtObject,
+}
+
+impl SharedLayoutRecord {
+    pub fn layout_record(&self) -> LayoutRecord<String> {
+        LayoutRecord {
+            gallery_id: self.gallery_id,
+            wall_id: self.wall_id.clone(),
+            art_object_id: self.art_object.id,
+            x: self.x,
+            y: self.y,
+            rotated: self.rotated,
+        }
+    }
+}
+
+/// A layout that can be shared with players who don't have the same database,
+/// e.g. a curated lobby.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SharedLayout {
+    pub version: usize,
+    pub records: Vec<SharedLayoutRecord>,
+}
+
+impl SharedLayout {
+    /// Embeds the metadata of the art objects in the given records. Records
+    /// whose art objects aren't in the database are left out, since there's
+    /// nothing to embed.
+    pub fn from_layout_records(db: &GalleryDb, records: Vec<LayoutRecord<String>>) -> Result<Self> {
+        let mut shared_records = Vec::with_capacity(records.len());
+        for record in records {
+            let Some(art_object) = db.get_art_object(record.art_object_id)? else {
+                warn!(
+                    "Not sharing layout record for {:?}, it isn't in the database.",
+                    record.art_object_id
+                );
+                continue;
+            };
+            shared_records.push(SharedLayoutRecord {
+                gallery_id: record.gallery_id,
+                wall_id: record.wall_id,
+                x: record.x,
+                y: record.y,
+                rotated: record.rotated,
+                art_object: SharedArtObject::from(&art_object),
+            });
+        }
+        Ok(SharedLayout {
+            version: SHARED_LAYOUT_VERSION,
+            records: shared_records,
+        })
+    }
+
+    /// Splits the layout into the records whose art objects are in the given
+    /// database, and the art objects that aren't (each listed only once).
+    pub fn split_by_known_art_objects(
+        self,
+        db: &GalleryDb,
+    ) -> Result<(Vec<LayoutRecord<String>>, Vec<SharedArtObject>)> {
+        let mut known = vec![];
+        let mut unknown: Vec<SharedArtObject> = vec![];
+        for record in self.records {
+            if db.get_art_object(record.art_object.id)?.is_some() {
+                known.push(record.layout_record());
+            } else if !unknown
+                .iter()
+                .any(|object| object.id == record.art_object.id)
+            {
+                unknown.push(record.art_object);
+            }
+        }
+        Ok((known, unknown))
+    }
+}
+
+/// An exported non-positive layout, in either of the formats that the game
+/// can export.
+#[derive(Debug, PartialEq)]
+pub enum LayoutDocument {
+    /// Just the layout records, which only make sense to someone with the same
+    /// database.
+    Records(Vec<LayoutRecord<String>>),
+    Shared(SharedLayout),
+}
+
+impl LayoutDocument {
+    /// Plain layouts are JSON arrays, while shared layouts are JSON objects.
+    pub fn parse(json_content: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(json_content)?;
+        if value.is_array() {
+            return Ok(LayoutDocument::Records(serde_json::from_value(value)?));
+        }
+        let layout: SharedLayout = serde_json::from_value(value)?;
+        if layout.version > SHARED_LAYOUT_VERSION {
+            return Err(anyhow!(
+                "Shared layout version {} is newer than the latest supported version {}",
+                layout.version,
+                SHARED_LAYOUT_VERSION
+            ));
+        }
+        Ok(LayoutDocument::Shared(layout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::{
+        art_object::ArtObjectId,
+        gallery_db::{ArtObjectRecord, GalleryDb, LayoutRecord},
+        medium::MediumCategory,
+    };
+
+    use super::{LayoutDocument, SharedArtObject, SharedLayout, SHARED_LAYOUT_VERSION};
+
+    fn make_record(object_id: ArtObjectId, title: &str) -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id,
+            object_date: "1890".into(),
+            culture: "".into(),
+            artist: "Boop Jones".into(),
+            artist_role: String::default(),
+            title: title.into(),
+            medium: "Oil on canvas".into(),
+            medium_category: MediumCategory::Oil,
+            width: 0.5,
+            height: 1.0,
+            fallback_wikidata_qid: None,
+            filename: "Boop.jpg".into(),
+            collection: "Martian Museum of Art".into(),
+            artist_qid: None,
+            highlight: false,
+            tags: String::default(),
+            classification: String::default(),
+            source_gallery: None,
+        }
+    }
+
+    fn make_layout_record(art_object_id: ArtObjectId) -> LayoutRecord<String> {
+        LayoutRecord {
+            gallery_id: -1,
+            wall_id: "wall_a".into(),
+            art_object_id,
+            x: 1.0,
+            y: 1.5,
+            rotated: false,
+        }
+    }
+
+    fn create_db(records: Vec<ArtObjectRecord>) -> GalleryDb {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.create_schema().unwrap();
+        db.add_art_objects(&records).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_shared_layouts_round_trip() {
+        let sender = create_db(vec![
+            make_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_record(ArtObjectId::Wikidata(2), "Funky Monkey"),
+        ]);
+        let shared = SharedLayout::from_layout_records(
+            &sender,
+            vec![
+                make_layout_record(ArtObjectId::Met(1)),
+                make_layout_record(ArtObjectId::Wikidata(2)),
+                make_layout_record(ArtObjectId::Met(3)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(shared.version, SHARED_LAYOUT_VERSION);
+        assert_eq!(shared.records.len(), 2);
+        assert_eq!(shared.records[0].art_object.image_filename, None);
+        assert_eq!(
+            shared.records[1].art_object,
+            SharedArtObject {
+                id: ArtObjectId::Wikidata(2),
+                title: "Funky Monkey".into(),
+                artist: "Boop Jones".into(),
+                collection: "Martian Museum of Art".into(),
+                width: 0.5,
+                height: 1.0,
+                url: "https://www.wikidata.org/wiki/Q2".into(),
+                image_filename: Some("Boop.jpg".into()),
+            }
+        );
+
+        let json = serde_json::to_string(&shared).unwrap();
+        let LayoutDocument::Shared(parsed) = LayoutDocument::parse(&json).unwrap() else {
+            panic!("expected shared layout");
+        };
+        assert_eq!(parsed, shared);
+
+        let receiver = create_db(vec![make_record(ArtObjectId::Met(1), "Funky Painting")]);
+        let (known, unknown) = parsed.split_by_known_art_objects(&receiver).unwrap();
+        assert_eq!(known, vec![make_layout_record(ArtObjectId::Met(1))]);
+        assert_eq!(
+            unknown
+                .iter()
+                .map(|object| object.id)
+                .collect::<Vec<ArtObjectId>>(),
+            vec![ArtObjectId::Wikidata(2)]
+        );
+    }
+
+    #[test]
+    fn test_layout_document_parses_plain_records() {
+        let records = vec![make_layout_record(ArtObjectId::Met(1))];
+        let json = serde_json::to_string(&records).unwrap();
+        assert_eq!(
+            LayoutDocument::parse(&json).unwrap(),
+            LayoutDocument::Records(records)
+        );
+        assert!(LayoutDocument::parse("boop").is_err());
+        assert!(LayoutDocument::parse(r#"{"version": 999, "records": []}"#).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+/// Shared between a worker and whoever asks it to quit, so that whatever the
+/// worker is in the middle of, e.g. a download, gets a little while to finish
+/// before it's abandoned. See `GalleryCache::set_shutdown_signal()`.
+#[derive(Default)]
+pub struct ShutdownSignal {
+    /// When anything that's still in progress should give up, if we've started
+    /// shutting down.
+    deadline: Mutex<Option<Instant>>,
+    /// Whether whoever asked the worker to quit has stopped waiting for it,
+    /// see `abandon()`.
+    abandoned: AtomicBool,
+}
+
+impl ShutdownSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts shutting down, giving anything in progress until `grace_period`
+    /// from now to finish. Calling this again doesn't move the deadline.
+    pub fn begin(&self, grace_period: Duration) {
+        let mut deadline = self.lock();
+        if deadline.is_none() {
+            *deadline = Some(Instant::now() + grace_period);
+        }
+    }
+
+    /// Whether `begin()` has been called, in which case nothing new should be
+    /// started.
+    pub fn is_shutting_down(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    /// Whether anything that's still in progress should give up.
+    pub fn is_past_deadline(&self) -> bool {
+        self.lock()
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Like `begin()` with no grace period, but also tells the worker that
+    /// nobody is waiting for it anymore, e.g. because it hung and has been
+    /// replaced by another worker using the same files. If it ever wakes up, it
+    /// shouldn't write to them anymore, since the new worker owns them now.
+    pub fn abandon(&self) {
+        self.begin(Duration::ZERO);
+        self.abandoned.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether `abandon()` has been called.
+    pub fn is_abandoned(&self) -> bool {
+        self.abandoned.load(Ordering::SeqCst)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Instant>> {
+        self.deadline.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Wraps a reader so that it errors once the given signal's deadline has
+/// passed, e.g. so that a download stops partway through.
+pub struct ShutdownAwareReader<'a, R: Read> {
+    inner: R,
+    signal: &'a ShutdownSignal,
+}
+
+impl<'a, R: Read> ShutdownAwareReader<'a, R> {
+    pub fn new(inner: R, signal: &'a ShutdownSignal) -> Self {
+        ShutdownAwareReader { inner, signal }
+    }
+}
+
+impl<R: Read> Read for ShutdownAwareReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.signal.is_past_deadline() {
+            // Note that this can't be `ErrorKind::Interrupted`, since
+            // `std::io::copy()` would just try again.
+            return Err(io::Error::other("Shutting down, gave up before finishing"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Cursor, Read},
+        time::Duration,
+    };
+
+    use super::{ShutdownAwareReader, ShutdownSignal};
+
+    #[test]
+    fn test_signal_works() {
+        let signal = ShutdownSignal::new();
+        assert!(!signal.is_shutting_down());
+        assert!(!signal.is_past_deadline());
+
+        signal.begin(Duration::from_secs(60));
+        assert!(signal.is_shutting_down());
+        assert!(!signal.is_past_deadline());
+
+        // The original deadline still applies.
+        signal.begin(Duration::ZERO);
+        assert!(!signal.is_past_deadline());
+    }
+
+    #[test]
+    fn test_abandoning_shuts_down_immediately() {
+        let signal = ShutdownSignal::new();
+        assert!(!signal.is_abandoned());
+        signal.abandon();
+        assert!(signal.is_abandoned());
+        assert!(signal.is_past_deadline());
+
+        // Merely shutting down isn't abandoning.
+        let signal = ShutdownSignal::new();
+        signal.begin(Duration::ZERO);
+        assert!(!signal.is_abandoned());
+    }
+
+    #[test]
+    fn test_reader_errors_once_past_deadline() {
+        let signal = ShutdownSignal::new();
+        let mut reader = ShutdownAwareReader::new(Cursor::new(b"boopboop"), &signal);
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"boop");
+
+        signal.begin(Duration::ZERO);
+        assert!(reader.read_exact(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_reader_works_during_grace_period() {
+        let signal = ShutdownSignal::new();
+        signal.begin(Duration::from_secs(60));
+        let mut contents = String::new();
+        ShutdownAwareReader::new(Cursor::new("boop"), &signal)
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "boop");
+    }
+}
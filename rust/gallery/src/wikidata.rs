@@ -1,15 +1,18 @@
+use std::collections::HashMap;
+
 use anyhow::{anyhow, Result};
+use log::debug;
 use percent_encoding::{utf8_percent_encode, CONTROLS};
 use serde::{de, Deserialize};
 
 use crate::{
-    gallery_cache::GalleryCache,
-    image::{cache_image, get_supported_image_ext, ImageSize},
+    gallery_cache::{get_http_status, GalleryCache},
+    image::{cache_image, get_cached_image, get_supported_image_ext, ImageCacheOptions, ImageSize},
 };
 
-const ROOT_CACHE_SUBDIR: &'static str = "wikidata";
+const ROOT_CACHE_SUBDIR: &str = "wikidata";
 
-const WIKIDATA_URL_PREFIXES: [&'static str; 3] = [
+const WIKIDATA_URL_PREFIXES: [&str; 3] = [
     "http://www.wikidata.org/entity/Q",
     "https://www.wikidata.org/wiki/Q",
     // I guess this is theoretically still a URL, it's just _very_ relative. Makes it easy for
@@ -19,6 +22,36 @@ const WIKIDATA_URL_PREFIXES: [&'static str; 3] = [
 
 const SMALL_IMAGE_WIDTH: usize = 500;
 
+/// A way of constructing the URL for a Wikimedia Commons image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFetchStrategy {
+    /// Construct the URL on upload.wikimedia.org directly, based on the MD5 hash of
+    /// the filename. This avoids a redirect, but doesn't work for files that have
+    /// been renamed or that live on legacy shards.
+    DirectUpload,
+    /// Use commons.wikimedia.org's `Special:FilePath`, which redirects to wherever
+    /// the file actually lives.
+    SpecialFilePath,
+}
+
+/// The strategies to try, in order. Later strategies are only tried if the
+/// earlier ones result in a 404.
+pub const DEFAULT_IMAGE_FETCH_STRATEGIES: [ImageFetchStrategy; 2] = [
+    ImageFetchStrategy::DirectUpload,
+    ImageFetchStrategy::SpecialFilePath,
+];
+
+impl ImageFetchStrategy {
+    pub fn get_url_for_image<T: AsRef<str>>(&self, image_filename: T, size: ImageSize) -> String {
+        match self {
+            ImageFetchStrategy::DirectUpload => get_url_for_image(image_filename, size),
+            ImageFetchStrategy::SpecialFilePath => {
+                get_special_file_path_url_for_image(image_filename, size)
+            }
+        }
+    }
+}
+
 pub fn try_to_parse_qid_from_wikidata_url<T: AsRef<str>>(url: T) -> Option<u64> {
     for prefix in WIKIDATA_URL_PREFIXES {
         if url.as_ref().starts_with(prefix) {
@@ -102,26 +135,87 @@ pub struct WikidataImageInfo {
 }
 
 impl WikidataImageInfo {
-    pub fn try_to_download_image(&self, cache: &GalleryCache, size: ImageSize) -> Result<String> {
-        let image_url = get_url_for_image(&self.image_filename, size);
+    pub fn try_to_download_image(
+        &self,
+        cache: &GalleryCache,
+        size: ImageSize,
+        options: &ImageCacheOptions,
+    ) -> Result<String> {
+        self.try_to_download_image_with_strategies(
+            cache,
+            size,
+            options,
+            &DEFAULT_IMAGE_FETCH_STRATEGIES,
+        )
+    }
+
+    /// Download the image using the first of the given strategies that doesn't
+    /// give us a 404. Regardless of which one succeeds, the image is cached under
+    /// the same filename (or its re-encoded equivalent, see `cache_image()`).
+    pub fn try_to_download_image_with_strategies(
+        &self,
+        cache: &GalleryCache,
+        size: ImageSize,
+        options: &ImageCacheOptions,
+        strategies: &[ImageFetchStrategy],
+    ) -> Result<String> {
         let Some(ext) = get_supported_image_ext(&self.image_filename) else {
             return Err(anyhow!(
                 "Invalid file extension for image: {}",
                 self.image_filename
             ));
         };
-        let image_filename = match size {
-            ImageSize::Small => format!(
-                "{ROOT_CACHE_SUBDIR}/Q{}-small-{SMALL_IMAGE_WIDTH}px{ext}",
-                self.qid
-            ),
-            ImageSize::Large => format!("{ROOT_CACHE_SUBDIR}/Q{}{ext}", self.qid),
-        };
-        cache_image(cache, &image_url, &image_filename, ext)?;
-        Ok(image_filename)
+        let image_filename = wikidata_image_cache_path(self.qid, size, ext);
+        let mut cached_filename = image_filename.clone();
+        fetch_with_strategies(&self.image_filename, size, strategies, |image_url| {
+            cached_filename = cache_image(cache, image_url, &image_filename, ext, options)?;
+            Ok(())
+        })?;
+        Ok(cached_filename)
+    }
+
+    /// Like `try_to_download_image()`, but only returns the filename if the
+    /// image is already cached.
+    pub fn get_cached_image(&self, cache: &GalleryCache, size: ImageSize) -> Option<String> {
+        let ext = get_supported_image_ext(&self.image_filename)?;
+        get_cached_image(cache, &wikidata_image_cache_path(self.qid, size, ext), ext)
+    }
+}
+
+/// The filename, relative to the cache directory, that the given size of the
+/// image of the art object with the given QID is cached under, if its filename
+/// on Wikimedia Commons has the given extension. Note that it may have been
+/// re-encoded, see `get_cached_image()`.
+pub fn wikidata_image_cache_path(qid: i64, size: ImageSize, ext: &str) -> String {
+    match size {
+        ImageSize::Small => format!("{ROOT_CACHE_SUBDIR}/Q{qid}-small-{SMALL_IMAGE_WIDTH}px{ext}"),
+        ImageSize::Large => format!("{ROOT_CACHE_SUBDIR}/Q{qid}{ext}"),
     }
 }
 
+/// Call `fetch` with the URL for each strategy in turn, until one of them
+/// succeeds or fails with something other than a 404.
+fn fetch_with_strategies<F: FnMut(&str) -> Result<()>>(
+    image_filename: &str,
+    size: ImageSize,
+    strategies: &[ImageFetchStrategy],
+    mut fetch: F,
+) -> Result<()> {
+    let mut last_err = anyhow!("No image fetch strategies provided");
+    for strategy in strategies {
+        let image_url = strategy.get_url_for_image(image_filename, size);
+        match fetch(&image_url) {
+            Ok(()) => return Ok(()),
+            Err(err) if get_http_status(&err) == Some(404) => {
+                debug!("Got 404 for {image_url} using {strategy:?}.");
+                last_err = err;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Err(last_err)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct WikidataEntity {
     #[serde(deserialize_with = "deserialize_wikidata_entity_url_string")]
@@ -129,8 +223,21 @@ pub struct WikidataEntity {
     labels: Option<LocalizedValues>,
     descriptions: Option<LocalizedValues>,
     claims: Claims,
+    /// Links to the entity's pages on Wikipedia and other Wikimedia sites. We only
+    /// care how many there are, as a rough measure of how well-known it is.
+    #[serde(default)]
+    sitelinks: HashMap<String, de::IgnoredAny>,
 }
 
+/// Wikidata entities with at least this many sitelinks are considered
+/// highlights, see `WikidataEntity::is_highlight()`.
+pub const HIGHLIGHT_SITELINK_THRESHOLD: usize = 10;
+
+/// Stands in for the creator of works whose P170 is the "unknown value"
+/// special value, which is how Wikidata marks anonymous works. There's no Q0,
+/// so this can't be confused with a real creator.
+pub const ANONYMOUS_CREATOR_ID: u64 = 0;
+
 #[derive(Debug, Deserialize)]
 pub struct WikidataEntityClaimsOnly {
     claims: Claims,
@@ -152,10 +259,15 @@ impl WikidataEntity {
     pub fn image_filename(&self) -> Option<&String> {
         self.claims.image_filename()
     }
+    /// Like `image_filename()`, but lets the caller decide whether to skip images
+    /// that look like they aren't of the whole artwork, see `Claims::image_filename_with_heuristics()`.
+    pub fn image_filename_with_heuristics(&self, skip_non_artwork: bool) -> Option<&String> {
+        self.claims.image_filename_with_heuristics(skip_non_artwork)
+    }
     pub fn dimensions_in_cm(&self) -> Option<(f64, f64)> {
         if let (Some(width), Some(height)) = (
-            self.claims.p2049.find_cm_amount(),
-            self.claims.p2048.find_cm_amount(),
+            self.claims.p2049.find_length_in_cm(),
+            self.claims.p2048.find_length_in_cm(),
         ) {
             if width > 0.0 && height > 0.0 {
                 return Some((width, height));
@@ -163,8 +275,30 @@ impl WikidataEntity {
         }
         None
     }
+    /// Returns every creator of the entity, best-ranked first, with
+    /// `ANONYMOUS_CREATOR_ID` standing in for unknown ones. Collaborations,
+    /// e.g. a work by an artist and their workshop, have more than one.
+    pub fn creator_ids(&self) -> Vec<u64> {
+        let mut ids = vec![];
+        for snak in self.claims.p170.snaks_by_rank() {
+            let id = match snak {
+                SnakValue::Value(datavalue) => datavalue.entity_id(),
+                SnakValue::SomeValue => Some(ANONYMOUS_CREATOR_ID),
+                SnakValue::NoValue => None,
+            };
+            if let Some(id) = id {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids
+    }
+    /// Returns the best-ranked creator that isn't anonymous, see `creator_ids()`.
     pub fn creator_id(&self) -> Option<u64> {
-        self.claims.p170.find(|datavalue| datavalue.entity_id())
+        self.creator_ids()
+            .into_iter()
+            .find(|id| *id != ANONYMOUS_CREATOR_ID)
     }
     pub fn material_ids(&self) -> Vec<u64> {
         self.claims.p186.find_all(|datavalue| match datavalue {
@@ -174,6 +308,15 @@ impl WikidataEntity {
             _ => None,
         })
     }
+    pub fn sitelink_count(&self) -> usize {
+        self.sitelinks.len()
+    }
+    /// Whether the entity seems notable enough to be featured, see
+    /// `ArtObjectRecord::highlight`.
+    pub fn is_highlight(&self) -> bool {
+        self.claims.p1257.find(|_| Some(())).is_some()
+            || self.sitelink_count() >= HIGHLIGHT_SITELINK_THRESHOLD
+    }
     pub fn collection_id(&self) -> Option<u64> {
         self.claims.p195.find(|datavalue| datavalue.entity_id())
     }
@@ -206,12 +349,15 @@ struct Statements(Vec<Statement>);
 impl Statements {
     /// Iterate through all statements calling the given callback with the statement's
     /// mainsnak datavalue. Once the callback returns a `Some()` value, return it immediately.
+    ///
+    /// Statements with the "unknown value" or "no value" special values are skipped,
+    /// use `snaks_by_rank()` to tell them apart from missing statements.
     fn find<'a, T, F>(&'a self, callback: F) -> Option<T>
     where
         F: Fn(&'a Datavalue) -> Option<T>,
     {
         for statement in &self.0 {
-            if let Some(datavalue) = &statement.mainsnak.datavalue {
+            if let Some(SnakValue::Value(datavalue)) = statement.mainsnak.value() {
                 if let Some(result) = callback(datavalue) {
                     return Some(result);
                 }
@@ -220,15 +366,60 @@ impl Statements {
         None
     }
 
-    fn find_cm_amount(&self) -> Option<f64> {
-        self.find(|datavalue| {
+    /// Iterate through statements with preferred rank, followed by the others,
+    /// ignoring deprecated statements entirely. Statements without a rank are
+    /// treated like normal ones.
+    fn statements_by_rank(&self) -> impl Iterator<Item = &Statement> {
+        let preferred = self
+            .0
+            .iter()
+            .filter(|statement| statement.rank == Some(Rank::Preferred));
+        let others = self.0.iter().filter(|statement| {
+            !matches!(
+                statement.rank,
+                Some(Rank::Preferred) | Some(Rank::Deprecated)
+            )
+        });
+        preferred.chain(others)
+    }
+
+    /// Like `statements_by_rank()`, but yields the values of their mainsnaks,
+    /// including the special ones.
+    fn snaks_by_rank(&self) -> impl Iterator<Item = SnakValue<'_>> {
+        self.statements_by_rank()
+            .filter_map(|statement| statement.mainsnak.value())
+    }
+
+    /// Like `snaks_by_rank()`, but only yields actual datavalues.
+    fn datavalues_by_rank(&self) -> impl Iterator<Item = &Datavalue> {
+        self.snaks_by_rank().filter_map(|snak| match snak {
+            SnakValue::Value(datavalue) => Some(datavalue),
+            _ => None,
+        })
+    }
+
+    /// Like `find()`, but tries statements with preferred rank before the others,
+    /// and ignores deprecated statements entirely.
+    fn find_by_rank<'a, T, F>(&'a self, callback: F) -> Option<T>
+    where
+        F: Fn(&'a Datavalue) -> Option<T>,
+    {
+        self.datavalues_by_rank().find_map(callback)
+    }
+
+    /// Returns the first length that's in a unit we know how to convert,
+    /// converted to centimetres.
+    fn find_length_in_cm(&self) -> Option<f64> {
+        self.find_by_rank(|datavalue| {
             if let Datavalue::Quantity {
-                value: Quantity { amount, unit },
+                value:
+                    Quantity {
+                        amount,
+                        unit: Some(unit),
+                    },
             } = datavalue
             {
-                if unit == &Some(CENTIMETRE_QID) {
-                    return Some(*amount);
-                }
+                return centimetres_per_unit(*unit).map(|factor| amount * factor);
             }
             None
         })
@@ -241,7 +432,7 @@ impl Statements {
     {
         let mut results = Vec::with_capacity(self.0.len());
         for statement in &self.0 {
-            if let Some(datavalue) = &statement.mainsnak.datavalue {
+            if let Some(SnakValue::Value(datavalue)) = statement.mainsnak.value() {
                 if let Some(result) = callback(datavalue) {
                     results.push(result);
                 }
@@ -283,38 +474,144 @@ struct Claims {
     /// P571 - Inception
     #[serde(rename = "P571", default)]
     p571: Statements,
+
+    /// P1257 - Depicts Iconclass notation
+    #[serde(rename = "P1257", default)]
+    p1257: Statements,
+}
+
+/// Words in image filenames that suggest the image isn't of the artwork as a
+/// whole, e.g. a close-up or the back of the canvas.
+const NON_ARTWORK_IMAGE_MARKERS: [&str; 3] = ["detail", "verso", "frame"];
+
+fn looks_like_non_artwork_image(image_filename: &str) -> bool {
+    let image_filename = image_filename.to_lowercase();
+    NON_ARTWORK_IMAGE_MARKERS
+        .iter()
+        .any(|marker| image_filename.contains(marker))
 }
 
 impl Claims {
     pub fn image_filename(&self) -> Option<&String> {
-        self.p18.find(|datavalue| match datavalue {
-            Datavalue::String {
-                value: image_filename,
-            } => {
-                if get_supported_image_ext(&image_filename).is_some() {
-                    Some(image_filename)
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        })
+        self.image_filename_with_heuristics(true)
+    }
+
+    /// Returns the best-ranked image with a supported extension. If `skip_non_artwork`
+    /// is true, images whose filenames suggest they're of a detail, the back, or
+    /// the frame of the artwork are only returned if there's nothing else.
+    pub fn image_filename_with_heuristics(&self, skip_non_artwork: bool) -> Option<&String> {
+        let mut image_filenames = self
+            .p18
+            .datavalues_by_rank()
+            .filter_map(|datavalue| match datavalue {
+                Datavalue::String {
+                    value: image_filename,
+                } if get_supported_image_ext(&image_filename).is_some() => Some(image_filename),
+                _ => None,
+            })
+            .peekable();
+        let first = *image_filenames.peek()?;
+        if !skip_non_artwork {
+            return Some(first);
+        }
+        Some(
+            image_filenames
+                .find(|image_filename| !looks_like_non_artwork_image(image_filename))
+                .unwrap_or(first),
+        )
     }
 }
 
 /// https://www.wikidata.org/wiki/Q174728
 const CENTIMETRE_QID: u64 = 174728;
 
+/// https://www.wikidata.org/wiki/Q11573
+const METRE_QID: u64 = 11573;
+
+/// https://www.wikidata.org/wiki/Q174789
+const MILLIMETRE_QID: u64 = 174789;
+
+/// https://www.wikidata.org/wiki/Q218593
+const INCH_QID: u64 = 218593;
+
+/// https://www.wikidata.org/wiki/Q3710
+const FOOT_QID: u64 = 3710;
+
+/// Returns how many centimetres are in the given unit of length, or `None` if
+/// it's not a unit of length we know about.
+fn centimetres_per_unit(unit_qid: u64) -> Option<f64> {
+    match unit_qid {
+        CENTIMETRE_QID => Some(1.0),
+        METRE_QID => Some(100.0),
+        MILLIMETRE_QID => Some(0.1),
+        INCH_QID => Some(2.54),
+        FOOT_QID => Some(30.48),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct Statement {
     mainsnak: Mainsnak,
+    /// Not all JSON includes this, e.g. our test fixtures.
+    #[serde(default)]
+    rank: Option<Rank>,
+}
+
+/// See https://www.wikidata.org/wiki/Help:Ranking.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Rank {
+    Preferred,
+    Normal,
+    Deprecated,
+    #[serde(other)]
+    Unknown,
+}
+
+/// See https://www.wikidata.org/wiki/Help:Statements#Unknown_or_no_values.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SnakType {
+    #[default]
+    Value,
+    SomeValue,
+    NoValue,
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Deserialize)]
 struct Mainsnak {
+    /// Not all JSON includes this, e.g. our test fixtures, in which case the
+    /// snak is assumed to have a value.
+    #[serde(default)]
+    snaktype: SnakType,
     datavalue: Option<Datavalue>,
 }
 
+/// What a mainsnak says about its property.
+#[derive(Debug)]
+enum SnakValue<'a> {
+    Value(&'a Datavalue),
+    /// The property has a value, but nobody knows what it is, e.g. the
+    /// creator of an anonymous work.
+    SomeValue,
+    /// The property definitely has no value.
+    NoValue,
+}
+
+impl Mainsnak {
+    fn value(&self) -> Option<SnakValue<'_>> {
+        match self.snaktype {
+            SnakType::Value => self.datavalue.as_ref().map(SnakValue::Value),
+            SnakType::SomeValue => Some(SnakValue::SomeValue),
+            SnakType::NoValue => Some(SnakValue::NoValue),
+            SnakType::Unknown => None,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct StringValue {
     value: String,
@@ -443,21 +740,60 @@ fn get_url_for_image<T: AsRef<str>>(image_filename: T, size: ImageSize) -> Strin
     }
 }
 
+fn get_special_file_path_url_for_image<T: AsRef<str>>(
+    image_filename: T,
+    size: ImageSize,
+) -> String {
+    let spaces_replaced = image_filename.as_ref().replace(' ', "_");
+    let encoded_filename = utf8_percent_encode(&spaces_replaced, CONTROLS);
+    let url = format!("https://commons.wikimedia.org/wiki/Special:FilePath/{encoded_filename}");
+
+    match size {
+        ImageSize::Small => format!("{url}?width={SMALL_IMAGE_WIDTH}"),
+        ImageSize::Large => url,
+    }
+}
+
 fn parse_wikidata_claims_json(value: &str) -> Result<WikidataEntityClaimsOnly, serde_json::Error> {
     serde_json::from_str(value)
 }
 
+pub(crate) fn wikidata_image_info_filename(qid: i64) -> String {
+    format!("{ROOT_CACHE_SUBDIR}/wbgetclaims-P18-Q{qid}.json")
+}
+
 pub fn load_wikidata_image_info(
     cache: &GalleryCache,
     qid: i64,
 ) -> Result<Option<WikidataImageInfo>> {
-    let filename = format!("{ROOT_CACHE_SUBDIR}/wbgetclaims-P18-Q{qid}.json");
+    let filename = wikidata_image_info_filename(qid);
     cache.cache_json_url(
         format!("https://www.wikidata.org/w/api.php?action=wbgetclaims&property=P18&entity=Q{qid}&format=json"),
         &filename,
+        false,
     )?;
+    parse_cached_wikidata_image_info(cache, qid, &filename)
+}
+
+/// Like `load_wikidata_image_info()`, but returns `None` instead of fetching the
+/// image info if it isn't already cached.
+pub fn load_cached_wikidata_image_info(
+    cache: &GalleryCache,
+    qid: i64,
+) -> Result<Option<WikidataImageInfo>> {
+    let filename = wikidata_image_info_filename(qid);
+    if cache.get_if_cached(&filename).is_none() {
+        return Ok(None);
+    }
+    parse_cached_wikidata_image_info(cache, qid, &filename)
+}
 
-    let response = parse_wikidata_claims_json(&cache.load_cached_string(&filename)?);
+fn parse_cached_wikidata_image_info(
+    cache: &GalleryCache,
+    qid: i64,
+    filename: &str,
+) -> Result<Option<WikidataImageInfo>> {
+    let response = parse_wikidata_claims_json(&cache.load_cached_string(filename)?);
     match response {
         Ok(response) => {
             let Some(image_filename) = response.claims.image_filename() else {
@@ -475,14 +811,21 @@ pub fn load_wikidata_image_info(
 #[cfg(test)]
 mod tests {
     use crate::{
+        gallery_cache::HttpStatusError,
         image::ImageSize,
         wikidata::{
-            get_url_for_image, parse_wikidata_claims_json, try_to_parse_year_from_iso_timestamp,
-            PRECISION_CENTURY, PRECISION_DECADE, PRECISION_YEAR,
+            fetch_with_strategies, get_special_file_path_url_for_image, get_url_for_image,
+            parse_wikidata_claims_json, try_to_parse_year_from_iso_timestamp,
+            wikidata_image_cache_path, ImageFetchStrategy, ANONYMOUS_CREATOR_ID,
+            DEFAULT_IMAGE_FETCH_STRATEGIES, HIGHLIGHT_SITELINK_THRESHOLD, PRECISION_CENTURY,
+            PRECISION_DECADE, PRECISION_YEAR,
         },
     };
 
-    use super::{get_supported_image_ext, try_to_parse_qid_from_wikidata_url, Time};
+    use super::{
+        get_supported_image_ext, try_to_parse_qid_from_wikidata_url, SnakValue, Time,
+        WikidataEntity, WikidataEntityClaimsOnly,
+    };
 
     #[test]
     fn test_try_to_parse_qid_from_wikidata_url_works() {
@@ -501,6 +844,18 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_wikidata_image_cache_path_works() {
+        assert_eq!(
+            wikidata_image_cache_path(3, ImageSize::Small, ".jpg"),
+            "wikidata/Q3-small-500px.jpg"
+        );
+        assert_eq!(
+            wikidata_image_cache_path(3, ImageSize::Large, ".png"),
+            "wikidata/Q3.png"
+        );
+    }
+
     #[test]
     fn test_get_url_for_image_small_works() {
         assert_eq!(
@@ -533,6 +888,92 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_special_file_path_url_for_image_works() {
+        assert_eq!(
+            get_special_file_path_url_for_image("Juan Gris - Nature morte à la nappe à carreaux.jpg", ImageSize::Small),
+            "https://commons.wikimedia.org/wiki/Special:FilePath/Juan_Gris_-_Nature_morte_%C3%A0_la_nappe_%C3%A0_carreaux.jpg?width=500"
+        );
+        assert_eq!(
+            get_special_file_path_url_for_image("Junior-Jaguar-Belize-Zoo.jpg", ImageSize::Large),
+            "https://commons.wikimedia.org/wiki/Special:FilePath/Junior-Jaguar-Belize-Zoo.jpg"
+        );
+    }
+
+    fn fetch_with_stubbed_statuses(
+        size: ImageSize,
+        strategies: &[ImageFetchStrategy],
+        statuses: &[u16],
+    ) -> (anyhow::Result<()>, Vec<String>) {
+        let mut urls: Vec<String> = vec![];
+        let result = fetch_with_strategies("Boop.jpg", size, strategies, |url| {
+            let status = statuses[urls.len()];
+            urls.push(url.to_string());
+            if status == 200 {
+                Ok(())
+            } else {
+                Err(HttpStatusError { status }.into())
+            }
+        });
+        (result, urls)
+    }
+
+    #[test]
+    fn test_fetch_falls_back_to_special_file_path_on_404() {
+        let (result, urls) = fetch_with_stubbed_statuses(
+            ImageSize::Small,
+            &DEFAULT_IMAGE_FETCH_STRATEGIES,
+            &[404, 200],
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            urls,
+            vec![
+                "https://upload.wikimedia.org/wikipedia/commons/thumb/8/8a/Boop.jpg/500px-Boop.jpg",
+                "https://commons.wikimedia.org/wiki/Special:FilePath/Boop.jpg?width=500"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fetch_does_not_fall_back_on_success_or_other_errors() {
+        for status in [200, 500] {
+            let (result, urls) = fetch_with_stubbed_statuses(
+                ImageSize::Large,
+                &DEFAULT_IMAGE_FETCH_STRATEGIES,
+                &[status],
+            );
+            assert_eq!(result.is_ok(), status == 200);
+            assert_eq!(urls.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_fetch_fails_when_all_strategies_404() {
+        let (result, urls) = fetch_with_stubbed_statuses(
+            ImageSize::Large,
+            &DEFAULT_IMAGE_FETCH_STRATEGIES,
+            &[404, 404],
+        );
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<HttpStatusError>(),
+            Some(&HttpStatusError { status: 404 })
+        );
+        assert_eq!(urls.len(), 2);
+
+        let (result, urls) = fetch_with_stubbed_statuses(
+            ImageSize::Large,
+            &[ImageFetchStrategy::SpecialFilePath],
+            &[200],
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            urls,
+            vec!["https://commons.wikimedia.org/wiki/Special:FilePath/Boop.jpg"]
+        );
+    }
+
     #[test]
     fn test_get_p18_image_works() {
         let response_json = r#"{"claims":{"P18":[{"mainsnak":{"snaktype":"value","property":"P18","hash":"9c96969b48408f6aa6d208542c338cadeff2dff9","datavalue":{"value":"Juan Gris - Nature morte \u00e0 la nappe \u00e0 carreaux.jpg","type":"string"},"datatype":"commonsMedia"},"type":"statement","id":"Q20189849$5E016A60-DF33-4157-A6F0-6E1E65411428","rank":"normal"}]}}"#;
@@ -543,6 +984,266 @@ mod tests {
         );
     }
 
+    /// Returns a P18 statement in the same shape as in wbgetclaims responses.
+    fn image_statement(image_filename: &str, rank: &str) -> String {
+        format!(
+            r#"{{"mainsnak":{{"snaktype":"value","property":"P18","datavalue":{{"value":"{image_filename}","type":"string"}},"datatype":"commonsMedia"}},"type":"statement","rank":"{rank}"}}"#
+        )
+    }
+
+    fn parse_image_claims(statements: &[String]) -> WikidataEntityClaimsOnly {
+        parse_wikidata_claims_json(&format!(
+            r#"{{"claims":{{"P18":[{}]}}}}"#,
+            statements.join(",")
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_get_p18_image_prefers_preferred_rank() {
+        let response = parse_image_claims(&[
+            image_statement("Deprecated.jpg", "deprecated"),
+            image_statement("Normal.jpg", "normal"),
+            image_statement("Preferred.jpg", "preferred"),
+        ]);
+        assert_eq!(
+            response.claims.image_filename(),
+            Some(&"Preferred.jpg".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_get_p18_image_never_uses_deprecated_rank() {
+        let response = parse_image_claims(&[
+            image_statement("Deprecated.jpg", "deprecated"),
+            image_statement("Unsupported.tiff", "normal"),
+        ]);
+        assert_eq!(response.claims.image_filename(), None);
+
+        let response = parse_image_claims(&[
+            image_statement("Deprecated.jpg", "deprecated"),
+            image_statement("Normal.jpg", "normal"),
+        ]);
+        assert_eq!(
+            response.claims.image_filename(),
+            Some(&"Normal.jpg".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_get_p18_image_without_ranks_uses_first() {
+        let response = parse_wikidata_claims_json(
+            r#"{"claims":{"P18":[{"mainsnak":{"datavalue":{"value":"First.jpg","type":"string"}}},{"mainsnak":{"datavalue":{"value":"Second.jpg","type":"string"}}}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response.claims.image_filename(),
+            Some(&"First.jpg".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_get_p18_image_skips_non_artwork_images() {
+        let response = parse_image_claims(&[
+            image_statement("Mona Lisa (Detail).jpg", "preferred"),
+            image_statement("Mona Lisa verso.jpg", "normal"),
+            image_statement("Mona Lisa.jpg", "normal"),
+        ]);
+        assert_eq!(
+            response.claims.image_filename(),
+            Some(&"Mona Lisa.jpg".to_owned())
+        );
+        assert_eq!(
+            response.claims.image_filename_with_heuristics(false),
+            Some(&"Mona Lisa (Detail).jpg".to_owned())
+        );
+
+        let response = parse_image_claims(&[image_statement("Mona Lisa frame.jpg", "normal")]);
+        assert_eq!(
+            response.claims.image_filename(),
+            Some(&"Mona Lisa frame.jpg".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_is_highlight_works() {
+        let parse = |json: &str| serde_json::from_str::<WikidataEntity>(json).unwrap();
+        let obscure = parse(r#"{"id":"Q1","claims":{},"sitelinks":{"enwiki":{"title":"Boop"}}}"#);
+        assert_eq!(obscure.sitelink_count(), 1);
+        assert!(!obscure.is_highlight());
+        assert!(!parse(r#"{"id":"Q1","claims":{}}"#).is_highlight());
+
+        let sitelinks: Vec<String> = (0..HIGHLIGHT_SITELINK_THRESHOLD)
+            .map(|i| format!(r#""wiki{i}":{{"title":"Boop"}}"#))
+            .collect();
+        let famous = parse(&format!(
+            r#"{{"id":"Q1","claims":{{}},"sitelinks":{{{}}}}}"#,
+            sitelinks.join(",")
+        ));
+        assert!(famous.is_highlight());
+
+        let iconic = parse(
+            r#"{"id":"Q1","claims":{"P1257":[{"mainsnak":{"datavalue":{"value":"25F23","type":"string"}}}]}}"#,
+        );
+        assert!(iconic.is_highlight());
+    }
+
+    /// Returns a P170 statement in the same shape as in wikidata dumps, with
+    /// the given snaktype and, if it's a value, creator.
+    fn creator_statement(snaktype: &str, qid: Option<u64>, rank: &str) -> String {
+        let datavalue = match qid {
+            Some(qid) => format!(
+                r#","datavalue":{{"value":{{"entity-type":"item","numeric-id":{qid},"id":"Q{qid}"}},"type":"wikibase-entityid"}}"#
+            ),
+            None => String::new(),
+        };
+        format!(
+            r#"{{"mainsnak":{{"snaktype":"{snaktype}","property":"P170"{datavalue},"datatype":"wikibase-item"}},"type":"statement","rank":"{rank}"}}"#
+        )
+    }
+
+    fn parse_creators(statements: &[String]) -> WikidataEntity {
+        serde_json::from_str(&format!(
+            r#"{{"id":"Q1","claims":{{"P170":[{}]}}}}"#,
+            statements.join(",")
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_creator_ids_returns_every_creator() {
+        let entity = parse_creators(&[
+            creator_statement("value", Some(5598), "normal"),
+            creator_statement("value", Some(1), "deprecated"),
+            creator_statement("value", Some(297838), "preferred"),
+            creator_statement("value", Some(5598), "normal"),
+        ]);
+        assert_eq!(entity.creator_ids(), vec![297838, 5598]);
+        assert_eq!(entity.creator_id(), Some(297838));
+    }
+
+    #[test]
+    fn test_creator_ids_maps_unknown_value_to_anonymous() {
+        let entity = parse_creators(&[creator_statement("somevalue", None, "normal")]);
+        assert_eq!(entity.creator_ids(), vec![ANONYMOUS_CREATOR_ID]);
+        assert_eq!(entity.creator_id(), None);
+
+        let entity = parse_creators(&[
+            creator_statement("somevalue", None, "normal"),
+            creator_statement("value", Some(5598), "normal"),
+        ]);
+        assert_eq!(entity.creator_ids(), vec![ANONYMOUS_CREATOR_ID, 5598]);
+        assert_eq!(entity.creator_id(), Some(5598));
+    }
+
+    #[test]
+    fn test_creator_ids_distinguishes_no_value_from_missing() {
+        let entity = parse_creators(&[creator_statement("novalue", None, "normal")]);
+        assert_eq!(entity.creator_ids(), Vec::<u64>::new());
+        assert_eq!(parse_creators(&[]).creator_ids(), Vec::<u64>::new());
+        assert!(matches!(
+            entity.claims.p170.snaks_by_rank().next(),
+            Some(SnakValue::NoValue)
+        ));
+        assert_eq!(entity.claims.p170.find(|_| Some(())), None);
+    }
+
+    /// Returns a dimension statement in the same shape as in wikidata dumps.
+    fn dimension_statement(property: &str, amount: &str, unit: &str, rank: &str) -> String {
+        format!(
+            r#"{{"mainsnak":{{"snaktype":"value","property":"{property}","datavalue":{{"value":{{"amount":"{amount}","unit":"http://www.wikidata.org/entity/{unit}"}},"type":"quantity"}},"datatype":"quantity"}},"type":"statement","rank":"{rank}"}}"#
+        )
+    }
+
+    fn dimensions_in_cm(width: &[String], height: &[String]) -> Option<(f64, f64)> {
+        let json = format!(
+            r#"{{"id":"Q1","claims":{{"P2049":[{}],"P2048":[{}]}}}}"#,
+            width.join(","),
+            height.join(",")
+        );
+        serde_json::from_str::<WikidataEntity>(&json)
+            .unwrap()
+            .dimensions_in_cm()
+    }
+
+    fn assert_dimensions_in_cm(width_unit: &str, height_unit: &str, expected: (f64, f64)) {
+        let (width, height) = dimensions_in_cm(
+            &[dimension_statement("P2049", "+3", width_unit, "normal")],
+            &[dimension_statement("P2048", "+2", height_unit, "normal")],
+        )
+        .unwrap();
+        assert!(
+            (width - expected.0).abs() < 1e-9 && (height - expected.1).abs() < 1e-9,
+            "{width_unit}/{height_unit}: expected {expected:?}, got {:?}",
+            (width, height)
+        );
+    }
+
+    #[test]
+    fn test_dimensions_in_cm_converts_units() {
+        assert_dimensions_in_cm("Q174728", "Q174728", (3.0, 2.0));
+        assert_dimensions_in_cm("Q11573", "Q11573", (300.0, 200.0));
+        assert_dimensions_in_cm("Q174789", "Q174789", (0.3, 0.2));
+        assert_dimensions_in_cm("Q218593", "Q218593", (7.62, 5.08));
+        assert_dimensions_in_cm("Q3710", "Q3710", (91.44, 60.96));
+        // Units can differ between width and height.
+        assert_dimensions_in_cm("Q11573", "Q174728", (300.0, 2.0));
+    }
+
+    #[test]
+    fn test_dimensions_in_cm_ignores_incompatible_units() {
+        // Q11570 is kilograms.
+        assert_eq!(
+            dimensions_in_cm(
+                &[dimension_statement("P2049", "+3", "Q11570", "normal")],
+                &[dimension_statement("P2048", "+2", "Q174728", "normal")],
+            ),
+            None
+        );
+        assert_eq!(
+            dimensions_in_cm(
+                &[
+                    dimension_statement("P2049", "+3", "Q11570", "normal"),
+                    dimension_statement("P2049", "+4", "Q174728", "normal")
+                ],
+                &[dimension_statement("P2048", "+2", "Q174728", "normal")],
+            ),
+            Some((4.0, 2.0))
+        );
+    }
+
+    #[test]
+    fn test_dimensions_in_cm_prefers_preferred_rank() {
+        let height = [dimension_statement("P2048", "+2", "Q174728", "normal")];
+        assert_eq!(
+            dimensions_in_cm(
+                &[
+                    dimension_statement("P2049", "+30", "Q174728", "normal"),
+                    dimension_statement("P2049", "+3", "Q174728", "preferred")
+                ],
+                &height,
+            ),
+            Some((3.0, 2.0))
+        );
+        assert_eq!(
+            dimensions_in_cm(
+                &[
+                    dimension_statement("P2049", "+30", "Q174728", "deprecated"),
+                    dimension_statement("P2049", "+3", "Q174728", "normal")
+                ],
+                &height,
+            ),
+            Some((3.0, 2.0))
+        );
+        assert_eq!(
+            dimensions_in_cm(
+                &[dimension_statement("P2049", "+30", "Q174728", "deprecated")],
+                &height,
+            ),
+            None
+        );
+    }
+
     #[test]
     fn test_try_to_parse_year_from_iso_timestamp_works() {
         assert_eq!(
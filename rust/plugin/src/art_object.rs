@@ -1,3 +1,4 @@
+use gallery::art_object::PlacedArtObject;
 use godot::prelude::*;
 
 #[derive(Debug, GodotClass)]
@@ -9,6 +10,9 @@ pub struct ArtObject {
     pub title: GString,
     #[var]
     pub artist: GString,
+    /// The wikidata QID of the artist, or 0 if unknown.
+    #[var]
+    pub artist_qid: i64,
     #[var]
     pub medium: GString,
     #[var]
@@ -23,4 +27,40 @@ pub struct ArtObject {
     pub x: f64,
     #[var]
     pub y: f64,
+    /// Whether the collection considers this one of its highlights.
+    #[var]
+    pub highlight: bool,
+    /// Whether this is hung rotated 90° clockwise, in which case `width` and
+    /// `height` are swapped on the wall.
+    #[var]
+    pub rotated: bool,
+    /// The suggested style of frame, e.g. "ornate-gold", or "default".
+    #[var]
+    pub frame_style: GString,
+    /// What kind of work the collection says this is, e.g. "Paintings", or
+    /// empty if unknown.
+    #[var]
+    pub classification: GString,
+}
+
+impl From<PlacedArtObject> for ArtObject {
+    fn from(object: PlacedArtObject) -> Self {
+        ArtObject {
+            object_id: object.object_id.to_raw_i64(),
+            title: object.title.into_godot(),
+            date: object.date.into_godot(),
+            width: object.width,
+            height: object.height,
+            x: object.x,
+            y: object.y,
+            artist: object.artist.into_godot(),
+            artist_qid: object.artist_qid.unwrap_or_default(),
+            medium: object.medium.into_godot(),
+            collection: object.collection.into_godot(),
+            highlight: object.highlight,
+            rotated: object.rotated,
+            frame_style: GString::from(object.frame_style.as_str()),
+            classification: object.classification.into_godot(),
+        }
+    }
 }
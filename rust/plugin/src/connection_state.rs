@@ -0,0 +1,118 @@
+use crate::worker_thread::MessageFromWorker;
+
+/// The state of the connection between the `GalleryClient` and its worker thread.
+///
+/// The integer values are exposed to GDScript, so they shouldn't be changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    /// `connect()` hasn't been called yet, or the worker exited cleanly.
+    #[default]
+    Disconnected = 0,
+
+    /// The worker thread has been spawned, but hasn't opened the database yet.
+    Connecting = 1,
+
+    /// The worker thread has opened the database and is processing requests.
+    Ready = 2,
+
+    /// The worker thread encountered a fatal error or hung up unexpectedly.
+    Dead = 3,
+}
+
+impl ConnectionState {
+    pub fn on_connect(self) -> Self {
+        ConnectionState::Connecting
+    }
+
+    pub fn on_message(self, message: &MessageFromWorker) -> Self {
+        match message {
+            MessageFromWorker::Ready | MessageFromWorker::Response(_) => match self {
+                ConnectionState::Connecting => ConnectionState::Ready,
+                _ => self,
+            },
+            MessageFromWorker::DbRecovered(_) | MessageFromWorker::Heartbeat => self,
+            MessageFromWorker::Done => ConnectionState::Disconnected,
+            MessageFromWorker::FatalError(_) => ConnectionState::Dead,
+        }
+    }
+
+    /// Called when the worker's end of the channel has hung up without telling us why.
+    pub fn on_worker_hung_up(self) -> Self {
+        ConnectionState::Dead
+    }
+
+    pub fn is_dead(self) -> bool {
+        self == ConnectionState::Dead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::worker_thread::{MessageFromWorker, Response, ResponseBody};
+
+    use super::ConnectionState;
+
+    fn make_response() -> MessageFromWorker {
+        MessageFromWorker::Response(Response {
+            peer_id: None,
+            request_id: 1,
+            body: ResponseBody::Empty,
+        })
+    }
+
+    #[test]
+    fn test_connecting_becomes_ready() {
+        let state = ConnectionState::default().on_connect();
+        assert_eq!(state, ConnectionState::Connecting);
+        let state = state.on_message(&MessageFromWorker::Ready);
+        assert_eq!(state, ConnectionState::Ready);
+        assert_eq!(state.on_message(&make_response()), ConnectionState::Ready);
+    }
+
+    #[test]
+    fn test_response_while_connecting_becomes_ready() {
+        let state = ConnectionState::Connecting.on_message(&make_response());
+        assert_eq!(state, ConnectionState::Ready);
+    }
+
+    #[test]
+    fn test_db_recovered_keeps_connecting() {
+        let message = MessageFromWorker::DbRecovered("DB was corrupt".into());
+        let state = ConnectionState::Connecting.on_message(&message);
+        assert_eq!(state, ConnectionState::Connecting);
+    }
+
+    #[test]
+    fn test_heartbeat_keeps_state() {
+        let state = ConnectionState::Connecting.on_message(&MessageFromWorker::Heartbeat);
+        assert_eq!(state, ConnectionState::Connecting);
+        let state = ConnectionState::Ready.on_message(&MessageFromWorker::Heartbeat);
+        assert_eq!(state, ConnectionState::Ready);
+    }
+
+    #[test]
+    fn test_fatal_error_is_dead() {
+        let message = MessageFromWorker::FatalError("DB does not exist".into());
+        assert!(ConnectionState::Connecting.on_message(&message).is_dead());
+        assert!(ConnectionState::Ready.on_message(&message).is_dead());
+    }
+
+    #[test]
+    fn test_hang_up_is_dead() {
+        assert!(ConnectionState::Ready.on_worker_hung_up().is_dead());
+    }
+
+    #[test]
+    fn test_done_is_disconnected() {
+        let state = ConnectionState::Ready.on_message(&MessageFromWorker::Done);
+        assert_eq!(state, ConnectionState::Disconnected);
+        assert!(!state.is_dead());
+    }
+
+    #[test]
+    fn test_dead_stays_dead_until_reconnect() {
+        let state = ConnectionState::Dead.on_message(&make_response());
+        assert_eq!(state, ConnectionState::Dead);
+        assert_eq!(state.on_connect(), ConnectionState::Connecting);
+    }
+}
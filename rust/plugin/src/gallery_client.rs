@@ -1,53 +1,117 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     path::PathBuf,
-    sync::mpsc::{channel, Receiver, SendError, Sender, TryRecvError},
+    sync::{
+        mpsc::{channel, Receiver, SendError, Sender, TryRecvError},
+        Arc,
+    },
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use gallery::{
-    art_object::ArtObjectId, gallery_db::get_default_gallery_db_filename, image::ImageSize,
+    art_object::ArtObjectId,
+    gallery_cache::GalleryCache,
+    gallery_db::{get_default_gallery_db_filename, ArtObjectLayoutInfo},
+    gallery_wall::{GalleryWall, GalleryWallSet},
+    image::{DecodedImage, ImageCacheOptions, ImageFormat, ImageSize},
+    layout::LayoutMode,
+    placement::{PlacementCheck, PlacementMargins},
+    shutdown::ShutdownSignal,
 };
 use godot::{
     engine::{
+        image::Format,
         multiplayer_api::RpcMode,
         multiplayer_peer::{ConnectionStatus, TransferMode},
-        FileAccess, MultiplayerPeer, OfflineMultiplayerPeer, ProjectSettings,
+        FileAccess, Image, MultiplayerPeer, OfflineMultiplayerPeer, ProjectSettings,
     },
     prelude::*,
 };
+use log::{debug, error, info, trace, warn};
 
 use crate::{
     art_object::ArtObject,
+    connection_state::ConnectionState,
     gallery_response::{GalleryResponse, InnerGalleryResponse},
+    godot_logger::{parse_log_level, set_log_level},
+    image_path_cache::ImagePathCache,
+    image_retry::ImageRetryTracker,
+    preview_server,
+    proxy::{
+        unwrap_binary_envelope, unwrap_envelope, wrap_in_binary_envelope, wrap_in_envelope,
+        ProxyCodec, ProxyError,
+    },
+    wall_contents::WallContentsCache,
     worker_thread::{
-        work_thread, MessageFromWorker, MessageToWorker, Request, RequestBody, Response,
-        ResponseBody,
+        wait_for_done, work_thread, ImportConflictPolicy, MessageFromWorker, MessageToWorker,
+        Request, RequestBody, Response, ResponseBody, DEFAULT_SHUTDOWN_GRACE_PERIOD,
     },
+    worker_watchdog::{WorkerWatchdog, DEFAULT_STALL_TIMEOUT},
 };
 
-const NULL_REQUEST_ID: u32 = 0;
+/// Returned instead of a request ID when a request couldn't be sent, e.g.
+/// because the worker isn't running. Real request IDs start at 1.
+pub const NULL_REQUEST_ID: u32 = 0;
+
+/// How long, beyond the shutdown grace period, to wait for the worker thread to
+/// finish when disconnecting, e.g. while it exports the autosync.
+const DISCONNECT_TIMEOUT_AFTER_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Everything needed to spawn a worker thread, kept around so a hung worker
+/// can be replaced by `force_reconnect()`.
+#[derive(Clone)]
+struct ConnectOptions {
+    root_dir: PathBuf,
+    slot: String,
+    enable_autosync: bool,
+    read_only: bool,
+    offline: bool,
+    /// The port to serve a preview of the layout on, if any, see `PreviewServer`.
+    preview_server_port: Option<u16>,
+    image_cache_options: ImageCacheOptions,
+}
 
 struct Connection {
     to_worker_tx: Sender<MessageToWorker>,
     from_worker_rx: Receiver<MessageFromWorker>,
     handler: JoinHandle<()>,
+    /// Tells the worker's cache to stop downloading things, see `disconnect()`.
+    shutdown: Arc<ShutdownSignal>,
 }
 
 impl Connection {
-    fn connect(root_dir: PathBuf, enable_autosync: bool) -> Self {
-        godot_print!("Root dir is {}.", root_dir.display());
+    fn connect(options: ConnectOptions) -> Self {
+        let ConnectOptions {
+            root_dir,
+            slot,
+            enable_autosync,
+            read_only,
+            offline,
+            preview_server_port,
+            image_cache_options,
+        } = options;
+        info!("Root dir is {}.", root_dir.display());
         let (to_worker_tx, to_worker_rx) = channel::<MessageToWorker>();
         let (from_worker_tx, from_worker_rx) = channel::<MessageFromWorker>();
-        godot_print!("Spawning gallery worker thread.");
+        let shutdown = Arc::new(ShutdownSignal::new());
+        let worker_shutdown = shutdown.clone();
+        debug!("Spawning gallery worker thread.");
         let handler = thread::spawn(move || {
+            let mut cache = GalleryCache::new(root_dir.clone());
+            cache.set_offline(offline);
+            cache.set_shutdown_signal(Some(worker_shutdown));
             if let Err(err) = work_thread(
-                root_dir.clone(),
+                cache,
+                slot,
                 enable_autosync,
+                read_only,
+                preview_server_port,
+                image_cache_options,
                 to_worker_rx,
                 from_worker_tx.clone(),
             ) {
-                eprintln!("Gallery worker thread errored: {err:?}");
+                error!("Gallery worker thread errored: {err:?}");
                 let _ = from_worker_tx.send(MessageFromWorker::FatalError(format!("{err:?}")));
             }
         });
@@ -55,26 +119,48 @@ impl Connection {
             to_worker_tx,
             from_worker_rx,
             handler,
+            shutdown,
         }
     }
 
-    fn disconnect(self) {
+    /// Tells the worker thread to end, giving any download it's in the middle
+    /// of `grace_period` to finish, and waits for it to export the autosync and
+    /// finish. If it takes much longer than that, we give up on it rather than
+    /// hanging.
+    fn disconnect(self, grace_period: Duration) {
+        self.shutdown.begin(grace_period);
         if let Err(err) = self.to_worker_tx.send(MessageToWorker::End) {
-            godot_print!(
+            warn!(
                 "Error sending end signal to gallery worker thread: {:?}",
                 err
             );
             return;
         }
+        let timeout = grace_period + DISCONNECT_TIMEOUT_AFTER_GRACE_PERIOD;
+        if !wait_for_done(&self.from_worker_rx, timeout) {
+            error!("Gave up waiting for gallery worker thread to finish after {timeout:?}.");
+            return;
+        }
         match self.handler.join() {
             Ok(_) => {
-                godot_print!("Joined gallery worker thread.");
+                debug!("Joined gallery worker thread.");
             }
             Err(err) => {
-                godot_print!("Error joining gallery worker thread: {:?}", err);
+                error!("Error joining gallery worker thread: {:?}", err);
             }
         }
     }
+
+    /// Like `disconnect()`, but doesn't wait for the worker thread to finish,
+    /// since it might never do so. If it ever wakes up, it'll find that we've
+    /// hung up on it, and leave the database alone, since its replacement will
+    /// be using it by then.
+    fn abandon(self) {
+        // Whatever it's downloading is probably what it's stuck on.
+        self.shutdown.abandon();
+        let _ = self.to_worker_tx.send(MessageToWorker::End);
+        warn!("Abandoning gallery worker thread without joining it.");
+    }
 }
 
 #[derive(GodotClass)]
@@ -82,10 +168,38 @@ impl Connection {
 pub struct GalleryClient {
     base: Base<Node>,
     connection: Option<Connection>,
+    connect_options: Option<ConnectOptions>,
+    watchdog: WorkerWatchdog,
+    worker_stalled: bool,
+    /// See `set_shutdown_grace_period()`.
+    shutdown_grace_period: Duration,
     queued_requests: Vec<(u32, RequestBody)>,
     queued_responses: VecDeque<(u32, ResponseBody)>,
+    image_retries: ImageRetryTracker,
+    /// Where recently fetched images are, so fetching them again doesn't need
+    /// the worker, see `fetch_image()`.
+    image_paths: ImagePathCache,
+    /// Image fetches that were responded to with how long to wait before
+    /// trying again, without bothering the worker, see `fetch_image()`.
+    queued_image_retry_responses: VecDeque<(u32, Duration)>,
+    wall_contents: WallContentsCache,
     fatal_error: Option<String>,
+    connection_state: ConnectionState,
     next_request_id: u32,
+    /// How to send proxied requests to the server, which we find out from its
+    /// responses.
+    server_codec: ProxyCodec,
+    /// How to send proxied responses to each peer, which we find out from their
+    /// requests.
+    peer_codecs: HashMap<i32, ProxyCodec>,
+}
+
+/// Returns the next request ID and advances the counter past it. This is a free
+/// function so it can be borrowed separately from the rest of the client.
+fn take_request_id(next_request_id: &mut u32) -> u32 {
+    let request_id = *next_request_id;
+    *next_request_id += 1;
+    request_id
 }
 
 fn normalize_path(path: String) -> PathBuf {
@@ -107,33 +221,54 @@ impl INode for GalleryClient {
         Self {
             base,
             connection: None,
+            connect_options: None,
+            watchdog: WorkerWatchdog::new(DEFAULT_STALL_TIMEOUT, Instant::now()),
+            worker_stalled: false,
+            shutdown_grace_period: DEFAULT_SHUTDOWN_GRACE_PERIOD,
             next_request_id: 1,
             fatal_error: None,
+            connection_state: ConnectionState::default(),
             queued_requests: vec![],
             queued_responses: VecDeque::new(),
+            image_retries: ImageRetryTracker::default(),
+            image_paths: ImagePathCache::default(),
+            queued_image_retry_responses: VecDeque::new(),
+            wall_contents: WallContentsCache::default(),
+            server_codec: ProxyCodec::default(),
+            peer_codecs: HashMap::new(),
         }
     }
 
     fn ready(&mut self) {
-        self.base_mut().rpc_config(
-            "proxy_request_to_server_internal".into(),
-            dict! {
-                "rpc_mode": RpcMode::ANY_PEER,
-                "transfer_mode": TransferMode::RELIABLE,
-                "call_local": false,
-            }
-            .to_variant(),
-        );
-        self.base_mut().rpc_config(
-            "proxy_response_from_server_internal".into(),
-            dict! {
-                "rpc_mode": RpcMode::AUTHORITY,
-                "transfer_mode": TransferMode::RELIABLE,
-                "call_local": false,
-            }
-            .to_variant(),
-        );
-        godot_print!(
+        for method in [
+            "proxy_request_to_server_internal",
+            "proxy_request_to_server_binary_internal",
+        ] {
+            self.base_mut().rpc_config(
+                method.into(),
+                dict! {
+                    "rpc_mode": RpcMode::ANY_PEER,
+                    "transfer_mode": TransferMode::RELIABLE,
+                    "call_local": false,
+                }
+                .to_variant(),
+            );
+        }
+        for method in [
+            "proxy_response_from_server_internal",
+            "proxy_response_from_server_binary_internal",
+        ] {
+            self.base_mut().rpc_config(
+                method.into(),
+                dict! {
+                    "rpc_mode": RpcMode::AUTHORITY,
+                    "transfer_mode": TransferMode::RELIABLE,
+                    "call_local": false,
+                }
+                .to_variant(),
+            );
+        }
+        info!(
             "GalleryClient ready, is_multiplayer_client={} is_multiplayer_server={} is_offline_mode={}",
             self.is_multiplayer_client(),
             self.is_multiplayer_server(),
@@ -144,20 +279,198 @@ impl INode for GalleryClient {
 
 #[godot_api]
 impl GalleryClient {
+    #[constant]
+    const CONNECTION_STATE_DISCONNECTED: i64 = ConnectionState::Disconnected as i64;
+
+    #[constant]
+    const CONNECTION_STATE_CONNECTING: i64 = ConnectionState::Connecting as i64;
+
+    #[constant]
+    const CONNECTION_STATE_READY: i64 = ConnectionState::Ready as i64;
+
+    #[constant]
+    const CONNECTION_STATE_DEAD: i64 = ConnectionState::Dead as i64;
+
+    #[constant]
+    const DEFAULT_PREVIEW_SERVER_PORT: i64 = preview_server::DEFAULT_PREVIEW_SERVER_PORT as i64;
+
+    /// Emitted when the worker thread encounters a fatal error or otherwise
+    /// exits unexpectedly.
+    #[signal]
+    fn worker_died();
+
+    /// Emitted when the database was corrupt and had to be rebuilt. The message
+    /// explains what happened, and can be shown to the player, who will need to
+    /// re-run the importer to restore art objects.
+    #[signal]
+    fn database_recovered(message: GString);
+
     #[func]
     fn default_db_filename(&mut self) -> GString {
         get_default_gallery_db_filename().into_godot()
     }
 
+    /// Connect to the gallery database in the given root dir, using the layout of
+    /// the given save slot. The art objects are shared by all slots, but each slot
+    /// has its own layout database. If `read_only` is true (e.g. for multiplayer
+    /// guests), any requests that would modify the database will respond with an
+    /// error. If `offline` is true, images are only looked up in the cache (see
+    /// `set_offline()`).
+    ///
+    /// If `enable_preview_server` is true, the layout is also served as JSON on
+    /// `http://127.0.0.1:<preview_server_port>/layout.json` (along with
+    /// `/galleries.json` and `/object/<id>.json`), e.g. so level designers can
+    /// look at it in a browser. If the port is 0, an arbitrary free one is used.
+    ///
+    /// If `image_format` isn't empty, downloaded images are re-encoded to it
+    /// (currently only "webp" is supported) to make the cache smaller, keeping
+    /// the originals around only if `keep_original_images` is true. Individual
+    /// fetches can override this, see `fetch_image_in_format()`.
+    #[func]
+    fn connect(
+        &mut self,
+        root_dir: GString,
+        slot: GString,
+        enable_autosync: bool,
+        read_only: bool,
+        offline: bool,
+        enable_preview_server: bool,
+        preview_server_port: i64,
+        image_format: GString,
+        keep_original_images: bool,
+    ) {
+        let preview_server_port = if enable_preview_server {
+            match u16::try_from(preview_server_port) {
+                Ok(port) => Some(port),
+                Err(_) => {
+                    error!("Invalid preview server port: {preview_server_port}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let options = ConnectOptions {
+            root_dir: globalize_path(root_dir),
+            slot: slot.to_string(),
+            enable_autosync,
+            read_only,
+            offline,
+            preview_server_port,
+            image_cache_options: ImageCacheOptions {
+                preferred_format: parse_image_format(&image_format.to_string()),
+                keep_original: keep_original_images,
+            },
+        };
+        self.connection = Some(Connection::connect(options.clone()));
+        self.connect_options = Some(options);
+        self.image_paths.clear();
+        self.watchdog.on_connect(Instant::now());
+        self.connection_state = self.connection_state.on_connect();
+    }
+
+    /// Sets how many seconds the worker thread can go without responding while
+    /// requests are outstanding before `is_worker_stalled()` returns true.
     #[func]
-    fn connect(&mut self, root_dir: GString, enable_autosync: bool) {
-        let globalized_root_dir = globalize_path(root_dir);
-        self.connection = Some(Connection::connect(globalized_root_dir, enable_autosync));
+    fn set_worker_stall_timeout(&mut self, seconds: f64) {
+        self.watchdog
+            .set_stall_timeout(Duration::from_secs_f64(seconds.max(0.0)));
+    }
+
+    /// Sets how many seconds downloads that are in progress when we disconnect
+    /// (e.g. because the player quit) get to finish before they're abandoned.
+    /// Defaults to 3.
+    #[func]
+    fn set_shutdown_grace_period(&mut self, seconds: f64) {
+        self.shutdown_grace_period = Duration::from_secs_f64(seconds.max(0.0));
+    }
+
+    /// Whether the worker thread seems to be hung, in which case
+    /// `force_reconnect()` can be used to replace it. Note that this is only
+    /// updated when `poll()` is called.
+    #[func]
+    fn is_worker_stalled(&self) -> bool {
+        self.worker_stalled
+    }
+
+    /// Replaces the worker thread with a fresh one, without waiting for the old
+    /// one to finish. Outstanding requests that are safe to retry are re-sent to
+    /// the new worker, while the rest are responded to with an error.
+    #[func]
+    fn force_reconnect(&mut self) {
+        let Some(options) = self.connect_options.clone() else {
+            error!("Can't reconnect, connect() was never called!");
+            return;
+        };
+        info!("Forcing gallery worker thread to reconnect.");
+        if let Some(connection) = self.connection.take() {
+            connection.abandon();
+        }
+        let unanswered = self.watchdog.take_unanswered();
+        self.connection = Some(Connection::connect(options));
+        self.watchdog.on_connect(Instant::now());
+        self.worker_stalled = false;
+        self.set_connection_state(self.connection_state.on_connect());
+        for request in unanswered.retryable {
+            self.send_to_worker(request);
+        }
+        for (peer_id, request_id) in unanswered.abandoned {
+            let body = ResponseBody::Error(
+                "The gallery worker thread stalled and had to be restarted.".to_string(),
+            );
+            match peer_id {
+                Some(peer_id) => self.send_proxied_response(peer_id as i64, request_id, &body),
+                None => self.queued_responses.push_back((request_id, body)),
+            }
+        }
+    }
+
+    /// Sets the most verbose level of messages that Rust code will print to the
+    /// Godot console: one of "off", "error", "warn", "info", "debug" or "trace".
+    /// Defaults to "info".
+    #[func]
+    fn set_log_level(&mut self, level: String) {
+        match parse_log_level(&level) {
+            Ok(level) => set_log_level(level),
+            Err(err) => error!("{err}"),
+        }
+    }
+
+    /// When offline, fetching an image that isn't already cached responds
+    /// immediately with no image, rather than trying the network.
+    #[func]
+    fn set_offline(&mut self, offline: bool) {
+        let was_offline = self.connect_options.as_ref().map(|options| options.offline);
+        if was_offline != Some(offline) {
+            // Images that were fetched while offline might have been
+            // placeholders, and images fetched while online might be gone by
+            // the time we're back.
+            self.image_paths.clear();
+        }
+        if let Some(options) = &mut self.connect_options {
+            options.offline = offline;
+        }
+        self.send(MessageToWorker::SetOffline(offline));
+    }
+
+    /// Returns one of the `CONNECTION_STATE_*` constants. Note that this is only
+    /// updated when `poll()` is called.
+    #[func]
+    fn connection_state(&self) -> i64 {
+        self.connection_state as i64
+    }
+
+    fn set_connection_state(&mut self, state: ConnectionState) {
+        let was_dead = self.connection_state.is_dead();
+        self.connection_state = state;
+        if state.is_dead() && !was_dead {
+            self.base_mut().emit_signal("worker_died".into(), &[]);
+        }
     }
 
     fn handle_send_error(&mut self, err: SendError<MessageToWorker>) {
         if self.connection.is_some() {
-            godot_error!("Sending message to gallery worker thread failed: {:?}", err);
+            error!("Sending message to gallery worker thread failed: {:?}", err);
         }
     }
 
@@ -210,46 +523,145 @@ impl GalleryClient {
         multiplayer.has_multiplayer_peer() && self.base().is_multiplayer_authority()
     }
 
+    /// Returns the ID of the peer that sent the RPC currently being handled, as
+    /// long as we're a server that can handle proxied requests.
+    fn get_proxied_request_sender(&mut self) -> Option<i32> {
+        if !self.is_multiplayer_server() {
+            error!("Non-servers cannot handle proxied requests!");
+            return None;
+        }
+        let multiplayer = &mut self.base().get_multiplayer().unwrap();
+        let remote_sender_id = multiplayer.get_remote_sender_id();
+        if remote_sender_id == 0 {
+            error!("Proxying requests must be done in an RPC context!");
+            return None;
+        }
+        Some(remote_sender_id)
+    }
+
     #[func]
     fn proxy_request_to_server_internal(
         &mut self,
         request_id: u32,
         serialized_request_body: String,
     ) {
-        if !self.is_multiplayer_server() {
-            godot_error!("Non-servers cannot handle proxied requests!");
+        let Some(remote_sender_id) = self.get_proxied_request_sender() else {
             return;
-        }
-        let multiplayer = &mut self.base().get_multiplayer().unwrap();
-        let remote_sender_id = multiplayer.get_remote_sender_id();
-        if remote_sender_id == 0 {
-            godot_error!("Proxying requests must be done in an RPC context!");
+        };
+        trace!(
+            "Received proxied request ({} bytes of JSON).",
+            serialized_request_body.len()
+        );
+        let body = match unwrap_envelope::<RequestBody>(&serialized_request_body) {
+            Ok((body, codec)) => {
+                self.peer_codecs.insert(remote_sender_id, codec);
+                Ok(body)
+            }
+            Err(err) => {
+                error!(
+                    "Unable to deserialize proxied request: {}, error={}",
+                    serialized_request_body, err
+                );
+                Err(err)
+            }
+        };
+        self.handle_proxied_request(remote_sender_id, request_id, body);
+    }
+
+    #[func]
+    fn proxy_request_to_server_binary_internal(
+        &mut self,
+        request_id: u32,
+        serialized_request_body: PackedByteArray,
+    ) {
+        let Some(remote_sender_id) = self.get_proxied_request_sender() else {
             return;
+        };
+        let bytes = serialized_request_body.to_vec();
+        trace!(
+            "Received proxied request ({} bytes of binary).",
+            bytes.len()
+        );
+        // Peers only send binary once they know we understand it, which means
+        // they understand it too.
+        self.peer_codecs
+            .insert(remote_sender_id, ProxyCodec::Binary);
+        let body = unwrap_binary_envelope::<RequestBody>(&bytes);
+        if let Err(err) = &body {
+            error!("Unable to deserialize binary proxied request: {err}");
         }
-        let body = serde_json::from_str::<RequestBody>(&serialized_request_body);
+        self.handle_proxied_request(remote_sender_id, request_id, body);
+    }
+
+    fn handle_proxied_request(
+        &mut self,
+        remote_sender_id: i32,
+        request_id: u32,
+        body: Result<RequestBody, ProxyError>,
+    ) {
         match body {
             Ok(body) => {
                 if !body.is_proxyable_to_server() {
-                    godot_error!("Proxied request is not proxyable to server: {:?}", body);
+                    error!("Proxied request is not proxyable to server: {:?}", body);
                     return;
                 }
-                //godot_print!("Received proxied request: {:?}", body);
-                self.send(MessageToWorker::Request(Request {
+                trace!("Received proxied request: {:?}", body);
+                self.send_to_worker(Request {
                     peer_id: Some(remote_sender_id),
                     request_id,
                     body,
-                }));
+                });
             }
             Err(err) => {
-                godot_error!(
-                    "Unable to deserialize proxied request: {}, error={:?}",
-                    serialized_request_body,
-                    err
+                // Let the peer know, so it doesn't wait forever for a response.
+                self.send_proxied_response(
+                    remote_sender_id as i64,
+                    request_id,
+                    &ResponseBody::Error(err.to_string()),
                 );
             }
         }
     }
 
+    fn send_proxied_response(&mut self, peer_id: i64, request_id: u32, body: &ResponseBody) {
+        let codec = self
+            .peer_codecs
+            .get(&(peer_id as i32))
+            .copied()
+            .unwrap_or_default();
+        let (method, serialized_response, len) = match codec {
+            ProxyCodec::Json => {
+                let Ok(serialized_response) = wrap_in_envelope(body) else {
+                    error!("Unable to serialize response: {:?}", body);
+                    return;
+                };
+                let len = serialized_response.len();
+                (
+                    "proxy_response_from_server_internal",
+                    serialized_response.into_godot().to_variant(),
+                    len,
+                )
+            }
+            ProxyCodec::Binary => {
+                let Ok(serialized_response) = wrap_in_binary_envelope(body) else {
+                    error!("Unable to serialize response: {:?}", body);
+                    return;
+                };
+                (
+                    "proxy_response_from_server_binary_internal",
+                    PackedByteArray::from(serialized_response.as_slice()).to_variant(),
+                    serialized_response.len(),
+                )
+            }
+        };
+        trace!("Sending proxied response to peer {peer_id} ({len} bytes, {codec:?}).");
+        self.base_mut().rpc_id(
+            peer_id, // TODO: Why do some Godot APIs think this is i32, while others think it's i64?
+            method.into(),
+            &[request_id.to_variant(), serialized_response],
+        );
+    }
+
     #[func]
     fn proxy_response_from_server_internal(
         &mut self,
@@ -257,25 +669,102 @@ impl GalleryClient {
         serialized_response_body: String,
     ) {
         if !self.is_multiplayer_client() {
-            godot_error!("Non-clients cannot handled proxied responses!");
+            error!("Non-clients cannot handled proxied responses!");
+            return;
+        }
+        trace!(
+            "Received proxied response ({} bytes of JSON).",
+            serialized_response_body.len()
+        );
+        let body = match unwrap_envelope::<ResponseBody>(&serialized_response_body) {
+            Ok((body, codec)) => {
+                self.server_codec = codec;
+                Ok(body)
+            }
+            Err(err) => {
+                error!(
+                    "Unable to deserialize proxied response body: {}, error={}",
+                    serialized_response_body, err
+                );
+                Err(err)
+            }
+        };
+        self.queue_proxied_response(request_id, body);
+    }
+
+    #[func]
+    fn proxy_response_from_server_binary_internal(
+        &mut self,
+        request_id: u32,
+        serialized_response_body: PackedByteArray,
+    ) {
+        if !self.is_multiplayer_client() {
+            error!("Non-clients cannot handled proxied responses!");
             return;
         }
-        let body = serde_json::from_str::<ResponseBody>(&serialized_response_body);
+        let bytes = serialized_response_body.to_vec();
+        trace!(
+            "Received proxied response ({} bytes of binary).",
+            bytes.len()
+        );
+        self.server_codec = ProxyCodec::Binary;
+        let body = unwrap_binary_envelope::<ResponseBody>(&bytes);
+        if let Err(err) = &body {
+            error!("Unable to deserialize binary proxied response body: {err}");
+        }
+        self.queue_proxied_response(request_id, body);
+    }
+
+    fn queue_proxied_response(&mut self, request_id: u32, body: Result<ResponseBody, ProxyError>) {
         match body {
             Ok(body) => {
-                //godot_print!("Received proxied response: {:?}", body);
+                trace!("Received proxied response: {:?}", body);
                 self.queued_responses.push_back((request_id, body));
             }
             Err(err) => {
-                godot_error!(
-                    "Unable to deserialize proxied response body: {}, error={:?}",
-                    serialized_response_body,
-                    err
-                );
+                // Respond with an error so whoever made the request isn't left hanging.
+                self.queued_responses
+                    .push_back((request_id, ResponseBody::Error(err.to_string())));
             }
         }
     }
 
+    fn send_proxied_request(&mut self, request_id: u32, body: &RequestBody) {
+        let (method, serialized_request_body, len) = match self.server_codec {
+            ProxyCodec::Json => {
+                let Ok(serialized_request_body) = wrap_in_envelope(body) else {
+                    error!("Unable to serialize request body: {:?}", body);
+                    return;
+                };
+                trace!("Proxying request to server: {}", serialized_request_body);
+                let len = serialized_request_body.len();
+                (
+                    "proxy_request_to_server_internal",
+                    serialized_request_body.into_godot().to_variant(),
+                    len,
+                )
+            }
+            ProxyCodec::Binary => {
+                let Ok(serialized_request_body) = wrap_in_binary_envelope(body) else {
+                    error!("Unable to serialize request body: {:?}", body);
+                    return;
+                };
+                trace!("Proxying request to server: {:?}", body);
+                (
+                    "proxy_request_to_server_binary_internal",
+                    PackedByteArray::from(serialized_request_body.as_slice()).to_variant(),
+                    serialized_request_body.len(),
+                )
+            }
+        };
+        trace!("Proxied request is {len} bytes ({:?}).", self.server_codec);
+        self.base_mut().rpc_id(
+            1, // Send to server only, its ID is always 1.
+            method.into(),
+            &[request_id.to_variant(), serialized_request_body],
+        );
+    }
+
     #[func]
     fn get_art_object_url(&self, art_object_id: i64) -> String {
         ArtObjectId::from_raw_i64(art_object_id).url()
@@ -289,24 +778,38 @@ impl GalleryClient {
             self.queued_requests.push((request_id, body));
             return request_id;
         }
+        let sent = self.send_to_worker(Request {
+            peer_id: None,
+            request_id,
+            body,
+        });
+        if sent {
+            request_id
+        } else {
+            NULL_REQUEST_ID
+        }
+    }
+
+    /// Sends the request to the worker thread, keeping track of it so we can tell
+    /// if the worker stalls. Returns whether it was sent.
+    fn send_to_worker(&mut self, request: Request) -> bool {
         let Some(connection) = &self.connection else {
-            return NULL_REQUEST_ID;
+            return false;
         };
+        self.watchdog.on_request_sent(&request, Instant::now());
         let result = connection
             .to_worker_tx
-            .send(MessageToWorker::Request(Request {
-                peer_id: None,
-                request_id,
-                body,
-            }));
+            .send(MessageToWorker::Request(request));
         if let Err(err) = result {
             self.handle_send_error(err);
-            NULL_REQUEST_ID
+            false
         } else {
-            request_id
+            true
         }
     }
 
+    /// Responds with a dictionary containing the `x` and `y` the art object was
+    /// actually moved to, or a `rejected` key explaining why it couldn't be moved.
     #[func]
     fn move_art_object(
         &mut self,
@@ -315,37 +818,238 @@ impl GalleryClient {
         wall_id: String,
         x: f64,
         y: f64,
-    ) {
+        strict: bool,
+    ) -> u32 {
         self.send_request(RequestBody::MoveArtObject {
             art_object_id: ArtObjectId::from_raw_i64(art_object_id),
             gallery_id,
             wall_id,
             x,
             y,
-        });
+            strict,
+        })
+    }
+
+    /// Undoes the most recent `move_art_object()`. Responds with a JSON object whose
+    /// `art_object_id` key is the art object that was moved back, and whose `restored`
+    /// key is null if it was removed from the layout, or else an object with the
+    /// `gallery_id`, `wall_id`, `x`, `y` and `rotated` it was moved back to. Responds
+    /// with JSON null if there's nothing to undo.
+    #[func]
+    fn undo_last_move(&mut self) -> u32 {
+        self.send_request(RequestBody::UndoLastMove)
     }
 
     #[func]
     fn get_art_objects_for_gallery_wall(&mut self, gallery_id: i64, wall_id: String) -> u32 {
-        self.send_request(RequestBody::GetArtObjectsForGalleryWall {
+        let request_id = self.send_request(RequestBody::GetArtObjectsForGalleryWall {
             gallery_id,
-            wall_id,
-        })
+            wall_id: wall_id.clone(),
+        });
+        if request_id != NULL_REQUEST_ID {
+            self.wall_contents
+                .on_request_sent(request_id, gallery_id, wall_id);
+        }
+        request_id
     }
 
+    /// Checks whether an art object of the given size could be hung with its
+    /// center at `x`, `y` on the given wall, without hanging past the wall's
+    /// edges (less `wall_margin`) or being closer than `spacing` to anything
+    /// else on it. This doesn't involve the worker, so it's cheap enough to
+    /// call every frame while a painting is dragged, but it only knows about
+    /// what was on the wall the last time `get_art_objects_for_gallery_wall()`
+    /// responded.
+    ///
+    /// Returns a dictionary whose `ok` key is whether the placement is fine.
+    /// If it isn't, either `off_wall` is a dictionary of how far past its
+    /// `left`, `right`, `bottom` and `top` edges the art object hangs, or
+    /// `overlaps` is a `PackedInt64Array` of the IDs of the art objects it's too close to.
+    #[func]
+    fn check_placement(
+        &self,
+        gallery_id: i64,
+        wall_id: String,
+        wall_width: f64,
+        wall_height: f64,
+        art_object_id: i64,
+        width: f64,
+        height: f64,
+        x: f64,
+        y: f64,
+        wall_margin: f64,
+        spacing: f64,
+    ) -> Dictionary {
+        let wall = GalleryWall {
+            name: wall_id,
+            width: wall_width,
+            height: wall_height,
+            index: None,
+        };
+        let candidate = ArtObjectLayoutInfo {
+            id: ArtObjectId::from_raw_i64(art_object_id),
+            width,
+            height,
+            highlight: false,
+            collection: String::new(),
+        };
+        let margins = PlacementMargins {
+            wall: wall_margin,
+            between: spacing,
+        };
+        match self
+            .wall_contents
+            .check_placement(gallery_id, &wall, &candidate, x, y, margins)
+        {
+            PlacementCheck::Ok => dict! { "ok": true },
+            PlacementCheck::OffWall {
+                left,
+                right,
+                bottom,
+                top,
+            } => dict! {
+                "ok": false,
+                "off_wall": dict! {
+                    "left": left,
+                    "right": right,
+                    "bottom": bottom,
+                    "top": top,
+                },
+            },
+            PlacementCheck::Overlaps { ids } => {
+                let ids: Vec<i64> = ids.iter().map(ArtObjectId::to_raw_i64).collect();
+                dict! { "ok": false, "overlaps": PackedInt64Array::from(ids.as_slice()) }
+            }
+        }
+    }
+
+    /// Responds with a dictionary containing the image's `path` and its `width`
+    /// and `height` (if known), or null if it couldn't be fetched.
+    ///
+    /// If fetching the image failed recently, this responds right away with a
+    /// dictionary whose `retry_after` key is how many seconds to wait before
+    /// trying again. The wait doubles with each consecutive failure, up to ten
+    /// minutes (see `clear_image_retry_state()`).
+    ///
+    /// The paths of recently fetched images are remembered, so fetching one
+    /// again doesn't need the worker (see `clear_image_path_cache()`).
     #[func]
     fn fetch_small_image(&mut self, object_id: i64) -> u32 {
-        self.send_request(RequestBody::FetchImage {
-            object_id: ArtObjectId::from_raw_i64(object_id),
-            size: ImageSize::Small,
-        })
+        self.fetch_image(object_id, ImageSize::Small, false, None)
     }
 
+    /// Like `fetch_small_image()`, but the image is decoded on the worker thread,
+    /// and the response is an `Image` (see `GalleryResponse.is_image()`). If the
+    /// image is too big or can't be decoded, it responds like `fetch_small_image()`.
+    #[func]
+    fn fetch_small_image_decoded(&mut self, object_id: i64) -> u32 {
+        self.fetch_image(object_id, ImageSize::Small, true, None)
+    }
+
+    /// Like `fetch_small_image()`, but for the large version of the image.
     #[func]
     fn fetch_large_image(&mut self, object_id: i64) -> u32 {
-        self.send_request(RequestBody::FetchImage {
-            object_id: ArtObjectId::from_raw_i64(object_id),
-            size: ImageSize::Large,
+        self.fetch_image(object_id, ImageSize::Large, false, None)
+    }
+
+    /// Like `fetch_small_image()` (or `fetch_large_image()` if `large` is true),
+    /// but the image is re-encoded to the given format when it's cached, rather
+    /// than whatever was passed to `connect()`. Currently only "webp" is
+    /// supported.
+    #[func]
+    fn fetch_image_in_format(&mut self, object_id: i64, large: bool, format: GString) -> u32 {
+        let size = if large {
+            ImageSize::Large
+        } else {
+            ImageSize::Small
+        };
+        let format = parse_image_format(&format.to_string());
+        self.fetch_image(object_id, size, false, format)
+    }
+
+    /// Forgets which images failed to fetch, so they can all be fetched again
+    /// right away, e.g. when the player turns off airplane mode.
+    #[func]
+    fn clear_image_retry_state(&mut self) {
+        self.image_retries.clear();
+    }
+
+    /// Forgets where every recently fetched image is, so they're all fetched
+    /// from the worker again, e.g. after something else has removed images
+    /// from the cache.
+    #[func]
+    fn clear_image_path_cache(&mut self) {
+        self.image_paths.clear();
+    }
+
+    /// Sets how many image paths are remembered, see `fetch_small_image()`. A
+    /// capacity of zero turns this off. Defaults to 512.
+    #[func]
+    fn set_image_path_cache_capacity(&mut self, capacity: i64) {
+        self.image_paths
+            .set_capacity(usize::try_from(capacity).unwrap_or(0));
+    }
+
+    fn fetch_image(
+        &mut self,
+        object_id: i64,
+        size: ImageSize,
+        decode: bool,
+        format: Option<ImageFormat>,
+    ) -> u32 {
+        let object_id = ArtObjectId::from_raw_i64(object_id);
+        if let Some(retry_after) = self
+            .image_retries
+            .retry_after(object_id, size, Instant::now())
+        {
+            trace!("Not fetching {size} image for {object_id:?} for another {retry_after:?}.");
+            let request_id = self.new_request_id();
+            self.queued_image_retry_responses
+                .push_back((request_id, retry_after));
+            return request_id;
+        }
+        if !decode {
+            let next_request_id = &mut self.next_request_id;
+            if let Some(request_id) = self.image_paths.queue_response(
+                object_id,
+                size,
+                format,
+                || take_request_id(next_request_id),
+                &mut self.queued_responses,
+            ) {
+                trace!("Already know where {size} image for {object_id:?} is.");
+                return request_id;
+            }
+        }
+        let request_id = self.send_request(RequestBody::FetchImage {
+            object_id,
+            size,
+            decode,
+            format,
+        });
+        if request_id != NULL_REQUEST_ID {
+            self.image_retries
+                .on_request_sent(request_id, object_id, size);
+            if !decode {
+                self.image_paths
+                    .on_request_sent(request_id, object_id, size, format);
+            }
+        }
+        request_id
+    }
+
+    /// Responds with a JSON object with `galleries`, `average_art_objects_per_gallery`
+    /// and `unplaceable` keys, describing what `layout()` would do with the same
+    /// walls, filter and density (and no other options), without changing the
+    /// layout.
+    #[func]
+    fn estimate_layout(&mut self, walls_json_path: GString, filter: String, dense: bool) -> u32 {
+        let walls_json = FileAccess::get_file_as_string(walls_json_path).to_string();
+        self.send_request(RequestBody::EstimateLayout {
+            walls_json,
+            filter: to_optional_string(filter),
+            dense,
+            mode: None,
         })
     }
 
@@ -356,13 +1060,335 @@ impl GalleryClient {
         })
     }
 
+    /// Responds with a JSON array of at most `limit` art objects matching the
+    /// filter, e.g. to show as pins on the museum map. Each has `object_id`,
+    /// `title`, `artist` and `placed` keys, along with `gallery_id`, `wall_id`
+    /// and `x` keys that are null unless it's placed. Placed art objects come
+    /// first, ordered by gallery and wall, followed by ones that aren't
+    /// currently on display.
+    #[func]
+    fn search_placed(&mut self, filter: String, limit: i64) -> u32 {
+        self.send_request(RequestBody::SearchPlaced {
+            filter: to_optional_string(filter),
+            limit: limit.max(0) as usize,
+        })
+    }
+
+    /// Responds with a JSON object with `galleries_created`, `first_gallery_id`,
+    /// `last_gallery_id` and `unplaceable` keys, the last being the number of art
+    /// objects that were too big to fit on any walls. The gallery IDs are null if
+    /// no galleries were created.
+    ///
+    /// Any art objects in `ordering` are laid out first, in that order, followed by
+    /// highlights if `featured_first` is true. Walls named in `reserved_walls` are
+    /// left empty in every gallery. If `allow_rotation` is true, art objects that
+    /// don't fit somewhere upright can be hung rotated 90° (see `ArtObject.rotated`).
+    ///
+    /// If `collect_free_regions` is true, the response also has a `free_regions`
+    /// key, an array of objects with `gallery_id`, `wall_id`, `x_start`, `x_end`
+    /// and `height_available` keys, one for each part of a wall that was left
+    /// empty, e.g. so props can be put there.
+    #[func]
+    fn layout(
+        &mut self,
+        walls_json_path: GString,
+        filter: String,
+        dense: bool,
+        ordering: PackedInt64Array,
+        reserved_walls: PackedStringArray,
+        featured_first: bool,
+        allow_rotation: bool,
+        collect_free_regions: bool,
+    ) -> u32 {
+        let walls_json = FileAccess::get_file_as_string(walls_json_path).to_string();
+        // Every wall is about to change.
+        self.wall_contents.clear();
+        self.send_request(RequestBody::Layout {
+            walls_json,
+            wall_sets_json: None,
+            filter: to_optional_string(filter),
+            dense,
+            mode: None,
+            ordering_json: to_ordering_json(ordering),
+            reserved_walls: to_string_vec(reserved_walls),
+            segments: vec![],
+            featured_first,
+            allow_rotation,
+            balance_by_collection: None,
+            collect_free_regions,
+            min_free_region_width: None,
+            exclude_previously_displayed: false,
+            gallery_id_range: None,
+        })
+    }
+
+    /// Like `layout()`, but takes the name of a layout mode instead of whether
+    /// the layout is dense: `sparse`, `dense`, `salon`, which packs walls of
+    /// small prints in a grid, or `real-gallery`, which gives each of the Met's
+    /// galleries a gallery of its own (see `get_gallery_source_gallery()`).
+    #[func]
+    fn layout_with_mode(
+        &mut self,
+        walls_json_path: GString,
+        filter: String,
+        mode: String,
+        ordering: PackedInt64Array,
+        reserved_walls: PackedStringArray,
+        featured_first: bool,
+        allow_rotation: bool,
+        collect_free_regions: bool,
+    ) -> u32 {
+        let mode = match mode.parse::<LayoutMode>() {
+            Ok(mode) => mode,
+            Err(err) => {
+                error!("{err}");
+                return NULL_REQUEST_ID;
+            }
+        };
+        let walls_json = FileAccess::get_file_as_string(walls_json_path).to_string();
+        // Every wall is about to change.
+        self.wall_contents.clear();
+        self.send_request(RequestBody::Layout {
+            walls_json,
+            wall_sets_json: None,
+            filter: to_optional_string(filter),
+            dense: mode == LayoutMode::Dense,
+            mode: Some(mode),
+            ordering_json: to_ordering_json(ordering),
+            reserved_walls: to_string_vec(reserved_walls),
+            segments: vec![],
+            featured_first,
+            allow_rotation,
+            balance_by_collection: None,
+            collect_free_regions,
+            min_free_region_width: None,
+            exclude_previously_displayed: false,
+            gallery_id_range: None,
+        })
+    }
+
+    /// Like `layout()`, but only lays out galleries `first_gallery_id` through
+    /// `last_gallery_id`, leaving the rest of the museum alone, e.g. to rotate
+    /// the art in a few galleries. If `exclude_previously_displayed` is true, only
+    /// art that has never been laid out in a positive gallery is used.
     #[func]
-    fn layout(&mut self, walls_json_path: GString, filter: String, dense: bool) -> u32 {
+    fn layout_gallery_range(
+        &mut self,
+        walls_json_path: GString,
+        filter: String,
+        dense: bool,
+        first_gallery_id: i64,
+        last_gallery_id: i64,
+        exclude_previously_displayed: bool,
+    ) -> u32 {
         let walls_json = FileAccess::get_file_as_string(walls_json_path).to_string();
+        self.wall_contents.clear();
         self.send_request(RequestBody::Layout {
             walls_json,
+            wall_sets_json: None,
             filter: to_optional_string(filter),
             dense,
+            mode: None,
+            ordering_json: None,
+            reserved_walls: vec![],
+            segments: vec![],
+            featured_first: false,
+            allow_rotation: false,
+            balance_by_collection: None,
+            collect_free_regions: false,
+            min_free_region_width: None,
+            exclude_previously_displayed,
+            gallery_id_range: Some((first_gallery_id, last_gallery_id)),
+        })
+    }
+
+    /// Like `layout()`, but takes a dictionary mapping wall set names to walls JSON
+    /// paths. Galleries will cycle through the wall sets in the dictionary's order.
+    #[func]
+    fn layout_with_wall_sets(
+        &mut self,
+        wall_sets_json_paths: Dictionary,
+        filter: String,
+        dense: bool,
+        ordering: PackedInt64Array,
+        reserved_walls: PackedStringArray,
+        featured_first: bool,
+        allow_rotation: bool,
+        collect_free_regions: bool,
+    ) -> u32 {
+        let mut wall_sets: Vec<GalleryWallSet> = vec![];
+        for (name, walls_json_path) in wall_sets_json_paths.iter_shared() {
+            let walls_json =
+                FileAccess::get_file_as_string(walls_json_path.stringify()).to_string();
+            let walls: Vec<GalleryWall> = match serde_json::from_str(&walls_json) {
+                Ok(walls) => walls,
+                Err(err) => {
+                    error!("Unable to parse walls JSON for {}: {:?}", name, err);
+                    return NULL_REQUEST_ID;
+                }
+            };
+            wall_sets.push(GalleryWallSet::new(name.stringify().to_string(), walls));
+        }
+        let Ok(wall_sets_json) = serde_json::to_string(&wall_sets) else {
+            error!("Unable to serialize wall sets!");
+            return NULL_REQUEST_ID;
+        };
+        // Every wall is about to change.
+        self.wall_contents.clear();
+        self.send_request(RequestBody::Layout {
+            walls_json: String::default(),
+            wall_sets_json: Some(wall_sets_json),
+            filter: to_optional_string(filter),
+            dense,
+            mode: None,
+            ordering_json: to_ordering_json(ordering),
+            reserved_walls: to_string_vec(reserved_walls),
+            segments: vec![],
+            featured_first,
+            allow_rotation,
+            balance_by_collection: None,
+            collect_free_regions,
+            min_free_region_width: None,
+            exclude_previously_displayed: false,
+            gallery_id_range: None,
+        })
+    }
+
+    /// Responds with the name of the wall set the given gallery uses, or an empty
+    /// string if it's unknown.
+    #[func]
+    fn get_gallery_wall_set(&mut self, gallery_id: i64) -> u32 {
+        self.send_request(RequestBody::GetGalleryWallSet { gallery_id })
+    }
+
+    /// Responds with the number or name of the real gallery that the given
+    /// gallery recreates, e.g. "825" for the Met's gallery 825, or an empty
+    /// string if it doesn't recreate one.
+    #[func]
+    fn get_gallery_source_gallery(&mut self, gallery_id: i64) -> u32 {
+        self.send_request(RequestBody::GetGallerySourceGallery { gallery_id })
+    }
+
+    /// Responds with a JSON array of the names of the walls that were intentionally
+    /// left empty in the given gallery.
+    #[func]
+    fn get_gallery_reserved_walls(&mut self, gallery_id: i64) -> u32 {
+        self.send_request(RequestBody::GetGalleryReservedWalls { gallery_id })
+    }
+
+    /// Responds with a dictionary containing the artist's `qid`, `name` and
+    /// `description`, or null if the artist is unknown.
+    #[func]
+    fn get_artist(&mut self, qid: i64) -> u32 {
+        self.send_request(RequestBody::GetArtist { qid })
+    }
+
+    /// Responds with a JSON object whose `same_artist` and `same_medium_century`
+    /// keys are arrays of at most `limit` art objects related to the given one,
+    /// e.g. to suggest on its plaque. Each art object has `object_id`, `title`,
+    /// `artist`, `artist_qid`, `date`, `medium`, `collection`, `width`, `height`
+    /// and `highlight` keys.
+    #[func]
+    fn get_related_art_objects(&mut self, object_id: i64, limit: i64) -> u32 {
+        self.send_request(RequestBody::GetRelatedArtObjects {
+            object_id: ArtObjectId::from_raw_i64(object_id),
+            limit: limit.max(0) as usize,
+        })
+    }
+
+    /// Responds with a JSON array of objects with `gallery_id` and `object_count`
+    /// keys, one for each positive gallery that has art in it, ordered by ID.
+    #[func]
+    fn get_gallery_graph(&mut self) -> u32 {
+        self.send_request(RequestBody::GetGalleryGraph)
+    }
+
+    /// Responds with the number of positive galleries that have art in them.
+    #[func]
+    fn count_galleries(&mut self) -> u32 {
+        self.send_request(RequestBody::CountGalleries)
+    }
+
+    /// Responds with a JSON array of objects with `id`, `name`, `object_count` and
+    /// `source` keys, one for each collection, ordered by name. Any of the names
+    /// can be used in a `collection:"<name>"` filter.
+    #[func]
+    fn list_collections(&mut self) -> u32 {
+        self.send_request(RequestBody::ListCollections)
+    }
+
+    /// Responds with a JSON array of objects with `value` and `count` keys, one for
+    /// each distinct non-empty value of the given column that at least `min_count`
+    /// art objects have, most common first. The column can be `artist`, `culture`,
+    /// `medium` or `collection`, and anything else responds with an error.
+    #[func]
+    fn distinct_values(&mut self, column: String, min_count: u32, limit: u32) -> u32 {
+        self.send_request(RequestBody::DistinctValues {
+            column,
+            min_count: min_count as usize,
+            limit: limit as usize,
+        })
+    }
+
+    /// Saves a filter that other filters can refer to as `@<name>`, replacing any
+    /// existing one with the same name. Responds with an error if the name is
+    /// invalid, or if the filter refers to itself or to saved filters that don't exist.
+    #[func]
+    fn save_filter(&mut self, name: String, filter: String) -> u32 {
+        self.send_request(RequestBody::SaveFilter { name, filter })
+    }
+
+    /// Responds with a JSON array of objects with `name`, `filter` and `created_at`
+    /// keys, one for each saved filter, ordered by name.
+    #[func]
+    fn list_filters(&mut self) -> u32 {
+        self.send_request(RequestBody::ListFilters)
+    }
+
+    /// Responds with 1 if the saved filter existed and was deleted, 0 otherwise.
+    #[func]
+    fn delete_filter(&mut self, name: String) -> u32 {
+        self.send_request(RequestBody::DeleteFilter { name })
+    }
+
+    /// Tags the art object with the given tag, so it can be found with a
+    /// `tag:<tag>` filter. Tags are case-insensitive, and can only contain
+    /// letters, numbers, underscores and dashes. Responds with 1 if the art
+    /// object wasn't already tagged with it, 0 otherwise, or an error if the
+    /// tag is invalid.
+    #[func]
+    fn tag_art_object(&mut self, object_id: i64, tag: String) -> u32 {
+        self.send_request(RequestBody::TagArtObject {
+            art_object_id: ArtObjectId::from_raw_i64(object_id),
+            tag,
+        })
+    }
+
+    /// Responds with 1 if the art object was tagged with the given tag and the
+    /// tag was removed, 0 otherwise.
+    #[func]
+    fn untag_art_object(&mut self, object_id: i64, tag: String) -> u32 {
+        self.send_request(RequestBody::UntagArtObject {
+            art_object_id: ArtObjectId::from_raw_i64(object_id),
+            tag,
+        })
+    }
+
+    /// Responds with a JSON array of the art object's tags, in alphabetical order.
+    #[func]
+    fn get_tags(&mut self, object_id: i64) -> u32 {
+        self.send_request(RequestBody::GetTags {
+            art_object_id: ArtObjectId::from_raw_i64(object_id),
+        })
+    }
+
+    /// Responds with a dictionary with `gallery_id`, `wall_id`, `x`, `y` and
+    /// `rotated` keys describing where the art object is hung, or null if it
+    /// isn't in the layout.
+    #[func]
+    fn get_art_object_placement(&mut self, art_object_id: i64) -> u32 {
+        self.send_request(RequestBody::GetArtObjectPlacement {
+            art_object_id: ArtObjectId::from_raw_i64(art_object_id),
         })
     }
 
@@ -371,20 +1397,92 @@ impl GalleryClient {
         self.send_request(RequestBody::Migrate)
     }
 
+    /// Migrates the DB, checks its schema, and if `relayout_if_empty` is true
+    /// and nothing's been laid out yet, lays everything out on the given walls.
+    /// Responds with a JSON object with `db_version`, `object_count`,
+    /// `gallery_count`, `migrated`, `laid_out` and `warnings` keys, the last
+    /// being a list of the steps that went wrong.
+    #[func]
+    fn ensure_ready(&mut self, walls_json_path: GString, relayout_if_empty: bool) -> u32 {
+        let walls_json = FileAccess::get_file_as_string(walls_json_path).to_string();
+        self.send_request(RequestBody::EnsureReady {
+            walls_json,
+            relayout_if_empty,
+        })
+    }
+
+    /// Responds with a JSON object with `imported`, `skipped`, `conflicted` and
+    /// `unknown_art_objects` keys. The conflict policy says what to do about art
+    /// objects that are hanging in positive galleries: `skip` them (the default if
+    /// it's empty), `overwrite` their positions, or `fail` the whole import.
+    #[func]
+    fn import_non_positive_layout(&mut self, json_content: String, conflict_policy: String) -> u32 {
+        let conflict_policy = if conflict_policy.is_empty() {
+            ImportConflictPolicy::default()
+        } else {
+            match ImportConflictPolicy::from_name(&conflict_policy) {
+                Some(conflict_policy) => conflict_policy,
+                None => {
+                    error!("Unknown conflict policy: {conflict_policy:?}");
+                    return NULL_REQUEST_ID;
+                }
+            }
+        };
+        self.send_request(RequestBody::ImportNonPositiveLayout {
+            json_content,
+            conflict_policy,
+        })
+    }
+
+    /// Merges an autosync, e.g. from another machine, into the non-positive
+    /// layout, keeping whichever of each art object's records was modified last
+    /// instead of replacing the whole layout. Responds with a JSON object with
+    /// `merged`, `kept` and `skipped` keys.
+    #[func]
+    fn merge_non_positive_layout(&mut self, json_content: String) -> u32 {
+        self.send_request(RequestBody::MergeNonPositiveLayout { json_content })
+    }
+
+    /// If `include_metadata` is true, the layout includes enough information
+    /// about its art objects to be shared with players who have different
+    /// databases.
     #[func]
-    fn import_non_positive_layout(&mut self, json_content: String) -> u32 {
-        self.send_request(RequestBody::ImportNonPositiveLayout { json_content })
+    fn export_non_positive_layout(&mut self, include_metadata: bool) -> u32 {
+        self.send_request(RequestBody::ExportNonPositiveLayout { include_metadata })
     }
 
+    /// Responds with a JSON object whose `path` key is the absolute path to the cache
+    /// directory, `total_bytes` and `total_files` keys are how much is in it, and
+    /// `breakdown` key is an array of objects with `name`, `bytes` and `files` keys,
+    /// one for each subdirectory (e.g. `met-api`), plus `db` for the databases and
+    /// `other` for any other files.
     #[func]
-    fn export_non_positive_layout(&mut self) -> u32 {
-        self.send_request(RequestBody::ExportNonPositiveLayout)
+    fn get_cache_info(&mut self) -> u32 {
+        self.send_request(RequestBody::GetCacheInfo)
+    }
+
+    /// Responds with a JSON object mapping each of the given art object IDs (as
+    /// strings) to an object whose `small` and `large` keys are whether that size
+    /// of its image is cached locally, e.g. so that paintings can be upgraded to
+    /// their large images whenever those are ready. This never touches the
+    /// network.
+    #[func]
+    fn get_cached_image_states(&mut self, ids: Array<i64>) -> u32 {
+        self.send_request(RequestBody::GetCachedImageStates {
+            object_ids: ids.iter_shared().map(ArtObjectId::from_raw_i64).collect(),
+        })
+    }
+
+    /// Responds with a dictionary containing the sizes in bytes of the database and its
+    /// write-ahead log before and after maintenance (-1 if unknown), e.g. `db_size_before`.
+    /// This is only performed once there aren't any other pending requests.
+    #[func]
+    fn maintenance(&mut self, vacuum: bool) -> u32 {
+        self.send_request(RequestBody::Maintenance { vacuum })
     }
 
     fn new_request_id(&mut self) -> u32 {
-        let request_id = self.next_request_id;
-        self.next_request_id += 1;
-        request_id
+        take_request_id(&mut self.next_request_id)
     }
 
     #[func]
@@ -394,26 +1492,26 @@ impl GalleryClient {
 
     #[func]
     fn poll(&mut self) -> Option<Gd<GalleryResponse>> {
+        if let Some((request_id, retry_after)) = self.queued_image_retry_responses.pop_front() {
+            return Some(Gd::from_object(GalleryResponse {
+                request_id,
+                response: InnerGalleryResponse::Variant(
+                    dict! { "retry_after": retry_after.as_secs_f64() }.to_variant(),
+                ),
+            }));
+        }
+
         if !self.queued_requests.is_empty() {
             if let Some(peer) = self.get_multiplayer_client() {
                 if peer.get_connection_status() == ConnectionStatus::CONNECTED {
                     let queued_requests = std::mem::take(&mut self.queued_requests);
                     for (request_id, body) in queued_requests {
-                        // TODO: Consider using postcard or something else that's more space-efficient.
-                        let Ok(serialized_request_body) = serde_json::to_string(&body) else {
-                            godot_error!("Unable to serialize request body: {:?}", body);
-                            continue;
-                        };
-                        //godot_print!("Proxying request to server: {}", serialized_request_body);
-                        self.base_mut().rpc_id(
-                            1, // Send to server only, its ID is always 1.
-                            "proxy_request_to_server_internal".into(),
-                            &[
-                                request_id.to_variant(),
-                                serialized_request_body.into_godot().to_variant(),
-                            ],
-                        );
+                        self.send_proxied_request(request_id, &body);
                     }
+                } else {
+                    // We might reconnect to a different server, which might not
+                    // understand binary.
+                    self.server_codec = ProxyCodec::Json;
                 }
             }
         }
@@ -429,27 +1527,48 @@ impl GalleryClient {
                 let Some(connection) = &self.connection else {
                     return None;
                 };
-                match connection.from_worker_rx.try_recv() {
+                let message = match connection.from_worker_rx.try_recv() {
                     Ok(message) => message,
                     Err(TryRecvError::Empty) => {
+                        let stalled = self.watchdog.is_stalled(Instant::now());
+                        if stalled && !self.worker_stalled {
+                            warn!("Gallery worker thread seems to have stalled.");
+                        }
+                        self.worker_stalled = stalled;
                         return None;
                     }
                     Err(TryRecvError::Disconnected) => {
-                        godot_print!("from_worker_rx.recv() failed, thread died!");
+                        error!("from_worker_rx.recv() failed, thread died!");
                         self.connection = None;
+                        self.set_connection_state(self.connection_state.on_worker_hung_up());
                         return None;
                     }
-                }
+                };
+                self.set_connection_state(self.connection_state.on_message(&message));
+                self.watchdog.on_message(&message, Instant::now());
+                self.worker_stalled = false;
+                message
             };
 
         match message {
+            MessageFromWorker::Ready => {
+                info!("Gallery worker thread is ready.");
+                None
+            }
+            MessageFromWorker::Heartbeat => None,
+            MessageFromWorker::DbRecovered(message) => {
+                warn!("{message}");
+                self.base_mut()
+                    .emit_signal("database_recovered".into(), &[message.to_variant()]);
+                None
+            }
             MessageFromWorker::Done => {
-                godot_print!("Gallery worker thread exited cleanly.");
+                info!("Gallery worker thread exited cleanly.");
                 self.connection = None;
                 None
             }
             MessageFromWorker::FatalError(message) => {
-                godot_error!("Gallery worker thread encountered fatal error: {message}");
+                error!("Gallery worker thread encountered fatal error: {message}");
                 self.fatal_error = Some(message);
                 self.connection = None;
                 None
@@ -457,20 +1576,13 @@ impl GalleryClient {
             MessageFromWorker::Response(response) => {
                 let request_id = response.request_id;
                 if let Some(peer_id) = response.peer_id {
-                    let Ok(serialized_response) = serde_json::to_string(&response.body) else {
-                        godot_error!("Unable to serialize response: {:?}", response);
-                        return None;
-                    };
-                    self.base_mut().rpc_id(
-                        peer_id as i64, // TODO: Why do some Godot APIs think this is i32, while others think it's i64?
-                        "proxy_response_from_server_internal".into(),
-                        &[
-                            request_id.to_variant(),
-                            serialized_response.into_godot().to_variant(),
-                        ],
-                    );
+                    self.send_proxied_response(peer_id as i64, request_id, &response.body);
                     None
                 } else {
+                    self.image_retries
+                        .on_response(request_id, &response.body, Instant::now());
+                    self.image_paths.on_response(request_id, &response.body);
+                    self.wall_contents.on_response(request_id, &response.body);
                     match response.body {
                         ResponseBody::Empty => Some(Gd::from_object(GalleryResponse {
                             request_id,
@@ -484,28 +1596,90 @@ impl GalleryClient {
                             request_id,
                             response: InnerGalleryResponse::Variant(string.to_variant()),
                         })),
+                        ResponseBody::Artist(artist) => {
+                            let variant = match artist {
+                                Some(artist) => dict! {
+                                    "qid": artist.qid,
+                                    "name": artist.name.into_godot(),
+                                    "description": artist.description.into_godot(),
+                                }
+                                .to_variant(),
+                                None => Variant::nil(),
+                            };
+                            Some(Gd::from_object(GalleryResponse {
+                                request_id,
+                                response: InnerGalleryResponse::Variant(variant),
+                            }))
+                        }
+                        ResponseBody::ArtObjectMoved { x, y } => {
+                            Some(Gd::from_object(GalleryResponse {
+                                request_id,
+                                response: InnerGalleryResponse::Variant(
+                                    dict! { "x": x, "y": y }.to_variant(),
+                                ),
+                            }))
+                        }
+                        ResponseBody::Maintenance(report) => {
+                            let size =
+                                |size: Option<u64>| size.map(|size| size as i64).unwrap_or(-1);
+                            Some(Gd::from_object(GalleryResponse {
+                                request_id,
+                                response: InnerGalleryResponse::Variant(
+                                    dict! {
+                                        "db_size_before": size(report.db_size_before),
+                                        "db_size_after": size(report.db_size_after),
+                                        "wal_size_before": size(report.wal_size_before),
+                                        "wal_size_after": size(report.wal_size_after),
+                                        "vacuumed": report.vacuumed,
+                                    }
+                                    .to_variant(),
+                                ),
+                            }))
+                        }
+                        ResponseBody::MoveRejected(reason) => {
+                            Some(Gd::from_object(GalleryResponse {
+                                request_id,
+                                response: InnerGalleryResponse::Variant(
+                                    dict! { "rejected": reason }.to_variant(),
+                                ),
+                            }))
+                        }
+                        ResponseBody::ArtObjectPlacement(record) => {
+                            let variant = match record {
+                                Some(record) => dict! {
+                                    "gallery_id": record.gallery_id,
+                                    "wall_id": record.wall_id.into_godot(),
+                                    "x": record.x,
+                                    "y": record.y,
+                                    "rotated": record.rotated,
+                                }
+                                .to_variant(),
+                                None => Variant::nil(),
+                            };
+                            Some(Gd::from_object(GalleryResponse {
+                                request_id,
+                                response: InnerGalleryResponse::Variant(variant),
+                            }))
+                        }
+                        ResponseBody::Error(message) => Some(Gd::from_object(GalleryResponse {
+                            request_id,
+                            response: InnerGalleryResponse::Error(message),
+                        })),
                         ResponseBody::ArtObjectsForGalleryWall(objects) => {
                             Some(Gd::from_object(GalleryResponse {
                                 request_id,
                                 response: InnerGalleryResponse::ArtObjects(Array::from_iter(
-                                    objects.into_iter().map(|object| {
-                                        Gd::from_object(ArtObject {
-                                            object_id: object.object_id.to_raw_i64(),
-                                            title: object.title.into_godot(),
-                                            date: object.date.into_godot(),
-                                            width: object.width,
-                                            height: object.height,
-                                            x: object.x,
-                                            y: object.y,
-                                            artist: object.artist.into_godot(),
-                                            medium: object.medium.into_godot(),
-                                            collection: object.collection.into_godot(),
-                                        })
-                                    }),
+                                    objects
+                                        .into_iter()
+                                        .map(|object| Gd::from_object(ArtObject::from(object))),
                                 )),
                             }))
                         }
-                        ResponseBody::Image(image_path) => {
+                        ResponseBody::Image {
+                            path: image_path,
+                            pixel_width,
+                            pixel_height,
+                        } => {
                             // Note that ideally we'd load this image in a separate thread, so we wouldn't
                             // potentially cause frame skips. But there are a few things in the way, at
                             // least for doing this in Rust:
@@ -540,12 +1714,16 @@ impl GalleryClient {
                             //
                             //     [1] https://docs.godotengine.org/en/stable/tutorials/performance/thread_safe_apis.html#rendering
                             //
-                            // Regardless, for now we're just going to pass the image path to Godot, and it
-                            // can do whatever it wants with it.
+                            // Regardless, unless the image was decoded on the worker thread (see below),
+                            // we're just going to pass the image path (along with its dimensions, if
+                            // known) to Godot, and it can do whatever it wants with it.
                             let variant: Variant = match image_path {
-                                Some(image_path) => {
-                                    Variant::from(image_path.to_string_lossy().into_godot())
+                                Some(image_path) => dict! {
+                                    "path": image_path.to_string_lossy().into_godot(),
+                                    "width": optional_u32_to_variant(pixel_width),
+                                    "height": optional_u32_to_variant(pixel_height),
                                 }
+                                .to_variant(),
                                 None => Variant::nil(),
                             };
                             Some(Gd::from_object(GalleryResponse {
@@ -553,6 +1731,30 @@ impl GalleryClient {
                                 response: InnerGalleryResponse::Variant(variant),
                             }))
                         }
+                        ResponseBody::DecodedImage {
+                            width,
+                            height,
+                            rgb8,
+                        } => {
+                            // The disk IO and decoding already happened on the worker thread, so all
+                            // that's left to do here is copy the pixels into an `Image`, which is cheap
+                            // in comparison.
+                            let image = DecodedImage {
+                                width,
+                                height,
+                                rgb8,
+                            };
+                            let response = match decoded_image_to_godot_image(image) {
+                                Some(image) => InnerGalleryResponse::Image(image),
+                                None => InnerGalleryResponse::Error(format!(
+                                    "Unable to create {width}x{height} image from decoded pixels"
+                                )),
+                            };
+                            Some(Gd::from_object(GalleryResponse {
+                                request_id,
+                                response,
+                            }))
+                        }
                     }
                 }
             }
@@ -563,13 +1765,13 @@ impl GalleryClient {
 impl Drop for GalleryClient {
     fn drop(&mut self) {
         if let Some(connection) = self.connection.take() {
-            connection.disconnect();
+            connection.disconnect(self.shutdown_grace_period);
         }
     }
 }
 
 /// Convert a Godot URL like `user://blah.json` to an absolute path.
-fn globalize_path(godot_url: GString) -> PathBuf {
+pub(crate) fn globalize_path(godot_url: GString) -> PathBuf {
     normalize_path(
         ProjectSettings::singleton()
             .globalize_path(godot_url)
@@ -577,10 +1779,67 @@ fn globalize_path(godot_url: GString) -> PathBuf {
     )
 }
 
-fn to_optional_string(value: String) -> Option<String> {
+pub(crate) fn to_optional_string(value: String) -> Option<String> {
     if value.len() > 0 {
         Some(value)
     } else {
         None
     }
 }
+
+/// Godot uses raw IDs for art objects, so convert them to the JSON-serialized
+/// list of art object IDs that layout requests expect, or `None` if empty.
+fn to_ordering_json(ordering: PackedInt64Array) -> Option<String> {
+    if ordering.is_empty() {
+        return None;
+    }
+    let ids: Vec<ArtObjectId> = ordering
+        .as_slice()
+        .iter()
+        .map(|&id| ArtObjectId::from_raw_i64(id))
+        .collect();
+    Some(serde_json::to_string(&ids).expect("art object IDs should be serializable"))
+}
+
+fn to_string_vec(strings: PackedStringArray) -> Vec<String> {
+    strings
+        .as_slice()
+        .iter()
+        .map(|string| string.to_string())
+        .collect()
+}
+
+/// An empty string means no preference. Anything we don't support is logged
+/// and treated the same way, since the original image is still usable.
+fn parse_image_format(format: &str) -> Option<ImageFormat> {
+    if format.is_empty() {
+        return None;
+    }
+    match format.parse() {
+        Ok(format) => Some(format),
+        Err(err) => {
+            error!("{err}");
+            None
+        }
+    }
+}
+
+fn decoded_image_to_godot_image(image: DecodedImage) -> Option<Gd<Image>> {
+    if !image.is_valid() {
+        return None;
+    }
+    Image::create_from_data(
+        image.width as i32,
+        image.height as i32,
+        false,
+        Format::RGB8,
+        PackedByteArray::from(image.rgb8.as_slice()),
+    )
+}
+
+fn optional_u32_to_variant(value: Option<u32>) -> Variant {
+    match value {
+        Some(value) => (value as i64).to_variant(),
+        None => Variant::nil(),
+    }
+}
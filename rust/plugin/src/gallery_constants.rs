@@ -0,0 +1,154 @@
+use gallery::image::ImageSize;
+use godot::prelude::*;
+
+use crate::{
+    connection_state::ConnectionState, gallery_client::NULL_REQUEST_ID,
+    gallery_response::ResponseKind,
+};
+
+/// Values that GDScript needs to agree with Rust on, so it doesn't have to
+/// hard-code them, e.g. `GalleryConstants.NULL_REQUEST_ID`.
+///
+/// Everything here is derived from the Rust values it mirrors, rather than
+/// being a copy of them.
+#[derive(GodotClass)]
+#[class(init, base=RefCounted)]
+pub struct GalleryConstants {}
+
+#[godot_api]
+impl GalleryConstants {
+    /// What `GalleryClient` returns instead of a request ID when a request
+    /// couldn't be sent.
+    #[constant]
+    const NULL_REQUEST_ID: i64 = NULL_REQUEST_ID as i64;
+
+    /// What `GalleryClient.connection_state()` returns.
+    #[constant]
+    const CONNECTION_STATE_DISCONNECTED: i64 = ConnectionState::Disconnected as i64;
+
+    #[constant]
+    const CONNECTION_STATE_CONNECTING: i64 = ConnectionState::Connecting as i64;
+
+    #[constant]
+    const CONNECTION_STATE_READY: i64 = ConnectionState::Ready as i64;
+
+    #[constant]
+    const CONNECTION_STATE_DEAD: i64 = ConnectionState::Dead as i64;
+
+    /// What `GalleryResponse.response_kind()` returns.
+    #[constant]
+    const RESPONSE_KIND_VARIANT: i64 = ResponseKind::Variant as i64;
+
+    #[constant]
+    const RESPONSE_KIND_ART_OBJECTS: i64 = ResponseKind::ArtObjects as i64;
+
+    #[constant]
+    const RESPONSE_KIND_IMAGE: i64 = ResponseKind::Image as i64;
+
+    #[constant]
+    const RESPONSE_KIND_ERROR: i64 = ResponseKind::Error as i64;
+
+    /// The name of the small image size, e.g. as it appears in cached image
+    /// filenames. Image sizes are names rather than numbers, so they can't be
+    /// constants.
+    #[func]
+    fn image_size_small() -> GString {
+        ImageSize::Small.to_string().into_godot()
+    }
+
+    /// See `image_size_small()`.
+    #[func]
+    fn image_size_large() -> GString {
+        ImageSize::Large.to_string().into_godot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gallery::image::ImageSize;
+
+    use crate::{
+        connection_state::ConnectionState,
+        gallery_client::NULL_REQUEST_ID,
+        gallery_response::{InnerGalleryResponse, ResponseKind},
+    };
+
+    use super::GalleryConstants;
+
+    // These matches are exhaustive so that adding a variant without a
+    // constant for it fails to compile.
+
+    fn connection_state_constant(state: ConnectionState) -> i64 {
+        match state {
+            ConnectionState::Disconnected => GalleryConstants::CONNECTION_STATE_DISCONNECTED,
+            ConnectionState::Connecting => GalleryConstants::CONNECTION_STATE_CONNECTING,
+            ConnectionState::Ready => GalleryConstants::CONNECTION_STATE_READY,
+            ConnectionState::Dead => GalleryConstants::CONNECTION_STATE_DEAD,
+        }
+    }
+
+    fn response_kind_constant(kind: ResponseKind) -> i64 {
+        match kind {
+            ResponseKind::Variant => GalleryConstants::RESPONSE_KIND_VARIANT,
+            ResponseKind::ArtObjects => GalleryConstants::RESPONSE_KIND_ART_OBJECTS,
+            ResponseKind::Image => GalleryConstants::RESPONSE_KIND_IMAGE,
+            ResponseKind::Error => GalleryConstants::RESPONSE_KIND_ERROR,
+        }
+    }
+
+    #[test]
+    fn test_null_request_id_matches() {
+        assert_eq!(GalleryConstants::NULL_REQUEST_ID, NULL_REQUEST_ID as i64);
+    }
+
+    #[test]
+    fn test_connection_states_match() {
+        let states = [
+            ConnectionState::Disconnected,
+            ConnectionState::Connecting,
+            ConnectionState::Ready,
+            ConnectionState::Dead,
+        ];
+        let constants: Vec<i64> = states
+            .iter()
+            .map(|&state| connection_state_constant(state))
+            .collect();
+        assert_eq!(constants, vec![0, 1, 2, 3]);
+        for state in states {
+            assert_eq!(connection_state_constant(state), state as i64);
+        }
+        assert_eq!(
+            GalleryConstants::CONNECTION_STATE_DISCONNECTED,
+            ConnectionState::default() as i64
+        );
+    }
+
+    #[test]
+    fn test_response_kinds_match() {
+        let kinds = [
+            ResponseKind::Variant,
+            ResponseKind::ArtObjects,
+            ResponseKind::Image,
+            ResponseKind::Error,
+        ];
+        let constants: Vec<i64> = kinds
+            .iter()
+            .map(|&kind| response_kind_constant(kind))
+            .collect();
+        assert_eq!(constants, vec![0, 1, 2, 3]);
+        for kind in kinds {
+            assert_eq!(response_kind_constant(kind), kind as i64);
+        }
+        let error = InnerGalleryResponse::Error("Oof".into());
+        assert_eq!(
+            response_kind_constant(error.kind()),
+            GalleryConstants::RESPONSE_KIND_ERROR
+        );
+    }
+
+    #[test]
+    fn test_image_size_names_match() {
+        assert_eq!(ImageSize::Small.to_string(), "small");
+        assert_eq!(ImageSize::Large.to_string(), "large");
+    }
+}
@@ -0,0 +1,246 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use gallery::{
+    art_object::{ArtObjectId, PlacedArtObject},
+    gallery_cache::GalleryCache,
+    gallery_db::{get_default_gallery_db_filename, ArtObjectQueryOptions, GalleryDb},
+};
+use godot::prelude::*;
+use log::error;
+
+use crate::{
+    art_object::ArtObject,
+    gallery_client::{globalize_path, to_optional_string},
+};
+
+/// The maximum number of art objects `search()` will return at once.
+///
+/// Unlike `GalleryClient`, all queries here block the calling thread, so this
+/// class is meant for editor tooling, and should never be used on the game's
+/// main loop. Even in the editor, though, we don't want to freeze things by
+/// loading the entire collection at once, so callers need to page through results.
+const MAX_SEARCH_LIMIT: i64 = 200;
+
+/// Synchronous, read-only access to the gallery database that doesn't require
+/// a worker thread, e.g. for use in `@tool` scripts.
+///
+/// The database is opened lazily on the first query, and stays open until
+/// `close()` is called or this object is freed.
+#[derive(GodotClass)]
+#[class(init, base=RefCounted)]
+pub struct GalleryDbDirect {
+    root_dir: Option<PathBuf>,
+    db: Option<GalleryDb>,
+}
+
+#[godot_api]
+impl GalleryDbDirect {
+    #[constant]
+    const MAX_SEARCH_LIMIT: i64 = MAX_SEARCH_LIMIT;
+
+    /// Set the root directory containing the gallery database. This closes
+    /// any previously opened database.
+    #[func]
+    fn open(&mut self, root_dir: GString) {
+        self.root_dir = Some(globalize_path(root_dir));
+        self.db = None;
+    }
+
+    #[func]
+    fn close(&mut self) {
+        self.db = None;
+    }
+
+    fn db(&mut self) -> Result<&GalleryDb> {
+        if self.db.is_none() {
+            let Some(root_dir) = &self.root_dir else {
+                return Err(anyhow!("open() must be called first"));
+            };
+            let db_path = GalleryCache::new(root_dir.clone())
+                .get_cached_path(get_default_gallery_db_filename());
+            // Check for existence, we don't want SQLite making a zero-byte DB file.
+            if !db_path.exists() {
+                return Err(anyhow!("DB does not exist: {}", db_path.display()));
+            }
+            self.db = Some(GalleryDb::open(db_path, true)?);
+        }
+        Ok(self.db.as_ref().unwrap())
+    }
+
+    /// Returns the number of art objects matching the filter, or -1 on error.
+    #[func]
+    fn count(&mut self, filter: String) -> i64 {
+        let result = self.db().and_then(|db| {
+            Ok(db.count_art_objects(&ArtObjectQueryOptions {
+                filter: to_optional_string(filter),
+                ..Default::default()
+            })?)
+        });
+        match result {
+            Ok(count) => count as i64,
+            Err(err) => {
+                error!("Unable to count art objects: {err:?}");
+                -1
+            }
+        }
+    }
+
+    #[func]
+    fn get_art_object(&mut self, id: i64) -> Option<Gd<ArtObject>> {
+        match self.db().and_then(|db| get_record(db, id)) {
+            Ok(record) => record.map(|record| Gd::from_object(ArtObject::from(record))),
+            Err(err) => {
+                error!("Unable to get art object {id}: {err:?}");
+                None
+            }
+        }
+    }
+
+    /// Returns at most `MAX_SEARCH_LIMIT` art objects matching the filter,
+    /// ordered by ID.
+    #[func]
+    fn search(&mut self, filter: String, offset: i64, limit: i64) -> Array<Gd<ArtObject>> {
+        match self
+            .db()
+            .and_then(|db| search_records(db, filter, offset, limit))
+        {
+            Ok(records) => Array::from_iter(
+                records
+                    .into_iter()
+                    .map(|record| Gd::from_object(ArtObject::from(record))),
+            ),
+            Err(err) => {
+                error!("Unable to search art objects: {err:?}");
+                Array::new()
+            }
+        }
+    }
+}
+
+fn clamp_limit(limit: i64) -> usize {
+    limit.clamp(0, MAX_SEARCH_LIMIT) as usize
+}
+
+// Art objects retrieved here aren't necessarily on a wall, so their position is
+// always zero.
+
+fn get_record(db: &GalleryDb, id: i64) -> Result<Option<PlacedArtObject>> {
+    Ok(db
+        .get_art_object(ArtObjectId::from_raw_i64(id))?
+        .map(|object| PlacedArtObject::from((object, (0.0, 0.0), false))))
+}
+
+fn search_records(
+    db: &GalleryDb,
+    filter: String,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<PlacedArtObject>> {
+    let options = ArtObjectQueryOptions {
+        filter: to_optional_string(filter),
+        ..Default::default()
+    };
+    let objects = db.get_art_objects(&options, offset.max(0) as usize, clamp_limit(limit))?;
+    Ok(objects
+        .into_iter()
+        .map(|object| PlacedArtObject::from((object, (0.0, 0.0), false)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use gallery::{
+        art_object::ArtObjectId,
+        gallery_db::{ArtObjectRecord, GalleryDb},
+        medium::MediumCategory,
+    };
+    use rusqlite::Connection;
+
+    use super::{clamp_limit, get_record, search_records, MAX_SEARCH_LIMIT};
+
+    fn make_record(object_id: ArtObjectId, title: &str) -> ArtObjectRecord {
+        ArtObjectRecord {
+            object_id,
+            object_date: "1864".into(),
+            culture: "Martian".into(),
+            artist: "Boop Jones".into(),
+            artist_role: String::default(),
+            title: title.into(),
+            medium: "Oil on canvas".into(),
+            medium_category: MediumCategory::Oil,
+            width: 0.6,
+            height: 0.3,
+            fallback_wikidata_qid: None,
+            filename: "".into(),
+            collection: "Martian Museum of Art".into(),
+            artist_qid: Some(42),
+            highlight: false,
+            tags: String::default(),
+            classification: String::default(),
+            source_gallery: None,
+        }
+    }
+
+    fn create_db() -> GalleryDb {
+        let mut db = GalleryDb::new(Connection::open_in_memory().unwrap());
+        db.reset_art_objects_table().unwrap();
+        db.add_art_objects(&vec![
+            make_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_record(ArtObjectId::Met(2), "Boring Painting"),
+            make_record(ArtObjectId::Wikidata(3), "Funky Monkey"),
+        ])
+        .unwrap();
+        db
+    }
+
+    #[test]
+    fn test_get_record_maps_fields() {
+        let db = create_db();
+        let record = get_record(&db, ArtObjectId::Met(1).to_raw_i64())
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.object_id, ArtObjectId::Met(1));
+        assert_eq!(record.title, "Funky Painting");
+        assert_eq!(record.date, "1864");
+        assert_eq!(record.artist, "Boop Jones");
+        assert_eq!(record.artist_qid, Some(42));
+        assert_eq!(record.medium, "Oil on canvas");
+        assert_eq!(record.collection, "Martian Museum of Art");
+        assert_eq!((record.width, record.height), (0.6, 0.3));
+        assert_eq!((record.x, record.y), (0.0, 0.0));
+
+        assert!(get_record(&db, 12345).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_search_records_works() {
+        let db = create_db();
+        let titles = |filter: &str, offset, limit| -> Vec<String> {
+            search_records(&db, filter.into(), offset, limit)
+                .unwrap()
+                .into_iter()
+                .map(|record| record.title)
+                .collect()
+        };
+        assert_eq!(
+            titles("", 0, 10),
+            vec!["Funky Painting", "Boring Painting", "Funky Monkey"]
+        );
+        assert_eq!(
+            titles("funky", 0, 10),
+            vec!["Funky Painting", "Funky Monkey"]
+        );
+        assert_eq!(titles("funky", 1, 10), vec!["Funky Monkey"]);
+        assert_eq!(titles("", 0, 1), vec!["Funky Painting"]);
+        assert_eq!(titles("", -5, 1), vec!["Funky Painting"]);
+        assert_eq!(titles("", 0, -1), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_clamp_limit_works() {
+        assert_eq!(clamp_limit(-1), 0);
+        assert_eq!(clamp_limit(10), 10);
+        assert_eq!(clamp_limit(MAX_SEARCH_LIMIT + 1), MAX_SEARCH_LIMIT as usize);
+    }
+}
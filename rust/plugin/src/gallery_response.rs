@@ -1,4 +1,5 @@
-use godot::prelude::*;
+use godot::{engine::Image, prelude::*};
+use log::error;
 
 use crate::art_object::ArtObject;
 
@@ -6,6 +7,8 @@ use crate::art_object::ArtObject;
 pub enum InnerGalleryResponse {
     Variant(Variant),
     ArtObjects(Array<Gd<ArtObject>>),
+    Image(Gd<Image>),
+    Error(String),
 }
 
 impl Default for InnerGalleryResponse {
@@ -14,6 +17,28 @@ impl Default for InnerGalleryResponse {
     }
 }
 
+impl InnerGalleryResponse {
+    pub fn kind(&self) -> ResponseKind {
+        match self {
+            InnerGalleryResponse::Variant(_) => ResponseKind::Variant,
+            InnerGalleryResponse::ArtObjects(_) => ResponseKind::ArtObjects,
+            InnerGalleryResponse::Image(_) => ResponseKind::Image,
+            InnerGalleryResponse::Error(_) => ResponseKind::Error,
+        }
+    }
+}
+
+/// What a `GalleryResponse` holds, i.e. which of its `take_*()` methods to call.
+///
+/// The integer values are exposed to GDScript, so they shouldn't be changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseKind {
+    Variant = 0,
+    ArtObjects = 1,
+    Image = 2,
+    Error = 3,
+}
+
 #[derive(Debug, GodotClass)]
 #[class(init)]
 pub struct GalleryResponse {
@@ -24,23 +49,61 @@ pub struct GalleryResponse {
 
 #[godot_api]
 impl GalleryResponse {
+    /// Returns one of the `GalleryConstants.RESPONSE_KIND_*` constants.
+    #[func]
+    fn response_kind(&self) -> i64 {
+        self.response.kind() as i64
+    }
+
     #[func]
     fn take_art_objects(&mut self) -> Array<Gd<ArtObject>> {
         match std::mem::take(&mut self.response) {
             InnerGalleryResponse::ArtObjects(response) => response,
             _ => {
-                godot_error!("GalleryResponse is not ArtObjects!");
+                error!("GalleryResponse is not ArtObjects!");
                 Array::new()
             }
         }
     }
 
+    #[func]
+    fn is_image(&self) -> bool {
+        matches!(self.response, InnerGalleryResponse::Image(_))
+    }
+
+    #[func]
+    fn take_image(&mut self) -> Option<Gd<Image>> {
+        match std::mem::take(&mut self.response) {
+            InnerGalleryResponse::Image(image) => Some(image),
+            _ => {
+                error!("GalleryResponse is not Image!");
+                None
+            }
+        }
+    }
+
+    #[func]
+    fn is_error(&self) -> bool {
+        matches!(self.response, InnerGalleryResponse::Error(_))
+    }
+
+    #[func]
+    fn take_error(&mut self) -> GString {
+        match std::mem::take(&mut self.response) {
+            InnerGalleryResponse::Error(message) => message.into_godot(),
+            _ => {
+                error!("GalleryResponse is not Error!");
+                GString::new()
+            }
+        }
+    }
+
     #[func]
     fn take_variant(&mut self) -> Variant {
         match std::mem::take(&mut self.response) {
             InnerGalleryResponse::Variant(variant) => variant,
             _ => {
-                godot_error!("GalleryResponse is not Variant!");
+                error!("GalleryResponse is not Variant!");
                 Variant::nil()
             }
         }
@@ -0,0 +1,171 @@
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{anyhow, Result};
+use godot::prelude::*;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// The threshold used until `set_log_level()` is called.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// Where `ThresholdLogger` sends the messages that pass its threshold.
+pub trait LogBackend: Send + Sync {
+    fn write(&self, level: Level, message: String);
+}
+
+/// Forwards messages to the Godot console, so that warnings and errors show up
+/// in the editor's debugger too.
+pub struct GodotBackend;
+
+impl LogBackend for GodotBackend {
+    fn write(&self, level: Level, message: String) {
+        match level {
+            Level::Error => godot_error!("{message}"),
+            Level::Warn => godot_warn!("{message}"),
+            Level::Info => godot_print!("{message}"),
+            Level::Debug | Level::Trace => godot_print!("[{level}] {message}"),
+        }
+    }
+}
+
+/// A logger that drops any messages more verbose than its current level. The
+/// level can be changed at any time, from any thread.
+pub struct ThresholdLogger<B: LogBackend> {
+    backend: B,
+    level: AtomicUsize,
+}
+
+impl<B: LogBackend> ThresholdLogger<B> {
+    pub const fn new(backend: B, level: LevelFilter) -> Self {
+        ThresholdLogger {
+            backend,
+            level: AtomicUsize::new(level as usize),
+        }
+    }
+
+    pub fn level(&self) -> LevelFilter {
+        match self.level.load(Ordering::Relaxed) {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    pub fn set_level(&self, level: LevelFilter) {
+        self.level.store(level as usize, Ordering::Relaxed);
+    }
+}
+
+impl<B: LogBackend> Log for ThresholdLogger<B> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.backend
+                .write(record.level(), record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: ThresholdLogger<GodotBackend> = ThresholdLogger::new(GodotBackend, DEFAULT_LEVEL);
+
+/// Parses a level name like "warn" or "DEBUG". "off" disables logging entirely.
+pub fn parse_log_level(level: &str) -> Result<LevelFilter> {
+    LevelFilter::from_str(level.trim()).map_err(|_| {
+        anyhow!("Invalid log level {level:?}, expected one of off, error, warn, info, debug, trace")
+    })
+}
+
+/// Routes the `log` facade to the Godot console. Calling this more than once
+/// is harmless.
+pub fn init_godot_logger() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LOGGER.level());
+    }
+}
+
+pub fn set_log_level(level: LevelFilter) {
+    LOGGER.set_level(level);
+    log::set_max_level(level);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use log::{Level, LevelFilter, Log, Record};
+
+    use super::{parse_log_level, LogBackend, ThresholdLogger};
+
+    #[derive(Default)]
+    struct CapturingBackend {
+        messages: Mutex<Vec<(Level, String)>>,
+    }
+
+    impl LogBackend for CapturingBackend {
+        fn write(&self, level: Level, message: String) {
+            self.messages.lock().unwrap().push((level, message));
+        }
+    }
+
+    fn log_every_level(logger: &ThresholdLogger<CapturingBackend>) -> Vec<(Level, String)> {
+        for level in [
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("hi"))
+                    .build(),
+            );
+        }
+        std::mem::take(&mut *logger.backend.messages.lock().unwrap())
+    }
+
+    fn levels(messages: Vec<(Level, String)>) -> Vec<Level> {
+        messages.into_iter().map(|(level, _)| level).collect()
+    }
+
+    #[test]
+    fn test_threshold_logger_filters_by_level() {
+        let logger = ThresholdLogger::new(CapturingBackend::default(), LevelFilter::Warn);
+        assert_eq!(
+            log_every_level(&logger),
+            vec![(Level::Error, "hi".into()), (Level::Warn, "hi".into())]
+        );
+
+        logger.set_level(LevelFilter::Debug);
+        assert_eq!(logger.level(), LevelFilter::Debug);
+        assert_eq!(
+            levels(log_every_level(&logger)),
+            vec![Level::Error, Level::Warn, Level::Info, Level::Debug]
+        );
+
+        logger.set_level(LevelFilter::Trace);
+        assert_eq!(levels(log_every_level(&logger)).len(), 5);
+
+        logger.set_level(LevelFilter::Off);
+        assert_eq!(log_every_level(&logger), vec![]);
+    }
+
+    #[test]
+    fn test_parse_log_level_works() {
+        assert_eq!(parse_log_level("warn").unwrap(), LevelFilter::Warn);
+        assert_eq!(parse_log_level(" DEBUG ").unwrap(), LevelFilter::Debug);
+        assert_eq!(parse_log_level("off").unwrap(), LevelFilter::Off);
+        assert!(parse_log_level("loud").is_err());
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+};
+
+use gallery::{
+    art_object::ArtObjectId,
+    image::{ImageFormat, ImageSize},
+};
+
+use crate::worker_thread::ResponseBody;
+
+/// How many image paths `ImagePathCache` remembers, unless told otherwise.
+pub const DEFAULT_IMAGE_PATH_CACHE_CAPACITY: usize = 512;
+
+/// The format is part of the key, since images fetched in a different format
+/// are cached under a different path.
+type ImageKey = (ArtObjectId, ImageSize, Option<ImageFormat>);
+
+struct CachedImagePath {
+    path: PathBuf,
+    pixel_width: Option<u32>,
+    pixel_height: Option<u32>,
+    last_used: u64,
+}
+
+/// Remembers where the most recently used images were cached, so that fetching
+/// the same image again, e.g. whenever a wall comes back into view, can be
+/// responded to without a round trip to the worker.
+///
+/// Note that this can't tell if the worker has since removed an image from its
+/// cache, so anything that does that should `clear()` this.
+pub struct ImagePathCache {
+    capacity: usize,
+    entries: HashMap<ImageKey, CachedImagePath>,
+    /// Image fetches the worker hasn't responded to yet, keyed by request ID.
+    pending: HashMap<u32, ImageKey>,
+    /// Incremented whenever an entry is used, so we know which one was used
+    /// least recently.
+    clock: u64,
+}
+
+impl Default for ImagePathCache {
+    fn default() -> Self {
+        ImagePathCache::new(DEFAULT_IMAGE_PATH_CACHE_CAPACITY)
+    }
+}
+
+impl ImagePathCache {
+    pub fn new(capacity: usize) -> Self {
+        ImagePathCache {
+            capacity,
+            entries: HashMap::new(),
+            pending: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Forgets the least recently used entries if there are now too many. A
+    /// capacity of zero disables the cache.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    /// If we know where the image is, returns what the worker would respond with
+    /// if it were fetched.
+    pub fn get(
+        &mut self,
+        object_id: ArtObjectId,
+        size: ImageSize,
+        format: Option<ImageFormat>,
+    ) -> Option<ResponseBody> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(&(object_id, size, format))?;
+        entry.last_used = self.clock;
+        Some(ResponseBody::Image {
+            path: Some(entry.path.clone()),
+            pixel_width: entry.pixel_width,
+            pixel_height: entry.pixel_height,
+        })
+    }
+
+    /// If we know where the image is, queues what the worker would respond with
+    /// under a new request ID from `new_request_id`, and returns that ID.
+    ///
+    /// The response goes behind any that are already queued, so they're still
+    /// polled in order. Like any other response, it shouldn't be passed to
+    /// `on_response()` until it's polled, so that e.g. a failed fetch queued
+    /// ahead of it still invalidates the image.
+    pub fn queue_response(
+        &mut self,
+        object_id: ArtObjectId,
+        size: ImageSize,
+        format: Option<ImageFormat>,
+        new_request_id: impl FnOnce() -> u32,
+        queued_responses: &mut VecDeque<(u32, ResponseBody)>,
+    ) -> Option<u32> {
+        let body = self.get(object_id, size, format)?;
+        let request_id = new_request_id();
+        queued_responses.push_back((request_id, body));
+        Some(request_id)
+    }
+
+    pub fn on_request_sent(
+        &mut self,
+        request_id: u32,
+        object_id: ArtObjectId,
+        size: ImageSize,
+        format: Option<ImageFormat>,
+    ) {
+        self.pending.insert(request_id, (object_id, size, format));
+    }
+
+    /// Remembers where the image is if it was fetched. If it wasn't, every size
+    /// of the art object's image is forgotten, since whatever we remember about
+    /// them probably isn't true anymore either.
+    pub fn on_response(&mut self, request_id: u32, body: &ResponseBody) {
+        let Some(key) = self.pending.remove(&request_id) else {
+            return;
+        };
+        match body {
+            ResponseBody::Image {
+                path: Some(path),
+                pixel_width,
+                pixel_height,
+            } => {
+                if self.capacity == 0 {
+                    return;
+                }
+                self.clock += 1;
+                self.entries.insert(
+                    key,
+                    CachedImagePath {
+                        path: path.clone(),
+                        pixel_width: *pixel_width,
+                        pixel_height: *pixel_height,
+                        last_used: self.clock,
+                    },
+                );
+                self.evict();
+            }
+            ResponseBody::Image { path: None, .. } | ResponseBody::Error(_) => {
+                let (object_id, _, _) = key;
+                self.entries.retain(|(id, _, _), _| *id != object_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets where every image is. Fetches that are still in progress won't
+    /// be remembered either.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.pending.clear();
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let Some(key) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key)
+            else {
+                return;
+            };
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, path::PathBuf};
+
+    use gallery::{
+        art_object::ArtObjectId,
+        image::{ImageFormat, ImageSize},
+    };
+
+    use crate::worker_thread::ResponseBody;
+
+    use super::ImagePathCache;
+
+    fn image(name: &str) -> ResponseBody {
+        ResponseBody::Image {
+            path: Some(PathBuf::from(name)),
+            pixel_width: Some(4),
+            pixel_height: Some(3),
+        }
+    }
+
+    fn no_image() -> ResponseBody {
+        ResponseBody::Image {
+            path: None,
+            pixel_width: None,
+            pixel_height: None,
+        }
+    }
+
+    /// Fetches the small image of the given Met object, with the worker
+    /// responding with the given body.
+    fn fetch(cache: &mut ImagePathCache, request_id: u32, id: i64, body: ResponseBody) {
+        cache.on_request_sent(request_id, ArtObjectId::Met(id), ImageSize::Small, None);
+        cache.on_response(request_id, &body);
+    }
+
+    fn cached_path(cache: &mut ImagePathCache, id: i64) -> Option<PathBuf> {
+        match cache.get(ArtObjectId::Met(id), ImageSize::Small, None) {
+            Some(ResponseBody::Image { path, .. }) => path,
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_fetched_images_are_remembered() {
+        let mut cache = ImagePathCache::default();
+        assert_eq!(cached_path(&mut cache, 1), None);
+        fetch(&mut cache, 1, 1, image("met/1-small.jpg"));
+        let Some(ResponseBody::Image {
+            path,
+            pixel_width,
+            pixel_height,
+        }) = cache.get(ArtObjectId::Met(1), ImageSize::Small, None)
+        else {
+            panic!("expected cached image");
+        };
+        assert_eq!(path, Some(PathBuf::from("met/1-small.jpg")));
+        assert_eq!((pixel_width, pixel_height), (Some(4), Some(3)));
+
+        // Other sizes and formats are cached separately.
+        assert!(cache
+            .get(ArtObjectId::Met(1), ImageSize::Large, None)
+            .is_none());
+        assert!(cache
+            .get(
+                ArtObjectId::Met(1),
+                ImageSize::Small,
+                Some(ImageFormat::Webp)
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_least_recently_used_images_are_evicted() {
+        let mut cache = ImagePathCache::new(2);
+        fetch(&mut cache, 1, 1, image("1.jpg"));
+        fetch(&mut cache, 2, 2, image("2.jpg"));
+        // Using the first image makes the second one the least recently used.
+        assert!(cached_path(&mut cache, 1).is_some());
+        fetch(&mut cache, 3, 3, image("3.jpg"));
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cached_path(&mut cache, 1).is_some());
+        assert_eq!(cached_path(&mut cache, 2), None);
+        assert!(cached_path(&mut cache, 3).is_some());
+
+        cache.set_capacity(1);
+        assert_eq!(cache.entries.len(), 1);
+        assert!(cached_path(&mut cache, 3).is_some());
+
+        cache.set_capacity(0);
+        fetch(&mut cache, 4, 4, image("4.jpg"));
+        assert_eq!(cache.entries.len(), 0);
+    }
+
+    #[test]
+    fn test_failed_fetches_invalidate_every_size() {
+        let mut cache = ImagePathCache::default();
+        fetch(&mut cache, 1, 1, image("1-small.jpg"));
+        cache.on_request_sent(2, ArtObjectId::Met(1), ImageSize::Large, None);
+        cache.on_response(2, &image("1.jpg"));
+        fetch(&mut cache, 3, 2, image("2-small.jpg"));
+
+        cache.on_request_sent(4, ArtObjectId::Met(1), ImageSize::Large, None);
+        cache.on_response(4, &ResponseBody::Error("Oof".into()));
+        assert_eq!(cached_path(&mut cache, 1), None);
+        assert!(cache
+            .get(ArtObjectId::Met(1), ImageSize::Large, None)
+            .is_none());
+        assert!(cached_path(&mut cache, 2).is_some());
+
+        fetch(&mut cache, 5, 2, no_image());
+        assert_eq!(cached_path(&mut cache, 2), None);
+    }
+
+    #[test]
+    fn test_clear_forgets_images_and_pending_fetches() {
+        let mut cache = ImagePathCache::default();
+        fetch(&mut cache, 1, 1, image("1.jpg"));
+        cache.on_request_sent(2, ArtObjectId::Met(2), ImageSize::Small, None);
+        cache.clear();
+        assert_eq!(cached_path(&mut cache, 1), None);
+
+        // The fetch that was in progress when we cleared isn't remembered.
+        cache.on_response(2, &image("2.jpg"));
+        assert_eq!(cached_path(&mut cache, 2), None);
+    }
+
+    #[test]
+    fn test_unrelated_responses_are_ignored() {
+        let mut cache = ImagePathCache::default();
+        cache.on_response(1, &image("1.jpg"));
+        assert_eq!(cache.entries.len(), 0);
+        cache.on_request_sent(2, ArtObjectId::Met(1), ImageSize::Small, None);
+        cache.on_response(2, &ResponseBody::Empty);
+        cache.on_response(2, &image("1.jpg"));
+        assert_eq!(cache.entries.len(), 0);
+    }
+
+    #[test]
+    fn test_cached_responses_are_queued_behind_real_ones() {
+        let mut cache = ImagePathCache::default();
+        let mut queued_responses: VecDeque<(u32, ResponseBody)> = VecDeque::new();
+        let mut next_request_id = 2;
+        let mut new_request_id = || {
+            next_request_id += 1;
+            next_request_id
+        };
+
+        // Images we don't know about aren't queued, and don't use up a request
+        // ID.
+        assert_eq!(
+            cache.queue_response(
+                ArtObjectId::Met(1),
+                ImageSize::Small,
+                None,
+                || panic!("request ID shouldn't be needed"),
+                &mut queued_responses,
+            ),
+            None
+        );
+        assert!(queued_responses.is_empty());
+        fetch(&mut cache, 1, 1, image("1.jpg"));
+
+        // A fetch is already in progress when the image is fetched from the
+        // cache, and fails.
+        cache.on_request_sent(2, ArtObjectId::Met(1), ImageSize::Small, None);
+        queued_responses.push_back((2, no_image()));
+        let request_id = cache.queue_response(
+            ArtObjectId::Met(1),
+            ImageSize::Small,
+            None,
+            &mut new_request_id,
+            &mut queued_responses,
+        );
+        assert_eq!(request_id, Some(3));
+
+        // This is how `GalleryClient::poll()` handles queued responses.
+        let mut polled = vec![];
+        while let Some((request_id, body)) = queued_responses.pop_front() {
+            cache.on_response(request_id, &body);
+            let path = match body {
+                ResponseBody::Image { path, .. } => path,
+                _ => None,
+            };
+            polled.push((request_id, path));
+        }
+        assert_eq!(polled, vec![(2, None), (3, Some(PathBuf::from("1.jpg")))]);
+        // The failure still invalidated the image, so the next fetch goes to
+        // the worker.
+        assert_eq!(cached_path(&mut cache, 1), None);
+    }
+}
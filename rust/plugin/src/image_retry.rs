@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use gallery::{art_object::ArtObjectId, image::ImageSize};
+
+use crate::worker_thread::ResponseBody;
+
+/// How long to wait before fetching an image again after it first fails.
+pub const INITIAL_IMAGE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Each consecutive failure doubles the delay, up to this much.
+pub const MAX_IMAGE_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Returns how long to wait before fetching an image again after it has failed
+/// the given number of times in a row.
+pub fn image_retry_delay(failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(1).min(31);
+    INITIAL_IMAGE_RETRY_DELAY
+        .saturating_mul(1 << doublings)
+        .min(MAX_IMAGE_RETRY_DELAY)
+}
+
+struct ImageFailures {
+    count: u32,
+    retry_at: Instant,
+}
+
+/// Keeps track of which image fetches have failed, so that fetching an image
+/// that recently failed can be responded to with how long to wait, instead of
+/// hammering the worker (and whatever server it's downloading from).
+///
+/// Note that there's no way to tell whether an image failed to fetch because
+/// the network is down or because it doesn't exist at all, so the latter is
+/// retried too, just no more often than `MAX_IMAGE_RETRY_DELAY`.
+#[derive(Default)]
+pub struct ImageRetryTracker {
+    failures: HashMap<(ArtObjectId, ImageSize), ImageFailures>,
+    /// Image fetches the worker hasn't responded to yet, keyed by request ID.
+    pending: HashMap<u32, (ArtObjectId, ImageSize)>,
+}
+
+impl ImageRetryTracker {
+    /// If the image recently failed to fetch, returns how long to wait before
+    /// fetching it again.
+    pub fn retry_after(
+        &self,
+        object_id: ArtObjectId,
+        size: ImageSize,
+        now: Instant,
+    ) -> Option<Duration> {
+        let failures = self.failures.get(&(object_id, size))?;
+        let remaining = failures.retry_at.saturating_duration_since(now);
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    pub fn on_request_sent(&mut self, request_id: u32, object_id: ArtObjectId, size: ImageSize) {
+        self.pending.insert(request_id, (object_id, size));
+    }
+
+    pub fn on_response(&mut self, request_id: u32, body: &ResponseBody, now: Instant) {
+        let Some(key) = self.pending.remove(&request_id) else {
+            return;
+        };
+        let failed = match body {
+            ResponseBody::Image { path, .. } => path.is_none(),
+            ResponseBody::DecodedImage { .. } => false,
+            ResponseBody::Error(_) => true,
+            _ => return,
+        };
+        if !failed {
+            self.failures.remove(&key);
+            return;
+        }
+        let failures = self.failures.entry(key).or_insert(ImageFailures {
+            count: 0,
+            retry_at: now,
+        });
+        failures.count += 1;
+        failures.retry_at = now + image_retry_delay(failures.count);
+    }
+
+    /// Forgets about all failures, so every image can be fetched again right
+    /// away. Fetches that are still in progress won't count as failures either.
+    pub fn clear(&mut self) {
+        self.failures.clear();
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    use gallery::{art_object::ArtObjectId, image::ImageSize};
+
+    use crate::worker_thread::ResponseBody;
+
+    use super::{
+        image_retry_delay, ImageRetryTracker, INITIAL_IMAGE_RETRY_DELAY, MAX_IMAGE_RETRY_DELAY,
+    };
+
+    const OBJECT_ID: ArtObjectId = ArtObjectId::Met(1);
+
+    fn no_image() -> ResponseBody {
+        ResponseBody::Image {
+            path: None,
+            pixel_width: None,
+            pixel_height: None,
+        }
+    }
+
+    fn image() -> ResponseBody {
+        ResponseBody::Image {
+            path: Some(PathBuf::from("met/1-small.jpg")),
+            pixel_width: None,
+            pixel_height: None,
+        }
+    }
+
+    /// Fetches the image at the given time, responding with the given body if
+    /// the tracker lets the request through. Returns the cooldown otherwise.
+    fn fetch(
+        tracker: &mut ImageRetryTracker,
+        request_id: u32,
+        body: ResponseBody,
+        now: Instant,
+    ) -> Option<Duration> {
+        if let Some(retry_after) = tracker.retry_after(OBJECT_ID, ImageSize::Small, now) {
+            return Some(retry_after);
+        }
+        tracker.on_request_sent(request_id, OBJECT_ID, ImageSize::Small);
+        tracker.on_response(request_id, &body, now);
+        None
+    }
+
+    #[test]
+    fn test_image_retry_delay_backs_off_exponentially() {
+        assert_eq!(image_retry_delay(1), INITIAL_IMAGE_RETRY_DELAY);
+        assert_eq!(image_retry_delay(2), INITIAL_IMAGE_RETRY_DELAY * 2);
+        assert_eq!(image_retry_delay(3), INITIAL_IMAGE_RETRY_DELAY * 4);
+        assert_eq!(image_retry_delay(20), MAX_IMAGE_RETRY_DELAY);
+        assert_eq!(image_retry_delay(u32::MAX), MAX_IMAGE_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_failed_fetches_cool_down() {
+        let start = Instant::now();
+        let mut tracker = ImageRetryTracker::default();
+        assert_eq!(fetch(&mut tracker, 1, no_image(), start), None);
+
+        let later = start + INITIAL_IMAGE_RETRY_DELAY / 5;
+        assert_eq!(
+            fetch(&mut tracker, 2, image(), later),
+            Some(INITIAL_IMAGE_RETRY_DELAY * 4 / 5)
+        );
+
+        // Other sizes and art objects aren't affected.
+        assert_eq!(
+            tracker.retry_after(OBJECT_ID, ImageSize::Large, later),
+            None
+        );
+        assert_eq!(
+            tracker.retry_after(ArtObjectId::Met(2), ImageSize::Small, later),
+            None
+        );
+    }
+
+    #[test]
+    fn test_backoff_schedule_is_followed() {
+        let mut now = Instant::now();
+        let mut tracker = ImageRetryTracker::default();
+        for failures in 1..=12 {
+            assert_eq!(fetch(&mut tracker, failures, no_image(), now), None);
+            let delay = image_retry_delay(failures);
+            assert_eq!(
+                tracker.retry_after(OBJECT_ID, ImageSize::Small, now),
+                Some(delay)
+            );
+            assert!(delay <= MAX_IMAGE_RETRY_DELAY);
+            now += delay;
+        }
+        assert_eq!(tracker.retry_after(OBJECT_ID, ImageSize::Small, now), None);
+    }
+
+    #[test]
+    fn test_success_resets_backoff() {
+        let start = Instant::now();
+        let mut tracker = ImageRetryTracker::default();
+        fetch(&mut tracker, 1, no_image(), start);
+        let retry_time = start + INITIAL_IMAGE_RETRY_DELAY;
+        fetch(
+            &mut tracker,
+            2,
+            ResponseBody::Error("Oof".into()),
+            retry_time,
+        );
+        let retry_time = retry_time + INITIAL_IMAGE_RETRY_DELAY * 2;
+        assert_eq!(fetch(&mut tracker, 3, image(), retry_time), None);
+        assert_eq!(
+            tracker.retry_after(OBJECT_ID, ImageSize::Small, retry_time),
+            None
+        );
+
+        // The next failure starts the schedule over.
+        fetch(&mut tracker, 4, no_image(), retry_time);
+        assert_eq!(
+            tracker.retry_after(OBJECT_ID, ImageSize::Small, retry_time),
+            Some(INITIAL_IMAGE_RETRY_DELAY)
+        );
+    }
+
+    #[test]
+    fn test_clear_forgets_failures_and_pending_fetches() {
+        let start = Instant::now();
+        let mut tracker = ImageRetryTracker::default();
+        fetch(&mut tracker, 1, no_image(), start);
+        tracker.on_request_sent(2, ArtObjectId::Met(2), ImageSize::Small);
+        tracker.clear();
+        assert_eq!(
+            tracker.retry_after(OBJECT_ID, ImageSize::Small, start),
+            None
+        );
+
+        // The fetch that was in progress when we cleared doesn't count.
+        tracker.on_response(2, &no_image(), start);
+        assert_eq!(
+            tracker.retry_after(ArtObjectId::Met(2), ImageSize::Small, start),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unrelated_responses_are_ignored() {
+        let start = Instant::now();
+        let mut tracker = ImageRetryTracker::default();
+        tracker.on_response(1, &no_image(), start);
+        tracker.on_request_sent(2, OBJECT_ID, ImageSize::Small);
+        tracker.on_response(2, &ResponseBody::Empty, start);
+        tracker.on_response(2, &no_image(), start);
+        assert_eq!(
+            tracker.retry_after(OBJECT_ID, ImageSize::Small, start),
+            None
+        );
+    }
+}
@@ -3,9 +3,30 @@ use godot::prelude::*;
 struct GalleryExtension;
 
 mod art_object;
+mod connection_state;
 mod gallery_client;
+mod gallery_constants;
+mod gallery_db_direct;
 mod gallery_response;
+mod godot_logger;
+mod image_path_cache;
+mod image_retry;
+mod preview_server;
+mod proxy;
+mod wall_contents;
 mod worker_thread;
+mod worker_watchdog;
+
+#[cfg(test)]
+mod test_worker;
+#[cfg(test)]
+mod worker_integration_tests;
 
 #[gdextension]
-unsafe impl ExtensionLibrary for GalleryExtension {}
+unsafe impl ExtensionLibrary for GalleryExtension {
+    fn on_level_init(level: InitLevel) {
+        if level == InitLevel::Scene {
+            godot_logger::init_godot_logger();
+        }
+    }
+}
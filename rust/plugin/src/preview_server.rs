@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Result;
+use gallery::{
+    art_object::{ArtObjectId, PlacedArtObject},
+    gallery_db::{ArtObjectRecord, GalleryDb},
+};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::worker_thread::RelatedArtObject;
+
+/// The port the preview server listens on unless told otherwise.
+pub const DEFAULT_PREVIEW_SERVER_PORT: u16 = 8765;
+
+/// How often the server checks whether it's been told to stop when nobody's
+/// connecting to it.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long we'll wait for a client to send its request or read our response,
+/// so a misbehaving client can't hang the server.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// We don't need any request headers, but we need to read them, and we don't
+/// want to do so forever.
+const MAX_REQUEST_HEADER_LINES: usize = 100;
+
+/// A layout record in `/layout.json`, along with the art object it places.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct PreviewLayoutRecord {
+    pub gallery_id: i64,
+    pub wall_id: String,
+    #[serde(flatten)]
+    pub art_object: PlacedArtObject,
+}
+
+/// A gallery with art in it, in `/galleries.json`.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct PreviewGallery {
+    pub gallery_id: i64,
+    pub object_count: usize,
+    /// The name of the gallery's wall set, or `None` if the gallery was laid
+    /// out before wall sets were recorded.
+    pub wall_set: Option<String>,
+    pub segment: Option<String>,
+    /// The real gallery it recreates, see `LayoutMode::RealGallery`.
+    pub source_gallery: Option<String>,
+    pub reserved_walls: Vec<String>,
+}
+
+/// A tiny read-only HTTP server on localhost that serves the current layout as
+/// JSON, so it can be looked at in a browser or by other tools while the game
+/// is running:
+///
+/// * `/layout.json` is every layout record, see `PreviewLayoutRecord`.
+/// * `/galleries.json` is every gallery with art in it, see `PreviewGallery`.
+/// * `/object/<raw_id>.json` is a single art object, see `RelatedArtObject`.
+///
+/// It uses its own read-only database connection on its own thread, so it never
+/// blocks the worker thread. It stops when dropped.
+///
+/// Note that it doesn't send any CORS headers, so web pages can't read from it,
+/// since any site the player visits could otherwise snoop on their layout.
+pub struct PreviewServer {
+    stopping: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PreviewServer {
+    /// Starts serving the layout in the given databases on the given port, or
+    /// on an arbitrary free port if it's 0.
+    pub fn start(db_path: PathBuf, layout_db_path: Option<PathBuf>, port: u16) -> Result<Self> {
+        let mut db = GalleryDb::open(db_path, true)?;
+        if let Some(layout_db_path) = layout_db_path {
+            db.attach_layout_db(layout_db_path)?;
+        }
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        // Otherwise we'd never notice that we've been told to stop.
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stopping = stopping.clone();
+            thread::spawn(move || serve(listener, db, &stopping))
+        };
+        info!("Serving layout preview at http://{addr}/layout.json");
+        Ok(PreviewServer {
+            stopping,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stops the server, waiting for it to finish responding to its current
+    /// request, if any.
+    pub fn stop(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        self.stopping.store(true, Ordering::SeqCst);
+        if handle.join().is_err() {
+            error!("Preview server thread panicked.");
+        } else {
+            debug!("Joined preview server thread.");
+        }
+    }
+}
+
+impl Drop for PreviewServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn serve(listener: TcpListener, db: GalleryDb, stopping: &AtomicBool) {
+    while !stopping.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(err) = handle_connection(&db, stream) {
+                    debug!("Preview server couldn't respond: {err:?}");
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(err) => {
+                warn!("Preview server couldn't accept connection: {err:?}");
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+fn handle_connection(db: &GalleryDb, stream: TcpStream) -> Result<()> {
+    // Accepted streams inherit non-blocking mode on some platforms.
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    for _ in 0..MAX_REQUEST_HEADER_LINES {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+    let (status, body) = respond(db, &request_line);
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\n\
+        Content-Type: application/json\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Returns the HTTP status and JSON body to respond to the given request line,
+/// e.g. `GET /layout.json HTTP/1.1`.
+fn respond(db: &GalleryDb, request_line: &str) -> (u16, String) {
+    let error = |status: u16, message: String| {
+        (status, serde_json::json!({ "error": message }).to_string())
+    };
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return error(400, "Malformed request".to_string());
+    };
+    if method != "GET" {
+        return error(405, format!("Method not allowed: {method}"));
+    }
+    let path = target.split('?').next().unwrap_or_default();
+    match get_json(db, path) {
+        Ok(Some(json)) => (200, json),
+        Ok(None) => error(404, format!("Not found: {path}")),
+        Err(err) => {
+            warn!("Preview server failed to respond to {path}: {err:?}");
+            error(500, err.to_string())
+        }
+    }
+}
+
+/// Returns the JSON at the given path, or `None` if there's nothing there.
+fn get_json(db: &GalleryDb, path: &str) -> Result<Option<String>> {
+    if path == "/layout.json" {
+        return Ok(Some(serde_json::to_string(&get_preview_layout(db)?)?));
+    }
+    if path == "/galleries.json" {
+        return Ok(Some(serde_json::to_string(&get_preview_galleries(db)?)?));
+    }
+    let Some(raw_id) = path
+        .strip_prefix("/object/")
+        .and_then(|rest| rest.strip_suffix(".json"))
+        .and_then(|raw_id| raw_id.parse::<i64>().ok())
+    else {
+        return Ok(None);
+    };
+    let Some(record) = db.get_art_object(ArtObjectId::from_raw_i64(raw_id))? else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::to_string(&RelatedArtObject::from(
+        record,
+    ))?))
+}
+
+fn get_preview_layout(db: &GalleryDb) -> Result<Vec<PreviewLayoutRecord>> {
+    let records = db.get_all_layout_records()?;
+    let ids: Vec<ArtObjectId> = records.iter().map(|record| record.art_object_id).collect();
+    let mut objects: HashMap<ArtObjectId, ArtObjectRecord> = db
+        .get_art_objects_with_ids(&ids)?
+        .into_iter()
+        .map(|object| (object.object_id, object))
+        .collect();
+    let mut result = vec![];
+    for record in records {
+        // Layout records can outlive their art objects, e.g. after a reimport.
+        let Some(object) = objects.remove(&record.art_object_id) else {
+            continue;
+        };
+        result.push(PreviewLayoutRecord {
+            gallery_id: record.gallery_id,
+            wall_id: record.wall_id,
+            art_object: PlacedArtObject::from((object, (record.x, record.y), record.rotated)),
+        });
+    }
+    Ok(result)
+}
+
+fn get_preview_galleries(db: &GalleryDb) -> Result<Vec<PreviewGallery>> {
+    let mut result = vec![];
+    for gallery in db.get_populated_positive_galleries()? {
+        let record = db.get_gallery_record(gallery.gallery_id)?;
+        result.push(PreviewGallery {
+            gallery_id: gallery.gallery_id,
+            object_count: gallery.object_count,
+            wall_set: record.as_ref().map(|record| record.wall_set.clone()),
+            segment: record.as_ref().and_then(|record| record.segment.clone()),
+            source_gallery: record
+                .as_ref()
+                .and_then(|record| record.source_gallery.clone()),
+            reserved_walls: record
+                .map(|record| record.reserved_walls)
+                .unwrap_or_default(),
+        });
+    }
+    Ok(result)
+}
@@ -0,0 +1,508 @@
+use std::fmt::Display;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// The version of the protocol used to proxy requests and responses between
+/// multiplayer peers. This should be bumped whenever a change is made to
+/// `RequestBody` or `ResponseBody` that older peers won't be able to understand,
+/// e.g. removing or renaming a variant. Adding new optional fields (with
+/// `#[serde(default)]`) doesn't require a bump, since unknown fields are ignored.
+pub const PROXY_PROTOCOL_VERSION: u32 = 1;
+
+/// The version of the binary encoding of `RequestBody` and `ResponseBody`. Unlike
+/// JSON, the binary encoding isn't self-describing, so this needs to be bumped
+/// whenever _anything_ about them changes, including adding optional fields.
+/// Peers whose binary versions differ just keep talking JSON.
+pub const BINARY_PROXY_PROTOCOL_VERSION: u32 = 12;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProxyEnvelope {
+    pub protocol_version: u32,
+    /// The JSON-serialized `RequestBody` or `ResponseBody`.
+    pub payload: String,
+    /// The sender's `BINARY_PROXY_PROTOCOL_VERSION`, which means it can receive
+    /// binary payloads. Peers that predate the binary encoding don't send this, so
+    /// they only ever get JSON.
+    #[serde(default)]
+    pub binary_protocol_version: Option<u32>,
+}
+
+/// How proxied requests and responses are serialized.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ProxyCodec {
+    /// A `ProxyEnvelope` as a string, which every peer understands.
+    #[default]
+    Json,
+    /// `BINARY_PROXY_PROTOCOL_VERSION` followed by the postcard-serialized body, as
+    /// bytes. This is much smaller than JSON, e.g. for
+    /// `ResponseBody::ArtObjectsForGalleryWall`, but is only used once we know the
+    /// peer understands it.
+    Binary,
+}
+
+impl ProxyCodec {
+    /// The best codec that a peer who sent us the given envelope can receive.
+    pub fn for_envelope(envelope: &ProxyEnvelope) -> Self {
+        if envelope.binary_protocol_version == Some(BINARY_PROXY_PROTOCOL_VERSION) {
+            ProxyCodec::Binary
+        } else {
+            ProxyCodec::Json
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProxyError {
+    /// The envelope itself couldn't be parsed, e.g. because the peer is running a
+    /// version of the plugin that predates envelopes.
+    MalformedEnvelope(String),
+    IncompatibleVersion {
+        ours: u32,
+        theirs: u32,
+    },
+    /// The envelope was fine, but its payload couldn't be parsed, e.g. because the
+    /// peer sent a variant that we don't know about.
+    MalformedPayload(String),
+}
+
+impl Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::MalformedEnvelope(err) => {
+                write!(f, "Unable to parse proxy envelope: {err}")
+            }
+            ProxyError::IncompatibleVersion { ours, theirs } => write!(
+                f,
+                "Incompatible proxy protocol version (ours is {ours}, theirs is {theirs}), please make sure everyone is running the same version of the game"
+            ),
+            ProxyError::MalformedPayload(err) => {
+                write!(f, "Unable to parse proxied payload: {err}")
+            }
+        }
+    }
+}
+
+pub fn wrap_in_envelope<T: Serialize>(payload: &T) -> serde_json::Result<String> {
+    serde_json::to_string(&ProxyEnvelope {
+        protocol_version: PROXY_PROTOCOL_VERSION,
+        payload: serde_json::to_string(payload)?,
+        binary_protocol_version: Some(BINARY_PROXY_PROTOCOL_VERSION),
+    })
+}
+
+/// Also returns the best codec to use when sending things back to the peer.
+pub fn unwrap_envelope<T: DeserializeOwned>(
+    serialized: &str,
+) -> Result<(T, ProxyCodec), ProxyError> {
+    let envelope: ProxyEnvelope = serde_json::from_str(serialized)
+        .map_err(|err| ProxyError::MalformedEnvelope(err.to_string()))?;
+    if envelope.protocol_version != PROXY_PROTOCOL_VERSION {
+        return Err(ProxyError::IncompatibleVersion {
+            ours: PROXY_PROTOCOL_VERSION,
+            theirs: envelope.protocol_version,
+        });
+    }
+    let payload = serde_json::from_str(&envelope.payload)
+        .map_err(|err| ProxyError::MalformedPayload(err.to_string()))?;
+    Ok((payload, ProxyCodec::for_envelope(&envelope)))
+}
+
+pub fn wrap_in_binary_envelope<T: Serialize>(payload: &T) -> postcard::Result<Vec<u8>> {
+    let mut bytes = postcard::to_allocvec(&BINARY_PROXY_PROTOCOL_VERSION)?;
+    bytes.extend(postcard::to_allocvec(payload)?);
+    Ok(bytes)
+}
+
+pub fn unwrap_binary_envelope<T: DeserializeOwned>(serialized: &[u8]) -> Result<T, ProxyError> {
+    let (version, payload) = postcard::take_from_bytes::<u32>(serialized)
+        .map_err(|err| ProxyError::MalformedEnvelope(err.to_string()))?;
+    if version != BINARY_PROXY_PROTOCOL_VERSION {
+        return Err(ProxyError::IncompatibleVersion {
+            ours: BINARY_PROXY_PROTOCOL_VERSION,
+            theirs: version,
+        });
+    }
+    postcard::from_bytes(payload).map_err(|err| ProxyError::MalformedPayload(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use gallery::{
+        art_object::{ArtObjectId, PlacedArtObject},
+        frame_style::FrameStyle,
+        gallery_db::{ArtistRecord, LayoutRecord, MaintenanceReport},
+        image::{ImageFormat, ImageSize},
+        layout::{CollectionWeight, LayoutMode, LayoutSegment},
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use crate::worker_thread::{ImportConflictPolicy, RequestBody, ResponseBody};
+
+    use super::{
+        unwrap_binary_envelope, unwrap_envelope, wrap_in_binary_envelope, wrap_in_envelope,
+        ProxyCodec, ProxyError, BINARY_PROXY_PROTOCOL_VERSION, PROXY_PROTOCOL_VERSION,
+    };
+
+    #[test]
+    fn test_envelope_round_trips() {
+        let serialized = wrap_in_envelope(&ResponseBody::Integer(5)).unwrap();
+        let (body, codec): (ResponseBody, _) = unwrap_envelope(&serialized).unwrap();
+        assert!(matches!(body, ResponseBody::Integer(5)));
+        assert_eq!(codec, ProxyCodec::Binary);
+    }
+
+    #[test]
+    fn test_incompatible_version_is_rejected() {
+        let serialized = format!(
+            r#"{{"protocol_version":{},"payload":"\"Empty\""}}"#,
+            PROXY_PROTOCOL_VERSION + 1
+        );
+        let result = unwrap_envelope::<ResponseBody>(&serialized);
+        assert_eq!(
+            result.err(),
+            Some(ProxyError::IncompatibleVersion {
+                ours: PROXY_PROTOCOL_VERSION,
+                theirs: PROXY_PROTOCOL_VERSION + 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_bare_payload_is_rejected() {
+        let result = unwrap_envelope::<RequestBody>(r#"{"CountArtObjects":{"filter":null}}"#);
+        assert!(matches!(result, Err(ProxyError::MalformedEnvelope(_))));
+    }
+
+    #[test]
+    fn test_unknown_fields_are_ignored() {
+        let serialized = format!(
+            r#"{{"protocol_version":{},"payload":"{{\"CountArtObjects\":{{\"filter\":\"boop\",\"extra\":1}}}}","extra":true}}"#,
+            PROXY_PROTOCOL_VERSION
+        );
+        let (body, codec): (RequestBody, _) = unwrap_envelope(&serialized).unwrap();
+        // Peers that don't mention the binary encoding only get JSON.
+        assert_eq!(codec, ProxyCodec::Json);
+        let RequestBody::CountArtObjects { filter } = body else {
+            panic!("expected count art objects request");
+        };
+        assert_eq!(filter, Some("boop".to_string()));
+    }
+
+    #[test]
+    fn test_unknown_variant_is_rejected() {
+        let serialized = format!(
+            r#"{{"protocol_version":{},"payload":"\"SomeFutureRequest\""}}"#,
+            PROXY_PROTOCOL_VERSION
+        );
+        let result = unwrap_envelope::<RequestBody>(&serialized);
+        assert!(matches!(result, Err(ProxyError::MalformedPayload(_))));
+    }
+
+    #[test]
+    fn test_binary_incompatible_version_is_rejected() {
+        let mut serialized = postcard::to_allocvec(&(BINARY_PROXY_PROTOCOL_VERSION + 1)).unwrap();
+        serialized.extend(postcard::to_allocvec(&ResponseBody::Empty).unwrap());
+        let result = unwrap_binary_envelope::<ResponseBody>(&serialized);
+        assert_eq!(
+            result.err(),
+            Some(ProxyError::IncompatibleVersion {
+                ours: BINARY_PROXY_PROTOCOL_VERSION,
+                theirs: BINARY_PROXY_PROTOCOL_VERSION + 1
+            })
+        );
+        assert!(matches!(
+            unwrap_binary_envelope::<ResponseBody>(&[]),
+            Err(ProxyError::MalformedEnvelope(_))
+        ));
+        let truncated = wrap_in_binary_envelope(&ResponseBody::String("boop".into())).unwrap();
+        assert!(matches!(
+            unwrap_binary_envelope::<ResponseBody>(&truncated[..truncated.len() - 1]),
+            Err(ProxyError::MalformedPayload(_))
+        ));
+    }
+
+    /// Neither body implements `PartialEq`, so their JSON is compared instead.
+    fn assert_round_trips<T: Serialize + DeserializeOwned>(body: &T) {
+        let expected = serde_json::to_string(body).unwrap();
+
+        let (from_json, _): (T, _) = unwrap_envelope(&wrap_in_envelope(body).unwrap()).unwrap();
+        assert_eq!(serde_json::to_string(&from_json).unwrap(), expected);
+
+        let from_binary: T =
+            unwrap_binary_envelope(&wrap_in_binary_envelope(body).unwrap()).unwrap();
+        assert_eq!(serde_json::to_string(&from_binary).unwrap(), expected);
+    }
+
+    /// Makes sure every variant has a sample in `sample_request_bodies()`.
+    fn request_variant_index(body: &RequestBody) -> usize {
+        match body {
+            RequestBody::MoveArtObject { .. } => 0,
+            RequestBody::GetArtObjectsForGalleryWall { .. } => 1,
+            RequestBody::FetchImage { .. } => 2,
+            RequestBody::Layout { .. } => 3,
+            RequestBody::GetGalleryWallSet { .. } => 4,
+            RequestBody::GetGalleryReservedWalls { .. } => 5,
+            RequestBody::GetArtist { .. } => 6,
+            RequestBody::GetRelatedArtObjects { .. } => 7,
+            RequestBody::GetGalleryGraph => 8,
+            RequestBody::CountGalleries => 9,
+            RequestBody::ListCollections => 10,
+            RequestBody::SaveFilter { .. } => 11,
+            RequestBody::ListFilters => 12,
+            RequestBody::DeleteFilter { .. } => 13,
+            RequestBody::CountArtObjects { .. } => 14,
+            RequestBody::DistinctValues { .. } => 15,
+            RequestBody::Migrate => 16,
+            RequestBody::UndoLastMove => 17,
+            RequestBody::ImportNonPositiveLayout { .. } => 18,
+            RequestBody::ExportNonPositiveLayout { .. } => 19,
+            RequestBody::Maintenance { .. } => 20,
+            RequestBody::GetCacheInfo => 21,
+            RequestBody::TagArtObject { .. } => 22,
+            RequestBody::UntagArtObject { .. } => 23,
+            RequestBody::GetTags { .. } => 24,
+            RequestBody::GetArtObjectPlacement { .. } => 25,
+            RequestBody::EstimateLayout { .. } => 26,
+            RequestBody::EnsureReady { .. } => 27,
+            RequestBody::GetCachedImageStates { .. } => 28,
+            RequestBody::SearchPlaced { .. } => 29,
+            RequestBody::MergeNonPositiveLayout { .. } => 30,
+            RequestBody::GetGallerySourceGallery { .. } => 31,
+        }
+    }
+
+    fn sample_request_bodies() -> Vec<RequestBody> {
+        vec![
+            RequestBody::MoveArtObject {
+                art_object_id: ArtObjectId::Wikidata(3),
+                gallery_id: -1,
+                wall_id: "wall_a".into(),
+                x: 1.5,
+                y: -2.25,
+                strict: true,
+            },
+            RequestBody::GetArtObjectsForGalleryWall {
+                gallery_id: 5,
+                wall_id: "wall_b".into(),
+            },
+            RequestBody::FetchImage {
+                object_id: ArtObjectId::Met(1),
+                size: ImageSize::Large,
+                decode: true,
+                format: Some(ImageFormat::Webp),
+            },
+            RequestBody::Layout {
+                walls_json: "[]".into(),
+                wall_sets_json: Some("[]".into()),
+                filter: Some("boop".into()),
+                dense: true,
+                mode: Some(LayoutMode::Salon { gutter: 0.3 }),
+                ordering_json: None,
+                reserved_walls: vec!["wall_a".into()],
+                segments: vec![LayoutSegment {
+                    name: "portraits".into(),
+                    filter: Some("portrait".into()),
+                }],
+                featured_first: true,
+                allow_rotation: false,
+                balance_by_collection: Some(vec![CollectionWeight {
+                    collection: "The Metropolitan Museum of Art".into(),
+                    weight: 0.5,
+                }]),
+                collect_free_regions: true,
+                min_free_region_width: Some(1.5),
+                exclude_previously_displayed: true,
+                gallery_id_range: Some((1, 3)),
+            },
+            RequestBody::GetGalleryWallSet { gallery_id: 2 },
+            RequestBody::GetGalleryReservedWalls { gallery_id: 3 },
+            RequestBody::GetGallerySourceGallery { gallery_id: 4 },
+            RequestBody::GetArtist { qid: 42 },
+            RequestBody::GetRelatedArtObjects {
+                object_id: ArtObjectId::Met(1),
+                limit: 10,
+            },
+            RequestBody::GetGalleryGraph,
+            RequestBody::CountGalleries,
+            RequestBody::ListCollections,
+            RequestBody::SaveFilter {
+                name: "monkeys".into(),
+                filter: "monkey".into(),
+            },
+            RequestBody::ListFilters,
+            RequestBody::DeleteFilter {
+                name: "monkeys".into(),
+            },
+            RequestBody::CountArtObjects { filter: None },
+            RequestBody::SearchPlaced {
+                filter: Some("funky".into()),
+                limit: 10,
+            },
+            RequestBody::DistinctValues {
+                column: "artist".into(),
+                min_count: 2,
+                limit: 50,
+            },
+            RequestBody::Migrate,
+            RequestBody::EnsureReady {
+                walls_json: "[]".into(),
+                relayout_if_empty: true,
+            },
+            RequestBody::UndoLastMove,
+            RequestBody::ImportNonPositiveLayout {
+                json_content: "[]".into(),
+                conflict_policy: ImportConflictPolicy::Fail,
+            },
+            RequestBody::MergeNonPositiveLayout {
+                json_content: "{\"version\": 2, \"records\": []}".into(),
+            },
+            RequestBody::ExportNonPositiveLayout {
+                include_metadata: true,
+            },
+            RequestBody::Maintenance { vacuum: false },
+            RequestBody::GetCacheInfo,
+            RequestBody::GetCachedImageStates {
+                object_ids: vec![ArtObjectId::Met(1), ArtObjectId::Wikidata(3)],
+            },
+            RequestBody::TagArtObject {
+                art_object_id: ArtObjectId::Met(1),
+                tag: "favorite".into(),
+            },
+            RequestBody::UntagArtObject {
+                art_object_id: ArtObjectId::Wikidata(3),
+                tag: "creepy".into(),
+            },
+            RequestBody::GetTags {
+                art_object_id: ArtObjectId::Met(1),
+            },
+            RequestBody::GetArtObjectPlacement {
+                art_object_id: ArtObjectId::Wikidata(3),
+            },
+            RequestBody::EstimateLayout {
+                walls_json: "[]".into(),
+                filter: Some("painting".into()),
+                dense: true,
+                mode: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_every_request_body_round_trips() {
+        let bodies = sample_request_bodies();
+        let mut indices: Vec<usize> = bodies.iter().map(request_variant_index).collect();
+        indices.dedup();
+        assert_eq!(indices, (0..=31).collect::<Vec<usize>>());
+        for body in bodies.iter() {
+            assert_round_trips(body);
+        }
+    }
+
+    fn make_placed_art_object(i: i64) -> PlacedArtObject {
+        PlacedArtObject {
+            object_id: ArtObjectId::Met(436_000 + i),
+            artist: "Vincent van Gogh".into(),
+            medium: "Oil on canvas".into(),
+            title: format!("Wheat Field with Cypresses #{i}"),
+            date: "1889".into(),
+            width: 0.73,
+            height: 0.93,
+            x: i as f64 * 1.25,
+            y: 1.5,
+            collection: "The Metropolitan Museum of Art".into(),
+            artist_qid: Some(5582),
+            highlight: i % 5 == 0,
+            rotated: false,
+            frame_style: FrameStyle::Default,
+            classification: "Paintings".into(),
+        }
+    }
+
+    /// Makes sure every variant has a sample in `sample_response_bodies()`.
+    fn response_variant_index(body: &ResponseBody) -> usize {
+        match body {
+            ResponseBody::ArtObjectsForGalleryWall(_) => 0,
+            ResponseBody::Image { .. } => 1,
+            ResponseBody::DecodedImage { .. } => 2,
+            ResponseBody::Artist(_) => 3,
+            ResponseBody::ArtObjectMoved { .. } => 4,
+            ResponseBody::MoveRejected(_) => 5,
+            ResponseBody::ArtObjectPlacement(_) => 6,
+            ResponseBody::Maintenance(_) => 7,
+            ResponseBody::Empty => 8,
+            ResponseBody::Integer(_) => 9,
+            ResponseBody::String(_) => 10,
+            ResponseBody::Error(_) => 11,
+        }
+    }
+
+    fn sample_response_bodies() -> Vec<ResponseBody> {
+        vec![
+            ResponseBody::ArtObjectsForGalleryWall(vec![
+                make_placed_art_object(1),
+                make_placed_art_object(2),
+            ]),
+            ResponseBody::Image {
+                path: Some(PathBuf::from("met/1-small.jpg")),
+                pixel_width: Some(500),
+                pixel_height: None,
+            },
+            ResponseBody::DecodedImage {
+                width: 2,
+                height: 1,
+                rgb8: vec![255, 0, 0, 0, 255, 0],
+            },
+            ResponseBody::Artist(Some(ArtistRecord {
+                qid: 5582,
+                name: "Vincent van Gogh".into(),
+                description: "Dutch painter".into(),
+            })),
+            ResponseBody::ArtObjectMoved { x: 2.5, y: 1.5 },
+            ResponseBody::MoveRejected("It overlaps another art object.".into()),
+            ResponseBody::ArtObjectPlacement(Some(LayoutRecord {
+                gallery_id: -1,
+                wall_id: "wall_a".into(),
+                art_object_id: ArtObjectId::Met(1),
+                x: 1.5,
+                y: 2.25,
+                rotated: true,
+            })),
+            ResponseBody::Maintenance(MaintenanceReport {
+                db_size_before: Some(1024),
+                db_size_after: None,
+                wal_size_before: Some(0),
+                wal_size_after: None,
+                vacuumed: true,
+            }),
+            ResponseBody::Empty,
+            ResponseBody::Integer(-5),
+            ResponseBody::String("{\"boop\": true}".into()),
+            ResponseBody::Error("Oof".into()),
+        ]
+    }
+
+    #[test]
+    fn test_every_response_body_round_trips() {
+        let bodies = sample_response_bodies();
+        let mut indices: Vec<usize> = bodies.iter().map(response_variant_index).collect();
+        indices.dedup();
+        assert_eq!(indices, (0..=11).collect::<Vec<usize>>());
+        for body in bodies.iter() {
+            assert_round_trips(body);
+        }
+    }
+
+    #[test]
+    fn test_binary_wall_responses_are_smaller() {
+        let body =
+            ResponseBody::ArtObjectsForGalleryWall((0..50).map(make_placed_art_object).collect());
+        let json_len = wrap_in_envelope(&body).unwrap().len();
+        let binary_len = wrap_in_binary_envelope(&body).unwrap().len();
+        assert!(
+            binary_len * 2 < json_len,
+            "binary is {binary_len} bytes, JSON is {json_len} bytes"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use gallery::{
+    art_object::ArtObjectId,
+    gallery_cache::{GalleryCache, HttpTransport},
+    gallery_db::{get_default_gallery_db_filename, ArtObjectRecord, GalleryDb},
+    image::ImageCacheOptions,
+    medium::MediumCategory,
+};
+
+use crate::worker_thread::{
+    work_thread, LayoutSummary, MessageFromWorker, MessageToWorker, Request, RequestBody, Response,
+    ResponseBody,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The save slot that test workers use unless told otherwise.
+pub const TEST_SLOT: &str = "user";
+
+pub fn make_art_object_record(object_id: ArtObjectId, title: &str) -> ArtObjectRecord {
+    ArtObjectRecord {
+        object_id,
+        object_date: "1990".to_string(),
+        culture: "".to_string(),
+        artist: "boop".to_string(),
+        artist_role: String::default(),
+        title: title.to_string(),
+        medium: "Oil on canvas".to_string(),
+        medium_category: MediumCategory::Oil,
+        width: 1.0,
+        height: 1.0,
+        fallback_wikidata_qid: None,
+        filename: "".to_string(),
+        collection: "Martian Museum of Art".to_string(),
+        artist_qid: None,
+        highlight: false,
+        tags: String::default(),
+        classification: String::default(),
+        source_gallery: None,
+    }
+}
+
+/// Creates a fresh temporary root directory containing a database with
+/// the given art objects.
+pub fn create_root_dir_with_art_objects(name: &str, records: Vec<ArtObjectRecord>) -> PathBuf {
+    let root_dir =
+        std::env::temp_dir().join(format!("gallery-worker-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root_dir);
+    std::fs::create_dir_all(&root_dir).unwrap();
+    let mut db = GalleryDb::open(root_dir.join(get_default_gallery_db_filename()), false).unwrap();
+    db.reset_art_objects_table().unwrap();
+    db.reset_layout_table().unwrap();
+    db.add_art_objects(&records).unwrap();
+    db.rebuild_collection_stats().unwrap();
+    root_dir
+}
+
+/// Creates a fresh temporary root directory containing a database with a
+/// single 1x1 meter painting, Met object 1.
+pub fn create_root_dir_with_db(name: &str) -> PathBuf {
+    create_root_dir_with_art_objects(
+        name,
+        vec![make_art_object_record(
+            ArtObjectId::Met(1),
+            "Funky Painting",
+        )],
+    )
+}
+
+/// Returns a port on localhost that nothing is listening on right now.
+pub fn find_free_port() -> u16 {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Parses the response to a `RequestBody::Layout`, panicking if it isn't one.
+pub fn parse_layout_summary(body: ResponseBody) -> LayoutSummary {
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected layout summary response, got {body:?}");
+    };
+    serde_json::from_str(&json_content).unwrap()
+}
+
+/// A transport that responds to every request with a 404, keeping track of how
+/// many requests were made (and to what URLs), so tests can be sure the network
+/// isn't touched.
+#[derive(Clone, Default)]
+pub struct CountingTransport {
+    requests: Arc<AtomicUsize>,
+    urls: Arc<Mutex<Vec<String>>>,
+}
+
+impl CountingTransport {
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// Returns the URLs requested so far, forgetting about them.
+    pub fn take_requested_urls(&self) -> Vec<String> {
+        std::mem::take(&mut *self.urls.lock().unwrap())
+    }
+}
+
+impl HttpTransport for CountingTransport {
+    fn get(&self, url: &str) -> Result<ureq::Response, Box<ureq::Error>> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        self.urls.lock().unwrap().push(url.to_string());
+        Err(Box::new(ureq::Error::Status(
+            404,
+            ureq::Response::new(404, "Not Found", "")?,
+        )))
+    }
+}
+
+/// A transport that slowly responds to every request with something that looks
+/// like a WebP image, letting whoever's listening know when each request starts.
+pub struct SlowImageTransport {
+    started: Mutex<Sender<()>>,
+    delay: Duration,
+}
+
+impl SlowImageTransport {
+    pub fn new(delay: Duration) -> (Self, Receiver<()>) {
+        let (started_tx, started_rx) = channel();
+        let transport = SlowImageTransport {
+            started: Mutex::new(started_tx),
+            delay,
+        };
+        (transport, started_rx)
+    }
+}
+
+impl HttpTransport for SlowImageTransport {
+    fn get(&self, _url: &str) -> Result<ureq::Response, Box<ureq::Error>> {
+        // The test might not care.
+        let _ = self.started.lock().unwrap().send(());
+        thread::sleep(self.delay);
+        Ok("HTTP/1.1 200 OK\r\nContent-Type: image/webp\r\n\r\nRIFFboopWEBP".parse()?)
+    }
+}
+
+/// A worker thread whose cache is offline, so tests never touch the network.
+pub struct TestWorker {
+    to_worker_tx: Sender<MessageToWorker>,
+    from_worker_rx: Receiver<MessageFromWorker>,
+    handle: JoinHandle<anyhow::Result<()>>,
+}
+
+impl TestWorker {
+    pub fn spawn(root_dir: &PathBuf, enable_autosync: bool, read_only: bool) -> Self {
+        Self::spawn_with_slot(root_dir, TEST_SLOT, enable_autosync, read_only)
+    }
+
+    /// Like `spawn()`, but uses the layout of the given save slot.
+    pub fn spawn_with_slot(
+        root_dir: &PathBuf,
+        slot: &str,
+        enable_autosync: bool,
+        read_only: bool,
+    ) -> Self {
+        let (worker, recovery_messages) = Self::spawn_with_cache(
+            GalleryCache::new_offline(root_dir.clone()),
+            slot,
+            enable_autosync,
+            read_only,
+        );
+        assert_eq!(recovery_messages, Vec::<String>::new());
+        worker
+    }
+
+    /// Like `spawn()`, but also returns the messages the worker sent for each
+    /// corrupt database it had to rebuild.
+    pub fn spawn_with_recovery_messages(
+        root_dir: &PathBuf,
+        enable_autosync: bool,
+        read_only: bool,
+    ) -> (Self, Vec<String>) {
+        Self::spawn_with_cache(
+            GalleryCache::new_offline(root_dir.clone()),
+            TEST_SLOT,
+            enable_autosync,
+            read_only,
+        )
+    }
+
+    /// Like `spawn()`, but also serves a preview of the layout on the given port,
+    /// see `PreviewServer`.
+    pub fn spawn_with_preview_server(root_dir: &PathBuf, read_only: bool, port: u16) -> Self {
+        let (worker, recovery_messages) = Self::spawn_with_options(
+            GalleryCache::new_offline(root_dir.clone()),
+            TEST_SLOT,
+            false,
+            read_only,
+            Some(port),
+            ImageCacheOptions::default(),
+        );
+        assert_eq!(recovery_messages, Vec::<String>::new());
+        worker
+    }
+
+    /// Like `spawn_with_recovery_messages()`, but uses the given cache, which
+    /// might not be offline, and the given save slot.
+    pub fn spawn_with_cache(
+        cache: GalleryCache,
+        slot: &str,
+        enable_autosync: bool,
+        read_only: bool,
+    ) -> (Self, Vec<String>) {
+        Self::spawn_with_options(
+            cache,
+            slot,
+            enable_autosync,
+            read_only,
+            None,
+            ImageCacheOptions::default(),
+        )
+    }
+
+    /// Like `spawn_with_cache()`, but with the given default for how fetched
+    /// images are cached.
+    pub fn spawn_with_image_cache_options(
+        cache: GalleryCache,
+        image_cache_options: ImageCacheOptions,
+    ) -> Self {
+        let (worker, recovery_messages) =
+            Self::spawn_with_options(cache, TEST_SLOT, false, false, None, image_cache_options);
+        assert_eq!(recovery_messages, Vec::<String>::new());
+        worker
+    }
+
+    fn spawn_with_options(
+        cache: GalleryCache,
+        slot: &str,
+        enable_autosync: bool,
+        read_only: bool,
+        preview_server_port: Option<u16>,
+        image_cache_options: ImageCacheOptions,
+    ) -> (Self, Vec<String>) {
+        let (to_worker_tx, to_worker_rx) = channel();
+        let (from_worker_tx, from_worker_rx) = channel();
+        let slot = slot.to_string();
+        let handle = thread::spawn(move || {
+            work_thread(
+                cache,
+                slot,
+                enable_autosync,
+                read_only,
+                preview_server_port,
+                image_cache_options,
+                to_worker_rx,
+                from_worker_tx,
+            )
+        });
+        let worker = TestWorker {
+            to_worker_tx,
+            from_worker_rx,
+            handle,
+        };
+        let mut recovery_messages = vec![];
+        let mut message = worker.recv();
+        while let MessageFromWorker::DbRecovered(text) = message {
+            recovery_messages.push(text);
+            message = worker.recv();
+        }
+        assert!(matches!(message, MessageFromWorker::Ready));
+        (worker, recovery_messages)
+    }
+
+    /// Receives the next message from the worker, ignoring heartbeats, which
+    /// can arrive at any time if a test is slow.
+    fn recv(&self) -> MessageFromWorker {
+        loop {
+            match self.from_worker_rx.recv_timeout(TIMEOUT).unwrap() {
+                MessageFromWorker::Heartbeat => continue,
+                message => return message,
+            }
+        }
+    }
+
+    pub fn send_request(&self, request_id: u32, body: RequestBody) -> ResponseBody {
+        self.send_request_without_waiting(request_id, body);
+        self.recv_response(request_id)
+    }
+
+    /// Like `send_request()`, but doesn't wait for the response, which can be
+    /// received with `recv_response()`.
+    pub fn send_request_without_waiting(&self, request_id: u32, body: RequestBody) {
+        self.to_worker_tx
+            .send(MessageToWorker::Request(Request {
+                peer_id: None,
+                request_id,
+                body,
+            }))
+            .unwrap();
+    }
+
+    pub fn recv_response(&self, request_id: u32) -> ResponseBody {
+        match self.recv() {
+            MessageFromWorker::Response(response) => {
+                assert_eq!(response.request_id, request_id);
+                response.body
+            }
+            MessageFromWorker::FatalError(err) => panic!("worker errored: {err}"),
+            MessageFromWorker::Done => panic!("worker finished prematurely"),
+            MessageFromWorker::Ready => panic!("worker sent ready more than once"),
+            MessageFromWorker::DbRecovered(_) => panic!("worker recovered DB after ready"),
+            MessageFromWorker::Heartbeat => unreachable!(),
+        }
+    }
+
+    pub fn set_offline(&self, offline: bool) {
+        self.to_worker_tx
+            .send(MessageToWorker::SetOffline(offline))
+            .unwrap();
+    }
+
+    /// Tell the worker to end, and make sure it finishes cleanly.
+    pub fn end(self) {
+        self.to_worker_tx.send(MessageToWorker::End).unwrap();
+        assert!(matches!(self.recv(), MessageFromWorker::Done));
+        self.handle.join().unwrap().unwrap();
+    }
+
+    /// Like `end()`, but for when requests are still in progress, returning the
+    /// responses the worker sent before it finished.
+    pub fn end_while_busy(self) -> Vec<Response> {
+        self.to_worker_tx.send(MessageToWorker::End).unwrap();
+        let mut responses = vec![];
+        loop {
+            match self.recv() {
+                MessageFromWorker::Response(response) => responses.push(response),
+                MessageFromWorker::Done => break,
+                MessageFromWorker::FatalError(err) => panic!("worker errored: {err}"),
+                _ => panic!("worker sent unexpected message while ending"),
+            }
+        }
+        self.handle.join().unwrap().unwrap();
+        responses
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use gallery::{
+    art_object::PlacedArtObject,
+    gallery_db::ArtObjectLayoutInfo,
+    gallery_wall::GalleryWall,
+    placement::{check_placement, PlacementCheck, PlacementMargins},
+};
+
+use crate::worker_thread::ResponseBody;
+
+type WallKey = (i64, String);
+
+/// Keeps the art objects on each wall as of the last time the wall was
+/// fetched, so that placements on it can be checked without a round trip to
+/// the worker, e.g. whenever the mouse moves while a painting is dragged.
+#[derive(Default)]
+pub struct WallContentsCache {
+    /// The size each art object is hung at, and where its center is.
+    walls: HashMap<WallKey, Vec<(ArtObjectLayoutInfo, (f64, f64))>>,
+    /// Wall fetches the worker hasn't responded to yet, keyed by request ID.
+    pending: HashMap<u32, WallKey>,
+}
+
+fn to_layout_info(object: &PlacedArtObject) -> (ArtObjectLayoutInfo, (f64, f64)) {
+    let (width, height) = if object.rotated {
+        (object.height, object.width)
+    } else {
+        (object.width, object.height)
+    };
+    let info = ArtObjectLayoutInfo {
+        id: object.object_id,
+        width,
+        height,
+        highlight: object.highlight,
+        collection: object.collection.clone(),
+    };
+    (info, (object.x, object.y))
+}
+
+impl WallContentsCache {
+    pub fn on_request_sent(&mut self, request_id: u32, gallery_id: i64, wall_id: String) {
+        self.pending.insert(request_id, (gallery_id, wall_id));
+    }
+
+    pub fn on_response(&mut self, request_id: u32, body: &ResponseBody) {
+        let Some(key) = self.pending.remove(&request_id) else {
+            return;
+        };
+        if let ResponseBody::ArtObjectsForGalleryWall(objects) = body {
+            let contents = objects.iter().map(to_layout_info).collect();
+            self.walls.insert(key, contents);
+        }
+    }
+
+    /// Like `check_placement()`, using the wall's cached contents. Walls that
+    /// haven't been fetched are treated as empty.
+    pub fn check_placement(
+        &self,
+        gallery_id: i64,
+        wall: &GalleryWall,
+        candidate: &ArtObjectLayoutInfo,
+        x: f64,
+        y: f64,
+        margins: PlacementMargins,
+    ) -> PlacementCheck {
+        let existing = self
+            .walls
+            .get(&(gallery_id, wall.name.clone()))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        check_placement(wall, existing, candidate, x, y, margins)
+    }
+
+    /// Forgets the contents of every wall, e.g. because there's a new layout.
+    pub fn clear(&mut self) {
+        self.walls.clear();
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gallery::{
+        art_object::{ArtObjectId, PlacedArtObject},
+        gallery_db::ArtObjectLayoutInfo,
+        gallery_wall::GalleryWall,
+        placement::PlacementCheck,
+    };
+
+    use crate::worker_thread::ResponseBody;
+
+    use super::WallContentsCache;
+
+    fn wall() -> GalleryWall {
+        GalleryWall {
+            name: "wall_a".to_string(),
+            width: 5.0,
+            height: 3.0,
+            index: None,
+        }
+    }
+
+    fn placed(id: i64, x: f64, rotated: bool) -> PlacedArtObject {
+        PlacedArtObject {
+            object_id: ArtObjectId::Met(id),
+            artist: String::new(),
+            medium: String::new(),
+            title: String::new(),
+            date: String::new(),
+            width: 2.0,
+            height: 1.0,
+            x,
+            y: 1.5,
+            collection: String::new(),
+            artist_qid: None,
+            highlight: false,
+            rotated,
+            frame_style: Default::default(),
+            classification: String::new(),
+        }
+    }
+
+    fn candidate() -> ArtObjectLayoutInfo {
+        ArtObjectLayoutInfo {
+            id: ArtObjectId::Met(1),
+            width: 1.0,
+            height: 1.0,
+            highlight: false,
+            collection: String::new(),
+        }
+    }
+
+    fn check(cache: &WallContentsCache, gallery_id: i64, x: f64) -> PlacementCheck {
+        cache.check_placement(
+            gallery_id,
+            &wall(),
+            &candidate(),
+            x,
+            1.5,
+            Default::default(),
+        )
+    }
+
+    #[test]
+    fn test_placements_are_checked_against_fetched_walls() {
+        let mut cache = WallContentsCache::default();
+        assert_eq!(check(&cache, 1, 1.0), PlacementCheck::Ok);
+
+        cache.on_request_sent(1, 1, "wall_a".to_string());
+        cache.on_response(
+            1,
+            &ResponseBody::ArtObjectsForGalleryWall(vec![placed(2, 1.0, false)]),
+        );
+        assert_eq!(
+            check(&cache, 1, 1.0),
+            PlacementCheck::Overlaps {
+                ids: vec![ArtObjectId::Met(2)]
+            }
+        );
+        // Other galleries aren't affected.
+        assert_eq!(check(&cache, 2, 1.0), PlacementCheck::Ok);
+
+        // Fetching the wall again replaces what was cached.
+        cache.on_request_sent(2, 1, "wall_a".to_string());
+        cache.on_response(2, &ResponseBody::ArtObjectsForGalleryWall(vec![]));
+        assert_eq!(check(&cache, 1, 1.0), PlacementCheck::Ok);
+    }
+
+    #[test]
+    fn test_rotated_art_objects_are_checked_as_hung() {
+        let mut cache = WallContentsCache::default();
+        cache.on_request_sent(1, 1, "wall_a".to_string());
+        cache.on_response(
+            1,
+            &ResponseBody::ArtObjectsForGalleryWall(vec![placed(2, 1.0, true)]),
+        );
+        // Hung upright, it'd reach all the way to x=2.0.
+        assert_eq!(check(&cache, 1, 2.0), PlacementCheck::Ok);
+    }
+
+    #[test]
+    fn test_unrelated_responses_are_ignored() {
+        let mut cache = WallContentsCache::default();
+        let objects = ResponseBody::ArtObjectsForGalleryWall(vec![placed(2, 1.0, false)]);
+        cache.on_response(1, &objects);
+        cache.on_request_sent(2, 1, "wall_a".to_string());
+        cache.on_response(2, &ResponseBody::Error("Oof".into()));
+        cache.on_response(2, &objects);
+        assert_eq!(check(&cache, 1, 1.0), PlacementCheck::Ok);
+
+        cache.on_request_sent(3, 1, "wall_a".to_string());
+        cache.clear();
+        cache.on_response(3, &objects);
+        assert_eq!(check(&cache, 1, 1.0), PlacementCheck::Ok);
+    }
+}
@@ -0,0 +1,2382 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{mpsc::channel, Arc},
+    time::Duration,
+};
+
+use gallery::{
+    art_object::{ArtObjectId, PlacedArtObject},
+    cache_usage::{CacheUsageReport, DB_FILES_CATEGORY},
+    gallery_cache::GalleryCache,
+    gallery_db::{
+        get_default_gallery_db_filename, get_layout_db_filename, ArtObjectRecord, CollectionRecord,
+        GalleryDb, GalleryObjectCount, LayoutRecord, SavedFilterRecord, TimestampedLayoutRecord,
+        LATEST_GALLERY_DB_VERSION,
+    },
+    image::{ImageCacheOptions, ImageFormat, ImageSize},
+    layout::{FreeRegion, LayoutEstimate, LayoutMode, LayoutSegment},
+    met_api::{met_image_cache_path, MetImageUrls},
+    shutdown::ShutdownSignal,
+    wikidata::wikidata_image_cache_path,
+};
+use rusqlite::Connection;
+
+use crate::{
+    preview_server::{PreviewGallery, PreviewLayoutRecord},
+    test_worker::{
+        create_root_dir_with_art_objects, create_root_dir_with_db, find_free_port,
+        make_art_object_record, parse_layout_summary, CountingTransport, SlowImageTransport,
+        TestWorker, TEST_SLOT,
+    },
+    worker_thread::{
+        get_autosync_gallery_path, work_thread, CacheInfo, CachedImageState, DistinctValue,
+        ImportConflictPolicy, ImportSummary, MergeSummary, PlacedSearchResultSummary,
+        ReadinessReport, RelatedArtObject, RelatedArtObjectsSummary, RequestBody, ResponseBody,
+        RestoredPosition, UndoneMoveSummary,
+    },
+};
+
+const WALLS_JSON: &str = r#"[
+    {"name": "wall_a", "width": 5.0, "height": 3.0},
+    {"name": "wall_b", "width": 5.0, "height": 3.0}
+]"#;
+
+const MONKEY_ID: ArtObjectId = ArtObjectId::Wikidata(3);
+
+/// Where the small image of the monkey painting is cached, so we can pre-seed
+/// it and avoid hitting the network.
+const MONKEY_SMALL_IMAGE_FILENAME: &str = "wikidata/Q3-small-500px.jpg";
+
+fn get_wall(worker: &TestWorker, request_id: u32, gallery_id: i64) -> Vec<PlacedArtObject> {
+    let body = worker.send_request(
+        request_id,
+        RequestBody::GetArtObjectsForGalleryWall {
+            gallery_id,
+            wall_id: "wall_a".to_string(),
+        },
+    );
+    let ResponseBody::ArtObjectsForGalleryWall(objects) = body else {
+        panic!("expected art objects response, got {body:?}");
+    };
+    objects
+}
+
+fn parse_import_summary(body: ResponseBody) -> ImportSummary {
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected import summary response, got {body:?}");
+    };
+    serde_json::from_str(&json_content).unwrap()
+}
+
+/// Parses the response to a `RequestBody::ExportNonPositiveLayout` without
+/// metadata, which is an autosync.
+fn parse_exported_records(body: ResponseBody) -> Vec<TimestampedLayoutRecord> {
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    let autosync: serde_json::Value = serde_json::from_str(&json_content).unwrap();
+    assert_eq!(autosync["version"], 2);
+    serde_json::from_value(autosync["records"].clone()).unwrap()
+}
+
+#[test]
+fn test_worker_handles_full_request_surface() {
+    let root_dir = create_root_dir_with_art_objects(
+        "integration",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Boring Painting"),
+            ArtObjectRecord {
+                filename: "Funky Monkey.jpg".to_string(),
+                ..make_art_object_record(MONKEY_ID, "Funky Monkey")
+            },
+        ],
+    );
+    let cache = GalleryCache::new_offline(root_dir.clone());
+    let image_path = cache.get_cached_path(MONKEY_SMALL_IMAGE_FILENAME);
+    std::fs::create_dir_all(image_path.parent().unwrap()).unwrap();
+    image::RgbImage::new(4, 3).save(&image_path).unwrap();
+
+    let worker = TestWorker::spawn(&root_dir, false, false);
+
+    let body = worker.send_request(1, RequestBody::Migrate);
+    assert!(matches!(body, ResponseBody::Empty), "{body:?}");
+
+    let body = worker.send_request(
+        2,
+        RequestBody::Layout {
+            walls_json: WALLS_JSON.to_string(),
+            wall_sets_json: None,
+            filter: None,
+            dense: false,
+            mode: None,
+            ordering_json: None,
+            reserved_walls: vec!["wall_b".to_string()],
+            segments: vec![],
+            featured_first: false,
+            allow_rotation: false,
+            balance_by_collection: None,
+            collect_free_regions: false,
+            min_free_region_width: None,
+            exclude_previously_displayed: false,
+            gallery_id_range: None,
+        },
+    );
+    let summary = parse_layout_summary(body);
+    assert_eq!(summary.unplaceable, 0);
+    assert!(summary.galleries_created > 0);
+    assert_eq!(summary.first_gallery_id, Some(1));
+    assert_eq!(
+        summary.last_gallery_id,
+        Some(summary.galleries_created as i64)
+    );
+
+    // The count should match the galleries that actually have art in them.
+    let body = worker.send_request(21, RequestBody::GetGalleryGraph);
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    let galleries: Vec<GalleryObjectCount> = serde_json::from_str(&json_content).unwrap();
+    assert_eq!(galleries.len(), summary.galleries_created);
+    let body = worker.send_request(22, RequestBody::CountGalleries);
+    let ResponseBody::Integer(count) = body else {
+        panic!("expected integer response, got {body:?}");
+    };
+    assert_eq!(count as usize, galleries.len());
+
+    let body = worker.send_request(3, RequestBody::CountArtObjects { filter: None });
+    assert!(matches!(body, ResponseBody::Integer(3)), "{body:?}");
+
+    let body = worker.send_request(
+        4,
+        RequestBody::CountArtObjects {
+            filter: Some("funky".to_string()),
+        },
+    );
+    assert!(matches!(body, ResponseBody::Integer(2)), "{body:?}");
+
+    let objects = get_wall(&worker, 5, 1);
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].object_id, ArtObjectId::Met(1));
+    assert_eq!(objects[0].title, "Funky Painting");
+
+    let body = worker.send_request(
+        6,
+        RequestBody::MoveArtObject {
+            art_object_id: ArtObjectId::Met(1),
+            gallery_id: -1,
+            wall_id: "wall_a".to_string(),
+            x: 2.5,
+            y: 1.5,
+            strict: true,
+        },
+    );
+    let ResponseBody::ArtObjectMoved { x, y } = body else {
+        panic!("expected art object moved response, got {body:?}");
+    };
+    assert_eq!((x, y), (2.5, 1.5));
+
+    let body = worker.send_request(
+        7,
+        RequestBody::ExportNonPositiveLayout {
+            include_metadata: false,
+        },
+    );
+    let mut records: Vec<LayoutRecord<String>> = parse_exported_records(body)
+        .into_iter()
+        .map(|timestamped| timestamped.record)
+        .collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].art_object_id, ArtObjectId::Met(1));
+    records[0].x = 1.0;
+
+    let body = worker.send_request(
+        8,
+        RequestBody::ImportNonPositiveLayout {
+            json_content: serde_json::to_string(&records).unwrap(),
+            conflict_policy: ImportConflictPolicy::Skip,
+        },
+    );
+    assert_eq!(
+        parse_import_summary(body),
+        ImportSummary {
+            imported: 1,
+            skipped: 0,
+            unknown_art_objects: vec![],
+            conflicted: 0,
+            rejected: vec![],
+        }
+    );
+
+    let objects = get_wall(&worker, 9, -1);
+    assert_eq!(objects.len(), 1);
+    assert_eq!((objects[0].x, objects[0].y), (1.0, 1.5));
+
+    let body = worker.send_request(
+        10,
+        RequestBody::FetchImage {
+            object_id: MONKEY_ID,
+            size: ImageSize::Small,
+            decode: false,
+            format: None,
+        },
+    );
+    let ResponseBody::Image {
+        path,
+        pixel_width,
+        pixel_height,
+    } = body
+    else {
+        panic!("expected image response, got {body:?}");
+    };
+    assert_eq!(path, Some(image_path));
+    assert_eq!((pixel_width, pixel_height), (Some(4), Some(3)));
+
+    let body = worker.send_request(
+        11,
+        RequestBody::FetchImage {
+            object_id: MONKEY_ID,
+            size: ImageSize::Small,
+            decode: true,
+            format: None,
+        },
+    );
+    let ResponseBody::DecodedImage {
+        width,
+        height,
+        rgb8,
+    } = body
+    else {
+        panic!("expected decoded image response, got {body:?}");
+    };
+    assert_eq!((width, height), (4, 3));
+    assert_eq!(rgb8.len(), 4 * 3 * 3);
+
+    // This isn't cached, and the worker is offline, so there's no image.
+    let body = worker.send_request(
+        12,
+        RequestBody::FetchImage {
+            object_id: ArtObjectId::Met(2),
+            size: ImageSize::Small,
+            decode: false,
+            format: None,
+        },
+    );
+    assert!(
+        matches!(body, ResponseBody::Image { path: None, .. }),
+        "{body:?}"
+    );
+
+    let body = worker.send_request(13, RequestBody::ListCollections);
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    let collections: Vec<CollectionRecord> = serde_json::from_str(&json_content).unwrap();
+    assert_eq!(collections.len(), 1);
+    assert_eq!(collections[0].object_count, 3);
+
+    let body = worker.send_request(
+        14,
+        RequestBody::SaveFilter {
+            name: "funky_ones".to_string(),
+            filter: "funky".to_string(),
+        },
+    );
+    assert!(matches!(body, ResponseBody::Empty), "{body:?}");
+
+    let body = worker.send_request(
+        15,
+        RequestBody::SaveFilter {
+            name: "loop".to_string(),
+            filter: "@loop".to_string(),
+        },
+    );
+    assert!(matches!(body, ResponseBody::Error(_)), "{body:?}");
+
+    let body = worker.send_request(
+        16,
+        RequestBody::CountArtObjects {
+            filter: Some("@funky_ones -monkey".to_string()),
+        },
+    );
+    assert!(matches!(body, ResponseBody::Integer(1)), "{body:?}");
+
+    // Referring to a nonexistent saved filter shouldn't kill the worker.
+    let body = worker.send_request(
+        17,
+        RequestBody::CountArtObjects {
+            filter: Some("@loop".to_string()),
+        },
+    );
+    assert!(matches!(body, ResponseBody::Error(_)), "{body:?}");
+
+    let body = worker.send_request(18, RequestBody::ListFilters);
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    let filters: Vec<SavedFilterRecord> = serde_json::from_str(&json_content).unwrap();
+    assert_eq!(filters.len(), 1);
+    assert_eq!(filters[0].name, "funky_ones");
+
+    let body = worker.send_request(
+        19,
+        RequestBody::DeleteFilter {
+            name: "funky_ones".to_string(),
+        },
+    );
+    assert!(matches!(body, ResponseBody::Integer(1)), "{body:?}");
+
+    let body = worker.send_request(20, RequestBody::GetGalleryReservedWalls { gallery_id: 1 });
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    let reserved_walls: Vec<String> = serde_json::from_str(&json_content).unwrap();
+    assert_eq!(reserved_walls, vec!["wall_b".to_string()]);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_recovers_from_corrupt_db() {
+    let root_dir = create_root_dir_with_db("corrupt");
+    let db_path = root_dir.join(get_default_gallery_db_filename());
+    std::fs::write(&db_path, b"this is definitely not a sqlite database").unwrap();
+
+    let (worker, recovery_messages) =
+        TestWorker::spawn_with_recovery_messages(&root_dir, false, false);
+    assert_eq!(recovery_messages.len(), 1, "{recovery_messages:?}");
+    assert!(
+        recovery_messages[0].contains("re-run the importer"),
+        "{recovery_messages:?}"
+    );
+
+    let body = worker.send_request(1, RequestBody::CountArtObjects { filter: None });
+    assert!(matches!(body, ResponseBody::Integer(0)), "{body:?}");
+    worker.end();
+
+    // The corrupt DB should have been set aside rather than deleted.
+    let quarantined: Vec<_> = std::fs::read_dir(&root_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.contains(".corrupt-"))
+        .collect();
+    assert_eq!(quarantined.len(), 1, "{quarantined:?}");
+
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_recovers_from_corrupt_layout_db() {
+    let root_dir = create_root_dir_with_db("corrupt-layout");
+    TestWorker::spawn(&root_dir, false, false).end();
+    let layout_db_path = root_dir.join(get_layout_db_filename(TEST_SLOT));
+    assert!(layout_db_path.exists());
+    std::fs::write(&layout_db_path, b"this is definitely not a sqlite database").unwrap();
+
+    let (worker, recovery_messages) =
+        TestWorker::spawn_with_recovery_messages(&root_dir, false, false);
+    assert_eq!(recovery_messages.len(), 1, "{recovery_messages:?}");
+    assert!(
+        recovery_messages[0].contains("layout database was corrupt"),
+        "{recovery_messages:?}"
+    );
+
+    // The art in the main database should be untouched.
+    let body = worker.send_request(1, RequestBody::CountArtObjects { filter: None });
+    assert!(matches!(body, ResponseBody::Integer(1)), "{body:?}");
+    worker.end();
+
+    let quarantined: Vec<_> = std::fs::read_dir(&root_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.contains(".corrupt-"))
+        .collect();
+    assert_eq!(quarantined.len(), 1, "{quarantined:?}");
+    assert!(
+        quarantined[0].starts_with(&get_layout_db_filename(TEST_SLOT)),
+        "{quarantined:?}"
+    );
+
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_refuses_db_with_mismatched_schema() {
+    let root_dir = create_root_dir_with_db("mismatched-schema");
+    Connection::open(root_dir.join(get_default_gallery_db_filename()))
+        .unwrap()
+        .execute_batch("ALTER TABLE art_objects DROP COLUMN collection")
+        .unwrap();
+
+    let (_to_worker_tx, to_worker_rx) = channel();
+    let (from_worker_tx, _from_worker_rx) = channel();
+    let err = work_thread(
+        GalleryCache::new_offline(root_dir.clone()),
+        TEST_SLOT.to_string(),
+        false,
+        false,
+        None,
+        ImageCacheOptions::default(),
+        to_worker_rx,
+        from_worker_tx,
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string().starts_with(
+            "database schema is missing column art_objects.collection — re-run the importer or Migrate"
+        ),
+        "{err}"
+    );
+
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_offline_mode_only_uses_cache() {
+    let root_dir = create_root_dir_with_art_objects(
+        "offline",
+        vec![
+            make_art_object_record(ArtObjectId::Met(2), "Boring Painting"),
+            ArtObjectRecord {
+                filename: "Funky Monkey.jpg".to_string(),
+                ..make_art_object_record(MONKEY_ID, "Funky Monkey")
+            },
+        ],
+    );
+    let transport = CountingTransport::default();
+    let cache = GalleryCache::with_transport(root_dir.clone(), Box::new(transport.clone()));
+    let image_path = cache.get_cached_path(MONKEY_SMALL_IMAGE_FILENAME);
+    std::fs::create_dir_all(image_path.parent().unwrap()).unwrap();
+    image::RgbImage::new(4, 3).save(&image_path).unwrap();
+    let fetch_image = |object_id| RequestBody::FetchImage {
+        object_id,
+        size: ImageSize::Small,
+        decode: false,
+        format: None,
+    };
+
+    let (worker, _) = TestWorker::spawn_with_cache(cache, TEST_SLOT, false, false);
+
+    // Make sure the transport actually gets used when we're online.
+    let body = worker.send_request(1, fetch_image(ArtObjectId::Met(2)));
+    assert!(
+        matches!(body, ResponseBody::Image { path: None, .. }),
+        "{body:?}"
+    );
+    let online_requests = transport.requests();
+    assert!(online_requests > 0);
+
+    worker.set_offline(true);
+    let body = worker.send_request(2, fetch_image(ArtObjectId::Met(2)));
+    assert!(
+        matches!(body, ResponseBody::Image { path: None, .. }),
+        "{body:?}"
+    );
+    let body = worker.send_request(3, fetch_image(MONKEY_ID));
+    let ResponseBody::Image { path, .. } = body else {
+        panic!("expected image response, got {body:?}");
+    };
+    assert_eq!(path, Some(image_path));
+    assert_eq!(transport.requests(), online_requests);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_prefers_met_image_urls_in_db() {
+    const SMALL_IMAGE_URL: &str = "https://images.metmuseum.org/web-large/boop.jpg";
+    let root_dir = create_root_dir_with_art_objects(
+        "met-image-urls",
+        vec![
+            make_art_object_record(ArtObjectId::Met(2), "Enriched Painting"),
+            make_art_object_record(ArtObjectId::Met(3), "Unenriched Painting"),
+            make_art_object_record(ArtObjectId::Met(4), "Cached Painting"),
+        ],
+    );
+    let mut db = GalleryDb::open(root_dir.join(get_default_gallery_db_filename()), false).unwrap();
+    db.set_met_image_urls(&[
+        (
+            2,
+            MetImageUrls {
+                primary_image: "https://images.metmuseum.org/original/boop.jpg".into(),
+                primary_image_small: SMALL_IMAGE_URL.into(),
+            },
+        ),
+        (
+            4,
+            MetImageUrls {
+                primary_image: "".into(),
+                primary_image_small: "https://images.metmuseum.org/web-large/cached.jpg".into(),
+            },
+        ),
+    ])
+    .unwrap();
+    drop(db);
+    let transport = CountingTransport::default();
+    let cache = GalleryCache::with_transport(root_dir.clone(), Box::new(transport.clone()));
+    let cached_image_path = cache.get_cached_path("met-api/object-4-small.jpg");
+    std::fs::create_dir_all(cached_image_path.parent().unwrap()).unwrap();
+    image::RgbImage::new(4, 3).save(&cached_image_path).unwrap();
+    let fetch_image = |object_id, size| RequestBody::FetchImage {
+        object_id,
+        size,
+        decode: false,
+        format: None,
+    };
+
+    let (worker, _) = TestWorker::spawn_with_cache(cache, TEST_SLOT, false, false);
+
+    // The image is fetched straight from the stored URL, without ever asking
+    // the Met API for the object's record.
+    worker.send_request(1, fetch_image(ArtObjectId::Met(2), ImageSize::Small));
+    let urls = transport.take_requested_urls();
+    assert!(!urls.is_empty());
+    assert!(urls.iter().all(|url| url == SMALL_IMAGE_URL), "{urls:?}");
+
+    // An empty URL means the Met doesn't have that image, so there's nothing
+    // to fetch at all.
+    let body = worker.send_request(2, fetch_image(ArtObjectId::Met(4), ImageSize::Large));
+    assert!(
+        matches!(body, ResponseBody::Image { path: None, .. }),
+        "{body:?}"
+    );
+    assert_eq!(transport.take_requested_urls(), Vec::<String>::new());
+
+    // Objects whose URLs aren't stored fall back to the Met API.
+    worker.send_request(3, fetch_image(ArtObjectId::Met(3), ImageSize::Small));
+    let urls = transport.take_requested_urls();
+    assert_eq!(
+        urls.first().map(String::as_str),
+        Some("https://collectionapi.metmuseum.org/public/collection/v1/objects/3")
+    );
+
+    // Stored URLs also let us find cached images when offline.
+    worker.set_offline(true);
+    let body = worker.send_request(4, fetch_image(ArtObjectId::Met(4), ImageSize::Small));
+    let ResponseBody::Image { path, .. } = body else {
+        panic!("expected image response, got {body:?}");
+    };
+    assert_eq!(path, Some(cached_image_path));
+    assert_eq!(transport.take_requested_urls(), Vec::<String>::new());
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_transcodes_images_to_preferred_format() {
+    let root_dir = create_root_dir_with_art_objects(
+        "image-format",
+        vec![
+            make_art_object_record(ArtObjectId::Met(4), "Painting"),
+            make_art_object_record(ArtObjectId::Met(5), "Other Painting"),
+        ],
+    );
+    let mut db = GalleryDb::open(root_dir.join(get_default_gallery_db_filename()), false).unwrap();
+    let urls = |name: &str| MetImageUrls {
+        primary_image: "".into(),
+        primary_image_small: format!("https://images.metmuseum.org/web-large/{name}.jpg"),
+    };
+    db.set_met_image_urls(&[(4, urls("four")), (5, urls("five"))])
+        .unwrap();
+    drop(db);
+    let transport = CountingTransport::default();
+    let make_cache = || GalleryCache::with_transport(root_dir.clone(), Box::new(transport.clone()));
+    let cache = make_cache();
+    for object_id in [4, 5] {
+        let path = cache.get_cached_path(format!("met-api/object-{object_id}-small.jpg"));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image::RgbImage::new(4, 3).save(&path).unwrap();
+    }
+    let fetch_image = |object_id, format| RequestBody::FetchImage {
+        object_id,
+        size: ImageSize::Small,
+        decode: false,
+        format,
+    };
+    let expect_image = |body: ResponseBody, filename: &str| {
+        let ResponseBody::Image {
+            path,
+            pixel_width,
+            pixel_height,
+        } = body
+        else {
+            panic!("expected image response, got {body:?}");
+        };
+        assert_eq!(path, Some(cache.get_cached_path(filename)));
+        assert_eq!((pixel_width, pixel_height), (Some(4), Some(3)));
+    };
+
+    let (worker, _) = TestWorker::spawn_with_cache(make_cache(), TEST_SLOT, false, false);
+    expect_image(
+        worker.send_request(1, fetch_image(ArtObjectId::Met(4), None)),
+        "met-api/object-4-small.jpg",
+    );
+    expect_image(
+        worker.send_request(2, fetch_image(ArtObjectId::Met(5), Some(ImageFormat::Webp))),
+        "met-api/object-5-small.webp",
+    );
+    assert!(cache.get_if_cached("met-api/object-5-small.jpg").is_none());
+    worker.end();
+
+    let worker = TestWorker::spawn_with_image_cache_options(
+        make_cache(),
+        ImageCacheOptions {
+            preferred_format: Some(ImageFormat::Webp),
+            keep_original: true,
+        },
+    );
+    expect_image(
+        worker.send_request(1, fetch_image(ArtObjectId::Met(4), None)),
+        "met-api/object-4-small.webp",
+    );
+    assert!(cache.get_if_cached("met-api/object-4-small.jpg").is_some());
+    assert_eq!(transport.requests(), 0);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_detects_unknown_walls() {
+    let root_dir = create_root_dir_with_art_objects(
+        "unknown-walls",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Boring Painting"),
+        ],
+    );
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let make_record = |art_object_id, wall_id: &str| LayoutRecord {
+        gallery_id: -1,
+        wall_id: wall_id.to_string(),
+        art_object_id,
+        x: 1.0,
+        y: 1.5,
+        rotated: false,
+    };
+
+    // We don't know about any walls yet, so this can't be caught.
+    let body = worker.send_request(
+        1,
+        RequestBody::MoveArtObject {
+            art_object_id: ArtObjectId::Met(2),
+            gallery_id: -1,
+            wall_id: "wall_O2".to_string(),
+            x: 1.0,
+            y: 1.5,
+            strict: false,
+        },
+    );
+    assert!(
+        matches!(body, ResponseBody::ArtObjectMoved { .. }),
+        "{body:?}"
+    );
+
+    // But once we do, the layout notices.
+    let body = worker.send_request(
+        2,
+        RequestBody::Layout {
+            walls_json: WALLS_JSON.to_string(),
+            wall_sets_json: None,
+            filter: None,
+            dense: false,
+            mode: None,
+            ordering_json: None,
+            reserved_walls: vec![],
+            segments: vec![],
+            featured_first: false,
+            allow_rotation: false,
+            balance_by_collection: None,
+            collect_free_regions: false,
+            min_free_region_width: None,
+            exclude_previously_displayed: false,
+            gallery_id_range: None,
+        },
+    );
+    assert_eq!(parse_layout_summary(body).unknown_wall_records, 1);
+
+    // Imports refer to walls by name or index, and skip any typos.
+    let body = worker.send_request(
+        3,
+        RequestBody::ImportNonPositiveLayout {
+            json_content: serde_json::to_string(&vec![
+                make_record(ArtObjectId::Met(1), "#1"),
+                make_record(ArtObjectId::Met(2), "wall_O2"),
+            ])
+            .unwrap(),
+            conflict_policy: ImportConflictPolicy::Skip,
+        },
+    );
+    assert_eq!(
+        parse_import_summary(body),
+        ImportSummary {
+            imported: 1,
+            skipped: 1,
+            unknown_art_objects: vec![],
+            conflicted: 0,
+            rejected: vec![],
+        }
+    );
+    let body = worker.send_request(
+        4,
+        RequestBody::ExportNonPositiveLayout {
+            include_metadata: false,
+        },
+    );
+    let records = parse_exported_records(body);
+    assert_eq!(records.len(), 1);
+    assert_eq!(
+        records[0].record,
+        make_record(ArtObjectId::Met(1), "wall_b")
+    );
+
+    let body = worker.send_request(
+        5,
+        RequestBody::ImportNonPositiveLayout {
+            json_content: "boop".to_string(),
+            conflict_policy: ImportConflictPolicy::Skip,
+        },
+    );
+    assert!(matches!(body, ResponseBody::Error(_)), "{body:?}");
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_shares_layouts_with_metadata() {
+    let sender_root_dir = create_root_dir_with_art_objects(
+        "share-sender",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            ArtObjectRecord {
+                filename: "Funky Monkey.jpg".to_string(),
+                ..make_art_object_record(MONKEY_ID, "Funky Monkey")
+            },
+        ],
+    );
+    let receiver_root_dir = create_root_dir_with_art_objects(
+        "share-receiver",
+        vec![make_art_object_record(
+            ArtObjectId::Met(1),
+            "Funky Painting",
+        )],
+    );
+    let move_request = |art_object_id, x| RequestBody::MoveArtObject {
+        art_object_id,
+        gallery_id: -1,
+        wall_id: "wall_a".to_string(),
+        x,
+        y: 1.5,
+        strict: false,
+    };
+
+    let sender = TestWorker::spawn(&sender_root_dir, false, false);
+    sender.send_request(1, move_request(ArtObjectId::Met(1), 1.0));
+    sender.send_request(2, move_request(MONKEY_ID, 3.0));
+    let body = sender.send_request(
+        3,
+        RequestBody::ExportNonPositiveLayout {
+            include_metadata: true,
+        },
+    );
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    sender.end();
+
+    let receiver = TestWorker::spawn(&receiver_root_dir, false, false);
+    let body = receiver.send_request(
+        1,
+        RequestBody::ImportNonPositiveLayout {
+            json_content,
+            conflict_policy: ImportConflictPolicy::Skip,
+        },
+    );
+    let summary = parse_import_summary(body);
+    assert_eq!(summary.imported, 1);
+    assert_eq!(summary.skipped, 0);
+    let [unknown] = summary.unknown_art_objects.as_slice() else {
+        panic!("expected one unknown art object, got {summary:?}");
+    };
+    assert_eq!(unknown.id, MONKEY_ID);
+    assert_eq!(unknown.title, "Funky Monkey");
+    assert_eq!(unknown.url, MONKEY_ID.url());
+    assert_eq!(unknown.image_filename.as_deref(), Some("Funky Monkey.jpg"));
+
+    let objects = get_wall(&receiver, 2, -1);
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].object_id, ArtObjectId::Met(1));
+    assert_eq!((objects[0].x, objects[0].y), (1.0, 1.5));
+
+    receiver.end();
+    std::fs::remove_dir_all(&sender_root_dir).unwrap();
+    std::fs::remove_dir_all(&receiver_root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_slots_have_independent_layouts() {
+    let root_dir = create_root_dir_with_db("slots");
+    let move_request = |x| RequestBody::MoveArtObject {
+        art_object_id: ArtObjectId::Met(1),
+        gallery_id: -1,
+        wall_id: "wall_a".to_string(),
+        x,
+        y: 1.5,
+        strict: false,
+    };
+
+    let worker_a = TestWorker::spawn_with_slot(&root_dir, "a", false, false);
+    let worker_b = TestWorker::spawn_with_slot(&root_dir, "b", false, false);
+    let body = worker_a.send_request(1, move_request(1.0));
+    assert!(
+        matches!(body, ResponseBody::ArtObjectMoved { .. }),
+        "{body:?}"
+    );
+    let body = worker_b.send_request(1, move_request(2.0));
+    assert!(
+        matches!(body, ResponseBody::ArtObjectMoved { .. }),
+        "{body:?}"
+    );
+
+    let objects = get_wall(&worker_a, 2, -1);
+    assert_eq!(objects.len(), 1);
+    assert_eq!((objects[0].x, objects[0].y), (1.0, 1.5));
+    let objects = get_wall(&worker_b, 2, -1);
+    assert_eq!(objects.len(), 1);
+    assert_eq!((objects[0].x, objects[0].y), (2.0, 1.5));
+    worker_a.end();
+    worker_b.end();
+
+    // The layouts should persist independently, too.
+    let worker_a = TestWorker::spawn_with_slot(&root_dir, "a", false, false);
+    let objects = get_wall(&worker_a, 1, -1);
+    assert_eq!((objects[0].x, objects[0].y), (1.0, 1.5));
+    worker_a.end();
+
+    let new_slot = TestWorker::spawn_with_slot(&root_dir, "c", false, false);
+    assert_eq!(get_wall(&new_slot, 1, -1).len(), 0);
+    new_slot.end();
+
+    for slot in ["a", "b", "c"] {
+        assert!(root_dir.join(get_layout_db_filename(slot)).exists());
+    }
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_rejects_huge_filters() {
+    let root_dir = create_root_dir_with_db("huge-filters");
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let huge_filter = vec!["funky"; 1000].join(" ");
+
+    let body = worker.send_request(
+        1,
+        RequestBody::CountArtObjects {
+            filter: Some(huge_filter.clone()),
+        },
+    );
+    let ResponseBody::Error(message) = body else {
+        panic!("expected error response, got {body:?}");
+    };
+    assert!(message.contains("characters long"), "{message}");
+
+    let body = worker.send_request(
+        2,
+        RequestBody::Layout {
+            walls_json: WALLS_JSON.to_string(),
+            wall_sets_json: None,
+            filter: Some(vec!["funky"; 100].join(" ")),
+            dense: false,
+            mode: None,
+            ordering_json: None,
+            reserved_walls: vec![],
+            segments: vec![],
+            featured_first: false,
+            allow_rotation: false,
+            balance_by_collection: None,
+            collect_free_regions: false,
+            min_free_region_width: None,
+            exclude_previously_displayed: false,
+            gallery_id_range: None,
+        },
+    );
+    let ResponseBody::Error(message) = body else {
+        panic!("expected error response, got {body:?}");
+    };
+    assert!(message.contains("100 terms"), "{message}");
+
+    // The worker should still be alive and well.
+    let body = worker.send_request(3, RequestBody::CountArtObjects { filter: None });
+    assert!(matches!(body, ResponseBody::Integer(1)), "{body:?}");
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_gets_related_art_objects() {
+    let root_dir = create_root_dir_with_art_objects(
+        "related",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Funkier Painting"),
+            ArtObjectRecord {
+                artist: "someone else".to_string(),
+                ..make_art_object_record(MONKEY_ID, "Funky Monkey")
+            },
+        ],
+    );
+    let worker = TestWorker::spawn(&root_dir, false, false);
+
+    let body = worker.send_request(
+        1,
+        RequestBody::GetRelatedArtObjects {
+            object_id: ArtObjectId::Met(1),
+            limit: 10,
+        },
+    );
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    let related: RelatedArtObjectsSummary = serde_json::from_str(&json_content).unwrap();
+    let ids = |objects: &Vec<RelatedArtObject>| {
+        objects
+            .iter()
+            .map(|object| object.object_id)
+            .collect::<Vec<i64>>()
+    };
+    assert_eq!(ids(&related.same_artist), vec![2]);
+    assert_eq!(
+        ids(&related.same_medium_century),
+        vec![2, MONKEY_ID.to_raw_i64()]
+    );
+
+    worker.end();
+}
+
+#[test]
+fn test_worker_undoes_moves() {
+    let root_dir = create_root_dir_with_db("undo");
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let move_request = |x| RequestBody::MoveArtObject {
+        art_object_id: ArtObjectId::Met(1),
+        gallery_id: -1,
+        wall_id: "wall_a".to_string(),
+        x,
+        y: 1.5,
+        strict: false,
+    };
+    let undo = |request_id| {
+        let body = worker.send_request(request_id, RequestBody::UndoLastMove);
+        let ResponseBody::String(json_content) = body else {
+            panic!("expected string response, got {body:?}");
+        };
+        serde_json::from_str::<Option<UndoneMoveSummary>>(&json_content).unwrap()
+    };
+
+    for (request_id, x) in [(1, 1.0), (2, 2.0)] {
+        let body = worker.send_request(request_id, move_request(x));
+        assert!(
+            matches!(body, ResponseBody::ArtObjectMoved { .. }),
+            "{body:?}"
+        );
+    }
+
+    assert_eq!(
+        undo(3),
+        Some(UndoneMoveSummary {
+            art_object_id: 1,
+            restored: Some(RestoredPosition {
+                gallery_id: -1,
+                wall_id: "wall_a".to_string(),
+                x: 1.0,
+                y: 1.5,
+                rotated: false,
+            }),
+        })
+    );
+    let objects = get_wall(&worker, 4, -1);
+    assert_eq!((objects[0].x, objects[0].y), (1.0, 1.5));
+
+    assert_eq!(
+        undo(5),
+        Some(UndoneMoveSummary {
+            art_object_id: 1,
+            restored: None,
+        })
+    );
+    assert_eq!(get_wall(&worker, 6, -1).len(), 0);
+    assert_eq!(undo(7), None);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_lists_distinct_values() {
+    let root_dir = create_root_dir_with_art_objects(
+        "distinct-values",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Funkier Painting"),
+            ArtObjectRecord {
+                artist: "someone else".to_string(),
+                ..make_art_object_record(MONKEY_ID, "Funky Monkey")
+            },
+        ],
+    );
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let request = |column: &str| RequestBody::DistinctValues {
+        column: column.to_string(),
+        min_count: 1,
+        limit: 10,
+    };
+
+    let body = worker.send_request(1, request("artist"));
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    let values: Vec<DistinctValue> = serde_json::from_str(&json_content).unwrap();
+    assert_eq!(
+        values,
+        vec![
+            DistinctValue {
+                value: "boop".to_string(),
+                count: 2,
+            },
+            DistinctValue {
+                value: "someone else".to_string(),
+                count: 1,
+            },
+        ]
+    );
+
+    let body = worker.send_request(2, request("title"));
+    assert!(matches!(body, ResponseBody::Error(_)), "{body:?}");
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_handles_import_conflicts() {
+    let root_dir = create_root_dir_with_art_objects(
+        "import-conflicts",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Boring Painting"),
+        ],
+    );
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let make_record = |art_object_id| LayoutRecord {
+        gallery_id: -1,
+        wall_id: "wall_a".to_string(),
+        art_object_id,
+        x: 1.0,
+        y: 1.5,
+        rotated: false,
+    };
+    let import = |request_id, art_object_ids: &[ArtObjectId], conflict_policy| {
+        let records: Vec<LayoutRecord<String>> =
+            art_object_ids.iter().copied().map(make_record).collect();
+        worker.send_request(
+            request_id,
+            RequestBody::ImportNonPositiveLayout {
+                json_content: serde_json::to_string(&records).unwrap(),
+                conflict_policy,
+            },
+        )
+    };
+    let both = [ArtObjectId::Met(1), ArtObjectId::Met(2)];
+
+    let body = worker.send_request(
+        1,
+        RequestBody::MoveArtObject {
+            art_object_id: ArtObjectId::Met(1),
+            gallery_id: 1,
+            wall_id: "wall_a".to_string(),
+            x: 1.0,
+            y: 1.5,
+            strict: false,
+        },
+    );
+    assert!(
+        matches!(body, ResponseBody::ArtObjectMoved { .. }),
+        "{body:?}"
+    );
+
+    let body = import(
+        2,
+        &[ArtObjectId::Met(2), ArtObjectId::Met(2)],
+        ImportConflictPolicy::Overwrite,
+    );
+    let ResponseBody::Error(message) = body else {
+        panic!("expected error response, got {body:?}");
+    };
+    assert!(message.contains("1 duplicate"), "{message}");
+    assert_eq!(get_wall(&worker, 3, -1).len(), 0);
+
+    let body = import(4, &both, ImportConflictPolicy::Fail);
+    let ResponseBody::Error(message) = body else {
+        panic!("expected error response, got {body:?}");
+    };
+    assert!(message.starts_with("1 art object"), "{message}");
+    assert_eq!(get_wall(&worker, 5, -1).len(), 0);
+
+    let body = import(6, &both, ImportConflictPolicy::Skip);
+    assert_eq!(
+        parse_import_summary(body),
+        ImportSummary {
+            imported: 1,
+            skipped: 0,
+            unknown_art_objects: vec![],
+            conflicted: 1,
+            rejected: vec![],
+        }
+    );
+    assert_eq!(get_wall(&worker, 7, 1).len(), 1);
+    let objects = get_wall(&worker, 8, -1);
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].object_id, ArtObjectId::Met(2));
+
+    let body = import(9, &both, ImportConflictPolicy::Overwrite);
+    assert_eq!(
+        parse_import_summary(body),
+        ImportSummary {
+            imported: 2,
+            skipped: 0,
+            unknown_art_objects: vec![],
+            conflicted: 1,
+            rejected: vec![],
+        }
+    );
+    assert_eq!(get_wall(&worker, 10, 1).len(), 0);
+    assert_eq!(get_wall(&worker, 11, -1).len(), 2);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_rejects_imports_into_generated_galleries() {
+    let root_dir = create_root_dir_with_art_objects(
+        "import-generated",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Boring Painting"),
+            make_art_object_record(ArtObjectId::Met(3), "Other Painting"),
+        ],
+    );
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let make_record = |art_object_id, gallery_id| LayoutRecord {
+        gallery_id,
+        wall_id: "wall_a".to_string(),
+        art_object_id,
+        x: 1.0,
+        y: 1.5,
+        rotated: false,
+    };
+    let records = vec![
+        make_record(ArtObjectId::Met(1), 1),
+        make_record(ArtObjectId::Met(2), 0),
+        make_record(ArtObjectId::Met(3), -1),
+    ];
+
+    let body = worker.send_request(
+        1,
+        RequestBody::ImportNonPositiveLayout {
+            json_content: serde_json::to_string(&records).unwrap(),
+            conflict_policy: ImportConflictPolicy::Skip,
+        },
+    );
+    let summary = parse_import_summary(body);
+    assert_eq!(summary.imported, 2);
+    assert_eq!(summary.rejected.len(), 1);
+    let rejected = &summary.rejected[0];
+    assert_eq!(rejected.art_object_id, ArtObjectId::Met(1));
+    assert_eq!(rejected.gallery_id, 1);
+    assert!(
+        rejected.reason.contains("Gallery 1 is generated"),
+        "{rejected:?}"
+    );
+    assert_eq!(get_wall(&worker, 2, 1).len(), 0);
+    assert_eq!(get_wall(&worker, 3, 0).len(), 1);
+    assert_eq!(get_wall(&worker, 4, -1).len(), 1);
+
+    // What's exported can be imported again exactly as it was.
+    let export = |request_id| {
+        let body = worker.send_request(
+            request_id,
+            RequestBody::ExportNonPositiveLayout {
+                include_metadata: false,
+            },
+        );
+        let ResponseBody::String(json_content) = body else {
+            panic!("expected string response, got {body:?}");
+        };
+        json_content
+    };
+    let exported = export(5);
+    let body = worker.send_request(
+        6,
+        RequestBody::ImportNonPositiveLayout {
+            json_content: exported.clone(),
+            conflict_policy: ImportConflictPolicy::Skip,
+        },
+    );
+    let summary = parse_import_summary(body);
+    assert_eq!(summary.imported, 2);
+    assert_eq!(summary.rejected, vec![]);
+    assert_eq!(export(7), exported);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_gets_cache_info() {
+    let root_dir = create_root_dir_with_db("cache-info");
+    let cache = GalleryCache::new_offline(root_dir.clone());
+    let image_path = cache.get_cached_path(MONKEY_SMALL_IMAGE_FILENAME);
+    std::fs::create_dir_all(image_path.parent().unwrap()).unwrap();
+    std::fs::write(&image_path, vec![0; 123]).unwrap();
+    let worker = TestWorker::spawn(&root_dir, false, false);
+
+    let body = worker.send_request(1, RequestBody::GetCacheInfo);
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    let report: CacheUsageReport = serde_json::from_str(&json_content).unwrap();
+    assert!(report.path.is_absolute());
+    let info: CacheInfo = serde_json::from_str(&json_content).unwrap();
+    assert_eq!(info.usage, report);
+    // The worker is offline, so it can't have fetched anything.
+    assert!(info.stats.hosts.values().all(|stats| stats.requests == 0));
+    let wikidata = report
+        .breakdown
+        .iter()
+        .find(|usage| usage.name == "wikidata")
+        .unwrap();
+    assert_eq!((wikidata.bytes, wikidata.files), (123, 1));
+    let db = report
+        .breakdown
+        .iter()
+        .find(|usage| usage.name == DB_FILES_CATEGORY)
+        .unwrap();
+    assert!(db.bytes > 0);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_gets_cached_image_states() {
+    let root_dir = create_root_dir_with_art_objects(
+        "cached-image-states",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Uncached Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Cached Painting"),
+            ArtObjectRecord {
+                filename: "Funky Monkey.jpg".to_string(),
+                ..make_art_object_record(MONKEY_ID, "Funky Monkey")
+            },
+        ],
+    );
+    let mut db = GalleryDb::open(root_dir.join(get_default_gallery_db_filename()), false).unwrap();
+    db.set_met_image_urls(&[(
+        2,
+        MetImageUrls {
+            primary_image: "https://images.metmuseum.org/original/two.jpg".into(),
+            primary_image_small: "https://images.metmuseum.org/web-large/two.jpg".into(),
+        },
+    )])
+    .unwrap();
+    drop(db);
+    let transport = CountingTransport::default();
+    let cache = GalleryCache::with_transport(root_dir.clone(), Box::new(transport.clone()));
+    for filename in [
+        met_image_cache_path(2, ImageSize::Large, ".jpg"),
+        wikidata_image_cache_path(3, ImageSize::Small, ".jpg"),
+    ] {
+        let image_path = cache.get_cached_path(&filename);
+        std::fs::create_dir_all(image_path.parent().unwrap()).unwrap();
+        image::RgbImage::new(4, 3).save(&image_path).unwrap();
+    }
+    let (worker, _) = TestWorker::spawn_with_cache(cache, TEST_SLOT, false, false);
+
+    let body = worker.send_request(
+        1,
+        RequestBody::GetCachedImageStates {
+            object_ids: vec![
+                ArtObjectId::Met(1),
+                ArtObjectId::Met(2),
+                MONKEY_ID,
+                ArtObjectId::Met(999),
+            ],
+        },
+    );
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    let states: HashMap<i64, CachedImageState> = serde_json::from_str(&json_content).unwrap();
+    let state = |object_id: ArtObjectId| &states[&object_id.to_raw_i64()];
+    let cached = |small, large| CachedImageState { small, large };
+    assert_eq!(states.len(), 4);
+    assert_eq!(state(ArtObjectId::Met(1)), &cached(false, false));
+    assert_eq!(state(ArtObjectId::Met(2)), &cached(false, true));
+    assert_eq!(state(MONKEY_ID), &cached(true, false));
+    assert_eq!(state(ArtObjectId::Met(999)), &cached(false, false));
+    assert_eq!(transport.requests(), 0);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+fn parse_tags(body: ResponseBody) -> Vec<String> {
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected tags response, got {body:?}");
+    };
+    serde_json::from_str(&json_content).unwrap()
+}
+
+#[test]
+fn test_worker_tags_art_objects() {
+    let root_dir = create_root_dir_with_db("tags");
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let tag_request = |tag: &str| RequestBody::TagArtObject {
+        art_object_id: ArtObjectId::Met(1),
+        tag: tag.to_string(),
+    };
+    let untag_request = |tag: &str| RequestBody::UntagArtObject {
+        art_object_id: ArtObjectId::Met(1),
+        tag: tag.to_string(),
+    };
+    let get_tags_request = || RequestBody::GetTags {
+        art_object_id: ArtObjectId::Met(1),
+    };
+    let count_request = |filter: &str| RequestBody::CountArtObjects {
+        filter: Some(filter.to_string()),
+    };
+
+    let body = worker.send_request(1, tag_request("Favorite"));
+    assert!(matches!(body, ResponseBody::Integer(1)), "{body:?}");
+    let body = worker.send_request(2, tag_request("favorite"));
+    assert!(matches!(body, ResponseBody::Integer(0)), "{body:?}");
+    let body = worker.send_request(3, tag_request("not valid"));
+    assert!(matches!(body, ResponseBody::Error(_)), "{body:?}");
+    assert_eq!(
+        parse_tags(worker.send_request(4, get_tags_request())),
+        vec!["favorite".to_string()]
+    );
+
+    let body = worker.send_request(5, count_request("tag:favorite"));
+    assert!(matches!(body, ResponseBody::Integer(1)), "{body:?}");
+    let body = worker.send_request(6, count_request("-tag:favorite"));
+    assert!(matches!(body, ResponseBody::Integer(0)), "{body:?}");
+
+    let body = worker.send_request(7, untag_request("favorite"));
+    assert!(matches!(body, ResponseBody::Integer(1)), "{body:?}");
+    let body = worker.send_request(8, untag_request("favorite"));
+    assert!(matches!(body, ResponseBody::Integer(0)), "{body:?}");
+    assert_eq!(
+        parse_tags(worker.send_request(9, get_tags_request())),
+        Vec::<String>::new()
+    );
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_reports_free_regions_from_layout() {
+    let root_dir = create_root_dir_with_art_objects(
+        "free-regions",
+        vec![make_art_object_record(
+            ArtObjectId::Met(1),
+            "Funky Painting",
+        )],
+    );
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let layout_request = |collect_free_regions| RequestBody::Layout {
+        walls_json: WALLS_JSON.to_string(),
+        wall_sets_json: None,
+        filter: None,
+        dense: false,
+        mode: None,
+        ordering_json: None,
+        reserved_walls: vec!["wall_b".to_string()],
+        segments: vec![],
+        featured_first: false,
+        allow_rotation: false,
+        balance_by_collection: None,
+        collect_free_regions,
+        min_free_region_width: None,
+        exclude_previously_displayed: false,
+        gallery_id_range: None,
+    };
+
+    let summary = parse_layout_summary(worker.send_request(1, layout_request(false)));
+    assert_eq!(summary.free_regions, vec![]);
+
+    let summary = parse_layout_summary(worker.send_request(2, layout_request(true)));
+    let free_region = |x_start, x_end| FreeRegion {
+        gallery_id: 1,
+        wall_id: "wall_a".to_string(),
+        x_start,
+        x_end,
+        height_available: 3.0,
+    };
+    assert_eq!(
+        summary.free_regions,
+        vec![free_region(0.0, 2.0), free_region(3.0, 5.0)]
+    );
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_lays_out_real_galleries() {
+    let in_source_gallery = |id, source_gallery: &str| ArtObjectRecord {
+        source_gallery: Some(source_gallery.to_string()),
+        ..make_art_object_record(ArtObjectId::Met(id), "Real Painting")
+    };
+    let mut records = vec![
+        in_source_gallery(1, "825"),
+        in_source_gallery(2, "825"),
+        make_art_object_record(ArtObjectId::Met(3), "Stored Painting"),
+    ];
+    // Far too many to fit in one gallery.
+    records.extend((4..=13).map(|id| in_source_gallery(id, "Great Hall")));
+    let root_dir = create_root_dir_with_art_objects("real-galleries", records);
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let layout_request = |segments| RequestBody::Layout {
+        walls_json: WALLS_JSON.to_string(),
+        wall_sets_json: None,
+        filter: None,
+        dense: false,
+        mode: Some(LayoutMode::RealGallery),
+        ordering_json: None,
+        reserved_walls: vec![],
+        segments,
+        featured_first: false,
+        allow_rotation: false,
+        balance_by_collection: None,
+        collect_free_regions: false,
+        min_free_region_width: None,
+        exclude_previously_displayed: false,
+        gallery_id_range: None,
+    };
+
+    let body = worker.send_request(
+        1,
+        layout_request(vec![LayoutSegment {
+            name: "portraits".to_string(),
+            filter: Some("portrait".to_string()),
+        }]),
+    );
+    assert!(matches!(body, ResponseBody::Error(_)), "{body:?}");
+
+    let summary = parse_layout_summary(worker.send_request(2, layout_request(vec![])));
+    assert_eq!(summary.galleries_created, 2);
+    assert_eq!(
+        summary.dropped_source_galleries,
+        vec!["Great Hall".to_string()]
+    );
+
+    let body = worker.send_request(3, RequestBody::GetGallerySourceGallery { gallery_id: 1 });
+    assert!(
+        matches!(&body, ResponseBody::String(s) if s == "825"),
+        "{body:?}"
+    );
+    let body = worker.send_request(4, RequestBody::GetGallerySourceGallery { gallery_id: 2 });
+    assert!(
+        matches!(&body, ResponseBody::String(s) if s.is_empty()),
+        "{body:?}"
+    );
+
+    let body = worker.send_request(
+        5,
+        RequestBody::GetArtObjectsForGalleryWall {
+            gallery_id: 2,
+            wall_id: "wall_a".to_string(),
+        },
+    );
+    let ResponseBody::ArtObjectsForGalleryWall(objects) = body else {
+        panic!("expected art objects response, got {body:?}");
+    };
+    let ids: Vec<ArtObjectId> = objects.iter().map(|object| object.object_id).collect();
+    assert_eq!(ids, vec![ArtObjectId::Met(3)]);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_rotates_exhibitions_in_gallery_range() {
+    let old_paintings =
+        (1..=30).map(|id| make_art_object_record(ArtObjectId::Met(id), "Old Painting"));
+    let new_paintings =
+        (101..=160).map(|id| make_art_object_record(ArtObjectId::Met(id), "New Painting"));
+    let root_dir = create_root_dir_with_art_objects(
+        "rotating-exhibitions",
+        old_paintings.chain(new_paintings).collect(),
+    );
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let layout_request = |filter: Option<&str>, gallery_id_range| RequestBody::Layout {
+        walls_json: WALLS_JSON.to_string(),
+        wall_sets_json: None,
+        filter: filter.map(String::from),
+        dense: false,
+        mode: None,
+        ordering_json: None,
+        reserved_walls: vec![],
+        segments: vec![],
+        featured_first: false,
+        allow_rotation: false,
+        balance_by_collection: None,
+        collect_free_regions: false,
+        min_free_region_width: None,
+        exclude_previously_displayed: gallery_id_range.is_some(),
+        gallery_id_range,
+    };
+    let mut request_id = 0;
+    let mut get_gallery = |gallery_id| -> Vec<ArtObjectId> {
+        let mut ids = vec![];
+        for wall_id in ["wall_a", "wall_b"] {
+            request_id += 1;
+            let body = worker.send_request(
+                request_id,
+                RequestBody::GetArtObjectsForGalleryWall {
+                    gallery_id,
+                    wall_id: wall_id.to_string(),
+                },
+            );
+            let ResponseBody::ArtObjectsForGalleryWall(objects) = body else {
+                panic!("expected art objects response, got {body:?}");
+            };
+            ids.extend(objects.iter().map(|object| object.object_id));
+        }
+        ids
+    };
+
+    let summary = parse_layout_summary(worker.send_request(100, layout_request(Some("old"), None)));
+    assert!(summary.galleries_created >= 3, "{summary:?}");
+    let untouched_galleries: Vec<Vec<ArtObjectId>> = (2..=summary.galleries_created as i64)
+        .map(|gallery_id| get_gallery(gallery_id))
+        .collect();
+
+    let mut exhibitions: Vec<HashSet<ArtObjectId>> = vec![];
+    for request_id in [101, 102] {
+        let summary = parse_layout_summary(
+            worker.send_request(request_id, layout_request(None, Some((1, 1)))),
+        );
+        assert_eq!(summary.galleries_created, 1);
+        assert_eq!(summary.first_gallery_id, Some(1));
+        assert_eq!(summary.last_gallery_id, Some(1));
+        let exhibition: HashSet<ArtObjectId> = get_gallery(1).into_iter().collect();
+        assert!(!exhibition.is_empty());
+        assert!(exhibition
+            .iter()
+            .all(|id| matches!(id, ArtObjectId::Met(id) if *id > 100)));
+        exhibitions.push(exhibition);
+    }
+    assert!(exhibitions[0].is_disjoint(&exhibitions[1]));
+    for (i, gallery) in untouched_galleries.iter().enumerate() {
+        assert_eq!(&get_gallery(i as i64 + 2), gallery);
+    }
+
+    let body = worker.send_request(103, layout_request(None, Some((0, 1))));
+    assert!(matches!(body, ResponseBody::Error(_)), "{body:?}");
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_rejects_invalid_walls() {
+    let root_dir = create_root_dir_with_db("invalid-walls");
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let layout_request = |walls_json: &str, wall_sets_json: Option<&str>| RequestBody::Layout {
+        walls_json: walls_json.to_string(),
+        wall_sets_json: wall_sets_json.map(String::from),
+        filter: None,
+        dense: false,
+        mode: None,
+        ordering_json: None,
+        reserved_walls: vec![],
+        segments: vec![],
+        featured_first: false,
+        allow_rotation: false,
+        balance_by_collection: None,
+        collect_free_regions: false,
+        min_free_region_width: None,
+        exclude_previously_displayed: false,
+        gallery_id_range: None,
+    };
+    let body = worker.send_request(
+        1,
+        layout_request(
+            r#"[
+                {"name": "wall_a", "width": 0.0, "height": 3.0},
+                {"name": "wall_a", "width": 5.0, "height": 3.0}
+            ]"#,
+            None,
+        ),
+    );
+    let ResponseBody::Error(message) = body else {
+        panic!("expected error response, got {body:?}");
+    };
+    assert!(message.contains("isn't positive"), "{message}");
+    assert!(message.contains("more than one wall"), "{message}");
+
+    // Mistakes in every wall set are reported, not just the first one's.
+    let body = worker.send_request(
+        2,
+        layout_request(
+            WALLS_JSON,
+            Some(
+                r#"[
+                    {"name": "small", "walls": [{"name": "wall_a", "width": 0.0, "height": 3.0}]},
+                    {"name": "large", "walls": [{"name": "wall_a", "width": 5.0, "height": 0.0}]}
+                ]"#,
+            ),
+        ),
+    );
+    let ResponseBody::Error(message) = body else {
+        panic!("expected error response, got {body:?}");
+    };
+    assert!(message.contains("Invalid walls in small:"), "{message}");
+    assert!(message.contains("Invalid walls in large:"), "{message}");
+
+    // The worker is still usable afterwards.
+    let body = worker.send_request(3, RequestBody::CountGalleries);
+    assert!(!matches!(body, ResponseBody::Error(_)), "{body:?}");
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_gets_art_object_placements() {
+    let root_dir = create_root_dir_with_art_objects(
+        "placement",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Boring Painting"),
+            make_art_object_record(ArtObjectId::Met(3), "Unhung Painting"),
+        ],
+    );
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let moves = [
+        (ArtObjectId::Met(1), 1, "wall_a", 2.0, 1.5),
+        (ArtObjectId::Met(2), -1, "wall_b", 1.0, 0.5),
+    ];
+    for (request_id, (art_object_id, gallery_id, wall_id, x, y)) in (1..).zip(moves) {
+        let body = worker.send_request(
+            request_id,
+            RequestBody::MoveArtObject {
+                art_object_id,
+                gallery_id,
+                wall_id: wall_id.to_string(),
+                x,
+                y,
+                strict: false,
+            },
+        );
+        assert!(
+            matches!(body, ResponseBody::ArtObjectMoved { .. }),
+            "{body:?}"
+        );
+    }
+    let get_placement = |request_id, art_object_id| {
+        let body = worker.send_request(
+            request_id,
+            RequestBody::GetArtObjectPlacement { art_object_id },
+        );
+        let ResponseBody::ArtObjectPlacement(record) = body else {
+            panic!("expected placement response, got {body:?}");
+        };
+        record
+    };
+
+    assert_eq!(
+        get_placement(3, ArtObjectId::Met(1)),
+        Some(LayoutRecord {
+            gallery_id: 1,
+            wall_id: "wall_a".to_string(),
+            art_object_id: ArtObjectId::Met(1),
+            x: 2.0,
+            y: 1.5,
+            rotated: false,
+        })
+    );
+    assert_eq!(
+        get_placement(4, ArtObjectId::Met(2)),
+        Some(LayoutRecord {
+            gallery_id: -1,
+            wall_id: "wall_b".to_string(),
+            art_object_id: ArtObjectId::Met(2),
+            x: 1.0,
+            y: 0.5,
+            rotated: false,
+        })
+    );
+    assert_eq!(get_placement(5, ArtObjectId::Met(3)), None);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_searches_placed_art_objects() {
+    let root_dir = create_root_dir_with_art_objects(
+        "search-placed",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Funky Sculpture"),
+            make_art_object_record(ArtObjectId::Met(3), "Unhung Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(4), "Boring Painting"),
+        ],
+    );
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let moves = [
+        (ArtObjectId::Met(1), 2, "wall_a", 2.0),
+        (ArtObjectId::Met(2), 1, "wall_b", 1.0),
+        (ArtObjectId::Met(4), 1, "wall_a", 3.0),
+    ];
+    for (request_id, (art_object_id, gallery_id, wall_id, x)) in (1..).zip(moves) {
+        let body = worker.send_request(
+            request_id,
+            RequestBody::MoveArtObject {
+                art_object_id,
+                gallery_id,
+                wall_id: wall_id.to_string(),
+                x,
+                y: 1.5,
+                strict: false,
+            },
+        );
+        assert!(
+            matches!(body, ResponseBody::ArtObjectMoved { .. }),
+            "{body:?}"
+        );
+    }
+    let search = |request_id, filter: &str, limit| {
+        let body = worker.send_request(
+            request_id,
+            RequestBody::SearchPlaced {
+                filter: Some(filter.to_string()),
+                limit,
+            },
+        );
+        let ResponseBody::String(json_content) = body else {
+            panic!("expected search results, got {body:?}");
+        };
+        serde_json::from_str::<Vec<PlacedSearchResultSummary>>(&json_content).unwrap()
+    };
+    let placed = |object_id, title: &str, gallery_id, wall_id: &str, x| PlacedSearchResultSummary {
+        object_id,
+        title: title.to_string(),
+        artist: "boop".to_string(),
+        placed: true,
+        gallery_id: Some(gallery_id),
+        wall_id: Some(wall_id.to_string()),
+        x: Some(x),
+    };
+
+    assert_eq!(
+        search(4, "funky", 10),
+        vec![
+            placed(
+                ArtObjectId::Met(2).to_raw_i64(),
+                "Funky Sculpture",
+                1,
+                "wall_b",
+                1.0
+            ),
+            placed(
+                ArtObjectId::Met(1).to_raw_i64(),
+                "Funky Painting",
+                2,
+                "wall_a",
+                2.0
+            ),
+            PlacedSearchResultSummary {
+                object_id: ArtObjectId::Met(3).to_raw_i64(),
+                title: "Unhung Funky Painting".to_string(),
+                artist: "boop".to_string(),
+                placed: false,
+                gallery_id: None,
+                wall_id: None,
+                x: None,
+            },
+        ]
+    );
+    assert_eq!(search(5, "funky", 1).len(), 1);
+
+    let body = worker.send_request(
+        6,
+        RequestBody::SearchPlaced {
+            filter: Some("@nonexistent".to_string()),
+            limit: 10,
+        },
+    );
+    assert!(matches!(body, ResponseBody::Error(_)), "{body:?}");
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_autosyncs_tags() {
+    let root_dir = create_root_dir_with_db("autosync-tags");
+    let autosync_path = root_dir.join(get_autosync_gallery_path(TEST_SLOT));
+    std::fs::create_dir_all(autosync_path.parent().unwrap()).unwrap();
+    // Autosyncs from before tags existed are just the layout records.
+    let records = vec![LayoutRecord {
+        gallery_id: -1,
+        wall_id: "wall_a".to_string(),
+        art_object_id: ArtObjectId::Met(1),
+        x: 1.0,
+        y: 1.5,
+        rotated: false,
+    }];
+    std::fs::write(&autosync_path, serde_json::to_string(&records).unwrap()).unwrap();
+
+    let worker = TestWorker::spawn(&root_dir, true, false);
+    assert_eq!(get_wall(&worker, 1, -1).len(), 1);
+    let body = worker.send_request(
+        2,
+        RequestBody::TagArtObject {
+            art_object_id: ArtObjectId::Met(1),
+            tag: "favorite".to_string(),
+        },
+    );
+    assert!(matches!(body, ResponseBody::Integer(1)), "{body:?}");
+    worker.end();
+
+    let autosync: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&autosync_path).unwrap()).unwrap();
+    assert_eq!(autosync["version"], 2);
+    assert_eq!(autosync["records"].as_array().unwrap().len(), 1);
+    assert_eq!(autosync["tags"][0]["tag"], "favorite");
+
+    // Pretend the database was rebuilt, e.g. because it was corrupt.
+    let mut db = GalleryDb::open(root_dir.join(get_default_gallery_db_filename()), false).unwrap();
+    assert!(db.remove_tag(ArtObjectId::Met(1), "favorite").unwrap());
+    drop(db);
+
+    let worker = TestWorker::spawn(&root_dir, true, false);
+    assert_eq!(get_wall(&worker, 1, -1).len(), 1);
+    let body = worker.send_request(
+        2,
+        RequestBody::GetTags {
+            art_object_id: ArtObjectId::Met(1),
+        },
+    );
+    assert_eq!(parse_tags(body), vec!["favorite".to_string()]);
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_merges_autosyncs() {
+    let root_dir = create_root_dir_with_art_objects(
+        "merge-autosyncs",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Met(2), "Funky Sculpture"),
+        ],
+    );
+    let autosync = |records: &[(ArtObjectId, f64, i64)]| {
+        let records: Vec<TimestampedLayoutRecord> = records
+            .iter()
+            .map(|&(art_object_id, x, modified_at)| TimestampedLayoutRecord {
+                record: LayoutRecord {
+                    gallery_id: -1,
+                    wall_id: "wall_a".to_string(),
+                    art_object_id,
+                    x,
+                    y: 1.5,
+                    rotated: false,
+                },
+                modified_at,
+            })
+            .collect();
+        serde_json::json!({ "version": 2, "records": records }).to_string()
+    };
+    let worker = TestWorker::spawn(&root_dir, false, false);
+
+    // Importing an autosync keeps its timestamps.
+    let body = worker.send_request(
+        1,
+        RequestBody::ImportNonPositiveLayout {
+            json_content: autosync(&[
+                (ArtObjectId::Met(1), 1.0, 10),
+                (ArtObjectId::Met(2), 3.0, 30),
+            ]),
+            conflict_policy: ImportConflictPolicy::Skip,
+        },
+    );
+    assert_eq!(parse_import_summary(body).imported, 2);
+    let body = worker.send_request(
+        2,
+        RequestBody::ExportNonPositiveLayout {
+            include_metadata: false,
+        },
+    );
+    let timestamps: Vec<(ArtObjectId, i64)> = parse_exported_records(body)
+        .iter()
+        .map(|timestamped| (timestamped.record.art_object_id, timestamped.modified_at))
+        .collect();
+    assert_eq!(
+        timestamps,
+        vec![(ArtObjectId::Met(1), 10), (ArtObjectId::Met(2), 30)]
+    );
+
+    // Each side has the newer record for a different art object.
+    let body = worker.send_request(
+        3,
+        RequestBody::MergeNonPositiveLayout {
+            json_content: autosync(&[
+                (ArtObjectId::Met(1), 2.0, 20),
+                (ArtObjectId::Met(2), 4.0, 20),
+            ]),
+        },
+    );
+    let ResponseBody::String(json_content) = body else {
+        panic!("expected merge summary response, got {body:?}");
+    };
+    assert_eq!(
+        serde_json::from_str::<MergeSummary>(&json_content).unwrap(),
+        MergeSummary {
+            merged: 1,
+            kept: 1,
+            skipped: 0,
+        }
+    );
+    let mut objects: Vec<(ArtObjectId, f64)> = get_wall(&worker, 4, -1)
+        .iter()
+        .map(|object| (object.object_id, object.x))
+        .collect();
+    objects.sort_by_key(|(object_id, _)| object_id.to_raw_i64());
+    assert_eq!(
+        objects,
+        vec![(ArtObjectId::Met(1), 2.0), (ArtObjectId::Met(2), 3.0)]
+    );
+
+    let body = worker.send_request(
+        5,
+        RequestBody::MergeNonPositiveLayout {
+            json_content: r#"{"version": 3, "records": []}"#.to_string(),
+        },
+    );
+    assert!(matches!(body, ResponseBody::Error(_)), "{body:?}");
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_serves_layout_preview() {
+    let root_dir = create_root_dir_with_art_objects(
+        "preview-server",
+        vec![
+            make_art_object_record(ArtObjectId::Met(1), "Funky Painting"),
+            make_art_object_record(ArtObjectId::Wikidata(2), "Boring Painting"),
+        ],
+    );
+    let port = find_free_port();
+    let worker = TestWorker::spawn_with_preview_server(&root_dir, false, port);
+    let body = worker.send_request(
+        1,
+        RequestBody::Layout {
+            walls_json: WALLS_JSON.to_string(),
+            wall_sets_json: None,
+            filter: None,
+            dense: false,
+            mode: None,
+            ordering_json: None,
+            reserved_walls: vec![],
+            segments: vec![],
+            featured_first: false,
+            allow_rotation: false,
+            balance_by_collection: None,
+            collect_free_regions: false,
+            min_free_region_width: None,
+            exclude_previously_displayed: false,
+            gallery_id_range: None,
+        },
+    );
+    assert_eq!(parse_layout_summary(body).galleries_created, 1);
+    let url = |path: &str| format!("http://127.0.0.1:{port}{path}");
+    let get_json = |path: &str| -> String {
+        let response = ureq::get(&url(path)).call().unwrap();
+        // Web pages shouldn't be able to read the player's layout.
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        response.into_string().unwrap()
+    };
+
+    let layout: Vec<PreviewLayoutRecord> = serde_json::from_str(&get_json("/layout.json")).unwrap();
+    assert_eq!(layout.len(), 2);
+    assert!(layout.iter().all(|record| record.gallery_id == 1));
+    let mut titles: Vec<&str> = layout
+        .iter()
+        .map(|record| record.art_object.title.as_str())
+        .collect();
+    titles.sort();
+    assert_eq!(titles, vec!["Boring Painting", "Funky Painting"]);
+
+    let galleries: Vec<PreviewGallery> =
+        serde_json::from_str(&get_json("/galleries.json")).unwrap();
+    assert_eq!(galleries.len(), 1);
+    assert_eq!(galleries[0].gallery_id, 1);
+    assert_eq!(galleries[0].object_count, 2);
+    assert!(galleries[0].wall_set.is_some());
+
+    let raw_id = ArtObjectId::Wikidata(2).to_raw_i64();
+    let object: RelatedArtObject =
+        serde_json::from_str(&get_json(&format!("/object/{raw_id}.json"))).unwrap();
+    assert_eq!(object.object_id, raw_id);
+    assert_eq!(object.title, "Boring Painting");
+
+    for path in ["/object/12345.json", "/object/boop.json", "/nope"] {
+        let Err(ureq::Error::Status(status, response)) = ureq::get(&url(path)).call() else {
+            panic!("expected {path} to not be found");
+        };
+        assert_eq!(status, 404);
+        let error: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert!(error["error"].is_string());
+    }
+
+    // The preview reflects changes made by the worker.
+    let body = worker.send_request(
+        2,
+        RequestBody::MoveArtObject {
+            art_object_id: ArtObjectId::Met(1),
+            gallery_id: -1,
+            wall_id: "wall_a".to_string(),
+            x: 1.0,
+            y: 1.5,
+            strict: false,
+        },
+    );
+    assert!(
+        matches!(body, ResponseBody::ArtObjectMoved { .. }),
+        "{body:?}"
+    );
+    let layout: Vec<PreviewLayoutRecord> = serde_json::from_str(&get_json("/layout.json")).unwrap();
+    assert!(layout.iter().any(
+        |record| record.gallery_id == -1 && record.art_object.object_id == ArtObjectId::Met(1)
+    ));
+
+    // Once the worker ends, so does the server.
+    worker.end();
+    assert!(ureq::get(&url("/layout.json")).call().is_err());
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_layout_estimates_match_layouts() {
+    let mut records: Vec<ArtObjectRecord> = (1..=12)
+        .map(|id| make_art_object_record(ArtObjectId::Met(id), "Painting"))
+        .collect();
+    records.push(ArtObjectRecord {
+        width: 100.0,
+        ..make_art_object_record(ArtObjectId::Met(13), "Enormous Painting")
+    });
+    let root_dir = create_root_dir_with_art_objects("estimate-layout", records);
+    let worker = TestWorker::spawn(&root_dir, false, false);
+    let estimate = |request_id, filter: Option<&str>| {
+        let body = worker.send_request(
+            request_id,
+            RequestBody::EstimateLayout {
+                walls_json: WALLS_JSON.to_string(),
+                filter: filter.map(String::from),
+                dense: false,
+                mode: None,
+            },
+        );
+        let ResponseBody::String(json) = body else {
+            panic!("expected string response, got {body:?}");
+        };
+        serde_json::from_str::<LayoutEstimate>(&json).unwrap()
+    };
+
+    let before = estimate(1, None);
+    assert!(before.galleries > 1, "{before:?}");
+    assert_eq!(before.unplaceable, 1);
+    assert_eq!(estimate(2, Some("enormous")).galleries, 0);
+    assert!(matches!(
+        worker.send_request(
+            3,
+            RequestBody::EstimateLayout {
+                walls_json: WALLS_JSON.to_string(),
+                filter: Some("funky -".to_string()),
+                dense: false,
+                mode: None,
+            },
+        ),
+        ResponseBody::Error(_)
+    ));
+    // Estimating doesn't lay anything out.
+    assert_eq!(get_wall(&worker, 4, 1), vec![]);
+
+    let summary = parse_layout_summary(worker.send_request(
+        5,
+        RequestBody::Layout {
+            walls_json: WALLS_JSON.to_string(),
+            wall_sets_json: None,
+            filter: None,
+            dense: false,
+            mode: None,
+            ordering_json: None,
+            reserved_walls: vec![],
+            segments: vec![],
+            featured_first: false,
+            allow_rotation: false,
+            balance_by_collection: None,
+            collect_free_regions: false,
+            min_free_region_width: None,
+            exclude_previously_displayed: false,
+            gallery_id_range: None,
+        },
+    ));
+    assert_eq!(summary.galleries_created, before.galleries);
+    assert_eq!(summary.unplaceable, before.unplaceable);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+fn ensure_ready(
+    worker: &TestWorker,
+    request_id: u32,
+    walls_json: &str,
+    relayout_if_empty: bool,
+) -> ReadinessReport {
+    let body = worker.send_request(
+        request_id,
+        RequestBody::EnsureReady {
+            walls_json: walls_json.to_string(),
+            relayout_if_empty,
+        },
+    );
+    let ResponseBody::String(json) = body else {
+        panic!("expected string response, got {body:?}");
+    };
+    serde_json::from_str(&json).unwrap()
+}
+
+#[test]
+fn test_worker_ensures_fresh_db_is_ready() {
+    let records: Vec<ArtObjectRecord> = (1..=6)
+        .map(|id| make_art_object_record(ArtObjectId::Met(id), "Painting"))
+        .collect();
+    let root_dir = create_root_dir_with_art_objects("ensure-ready-fresh", records);
+    let worker = TestWorker::spawn(&root_dir, false, false);
+
+    // Not being able to lay out doesn't stop everything else from happening.
+    let report = ensure_ready(&worker, 1, "not json", true);
+    assert_eq!(report.object_count, 6);
+    assert_eq!(report.gallery_count, 0);
+    assert!(!report.laid_out);
+    assert_eq!(report.warnings.len(), 1, "{report:?}");
+    assert!(
+        report.warnings[0].starts_with("Initial layout failed"),
+        "{report:?}"
+    );
+
+    let report = ensure_ready(&worker, 2, WALLS_JSON, false);
+    assert!(!report.laid_out);
+    assert_eq!(report.gallery_count, 0);
+
+    let report = ensure_ready(&worker, 3, WALLS_JSON, true);
+    assert_eq!(
+        report,
+        ReadinessReport {
+            db_version: Some(LATEST_GALLERY_DB_VERSION),
+            object_count: 6,
+            gallery_count: report.gallery_count,
+            migrated: false,
+            laid_out: true,
+            warnings: vec![],
+        }
+    );
+    assert!(report.gallery_count > 0);
+    assert!(!get_wall(&worker, 4, 1).is_empty());
+
+    // Now that it's ready, nothing else needs doing.
+    let again = ensure_ready(&worker, 5, WALLS_JSON, true);
+    assert_eq!(
+        again,
+        ReadinessReport {
+            laid_out: false,
+            ..report
+        }
+    );
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_ensures_outdated_db_is_ready() {
+    let root_dir = create_root_dir_with_db("ensure-ready-outdated");
+    // This is what a DB from before `migrate_v12_to_v13()` looks like.
+    Connection::open(root_dir.join(get_default_gallery_db_filename()))
+        .unwrap()
+        .execute_batch(
+            "ALTER TABLE art_objects DROP COLUMN artist_role; PRAGMA main.user_version = 12",
+        )
+        .unwrap();
+    let worker = TestWorker::spawn(&root_dir, false, false);
+
+    let report = ensure_ready(&worker, 1, WALLS_JSON, true);
+    assert_eq!(
+        report,
+        ReadinessReport {
+            db_version: Some(LATEST_GALLERY_DB_VERSION),
+            object_count: 1,
+            gallery_count: 1,
+            migrated: true,
+            laid_out: true,
+            warnings: vec![],
+        }
+    );
+    assert_eq!(get_wall(&worker, 2, 1).len(), 1);
+    assert!(!ensure_ready(&worker, 3, WALLS_JSON, true).migrated);
+
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+/// Starts fetching the monkey painting's image from a slow server, then tells
+/// the worker to end once it's downloading, giving it the given grace period to
+/// finish. Returns the root dir, the image's response, and where the image
+/// would be cached.
+fn end_worker_while_downloading(
+    name: &str,
+    grace_period: Duration,
+) -> (PathBuf, ResponseBody, PathBuf) {
+    let root_dir = create_root_dir_with_art_objects(
+        name,
+        vec![ArtObjectRecord {
+            filename: "Funky Monkey.webp".to_string(),
+            ..make_art_object_record(MONKEY_ID, "Funky Monkey")
+        }],
+    );
+    let (transport, started_rx) = SlowImageTransport::new(Duration::from_millis(250));
+    let mut cache = GalleryCache::with_transport(root_dir.clone(), Box::new(transport));
+    let shutdown = Arc::new(ShutdownSignal::new());
+    cache.set_shutdown_signal(Some(shutdown.clone()));
+    let image_path =
+        cache.get_cached_path(&wikidata_image_cache_path(3, ImageSize::Small, ".webp"));
+    let (worker, _) = TestWorker::spawn_with_cache(cache, TEST_SLOT, true, false);
+
+    worker.send_request_without_waiting(
+        1,
+        RequestBody::FetchImage {
+            object_id: MONKEY_ID,
+            size: ImageSize::Small,
+            decode: false,
+            format: None,
+        },
+    );
+    started_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    shutdown.begin(grace_period);
+    let mut responses = worker.end_while_busy();
+    assert_eq!(responses.len(), 1);
+    let response = responses.pop().unwrap();
+    assert_eq!(response.request_id, 1);
+
+    // The layout should still have been saved on the way out.
+    assert!(root_dir.join(get_autosync_gallery_path(TEST_SLOT)).exists());
+    (root_dir, response.body, image_path)
+}
+
+#[test]
+fn test_worker_finishes_downloads_during_shutdown_grace_period() {
+    let (root_dir, body, image_path) =
+        end_worker_while_downloading("shutdown-grace", Duration::from_secs(60));
+    let ResponseBody::Image { path, .. } = body else {
+        panic!("expected image response, got {body:?}");
+    };
+    assert_eq!(path, Some(image_path.clone()));
+    assert_eq!(std::fs::read(&image_path).unwrap(), b"RIFFboopWEBP");
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_worker_abandons_downloads_after_shutdown_grace_period() {
+    let (root_dir, body, image_path) =
+        end_worker_while_downloading("shutdown-no-grace", Duration::ZERO);
+    assert!(
+        matches!(body, ResponseBody::Image { path: None, .. }),
+        "{body:?}"
+    );
+    assert!(!image_path.exists());
+    let leftovers: Vec<_> = std::fs::read_dir(image_path.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(leftovers, Vec::<std::ffi::OsString>::new());
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
+
+#[test]
+fn test_abandoned_worker_leaves_the_database_alone() {
+    let root_dir = create_root_dir_with_art_objects(
+        "abandoned-worker",
+        vec![ArtObjectRecord {
+            filename: "Funky Monkey.webp".to_string(),
+            ..make_art_object_record(MONKEY_ID, "Funky Monkey")
+        }],
+    );
+    let (transport, started_rx) = SlowImageTransport::new(Duration::from_millis(250));
+    let mut cache = GalleryCache::with_transport(root_dir.clone(), Box::new(transport));
+    let shutdown = Arc::new(ShutdownSignal::new());
+    cache.set_shutdown_signal(Some(shutdown.clone()));
+    let (worker, _) = TestWorker::spawn_with_cache(cache, TEST_SLOT, true, false);
+
+    worker.send_request_without_waiting(
+        1,
+        RequestBody::FetchImage {
+            object_id: MONKEY_ID,
+            size: ImageSize::Small,
+            decode: false,
+            format: None,
+        },
+    );
+    started_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    shutdown.abandon();
+    worker.send_request_without_waiting(
+        2,
+        RequestBody::SaveFilter {
+            name: "funky_ones".to_string(),
+            filter: "funky".to_string(),
+        },
+    );
+    worker.recv_response(1);
+    let body = worker.recv_response(2);
+    assert!(
+        matches!(&body, ResponseBody::Error(message) if message.contains("replaced")),
+        "{body:?}"
+    );
+    assert_eq!(worker.end_while_busy().len(), 0);
+    // Its replacement might have exported one by now, so it mustn't be clobbered.
+    assert!(!root_dir.join(get_autosync_gallery_path(TEST_SLOT)).exists());
+
+    // The replacement can use the database just fine.
+    let worker = TestWorker::spawn(&root_dir, true, false);
+    let body = worker.send_request(1, RequestBody::ListFilters);
+    assert!(!matches!(body, ResponseBody::Error(_)), "{body:?}");
+    worker.end();
+    std::fs::remove_dir_all(&root_dir).unwrap();
+}
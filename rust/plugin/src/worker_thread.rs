@@ -1,40 +1,77 @@
 use std::{
-    collections::VecDeque,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io::Write,
     path::PathBuf,
-    sync::mpsc::{Receiver, RecvError, Sender, TryRecvError},
+    sync::mpsc::{Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::anyhow;
 use anyhow::Result;
 use gallery::{
-    art_object::ArtObjectId,
-    gallery_cache::{ensure_parent_dir, GalleryCache},
-    gallery_db::{get_default_gallery_db_filename, ArtObjectQueryOptions, GalleryDb, LayoutRecord},
-    gallery_db_migration::migrate_gallery_db,
-    gallery_wall::GalleryWall,
-    image::ImageSize,
-    layout::layout,
-    met_api::{load_met_api_record, migrate_met_api_cache},
-    wikidata::{load_wikidata_image_info, WikidataImageInfo},
+    art_object::{ArtObjectId, PlacedArtObject},
+    cache_usage::CacheUsageReport,
+    gallery_cache::{ensure_parent_dir, CacheStats, GalleryCache},
+    gallery_db::{
+        check_positive_gallery_range, get_default_gallery_db_filename, get_layout_db_filename,
+        is_busy_error, is_valid_slot_name, normalize_tag, ArtObjectQueryOptions, ArtObjectRecord,
+        ArtistRecord, DistinctColumn, GalleryDb, LayoutAnchor, LayoutRecord, MaintenanceReport,
+        PlacedSearchResult, RelatedArtObjects, TagRecord, TimestampedLayoutRecord, UndoneMove,
+        LATEST_GALLERY_DB_VERSION,
+    },
+    gallery_db_migration::{adopt_older_gallery_db, migrate_gallery_db},
+    gallery_db_recovery::{
+        is_corruption_error, mark_db_for_check, recover_corrupt_gallery_db,
+        recover_corrupt_layout_db,
+    },
+    gallery_wall::{
+        check_wall_sets, hash_wall_sets, resolve_layout_wall_ids, resolve_wall_id_in_wall_sets,
+        GalleryWall, GalleryWallSet, DEFAULT_WALL_SET_NAME,
+    },
+    gallery_zone::GalleryZone,
+    image::{
+        decode_image_as_rgb8, get_image_pixel_dimensions, ImageCacheOptions, ImageFormat,
+        ImageSize, MAX_DECODED_IMAGE_PIXELS,
+    },
+    layout::{
+        balance_by_collection, estimate_layout, layout, layout_segments, layout_source_galleries,
+        move_highlights_to_front, CollectionWeight, FreeRegion, LayoutMode, LayoutOptions,
+        LayoutResult, LayoutSegment, DEFAULT_MIN_FREE_REGION_WIDTH, LAYOUT_MODE_METADATA_KEY,
+    },
+    met_api::{
+        load_cached_met_api_record, load_met_api_record, migrate_met_api_cache, MetImageUrls,
+    },
+    placement::{validate_placement, Placement},
+    shared_layout::{LayoutDocument, SharedArtObject, SharedLayout},
+    wikidata::{load_cached_wikidata_image_info, load_wikidata_image_info, WikidataImageInfo},
 };
-use rusqlite::Connection;
+use log::{debug, error, info, trace, warn};
 use serde::{Deserialize, Serialize};
 
-pub enum GdScriptResultCode {
-    /// Equivalent to GDScript's `OK` constant.
-    Ok = 0,
+use crate::preview_server::PreviewServer;
 
-    /// Equivalent to GDScript's `FAILED` constant.
-    Failed = 1,
+/// Where the non-positive layout of a save slot is autosynced to. Note that
+/// before save slots existed, this was always `autosync/user.gallery.json`,
+/// which is why the game's default slot is called `user`.
+pub fn get_autosync_gallery_path(slot: &str) -> String {
+    format!("autosync/{slot}.gallery.json")
 }
 
-impl Into<ResponseBody> for GdScriptResultCode {
-    fn into(self) -> ResponseBody {
-        ResponseBody::Integer(self as i64)
-    }
-}
+/// The layout metadata key for the hash of the wall sets used by the most recent layout.
+const WALLS_HASH_METADATA_KEY: &str = "walls_hash";
 
-const AUTOSYNC_GALLERY_PATH: &'static str = "autosync/user.gallery.json";
+/// How often to checkpoint the database's write-ahead log while we're
+/// processing requests, so it doesn't grow unbounded during long sessions.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How often to let the client know we're still alive while we're idle, see
+/// `MessageFromWorker::Heartbeat`.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(3);
+
+/// How long downloads that are in progress when the worker is told to end get
+/// to finish before they're abandoned, unless configured otherwise. See
+/// `GalleryCache::set_shutdown_signal()`.
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(3);
 
 #[derive(Debug)]
 pub struct Request {
@@ -45,7 +82,7 @@ pub struct Request {
 
 // We need to support serialization here to allow other godot clients
 // to proxy requests to and from servers.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum RequestBody {
     MoveArtObject {
         art_object_id: ArtObjectId,
@@ -53,6 +90,10 @@ pub enum RequestBody {
         wall_id: String,
         x: f64,
         y: f64,
+        /// If true, moves that would hang off the wall or overlap other art objects
+        /// are rejected. Otherwise, the art object is clamped to the wall.
+        #[serde(default)]
+        strict: bool,
     },
     GetArtObjectsForGalleryWall {
         gallery_id: i64,
@@ -61,20 +102,185 @@ pub enum RequestBody {
     FetchImage {
         object_id: ArtObjectId,
         size: ImageSize,
+        /// If true, the image is decoded on the worker thread, and sent as a
+        /// `DecodedImage` if possible.
+        #[serde(default)]
+        decode: bool,
+        /// The format to re-encode the image to when it's cached, overriding
+        /// the worker's default, see `ImageCacheOptions`.
+        #[serde(default)]
+        format: Option<ImageFormat>,
     },
     Layout {
         walls_json: String,
+        /// JSON-serialized list of wall sets. If present, this takes precedence over
+        /// `walls_json`, which is treated as a single wall set.
+        #[serde(default)]
+        wall_sets_json: Option<String>,
         filter: Option<String>,
         dense: bool,
+        /// How to arrange art objects on each wall. If absent, `dense` says
+        /// whether it's `LayoutMode::Dense` or `LayoutMode::Sparse`.
+        #[serde(default)]
+        mode: Option<LayoutMode>,
+        /// JSON-serialized list of art object IDs to lay out first, in order.
+        #[serde(default)]
+        ordering_json: Option<String>,
+        /// Names of walls to leave empty in every gallery.
+        #[serde(default)]
+        reserved_walls: Vec<String>,
+        /// If present, each segment's art objects are laid out in their own
+        /// range of galleries, in order. `filter` still applies to all of them.
+        #[serde(default)]
+        segments: Vec<LayoutSegment>,
+        /// Lay out highlighted art objects first (within each segment, if
+        /// there are any). `ordering_json` still takes precedence.
+        #[serde(default)]
+        featured_first: bool,
+        /// Let art objects that don't fit somewhere upright be rotated 90°.
+        #[serde(default)]
+        allow_rotation: bool,
+        /// If present, interleave the art objects of each collection according
+        /// to these weights (within each segment, if there are any), see
+        /// `balance_by_collection()`. An empty list gives every collection an
+        /// equal share.
+        #[serde(default)]
+        balance_by_collection: Option<Vec<CollectionWeight>>,
+        /// Include the parts of walls that were left empty in the response, see
+        /// `LayoutSummary::free_regions`.
+        #[serde(default)]
+        collect_free_regions: bool,
+        /// Free regions narrower than this are merged or dropped. Defaults to
+        /// `DEFAULT_MIN_FREE_REGION_WIDTH`.
+        #[serde(default)]
+        min_free_region_width: Option<f64>,
+        /// Leave out art objects that have ever been laid out in a positive
+        /// gallery, e.g. for rotating exhibitions.
+        #[serde(default)]
+        exclude_previously_displayed: bool,
+        /// If present, only the positive galleries in this (inclusive) range
+        /// are laid out, leaving the rest alone. Art objects hanging elsewhere
+        /// aren't laid out again.
+        #[serde(default)]
+        gallery_id_range: Option<(i64, i64)>,
+    },
+    GetGalleryWallSet {
+        gallery_id: i64,
+    },
+    GetGalleryReservedWalls {
+        gallery_id: i64,
+    },
+    /// Responds with the source gallery the given gallery recreates, see
+    /// `LayoutMode::RealGallery`, or an empty string if it doesn't.
+    GetGallerySourceGallery {
+        gallery_id: i64,
+    },
+    GetArtist {
+        qid: i64,
+    },
+    /// Responds with a `RelatedArtObjectsSummary`, see `GalleryDb::get_related_art_objects()`.
+    GetRelatedArtObjects {
+        object_id: ArtObjectId,
+        limit: usize,
+    },
+    GetGalleryGraph,
+    /// Responds with the number of positive galleries that have art in them.
+    CountGalleries,
+    ListCollections,
+    /// Saves a filter that other filters can refer to as `@<name>`.
+    SaveFilter {
+        name: String,
+        filter: String,
+    },
+    ListFilters,
+    DeleteFilter {
+        name: String,
+    },
+    /// Responds with 1 if the art object wasn't already tagged with the tag,
+    /// 0 otherwise, or an error if the tag is invalid, see `normalize_tag()`.
+    TagArtObject {
+        art_object_id: ArtObjectId,
+        tag: String,
+    },
+    /// Responds with 1 if the art object was tagged with the tag, 0 otherwise.
+    UntagArtObject {
+        art_object_id: ArtObjectId,
+        tag: String,
+    },
+    /// Responds with a JSON array of the art object's tags, in alphabetical order.
+    GetTags {
+        art_object_id: ArtObjectId,
+    },
+    /// Responds with where the art object is hung, or `None` if it isn't in the
+    /// layout, see `ResponseBody::ArtObjectPlacement`.
+    GetArtObjectPlacement {
+        art_object_id: ArtObjectId,
+    },
+    /// Responds with a JSON-serialized `LayoutEstimate` of a `Layout` with the
+    /// same walls, filter and mode, without changing the layout.
+    EstimateLayout {
+        walls_json: String,
+        filter: Option<String>,
+        dense: bool,
+        #[serde(default)]
+        mode: Option<LayoutMode>,
     },
     CountArtObjects {
         filter: Option<String>,
     },
+    /// Responds with a JSON array of at most `limit` `PlacedSearchResultSummary`s,
+    /// see `GalleryDb::search_placed_art_objects()`.
+    SearchPlaced {
+        filter: Option<String>,
+        limit: usize,
+    },
+    /// Responds with a JSON array of `DistinctValue`s, see `GalleryDb::distinct_values()`.
+    /// Unknown column names are rejected.
+    DistinctValues {
+        column: String,
+        min_count: usize,
+        limit: usize,
+    },
     Migrate,
+    /// Does everything the game needs to at startup in one go: migrates the DB,
+    /// checks its schema, and if `relayout_if_empty` is set and nothing's been
+    /// laid out yet, lays everything out on the given walls. Responds with a
+    /// JSON-serialized `ReadinessReport`.
+    EnsureReady {
+        walls_json: String,
+        relayout_if_empty: bool,
+    },
+    /// Undoes the most recent `MoveArtObject`, see `GalleryDb::undo_last_move()`.
+    UndoLastMove,
     ImportNonPositiveLayout {
         json_content: String,
+        #[serde(default)]
+        conflict_policy: ImportConflictPolicy,
+    },
+    /// Merges the non-positive layout in the given autosync JSON into ours,
+    /// keeping whichever of each art object's records was modified last, see
+    /// `merge_autosync()`. Responds with a JSON-serialized `MergeSummary`.
+    MergeNonPositiveLayout {
+        json_content: String,
+    },
+    ExportNonPositiveLayout {
+        /// Embed each art object's metadata, so the layout can be shared with
+        /// players who don't have the same database, see `SharedLayout`.
+        #[serde(default)]
+        include_metadata: bool,
+    },
+    /// Responds with a JSON `CacheInfo`.
+    GetCacheInfo,
+    /// Responds with a JSON object mapping the raw ID of each of the given art
+    /// objects to its `CachedImageState`, without touching the network.
+    GetCachedImageStates {
+        object_ids: Vec<ArtObjectId>,
+    },
+    /// Only processed once there aren't any other requests waiting, since it
+    /// can take a while.
+    Maintenance {
+        vacuum: bool,
     },
-    ExportNonPositiveLayout,
 }
 
 #[derive(Debug)]
@@ -86,16 +292,45 @@ pub struct Response {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum ResponseBody {
-    ArtObjectsForGalleryWall(Vec<SimplifiedRecord>),
-    Image(Option<PathBuf>),
+    ArtObjectsForGalleryWall(Vec<PlacedArtObject>),
+    Image {
+        path: Option<PathBuf>,
+        #[serde(default)]
+        pixel_width: Option<u32>,
+        #[serde(default)]
+        pixel_height: Option<u32>,
+    },
+    /// Pixels in the same layout as Godot's `Image.FORMAT_RGB8`.
+    DecodedImage {
+        width: u32,
+        height: u32,
+        rgb8: Vec<u8>,
+    },
+    Artist(Option<ArtistRecord>),
+    /// Where the art object actually ended up, which may differ from where it was
+    /// requested to be moved if it was clamped to the wall.
+    ArtObjectMoved {
+        x: f64,
+        y: f64,
+    },
+    /// The art object couldn't be moved, for the given reason. Unlike `Error`, this
+    /// is an expected outcome that should be shown to the user.
+    MoveRejected(String),
+    /// Where an art object is hung, or `None` if it isn't in the layout.
+    ArtObjectPlacement(Option<LayoutRecord<String>>),
+    Maintenance(MaintenanceReport),
     Empty,
     Integer(i64),
     String(String),
+    Error(String),
 }
 
 pub enum MessageToWorker {
     End,
     Request(Request),
+    /// Whether images should only be looked up in the cache, rather than
+    /// fetched from the network, e.g. because the player is on a flight.
+    SetOffline(bool),
 }
 
 impl RequestBody {
@@ -106,63 +341,695 @@ impl RequestBody {
         // everything is called directly by the server itself.
         false
     }
+
+    /// Whether this request modifies the gallery database. Such requests are rejected
+    /// when the worker has been started in read-only mode.
+    pub fn is_mutating(&self) -> bool {
+        match self {
+            RequestBody::MoveArtObject { .. } => true,
+            RequestBody::Layout { .. } => true,
+            RequestBody::ImportNonPositiveLayout { .. } => true,
+            RequestBody::MergeNonPositiveLayout { .. } => true,
+            RequestBody::Migrate => true,
+            RequestBody::EnsureReady { .. } => true,
+            RequestBody::UndoLastMove => true,
+            RequestBody::Maintenance { .. } => true,
+            RequestBody::SaveFilter { .. } => true,
+            RequestBody::DeleteFilter { .. } => true,
+            RequestBody::TagArtObject { .. } => true,
+            RequestBody::UntagArtObject { .. } => true,
+            RequestBody::GetArtObjectsForGalleryWall { .. } => false,
+            RequestBody::FetchImage { .. } => false,
+            RequestBody::GetGalleryWallSet { .. } => false,
+            RequestBody::GetGallerySourceGallery { .. } => false,
+            RequestBody::GetGalleryReservedWalls { .. } => false,
+            RequestBody::GetArtist { .. } => false,
+            RequestBody::GetRelatedArtObjects { .. } => false,
+            RequestBody::GetGalleryGraph => false,
+            RequestBody::CountGalleries => false,
+            RequestBody::ListCollections => false,
+            RequestBody::ListFilters => false,
+            RequestBody::GetTags { .. } => false,
+            RequestBody::GetArtObjectPlacement { .. } => false,
+            RequestBody::EstimateLayout { .. } => false,
+            RequestBody::CountArtObjects { .. } => false,
+            RequestBody::SearchPlaced { .. } => false,
+            RequestBody::DistinctValues { .. } => false,
+            RequestBody::ExportNonPositiveLayout { .. } => false,
+            RequestBody::GetCacheInfo => false,
+            RequestBody::GetCachedImageStates { .. } => false,
+        }
+    }
 }
 
-pub enum MessageFromWorker {
-    Done,
-    FatalError(String),
-    Response(Response),
+/// Sent as a JSON string in response to a `RequestBody::Layout`. Fields are
+/// defaulted so that responses from older workers, e.g. proxied over the
+/// network, can still be parsed.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct LayoutSummary {
+    #[serde(default)]
+    pub galleries_created: usize,
+    /// The ID of the first gallery that was created, if any.
+    #[serde(default)]
+    pub first_gallery_id: Option<i64>,
+    /// The ID of the last gallery that was created, if any.
+    #[serde(default)]
+    pub last_gallery_id: Option<i64>,
+    /// The number of art objects that were too big to fit on any walls.
+    #[serde(default)]
+    pub unplaceable: usize,
+    /// The number of art objects in non-positive galleries that are on walls
+    /// that don't exist, e.g. because of a typo in an imported layout.
+    #[serde(default)]
+    pub unknown_wall_records: usize,
+    /// The parts of walls that were left empty, e.g. for props, if they were
+    /// asked for.
+    #[serde(default)]
+    pub free_regions: Vec<FreeRegion<String>>,
+    /// The source galleries that were left out of a `LayoutMode::RealGallery`
+    /// layout because their art objects don't fit in a single gallery.
+    #[serde(default)]
+    pub dropped_source_galleries: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct SimplifiedRecord {
-    pub object_id: ArtObjectId,
+/// Sent as a JSON string in response to a `RequestBody::GetCacheInfo`. The
+/// usage report's fields are at the top level, as they were before the stats
+/// were added.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct CacheInfo {
+    #[serde(flatten)]
+    pub usage: CacheUsageReport,
+    /// What the worker's cache has fetched since it started.
+    #[serde(default)]
+    pub stats: CacheStats,
+}
+
+/// Sent as a JSON string in response to a `RequestBody::EnsureReady`. Steps that
+/// fail don't stop the ones after them, they're just mentioned in `warnings`.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ReadinessReport {
+    /// The DB's schema version after migrating, if it records one.
+    #[serde(default)]
+    pub db_version: Option<usize>,
+    #[serde(default)]
+    pub object_count: usize,
+    /// The number of positive galleries that have art in them.
+    #[serde(default)]
+    pub gallery_count: usize,
+    /// Whether the DB's schema or layout needed migrating.
+    #[serde(default)]
+    pub migrated: bool,
+    /// Whether an initial layout was created.
+    #[serde(default)]
+    pub laid_out: bool,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// Sent as part of the JSON response to a `RequestBody::SearchPlaced`. Its ID
+/// is the raw one that Godot uses, see `ArtObjectId::to_raw_i64()`. The
+/// gallery, wall and position are `None` unless the art object is `placed`.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct PlacedSearchResultSummary {
+    pub object_id: i64,
+    pub title: String,
     pub artist: String,
-    pub medium: String,
+    pub placed: bool,
+    pub gallery_id: Option<i64>,
+    pub wall_id: Option<String>,
+    pub x: Option<f64>,
+}
+
+impl From<PlacedSearchResult> for PlacedSearchResultSummary {
+    fn from(result: PlacedSearchResult) -> Self {
+        let placed = result.placement.is_some();
+        let (gallery_id, wall_id, x) = match result.placement {
+            Some(placement) => (
+                Some(placement.gallery_id),
+                Some(placement.wall_id),
+                Some(placement.x),
+            ),
+            None => (None, None, None),
+        };
+        PlacedSearchResultSummary {
+            object_id: result.object_id.to_raw_i64(),
+            title: result.title,
+            artist: result.artist,
+            placed,
+            gallery_id,
+            wall_id,
+            x,
+        }
+    }
+}
+
+/// Which sizes of an art object's image are cached locally, sent as part of the
+/// response to a `RequestBody::GetCachedImageStates`.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct CachedImageState {
+    #[serde(default)]
+    pub small: bool,
+    #[serde(default)]
+    pub large: bool,
+}
+
+/// Sent as a JSON string in response to a `RequestBody::ImportNonPositiveLayout`.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ImportSummary {
+    #[serde(default)]
+    pub imported: usize,
+    /// The number of records that were left out because their walls don't
+    /// exist, see `resolve_layout_wall_ids()`.
+    #[serde(default)]
+    pub skipped: usize,
+    /// The art objects in a shared layout that aren't in our database, so their
+    /// records were left out too.
+    #[serde(default)]
+    pub unknown_art_objects: Vec<SharedArtObject>,
+    /// The number of records for art objects that were hanging in positive
+    /// galleries. Whether they were imported depends on the `ImportConflictPolicy`.
+    #[serde(default)]
+    pub conflicted: usize,
+    /// Records that were left out because they're in generated galleries, which
+    /// the next layout would replace anyway, e.g. because the layout was edited
+    /// by hand.
+    #[serde(default)]
+    pub rejected: Vec<RejectedLayoutRecord>,
+}
+
+/// A record that `RequestBody::ImportNonPositiveLayout` left out, see
+/// `ImportSummary::rejected`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RejectedLayoutRecord {
+    pub art_object_id: ArtObjectId,
+    pub gallery_id: i64,
+    pub reason: String,
+}
+
+/// Sent as a JSON string in response to a `RequestBody::MergeNonPositiveLayout`.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct MergeSummary {
+    /// The number of records that were taken from the merged layout, because
+    /// they were newer than ours or we didn't have them.
+    #[serde(default)]
+    pub merged: usize,
+    /// The number of records in the merged layout that were no newer than ours.
+    #[serde(default)]
+    pub kept: usize,
+    /// The number of records that were left out because their walls don't
+    /// exist, see `resolve_layout_wall_ids()`.
+    #[serde(default)]
+    pub skipped: usize,
+}
+
+/// What `RequestBody::ImportNonPositiveLayout` does with records for art objects
+/// that are currently hanging in positive galleries.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportConflictPolicy {
+    /// Leave the art objects where they are, and leave their records out.
+    #[default]
+    Skip,
+    /// Move the art objects out of the positive galleries.
+    Overwrite,
+    /// Don't import anything.
+    Fail,
+}
+
+impl ImportConflictPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "skip" => Some(ImportConflictPolicy::Skip),
+            "overwrite" => Some(ImportConflictPolicy::Overwrite),
+            "fail" => Some(ImportConflictPolicy::Fail),
+            _ => None,
+        }
+    }
+}
+
+/// Sent as part of a JSON array in response to a `RequestBody::DistinctValues`.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct DistinctValue {
+    pub value: String,
+    pub count: usize,
+}
+
+/// An art object in a `RelatedArtObjectsSummary`. Its ID is the raw one
+/// that Godot uses, see `ArtObjectId::to_raw_i64()`.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct RelatedArtObject {
+    pub object_id: i64,
     pub title: String,
+    pub artist: String,
+    pub artist_qid: Option<i64>,
     pub date: String,
+    pub medium: String,
+    pub collection: String,
     pub width: f64,
     pub height: f64,
+    pub highlight: bool,
+}
+
+impl From<ArtObjectRecord> for RelatedArtObject {
+    fn from(record: ArtObjectRecord) -> Self {
+        RelatedArtObject {
+            object_id: record.object_id.to_raw_i64(),
+            title: record.title,
+            artist: record.artist,
+            artist_qid: record.artist_qid,
+            date: record.object_date,
+            medium: record.medium,
+            collection: record.collection,
+            width: record.width,
+            height: record.height,
+            highlight: record.highlight,
+        }
+    }
+}
+
+/// Sent as a JSON string in response to a `RequestBody::GetRelatedArtObjects`.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct RelatedArtObjectsSummary {
+    #[serde(default)]
+    pub same_artist: Vec<RelatedArtObject>,
+    #[serde(default)]
+    pub same_medium_century: Vec<RelatedArtObject>,
+}
+
+impl From<RelatedArtObjects> for RelatedArtObjectsSummary {
+    fn from(related: RelatedArtObjects) -> Self {
+        RelatedArtObjectsSummary {
+            same_artist: related.same_artist.into_iter().map(Into::into).collect(),
+            same_medium_century: related
+                .same_medium_century
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
+
+/// Sent as a JSON string in response to a `RequestBody::UndoLastMove`, so the
+/// game can animate the art object back to where it was.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct UndoneMoveSummary {
+    /// The raw ID that Godot uses, see `ArtObjectId::to_raw_i64()`.
+    pub art_object_id: i64,
+    /// Where the art object was put back, or `None` if it was removed from the
+    /// layout, since it wasn't in it before it was moved.
+    pub restored: Option<RestoredPosition>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct RestoredPosition {
+    pub gallery_id: i64,
+    pub wall_id: String,
     pub x: f64,
     pub y: f64,
-    pub collection: String,
+    pub rotated: bool,
+}
+
+impl From<UndoneMove> for UndoneMoveSummary {
+    fn from(undone: UndoneMove) -> Self {
+        UndoneMoveSummary {
+            art_object_id: undone.art_object_id.to_raw_i64(),
+            restored: undone.restored.map(|record| RestoredPosition {
+                gallery_id: record.gallery_id,
+                wall_id: record.wall_id,
+                x: record.x,
+                y: record.y,
+                rotated: record.rotated,
+            }),
+        }
+    }
+}
+
+pub enum MessageFromWorker {
+    /// Sent once the database has been opened and we're ready to process requests.
+    Ready,
+    /// Sent before `Ready` if the database was corrupt and had to be rebuilt, with
+    /// a human-readable explanation of what happened.
+    DbRecovered(String),
+    /// Sent periodically from the top of the worker's loop, so the client can
+    /// tell the difference between a busy worker and a hung one.
+    Heartbeat,
+    Done,
+    FatalError(String),
+    Response(Response),
+}
+
+/// Waits for the worker to send `MessageFromWorker::Done` (or otherwise finish),
+/// ignoring any responses it sends first, e.g. to requests that were in
+/// progress when it was told to end. Returns false if it took too long.
+pub fn wait_for_done(from_worker_rx: &Receiver<MessageFromWorker>, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match from_worker_rx.recv_timeout(remaining) {
+            Ok(MessageFromWorker::Done) | Err(RecvTimeoutError::Disconnected) => return true,
+            Ok(MessageFromWorker::FatalError(message)) => {
+                warn!("Gallery worker thread errored while ending: {message}");
+                return true;
+            }
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => return false,
+        }
+    }
 }
 
 fn get_art_objects_for_gallery_wall(
     db: &mut GalleryDb,
     gallery_id: i64,
     wall_id: String,
-) -> Result<Vec<SimplifiedRecord>> {
-    let mut result = vec![];
+) -> Result<Vec<PlacedArtObject>> {
     let objects = db.get_art_objects_for_gallery_wall(gallery_id, wall_id)?;
-    for (object, (x, y)) in objects {
-        result.push(SimplifiedRecord {
-            object_id: object.object_id,
-            title: object.title,
-            date: object.object_date,
-            width: object.width,
-            height: object.height,
-            artist: object.artist,
-            medium: object.medium,
-            collection: object.collection,
-            x,
-            y,
+    Ok(objects.into_iter().map(PlacedArtObject::from).collect())
+}
+
+/// Filters can refer to saved filters that don't exist or are otherwise broken.
+/// That's the player's mistake rather than a fatal error, so it's reported as an
+/// error response.
+fn check_filter(db: &GalleryDb, options: &ArtObjectQueryOptions) -> Option<ResponseBody> {
+    db.where_clause(options)
+        .err()
+        .map(|err| ResponseBody::Error(err.to_string()))
+}
+
+/// Find the dimensions of a wall, if we know them.
+///
+/// For galleries that were created by a layout, we know which wall set they
+/// use. Other galleries (e.g. non-positive ones) don't have a wall set on
+/// record, so we assume they use the only wall set, if there's just one.
+fn find_wall<'a>(
+    db: &GalleryDb,
+    wall_sets: &'a [GalleryWallSet],
+    gallery_id: i64,
+    wall_id: &str,
+) -> Result<Option<&'a GalleryWall>> {
+    let wall_set = match db.get_gallery_record(gallery_id)? {
+        Some(gallery) => wall_sets
+            .iter()
+            .find(|wall_set| wall_set.name == gallery.wall_set),
+        None if wall_sets.len() == 1 => wall_sets.first(),
+        None => None,
+    };
+    Ok(wall_set.and_then(|wall_set| wall_set.walls.iter().find(|wall| wall.name == wall_id)))
+}
+
+fn move_art_object(
+    db: &mut GalleryDb,
+    wall_sets: &[GalleryWallSet],
+    record: LayoutRecord<String>,
+    strict: bool,
+) -> Result<ResponseBody> {
+    let Some(art_object) = db.get_art_object(record.art_object_id)? else {
+        return Ok(ResponseBody::Error(format!(
+            "Art object does not exist: {:?}",
+            record.art_object_id
+        )));
+    };
+    let wall = find_wall(db, wall_sets, record.gallery_id, &record.wall_id)?;
+    let others: Vec<(ArtObjectId, Placement)> = db
+        .get_art_objects_for_gallery_wall(record.gallery_id, &record.wall_id)?
+        .into_iter()
+        .filter(|(other, _, _)| other.object_id != record.art_object_id)
+        .map(|(other, (x, y), rotated)| {
+            let (width, height) = if rotated {
+                (other.height, other.width)
+            } else {
+                (other.width, other.height)
+            };
+            (
+                other.object_id,
+                Placement {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+            )
+        })
+        .collect();
+    let placement = Placement {
+        x: record.x,
+        y: record.y,
+        width: art_object.width,
+        height: art_object.height,
+    };
+    let placement = match validate_placement(placement, wall, &others, strict) {
+        Ok(placement) => placement,
+        Err(err) => return Ok(ResponseBody::MoveRejected(err.to_string())),
+    };
+    let art_object_id = record.art_object_id;
+    db.upsert_layout_record_with_history(&LayoutRecord {
+        x: placement.x,
+        y: placement.y,
+        ..record
+    })?;
+    if let Some(wall) = wall {
+        db.set_layout_anchors(&[(
+            art_object_id,
+            LayoutAnchor::from_position(placement.x, placement.y, wall),
+        )])?;
+    }
+    Ok(ResponseBody::ArtObjectMoved {
+        x: placement.x,
+        y: placement.y,
+    })
+}
+
+/// Responds with an `UndoneMoveSummary`, or JSON null if there was nothing to undo.
+fn undo_last_move(db: &mut GalleryDb, wall_sets: &[GalleryWallSet]) -> Result<ResponseBody> {
+    let Some(undone) = db.undo_last_move()? else {
+        return Ok(ResponseBody::String("null".to_string()));
+    };
+    if let Some(record) = &undone.restored {
+        // Restoring the position clears the anchor, so set it like `move_art_object()` does.
+        if let Some(wall) = find_wall(db, wall_sets, record.gallery_id, &record.wall_id)? {
+            db.set_layout_anchors(&[(
+                undone.art_object_id,
+                LayoutAnchor::from_position(record.x, record.y, wall),
+            )])?;
+        }
+    }
+    let summary = UndoneMoveSummary::from(undone);
+    Ok(ResponseBody::String(serde_json::to_string(&summary)?))
+}
+
+fn get_wall_sets(walls_json: &str, wall_sets_json: Option<&str>) -> Result<Vec<GalleryWallSet>> {
+    if let Some(wall_sets_json) = wall_sets_json {
+        return Ok(serde_json::from_str(wall_sets_json)?);
+    }
+    let walls: Vec<GalleryWall> = serde_json::from_str(walls_json)?;
+    Ok(vec![GalleryWallSet::new(DEFAULT_WALL_SET_NAME, walls)])
+}
+
+/// Replaces the layout of positive galleries with the given one, remembering
+/// the hash of the wall sets and the mode it was made with.
+fn save_layout_result(
+    db: &mut GalleryDb,
+    result: &LayoutResult,
+    wall_sets: &[GalleryWallSet],
+    walls_hash: &str,
+    mode: LayoutMode,
+) -> Result<()> {
+    db.set_layout_records_in_positive_galleries(&result.layout_records)?;
+    db.set_gallery_records_in_positive_galleries(&result.gallery_records)?;
+    db.set_layout_anchors(&result.anchors(wall_sets))?;
+    db.set_layout_metadata(WALLS_HASH_METADATA_KEY, walls_hash)?;
+    db.set_layout_metadata(LAYOUT_MODE_METADATA_KEY, mode.name())?;
+    Ok(())
+}
+
+/// Like `save_layout_result()`, for a layout confined to the given range of
+/// galleries. The walls hash isn't updated, since galleries outside the range
+/// were laid out on whatever walls they were before.
+fn save_layout_result_in_gallery_range(
+    db: &mut GalleryDb,
+    result: &LayoutResult,
+    wall_sets: &[GalleryWallSet],
+    range: (i64, i64),
+) -> Result<()> {
+    db.set_layout_records_in_gallery_range(&result.layout_records, range)?;
+    db.set_gallery_records_in_gallery_range(&result.gallery_records, range)?;
+    db.set_layout_anchors(&result.anchors(wall_sets))?;
+    Ok(())
+}
+
+/// Lays out every art object that isn't in a non-positive gallery on the given
+/// walls, with the default options. Returns the number of galleries created.
+fn create_initial_layout(db: &mut GalleryDb, wall_sets: &Vec<GalleryWallSet>) -> Result<usize> {
+    let art_objects = db.get_all_art_objects_for_layout(&Default::default())?;
+    let except_art_object_ids = db.get_art_object_ids_in_non_positive_galleries()?;
+    let result = layout(
+        wall_sets,
+        art_objects,
+        &LayoutOptions {
+            except_art_object_ids: Some(&except_art_object_ids),
+            ..Default::default()
+        },
+    )?;
+    save_layout_result(
+        db,
+        &result,
+        wall_sets,
+        &hash_wall_sets(wall_sets)?,
+        LayoutMode::Sparse,
+    )?;
+    info!(
+        "Created initial layout across {} galleries, {} unplaceable.",
+        result.galleries_created,
+        result.unplaceable_art_object_ids.len()
+    );
+    Ok(result.galleries_created)
+}
+
+/// Performs a `RequestBody::EnsureReady`. Returns the wall sets of the initial
+/// layout too, if one was created.
+fn ensure_ready(
+    cache: &GalleryCache,
+    slot: &str,
+    db: &mut GalleryDb,
+    walls_json: &str,
+    relayout_if_empty: bool,
+) -> (ReadinessReport, Option<Vec<GalleryWallSet>>) {
+    let mut report = ReadinessReport::default();
+    let mut warnings = vec![];
+    let mut warn_about = |step: &str, err: anyhow::Error| {
+        let warning = format!("{step} failed: {err}");
+        warn!("{warning}");
+        warnings.push(warning);
+    };
+    let version_before = db.schema_version().ok().flatten();
+    match migrate_gallery_db(cache, slot) {
+        Ok(migrated_layout) => report.migrated = migrated_layout,
+        Err(err) => warn_about("Migration", err),
+    }
+    match db.schema_version() {
+        Ok(version) => {
+            report.db_version = version;
+            report.migrated |= version != version_before;
+        }
+        Err(err) => warn_about("Getting schema version", err),
+    }
+    match db.check_schema() {
+        Ok(schema_report) => {
+            for problem in schema_report.problems() {
+                warn_about("Schema check", anyhow!(problem));
+            }
+        }
+        Err(err) => warn_about("Schema check", err),
+    }
+    match db.count_art_objects(&Default::default()) {
+        Ok(count) => report.object_count = count,
+        Err(err) => warn_about("Counting art objects", err),
+    }
+    let gallery_count = match db.count_positive_galleries() {
+        Ok(count) => Some(count),
+        Err(err) => {
+            warn_about("Counting galleries", err);
+            None
+        }
+    };
+    report.gallery_count = gallery_count.unwrap_or_default();
+    let mut wall_sets = None;
+    if relayout_if_empty && gallery_count == Some(0) {
+        let result = get_wall_sets(walls_json, None).and_then(|new_wall_sets| {
+            let galleries_created = create_initial_layout(db, &new_wall_sets)?;
+            Ok((galleries_created, new_wall_sets))
         });
+        match result {
+            Ok((galleries_created, new_wall_sets)) => {
+                report.gallery_count = galleries_created;
+                report.laid_out = true;
+                wall_sets = Some(new_wall_sets);
+            }
+            Err(err) => warn_about("Initial layout", err),
+        }
+    }
+    report.warnings = warnings;
+    (report, wall_sets)
+}
+
+fn image_response(path: Option<PathBuf>) -> ResponseBody {
+    let dimensions = path
+        .as_ref()
+        .map(|path| get_image_pixel_dimensions(path))
+        .flatten();
+    ResponseBody::Image {
+        path,
+        pixel_width: dimensions.map(|(width, _)| width),
+        pixel_height: dimensions.map(|(_, height)| height),
+    }
+}
+
+/// Like `image_response()`, but decodes the image so the main thread doesn't
+/// have to. Images with more than `max_pixels` pixels, or that can't be
+/// decoded, are responded to with their path instead.
+fn decoded_image_response(path: Option<PathBuf>, max_pixels: u64) -> ResponseBody {
+    if let Some(path) = &path {
+        match decode_image_as_rgb8(path, max_pixels) {
+            Ok(Some(image)) => {
+                return ResponseBody::DecodedImage {
+                    width: image.width,
+                    height: image.height,
+                    rgb8: image.rgb8,
+                };
+            }
+            Ok(None) => {
+                debug!("{} is too big to decode, sending its path.", path.display());
+            }
+            Err(err) => {
+                warn!("Unable to decode {}: {:?}", path.display(), err);
+            }
+        }
     }
-    Ok(result)
+    image_response(path)
 }
 
+/// Note that when the cache is offline, the fetch functions only look in the
+/// cache, so they respond immediately without touching the network.
 fn fetch_met_api_image(
+    db: &GalleryDb,
     cache: &GalleryCache,
     met_object_id: i64,
     size: ImageSize,
+    options: &ImageCacheOptions,
 ) -> Option<PathBuf> {
+    // If the art object's image URLs were stored in the DB at import time, we
+    // don't need its Met API record to find its image.
+    match db.get_met_image_urls(met_object_id) {
+        Ok(Some(urls)) => {
+            return fetch_met_image_from_urls(cache, met_object_id, &urls, size, options)
+        }
+        Ok(None) => {}
+        Err(err) => {
+            warn!(
+                "Unable to get image URLs for met object ID {}: {:?}",
+                met_object_id, err
+            );
+        }
+    }
+    if cache.is_offline() {
+        return match load_cached_met_api_record(cache, met_object_id) {
+            Ok(obj_record) => obj_record
+                .and_then(|obj_record| obj_record.get_cached_image(cache, size))
+                .map(|image| cache.cache_dir().join(image)),
+            Err(err) => {
+                warn!(
+                    "Unable to load cached Met API record for met object ID {}: {:?}",
+                    met_object_id, err
+                );
+                None
+            }
+        };
+    }
     match load_met_api_record(&cache, met_object_id) {
-        Ok(obj_record) => match obj_record.try_to_download_image(&cache, size) {
+        Ok(obj_record) => match obj_record.try_to_download_image(&cache, size, options) {
             Ok(Some(image)) => Some(cache.cache_dir().join(image)),
             Ok(None) => None,
             Err(err) => {
-                eprintln!(
+                warn!(
                     "Unable to download {size} image for met object ID {}: {:?}",
                     met_object_id, err
                 );
@@ -170,7 +1037,7 @@ fn fetch_met_api_image(
             }
         },
         Err(err) => {
-            eprintln!(
+            warn!(
                 "Unable to load Met API record for met object ID {}: {:?}",
                 met_object_id, err
             );
@@ -179,11 +1046,36 @@ fn fetch_met_api_image(
     }
 }
 
+fn fetch_met_image_from_urls(
+    cache: &GalleryCache,
+    met_object_id: i64,
+    urls: &MetImageUrls,
+    size: ImageSize,
+    options: &ImageCacheOptions,
+) -> Option<PathBuf> {
+    if cache.is_offline() {
+        return urls
+            .get_cached_image(cache, met_object_id, size)
+            .map(|image| cache.cache_dir().join(image));
+    }
+    match urls.try_to_download_image(cache, met_object_id, size, options) {
+        Ok(image) => image.map(|image| cache.cache_dir().join(image)),
+        Err(err) => {
+            warn!(
+                "Unable to download {size} image for met object ID {}: {:?}",
+                met_object_id, err
+            );
+            None
+        }
+    }
+}
+
 fn try_to_download_wikidata_image(
     db: &GalleryDb,
     cache: &GalleryCache,
     object_id: ArtObjectId,
     size: ImageSize,
+    options: &ImageCacheOptions,
 ) -> Result<Option<PathBuf>> {
     if let Some(record) = db.get_art_object(object_id)? {
         if let ArtObjectId::Wikidata(qid) = object_id {
@@ -194,14 +1086,17 @@ fn try_to_download_wikidata_image(
                     image_filename: record.filename,
                 },
                 size,
+                options,
             ))
         } else if let Some(qid) = record.fallback_wikidata_qid {
-            Ok(fetch_wikidata_image_from_qid_only(&cache, qid, size))
+            Ok(fetch_wikidata_image_from_qid_only(
+                &cache, qid, size, options,
+            ))
         } else {
             Ok(None)
         }
     } else {
-        println!("WARNING: Could not find {:?} in the database.", object_id);
+        warn!("Could not find {:?} in the database.", object_id);
         Ok(None)
     }
 }
@@ -210,11 +1105,17 @@ fn fetch_wikidata_image_from_qid_and_filename(
     cache: &GalleryCache,
     info: WikidataImageInfo,
     size: ImageSize,
+    options: &ImageCacheOptions,
 ) -> Option<PathBuf> {
-    match info.try_to_download_image(&cache, size) {
+    if cache.is_offline() {
+        return info
+            .get_cached_image(cache, size)
+            .map(|filename| cache.cache_dir().join(filename));
+    }
+    match info.try_to_download_image(&cache, size, options) {
         Ok(filename) => Some(cache.cache_dir().join(filename)),
         Err(err) => {
-            eprintln!(
+            warn!(
                 "Unable to fetch wikidata image for Q{}: {:?}",
                 info.qid, err
             );
@@ -227,20 +1128,99 @@ fn fetch_wikidata_image_from_qid_only(
     cache: &GalleryCache,
     qid: i64,
     size: ImageSize,
+    options: &ImageCacheOptions,
 ) -> Option<PathBuf> {
-    match load_wikidata_image_info(&cache, qid) {
-        Ok(Some(info)) => fetch_wikidata_image_from_qid_and_filename(cache, info, size),
+    let info = if cache.is_offline() {
+        load_cached_wikidata_image_info(cache, qid)
+    } else {
+        load_wikidata_image_info(cache, qid)
+    };
+    match info {
+        Ok(Some(info)) => fetch_wikidata_image_from_qid_and_filename(cache, info, size, options),
         Ok(None) => {
-            eprintln!("Wikidata has no image info for Q{qid}.");
+            warn!("Wikidata has no image info for Q{qid}.");
             None
         }
         Err(err) => {
-            eprintln!("Unable to fetch wikidata image info for Q{qid}: {:?}", err);
+            warn!("Unable to fetch wikidata image info for Q{qid}: {:?}", err);
             None
         }
     }
 }
 
+/// Returns the filename, relative to the cache directory, of the image that
+/// `FetchImage` would respond with for the given art object if we were offline.
+fn get_cached_image(
+    db: &GalleryDb,
+    cache: &GalleryCache,
+    object_id: ArtObjectId,
+    size: ImageSize,
+) -> Result<Option<String>> {
+    if let ArtObjectId::Met(met_object_id) = object_id {
+        let image = match db.get_met_image_urls(met_object_id)? {
+            Some(urls) => urls.get_cached_image(cache, met_object_id, size),
+            None => load_cached_met_api_record(cache, met_object_id)?
+                .and_then(|obj_record| obj_record.get_cached_image(cache, size)),
+        };
+        if image.is_some() {
+            return Ok(image);
+        }
+    }
+    let Some(record) = db.get_art_object(object_id)? else {
+        return Ok(None);
+    };
+    let info = match object_id {
+        ArtObjectId::Wikidata(qid) => Some(WikidataImageInfo {
+            qid,
+            image_filename: record.filename,
+        }),
+        ArtObjectId::Met(_) => match record.fallback_wikidata_qid {
+            Some(qid) => load_cached_wikidata_image_info(cache, qid)?,
+            None => None,
+        },
+    };
+    Ok(info.and_then(|info| info.get_cached_image(cache, size)))
+}
+
+/// Performs a `RequestBody::GetCachedImageStates`.
+fn get_cached_image_states(
+    db: &GalleryDb,
+    cache: &GalleryCache,
+    object_ids: &[ArtObjectId],
+) -> BTreeMap<i64, CachedImageState> {
+    let is_cached = |object_id: ArtObjectId, size: ImageSize| match get_cached_image(
+        db, cache, object_id, size,
+    ) {
+        Ok(image) => image.is_some(),
+        Err(err) => {
+            warn!("Unable to find cached {size} image for {object_id:?}: {err:?}");
+            false
+        }
+    };
+    object_ids
+        .iter()
+        .map(|&object_id| {
+            let state = CachedImageState {
+                small: is_cached(object_id, ImageSize::Small),
+                large: is_cached(object_id, ImageSize::Large),
+            };
+            (object_id.to_raw_i64(), state)
+        })
+        .collect()
+}
+
+/// Whether there are any requests in the queue other than maintenance ones.
+fn has_pending_non_maintenance_requests(
+    queue: &VecDeque<Result<MessageToWorker, RecvError>>,
+) -> bool {
+    queue.iter().any(|message| match message {
+        Ok(MessageToWorker::Request(request)) => {
+            !matches!(request.body, RequestBody::Maintenance { .. })
+        }
+        _ => false,
+    })
+}
+
 fn fill_queue(
     queue: &mut VecDeque<Result<MessageToWorker, RecvError>>,
     to_worker_rx: &Receiver<MessageToWorker>,
@@ -250,13 +1230,18 @@ fn fill_queue(
     // been queued up. This is intentional: if the user suddenly decides to quit,
     // we want to quit ASAP, effectively aborting all in-flight requests.
     if queue.len() == 0 {
-        // We don't have anything in the queue, so wait until we do.
-        match to_worker_rx.recv() {
+        // We don't have anything in the queue, so wait until we do, or until
+        // it's time to send another heartbeat, in which case the queue will
+        // still be empty.
+        match to_worker_rx.recv_timeout(HEARTBEAT_INTERVAL) {
             Ok(MessageToWorker::End) => {
                 queue.push_front(Ok(MessageToWorker::End));
                 return;
             }
-            Err(RecvError) => {
+            Err(RecvTimeoutError::Timeout) => {
+                return;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
                 queue.push_front(Err(RecvError));
                 return;
             }
@@ -287,39 +1272,227 @@ fn fill_queue(
     }
 }
 
+/// How many times to try opening the database when another connection keeps
+/// it locked for longer than `gallery_db::BUSY_TIMEOUT`, e.g. a worker that was replaced by
+/// `GalleryClient::force_reconnect()` and is still finishing a write.
+const OPEN_DB_ATTEMPTS: usize = 3;
+
+/// Calls `f` until it succeeds, fails for a reason other than the database
+/// being busy, or has been tried `OPEN_DB_ATTEMPTS` times.
+fn retry_while_busy<T>(what: &str, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(err) if is_busy_error(&err) && attempt < OPEN_DB_ATTEMPTS => {
+                warn!("DB was busy while {what} (attempt {attempt} of {OPEN_DB_ATTEMPTS}), trying again.");
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Opens the database, attaching the save slot's layout DB unless it doesn't
+/// exist and can't be created. Returns the database along with the path of the
+/// attached layout DB, if any.
+fn open_gallery_db(
+    db_path: &PathBuf,
+    layout_db_path: &PathBuf,
+    read_only: bool,
+) -> Result<(GalleryDb, Option<PathBuf>)> {
+    let mut db = GalleryDb::open(db_path, read_only)?;
+    // Older schemas are migrated in place by `RequestBody::Migrate`, but
+    // there's no telling what a newer one looks like.
+    if let Some(version) = db.schema_version()? {
+        if version > LATEST_GALLERY_DB_VERSION {
+            return Err(anyhow!(
+                "DB schema version {version} is newer than the latest supported version {LATEST_GALLERY_DB_VERSION}: {}",
+                db_path.display()
+            ));
+        }
+    }
+    if read_only && !layout_db_path.exists() {
+        // We can't create the slot's layout DB, so fall back to whatever
+        // layout is in the default DB.
+        info!(
+            "Layout DB does not exist, using default DB layout: {}",
+            layout_db_path.display()
+        );
+        return Ok((db, None));
+    }
+    db.attach_layout_db(layout_db_path)?;
+    if !read_only {
+        db.copy_main_layout_into_layout_db()?;
+    }
+    Ok((db, Some(layout_db_path.clone())))
+}
+
 pub fn work_thread(
-    root_dir: PathBuf,
+    cache: GalleryCache,
+    slot: String,
+    enable_autosync: bool,
+    read_only: bool,
+    preview_server_port: Option<u16>,
+    image_cache_options: ImageCacheOptions,
+    to_worker_rx: Receiver<MessageToWorker>,
+    from_worker_tx: Sender<MessageFromWorker>,
+) -> Result<()> {
+    let mut db_paths = vec![cache.get_cached_path(get_default_gallery_db_filename())];
+    if is_valid_slot_name(&slot) {
+        db_paths.push(cache.get_cached_path(get_layout_db_filename(&slot)));
+    }
+    let result = run_work_thread(
+        cache,
+        slot,
+        enable_autosync,
+        read_only,
+        preview_server_port,
+        image_cache_options,
+        to_worker_rx,
+        from_worker_tx,
+    );
+    if let Err(err) = &result {
+        // Checking the whole database takes too long to do on every startup,
+        // so we only do it once we know something's wrong. We don't know which
+        // of the databases is corrupt, so they all get checked.
+        if !read_only && is_corruption_error(err) {
+            warn!("DB is corrupt, it will be checked on the next startup.");
+            for db_path in db_paths {
+                if let Err(err) = mark_db_for_check(&db_path) {
+                    warn!("Unable to mark {} for checking: {err:?}", db_path.display());
+                }
+            }
+        }
+    }
+    result
+}
+
+fn run_work_thread(
+    mut cache: GalleryCache,
+    slot: String,
     enable_autosync: bool,
+    read_only: bool,
+    preview_server_port: Option<u16>,
+    image_cache_options: ImageCacheOptions,
     to_worker_rx: Receiver<MessageToWorker>,
     from_worker_tx: Sender<MessageFromWorker>,
 ) -> Result<()> {
-    let cache = GalleryCache::new(root_dir);
     migrate_met_api_cache(&cache)?;
+    if !read_only {
+        if let Some(version) = adopt_older_gallery_db(&cache)? {
+            info!("Copied version {version} of the DB and migrated it.");
+        }
+    }
     let db_path = cache.get_cached_path(get_default_gallery_db_filename());
     // Check for existence, we don't want SQLite making a zero-byte DB file.
     if !db_path.exists() {
         return Err(anyhow!("DB does not exist: {}", db_path.display()));
     }
-    let mut db = GalleryDb::new(Connection::open(db_path)?);
+    if !is_valid_slot_name(&slot) {
+        return Err(anyhow!("Invalid save slot name: {slot:?}"));
+    }
+    let layout_db_path = cache.get_cached_path(get_layout_db_filename(&slot));
+    // We can't rebuild the databases in read-only mode, so we'll just have to
+    // hope they're not corrupt.
+    if !read_only {
+        let reports = [
+            retry_while_busy("checking it for corruption", || {
+                recover_corrupt_gallery_db(&db_path)
+            })?,
+            retry_while_busy("checking the layout DB for corruption", || {
+                recover_corrupt_layout_db(&layout_db_path)
+            })?,
+        ];
+        for report in reports.into_iter().flatten() {
+            warn!("{report}");
+            // Ignore result, we'll find out if the other end hung up soon enough.
+            let _ = from_worker_tx.send(MessageFromWorker::DbRecovered(report.to_string()));
+        }
+    }
+    let (mut db, attached_layout_db_path) = retry_while_busy("opening it", || {
+        open_gallery_db(&db_path, &layout_db_path, read_only)
+    })?;
+    // Otherwise a schema that's missing something only shows up as a cryptic
+    // error whenever a query happens to need it, which could be minutes into
+    // playing. Older schemas are expected to be missing things until
+    // `RequestBody::Migrate` brings them up to date, though.
+    let schema_report = db.check_schema()?;
+    for column in schema_report.extra_columns.iter() {
+        debug!("DB has unexpected column {column}.");
+    }
+    if db
+        .schema_version()?
+        .is_some_and(|version| version < LATEST_GALLERY_DB_VERSION)
+    {
+        if !schema_report.is_ok() {
+            info!("DB schema is out of date, it will need to be migrated.");
+        }
+    } else {
+        schema_report
+            .to_result()
+            .map_err(|err| anyhow!("{err}: {}", db_path.display()))?;
+    }
+    // If we're abandoned, a new worker has taken over our files, so we mustn't
+    // write to them anymore, see `ShutdownSignal::abandon()`.
+    let shutdown = cache.shutdown_signal().cloned();
+    let is_abandoned = || {
+        shutdown
+            .as_ref()
+            .is_some_and(|shutdown| shutdown.is_abandoned())
+    };
     let mut queue = VecDeque::new();
+    // The wall sets from the most recent layout, used to validate moves.
+    let mut known_wall_sets: Vec<GalleryWallSet> = vec![];
+    let mut last_checkpoint = Instant::now();
+    let mut last_heartbeat = Instant::now();
     let send_message = |response: MessageFromWorker| {
         // Ignore result, `fill_queue()` will just give us a RecvError next if we're disconnected.
         if from_worker_tx.send(response).is_err() {
-            println!("work_thread unable to send response, other end hung up.");
+            warn!("work_thread unable to send response, other end hung up.");
         };
     };
-    let autosync_path = cache.get_cached_path(AUTOSYNC_GALLERY_PATH);
+    let autosync_path = cache.get_cached_path(get_autosync_gallery_path(&slot));
+    // Autosync needs to write to the database, so it's disabled in read-only mode.
+    let enable_autosync = enable_autosync && !read_only;
     if enable_autosync {
         import_autosync(&mut db, &autosync_path)?;
     }
-    println!("work_thread waiting for message.");
+    // The preview server is just for tooling, so it's not a big deal if it
+    // can't start, e.g. because something else is using its port.
+    let mut preview_server = preview_server_port.and_then(|port| {
+        PreviewServer::start(db_path, attached_layout_db_path, port)
+            .map_err(|err| warn!("Unable to start preview server on port {port}: {err:?}"))
+            .ok()
+    });
+    send_message(MessageFromWorker::Ready);
+    debug!("work_thread waiting for message.");
     loop {
+        if last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+            send_message(MessageFromWorker::Heartbeat);
+            last_heartbeat = Instant::now();
+        }
         fill_queue(&mut queue, &to_worker_rx);
-        match queue.pop_front().expect("queue should not be empty") {
+        let Some(message) = queue.pop_front() else {
+            continue;
+        };
+        match message {
             Ok(MessageToWorker::End) => {
-                println!("work_thread received 'end' message.");
+                debug!("work_thread received 'end' message.");
+                if !queue.is_empty() {
+                    info!(
+                        "work_thread ending without processing {} queued message(s).",
+                        queue.len()
+                    );
+                }
+                if let Some(mut preview_server) = preview_server.take() {
+                    preview_server.stop();
+                }
                 break;
             }
+            Ok(MessageToWorker::SetOffline(offline)) => {
+                info!("work_thread setting offline={offline}.");
+                cache.set_offline(offline);
+            }
             Ok(MessageToWorker::Request(request)) => {
                 let peer_id = request.peer_id;
                 let request_id = request.request_id;
@@ -330,70 +1503,458 @@ pub fn work_thread(
                         body,
                     }));
                 };
-                //println!("work_thread received request: {:?}", request.body);
+                trace!("work_thread received request: {:?}", request.body);
+                if read_only && request.body.is_mutating() {
+                    send_response(ResponseBody::Error(
+                        "The gallery database was opened in read-only mode.".to_string(),
+                    ));
+                    continue;
+                }
+                if is_abandoned() && request.body.is_mutating() {
+                    send_response(ResponseBody::Error(
+                        "The gallery worker thread was replaced by another one.".to_string(),
+                    ));
+                    continue;
+                }
+                if matches!(request.body, RequestBody::Maintenance { .. })
+                    && has_pending_non_maintenance_requests(&queue)
+                {
+                    // Wait until we're otherwise idle.
+                    queue.push_back(Ok(MessageToWorker::Request(request)));
+                    continue;
+                }
                 match request.body {
                     RequestBody::Migrate => {
-                        migrate_gallery_db(&cache)?;
+                        migrate_gallery_db(&cache, &slot)?;
+                        db.check_schema()?.to_result()?;
                         send_response(ResponseBody::Empty);
                     }
-                    RequestBody::ImportNonPositiveLayout { json_content } => {
-                        send_response(import_non_positive_layout(&mut db, json_content)?.into());
+                    RequestBody::EnsureReady {
+                        walls_json,
+                        relayout_if_empty,
+                    } => {
+                        let (report, wall_sets) =
+                            ensure_ready(&cache, &slot, &mut db, &walls_json, relayout_if_empty);
+                        if let Some(wall_sets) = wall_sets {
+                            known_wall_sets = wall_sets;
+                        }
+                        send_response(ResponseBody::String(serde_json::to_string(&report)?));
+                    }
+                    RequestBody::ImportNonPositiveLayout {
+                        json_content,
+                        conflict_policy,
+                    } => {
+                        send_response(import_non_positive_layout(
+                            &mut db,
+                            &known_wall_sets,
+                            json_content,
+                            conflict_policy,
+                        )?);
+                    }
+                    RequestBody::MergeNonPositiveLayout { json_content } => {
+                        send_response(merge_autosync(&mut db, &known_wall_sets, &json_content)?);
+                    }
+                    RequestBody::ExportNonPositiveLayout { include_metadata } => {
+                        let json = if include_metadata {
+                            export_shared_non_positive_layout(&mut db)?
+                        } else {
+                            export_non_positive_layout(&mut db)?
+                        };
+                        send_response(ResponseBody::String(json));
                     }
-                    RequestBody::ExportNonPositiveLayout => {
-                        send_response(ResponseBody::String(export_non_positive_layout(&mut db)?));
+                    RequestBody::GetCacheInfo => {
+                        let info = CacheInfo {
+                            usage: CacheUsageReport::for_dir(cache.cache_dir())?,
+                            stats: cache.stats(),
+                        };
+                        send_response(ResponseBody::String(serde_json::to_string(&info)?));
+                    }
+                    RequestBody::GetCachedImageStates { object_ids } => {
+                        let states = get_cached_image_states(&db, &cache, &object_ids);
+                        send_response(ResponseBody::String(serde_json::to_string(&states)?));
+                    }
+                    RequestBody::Maintenance { vacuum } => {
+                        let report = db.maintenance(vacuum)?;
+                        info!("Performed database maintenance: {report:?}");
+                        last_checkpoint = Instant::now();
+                        send_response(ResponseBody::Maintenance(report));
                     }
                     RequestBody::Layout {
                         walls_json,
+                        wall_sets_json,
                         filter,
                         dense,
+                        mode,
+                        ordering_json,
+                        reserved_walls,
+                        segments,
+                        featured_first,
+                        allow_rotation,
+                        balance_by_collection: collection_weights,
+                        collect_free_regions,
+                        min_free_region_width,
+                        exclude_previously_displayed,
+                        gallery_id_range,
                     } => {
+                        let mode = mode.unwrap_or(LayoutMode::from_dense(dense));
+                        if mode == LayoutMode::RealGallery && !segments.is_empty() {
+                            send_response(ResponseBody::Error(
+                                "Segments can't be used with the real-gallery layout mode."
+                                    .to_string(),
+                            ));
+                            continue;
+                        }
+                        if let Some((first_gallery_id, last_gallery_id)) = gallery_id_range {
+                            if let Err(err) =
+                                check_positive_gallery_range(first_gallery_id, last_gallery_id)
+                            {
+                                send_response(ResponseBody::Error(err.to_string()));
+                                continue;
+                            }
+                        }
+                        let wall_sets = get_wall_sets(&walls_json, wall_sets_json.as_deref())?;
+                        if let Err(err) = check_wall_sets(&wall_sets) {
+                            send_response(ResponseBody::Error(err.to_string()));
+                            continue;
+                        }
+                        for wall_id in reserved_walls.iter() {
+                            if resolve_wall_id_in_wall_sets(&wall_sets, wall_id).is_none() {
+                                warn!("Reserved wall {wall_id:?} isn't in any wall set.");
+                            }
+                        }
+                        let ordering: Option<Vec<ArtObjectId>> = match ordering_json {
+                            Some(ordering_json) => Some(serde_json::from_str(&ordering_json)?),
+                            None => None,
+                        };
+                        let segment_filters: Vec<(Option<String>, Option<String>)> =
+                            if segments.is_empty() {
+                                vec![(None, filter)]
+                            } else {
+                                segments
+                                    .iter()
+                                    .map(|segment| {
+                                        (
+                                            Some(segment.name.clone()),
+                                            segment.combined_filter(filter.as_deref()),
+                                        )
+                                    })
+                                    .collect()
+                            };
+                        let mut segment_art_objects = Vec::with_capacity(segment_filters.len());
+                        let mut filter_error = None;
+                        for (name, filter) in segment_filters {
+                            let options = ArtObjectQueryOptions {
+                                filter,
+                                exclude_previously_displayed,
+                            };
+                            if let Some(error) = check_filter(&db, &options) {
+                                filter_error = Some(error);
+                                break;
+                            }
+                            let mut art_objects = db.get_all_art_objects_for_layout(&options)?;
+                            if let Some(range) = gallery_id_range {
+                                let elsewhere =
+                                    db.get_art_object_ids_outside_gallery_range(range)?;
+                                art_objects.retain(|object| !elsewhere.contains(&object.id));
+                            }
+                            if let Some(weights) = &collection_weights {
+                                art_objects = balance_by_collection(art_objects, weights, None);
+                            }
+                            if featured_first {
+                                move_highlights_to_front(&mut art_objects);
+                            }
+                            segment_art_objects.push((name, art_objects));
+                        }
+                        if let Some(error) = filter_error {
+                            send_response(error);
+                            continue;
+                        }
+                        let walls_hash = hash_wall_sets(&wall_sets)?;
+                        let previous_walls_hash =
+                            db.get_layout_metadata(WALLS_HASH_METADATA_KEY)?;
+                        if previous_walls_hash.is_some_and(|hash| hash != walls_hash) {
+                            // Art objects in non-positive galleries only know which walls
+                            // they're on if there's a single wall set, see `find_wall()`.
+                            if let [wall_set] = wall_sets.as_slice() {
+                                let moved = db.rescale_layout_to_walls(&wall_set.walls)?;
+                                info!(
+                                    "Walls changed since the last layout, moved {moved} art object(s) in non-positive galleries."
+                                );
+                            }
+                        }
+                        let gallery_start_id = match gallery_id_range {
+                            Some((first_gallery_id, _)) => first_gallery_id,
+                            None => 1,
+                        };
+                        let except_art_object_ids =
+                            db.get_art_object_ids_in_non_positive_galleries()?;
+                        let min_free_region_width =
+                            min_free_region_width.unwrap_or(DEFAULT_MIN_FREE_REGION_WIDTH);
+                        let layout_options = LayoutOptions {
+                            mode,
+                            gallery_start_id,
+                            ordering: ordering.as_deref(),
+                            except_art_object_ids: Some(&except_art_object_ids),
+                            reserved_walls: &reserved_walls,
+                            allow_rotation,
+                            collect_free_regions,
+                            min_free_region_width,
+                            ..Default::default()
+                        };
+                        let result = if mode == LayoutMode::RealGallery {
+                            let (_, art_objects) = segment_art_objects.pop().unwrap();
+                            layout_source_galleries(
+                                &wall_sets,
+                                art_objects,
+                                &db.get_source_galleries_by_id()?,
+                                &layout_options,
+                            )
+                        } else if segments.is_empty() {
+                            let (_, art_objects) = segment_art_objects.pop().unwrap();
+                            layout(&wall_sets, art_objects, &layout_options)
+                        } else {
+                            layout_segments(
+                                &wall_sets,
+                                segment_art_objects
+                                    .into_iter()
+                                    .map(|(name, art_objects)| (name.unwrap(), art_objects))
+                                    .collect(),
+                                &layout_options,
+                            )
+                        };
+                        // This is usually because all of a wall set's walls are reserved,
+                        // which is the caller's mistake rather than a fatal error.
+                        let mut result = match result {
+                            Ok(result) => result,
+                            Err(err) => {
+                                send_response(ResponseBody::Error(err.to_string()));
+                                continue;
+                            }
+                        };
+                        if let Some((_, last_gallery_id)) = gallery_id_range {
+                            result.truncate_galleries(last_gallery_id);
+                        }
+                        for id in result.unmatched_ordering_ids.iter() {
+                            warn!(
+                                "Art object {id:?} in layout ordering doesn't exist or doesn't match the filter."
+                            );
+                        }
+                        match gallery_id_range {
+                            Some(range) => {
+                                save_layout_result_in_gallery_range(
+                                    &mut db, &result, &wall_sets, range,
+                                )?;
+                                db.set_layout_metadata(LAYOUT_MODE_METADATA_KEY, mode.name())?;
+                            }
+                            None => {
+                                save_layout_result(&mut db, &result, &wall_sets, &walls_hash, mode)?
+                            }
+                        }
+                        let unplaceable = result.unplaceable_art_object_ids.len();
+                        let dropped_source_galleries = result.dropped_source_galleries;
+                        let free_regions: Vec<FreeRegion<String>> = result
+                            .free_regions
+                            .into_iter()
+                            .map(|region| FreeRegion {
+                                gallery_id: region.gallery_id,
+                                wall_id: region.wall_id.to_string(),
+                                x_start: region.x_start,
+                                x_end: region.x_end,
+                                height_available: region.height_available,
+                            })
+                            .collect();
+                        info!(
+                            "Created layout across {} galleries using {} wall set(s), mode={}, {unplaceable} unplaceable.",
+                            result.galleries_created,
+                            wall_sets.len(),
+                            mode.name()
+                        );
+                        let unknown_wall_records = count_unknown_wall_records(&mut db, &wall_sets)?;
+                        known_wall_sets = wall_sets;
+                        let galleries_created = result.galleries_created as i64;
+                        let summary = LayoutSummary {
+                            galleries_created: result.galleries_created,
+                            first_gallery_id: (galleries_created > 0).then_some(gallery_start_id),
+                            last_gallery_id: (galleries_created > 0)
+                                .then_some(gallery_start_id + galleries_created - 1),
+                            unplaceable,
+                            unknown_wall_records,
+                            free_regions,
+                            dropped_source_galleries,
+                        };
+                        send_response(ResponseBody::String(serde_json::to_string(&summary)?));
+                    }
+                    RequestBody::GetGalleryWallSet { gallery_id } => {
+                        let wall_set = db
+                            .get_gallery_record(gallery_id)?
+                            .map(|gallery| gallery.wall_set)
+                            .unwrap_or_default();
+                        send_response(ResponseBody::String(wall_set));
+                    }
+                    RequestBody::GetGallerySourceGallery { gallery_id } => {
+                        let source_gallery = db
+                            .get_gallery_record(gallery_id)?
+                            .and_then(|gallery| gallery.source_gallery)
+                            .unwrap_or_default();
+                        send_response(ResponseBody::String(source_gallery));
+                    }
+                    RequestBody::GetGalleryReservedWalls { gallery_id } => {
+                        let reserved_walls = db
+                            .get_gallery_record(gallery_id)?
+                            .map(|gallery| gallery.reserved_walls)
+                            .unwrap_or_default();
+                        send_response(ResponseBody::String(serde_json::to_string(
+                            &reserved_walls,
+                        )?));
+                    }
+                    RequestBody::GetGalleryGraph => {
+                        let galleries = db.get_populated_positive_galleries()?;
+                        send_response(ResponseBody::String(serde_json::to_string(&galleries)?));
+                    }
+                    RequestBody::CountGalleries => {
+                        let count = db.count_positive_galleries()?;
+                        send_response(ResponseBody::Integer(count as i64));
+                    }
+                    RequestBody::ListCollections => {
+                        let collections = db.list_collections()?;
+                        send_response(ResponseBody::String(serde_json::to_string(&collections)?));
+                    }
+                    RequestBody::SaveFilter { name, filter } => {
+                        if let Err(err) = db.validate_saved_filter(&name, &filter) {
+                            send_response(ResponseBody::Error(err.to_string()));
+                        } else {
+                            db.save_filter(&name, &filter)?;
+                            send_response(ResponseBody::Empty);
+                        }
+                    }
+                    RequestBody::ListFilters => {
+                        let filters = db.list_saved_filters()?;
+                        send_response(ResponseBody::String(serde_json::to_string(&filters)?));
+                    }
+                    RequestBody::DeleteFilter { name } => {
+                        let deleted = db.delete_saved_filter(&name)?;
+                        send_response(ResponseBody::Integer(deleted as i64));
+                    }
+                    RequestBody::TagArtObject { art_object_id, tag } => {
+                        if let Err(err) = normalize_tag(&tag) {
+                            send_response(ResponseBody::Error(err.to_string()));
+                        } else {
+                            let added = db.add_tag(art_object_id, &tag)?;
+                            send_response(ResponseBody::Integer(added as i64));
+                        }
+                    }
+                    RequestBody::UntagArtObject { art_object_id, tag } => {
+                        let removed = db.remove_tag(art_object_id, &tag)?;
+                        send_response(ResponseBody::Integer(removed as i64));
+                    }
+                    RequestBody::GetTags { art_object_id } => {
+                        let tags = db.get_tags(art_object_id)?;
+                        send_response(ResponseBody::String(serde_json::to_string(&tags)?));
+                    }
+                    RequestBody::GetArtObjectPlacement { art_object_id } => {
+                        send_response(ResponseBody::ArtObjectPlacement(
+                            db.get_layout_record(art_object_id)?,
+                        ));
+                    }
+                    RequestBody::GetArtist { qid } => {
+                        send_response(ResponseBody::Artist(db.get_artist(qid)?));
+                    }
+                    RequestBody::GetRelatedArtObjects { object_id, limit } => {
+                        let related: RelatedArtObjectsSummary =
+                            db.get_related_art_objects(object_id, limit)?.into();
+                        send_response(ResponseBody::String(serde_json::to_string(&related)?));
+                    }
+                    RequestBody::DistinctValues {
+                        column,
+                        min_count,
+                        limit,
+                    } => {
+                        let Some(column) = DistinctColumn::from_name(&column) else {
+                            send_response(ResponseBody::Error(format!(
+                                "Unknown column: {column:?}"
+                            )));
+                            continue;
+                        };
+                        let values: Vec<DistinctValue> = db
+                            .distinct_values(column, min_count, limit)?
+                            .into_iter()
+                            .map(|(value, count)| DistinctValue { value, count })
+                            .collect();
+                        send_response(ResponseBody::String(serde_json::to_string(&values)?));
+                    }
+                    RequestBody::EstimateLayout {
+                        walls_json,
+                        filter,
+                        dense,
+                        mode,
+                    } => {
+                        let mode = mode.unwrap_or(LayoutMode::from_dense(dense));
                         let walls: Vec<GalleryWall> = serde_json::from_str(&walls_json)?;
                         let options = ArtObjectQueryOptions {
                             filter,
                             ..Default::default()
                         };
+                        if let Some(error) = check_filter(&db, &options) {
+                            send_response(error);
+                            continue;
+                        }
                         let art_objects = db.get_all_art_objects_for_layout(&options)?;
-                        let gallery_start_id = 1;
-                        let except_art_object_ids =
-                            db.get_art_object_ids_in_non_positive_galleries()?;
-                        let (galleries_created, layout_records) = layout(
-                            dense,
-                            gallery_start_id,
-                            &walls,
-                            art_objects,
-                            &except_art_object_ids,
-                            false,
-                        )?;
-                        db.set_layout_records_in_positive_galleries(&layout_records)?;
-                        println!(
-                            "Created layout across {} galleries with {} walls each, dense={dense}.",
-                            galleries_created,
-                            walls.len()
-                        );
-                        send_response(ResponseBody::Empty);
+                        match estimate_layout(&walls, &art_objects, mode) {
+                            Ok(estimate) => send_response(ResponseBody::String(
+                                serde_json::to_string(&estimate)?,
+                            )),
+                            Err(err) => send_response(ResponseBody::Error(err.to_string())),
+                        }
                     }
                     RequestBody::CountArtObjects { filter } => {
                         let options = ArtObjectQueryOptions {
                             filter,
                             ..Default::default()
                         };
+                        if let Some(error) = check_filter(&db, &options) {
+                            send_response(error);
+                            continue;
+                        }
                         let count = db.count_art_objects(&options)?;
                         send_response(ResponseBody::Integer(count as i64))
                     }
+                    RequestBody::SearchPlaced { filter, limit } => {
+                        let options = ArtObjectQueryOptions {
+                            filter,
+                            ..Default::default()
+                        };
+                        if let Some(error) = check_filter(&db, &options) {
+                            send_response(error);
+                            continue;
+                        }
+                        let results: Vec<PlacedSearchResultSummary> = db
+                            .search_placed_art_objects(&options, limit)?
+                            .into_iter()
+                            .map(Into::into)
+                            .collect();
+                        send_response(ResponseBody::String(serde_json::to_string(&results)?));
+                    }
                     RequestBody::MoveArtObject {
                         art_object_id,
                         gallery_id,
                         wall_id,
                         x,
                         y,
+                        strict,
                     } => {
-                        db.upsert_layout_records(&vec![LayoutRecord {
+                        let record = LayoutRecord {
                             gallery_id,
                             wall_id,
                             art_object_id,
                             x,
                             y,
-                        }])?;
+                            // Players always hang art upright.
+                            rotated: false,
+                        };
+                        send_response(move_art_object(&mut db, &known_wall_sets, record, strict)?);
+                    }
+                    RequestBody::UndoLastMove => {
+                        send_response(undo_last_move(&mut db, &known_wall_sets)?);
                     }
                     RequestBody::GetArtObjectsForGalleryWall {
                         gallery_id,
@@ -403,32 +1964,64 @@ pub fn work_thread(
                             get_art_objects_for_gallery_wall(&mut db, gallery_id, wall_id)?;
                         send_response(ResponseBody::ArtObjectsForGalleryWall(objects));
                     }
-                    RequestBody::FetchImage { object_id, size } => match object_id {
-                        ArtObjectId::Met(met_object_id) => {
-                            let mut image_path = fetch_met_api_image(&cache, met_object_id, size);
-                            if image_path.is_none() {
-                                image_path =
-                                    try_to_download_wikidata_image(&db, &cache, object_id, size)?;
+                    RequestBody::FetchImage {
+                        object_id,
+                        size,
+                        decode,
+                        format,
+                    } => {
+                        let options = ImageCacheOptions {
+                            preferred_format: format.or(image_cache_options.preferred_format),
+                            ..image_cache_options
+                        };
+                        let image_path = match object_id {
+                            ArtObjectId::Met(met_object_id) => {
+                                let mut image_path =
+                                    fetch_met_api_image(&db, &cache, met_object_id, size, &options);
+                                if image_path.is_none() {
+                                    image_path = try_to_download_wikidata_image(
+                                        &db, &cache, object_id, size, &options,
+                                    )?;
+                                }
+                                image_path
                             }
-                            send_response(ResponseBody::Image(image_path));
-                        }
-                        ArtObjectId::Wikidata(_qid) => {
-                            let image_path =
-                                try_to_download_wikidata_image(&db, &cache, object_id, size)?;
-                            send_response(ResponseBody::Image(image_path));
+                            ArtObjectId::Wikidata(_qid) => try_to_download_wikidata_image(
+                                &db, &cache, object_id, size, &options,
+                            )?,
+                        };
+                        if decode {
+                            send_response(decoded_image_response(
+                                image_path,
+                                MAX_DECODED_IMAGE_PIXELS,
+                            ));
+                        } else {
+                            send_response(image_response(image_path));
                         }
-                    },
+                    }
+                }
+                if !read_only && !is_abandoned() && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL
+                {
+                    // This isn't critical, so just log any errors.
+                    if let Err(err) = db.checkpoint_passive() {
+                        error!("Unable to checkpoint database: {err:?}");
+                    }
+                    last_checkpoint = Instant::now();
                 }
             }
             Err(RecvError) => {
-                println!("work_thread client hung up prematurely.");
+                warn!("work_thread client hung up prematurely.");
                 break;
             }
         }
     }
 
     if enable_autosync {
-        export_autosync(&mut db, &autosync_path)?;
+        if is_abandoned() {
+            // The new worker will export its own, and ours could clobber it.
+            warn!("work_thread was abandoned, not exporting autosync.");
+        } else {
+            export_autosync(&mut db, &autosync_path)?;
+        }
     }
 
     // Ignoring result, there's not much we can do if this send fails.
@@ -437,59 +2030,732 @@ pub fn work_thread(
     Ok(())
 }
 
+/// Replaces the non-positive layout with the one in the given JSON, which is in
+/// either of the formats that `LayoutDocument` understands or is an autosync
+/// (whose records keep their timestamps), leaving out any records whose walls
+/// aren't in `wall_sets`. Records in generated galleries are left out, since
+/// only the player's own galleries are imported (see `GalleryZone`), just like
+/// they're the only ones exported. Art objects hanging in positive galleries are
+/// handled according to `conflict_policy`, and layouts that contain the same
+/// art object more than once are rejected, since it can't be in two places at
+/// once.
 fn import_non_positive_layout(
     db: &mut GalleryDb,
+    wall_sets: &[GalleryWallSet],
     json_content: String,
-) -> Result<GdScriptResultCode> {
-    let records: serde_json::Result<Vec<LayoutRecord<String>>> =
-        serde_json::from_str(&json_content);
-    match records {
-        Ok(records) => {
-            db.clear_layout_records_in_non_positive_galleries()?;
-            db.upsert_layout_records(&records)?;
-            Ok(GdScriptResultCode::Ok)
+    conflict_policy: ImportConflictPolicy,
+) -> Result<ResponseBody> {
+    // Records that don't say when they were modified are being modified now.
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("current time should be after the unix epoch")
+        .as_secs() as i64;
+    let (records, unknown_art_objects) = match LayoutDocument::parse(&json_content) {
+        Ok(LayoutDocument::Records(records)) => (timestamp_records(records, now), vec![]),
+        Ok(LayoutDocument::Shared(layout)) => {
+            let (records, unknown_art_objects) = layout.split_by_known_art_objects(db)?;
+            (timestamp_records(records, now), unknown_art_objects)
         }
-        Err(err) => {
-            println!("Unable to parse JSON into layout records: {:?}", err);
-            Ok(GdScriptResultCode::Failed)
+        Err(err) => match parse_autosync(&json_content) {
+            Ok(autosync) => (autosync.records, vec![]),
+            Err(_) => {
+                warn!("Unable to parse JSON into layout records: {:?}", err);
+                return Ok(ResponseBody::Error(format!(
+                    "Unable to parse JSON into layout records: {err}"
+                )));
+            }
+        },
+    };
+    for art_object in unknown_art_objects.iter() {
+        warn!(
+            "Skipping layout record for {}, it isn't in the database.",
+            art_object.url
+        );
+    }
+
+    let mut rejected = vec![];
+    let records: Vec<TimestampedLayoutRecord> = records
+        .into_iter()
+        .filter(|timestamped| {
+            let record = &timestamped.record;
+            if GalleryZone::from_gallery_id(record.gallery_id).is_player_owned() {
+                return true;
+            }
+            warn!(
+                "Skipping layout record for {:?}, gallery {} is generated.",
+                record.art_object_id, record.gallery_id
+            );
+            rejected.push(RejectedLayoutRecord {
+                art_object_id: record.art_object_id,
+                gallery_id: record.gallery_id,
+                reason: format!(
+                    "Gallery {} is generated by the layout, only galleries {} and below can be imported",
+                    record.gallery_id,
+                    GalleryZone::PLAYER_GALLERY_IDS.end()
+                ),
+            });
+            false
+        })
+        .collect();
+
+    let distinct_ids: HashSet<ArtObjectId> = records
+        .iter()
+        .map(|timestamped| timestamped.record.art_object_id)
+        .collect();
+    let duplicates = records.len() - distinct_ids.len();
+    if duplicates > 0 {
+        return Ok(ResponseBody::Error(format!(
+            "Layout contains {duplicates} duplicate art object record(s), not importing it."
+        )));
+    }
+
+    let mut conflicted = 0;
+    let mut records_to_import = Vec::with_capacity(records.len());
+    for timestamped in records {
+        let in_positive_gallery =
+            db.get_gallery_zone(timestamped.record.art_object_id)? == Some(GalleryZone::Generated);
+        if in_positive_gallery {
+            conflicted += 1;
+            if conflict_policy == ImportConflictPolicy::Skip {
+                warn!(
+                    "Skipping layout record for {:?}, it's hanging in a positive gallery.",
+                    timestamped.record.art_object_id
+                );
+                continue;
+            }
         }
+        records_to_import.push(timestamped);
     }
+    if conflicted > 0 && conflict_policy == ImportConflictPolicy::Fail {
+        return Ok(ResponseBody::Error(format!(
+            "{conflicted} art object(s) in the layout are hanging in positive galleries, not importing it."
+        )));
+    }
+
+    let (records, skipped) = resolve_timestamped_layout_wall_ids(records_to_import, wall_sets);
+    db.clear_layout_records_in_non_positive_galleries()?;
+    db.upsert_timestamped_layout_records(&records)?;
+    let summary = ImportSummary {
+        imported: records.len(),
+        skipped,
+        unknown_art_objects,
+        conflicted,
+        rejected,
+    };
+    Ok(ResponseBody::String(serde_json::to_string(&summary)?))
+}
+
+/// Returns the number of layout records in non-positive galleries whose walls
+/// aren't in `wall_sets`, warning about each unknown wall.
+fn count_unknown_wall_records(db: &mut GalleryDb, wall_sets: &[GalleryWallSet]) -> Result<usize> {
+    let records = db.get_layout_records_in_non_positive_galleries()?;
+    let resolved = resolve_layout_wall_ids(records, wall_sets);
+    for (wall_id, count) in resolved.unknown_wall_ids.iter() {
+        warn!("{count} art object(s) in non-positive galleries are on unknown wall {wall_id:?}.");
+    }
+    Ok(resolved.skipped())
+}
+
+/// Like `resolve_layout_wall_ids()`, but keeps when each record was modified.
+/// Returns the resolved records, and how many were left out.
+fn resolve_timestamped_layout_wall_ids(
+    records: Vec<TimestampedLayoutRecord>,
+    wall_sets: &[GalleryWallSet],
+) -> (Vec<TimestampedLayoutRecord>, usize) {
+    let modified_at: HashMap<ArtObjectId, i64> = records
+        .iter()
+        .map(|timestamped| (timestamped.record.art_object_id, timestamped.modified_at))
+        .collect();
+    let resolved = resolve_layout_wall_ids(
+        records
+            .into_iter()
+            .map(|timestamped| timestamped.record)
+            .collect(),
+        wall_sets,
+    );
+    for (wall_id, count) in resolved.unknown_wall_ids.iter() {
+        warn!("Skipping {count} layout record(s) on unknown wall {wall_id:?}.");
+    }
+    let skipped = resolved.skipped();
+    let records = resolved
+        .records
+        .into_iter()
+        .map(|record| TimestampedLayoutRecord {
+            modified_at: modified_at[&record.art_object_id],
+            record,
+        })
+        .collect();
+    (records, skipped)
+}
+
+fn timestamp_records(
+    records: Vec<LayoutRecord<String>>,
+    modified_at: i64,
+) -> Vec<TimestampedLayoutRecord> {
+    records
+        .into_iter()
+        .map(|record| TimestampedLayoutRecord {
+            record,
+            modified_at,
+        })
+        .collect()
 }
 
 fn export_non_positive_layout(db: &mut GalleryDb) -> Result<String> {
+    let autosync = AutosyncV2::from_db(db)?;
+    Ok(serde_json::to_string_pretty(&autosync)?)
+}
+
+fn export_shared_non_positive_layout(db: &mut GalleryDb) -> Result<String> {
     let records = db.get_layout_records_in_non_positive_galleries()?;
-    let json = serde_json::to_string_pretty(&records)?;
-    Ok(json)
+    let layout = SharedLayout::from_layout_records(db, records)?;
+    Ok(serde_json::to_string_pretty(&layout)?)
+}
+
+/// The version of the autosync format that `export_autosync()` writes. Version
+/// 1 autosyncs are either just the layout records, as a JSON array, or, once
+/// tags existed, an `AutosyncV1`.
+pub const AUTOSYNC_VERSION: u32 = 2;
+
+/// What was autosynced for a save slot before layout records had timestamps.
+#[derive(Debug, Deserialize)]
+struct AutosyncV1 {
+    layout: Vec<LayoutRecord<String>>,
+    #[serde(default)]
+    tags: Vec<TagRecord>,
+}
+
+/// What's autosynced for a save slot. Each record says when it was last
+/// modified, so that autosyncs from different machines can be merged rather
+/// than one replacing the other, see `merge_autosync()`.
+#[derive(Debug, Deserialize, Serialize)]
+struct AutosyncV2 {
+    version: u32,
+    records: Vec<TimestampedLayoutRecord>,
+    #[serde(default)]
+    tags: Vec<TagRecord>,
+}
+
+impl AutosyncV2 {
+    fn from_db(db: &mut GalleryDb) -> Result<Self> {
+        Ok(AutosyncV2 {
+            version: AUTOSYNC_VERSION,
+            records: db.get_timestamped_layout_records_in_non_positive_galleries()?,
+            tags: db.get_all_tags()?,
+        })
+    }
+}
+
+/// Parses an autosync of any version. Records from version 1 autosyncs were
+/// modified at 0, i.e. before anything else.
+fn parse_autosync(json: &str) -> Result<AutosyncV2> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    if value.is_array() {
+        let records: Vec<LayoutRecord<String>> = serde_json::from_value(value)?;
+        return Ok(AutosyncV2 {
+            version: AUTOSYNC_VERSION,
+            records: timestamp_records(records, 0),
+            tags: vec![],
+        });
+    }
+    if value.get("layout").is_some() {
+        let autosync: AutosyncV1 = serde_json::from_value(value)?;
+        return Ok(AutosyncV2 {
+            version: AUTOSYNC_VERSION,
+            records: timestamp_records(autosync.layout, 0),
+            tags: autosync.tags,
+        });
+    }
+    let autosync: AutosyncV2 = serde_json::from_value(value)?;
+    if autosync.version > AUTOSYNC_VERSION {
+        return Err(anyhow!(
+            "Autosync is version {}, but only versions up to {AUTOSYNC_VERSION} are supported",
+            autosync.version
+        ));
+    }
+    Ok(autosync)
 }
 
 fn import_autosync(db: &mut GalleryDb, autosync_path: &PathBuf) -> Result<()> {
     if autosync_path.exists() {
-        println!("autosync: importing {}.", autosync_path.display());
+        info!("autosync: importing {}.", autosync_path.display());
         match std::fs::read_to_string(&autosync_path) {
             Ok(json_contents) => {
-                import_non_positive_layout(db, json_contents)?;
+                let autosync = match parse_autosync(&json_contents) {
+                    Ok(autosync) => autosync,
+                    Err(err) => {
+                        error!("Failed to parse autosync: {err:?}");
+                        return Ok(());
+                    }
+                };
+                // We don't know about any walls before the first layout. The autosync is
+                // the player's own layout from last time, so it takes precedence over
+                // anything hanging in positive galleries.
+                let ids: HashSet<ArtObjectId> = autosync
+                    .records
+                    .iter()
+                    .map(|timestamped| timestamped.record.art_object_id)
+                    .collect();
+                if ids.len() < autosync.records.len() {
+                    error!("Autosync contains duplicate art object records, not importing it.");
+                } else {
+                    let (records, _) = resolve_timestamped_layout_wall_ids(autosync.records, &[]);
+                    db.clear_layout_records_in_non_positive_galleries()?;
+                    db.upsert_timestamped_layout_records(&records)?;
+                    info!("autosync: imported {} layout record(s).", records.len());
+                }
+                // Tags are shared by all save slots, so they're merged rather than
+                // replaced, in case another slot's autosync has newer ones.
+                let added = db.import_tags(&autosync.tags)?;
+                info!("autosync: imported {added} new tag(s).");
             }
             Err(err) => {
-                println!("Failed to read from file: {err:?}");
+                error!("Failed to read from file: {err:?}");
             }
         }
     }
     Ok(())
 }
 
+/// Merges the non-positive layout in the given autosync, of any version, into
+/// ours, keeping whichever of each art object's records was modified last, e.g.
+/// to reconcile autosyncs from two machines. Records whose walls aren't in
+/// `wall_sets` are left out, and tags are merged as they are when importing
+/// autosyncs.
+fn merge_autosync(
+    db: &mut GalleryDb,
+    wall_sets: &[GalleryWallSet],
+    json_content: &str,
+) -> Result<ResponseBody> {
+    let autosync = match parse_autosync(json_content) {
+        Ok(autosync) => autosync,
+        Err(err) => {
+            warn!("Unable to parse autosync: {:?}", err);
+            return Ok(ResponseBody::Error(format!(
+                "Unable to parse autosync: {err}"
+            )));
+        }
+    };
+    let (records, skipped) = resolve_timestamped_layout_wall_ids(autosync.records, wall_sets);
+    let merged = db.merge_layout_records(&records)?;
+    db.import_tags(&autosync.tags)?;
+    let summary = MergeSummary {
+        merged,
+        kept: records.len() - merged,
+        skipped,
+    };
+    Ok(ResponseBody::String(serde_json::to_string(&summary)?))
+}
+
 fn export_autosync(db: &mut GalleryDb, autosync_path: &PathBuf) -> Result<()> {
-    println!("autosync: exporting {}.", autosync_path.display());
-    let contents = export_non_positive_layout(db)?;
+    info!("autosync: exporting {}.", autosync_path.display());
 
-    let write = || -> Result<()> {
+    let mut write = || -> Result<()> {
         ensure_parent_dir(&autosync_path)?;
-        std::fs::write(autosync_path, contents)?;
+        // Write to a temporary file first, so a failure partway through doesn't
+        // clobber the last good autosync.
+        let temp_path = autosync_path.with_extension("json.tmp");
+        let autosync = AutosyncV2::from_db(db)?;
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&temp_path)?);
+        serde_json::to_writer_pretty(&mut file, &autosync)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        drop(file);
+        std::fs::rename(&temp_path, autosync_path)?;
+        info!(
+            "autosync: exported {} layout record(s) and {} tag(s).",
+            autosync.records.len(),
+            autosync.tags.len()
+        );
         Ok(())
     };
 
     if let Err(err) = write() {
-        println!("Failed to write file: {err:?}")
+        error!("Failed to write file: {err:?}")
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, path::PathBuf};
+
+    use gallery::art_object::ArtObjectId;
+
+    use crate::test_worker::{
+        create_root_dir_with_db, parse_layout_summary, TestWorker, TEST_SLOT,
+    };
+
+    use super::{
+        decoded_image_response, get_wall_sets, has_pending_non_maintenance_requests,
+        image_response, LayoutSummary, MessageToWorker, Request, RequestBody, ResponseBody,
+    };
+
+    #[test]
+    fn test_read_only_worker_rejects_mutating_requests() {
+        let root_dir = create_root_dir_with_db("read-only");
+        let worker = TestWorker::spawn(&root_dir, true, true);
+        let send_request = |request_id, body| worker.send_request(request_id, body);
+
+        let body = send_request(
+            1,
+            RequestBody::MoveArtObject {
+                art_object_id: ArtObjectId::Met(1),
+                gallery_id: 1,
+                wall_id: "wall_a".to_string(),
+                x: 0.0,
+                y: 0.0,
+                strict: false,
+            },
+        );
+        assert!(matches!(body, ResponseBody::Error(_)));
+
+        let body = send_request(
+            2,
+            RequestBody::Layout {
+                walls_json: "[]".to_string(),
+                wall_sets_json: None,
+                filter: None,
+                dense: false,
+                mode: None,
+                ordering_json: None,
+                reserved_walls: vec![],
+                segments: vec![],
+                featured_first: false,
+                allow_rotation: false,
+                balance_by_collection: None,
+                collect_free_regions: false,
+                min_free_region_width: None,
+                exclude_previously_displayed: false,
+                gallery_id_range: None,
+            },
+        );
+        assert!(matches!(body, ResponseBody::Error(_)));
+
+        let body = send_request(
+            3,
+            RequestBody::GetArtObjectsForGalleryWall {
+                gallery_id: 1,
+                wall_id: "wall_a".to_string(),
+            },
+        );
+        let ResponseBody::ArtObjectsForGalleryWall(objects) = body else {
+            panic!("expected art objects response, got {body:?}");
+        };
+        assert_eq!(objects.len(), 0);
+
+        let body = send_request(4, RequestBody::CountArtObjects { filter: None });
+        assert!(matches!(body, ResponseBody::Integer(1)));
+
+        worker.end();
+
+        // Autosync should have been disabled, since it would need to write to the DB.
+        assert!(!root_dir
+            .join(super::get_autosync_gallery_path(TEST_SLOT))
+            .exists());
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_art_object_is_validated_against_layout_walls() {
+        let root_dir = create_root_dir_with_db("move");
+        let worker = TestWorker::spawn(&root_dir, false, false);
+        let body = worker.send_request(
+            1,
+            RequestBody::Layout {
+                walls_json: r#"[{"name":"wall_a","width":5.0,"height":3.0}]"#.to_string(),
+                wall_sets_json: None,
+                filter: None,
+                dense: false,
+                mode: None,
+                ordering_json: None,
+                reserved_walls: vec![],
+                segments: vec![],
+                featured_first: false,
+                allow_rotation: false,
+                balance_by_collection: None,
+                collect_free_regions: false,
+                min_free_region_width: None,
+                exclude_previously_displayed: false,
+                gallery_id_range: None,
+            },
+        );
+        assert_eq!(
+            parse_layout_summary(body),
+            LayoutSummary {
+                galleries_created: 1,
+                first_gallery_id: Some(1),
+                last_gallery_id: Some(1),
+                unplaceable: 0,
+                unknown_wall_records: 0,
+                free_regions: vec![],
+                dropped_source_galleries: vec![],
+            }
+        );
+
+        let move_request = |strict| RequestBody::MoveArtObject {
+            art_object_id: ArtObjectId::Met(1),
+            gallery_id: 1,
+            wall_id: "wall_a".to_string(),
+            x: 4.9,
+            y: 1.5,
+            strict,
+        };
+        let body = worker.send_request(2, move_request(true));
+        assert!(matches!(body, ResponseBody::MoveRejected(_)));
+
+        let body = worker.send_request(3, move_request(false));
+        let ResponseBody::ArtObjectMoved { x, y } = body else {
+            panic!("expected art object moved response, got {body:?}");
+        };
+        assert_eq!((x, y), (4.5, 1.5));
+
+        let body = worker.send_request(
+            4,
+            RequestBody::GetArtObjectsForGalleryWall {
+                gallery_id: 1,
+                wall_id: "wall_a".to_string(),
+            },
+        );
+        let ResponseBody::ArtObjectsForGalleryWall(objects) = body else {
+            panic!("expected art objects response, got {body:?}");
+        };
+        assert_eq!(objects.len(), 1);
+        assert_eq!((objects[0].x, objects[0].y), (4.5, 1.5));
+
+        worker.end();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn test_layout_rescales_non_positive_galleries_when_walls_change() {
+        let root_dir = create_root_dir_with_db("rescale");
+        let worker = TestWorker::spawn(&root_dir, false, false);
+        let layout_request = |walls_json: &str| RequestBody::Layout {
+            walls_json: walls_json.to_string(),
+            wall_sets_json: None,
+            filter: None,
+            dense: false,
+            mode: None,
+            ordering_json: None,
+            reserved_walls: vec![],
+            segments: vec![],
+            featured_first: false,
+            allow_rotation: false,
+            balance_by_collection: None,
+            collect_free_regions: false,
+            min_free_region_width: None,
+            exclude_previously_displayed: false,
+            gallery_id_range: None,
+        };
+        let body = worker.send_request(
+            1,
+            layout_request(r#"[{"name":"wall_a","width":5.0,"height":3.0}]"#),
+        );
+        assert_eq!(parse_layout_summary(body).unplaceable, 0);
+
+        let body = worker.send_request(
+            2,
+            RequestBody::MoveArtObject {
+                art_object_id: ArtObjectId::Met(1),
+                gallery_id: -1,
+                wall_id: "wall_a".to_string(),
+                x: 2.5,
+                y: 1.5,
+                strict: true,
+            },
+        );
+        assert!(matches!(body, ResponseBody::ArtObjectMoved { .. }));
+
+        let body = worker.send_request(
+            3,
+            layout_request(r#"[{"name":"wall_a","width":4.0,"height":2.0}]"#),
+        );
+        assert_eq!(parse_layout_summary(body).unplaceable, 0);
+
+        let body = worker.send_request(
+            4,
+            RequestBody::GetArtObjectsForGalleryWall {
+                gallery_id: -1,
+                wall_id: "wall_a".to_string(),
+            },
+        );
+        let ResponseBody::ArtObjectsForGalleryWall(objects) = body else {
+            panic!("expected art objects response, got {body:?}");
+        };
+        assert_eq!(objects.len(), 1);
+        // It should still be in the center of the wall.
+        assert_eq!((objects[0].x, objects[0].y), (2.0, 1.0));
+
+        worker.end();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn test_maintenance_request_works() {
+        let root_dir = create_root_dir_with_db("maintenance");
+        let worker = TestWorker::spawn(&root_dir, false, false);
+        let body = worker.send_request(1, RequestBody::Maintenance { vacuum: true });
+        let ResponseBody::Maintenance(report) = body else {
+            panic!("expected maintenance response, got {body:?}");
+        };
+        assert!(report.vacuumed);
+        assert!(report.db_size_after.unwrap() > 0);
+
+        let body = worker.send_request(2, RequestBody::CountArtObjects { filter: None });
+        assert!(matches!(body, ResponseBody::Integer(1)));
+
+        worker.end();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn test_maintenance_waits_for_other_requests() {
+        let request = |body| {
+            Ok(MessageToWorker::Request(Request {
+                peer_id: None,
+                request_id: 1,
+                body,
+            }))
+        };
+        let mut queue = VecDeque::new();
+        assert!(!has_pending_non_maintenance_requests(&queue));
+        queue.push_back(request(RequestBody::Maintenance { vacuum: false }));
+        assert!(!has_pending_non_maintenance_requests(&queue));
+        queue.push_back(request(RequestBody::CountArtObjects { filter: None }));
+        assert!(has_pending_non_maintenance_requests(&queue));
+    }
+
+    #[test]
+    fn test_get_wall_sets_works_with_walls_json() {
+        let wall_sets = get_wall_sets(r#"[{"name":"a","width":1,"height":2}]"#, None).unwrap();
+        assert_eq!(wall_sets.len(), 1);
+        assert_eq!(wall_sets[0].name, "default");
+        assert_eq!(wall_sets[0].walls[0].name, "a");
+    }
+
+    #[test]
+    fn test_get_wall_sets_prefers_wall_sets_json() {
+        let wall_sets = get_wall_sets(
+            "[]",
+            Some(r#"[{"name":"big","walls":[{"name":"a","width":1,"height":2}]},{"name":"small","walls":[],"weight":2}]"#),
+        )
+        .unwrap();
+        assert_eq!(wall_sets.len(), 2);
+        assert_eq!(wall_sets[0].weight, 1);
+        assert_eq!(wall_sets[1].name, "small");
+        assert_eq!(wall_sets[1].weight, 2);
+    }
+
+    #[test]
+    fn test_layout_request_without_wall_sets_deserializes() {
+        let body: RequestBody =
+            serde_json::from_str(r#"{"Layout":{"walls_json":"[]","filter":null,"dense":false}}"#)
+                .unwrap();
+        let RequestBody::Layout { wall_sets_json, .. } = body else {
+            panic!("expected layout request");
+        };
+        assert_eq!(wall_sets_json, None);
+    }
+
+    #[test]
+    fn test_image_response_without_path_has_no_dimensions() {
+        let ResponseBody::Image {
+            path,
+            pixel_width,
+            pixel_height,
+        } = image_response(None)
+        else {
+            panic!("expected image response");
+        };
+        assert_eq!(path, None);
+        assert_eq!(pixel_width, None);
+        assert_eq!(pixel_height, None);
+    }
+
+    #[test]
+    fn test_image_response_round_trips_through_proxy_serialization() {
+        let body = ResponseBody::Image {
+            path: Some(PathBuf::from("boop.jpg")),
+            pixel_width: Some(640),
+            pixel_height: Some(480),
+        };
+        let serialized = serde_json::to_string(&body).unwrap();
+        let ResponseBody::Image {
+            path,
+            pixel_width,
+            pixel_height,
+        } = serde_json::from_str(&serialized).unwrap()
+        else {
+            panic!("expected image response");
+        };
+        assert_eq!(path, Some(PathBuf::from("boop.jpg")));
+        assert_eq!(pixel_width, Some(640));
+        assert_eq!(pixel_height, Some(480));
+    }
+
+    #[test]
+    fn test_image_response_without_dimensions_deserializes() {
+        let ResponseBody::Image {
+            path,
+            pixel_width,
+            pixel_height,
+        } = serde_json::from_str(r#"{"Image":{"path":"boop.jpg"}}"#).unwrap()
+        else {
+            panic!("expected image response");
+        };
+        assert_eq!(path, Some(PathBuf::from("boop.jpg")));
+        assert_eq!(pixel_width, None);
+        assert_eq!(pixel_height, None);
+    }
+
+    #[test]
+    fn test_decoded_image_response_falls_back_to_path() {
+        let path = std::env::temp_dir().join(format!(
+            "gallery-worker-test-decode-{}.jpg",
+            std::process::id()
+        ));
+        image::RgbImage::new(4, 3).save(&path).unwrap();
+
+        let ResponseBody::DecodedImage {
+            width,
+            height,
+            rgb8,
+        } = decoded_image_response(Some(path.clone()), 12)
+        else {
+            panic!("expected decoded image response");
+        };
+        assert_eq!((width, height), (4, 3));
+        assert_eq!(rgb8.len(), 4 * 3 * 3);
+
+        let ResponseBody::Image {
+            path: image_path,
+            pixel_width,
+            pixel_height,
+        } = decoded_image_response(Some(path.clone()), 11)
+        else {
+            panic!("expected image response");
+        };
+        assert_eq!(image_path, Some(path.clone()));
+        assert_eq!((pixel_width, pixel_height), (Some(4), Some(3)));
+
+        assert!(matches!(
+            decoded_image_response(None, 12),
+            ResponseBody::Image { path: None, .. }
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_layout_summary_parses_partial_json() {
+        let summary: LayoutSummary = serde_json::from_str(r#"{"unplaceable": 2}"#).unwrap();
+        assert_eq!(
+            summary,
+            LayoutSummary {
+                unplaceable: 2,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::worker_thread::{MessageFromWorker, Request, RequestBody};
+
+/// How long the worker can go without sending us anything while requests are
+/// outstanding before we consider it stalled. This is much longer than
+/// `HEARTBEAT_INTERVAL`, since some requests (e.g. layouts) legitimately keep
+/// the worker busy for a while.
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Keeps track of when we last heard from the worker thread, and which of our
+/// requests it hasn't responded to yet, so we can tell if it's hung, e.g.
+/// because a server stalled in the middle of a download.
+pub struct WorkerWatchdog {
+    stall_timeout: Duration,
+    last_heard_from: Instant,
+    /// Requests the worker hasn't responded to, keyed by peer and request ID.
+    /// Requests that are safe to retry keep their body, so they can be re-sent
+    /// to a new worker.
+    outstanding: HashMap<(Option<i32>, u32), Option<RequestBody>>,
+}
+
+/// The requests that a stalled worker never responded to, see
+/// `WorkerWatchdog::take_unanswered()`.
+#[derive(Debug, Default)]
+pub struct UnansweredRequests {
+    /// Requests that can be re-sent to a new worker.
+    pub retryable: Vec<Request>,
+    /// The peer and request IDs of requests that can't be safely re-sent, e.g.
+    /// because they might have modified the database before the worker hung.
+    pub abandoned: Vec<(Option<i32>, u32)>,
+}
+
+impl WorkerWatchdog {
+    pub fn new(stall_timeout: Duration, now: Instant) -> Self {
+        WorkerWatchdog {
+            stall_timeout,
+            last_heard_from: now,
+            outstanding: HashMap::new(),
+        }
+    }
+
+    pub fn set_stall_timeout(&mut self, stall_timeout: Duration) {
+        self.stall_timeout = stall_timeout;
+    }
+
+    /// Called when a new worker is spawned, which we haven't heard from yet.
+    pub fn on_connect(&mut self, now: Instant) {
+        self.last_heard_from = now;
+    }
+
+    pub fn on_request_sent(&mut self, request: &Request, now: Instant) {
+        if self.outstanding.is_empty() {
+            // The worker may have been idle for a while, and we don't want to
+            // count that against it.
+            self.last_heard_from = now;
+        }
+        let retry_body = request
+            .body
+            .is_proxyable_to_server()
+            .then(|| request.body.clone());
+        self.outstanding
+            .insert((request.peer_id, request.request_id), retry_body);
+    }
+
+    pub fn on_message(&mut self, message: &MessageFromWorker, now: Instant) {
+        self.last_heard_from = now;
+        if let MessageFromWorker::Response(response) = message {
+            self.outstanding
+                .remove(&(response.peer_id, response.request_id));
+        }
+    }
+
+    /// Whether requests are outstanding and we haven't heard anything from the
+    /// worker, not even a heartbeat, for longer than the stall timeout.
+    pub fn is_stalled(&self, now: Instant) -> bool {
+        !self.outstanding.is_empty()
+            && now.saturating_duration_since(self.last_heard_from) >= self.stall_timeout
+    }
+
+    /// Forgets about all outstanding requests, returning them so they can be
+    /// re-sent to a new worker or responded to with an error.
+    pub fn take_unanswered(&mut self) -> UnansweredRequests {
+        let mut unanswered = UnansweredRequests::default();
+        let mut outstanding: Vec<_> = self.outstanding.drain().collect();
+        // Re-send requests in the order they were originally made.
+        outstanding.sort_by_key(|((_, request_id), _)| *request_id);
+        for ((peer_id, request_id), retry_body) in outstanding {
+            match retry_body {
+                Some(body) => unanswered.retryable.push(Request {
+                    peer_id,
+                    request_id,
+                    body,
+                }),
+                None => unanswered.abandoned.push((peer_id, request_id)),
+            }
+        }
+        unanswered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc::{channel, TryRecvError},
+        time::{Duration, Instant},
+    };
+
+    use crate::worker_thread::{MessageFromWorker, Request, RequestBody, Response, ResponseBody};
+
+    use super::WorkerWatchdog;
+
+    const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn make_request(request_id: u32) -> Request {
+        Request {
+            peer_id: None,
+            request_id,
+            body: RequestBody::CountGalleries,
+        }
+    }
+
+    fn make_response(request_id: u32) -> MessageFromWorker {
+        MessageFromWorker::Response(Response {
+            peer_id: None,
+            request_id,
+            body: ResponseBody::Empty,
+        })
+    }
+
+    #[test]
+    fn test_idle_worker_is_never_stalled() {
+        let start = Instant::now();
+        let watchdog = WorkerWatchdog::new(STALL_TIMEOUT, start);
+        assert!(!watchdog.is_stalled(start + STALL_TIMEOUT * 100));
+    }
+
+    #[test]
+    fn test_worker_without_heartbeats_is_stalled() {
+        let start = Instant::now();
+        let mut watchdog = WorkerWatchdog::new(STALL_TIMEOUT, start);
+        let (from_worker_tx, from_worker_rx) = channel::<MessageFromWorker>();
+        watchdog.on_request_sent(&make_request(1), start);
+
+        // The worker never sends anything, not even a heartbeat.
+        assert!(matches!(
+            from_worker_rx.try_recv(),
+            Err(TryRecvError::Empty)
+        ));
+        assert!(!watchdog.is_stalled(start + STALL_TIMEOUT / 2));
+        assert!(watchdog.is_stalled(start + STALL_TIMEOUT));
+        drop(from_worker_tx);
+    }
+
+    #[test]
+    fn test_heartbeats_keep_busy_worker_alive() {
+        let start = Instant::now();
+        let mut watchdog = WorkerWatchdog::new(STALL_TIMEOUT, start);
+        let (from_worker_tx, from_worker_rx) = channel::<MessageFromWorker>();
+        watchdog.on_request_sent(&make_request(1), start);
+
+        let heartbeat_time = start + STALL_TIMEOUT / 2;
+        from_worker_tx.send(MessageFromWorker::Heartbeat).unwrap();
+        watchdog.on_message(&from_worker_rx.try_recv().unwrap(), heartbeat_time);
+        assert!(!watchdog.is_stalled(start + STALL_TIMEOUT));
+        assert!(watchdog.is_stalled(heartbeat_time + STALL_TIMEOUT));
+    }
+
+    #[test]
+    fn test_responses_clear_outstanding_requests() {
+        let start = Instant::now();
+        let mut watchdog = WorkerWatchdog::new(STALL_TIMEOUT, start);
+        watchdog.on_request_sent(&make_request(1), start);
+        watchdog.on_message(&make_response(1), start);
+        assert!(!watchdog.is_stalled(start + STALL_TIMEOUT * 2));
+    }
+
+    #[test]
+    fn test_idle_time_before_request_does_not_count() {
+        let start = Instant::now();
+        let mut watchdog = WorkerWatchdog::new(STALL_TIMEOUT, start);
+        let sent = start + STALL_TIMEOUT * 2;
+        watchdog.on_request_sent(&make_request(1), sent);
+        assert!(!watchdog.is_stalled(sent + STALL_TIMEOUT / 2));
+    }
+
+    #[test]
+    fn test_take_unanswered_abandons_non_proxyable_requests() {
+        let start = Instant::now();
+        let mut watchdog = WorkerWatchdog::new(STALL_TIMEOUT, start);
+        watchdog.on_request_sent(&make_request(2), start);
+        watchdog.on_request_sent(&make_request(1), start);
+        let unanswered = watchdog.take_unanswered();
+        // No requests are currently proxyable, see `RequestBody::is_proxyable_to_server()`.
+        assert_eq!(unanswered.retryable.len(), 0);
+        assert_eq!(unanswered.abandoned, vec![(None, 1), (None, 2)]);
+        assert!(!watchdog.is_stalled(start + STALL_TIMEOUT));
+    }
+}